@@ -0,0 +1,30 @@
+#![no_main]
+
+use bxssh::cli_terminal::CliTerminalIO;
+use bxssh::sanitize::strip_escape_sequences;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the session setup the remote output is filtered for
+    let Some((&setup, output)) = data.split_first() else {
+        return;
+    };
+    let mut terminal = CliTerminalIO::new().with_alt_screen(match setup & 3 {
+        0 => None,
+        1 => Some(true),
+        _ => Some(false),
+    });
+    if setup & 4 != 0 {
+        terminal = terminal.with_title("web1".to_string());
+    }
+    if setup & 8 != 0 {
+        terminal = terminal.with_line_editing();
+    }
+    terminal.filter_output(output);
+
+    // Banners and remote command output shown outside the session go through this instead
+    let text = String::from_utf8_lossy(output);
+    let clean = strip_escape_sequences(&text);
+    assert!(clean.len() <= text.len());
+    assert!(!clean.chars().any(|c| c.is_control() && c != '\n' && c != '\t'));
+});
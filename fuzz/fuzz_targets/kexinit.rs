@@ -0,0 +1,17 @@
+#![no_main]
+
+use bxssh::ssh_wire::{parse_packet, KexInit};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Raw payloads and framed packets both reach the parser in practice
+    let client = KexInit::parse(data).ok();
+
+    if let Ok((packet, _)) = parse_packet(data) {
+        if let (Some(client), Ok(server)) = (client, KexInit::parse(packet.payload)) {
+            if let Ok(negotiated) = client.negotiate(&server) {
+                assert!(server.kex_algorithms.contains(&negotiated.kex));
+            }
+        }
+    }
+});
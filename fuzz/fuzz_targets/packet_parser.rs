@@ -0,0 +1,14 @@
+#![no_main]
+
+use bxssh::ssh_wire::parse_packet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Walk a stream of packets the way a transport reader would
+    let mut rest = data;
+    while let Ok((packet, consumed)) = parse_packet(rest) {
+        assert!(consumed <= rest.len());
+        assert!(packet.payload.len() + packet.padding.len() < consumed);
+        rest = &rest[consumed..];
+    }
+});
@@ -0,0 +1,75 @@
+// Page side of the bxssh relay transport.
+//
+// installRelayBridge() registers relay-sw.js and defines the bridge functions the
+// WASM module imports: js_relay_connect opens a relayed stream, after which
+// js_tcp_send / js_tcp_receive / js_tcp_close carry the SSH connection over it.
+
+export async function installRelayBridge(serviceWorkerUrl = "relay-sw.js") {
+  await navigator.serviceWorker.register(serviceWorkerUrl);
+  const { active } = await navigator.serviceWorker.ready;
+
+  let port = null;
+  let buffered = new Uint8Array(0);
+  let eof = false;
+  let error = null;
+  let wake = null;
+
+  function append(bytes) {
+    const joined = new Uint8Array(buffered.length + bytes.length);
+    joined.set(buffered);
+    joined.set(bytes, buffered.length);
+    buffered = joined;
+  }
+
+  function arrival() {
+    return new Promise((resolve) => (wake = resolve));
+  }
+
+  function take(length) {
+    const chunk = buffered.slice(0, length);
+    buffered = buffered.slice(length);
+    return chunk;
+  }
+
+  globalThis.js_relay_connect = async (url, request) => {
+    port?.close();
+    const channel = new MessageChannel();
+    port = channel.port1;
+    buffered = new Uint8Array(0);
+    eof = false;
+    error = null;
+    port.onmessage = ({ data }) => {
+      if (data === null) eof = true;
+      else if (data.error) error = data.error;
+      else append(new Uint8Array(data));
+      wake?.();
+    };
+    active.postMessage({ type: "bxssh-relay-open", url, request: request.slice() }, [channel.port2]);
+
+    // The relay answers with one line before the SSH stream starts
+    for (;;) {
+      const newline = buffered.indexOf(10);
+      if (newline >= 0) return new TextDecoder().decode(take(newline + 1));
+      if (error) throw new Error(error);
+      if (eof) throw new Error("Relay closed the stream before replying");
+      await arrival();
+    }
+  };
+
+  globalThis.js_tcp_send = async (data) => {
+    if (!port || eof) throw new Error("Relay stream is not open");
+    const copy = data.slice();
+    port.postMessage(copy.buffer, [copy.buffer]);
+  };
+
+  globalThis.js_tcp_receive = async (maxLen) => {
+    while (buffered.length === 0 && !eof) await arrival();
+    return take(maxLen);
+  };
+
+  globalThis.js_tcp_close = async () => {
+    port?.postMessage(null);
+    port?.close();
+    port = null;
+  };
+}
@@ -0,0 +1,75 @@
+// bxssh relay service worker.
+//
+// Holds one WebTransport session per relay URL and opens a bidirectional stream on it
+// for every SSH connection a page asks for; ws:// and wss:// relays (`bxssh relay`)
+// get one WebSocket per connection instead. Pages talk to it through relay-bridge.js:
+// they post {type: "bxssh-relay-open", url, request} with a MessagePort, send SSH
+// bytes (ArrayBuffer) or null for EOF on the port, and receive the relay's bytes
+// back on it, ending with null.
+
+const sessions = new Map();
+
+function session(url) {
+  let ready = sessions.get(url);
+  if (!ready) {
+    const transport = new WebTransport(url);
+    ready = transport.ready.then(() => transport);
+    transport.closed.catch(() => {}).finally(() => sessions.delete(url));
+    sessions.set(url, ready);
+  }
+  return ready;
+}
+
+self.addEventListener("install", () => self.skipWaiting());
+self.addEventListener("activate", (event) => event.waitUntil(self.clients.claim()));
+
+self.addEventListener("message", (event) => {
+  if (event.data?.type !== "bxssh-relay-open" || !event.ports[0]) return;
+  event.waitUntil(relay(event.data, event.ports[0]));
+});
+
+function relayWebSocket(url, request, port) {
+  const socket = new WebSocket(url);
+  socket.binaryType = "arraybuffer";
+  socket.onopen = () => socket.send(request);
+  socket.onmessage = ({ data }) => port.postMessage(data, [data]);
+  socket.onerror = () => port.postMessage({ error: `Cannot reach relay ${url}` });
+  port.onmessage = ({ data }) => (data === null ? socket.close() : socket.send(data));
+  return new Promise((resolve) => {
+    socket.onclose = () => {
+      port.postMessage(null);
+      port.close();
+      resolve();
+    };
+  });
+}
+
+async function relay({ url, request }, port) {
+  if (/^wss?:/.test(url)) return relayWebSocket(url, new Uint8Array(request), port);
+
+  let stream;
+  try {
+    stream = await (await session(url)).createBidirectionalStream();
+  } catch (error) {
+    port.postMessage({ error: `Cannot reach relay ${url}: ${error?.message ?? error}` });
+    port.close();
+    return;
+  }
+
+  const writer = stream.writable.getWriter();
+  const reader = stream.readable.getReader();
+  port.onmessage = ({ data }) => (data === null ? writer.close() : writer.write(new Uint8Array(data))).catch(() => {});
+  await writer.write(new Uint8Array(request));
+
+  try {
+    for (;;) {
+      const { value, done } = await reader.read();
+      if (done) break;
+      port.postMessage(value.buffer, [value.buffer]);
+    }
+  } catch {
+    // Relay or server went away; the page sees EOF
+  }
+  port.postMessage(null);
+  port.close();
+}
@@ -0,0 +1,193 @@
+//! Node.js bindings: the native `ssh_client` core over real TCP sockets, for
+//! Electron apps and JS CLI tools that cannot use the browser WASM build.
+
+use bxssh::asyncapi;
+use bxssh::key_manager::{KeyManager, KeyPair};
+use bxssh::ssh_impl::RealSshConnection;
+use napi::bindgen_prelude::{Buffer, Error, Result};
+use napi_derive::napi;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+/// Bytes returned by one `Channel.read()` when no limit is given
+const DEFAULT_READ_SIZE: u32 = 64 * 1024;
+
+fn to_js(error: anyhow::Error) -> Error {
+    Error::from_reason(format!("{:#}", error))
+}
+
+fn io_to_js(error: std::io::Error) -> Error {
+    Error::from_reason(error.to_string())
+}
+
+/// A key from the bxssh key store (`~/.local/share/bxssh/keys/`), without its private half
+#[napi(object)]
+pub struct KeyInfo {
+    pub name: String,
+    pub public_key: String,
+    /// RFC 3339 timestamp, when known
+    pub created_at: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+impl From<&KeyPair> for KeyInfo {
+    fn from(key: &KeyPair) -> Self {
+        Self {
+            name: key.name.clone(),
+            public_key: key.public_key.clone(),
+            created_at: key.created_at.map(|at| at.to_rfc3339()),
+            expires_at: key.expires_at.map(|at| at.to_rfc3339()),
+        }
+    }
+}
+
+/// Keys in the shared bxssh key store
+#[napi]
+pub fn list_keys() -> Result<Vec<KeyInfo>> {
+    let manager = KeyManager::new().map_err(to_js)?;
+    let mut keys: Vec<KeyInfo> = manager.list_keys().into_iter().map(KeyInfo::from).collect();
+    keys.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(keys)
+}
+
+/// Generate an Ed25519 key in the shared key store, as `bxssh key generate` does
+#[napi]
+pub fn generate_key(name: String) -> Result<KeyInfo> {
+    let mut manager = KeyManager::new().map_err(to_js)?;
+    manager.generate_ed25519_key(&name).map(KeyInfo::from).map_err(to_js)
+}
+
+/// SSH client over a native TCP connection. All methods return promises and
+/// run the blocking libssh2 work off the Node event loop.
+#[napi]
+pub struct SshClient {
+    inner: asyncapi::SshClient,
+}
+
+#[napi]
+impl SshClient {
+    #[napi(constructor)]
+    #[allow(clippy::new_without_default)] // JS constructors take no Default
+    pub fn new() -> Self {
+        Self {
+            inner: asyncapi::SshClient::new(Box::new(RealSshConnection::new())),
+        }
+    }
+
+    #[napi]
+    pub async fn connect(&self, host: String, port: Option<u32>) -> Result<()> {
+        let port = u16::try_from(port.unwrap_or(22)).map_err(|_| Error::from_reason("Invalid port number"))?;
+        self.inner.connect(&host, port).await.map_err(to_js)
+    }
+
+    #[napi]
+    pub async fn authenticate_with_key(&self, username: String, private_key_path: String) -> Result<()> {
+        self.inner.authenticate_with_key(&username, &private_key_path).await.map_err(to_js)
+    }
+
+    #[napi]
+    pub async fn authenticate_with_password(&self, username: String, password: String) -> Result<()> {
+        self.inner.authenticate_with_password(&username, &password).await.map_err(to_js)
+    }
+
+    /// Authenticate with key `name` from the bxssh key store, or its default key
+    #[napi]
+    pub async fn authenticate_with_stored_key(&self, username: String, name: Option<String>) -> Result<()> {
+        let mut manager = KeyManager::new().map_err(to_js)?;
+        let key = match &name {
+            Some(name) => manager.get_key(name),
+            None => manager.get_default_key(),
+        }
+        .cloned()
+        .ok_or_else(|| Error::from_reason(format!("No key '{}' in the bxssh key store", name.as_deref().unwrap_or("default"))))?;
+
+        let key_file = bxssh::native::TempKeyFile::create(&key.private_key).map_err(to_js)?;
+        self.inner.authenticate_with_key(&username, key_file.path()).await.map_err(to_js)?;
+        drop(key_file);
+
+        manager.record_usage(&key.name).map_err(to_js)
+    }
+
+    /// Run `command` and resolve with its output; rejects on a non-zero exit
+    #[napi]
+    pub async fn exec(&self, command: String) -> Result<String> {
+        self.inner.execute_command(&command).await.map_err(to_js)
+    }
+
+    /// Start `command` with its stdin/stdout streamed through a `Channel`
+    #[napi]
+    pub async fn open_exec(&self, command: String) -> Result<Channel> {
+        self.inner.open_exec(&command).await.map(Channel::new).map_err(to_js)
+    }
+
+    /// Start an interactive shell on a PTY
+    #[napi]
+    pub async fn start_shell(&self) -> Result<Channel> {
+        self.inner.start_shell().await.map(Channel::new).map_err(to_js)
+    }
+
+    #[napi]
+    pub async fn is_authenticated(&self) -> bool {
+        self.inner.is_authenticated().await
+    }
+}
+
+/// A streaming SSH channel
+#[napi]
+pub struct Channel {
+    /// `None` once `waitClose()` has taken the channel
+    inner: Arc<Mutex<Option<asyncapi::Channel>>>,
+}
+
+impl Channel {
+    fn new(channel: asyncapi::Channel) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Some(channel))),
+        }
+    }
+}
+
+fn closed() -> Error {
+    Error::from_reason("Channel is closed")
+}
+
+#[napi]
+impl Channel {
+    /// Resolve with the next chunk of output, or `null` at end of stream
+    #[napi]
+    pub async fn read(&self, max_bytes: Option<u32>) -> Result<Option<Buffer>> {
+        let mut guard = self.inner.lock().await;
+        let channel = guard.as_mut().ok_or_else(closed)?;
+
+        let mut buf = vec![0u8; max_bytes.unwrap_or(DEFAULT_READ_SIZE).max(1) as usize];
+        let n = channel.read(&mut buf).await.map_err(io_to_js)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.truncate(n);
+        Ok(Some(buf.into()))
+    }
+
+    #[napi]
+    pub async fn write(&self, data: Buffer) -> Result<()> {
+        let mut guard = self.inner.lock().await;
+        let channel = guard.as_mut().ok_or_else(closed)?;
+        channel.write_all(&data).await.map_err(io_to_js)
+    }
+
+    /// Send EOF; the remote command sees its stdin close
+    #[napi]
+    pub async fn end(&self) -> Result<()> {
+        let mut guard = self.inner.lock().await;
+        let channel = guard.as_mut().ok_or_else(closed)?;
+        channel.shutdown().await.map_err(io_to_js)
+    }
+
+    /// Wait for the remote side to close and resolve with the exit status
+    #[napi]
+    pub async fn wait_close(&self) -> Result<i32> {
+        let channel = self.inner.lock().await.take().ok_or_else(closed)?;
+        channel.wait_close().await.map_err(to_js)
+    }
+}
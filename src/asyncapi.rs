@@ -0,0 +1,348 @@
+use anyhow::{Context as _, Result};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+use crate::ssh_client::{self, CommandOutput, ShellSession, Signal, SshConnection, WriteOutcome};
+
+/// Backoff before polling an idle channel again; the backends have no readiness notification
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The client behind an [`SshClient`] and the channels it opened
+struct Shared {
+    client: Mutex<ssh_client::SshClient>,
+    /// Channels that found the client busy, woken once it is free again
+    waiting: Mutex<Vec<Waker>>,
+}
+
+impl Shared {
+    /// The client, unless an operation on the blocking pool holds it; that operation
+    /// may have switched the session to blocking mode, so channel I/O must not run
+    /// until it ends, and `cx` is woken when it does
+    fn idle(&self, cx: &Context<'_>) -> Option<MutexGuard<'_, ssh_client::SshClient>> {
+        let try_lock = || match self.client.try_lock() {
+            Ok(client) => Some(client),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        };
+        try_lock().or_else(|| {
+            self.waiting.lock().unwrap_or_else(|e| e.into_inner()).push(cx.waker().clone());
+            // The operation may have ended before the waker was in place
+            try_lock()
+        })
+    }
+
+    fn wake_waiting(&self) {
+        let waiting = std::mem::take(&mut *self.waiting.lock().unwrap_or_else(|e| e.into_inner()));
+        waiting.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Async wrapper around [`ssh_client::SshClient`] for tokio applications.
+/// Handshakes, authentication and commands run on tokio's blocking pool, so they
+/// never stall the calling runtime; cloning shares the underlying connection.
+#[derive(Clone)]
+pub struct SshClient {
+    inner: Arc<Shared>,
+}
+
+impl SshClient {
+    pub fn new(connection: Box<dyn SshConnection>) -> Self {
+        Self::from_client(ssh_client::SshClient::new(connection))
+    }
+
+    pub fn from_client(client: ssh_client::SshClient) -> Self {
+        Self {
+            inner: Arc::new(Shared { client: Mutex::new(client), waiting: Mutex::new(Vec::new()) }),
+        }
+    }
+
+    /// Run `f` against the blocking client on tokio's blocking pool
+    async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ssh_client::SshClient) -> Result<T> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || {
+            let result = match inner.client.lock() {
+                Ok(mut client) => f(&mut client),
+                Err(_) => Err(anyhow::anyhow!("SSH client lock poisoned")),
+            };
+            inner.wake_waiting();
+            result
+        })
+        .await
+        .context("SSH task failed")?
+    }
+
+    pub async fn connect(&self, host: &str, port: u16) -> Result<()> {
+        let host = host.to_string();
+        self.blocking(move |client| client.connect(&host, port)).await
+    }
+
+    pub async fn authenticate_with_key(&self, username: &str, private_key_path: &str) -> Result<()> {
+        let (username, path) = (username.to_string(), private_key_path.to_string());
+        self.blocking(move |client| client.authenticate_with_key(&username, &path)).await
+    }
+
+    pub async fn authenticate_with_password(&self, username: &str, password: &str) -> Result<()> {
+        let (username, password) = (username.to_string(), password.to_string());
+        self.blocking(move |client| client.authenticate_with_password(&username, &password)).await
+    }
+
+    /// Run `command` to completion and collect its output
+    pub async fn execute_command(&self, command: &str) -> Result<String> {
+        let command = command.to_string();
+        self.blocking(move |client| client.execute_command(&command)).await
+    }
+
+    /// Run `command` to completion, reporting its exit status or signal
+    pub async fn run_command(&self, command: &str) -> Result<CommandOutput> {
+        let command = command.to_string();
+        self.blocking(move |client| client.run_command(&command)).await
+    }
+
+    /// Start `command` and stream its stdin/stdout through the returned channel
+    pub async fn open_exec(&self, command: &str) -> Result<Channel> {
+        let command = command.to_string();
+        self.blocking(move |client| client.open_exec(&command)).await.map(|session| self.channel(session))
+    }
+
+    /// Start an interactive shell on a PTY
+    pub async fn start_shell(&self) -> Result<Channel> {
+        self.blocking(|client| client.start_shell()).await.map(|session| self.channel(session))
+    }
+
+    /// Open a `direct-tcpip` channel to `host:port` as seen from the server
+    pub async fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<Channel> {
+        let host = host.to_string();
+        let channel = self.blocking(move |client| client.open_direct_tcpip(&host, port)).await;
+        channel.map(|session| self.channel(session))
+    }
+
+    pub async fn is_authenticated(&self) -> bool {
+        self.blocking(|client| Ok(client.is_authenticated())).await.unwrap_or(false)
+    }
+
+    fn channel(&self, session: Box<dyn ShellSession>) -> Channel {
+        Channel { client: Some(Arc::clone(&self.inner)), ..Channel::new(session) }
+    }
+}
+
+/// An SSH channel as a tokio byte stream. `shutdown` sends EOF to the remote side.
+/// The session stays non-blocking for channel I/O: a channel opened by an
+/// [`SshClient`] waits while a command or another channel is being set up on it.
+pub struct Channel {
+    session: Box<dyn ShellSession>,
+    backoff: Option<Pin<Box<Sleep>>>,
+    /// The client that opened the channel, whose operations it must not overlap
+    client: Option<Arc<Shared>>,
+}
+
+impl std::fmt::Debug for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Channel").field("session", &self.session).finish()
+    }
+}
+
+impl Channel {
+    pub fn new(session: Box<dyn ShellSession>) -> Self {
+        Self { session, backoff: None, client: None }
+    }
+
+    /// Wait for the remote side to close the channel and return its exit status
+    pub async fn wait_close(self) -> Result<i32> {
+        let mut session = self.session;
+        tokio::task::spawn_blocking(move || session.wait_close())
+            .await
+            .context("SSH task failed")?
+    }
+
+    /// Send `signal` to the remote process; waits for the server's reply, so this
+    /// blocks the calling task for a round trip
+    pub fn send_signal(&mut self, signal: Signal) -> Result<()> {
+        self.session.send_signal(signal)
+    }
+
+    /// Register a wakeup after the backoff interval and report `Pending`
+    fn retry_later<T>(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        let backoff = self
+            .backoff
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(POLL_INTERVAL)));
+        if backoff.as_mut().poll(cx).is_ready() {
+            // Timer already fired: re-arm it so the waker is registered again
+            backoff.as_mut().reset(tokio::time::Instant::now() + POLL_INTERVAL);
+            let _ = backoff.as_mut().poll(cx);
+        }
+        Poll::Pending
+    }
+}
+
+/// [`Shared::idle`] for a channel's client; a channel of no client never waits
+fn idle<'a>(client: Option<&'a Shared>, cx: &Context<'_>) -> Poll<Option<MutexGuard<'a, ssh_client::SshClient>>> {
+    match client.map(|client| client.idle(cx)) {
+        Some(None) => Poll::Pending,
+        idle => Poll::Ready(idle.flatten()),
+    }
+}
+
+fn io_error(error: anyhow::Error) -> io::Error {
+    io::Error::other(format!("{:#}", error))
+}
+
+impl AsyncRead for Channel {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let client = self.client.clone();
+        let _idle = ready!(idle(client.as_deref(), cx));
+        let n = self.session.read(buf.initialize_unfilled()).map_err(io_error)?;
+        if n > 0 {
+            buf.advance(n);
+            self.backoff = None;
+            return Poll::Ready(Ok(()));
+        }
+        if self.session.is_eof() {
+            return Poll::Ready(Ok(()));
+        }
+        self.retry_later(cx)
+    }
+}
+
+impl AsyncWrite for Channel {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let client = self.client.clone();
+        let _idle = ready!(idle(client.as_deref(), cx));
+        match self.session.try_write(data).map_err(io_error)? {
+            WriteOutcome::Written(n) => {
+                self.backoff = None;
+                Poll::Ready(Ok(n))
+            }
+            WriteOutcome::Blocked => self.retry_later(cx),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let client = self.client.clone();
+        let _idle = ready!(idle(client.as_deref(), cx));
+        Poll::Ready(self.session.send_eof().map_err(io_error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Script, ScriptedSshServer};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn client(server: ScriptedSshServer) -> SshClient {
+        let client = SshClient::new(Box::new(server.accept_password("alice", "secret")));
+        client.connect("example.com", 22).await.unwrap();
+        client.authenticate_with_password("alice", "secret").await.unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_execute() {
+        let client = client(ScriptedSshServer::new().command("uptime", Script::new().send("up 3 days\n").eof())).await;
+
+        assert!(client.is_authenticated().await);
+        assert_eq!(client.execute_command("uptime").await.unwrap(), "up 3 days\n");
+        assert!(client.execute_command("missing").await.is_err());
+        assert_eq!(client.run_command("uptime").await.unwrap().exit_status, 0);
+    }
+
+    #[tokio::test]
+    async fn test_authentication_failure() {
+        let client = SshClient::new(Box::new(ScriptedSshServer::new().accept_password("alice", "secret")));
+        client.connect("example.com", 22).await.unwrap();
+        assert!(client.authenticate_with_password("alice", "wrong").await.is_err());
+        assert!(!client.is_authenticated().await);
+    }
+
+    #[tokio::test]
+    async fn test_exec_channel_streams() {
+        let client = client(ScriptedSshServer::new().command(
+            "cat",
+            Script::new()
+                .delay(Duration::from_millis(20))
+                .send("ready\n")
+                .expect("ping\n")
+                .send("pong\n")
+                .exit_status(7)
+                .eof(),
+        ))
+        .await;
+
+        let mut channel = client.open_exec("cat").await.unwrap();
+        let mut ready = [0u8; 6];
+        channel.read_exact(&mut ready).await.unwrap();
+        assert_eq!(&ready, b"ready\n");
+
+        channel.write_all(b"ping\n").await.unwrap();
+        channel.shutdown().await.unwrap();
+        let mut rest = String::new();
+        channel.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "pong\n");
+        assert_eq!(channel.wait_close().await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_channel_waits_out_commands() {
+        let server = ScriptedSshServer::new()
+            .command("tail -f log", Script::new().delay(Duration::from_millis(50)).send("line\n").eof())
+            .command("uptime", Script::new().delay(Duration::from_millis(20)).send("up\n").eof());
+        let client = client(server).await;
+
+        let mut channel = client.open_exec("tail -f log").await.unwrap();
+        let read = tokio::spawn(async move {
+            let mut line = String::new();
+            channel.read_to_string(&mut line).await.unwrap();
+            line
+        });
+        for _ in 0..3 {
+            assert_eq!(client.execute_command("uptime").await.unwrap(), "up\n");
+        }
+        assert_eq!(read.await.unwrap(), "line\n");
+    }
+
+    #[tokio::test]
+    async fn test_signal_interrupts_command() {
+        let client = client(ScriptedSshServer::new().command(
+            "sleep 600",
+            Script::new().delay(Duration::from_secs(600)).eof(),
+        ))
+        .await;
+
+        let mut channel = client.open_exec("sleep 600").await.unwrap();
+        channel.send_signal(Signal::Term).unwrap();
+        let mut rest = Vec::new();
+        channel.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(channel.wait_close().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shell_channel() {
+        let client = client(ScriptedSshServer::new().shell(Script::new().send("$ ").expect("exit\n").eof())).await;
+
+        let mut shell = client.start_shell().await.unwrap();
+        let mut prompt = [0u8; 2];
+        shell.read_exact(&mut prompt).await.unwrap();
+        shell.write_all(b"exit\n").await.unwrap();
+
+        let mut rest = Vec::new();
+        shell.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! Failed logins per host (`auth_failures.json` in the state directory), so repeated attempts
+//! back off and warn before the server's own lockout (sshd's `MaxAuthTries`,
+//! fail2ban's `maxretry`) kicks in
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Failures older than this are forgotten, like fail2ban's `findtime`
+pub const FORGET_AFTER_SECS: i64 = 3600;
+
+/// How hard to push after failed logins; see [`crate::config::SshConfig`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthPolicy {
+    /// Pause after the first failure, doubling with each one after it (zero disables)
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Warn once this many recent failures have piled up (0 disables)
+    pub warn_after: u32,
+    /// Password prompts per login, as OpenSSH's `NumberOfPasswordPrompts`
+    pub max_password_attempts: u32,
+}
+
+impl Default for AuthPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+            warn_after: 3,
+            max_password_attempts: 3,
+        }
+    }
+}
+
+impl AuthPolicy {
+    /// Pause owed after `failures` recent failures
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+        let factor = 2u32.saturating_pow(failures - 1);
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Recent failed logins as one user to one host
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostFailures {
+    pub host: String,
+    pub user: String,
+    pub port: u16,
+    pub count: u32,
+    pub last_failure: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuthFailures {
+    pub hosts: Vec<HostFailures>,
+}
+
+impl AuthFailures {
+    /// `auth_failures.json` in the state directory
+    pub fn path() -> Result<PathBuf> {
+        Ok(crate::paths::state_dir()?.join("auth_failures.json"))
+    }
+
+    /// The failures in `path`; a missing file has none
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&content).with_context(|| format!("Invalid auth failures file {}", path.display()))
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        crate::private_file::write(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Failures for `user@host:port` that are still recent at `now`
+    pub fn recent(&self, host: &str, user: &str, port: u16, now: DateTime<Utc>) -> Option<&HostFailures> {
+        self.hosts.iter().find(|entry| {
+            entry.host == host
+                && entry.user == user
+                && entry.port == port
+                && (now - entry.last_failure).num_seconds() < FORGET_AFTER_SECS
+        })
+    }
+
+    /// Count a failure at `now`, dropping stale entries; returns the recent total
+    pub fn record_failure(&mut self, host: &str, user: &str, port: u16, now: DateTime<Utc>) -> u32 {
+        let count = self.recent(host, user, port, now).map_or(0, |entry| entry.count) + 1;
+        self.clear(host, user, port);
+        self.hosts.retain(|entry| (now - entry.last_failure).num_seconds() < FORGET_AFTER_SECS);
+        let (host, user) = (host.to_string(), user.to_string());
+        self.hosts.push(HostFailures { host, user, port, count, last_failure: now });
+        count
+    }
+
+    /// Forget `user@host:port`'s failures after a successful login
+    pub fn clear(&mut self, host: &str, user: &str, port: u16) {
+        self.hosts.retain(|entry| !(entry.host == host && entry.user == user && entry.port == port));
+    }
+}
+
+/// One login's view of [`AuthFailures`]. Trouble reading or writing the file is
+/// only logged: bookkeeping must not stop a connection.
+#[derive(Debug, Clone)]
+pub struct AuthThrottle {
+    host: String,
+    user: String,
+    port: u16,
+    policy: AuthPolicy,
+    path: Option<PathBuf>,
+    failures: u32,
+    last_failure: Option<DateTime<Utc>>,
+}
+
+impl AuthThrottle {
+    /// Failures recorded for `user@host:port` in `auth_failures.json`
+    pub fn load(host: &str, user: &str, port: u16, policy: AuthPolicy) -> Self {
+        let path = AuthFailures::path().map_err(|e| log::debug!("No auth failures file: {:#}", e)).ok();
+        Self::load_from(path, host, user, port, policy)
+    }
+
+    pub fn load_from(path: Option<PathBuf>, host: &str, user: &str, port: u16, policy: AuthPolicy) -> Self {
+        let recent = path.as_deref().and_then(|path| match AuthFailures::load_from(path) {
+            Ok(failures) => failures.recent(host, user, port, Utc::now()).cloned(),
+            Err(e) => {
+                log::debug!("Ignoring auth failures: {:#}", e);
+                None
+            }
+        });
+        Self {
+            host: host.to_string(),
+            user: user.to_string(),
+            port,
+            policy,
+            path,
+            failures: recent.as_ref().map_or(0, |entry| entry.count),
+            last_failure: recent.map(|entry| entry.last_failure),
+        }
+    }
+
+    pub fn policy(&self) -> &AuthPolicy {
+        &self.policy
+    }
+
+    /// Recent failed logins, including this run's
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// What is left of the pause owed since the last failure
+    pub fn pending_delay(&self, now: DateTime<Utc>) -> Duration {
+        let Some(last_failure) = self.last_failure else {
+            return Duration::ZERO;
+        };
+        let elapsed = (now - last_failure).to_std().unwrap_or_default();
+        self.policy.delay(self.failures).saturating_sub(elapsed)
+    }
+
+    /// Enough recent failures that the next ones may lock the account or address out
+    pub fn near_lockout(&self) -> bool {
+        self.policy.warn_after > 0 && self.failures >= self.policy.warn_after
+    }
+
+    pub fn record_failure(&mut self) {
+        let now = Utc::now();
+        self.failures += 1;
+        self.last_failure = Some(now);
+        self.update(|failures| {
+            failures.record_failure(&self.host, &self.user, self.port, now);
+        });
+    }
+
+    pub fn record_success(&mut self) {
+        if self.failures == 0 {
+            return;
+        }
+        self.failures = 0;
+        self.last_failure = None;
+        self.update(|failures| failures.clear(&self.host, &self.user, self.port));
+    }
+
+    fn update(&self, change: impl FnOnce(&mut AuthFailures)) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let updated = AuthFailures::load_from(path).and_then(|mut failures| {
+            change(&mut failures);
+            failures.save_to(path)
+        });
+        if let Err(e) = updated {
+            log::debug!("Failed to update {}: {:#}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_policy_delay_doubles_up_to_the_cap() {
+        let policy = AuthPolicy::default();
+        assert_eq!(policy.delay(0), Duration::ZERO);
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(10), Duration::from_secs(60));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(60));
+        assert_eq!(AuthPolicy { backoff: Duration::ZERO, ..policy }.delay(5), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_failures_are_counted_per_host_and_forgotten() {
+        let now = Utc::now();
+        let mut failures = AuthFailures::default();
+        assert_eq!(failures.record_failure("web", "alice", 22, now), 1);
+        assert_eq!(failures.record_failure("web", "alice", 22, now), 2);
+        assert_eq!(failures.record_failure("web", "bob", 22, now), 1);
+        assert_eq!(failures.recent("web", "alice", 22, now).unwrap().count, 2);
+        assert!(failures.recent("web", "alice", 2222, now).is_none());
+
+        let later = now + chrono::Duration::seconds(FORGET_AFTER_SECS);
+        assert!(failures.recent("web", "alice", 22, later).is_none());
+        assert_eq!(failures.record_failure("web", "alice", 22, later), 1);
+        assert_eq!(failures.hosts.len(), 1);
+
+        failures.clear("web", "alice", 22);
+        assert!(failures.hosts.is_empty());
+    }
+
+    #[test]
+    fn test_throttle_persists_failures_until_success() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("auth_failures.json");
+        let policy = AuthPolicy::default();
+
+        let mut throttle = AuthThrottle::load_from(Some(path.clone()), "web", "alice", 22, policy);
+        assert_eq!(throttle.pending_delay(Utc::now()), Duration::ZERO);
+        for _ in 0..3 {
+            throttle.record_failure();
+        }
+
+        let mut throttle = AuthThrottle::load_from(Some(path.clone()), "web", "alice", 22, policy);
+        assert_eq!(throttle.failures(), 3);
+        assert!(throttle.near_lockout());
+        let delay = throttle.pending_delay(Utc::now());
+        assert!(delay > Duration::from_secs(7) && delay <= Duration::from_secs(8), "{:?}", delay);
+
+        throttle.record_success();
+        let throttle = AuthThrottle::load_from(Some(path.clone()), "web", "alice", 22, policy);
+        assert_eq!(throttle.failures(), 0);
+        assert!(!throttle.near_lockout());
+        assert!(AuthFailures::load_from(&path).unwrap().hosts.is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! `bxssh key authorize` and `key revoke`: add or remove a stored key's line in
+//! the server's `~/.ssh/authorized_keys`. Edits hold a lock directory so two runs
+//! can't interleave, keep a backup of the previous file, and replace it with a
+//! rename, so a dropped connection never leaves it half written.
+use anyhow::{Context, Result};
+use std::time::Duration;
+
+use crate::session_cache::Remote;
+
+/// Relative to the remote home directory, where exec and SFTP both start
+pub const AUTHORIZED_KEYS: &str = ".ssh/authorized_keys";
+pub const BACKUP: &str = ".ssh/authorized_keys.bxssh-backup";
+const STAGED: &str = ".ssh/authorized_keys.bxssh-new";
+const LOCK: &str = ".ssh/.bxssh-authorized_keys.lock";
+
+/// Tries for the lock, a second apart, before giving up on another run holding it
+const LOCK_ATTEMPTS: u32 = 5;
+const LOCK_RETRY: Duration = Duration::from_secs(1);
+
+/// What an edit did to the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    AlreadyPresent,
+    /// This many lines for the key were removed
+    Removed(usize),
+    NotPresent,
+}
+
+/// The base64 key of an `authorized_keys` line, after any options such as
+/// `command="..."`; `None` for blank lines and comments
+pub fn key_data(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split_whitespace();
+    fields.find(|field| is_key_type(field))?;
+    fields.next()
+}
+
+fn is_key_type(field: &str) -> bool {
+    field.starts_with("ssh-") || field.starts_with("ecdsa-sha2-") || field.starts_with("sk-")
+}
+
+/// `content` with `line` appended, or `None` when a line for the same key is there
+/// already (whatever its options or comment)
+pub fn add_line(content: &str, line: &str) -> Option<String> {
+    let key = key_data(line)?;
+    if content.lines().any(|existing| key_data(existing) == Some(key)) {
+        return None;
+    }
+    let mut updated = content.to_string();
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str(line);
+    updated.push('\n');
+    Some(updated)
+}
+
+/// `content` without the lines for `key` (base64 key data), and how many there were
+pub fn remove_key(content: &str, key: &str) -> (String, usize) {
+    let mut removed = 0;
+    let mut updated = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        if key_data(line) == Some(key) {
+            removed += 1;
+        } else {
+            updated.push_str(line);
+        }
+    }
+    (updated, removed)
+}
+
+/// Append `line` to the remote `authorized_keys`, creating `~/.ssh` as needed
+pub fn authorize(remote: &dyn Remote, line: &str) -> Result<Change> {
+    key_data(line).ok_or_else(|| anyhow::anyhow!("Not an authorized_keys line: {}", line))?;
+    edit(remote, |content| match add_line(content, line) {
+        Some(updated) => (Some(updated), Change::Added),
+        None => (None, Change::AlreadyPresent),
+    })
+}
+
+/// Remove every line for `key` (base64 key data) from the remote `authorized_keys`
+pub fn revoke(remote: &dyn Remote, key: &str) -> Result<Change> {
+    edit(remote, |content| match remove_key(content, key) {
+        (_, 0) => (None, Change::NotPresent),
+        (updated, removed) => (Some(updated), Change::Removed(removed)),
+    })
+}
+
+/// Run `change` on the current file under the lock, writing back what it returns
+fn edit(remote: &dyn Remote, change: impl FnOnce(&str) -> (Option<String>, Change)) -> Result<Change> {
+    lock(remote)?;
+    let result = edit_locked(remote, change);
+    if let Err(e) = remote.execute_command(&format!("rmdir {}", LOCK)) {
+        log::warn!("Failed to remove the remote lock ~/{}: {:#}", LOCK, e);
+    }
+    result
+}
+
+fn lock(remote: &dyn Remote) -> Result<()> {
+    let command = format!("umask 077 && mkdir -p .ssh && chmod 700 .ssh && mkdir {}", LOCK);
+    let mut attempt = 1;
+    loop {
+        match remote.execute_command(&command) {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= LOCK_ATTEMPTS => {
+                return Err(e).with_context(|| {
+                    format!("Could not lock ~/{} (if no other bxssh is editing it, remove ~/{})", AUTHORIZED_KEYS, LOCK)
+                });
+            }
+            Err(e) => log::debug!("Waiting for the authorized_keys lock: {:#}", e),
+        }
+        std::thread::sleep(LOCK_RETRY);
+        attempt += 1;
+    }
+}
+
+fn edit_locked(remote: &dyn Remote, change: impl FnOnce(&str) -> (Option<String>, Change)) -> Result<Change> {
+    let current = remote
+        .execute_command(&format!("if [ -f {0} ]; then cat {0}; fi", AUTHORIZED_KEYS))
+        .with_context(|| format!("Failed to read ~/{}", AUTHORIZED_KEYS))?;
+    let (updated, outcome) = change(&current);
+    let Some(updated) = updated else {
+        return Ok(outcome);
+    };
+
+    remote
+        .execute_command(&format!("if [ -f {0} ]; then cp -p {0} {1}; fi", AUTHORIZED_KEYS, BACKUP))
+        .with_context(|| format!("Failed to back up ~/{}", AUTHORIZED_KEYS))?;
+    remote.upload(&mut updated.as_bytes(), STAGED).context("Failed to upload the new authorized_keys")?;
+    remote
+        .execute_command(&format!("chmod 600 {0} && mv -f {0} {1}", STAGED, AUTHORIZED_KEYS))
+        .with_context(|| format!("Failed to replace ~/{}", AUTHORIZED_KEYS))?;
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::TransferReport;
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIPLWDCHnqG7O4c/C88yWbYjHqCmKjuRalSA2+TSulwDN";
+
+    #[test]
+    fn test_key_data() {
+        assert_eq!(key_data(&format!("ssh-ed25519 {} laptop", KEY)), Some(KEY));
+        assert_eq!(key_data(&format!("command=\"uptime\",no-pty ssh-ed25519 {}", KEY)), Some(KEY));
+        assert_eq!(key_data("ecdsa-sha2-nistp256 AAAAE2 x"), Some("AAAAE2"));
+        assert_eq!(key_data("# ssh-ed25519 AAAA"), None);
+        assert_eq!(key_data("   "), None);
+    }
+
+    #[test]
+    fn test_add_line_dedupes_by_key() {
+        let line = format!("ssh-ed25519 {} deploy", KEY);
+        assert_eq!(add_line("", &line).unwrap(), format!("{}\n", line));
+        assert_eq!(add_line("ssh-rsa AAAAB3 old", &line).unwrap(), format!("ssh-rsa AAAAB3 old\n{}\n", line));
+        let existing = format!("from=\"10.0.0.0/8\" ssh-ed25519 {} other-comment\n", KEY);
+        assert_eq!(add_line(&existing, &line), None);
+    }
+
+    #[test]
+    fn test_remove_key_keeps_other_lines() {
+        let content = format!("# keys\nssh-rsa AAAAB3 old\nssh-ed25519 {0} a\nno-pty ssh-ed25519 {0} b\n", KEY);
+        assert_eq!(remove_key(&content, KEY), ("# keys\nssh-rsa AAAAB3 old\n".to_string(), 2));
+        assert_eq!(remove_key("ssh-rsa AAAAB3 old", KEY), ("ssh-rsa AAAAB3 old".to_string(), 0));
+    }
+
+    /// Remote with one `authorized_keys`, logging the commands it runs
+    #[derive(Default)]
+    struct FakeRemote {
+        content: RefCell<String>,
+        commands: RefCell<Vec<String>>,
+    }
+
+    impl Remote for FakeRemote {
+        fn execute_command(&self, command: &str) -> Result<String> {
+            self.commands.borrow_mut().push(command.to_string());
+            Ok(if command.contains("cat ") { self.content.borrow().clone() } else { String::new() })
+        }
+
+        fn exec(&self, _command: &str, _output: &mut dyn Write) -> Result<i32> {
+            unreachable!("authorized_keys edits only use execute_command")
+        }
+
+        fn download(&self, _path: &str, _output: &mut dyn Write) -> Result<TransferReport> {
+            unreachable!("authorized_keys is read with cat")
+        }
+
+        fn upload(&self, input: &mut dyn Read, path: &str) -> Result<TransferReport> {
+            assert_eq!(path, STAGED);
+            let mut content = String::new();
+            input.read_to_string(&mut content)?;
+            *self.content.borrow_mut() = content;
+            Ok(TransferReport { path: path.to_string(), bytes: 0, sha256: String::new(), verified: false })
+        }
+    }
+
+    #[test]
+    fn test_authorize_and_revoke() {
+        let remote = FakeRemote::default();
+        let line = format!("ssh-ed25519 {} deploy", KEY);
+
+        assert_eq!(authorize(&remote, &line).unwrap(), Change::Added);
+        assert_eq!(*remote.content.borrow(), format!("{}\n", line));
+        let commands = remote.commands.take();
+        assert!(commands[0].ends_with(&format!("mkdir {}", LOCK)));
+        assert!(commands[2].contains(&format!("cp -p {} {}", AUTHORIZED_KEYS, BACKUP)));
+        assert!(commands[3].contains(&format!("mv -f {} {}", STAGED, AUTHORIZED_KEYS)));
+        assert_eq!(commands.last().unwrap(), &format!("rmdir {}", LOCK));
+
+        assert_eq!(authorize(&remote, &line).unwrap(), Change::AlreadyPresent);
+        assert!(!remote.commands.take().iter().any(|command| command.contains("mv -f")));
+
+        assert_eq!(revoke(&remote, KEY).unwrap(), Change::Removed(1));
+        assert_eq!(*remote.content.borrow(), "");
+        assert_eq!(revoke(&remote, KEY).unwrap(), Change::NotPresent);
+        assert!(authorize(&remote, "not a key").is_err());
+    }
+}
@@ -0,0 +1,452 @@
+//! Automatic local forwards for ports opened during an interactive session, as
+//! remote-development editors do: dev servers announcing "listening on port 3000"
+//! in their output, and optionally any new listening socket `ss` reports, get a
+//! listener on localhost that tunnels to them over `direct-tcpip` channels.
+//! Everything runs on the session loop's thread, polled between terminal reads.
+use anyhow::Result;
+use log::{debug, info, warn};
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::ssh_client::{ShellSession, SshClient, WriteOutcome};
+use crate::terminal::SessionHook;
+
+/// How often `--scan-ports` checks the server's listening sockets
+pub const DEFAULT_SCAN_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Lists listening TCP sockets: `ss` on Linux, `netstat` elsewhere
+pub const SCAN_COMMAND: &str = "ss -ltnH 2>/dev/null || netstat -ltn 2>/dev/null";
+
+/// Ports below this are system services, never dev servers
+pub(crate) const MIN_PORT: u16 = 1024;
+
+/// Longest output line kept while waiting for its newline
+const MAX_LINE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoForwardOptions {
+    /// Also poll the server's listening sockets this often
+    pub scan_interval: Option<Duration>,
+}
+
+/// A forward that was set up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forwarded {
+    pub local_port: u16,
+    /// `localhost`, or the address a scanned socket is bound to
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+/// Ports a line of terminal output announces: `localhost:3000`, `0.0.0.0:8080`,
+/// `[::]:5173`, "listening on port 4000", "running on 8000"
+pub fn detect_ports(line: &str) -> Vec<u16> {
+    static PATTERNS: OnceLock<[Regex; 2]> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r"(?i)(?:localhost|127\.0\.0\.1|0\.0\.0\.0|\[::1?\]|\*):(\d{4,5})\b").unwrap(),
+            Regex::new(
+                r"(?i)\b(?:listen(?:ing)?|running|serving|started)\b[^\r\n]{0,40}?\b(?:port|on)\s*:?\s*(\d{4,5})\b",
+            )
+            .unwrap(),
+        ]
+    });
+    let mut ports = Vec::new();
+    for captures in patterns.iter().flat_map(|pattern| pattern.captures_iter(line)) {
+        if let Ok(port) = captures[1].parse::<u16>() {
+            if port >= MIN_PORT && !ports.contains(&port) {
+                ports.push(port);
+            }
+        }
+    }
+    ports
+}
+
+/// Listening sockets in `ss -ltnH` or `netstat -ltn` output, as the host to
+/// forward to and the port. Wildcard and loopback binds map to `localhost`.
+pub fn parse_listening(output: &str) -> Vec<(String, u16)> {
+    let mut sockets = Vec::new();
+    for line in output.lines().filter(|line| line.contains("LISTEN")) {
+        let local = line.split_whitespace().find_map(|field| {
+            let (address, port) = field.rsplit_once(':')?;
+            Some((address, port.parse::<u16>().ok()?))
+        });
+        let Some((address, port)) = local else { continue };
+        if port < MIN_PORT {
+            continue;
+        }
+        // `127.0.0.53%lo` names the interface
+        let address = address.split('%').next().unwrap_or(address);
+        let host = match address.trim_start_matches('[').trim_end_matches(']') {
+            "" | "*" | "0.0.0.0" | "::" | "127.0.0.1" | "::1" => "localhost".to_string(),
+            other => other.to_string(),
+        };
+        if !sockets.contains(&(host.clone(), port)) {
+            sockets.push((host, port));
+        }
+    }
+    sockets
+}
+
+/// Watches an interactive session for new ports and forwards them
+pub struct AutoForward {
+    client: Rc<SshClient>,
+    options: AutoForwardOptions,
+    on_forward: Box<dyn FnMut(&Forwarded)>,
+    line: Vec<u8>,
+    /// Remote ports already forwarded, failed, or listening before the first scan
+    seen: HashSet<u16>,
+    listeners: Vec<(TcpListener, String, u16)>,
+    connections: Vec<Connection>,
+    scan: Option<Scan>,
+    next_scan: Instant,
+    baseline: bool,
+}
+
+impl AutoForward {
+    /// Open forwards through `client`, calling `on_forward` for each
+    pub fn new(
+        client: Rc<SshClient>,
+        options: AutoForwardOptions,
+        on_forward: impl FnMut(&Forwarded) + 'static,
+    ) -> Self {
+        Self {
+            client,
+            options,
+            on_forward: Box::new(on_forward),
+            line: Vec::new(),
+            seen: HashSet::new(),
+            listeners: Vec::new(),
+            connections: Vec::new(),
+            scan: None,
+            next_scan: Instant::now(),
+            baseline: true,
+        }
+    }
+
+    /// Carry on over `client` after the connection was re-dialled. The local listeners
+    /// stay bound, so connections made meanwhile wait in their backlog; those that
+    /// were open went down with the old connection.
+    pub fn reconnect(&mut self, client: Rc<SshClient>) {
+        self.client = client;
+        self.connections.clear();
+        self.scan = None;
+    }
+
+    /// Listen on the same port locally, or any free one if it is taken
+    fn forward(&mut self, remote_host: &str, remote_port: u16) {
+        if !self.seen.insert(remote_port) {
+            return;
+        }
+        let bound = TcpListener::bind(("127.0.0.1", remote_port)).or_else(|_| TcpListener::bind("127.0.0.1:0"));
+        let listener = match bound {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Cannot listen locally for remote port {}: {}", remote_port, e);
+                return;
+            }
+        };
+        let local_port = match listener.set_nonblocking(true).and_then(|_| listener.local_addr()) {
+            Ok(address) => address.port(),
+            Err(e) => {
+                warn!("Cannot listen locally for remote port {}: {}", remote_port, e);
+                return;
+            }
+        };
+        info!("Forwarding localhost:{} to {}:{}", local_port, remote_host, remote_port);
+        (self.on_forward)(&Forwarded { local_port, remote_host: remote_host.to_string(), remote_port });
+        self.listeners.push((listener, remote_host.to_string(), remote_port));
+    }
+
+    fn scan_sockets(&mut self, interval: Duration) -> bool {
+        if self.scan.is_none() {
+            if Instant::now() < self.next_scan {
+                return false;
+            }
+            self.next_scan = Instant::now() + interval;
+            match self.client.open_exec(SCAN_COMMAND) {
+                Ok(channel) => self.scan = Some(Scan { channel, output: Vec::new() }),
+                Err(e) => debug!("Cannot scan listening sockets: {:#}", e),
+            }
+            return false;
+        }
+
+        let Some(scan) = self.scan.as_mut() else { return false };
+        match scan.poll() {
+            Ok(false) => return false,
+            Ok(true) => {
+                let sockets = parse_listening(&String::from_utf8_lossy(&scan.output));
+                if std::mem::take(&mut self.baseline) {
+                    // Already listening when the session started: not ours to forward
+                    self.seen.extend(sockets.iter().map(|(_, port)| *port));
+                } else {
+                    for (host, port) in sockets {
+                        self.forward(&host, port);
+                    }
+                }
+            }
+            Err(e) => debug!("Scanning listening sockets failed: {:#}", e),
+        }
+        self.scan = None;
+        true
+    }
+
+    fn accept(&mut self) -> bool {
+        let mut accepted = false;
+        for (listener, host, port) in &self.listeners {
+            while let Ok((stream, peer)) = listener.accept() {
+                accepted = true;
+                debug!("Connection from {} for {}:{}", peer, host, port);
+                if let Err(e) = stream.set_nonblocking(true) {
+                    debug!("Dropping connection from {}: {}", peer, e);
+                    continue;
+                }
+                match self.client.open_direct_tcpip(host, *port) {
+                    Ok(channel) => self.connections.push(Connection::new(stream, channel)),
+                    Err(e) => warn!("Cannot reach {}:{} on the server: {:#}", host, port, e),
+                }
+            }
+        }
+        accepted
+    }
+}
+
+impl SessionHook for AutoForward {
+    fn on_output(&mut self, data: &[u8]) {
+        for &byte in data {
+            if byte != b'\n' {
+                if self.line.len() < MAX_LINE {
+                    self.line.push(byte);
+                }
+                continue;
+            }
+            let line = crate::sanitize::strip_escape_sequences(&String::from_utf8_lossy(&self.line));
+            self.line.clear();
+            for port in detect_ports(&line) {
+                self.forward("localhost", port);
+            }
+        }
+    }
+
+    fn poll(&mut self) -> bool {
+        let mut activity = match self.options.scan_interval {
+            Some(interval) => self.scan_sockets(interval),
+            None => false,
+        };
+        activity |= self.accept();
+        self.connections.retain_mut(|connection| match connection.pump() {
+            Ok(moved) => {
+                activity |= moved;
+                !connection.is_done()
+            }
+            Err(e) => {
+                debug!("Forwarded connection failed: {:#}", e);
+                false
+            }
+        });
+        activity
+    }
+}
+
+/// A running `SCAN_COMMAND`, read as its output arrives
+struct Scan {
+    channel: Box<dyn ShellSession>,
+    output: Vec<u8>,
+}
+
+impl Scan {
+    /// Read what's available; true once the command has finished
+    fn poll(&mut self) -> Result<bool> {
+        let mut buffer = [0u8; 8192];
+        loop {
+            match self.channel.read(&mut buffer)? {
+                0 => return Ok(self.channel.is_eof()),
+                n => self.output.extend_from_slice(&buffer[..n]),
+            }
+        }
+    }
+}
+
+/// One local connection tunnelled over its own channel; remote forwards reuse it
+/// for the connections the server hands back
+pub(crate) struct Connection {
+    local: TcpStream,
+    channel: Box<dyn ShellSession>,
+    to_remote: Vec<u8>,
+    to_local: Vec<u8>,
+    local_open: bool,
+    remote_open: bool,
+}
+
+impl Connection {
+    pub(crate) fn new(local: TcpStream, channel: Box<dyn ShellSession>) -> Self {
+        Self { local, channel, to_remote: Vec::new(), to_local: Vec::new(), local_open: true, remote_open: true }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        !self.remote_open && self.to_local.is_empty()
+    }
+
+    /// Move what data is ready in each direction without blocking; true if any moved
+    pub(crate) fn pump(&mut self) -> Result<bool> {
+        let mut buffer = [0u8; 32768];
+        let mut moved = false;
+
+        if self.to_remote.is_empty() && self.local_open {
+            match self.local.read(&mut buffer) {
+                Ok(0) => {
+                    self.local_open = false;
+                    self.channel.send_eof()?;
+                }
+                Ok(n) => self.to_remote.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if !self.to_remote.is_empty() {
+            if let WriteOutcome::Written(n) = self.channel.try_write(&self.to_remote)? {
+                self.to_remote.drain(..n);
+                moved = true;
+            }
+        }
+
+        if self.to_local.is_empty() && self.remote_open {
+            match self.channel.read(&mut buffer)? {
+                0 if self.channel.is_eof() => {
+                    self.remote_open = false;
+                    // The client may already be gone
+                    let _ = self.local.shutdown(Shutdown::Write);
+                }
+                0 => {}
+                n => self.to_local.extend_from_slice(&buffer[..n]),
+            }
+        }
+        if !self.to_local.is_empty() {
+            match self.local.write(&self.to_local) {
+                Ok(n) => {
+                    self.to_local.drain(..n);
+                    moved = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Script, ScriptedSshServer};
+    use std::cell::RefCell;
+
+    fn client(server: ScriptedSshServer) -> Rc<SshClient> {
+        let mut client = SshClient::new(Box::new(server.accept_key("alice")));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("alice", "/tmp/key").unwrap();
+        Rc::new(client)
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn test_detect_ports() {
+        assert_eq!(detect_ports("  VITE v5.0.0  ready in 300 ms  ➜  Local:   http://localhost:5173/"), [5173]);
+        assert_eq!(detect_ports("Listening on port 3000"), [3000]);
+        assert_eq!(detect_ports("Server running on 8000, also on http://0.0.0.0:8000"), [8000]);
+        assert_eq!(detect_ports("Serving HTTP on [::] port 8080 (http://[::]:8080/) ..."), [8080]);
+        assert_eq!(detect_ports("app listening at http://127.0.0.1:4000 and [::1]:4001"), [4000, 4001]);
+        assert!(detect_ports("listening on port 80").is_empty());
+        assert!(detect_ports("Compiled 3000 modules in 5173ms").is_empty());
+        assert!(detect_ports("ssh alice@example.com:2222").is_empty());
+    }
+
+    #[test]
+    fn test_parse_listening() {
+        let ss = "LISTEN 0      4096   127.0.0.53%lo:53        0.0.0.0:*\n\
+                  LISTEN 0      511          0.0.0.0:3000      0.0.0.0:*\n\
+                  LISTEN 0      128             [::]:8080         [::]:*\n\
+                  LISTEN 0      128        10.0.0.5:9090      0.0.0.0:*\n\
+                  LISTEN 0      511                *:3000            *:*\n";
+        assert_eq!(
+            parse_listening(ss),
+            [("localhost".to_string(), 3000), ("localhost".to_string(), 8080), ("10.0.0.5".to_string(), 9090)]
+        );
+
+        let netstat = "Active Internet connections (only servers)\n\
+                       Proto Recv-Q Send-Q Local Address           Foreign Address         State\n\
+                       tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN\n\
+                       tcp6       0      0 :::5432                 :::*                    LISTEN\n";
+        assert_eq!(parse_listening(netstat), [("localhost".to_string(), 5432)]);
+    }
+
+    #[test]
+    fn test_forwards_announced_port() {
+        let port = free_port();
+        let service = Script::new().expect("GET / HTTP/1.0\r\n\r\n").send("HTTP/1.0 200 OK\r\n\r\nhello").eof();
+        let forwarded = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&forwarded);
+        let mut hook = AutoForward::new(
+            client(ScriptedSshServer::new().forward("localhost", port, service)),
+            AutoForwardOptions { scan_interval: None },
+            move |forward| log.borrow_mut().push(forward.clone()),
+        );
+
+        hook.on_output(format!("\x1b[32mready\x1b[0m on http://localhost:{}", port).as_bytes());
+        assert!(forwarded.borrow().is_empty(), "waits for the end of the line");
+        hook.on_output(b"/\r\n");
+        hook.on_output(format!("listening on port {}\n", port).as_bytes());
+        let expected = Forwarded { local_port: port, remote_host: "localhost".into(), remote_port: port };
+        assert_eq!(*forwarded.borrow(), [expected]);
+
+        let mut local = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        local.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        local.set_nonblocking(true).unwrap();
+        let mut response = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            hook.poll();
+            match local.read_to_end(&mut response) {
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => panic!("{}", e),
+            }
+            assert!(Instant::now() < deadline, "no response");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(response, b"HTTP/1.0 200 OK\r\n\r\nhello");
+        assert!(hook.connections.is_empty());
+    }
+
+    #[test]
+    fn test_scan_forwards_only_new_sockets() {
+        let (old, new) = (free_port(), free_port());
+        let listening = |ports: &[u16]| {
+            ports.iter().map(|port| format!("LISTEN 0 511 0.0.0.0:{} 0.0.0.0:*\n", port)).collect::<String>()
+        };
+        let mut hook = AutoForward::new(
+            client(ScriptedSshServer::new().command(SCAN_COMMAND, Script::new().send(listening(&[old])).eof())),
+            AutoForwardOptions { scan_interval: Some(Duration::ZERO) },
+            |_| {},
+        );
+        hook.poll();
+        hook.poll();
+        assert!(hook.seen.contains(&old));
+        assert!(hook.listeners.is_empty(), "sockets from before the session are left alone");
+
+        let rescan = Script::new().send(listening(&[old, new])).eof();
+        hook.client = client(ScriptedSshServer::new().command(SCAN_COMMAND, rescan));
+        hook.poll();
+        hook.poll();
+        assert_eq!(hook.listeners.len(), 1);
+        assert_eq!(hook.listeners[0].2, new);
+    }
+}
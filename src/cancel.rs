@@ -0,0 +1,193 @@
+//! Cancellation for blocking client operations. A [`CancelToken`] is shared between
+//! the code running an operation and whoever may stop it, such as a GUI's Cancel
+//! button or a deadline.
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type Handler = Box<dyn FnOnce() + Send>;
+
+/// Cancelled by [`CancelToken::cancel`] or once its deadline passes. Clones share
+/// the same state.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+    /// Run once on `cancel`, e.g. to shut down a socket a blocking call waits on
+    handlers: Mutex<Vec<Handler>>,
+}
+
+impl std::fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.inner.cancelled.load(Ordering::SeqCst))
+            .field("deadline", &self.inner.deadline)
+            .finish()
+    }
+}
+
+/// Why an operation stopped early; find it with `error.downcast_ref::<Cancelled>()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled {
+    /// The deadline passed, rather than `cancel` being called
+    pub timed_out: bool,
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.timed_out { "Operation timed out" } else { "Operation cancelled" })
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that fires at `deadline`
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            inner: Arc::new(Inner { deadline: Some(deadline), ..Inner::default() }),
+        }
+    }
+
+    /// A token that fires `timeout` from now
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    /// Stop the operations using this token and run the registered handlers
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let handlers = std::mem::take(&mut *self.inner.handlers.lock().unwrap_or_else(|e| e.into_inner()));
+        for handler in handlers {
+            handler();
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// Time left before the deadline; `None` without one
+    pub fn remaining(&self) -> Option<Duration> {
+        self.inner.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Why the token fired, if it has
+    pub fn reason(&self) -> Option<Cancelled> {
+        if self.inner.cancelled.load(Ordering::SeqCst) {
+            Some(Cancelled { timed_out: false })
+        } else if self.remaining() == Some(Duration::ZERO) {
+            Some(Cancelled { timed_out: true })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    /// `Err(Cancelled)` once the token has fired
+    pub fn check(&self) -> Result<()> {
+        match self.reason() {
+            Some(cancelled) => Err(cancelled.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Run `handler` when `cancel` is called, or now if it already was. Deadlines
+    /// don't run handlers; backends turn them into I/O timeouts instead.
+    pub fn on_cancel(&self, handler: impl FnOnce() + Send + 'static) {
+        let mut handlers = self.inner.handlers.lock().unwrap_or_else(|e| e.into_inner());
+        if self.inner.cancelled.load(Ordering::SeqCst) {
+            drop(handlers);
+            handler();
+        } else {
+            handlers.push(Box::new(handler));
+        }
+    }
+}
+
+/// Run `operation` unless `token` has fired. An error from an operation the token
+/// interrupted carries [`Cancelled`] as context.
+pub fn cancellable<T>(token: Option<&CancelToken>, operation: impl FnOnce() -> Result<T>) -> Result<T> {
+    let Some(token) = token else {
+        return operation();
+    };
+    token.check()?;
+    operation().map_err(|e| match token.reason() {
+        Some(cancelled) => e.context(cancelled),
+        None => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_runs_handlers_once() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        let ran = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&ran);
+        token.on_cancel(move || *counter.lock().unwrap() += 1);
+        assert!(token.check().is_ok());
+
+        clone.cancel();
+        clone.cancel();
+        assert_eq!(*ran.lock().unwrap(), 1);
+        assert_eq!(token.reason(), Some(Cancelled { timed_out: false }));
+
+        // Registered after the fact: runs straight away
+        let counter = Arc::clone(&ran);
+        token.on_cancel(move || *counter.lock().unwrap() += 1);
+        assert_eq!(*ran.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let token = CancelToken::with_timeout(Duration::from_secs(60));
+        assert!(!token.is_cancelled());
+        assert!(token.remaining().unwrap() > Duration::from_secs(50));
+
+        let expired = CancelToken::with_deadline(Instant::now());
+        let err = expired.check().unwrap_err();
+        assert_eq!(err.downcast_ref::<Cancelled>(), Some(&Cancelled { timed_out: true }));
+        assert_eq!(err.to_string(), "Operation timed out");
+        assert!(CancelToken::new().remaining().is_none());
+    }
+
+    #[test]
+    fn test_cancellable() {
+        assert_eq!(cancellable(None, || Ok(1)).unwrap(), 1);
+
+        let token = CancelToken::new();
+        assert_eq!(cancellable(Some(&token), || Ok(2)).unwrap(), 2);
+        let err = cancellable::<()>(Some(&token), || {
+            token.cancel();
+            Err(anyhow::anyhow!("Connection reset"))
+        })
+        .unwrap_err();
+        assert!(err.downcast_ref::<Cancelled>().is_some());
+        assert_eq!(format!("{:#}", err), "Operation cancelled: Connection reset");
+
+        let mut ran = false;
+        assert!(cancellable(Some(&token), || {
+            ran = true;
+            Ok(())
+        })
+        .is_err());
+        assert!(!ran, "nothing starts after cancellation");
+    }
+}
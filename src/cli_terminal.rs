@@ -1,18 +1,33 @@
 use anyhow::{Context, Result};
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEvent},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
     terminal::{disable_raw_mode, enable_raw_mode},
 };
 use log::{error, info};
 use std::io::{self, Write};
 use std::time::Duration;
 
-use crate::terminal::TerminalIO;
+use crate::terminal::{edit_line, with_carriage_returns, KeyBindings, LineInput, TerminalIO};
+
+/// Save and restore the window title on xterm's title stack (`XTPUSHTITLE`/`XTPOPTITLE`)
+const PUSH_TITLE: &str = "\x1b[22;0t";
+const POP_TITLE: &str = "\x1b[23;0t";
+
+/// Switch to and from the alternate screen, saving the cursor (`smcup`/`rmcup`)
+const ENTER_ALT_SCREEN: &str = "\x1b[?1049h";
+const LEAVE_ALT_SCREEN: &str = "\x1b[?1049l";
 
 /// CLI-specific terminal I/O implementation
 pub struct CliTerminalIO {
     should_continue: bool,
     raw_mode_enabled: bool,
+    title: Option<String>,
+    alt_screen: Option<bool>,
+    key_bindings: KeyBindings,
+    /// The line being typed, when lines are edited locally
+    line: Option<String>,
+    /// Ctrl+D was typed on an empty edited line
+    eof: bool,
 }
 
 impl CliTerminalIO {
@@ -20,7 +35,158 @@ impl CliTerminalIO {
         Self {
             should_continue: true,
             raw_mode_enabled: false,
+            title: None,
+            alt_screen: None,
+            key_bindings: KeyBindings::Default,
+            line: None,
+            eof: false,
+        }
+    }
+
+    /// Edit lines here and send them on Enter, for a shell without a remote PTY
+    /// to echo and edit them
+    pub fn with_line_editing(mut self) -> Self {
+        self.line = Some(String::new());
+        self
+    }
+
+    /// Input as it goes to the server: as typed, or whole lines when edited locally
+    fn typed(&mut self, input: Vec<u8>) -> Option<Vec<u8>> {
+        let Some(line) = &mut self.line else {
+            return Some(input);
+        };
+        let (echo, send) = edit_line(line, &input);
+        if !echo.is_empty() {
+            let _ = io::stdout().write_all(echo.as_bytes());
+            let _ = io::stdout().flush();
+        }
+        match send? {
+            LineInput::Line(line) => Some(line),
+            LineInput::Eof => {
+                self.eof = true;
+                None
+            }
+        }
+    }
+
+    /// `Some(true)` runs the session on the alternate screen; `Some(false)` drops
+    /// the remote side's switches to it
+    pub fn with_alt_screen(mut self, alt_screen: Option<bool>) -> Self {
+        self.alt_screen = alt_screen;
+        self
+    }
+
+    pub fn with_key_bindings(mut self, key_bindings: KeyBindings) -> Self {
+        self.key_bindings = key_bindings;
+        self
+    }
+
+    /// Set the window title to `title` for the session, pass the remote side's
+    /// title changes through, and restore the previous title on cleanup
+    pub fn with_title(mut self, title: String) -> Self {
+        self.title = Some(title);
+        self
+    }
+
+    /// Remote output as it is written to the local terminal, without the sequences
+    /// terminals can't handle and, as configured, title changes and alternate screen switches
+    pub fn filter_output(&self, data: &[u8]) -> Vec<u8> {
+        use log::debug;
+        
+        // Enhanced debugging for vim escape sequences
+        let data_str = String::from_utf8_lossy(data);
+        
+        // Check for problematic patterns that vim might send
+        if data_str.contains('\x1b') { // ESC character
+            let esc_count = data_str.matches('\x1b').count();
+            if esc_count > 5 {
+                debug!("High volume of escape sequences ({} ESC chars): {:?}", 
+                    esc_count,
+                    data_str.chars().take(100).collect::<String>()
+                        .replace('\x1b', "\\e")
+                        .replace('\r', "\\r")
+                        .replace('\n', "\\n"));
+            }
+        }
+        
+        // Check for vim-specific sequences that might cause display issues
+        if data_str.contains("\x1b[?1049h") || data_str.contains("\x1b[?1049l") {
+            debug!("Vim alternate screen buffer command detected");
+        }
+        
+        if data_str.contains("\x1b[?25l") || data_str.contains("\x1b[?25h") {
+            debug!("Vim cursor visibility command detected");
+        }
+        
+        // Filter out problematic sequences that vim sends but terminals can't handle properly
+        let mut filtered_data = data.to_vec();
+        let mut filtered = false;
+        
+        // Remove SGR mouse sequences
+        if data_str.contains("\x1b[>4;2m") || data_str.contains("\x1b[<") {
+            debug!("Filtering problematic mouse/terminal sequences");
+            let temp_str = data_str.replace("\x1b[>4;2m", "").replace("\x1b[<", "");
+            filtered_data = temp_str.into_bytes();
+            filtered = true;
         }
+        
+        // Remove specific vim color sequences that appear as literal text
+        if data_str.contains(":ffff/ffff/ffff\x07") || 
+           data_str.contains("]11;rgb:1e1e/1e1e/1e1e\x07") ||
+           data_str.contains("ffff/ffff/ffff") ||
+           data_str.contains("1e1e/1e1e/1e1e") {
+            debug!("Filtering vim color response sequences: {:?}", 
+                data_str.chars().take(50).collect::<String>());
+            
+            let mut temp_str = String::from_utf8_lossy(&filtered_data).to_string();
+            
+            // Remove the specific sequences you reported
+            temp_str = temp_str.replace(":ffff/ffff/ffff\x07", "");
+            temp_str = temp_str.replace("]11;rgb:1e1e/1e1e/1e1e\x07", "");
+            
+            // Remove similar patterns (hex color codes with ^G)
+            temp_str = remove_color_sequences(&temp_str);
+            
+            filtered_data = temp_str.into_bytes();
+            filtered = true;
+        }
+        
+        // Remove OSC sequences that start with ESC]
+        if data_str.contains("\x1b]") {
+            debug!("Filtering OSC sequences from vim");
+            let temp_str = String::from_utf8_lossy(&filtered_data).to_string();
+            filtered_data = remove_osc_sequences(&temp_str, self.title.is_some()).into_bytes();
+            filtered = true;
+        }
+        
+        if self.alt_screen == Some(false) && data_str.contains("\x1b[?") {
+            let temp_str = String::from_utf8_lossy(&filtered_data).to_string();
+            filtered_data = remove_alt_screen_switches(&temp_str).into_bytes();
+            filtered = true;
+        }
+
+        if filtered {
+            debug!("Filtered data, original length: {}, new length: {}", 
+                data.len(), filtered_data.len());
+        }
+        // Without a remote PTY nothing turns newlines into the CRLF a raw terminal needs
+        if self.line.is_some() {
+            filtered_data = with_carriage_returns(&filtered_data);
+        }
+        filtered_data
+    }
+}
+
+/// OSC 0 sequence setting the icon name and window title to `title`, without its
+/// control characters
+pub fn set_title_sequence(title: &str) -> String {
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    format!("\x1b]0;{}\x07", title)
+}
+
+impl Default for CliTerminalIO {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -31,11 +197,13 @@ impl TerminalIO for CliTerminalIO {
         // Non-blocking input polling
         if let Ok(true) = event::poll(Duration::from_millis(10)) {
             match event::read() {
+                // Windows consoles report releases too; only presses produce input
+                Ok(Event::Key(KeyEvent { kind: KeyEventKind::Release, .. })) => Ok(None),
                 Ok(Event::Key(KeyEvent {
                     code: KeyCode::Char('c'),
                     modifiers: event::KeyModifiers::CONTROL,
                     ..
-                })) => {
+                })) if self.key_bindings == KeyBindings::Default => {
                     info!("Ctrl+C pressed, exiting shell");
                     self.should_continue = false;
                     Ok(None)
@@ -46,6 +214,7 @@ impl TerminalIO for CliTerminalIO {
                         let ctrl_bytes = match code {
                             KeyCode::Char('a') => b"\x01".to_vec(), // Ctrl+A
                             KeyCode::Char('b') => b"\x02".to_vec(), // Ctrl+B  
+                            KeyCode::Char('c') => b"\x03".to_vec(), // Ctrl+C (passthrough bindings)
                             KeyCode::Char('d') => b"\x04".to_vec(), // Ctrl+D
                             KeyCode::Char('e') => b"\x05".to_vec(), // Ctrl+E
                             KeyCode::Char('f') => b"\x06".to_vec(), // Ctrl+F
@@ -69,10 +238,11 @@ impl TerminalIO for CliTerminalIO {
                             KeyCode::Char('x') => b"\x18".to_vec(), // Ctrl+X
                             KeyCode::Char('y') => b"\x19".to_vec(), // Ctrl+Y
                             KeyCode::Char('z') => b"\x1a".to_vec(), // Ctrl+Z
+                            KeyCode::Char(']') => b"\x1d".to_vec(), // Ctrl+] (tab commands in `bxssh tabs`)
                             _ => return Ok(None), // Ignore other Ctrl combinations
                         };
                         debug!("Ctrl+{:?} pressed -> bytes: {:?}", code, String::from_utf8_lossy(&ctrl_bytes));
-                        return Ok(Some(ctrl_bytes));
+                        return Ok(self.typed(ctrl_bytes));
                     }
                     let input_bytes = match code {
                         KeyCode::Enter => b"\r".to_vec(),
@@ -109,7 +279,7 @@ impl TerminalIO for CliTerminalIO {
                         }
                     };
                     debug!("Key pressed: {:?} -> bytes: {:?}", code, String::from_utf8_lossy(&input_bytes));
-                    Ok(Some(input_bytes))
+                    Ok(self.typed(input_bytes))
                 }
                 Ok(event) => {
                     debug!("Non-key event: {:?}", event);
@@ -127,78 +297,7 @@ impl TerminalIO for CliTerminalIO {
     }
     
     fn write_output(&mut self, data: &[u8]) -> Result<()> {
-        use log::debug;
-        
-        // Enhanced debugging for vim escape sequences
-        let data_str = String::from_utf8_lossy(data);
-        
-        // Check for problematic patterns that vim might send
-        if data_str.contains('\x1b') { // ESC character
-            let esc_count = data_str.matches('\x1b').count();
-            if esc_count > 5 {
-                debug!("High volume of escape sequences ({} ESC chars): {:?}", 
-                    esc_count,
-                    data_str.chars().take(100).collect::<String>()
-                        .replace('\x1b', "\\e")
-                        .replace('\r', "\\r")
-                        .replace('\n', "\\n"));
-            }
-        }
-        
-        // Check for vim-specific sequences that might cause display issues
-        if data_str.contains("\x1b[?1049h") || data_str.contains("\x1b[?1049l") {
-            debug!("Vim alternate screen buffer command detected");
-        }
-        
-        if data_str.contains("\x1b[?25l") || data_str.contains("\x1b[?25h") {
-            debug!("Vim cursor visibility command detected");
-        }
-        
-        // Filter out problematic sequences that vim sends but terminals can't handle properly
-        let mut filtered_data = data.to_vec();
-        let mut filtered = false;
-        
-        // Remove SGR mouse sequences
-        if data_str.contains("\x1b[>4;2m") || data_str.contains("\x1b[<") {
-            debug!("Filtering problematic mouse/terminal sequences");
-            let temp_str = data_str.replace("\x1b[>4;2m", "").replace("\x1b[<", "");
-            filtered_data = temp_str.into_bytes();
-            filtered = true;
-        }
-        
-        // Remove specific vim color sequences that appear as literal text
-        if data_str.contains(":ffff/ffff/ffff\x07") || 
-           data_str.contains("]11;rgb:1e1e/1e1e/1e1e\x07") ||
-           data_str.contains("ffff/ffff/ffff") ||
-           data_str.contains("1e1e/1e1e/1e1e") {
-            debug!("Filtering vim color response sequences: {:?}", 
-                data_str.chars().take(50).collect::<String>());
-            
-            let mut temp_str = String::from_utf8_lossy(&filtered_data).to_string();
-            
-            // Remove the specific sequences you reported
-            temp_str = temp_str.replace(":ffff/ffff/ffff\x07", "");
-            temp_str = temp_str.replace("]11;rgb:1e1e/1e1e/1e1e\x07", "");
-            
-            // Remove similar patterns (hex color codes with ^G)
-            temp_str = remove_color_sequences(&temp_str);
-            
-            filtered_data = temp_str.into_bytes();
-            filtered = true;
-        }
-        
-        // Remove OSC sequences that start with ESC]
-        if data_str.contains("\x1b]") {
-            debug!("Filtering OSC sequences from vim");
-            let temp_str = String::from_utf8_lossy(&filtered_data).to_string();
-            filtered_data = remove_osc_sequences(&temp_str).into_bytes();
-            filtered = true;
-        }
-        
-        if filtered {
-            debug!("Filtered data, original length: {}, new length: {}", 
-                data.len(), filtered_data.len());
-        }
+        let filtered_data = self.filter_output(data);
         
         // Write data directly to stdout - let the terminal handle escape sequences
         io::stdout().write_all(&filtered_data)
@@ -215,8 +314,17 @@ impl TerminalIO for CliTerminalIO {
     fn initialize(&mut self) -> Result<()> {
         use crossterm::{execute, cursor, terminal};
         
+        // Remote output is VT100; older Windows consoles need it switched on explicitly
+        #[cfg(windows)]
+        if !crossterm::ansi_support::supports_ansi() {
+            log::warn!("Console does not support ANSI escape sequences; remote output may be garbled");
+        }
+
         enable_raw_mode().context("Failed to enable raw mode")?;
         self.raw_mode_enabled = true;
+        if self.alt_screen == Some(true) {
+            write!(io::stdout(), "{}", ENTER_ALT_SCREEN)?;
+        }
         
         // Set up terminal for vim compatibility
         execute!(
@@ -225,9 +333,16 @@ impl TerminalIO for CliTerminalIO {
             cursor::MoveTo(0, 0),
         )?;
         
-        println!("🔗 Connected to remote server. Use Ctrl+C to exit.\r");
-        io::stdout().flush()?;
+        if !crate::ui::is_quiet() {
+            println!("{}\r", crate::ui::tr(crate::ui::Icon::Connected, crate::i18n::Msg::Connected, &[]));
+            io::stdout().flush()?;
+        }
         
+        if let Some(title) = &self.title {
+            write!(io::stdout(), "{}{}", PUSH_TITLE, set_title_sequence(title))?;
+            io::stdout().flush()?;
+        }
+
         // Small delay to let terminal settle
         std::thread::sleep(std::time::Duration::from_millis(100));
         
@@ -245,13 +360,27 @@ impl TerminalIO for CliTerminalIO {
                 terminal::Clear(terminal::ClearType::FromCursorDown)
             );
             
+            if self.alt_screen == Some(true) {
+                let _ = write!(io::stdout(), "{}", LEAVE_ALT_SCREEN);
+            }
             disable_raw_mode().context("Failed to disable raw mode")?;
             self.raw_mode_enabled = false;
         }
+
+        if self.title.take().is_some() {
+            print!("{}", POP_TITLE);
+            io::stdout().flush()?;
+        }
         
-        println!("\n🔌 Disconnected from remote server.");
+        if !crate::ui::is_quiet() {
+            println!("\n{}", crate::ui::tr(crate::ui::Icon::Session, crate::i18n::Msg::Disconnected, &[]));
+        }
         Ok(())
     }
+
+    fn take_eof(&mut self) -> bool {
+        std::mem::take(&mut self.eof)
+    }
 }
 
 impl Drop for CliTerminalIO {
@@ -262,6 +391,18 @@ impl Drop for CliTerminalIO {
     }
 }
 
+/// `input` without switches to or from the alternate screen (modes 47, 1047 and
+/// 1049), so full-screen programs draw into the normal screen and its scrollback
+fn remove_alt_screen_switches(input: &str) -> String {
+    let mut output = input.to_string();
+    for mode in ["1049", "1047", "47"] {
+        for end in ['h', 'l'] {
+            output = output.replace(&format!("\x1b[?{}{}", mode, end), "");
+        }
+    }
+    output
+}
+
 /// Remove hex color sequences in the format xxxx/xxxx/xxxx followed by BEL (^G)
 fn remove_color_sequences(input: &str) -> String {
     let mut result = String::new();
@@ -345,7 +486,9 @@ fn is_hex_color_sequence(s: &str) -> bool {
 
 /// Remove OSC (Operating System Command) sequences
 /// These start with ESC] and end with BEL (^G) or ST (ESC\)
-fn remove_osc_sequences(input: &str) -> String {
+/// With `keep_titles`, title changes (OSC 0, 1 and 2) are kept, rewritten without
+/// control characters
+fn remove_osc_sequences(input: &str, keep_titles: bool) -> String {
     let mut result = String::new();
     let mut chars = input.chars().peekable();
     
@@ -355,19 +498,21 @@ fn remove_osc_sequences(input: &str) -> String {
             if let Some(&']') = chars.peek() {
                 chars.next(); // consume ']'
                 
-                // Skip until BEL (^G) or ST (ESC\)
+                // Collect until BEL (^G) or ST (ESC\)
+                let mut body = String::new();
                 while let Some(next_ch) = chars.next() {
                     if next_ch == '\x07' { // BEL
                         break;
-                    } else if next_ch == '\x1b' {
-                        // Check for ST (ESC\)
-                        if let Some(&'\\') = chars.peek() {
-                            chars.next(); // consume '\'
-                            break;
-                        } else {
-                            // Put back the ESC and continue
-                            result.push(next_ch);
-                        }
+                    } else if next_ch == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next(); // consume '\'
+                        break;
+                    }
+                    body.push(next_ch);
+                }
+                if keep_titles {
+                    if let Some((kind @ ("0" | "1" | "2"), title)) = body.split_once(';') {
+                        let title: String = title.chars().filter(|c| !c.is_control()).collect();
+                        result.push_str(&format!("\x1b]{};{}\x07", kind, title));
                     }
                 }
                 continue;
@@ -432,16 +577,90 @@ mod tests {
     #[test]
     fn test_remove_osc_sequences() {
         let input = "Before\x1b]11;rgb:ffff/ffff/ffff\x07After";
-        let result = remove_osc_sequences(input);
+        let result = remove_osc_sequences(input, false);
         assert_eq!(result, "BeforeAfter");
         
         let input = "Test\x1b]11;some-sequence\x1b\\More";
-        let result = remove_osc_sequences(input);
+        let result = remove_osc_sequences(input, false);
         assert_eq!(result, "TestMore");
         
         // Should not remove regular escape sequences
         let input = "Text\x1b[31mRed\x1b[0mNormal";
-        let result = remove_osc_sequences(input);
+        let result = remove_osc_sequences(input, false);
         assert_eq!(result, input);
     }
+
+    #[test]
+    fn test_title_changes_pass_through_when_kept() {
+        let input = "a\x1b]2;vim main.rs\x1b\\b\x1b]11;rgb:ffff/ffff/ffff\x07c";
+        assert_eq!(remove_osc_sequences(input, false), "abc");
+        assert_eq!(remove_osc_sequences(input, true), "a\x1b]2;vim main.rs\x07bc");
+        assert_eq!(remove_osc_sequences("\x1b]0;x\ty\x07", true), "\x1b]0;xy\x07");
+        assert_eq!(remove_osc_sequences("\x1b]52;c;ZXZpbA==\x07", true), "");
+    }
+
+    #[test]
+    fn test_remove_alt_screen_switches() {
+        let input = "\x1b[?1049h\x1b[?25lvim\x1b[?47l\x1b[?1047h~\x1b[?1049l$ ";
+        assert_eq!(remove_alt_screen_switches(input), "\x1b[?25lvim~$ ");
+        assert_eq!(remove_alt_screen_switches("\x1b[?2004h$ "), "\x1b[?2004h$ ");
+    }
+
+    #[test]
+    fn test_line_editing_adds_carriage_returns() {
+        let terminal = CliTerminalIO::new().with_line_editing();
+        assert_eq!(terminal.filter_output(b"a\nb\r\nc\n"), b"a\r\nb\r\nc\r\n");
+        assert_eq!(CliTerminalIO::new().filter_output(b"a\nb"), b"a\nb");
+    }
+
+    /// Up to 60 bytes of `bytes` from a little before `at`, escaped
+    fn escaped_around(bytes: &[u8], at: usize) -> String {
+        let context = bytes[at.saturating_sub(20)..].iter().take(60);
+        context.flat_map(|&byte| std::ascii::escape_default(byte)).map(char::from).collect()
+    }
+
+    /// Captures of real programs in `tests/fixtures/terminal`, in the `--dump-io` format
+    const REPLAYS: &[&str] = &["vim-startup", "top", "tmux"];
+
+    /// Feed each capture's output through `filter_output` chunk by chunk, as the
+    /// session loop does, and compare what reaches the terminal with `NAME.VARIANT.out`.
+    /// After an intended change to the filtering, rewrite those files with
+    /// `BXSSH_UPDATE_REPLAYS=1 cargo test replay` and review the diff.
+    #[test]
+    fn test_replay_captures() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/terminal");
+        let update = std::env::var_os("BXSSH_UPDATE_REPLAYS").is_some();
+        let variants = [
+            ("default", CliTerminalIO::new()),
+            ("no-alt-screen", CliTerminalIO::new().with_title("bxssh".to_string()).with_alt_screen(Some(false))),
+        ];
+        for name in REPLAYS {
+            let dump = std::fs::read_to_string(dir.join(format!("{}.hex", name))).unwrap();
+            let chunks = crate::dump_io::read_dump(&dump).unwrap();
+            for (variant, terminal) in &variants {
+                let rendered: Vec<u8> = chunks
+                    .iter()
+                    .filter(|(direction, _)| *direction == crate::dump_io::Direction::Received)
+                    .flat_map(|(_, data)| terminal.filter_output(data))
+                    .collect();
+                let path = dir.join(format!("{}.{}.out", name, variant));
+                if update {
+                    std::fs::write(&path, &rendered).unwrap();
+                    continue;
+                }
+                let expected = std::fs::read(&path).unwrap();
+                if let Some(at) = rendered.iter().zip(&expected).position(|(a, b)| a != b) {
+                    let (got, want) = (escaped_around(&rendered, at), escaped_around(&expected, at));
+                    panic!("{} ({}) differs at byte {}:\n  got      {}\n  expected {}", name, variant, at, got, want);
+                }
+                assert_eq!(rendered.len(), expected.len(), "{} ({}) differs in length", name, variant);
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_title_sequence() {
+        assert_eq!(set_title_sequence("alice@web1: /srv"), "\x1b]0;alice@web1: /srv\x07");
+        assert_eq!(set_title_sequence("a\x1b]\x07b\n"), "\x1b]0;a]b\x07");
+    }
 }
\ No newline at end of file
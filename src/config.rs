@@ -1,8 +1,6 @@
+use crate::i18n::{Lang, Theme};
 use anyhow::Result;
 
-#[cfg(not(target_arch = "wasm32"))]
-use dirs;
-
 #[derive(Debug, Clone)]
 pub struct SshConfig {
     #[allow(dead_code)] // Used in tests and future features
@@ -10,6 +8,113 @@ pub struct SshConfig {
     #[allow(dead_code)] // Used in tests and future features
     pub default_port: u16,
     pub identity_file: Option<String>,
+    /// Warn when a stored key is older than this many days (0 disables)
+    pub key_max_age_days: u32,
+    /// Bytes read from the channel per terminal write in interactive sessions
+    pub read_buffer_size: usize,
+    /// Identification string sent to servers
+    pub client_version: String,
+    /// Language and color theme of CLI messages
+    pub lang: Lang,
+    pub theme: Theme,
+    /// Print a one-line summary of host key, algorithms and latency after login
+    pub session_header: bool,
+    /// Title interactive sessions' terminal windows `user@host: cwd` and follow
+    /// title changes from the remote side
+    pub terminal_title: bool,
+    /// Pause after a failed login to the same host, doubling per failure (0 disables)
+    pub auth_backoff_secs: u64,
+    /// Warn before logging in after this many recent failures (0 disables)
+    pub auth_warn_after: u32,
+    /// Password prompts per login before giving up
+    pub password_attempts: u32,
+    /// Whether a failed key login goes on to a password prompt
+    pub password_fallback: PasswordFallback,
+}
+
+/// What to do when no key logs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PasswordFallback {
+    /// Fail with the reason
+    Never,
+    /// Say why the keys failed and ask before prompting for a password
+    #[default]
+    Ask,
+    /// Say why the keys failed and prompt for a password straight away
+    Auto,
+}
+
+impl PasswordFallback {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "never" | "no" => Some(Self::Never),
+            "ask" => Some(Self::Ask),
+            "auto" | "yes" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+}
+
+/// Default for `read_buffer_size`
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 8192;
+/// Largest `read_buffer_size` honoured; the buffer is allocated per read loop
+pub const MAX_READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Identification string sent to servers (RFC 4253 section 4.2), without CR LF
+pub const CLIENT_VERSION: &str = concat!("SSH-2.0-bxssh_", env!("CARGO_PKG_VERSION"));
+
+/// Environment variable overriding `client_version`, for compatibility testing
+#[cfg(not(target_arch = "wasm32"))]
+const CLIENT_VERSION_ENV: &str = "BXSSH_CLIENT_VERSION";
+
+/// Environment variable overriding `key_max_age_days`
+#[cfg(not(target_arch = "wasm32"))]
+const KEY_MAX_AGE_ENV: &str = "BXSSH_KEY_MAX_AGE_DAYS";
+
+/// Environment variable overriding `read_buffer_size`
+#[cfg(not(target_arch = "wasm32"))]
+const READ_BUFFER_ENV: &str = "BXSSH_READ_BUFFER_SIZE";
+
+/// Environment variables choosing `lang` (`en`, `es`, `ja`) and `theme`
+/// (`default`, `high-contrast`, `none`)
+#[cfg(not(target_arch = "wasm32"))]
+const LANG_ENV: &str = "BXSSH_LANG";
+#[cfg(not(target_arch = "wasm32"))]
+const THEME_ENV: &str = "BXSSH_THEME";
+
+/// Environment variable turning on `session_header` (`1`, `yes`, `true` or `on`)
+#[cfg(not(target_arch = "wasm32"))]
+const SESSION_HEADER_ENV: &str = "BXSSH_SESSION_HEADER";
+
+/// Environment variable turning on `terminal_title`, with the same values
+#[cfg(not(target_arch = "wasm32"))]
+const TERMINAL_TITLE_ENV: &str = "BXSSH_TERMINAL_TITLE";
+
+/// Environment variables overriding `auth_backoff_secs`, `auth_warn_after` and
+/// `password_attempts`
+#[cfg(not(target_arch = "wasm32"))]
+const AUTH_BACKOFF_ENV: &str = "BXSSH_AUTH_BACKOFF_SECS";
+#[cfg(not(target_arch = "wasm32"))]
+const AUTH_WARN_AFTER_ENV: &str = "BXSSH_AUTH_WARN_AFTER";
+#[cfg(not(target_arch = "wasm32"))]
+const PASSWORD_ATTEMPTS_ENV: &str = "BXSSH_PASSWORD_ATTEMPTS";
+
+/// Environment variable choosing `password_fallback` (`never`, `ask` or `auto`)
+#[cfg(not(target_arch = "wasm32"))]
+const PASSWORD_FALLBACK_ENV: &str = "BXSSH_PASSWORD_FALLBACK";
+
+/// Environment variable holding the login name
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) const USER_ENV: &str = if cfg!(windows) { "USERNAME" } else { "USER" };
+
+/// The user's home directory: `$HOME` when set, then `%USERPROFILE%` on Windows,
+/// then the platform lookup
+#[cfg(not(target_arch = "wasm32"))]
+pub fn home_dir() -> Option<std::path::PathBuf> {
+    let from_env = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(Into::into);
+    from_env("HOME")
+        .or_else(|| if cfg!(windows) { from_env("USERPROFILE") } else { None })
+        .or_else(dirs::home_dir)
 }
 
 impl Default for SshConfig {
@@ -18,16 +123,59 @@ impl Default for SshConfig {
             default_user: None,
             default_port: 22,
             identity_file: None,
+            key_max_age_days: 365,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            client_version: CLIENT_VERSION.to_string(),
+            lang: Lang::default(),
+            theme: Theme::default(),
+            session_header: false,
+            terminal_title: false,
+            auth_backoff_secs: 2,
+            auth_warn_after: 3,
+            password_attempts: 3,
+            password_fallback: PasswordFallback::default(),
+        }
+    }
+}
+
+/// Check an identification string: `SSH-2.0-softwareversion`, optionally followed by
+/// a space and comments, printable ASCII and at most 255 bytes with CR LF
+pub fn validate_client_version(version: &str) -> Result<()> {
+    let software = version
+        .strip_prefix("SSH-2.0-")
+        .ok_or_else(|| anyhow::anyhow!("Client version must start with 'SSH-2.0-'"))?;
+    let software = software.split(' ').next().unwrap_or_default();
+    if software.is_empty() || software.contains('-') {
+        return Err(anyhow::anyhow!("Client version needs a software version without '-' after 'SSH-2.0-'"));
+    }
+    if version.len() + 2 > 255 || !version.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        return Err(anyhow::anyhow!("Client version must be printable ASCII of at most 253 characters"));
+    }
+    Ok(())
+}
+
+/// A yes/no environment variable: `1`, `yes`, `true` or `on`, or `0`, `no`,
+/// `false`, `off` or empty; other values are warned about and ignored
+#[cfg(not(target_arch = "wasm32"))]
+fn bool_env(name: &str) -> Option<bool> {
+    let value = std::env::var(name).ok()?;
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "yes" | "true" | "on" => Some(true),
+        "0" | "no" | "false" | "off" | "" => Some(false),
+        _ => {
+            log::warn!("Ignoring invalid {}: {}", name, value);
+            None
         }
     }
 }
 
 impl SshConfig {
+    /// Built-in defaults, then `~/.config/bxssh/config.toml`, then `BXSSH_*` variables
     #[cfg(not(target_arch = "wasm32"))]
     pub fn load() -> Result<Self> {
         let mut config = Self::default();
         
-        if let Some(home_dir) = dirs::home_dir() {
+        if let Some(home_dir) = home_dir() {
             let ssh_dir = home_dir.join(".ssh");
             if ssh_dir.exists() {
                 let id_rsa = ssh_dir.join("id_rsa");
@@ -41,9 +189,90 @@ impl SshConfig {
             }
         }
         
-        if let Ok(user) = std::env::var("USER") {
+        if let Ok(user) = std::env::var(USER_ENV) {
             config.default_user = Some(user);
         }
+
+        crate::settings::Settings::load()?.apply(&mut config);
+
+        if let Ok(days) = std::env::var(KEY_MAX_AGE_ENV) {
+            match days.parse() {
+                Ok(days) => config.key_max_age_days = days,
+                Err(_) => log::warn!("Ignoring invalid {}: {}", KEY_MAX_AGE_ENV, days),
+            }
+        }
+
+        if let Ok(size) = std::env::var(READ_BUFFER_ENV) {
+            match size.parse() {
+                Ok(size) if size > 0 => config.read_buffer_size = size,
+                _ => log::warn!("Ignoring invalid {}: {}", READ_BUFFER_ENV, size),
+            }
+        }
+        if config.read_buffer_size > MAX_READ_BUFFER_SIZE {
+            log::warn!(
+                "Read buffer size {} is above the maximum, using {}",
+                config.read_buffer_size,
+                MAX_READ_BUFFER_SIZE
+            );
+            config.read_buffer_size = MAX_READ_BUFFER_SIZE;
+        }
+
+        if let Ok(version) = std::env::var(CLIENT_VERSION_ENV) {
+            match validate_client_version(&version) {
+                Ok(()) => config.client_version = version,
+                Err(e) => log::warn!("Ignoring invalid {}: {}", CLIENT_VERSION_ENV, e),
+            }
+        }
+
+        if let Ok(lang) = std::env::var(LANG_ENV) {
+            match Lang::parse(&lang) {
+                Some(lang) => config.lang = lang,
+                None => log::warn!("Ignoring unsupported {}: {}", LANG_ENV, lang),
+            }
+        }
+
+        if let Ok(theme) = std::env::var(THEME_ENV) {
+            match Theme::parse(&theme) {
+                Some(theme) => config.theme = theme,
+                None => log::warn!("Ignoring unknown {}: {}", THEME_ENV, theme),
+            }
+        }
+
+        if let Some(header) = bool_env(SESSION_HEADER_ENV) {
+            config.session_header = header;
+        }
+
+        if let Some(title) = bool_env(TERMINAL_TITLE_ENV) {
+            config.terminal_title = title;
+        }
+
+        if let Ok(secs) = std::env::var(AUTH_BACKOFF_ENV) {
+            match secs.parse() {
+                Ok(secs) => config.auth_backoff_secs = secs,
+                Err(_) => log::warn!("Ignoring invalid {}: {}", AUTH_BACKOFF_ENV, secs),
+            }
+        }
+
+        if let Ok(count) = std::env::var(AUTH_WARN_AFTER_ENV) {
+            match count.parse() {
+                Ok(count) => config.auth_warn_after = count,
+                Err(_) => log::warn!("Ignoring invalid {}: {}", AUTH_WARN_AFTER_ENV, count),
+            }
+        }
+
+        if let Ok(count) = std::env::var(PASSWORD_ATTEMPTS_ENV) {
+            match count.parse() {
+                Ok(count) if count > 0 => config.password_attempts = count,
+                _ => log::warn!("Ignoring invalid {}: {}", PASSWORD_ATTEMPTS_ENV, count),
+            }
+        }
+
+        if let Ok(fallback) = std::env::var(PASSWORD_FALLBACK_ENV) {
+            match PasswordFallback::parse(&fallback) {
+                Some(fallback) => config.password_fallback = fallback,
+                None => log::warn!("Ignoring invalid {}: {}", PASSWORD_FALLBACK_ENV, fallback),
+            }
+        }
         
         Ok(config)
     }
@@ -53,6 +282,17 @@ impl SshConfig {
         Ok(Self::default())
     }
 
+    /// Backoff, warning and prompt limits for logins
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn auth_policy(&self) -> crate::auth_throttle::AuthPolicy {
+        crate::auth_throttle::AuthPolicy {
+            backoff: std::time::Duration::from_secs(self.auth_backoff_secs),
+            warn_after: self.auth_warn_after,
+            max_password_attempts: self.password_attempts,
+            ..Default::default()
+        }
+    }
+
     pub fn get_identity_file(&self) -> Option<&str> {
         self.identity_file.as_deref()
     }
@@ -95,6 +335,26 @@ mod tests {
         assert_eq!(config.default_port, 22);
         assert!(config.default_user.is_none());
         assert!(config.identity_file.is_none());
+        assert_eq!(config.key_max_age_days, 365);
+    }
+
+    #[test]
+    fn test_validate_client_version() {
+        validate_client_version(CLIENT_VERSION).unwrap();
+        assert_eq!(SshConfig::default().client_version, CLIENT_VERSION);
+        validate_client_version("SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13").unwrap();
+
+        for bad in [
+            "",
+            "SSH-1.99-client",
+            "SSH-2.0-",
+            "SSH-2.0-two-dashes",
+            "SSH-2.0-x\r\nSSH-2.0-y",
+            "SSH-2.0-caf\u{e9}",
+        ] {
+            assert!(validate_client_version(bad).is_err(), "{:?}", bad);
+        }
+        assert!(validate_client_version(&format!("SSH-2.0-{}", "x".repeat(250))).is_err());
     }
 
     #[test]
@@ -217,20 +477,183 @@ mod tests {
     #[cfg(not(target_arch = "wasm32"))]
     #[test] 
     fn test_load_with_user_env_var() {
-        let original_user = env::var("USER").ok();
-        env::set_var("USER", "testuser");
+        let original_user = env::var(USER_ENV).ok();
+        env::set_var(USER_ENV, "testuser");
 
         let config = SshConfig::load().unwrap();
 
         if let Some(user) = original_user {
-            env::set_var("USER", user);
+            env::set_var(USER_ENV, user);
         } else {
-            env::remove_var("USER");
+            env::remove_var(USER_ENV);
         }
 
         assert_eq!(config.default_user, Some("testuser".to_string()));
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_load_key_max_age_from_env() {
+        let original = env::var(KEY_MAX_AGE_ENV).ok();
+        env::set_var(KEY_MAX_AGE_ENV, "90");
+
+        let config = SshConfig::load().unwrap();
+
+        env::set_var(KEY_MAX_AGE_ENV, "not-a-number");
+        let fallback = SshConfig::load().unwrap();
+
+        if let Some(days) = original {
+            env::set_var(KEY_MAX_AGE_ENV, days);
+        } else {
+            env::remove_var(KEY_MAX_AGE_ENV);
+        }
+
+        assert_eq!(config.key_max_age_days, 90);
+        assert_eq!(fallback.key_max_age_days, 365);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_load_lang_and_theme_from_env() {
+        let original = (env::var(LANG_ENV).ok(), env::var(THEME_ENV).ok());
+        env::set_var(LANG_ENV, "ja_JP.UTF-8");
+        env::set_var(THEME_ENV, "high-contrast");
+
+        let config = SshConfig::load().unwrap();
+
+        env::set_var(LANG_ENV, "klingon");
+        env::set_var(THEME_ENV, "neon");
+        let fallback = SshConfig::load().unwrap();
+
+        for (name, value) in [(LANG_ENV, original.0), (THEME_ENV, original.1)] {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+
+        assert_eq!((config.lang, config.theme), (Lang::Ja, Theme::HighContrast));
+        assert_eq!((fallback.lang, fallback.theme), (Lang::En, Theme::Default));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_load_session_header_from_env() {
+        let original = env::var(SESSION_HEADER_ENV).ok();
+        env::set_var(SESSION_HEADER_ENV, "Yes");
+
+        let config = SshConfig::load().unwrap();
+
+        env::set_var(SESSION_HEADER_ENV, "maybe");
+        let fallback = SshConfig::load().unwrap();
+
+        if let Some(value) = original {
+            env::set_var(SESSION_HEADER_ENV, value);
+        } else {
+            env::remove_var(SESSION_HEADER_ENV);
+        }
+
+        assert!(config.session_header);
+        assert!(!fallback.session_header);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_load_terminal_title_from_env() {
+        let original = env::var(TERMINAL_TITLE_ENV).ok();
+        env::set_var(TERMINAL_TITLE_ENV, "on");
+
+        let config = SshConfig::load().unwrap();
+
+        env::set_var(TERMINAL_TITLE_ENV, "0");
+        let disabled = SshConfig::load().unwrap();
+
+        if let Some(value) = original {
+            env::set_var(TERMINAL_TITLE_ENV, value);
+        } else {
+            env::remove_var(TERMINAL_TITLE_ENV);
+        }
+
+        assert!(config.terminal_title);
+        assert!(!disabled.terminal_title);
+        assert!(!SshConfig::default().terminal_title);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_load_auth_policy_from_env() {
+        let names = [AUTH_BACKOFF_ENV, AUTH_WARN_AFTER_ENV, PASSWORD_ATTEMPTS_ENV, PASSWORD_FALLBACK_ENV];
+        let original: Vec<_> = names.iter().map(|name| env::var(name).ok()).collect();
+        env::set_var(AUTH_BACKOFF_ENV, "5");
+        env::set_var(AUTH_WARN_AFTER_ENV, "0");
+        env::set_var(PASSWORD_ATTEMPTS_ENV, "1");
+        env::set_var(PASSWORD_FALLBACK_ENV, "never");
+
+        let config = SshConfig::load().unwrap();
+
+        env::set_var(PASSWORD_ATTEMPTS_ENV, "0");
+        env::set_var(PASSWORD_FALLBACK_ENV, "sometimes");
+        let fallback = SshConfig::load().unwrap();
+
+        for (name, value) in names.iter().zip(original) {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+
+        let policy = config.auth_policy();
+        assert_eq!(policy.backoff, std::time::Duration::from_secs(5));
+        assert_eq!((policy.warn_after, policy.max_password_attempts), (0, 1));
+        assert_eq!(fallback.password_attempts, 3);
+        assert_eq!(config.password_fallback, PasswordFallback::Never);
+        assert_eq!(fallback.password_fallback, PasswordFallback::Ask);
+        assert_eq!(SshConfig::default().auth_policy(), crate::auth_throttle::AuthPolicy::default());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_load_read_buffer_size_from_env() {
+        let original = env::var(READ_BUFFER_ENV).ok();
+        env::set_var(READ_BUFFER_ENV, "65536");
+
+        let config = SshConfig::load().unwrap();
+
+        env::set_var(READ_BUFFER_ENV, "0");
+        let fallback = SshConfig::load().unwrap();
+
+        env::set_var(READ_BUFFER_ENV, "4294967296");
+        let clamped = SshConfig::load().unwrap();
+
+        if let Some(size) = original {
+            env::set_var(READ_BUFFER_ENV, size);
+        } else {
+            env::remove_var(READ_BUFFER_ENV);
+        }
+
+        assert_eq!(config.read_buffer_size, 65536);
+        assert_eq!(fallback.read_buffer_size, 8192);
+        assert_eq!(clamped.read_buffer_size, MAX_READ_BUFFER_SIZE);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_home_dir_prefers_home_env() {
+        let temp_dir = TempDir::new().unwrap();
+        let original_home = env::var("HOME").ok();
+        env::set_var("HOME", temp_dir.path());
+
+        let home = home_dir();
+
+        if let Some(home) = original_home {
+            env::set_var("HOME", home);
+        } else {
+            env::remove_var("HOME");
+        }
+
+        assert_eq!(home.as_deref(), Some(temp_dir.path()));
+    }
+
     #[cfg(target_arch = "wasm32")]
     #[test]
     fn test_load_wasm() {
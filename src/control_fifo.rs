@@ -0,0 +1,180 @@
+//! `--control-fifo DIR`: the interactive session's input and output as named pipes,
+//! so a script can type into the session and read what it shows while someone
+//! watches. Whatever is written to `DIR/in` is sent as if typed; `DIR/out` carries
+//! everything the session displays.
+use anyhow::{Context, Result};
+use log::debug;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::terminal::SessionHook;
+
+/// Pipe in the control directory whose data is typed into the session
+pub const INPUT_FIFO: &str = "in";
+/// Pipe in the control directory carrying the session's output
+pub const OUTPUT_FIFO: &str = "out";
+
+/// Output held for a reader of `out` before the oldest is dropped
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+
+/// Session hook serving the two pipes; they are removed again when it is dropped
+pub struct ControlFifo {
+    dir: PathBuf,
+    input: File,
+    /// Our own writer on `in`, so reads wait for the next writer instead of seeing end of file
+    _input_writer: File,
+    output: Option<File>,
+    pending_output: VecDeque<u8>,
+}
+
+impl ControlFifo {
+    /// Create `dir` and its `in` and `out` pipes, reusing pipes left there before
+    pub fn create(dir: &Path) -> Result<Self> {
+        crate::private_file::create_dir(dir)?;
+        for name in [INPUT_FIFO, OUTPUT_FIFO] {
+            make_fifo(&dir.join(name))?;
+        }
+        let input_path = dir.join(INPUT_FIFO);
+        let input = open_nonblocking(&input_path, false)
+            .with_context(|| format!("Cannot open {}", input_path.display()))?;
+        let input_writer = open_nonblocking(&input_path, true)
+            .with_context(|| format!("Cannot open {}", input_path.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            input,
+            _input_writer: input_writer,
+            output: None,
+            pending_output: VecDeque::new(),
+        })
+    }
+
+    /// Write what `out`'s reader can take; returns whether anything was written
+    fn flush_output(&mut self) -> bool {
+        if self.pending_output.is_empty() {
+            return false;
+        }
+        if self.output.is_none() {
+            // Fails with ENXIO until a reader opens the pipe
+            self.output = open_nonblocking(&self.dir.join(OUTPUT_FIFO), true).ok();
+        }
+        let Some(output) = &mut self.output else {
+            return false;
+        };
+        let mut wrote = false;
+        while !self.pending_output.is_empty() {
+            match output.write(self.pending_output.make_contiguous()) {
+                Ok(n) => {
+                    self.pending_output.drain(..n);
+                    wrote = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    debug!("Control pipe reader went away: {}", e);
+                    self.output = None;
+                    break;
+                }
+            }
+        }
+        wrote
+    }
+}
+
+impl SessionHook for ControlFifo {
+    fn on_output(&mut self, data: &[u8]) {
+        self.pending_output.extend(data);
+        if self.pending_output.len() > MAX_PENDING_OUTPUT {
+            let excess = self.pending_output.len() - MAX_PENDING_OUTPUT;
+            debug!("Nobody is reading the control pipe; dropping {} bytes of output", excess);
+            self.pending_output.drain(..excess);
+        }
+        self.flush_output();
+    }
+
+    fn take_input(&mut self) -> Option<Vec<u8>> {
+        let mut buffer = [0u8; 4096];
+        match self.input.read(&mut buffer) {
+            Ok(n) if n > 0 => Some(buffer[..n].to_vec()),
+            Ok(_) => None,
+            Err(e) => {
+                if e.kind() != ErrorKind::WouldBlock {
+                    debug!("Reading the control pipe failed: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    fn poll(&mut self) -> bool {
+        self.flush_output()
+    }
+}
+
+impl Drop for ControlFifo {
+    fn drop(&mut self) {
+        for name in [INPUT_FIFO, OUTPUT_FIFO] {
+            let _ = std::fs::remove_file(self.dir.join(name));
+        }
+    }
+}
+
+/// A named pipe only the user can use at `path`, unless one is already there
+fn make_fifo(path: &Path) -> Result<()> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        anyhow::ensure!(metadata.file_type().is_fifo(), "{} exists and is not a named pipe", path.display());
+        return Ok(());
+    }
+    let c_path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes())?;
+    // SAFETY: c_path is a valid NUL-terminated string for the duration of the call
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        let error = std::io::Error::last_os_error();
+        return Err(error).with_context(|| format!("Cannot create named pipe {}", path.display()));
+    }
+    Ok(())
+}
+
+fn open_nonblocking(path: &Path, write: bool) -> std::io::Result<File> {
+    OpenOptions::new().read(!write).write(write).custom_flags(libc::O_NONBLOCK).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pipes_carry_input_and_output() {
+        let dir = TempDir::new().unwrap();
+        let control = dir.path().join("control");
+        let mut fifo = ControlFifo::create(&control).unwrap();
+        assert_eq!(fifo.take_input(), None);
+
+        // Output waits for a reader
+        fifo.on_output(b"$ ");
+        let mut reader = open_nonblocking(&control.join(OUTPUT_FIFO), false).unwrap();
+        fifo.on_output(b"uptime\r\n");
+        let mut shown = Vec::new();
+        let _ = reader.read_to_end(&mut shown);
+        assert_eq!(shown, b"$ uptime\r\n");
+
+        let mut writer = OpenOptions::new().write(true).open(control.join(INPUT_FIFO)).unwrap();
+        writer.write_all(b"uptime\n").unwrap();
+        drop(writer);
+        assert_eq!(fifo.take_input().as_deref(), Some(&b"uptime\n"[..]));
+        // No end of file once the writer has gone
+        assert_eq!(fifo.take_input(), None);
+
+        drop(fifo);
+        assert!(!control.join(INPUT_FIFO).exists());
+    }
+
+    #[test]
+    fn test_refuses_a_regular_file() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join(INPUT_FIFO), "").unwrap();
+        let error = ControlFifo::create(dir.path()).err().unwrap();
+        assert!(error.to_string().contains("is not a named pipe"));
+    }
+}
@@ -0,0 +1,487 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::ssh_client::ShellSession;
+
+/// First line an attaching client sends; the daemon answers `OK`, `BUSY` or `ERR ...`
+const ATTACH_HELLO: &str = "BXSSH-ATTACH 1";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Output kept while detached and replayed on attach
+const SCROLLBACK_LIMIT: usize = 64 * 1024;
+
+/// Registry entry for a running detached session (`run/<name>.json` in the state directory)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub name: String,
+    pub pid: u32,
+    pub target: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Directory holding session sockets, registry entries and daemon logs
+pub fn run_dir() -> Result<PathBuf> {
+    Ok(crate::paths::state_dir()?.join("run"))
+}
+
+pub fn socket_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.sock", name))
+}
+
+pub fn log_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.log", name))
+}
+
+fn info_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", name))
+}
+
+/// Session names become file names, so keep them to a safe character set
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'))
+        && !name.starts_with('.');
+    if !valid {
+        return Err(anyhow::anyhow!(
+            "Invalid session name '{}'. Use letters, digits, '-', '_', '.' or '@'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Create the run directory, readable only by the current user
+pub fn create_run_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::create_dir_all(dir).context("Failed to create session directory")?;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .context("Failed to secure session directory")
+}
+
+/// Live sessions in `dir`; entries whose daemon is gone are removed
+pub fn list_sessions(dir: &Path) -> Result<Vec<SessionInfo>> {
+    let mut sessions = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(sessions),
+        Err(e) => return Err(e).context("Failed to read session directory"),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let info: SessionInfo = match std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+        {
+            Some(info) => info,
+            None => continue,
+        };
+
+        if UnixStream::connect(socket_path(dir, &info.name)).is_ok() {
+            sessions.push(info);
+        } else {
+            debug!("Removing stale session '{}'", info.name);
+            remove_session_files(dir, &info.name);
+        }
+    }
+
+    sessions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(sessions)
+}
+
+fn remove_session_files(dir: &Path, name: &str) {
+    let _ = std::fs::remove_file(socket_path(dir, name));
+    let _ = std::fs::remove_file(info_path(dir, name));
+}
+
+/// Start a copy of this executable with `args` in the background, detached from the
+/// terminal's process group, and wait until it has registered session `name`
+pub fn spawn(args: &[String], dir: &Path, name: &str) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    validate_name(name)?;
+    create_run_dir(dir)?;
+    if list_sessions(dir)?.iter().any(|session| session.name == name) {
+        return Err(anyhow::anyhow!("Session '{}' is already running", name));
+    }
+
+    let log_file = log_path(dir, name);
+    let log = std::fs::File::create(&log_file).context("Failed to create session log")?;
+    let exe = std::env::current_exe().context("Failed to locate bxssh executable")?;
+
+    let mut child = Command::new(exe)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().context("Failed to open session log")?)
+        .stderr(log)
+        .process_group(0)
+        .spawn()
+        .context("Failed to start session daemon")?;
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(60);
+    while std::time::Instant::now() < deadline {
+        if UnixStream::connect(socket_path(dir, name)).is_ok() {
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            let log = std::fs::read_to_string(&log_file).unwrap_or_default();
+            return Err(anyhow::anyhow!(
+                "Session daemon exited ({}):\n{}",
+                status,
+                log.trim_end()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    Err(anyhow::anyhow!(
+        "Session daemon did not start in time; see {}",
+        log_file.display()
+    ))
+}
+
+/// Register `info` in `dir`, listen for attach requests and relay `shell` until it closes
+pub fn run(shell: &mut dyn ShellSession, dir: &Path, info: &SessionInfo) -> Result<()> {
+    validate_name(&info.name)?;
+    create_run_dir(dir)?;
+
+    let socket = socket_path(dir, &info.name);
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(anyhow::anyhow!("Session '{}' is already running", info.name));
+    }
+    let _ = std::fs::remove_file(&socket);
+
+    let listener = UnixListener::bind(&socket).context("Failed to create session socket")?;
+    let content = serde_json::to_string_pretty(info).context("Failed to serialize session info")?;
+    std::fs::write(info_path(dir, &info.name), content).context("Failed to register session")?;
+
+    info!("Session '{}' ready at {}", info.name, socket.display());
+    let result = serve(shell, listener);
+    remove_session_files(dir, &info.name);
+    result
+}
+
+/// Relay bytes between `shell` and at most one attached client at a time,
+/// buffering recent output while nobody is attached
+pub fn serve(shell: &mut dyn ShellSession, listener: UnixListener) -> Result<()> {
+    listener.set_nonblocking(true).context("Failed to configure session socket")?;
+
+    let mut scrollback: VecDeque<u8> = VecDeque::new();
+    let mut attached: Option<UnixStream> = None;
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        let mut had_activity = false;
+
+        match listener.accept() {
+            Ok((stream, _)) => {
+                had_activity = true;
+                match accept_client(stream, attached.is_some(), &scrollback) {
+                    Ok(Some(stream)) => {
+                        info!("Client attached");
+                        attached = Some(stream);
+                    }
+                    Ok(None) => {}
+                    Err(e) => debug!("Rejected attach request: {}", e),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e).context("Failed to accept attach request"),
+        }
+
+        // Remote output -> scrollback and the attached client
+        let n = shell.read(&mut buffer)?;
+        if n > 0 {
+            had_activity = true;
+            push_scrollback(&mut scrollback, &buffer[..n]);
+            if let Some(stream) = attached.as_mut() {
+                if stream.write_all(&buffer[..n]).is_err() {
+                    info!("Client detached");
+                    attached = None;
+                }
+            }
+        } else if shell.is_eof() {
+            info!("Remote session ended");
+            break;
+        }
+
+        // Client input -> remote
+        if let Some(stream) = attached.as_mut() {
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    info!("Client detached");
+                    attached = None;
+                }
+                Ok(n) => {
+                    had_activity = true;
+                    write_all(shell, &buffer[..n])?;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(_) => {
+                    info!("Client detached");
+                    attached = None;
+                }
+            }
+        }
+
+        if !had_activity {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the attach handshake; returns the stream if the client should become attached
+fn accept_client(stream: UnixStream, busy: bool, scrollback: &VecDeque<u8>) -> Result<Option<UnixStream>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let mut hello = String::new();
+    BufReader::new(&stream).read_line(&mut hello)?;
+    let mut stream = stream;
+
+    if hello.trim_end() != ATTACH_HELLO {
+        stream.write_all(b"ERR unsupported protocol\n")?;
+        return Ok(None);
+    }
+    if busy {
+        stream.write_all(b"BUSY\n")?;
+        return Ok(None);
+    }
+
+    stream.write_all(b"OK\n")?;
+    let (front, back) = scrollback.as_slices();
+    stream.write_all(front)?;
+    stream.write_all(back)?;
+    stream.set_nonblocking(true)?;
+    Ok(Some(stream))
+}
+
+fn push_scrollback(scrollback: &mut VecDeque<u8>, data: &[u8]) {
+    scrollback.extend(data.iter().copied());
+    let excess = scrollback.len().saturating_sub(SCROLLBACK_LIMIT);
+    scrollback.drain(..excess);
+}
+
+fn write_all(session: &mut dyn ShellSession, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        let n = session.write(data)?;
+        if n == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+/// Client side of an attached session, usable anywhere a `ShellSession` is
+#[derive(Debug)]
+pub struct AttachedSession {
+    stream: UnixStream,
+    eof: bool,
+}
+
+impl AttachedSession {
+    /// Connect to the daemon for `name` in `dir` and complete the handshake
+    pub fn connect(dir: &Path, name: &str) -> Result<Self> {
+        validate_name(name)?;
+        let stream = UnixStream::connect(socket_path(dir, name))
+            .with_context(|| format!("No running session named '{}'", name))?;
+        Self::handshake(stream)
+    }
+
+    fn handshake(mut stream: UnixStream) -> Result<Self> {
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        stream.write_all(format!("{}\n", ATTACH_HELLO).as_bytes())?;
+
+        // Read the reply byte by byte so no replayed output is lost to a buffer
+        let mut reply = Vec::new();
+        let mut byte = [0u8; 1];
+        while reply.last() != Some(&b'\n') {
+            if stream.read(&mut byte).context("Session did not answer")? == 0 {
+                return Err(anyhow::anyhow!("Session closed during attach"));
+            }
+            reply.push(byte[0]);
+        }
+
+        match String::from_utf8_lossy(&reply).trim_end() {
+            "OK" => {}
+            "BUSY" => return Err(anyhow::anyhow!("Session is already attached elsewhere")),
+            other => return Err(anyhow::anyhow!("Attach refused: {}", other)),
+        }
+
+        stream.set_nonblocking(true)?;
+        Ok(Self { stream, eof: false })
+    }
+}
+
+impl ShellSession for AttachedSession {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.stream.read(buf) {
+            Ok(0) => {
+                self.eof = true;
+                Ok(0)
+            }
+            Ok(n) => Ok(n),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(anyhow::anyhow!("Failed to read from session: {}", e)),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        match self.stream.write(data) {
+            Ok(n) => Ok(n),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(anyhow::anyhow!("Failed to write to session: {}", e)),
+        }
+    }
+
+    fn send_eof(&mut self) -> Result<()> {
+        self.stream
+            .shutdown(std::net::Shutdown::Write)
+            .context("Failed to close session input")
+    }
+
+    fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    fn wait_close(&mut self) -> Result<i32> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Script, ScriptedShellSession};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    fn read_until(session: &mut dyn ShellSession, pattern: &[u8]) -> Vec<u8> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while Instant::now() < deadline {
+            let n = session.read(&mut buffer).unwrap();
+            received.extend_from_slice(&buffer[..n]);
+            if received.windows(pattern.len()).any(|w| w == pattern) {
+                return received;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("timed out; got {:?}", String::from_utf8_lossy(&received));
+    }
+
+    fn info(name: &str) -> SessionInfo {
+        SessionInfo {
+            name: name.to_string(),
+            pid: std::process::id(),
+            target: "alice@example.com:22".to_string(),
+            started_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("work").is_ok());
+        assert!(validate_name("alice@db-1.example").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("../escape").is_err());
+        assert!(validate_name(".hidden").is_err());
+        assert!(validate_name("has space").is_err());
+    }
+
+    #[test]
+    fn test_scrollback_is_capped() {
+        let mut scrollback = VecDeque::new();
+        push_scrollback(&mut scrollback, &vec![b'a'; SCROLLBACK_LIMIT]);
+        push_scrollback(&mut scrollback, b"tail");
+
+        assert_eq!(scrollback.len(), SCROLLBACK_LIMIT);
+        assert!(scrollback.iter().rev().take(4).eq(b"liat".iter()));
+    }
+
+    #[test]
+    fn test_list_sessions_missing_dir() {
+        let temp = TempDir::new().unwrap();
+        assert!(list_sessions(&temp.path().join("run")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_sessions_removes_stale_entries() {
+        let temp = TempDir::new().unwrap();
+        let stale = info("stale");
+        std::fs::write(info_path(temp.path(), "stale"), serde_json::to_string(&stale).unwrap()).unwrap();
+
+        assert!(list_sessions(temp.path()).unwrap().is_empty());
+        assert!(!info_path(temp.path(), "stale").exists());
+    }
+
+    #[test]
+    fn test_detach_and_reattach() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().to_path_buf();
+        let input_log = Arc::new(Mutex::new(Vec::new()));
+        let script = Script::new()
+            .send("$ ")
+            .expect("first\r")
+            .send("one\r\n$ ")
+            .expect("second\r")
+            .send("two\r\n")
+            .eof();
+        let mut shell = ScriptedShellSession::new(script, Arc::clone(&input_log));
+
+        let server_dir = dir.clone();
+        let server = std::thread::spawn(move || run(&mut shell, &server_dir, &info("work")));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while list_sessions(&dir).unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "session never registered");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(list_sessions(&dir).unwrap()[0].target, "alice@example.com:22");
+
+        // First attach, run a command, then drop the connection (detach)
+        let mut first = AttachedSession::connect(&dir, "work").unwrap();
+        read_until(&mut first, b"$ ");
+        first.write(b"first\r").unwrap();
+        read_until(&mut first, b"one");
+
+        // A second client is refused while the first is attached
+        let busy = AttachedSession::connect(&dir, "work").unwrap_err();
+        assert!(busy.to_string().contains("already attached"));
+        drop(first);
+
+        // Reattach: scrollback is replayed, then the session continues
+        let mut second = loop {
+            match AttachedSession::connect(&dir, "work") {
+                Ok(session) => break session,
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        read_until(&mut second, b"one\r\n$ ");
+        second.write(b"second\r").unwrap();
+        read_until(&mut second, b"two");
+
+        server.join().unwrap().unwrap();
+        assert!(list_sessions(&dir).unwrap().is_empty());
+        assert_eq!(input_log.lock().unwrap().as_slice(), b"first\rsecond\r");
+    }
+}
@@ -0,0 +1,142 @@
+//! Finite-field Diffie-Hellman key exchange (RFC 4253 section 8) over the
+//! RFC 3526 MODP groups, for servers without curve25519
+use anyhow::Result;
+use rand::RngCore;
+use rsa::BigUint;
+
+/// 2048-bit MODP group 14 prime (RFC 3526 section 3)
+const GROUP14_PRIME: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF",
+);
+
+/// 4096-bit MODP group 16 prime (RFC 3526 section 5)
+const GROUP16_PRIME: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+    "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+    "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+    "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+    "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7",
+    "88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8",
+    "DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2",
+    "233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9",
+    "93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C934063199FFFFFFFFFFFFFFFF",
+);
+
+/// Both groups use generator 2
+const GENERATOR: u32 = 2;
+
+/// Bits in the private exponent: twice the strength of the strongest cipher offered
+const PRIVATE_BITS: usize = 512;
+
+/// A MODP group used by a `diffie-hellman-*` key exchange method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhGroup {
+    Group14,
+    Group16,
+}
+
+impl DhGroup {
+    /// Group for a negotiated key exchange method, if it is a supported DH one
+    pub fn for_kex(kex: &str) -> Option<Self> {
+        match kex {
+            "diffie-hellman-group14-sha256" => Some(DhGroup::Group14),
+            "diffie-hellman-group16-sha512" => Some(DhGroup::Group16),
+            _ => None,
+        }
+    }
+
+    fn prime(self) -> BigUint {
+        let hex = match self {
+            DhGroup::Group14 => GROUP14_PRIME,
+            DhGroup::Group16 => GROUP16_PRIME,
+        };
+        BigUint::parse_bytes(hex.as_bytes(), 16).expect("valid group prime")
+    }
+}
+
+/// The client's ephemeral exponent x and public value e = g^x mod p
+pub struct DhKeyPair {
+    prime: BigUint,
+    private: BigUint,
+    public: Vec<u8>,
+}
+
+impl DhKeyPair {
+    pub fn generate(group: DhGroup) -> Self {
+        let mut bytes = [0u8; PRIVATE_BITS / 8];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self::from_private(group, &bytes)
+    }
+
+    fn from_private(group: DhGroup, private: &[u8]) -> Self {
+        let prime = group.prime();
+        let private = BigUint::from_bytes_be(private);
+        let public = BigUint::from(GENERATOR).modpow(&private, &prime).to_bytes_be();
+        Self { prime, private, public }
+    }
+
+    /// e as unsigned big-endian bytes
+    pub fn public(&self) -> &[u8] {
+        &self.public
+    }
+
+    /// K = f^x mod p, after checking that 1 < f < p - 1 (RFC 4253 section 8)
+    pub fn shared_secret(&self, server_public: &[u8]) -> Result<Vec<u8>> {
+        let f = BigUint::from_bytes_be(server_public);
+        let one = BigUint::from(1u32);
+        if f <= one || f >= &self.prime - &one {
+            return Err(anyhow::anyhow!("Server sent an invalid Diffie-Hellman value"));
+        }
+        Ok(f.modpow(&self.private, &self.prime).to_bytes_be())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(DhGroup::for_kex("diffie-hellman-group14-sha256"), Some(DhGroup::Group14));
+        assert_eq!(DhGroup::for_kex("diffie-hellman-group16-sha512"), Some(DhGroup::Group16));
+        assert_eq!(DhGroup::for_kex("diffie-hellman-group1-sha1"), None);
+        assert_eq!(DhGroup::Group14.prime().bits(), 2048);
+        assert_eq!(DhGroup::Group16.prime().bits(), 4096);
+    }
+
+    #[test]
+    fn test_key_agreement() {
+        for group in [DhGroup::Group14, DhGroup::Group16] {
+            let client = DhKeyPair::generate(group);
+            let server = DhKeyPair::from_private(group, &[0x5A; 32]);
+            let secret = client.shared_secret(server.public()).unwrap();
+            assert_eq!(secret, server.shared_secret(client.public()).unwrap());
+            assert!(secret.len() > 200);
+        }
+    }
+
+    #[test]
+    fn test_rejects_degenerate_server_values() {
+        let client = DhKeyPair::generate(DhGroup::Group14);
+        let prime = DhGroup::Group14.prime();
+        let p_minus_one = (&prime - 1u32).to_bytes_be();
+        for bad in [vec![], vec![0], vec![1], p_minus_one, prime.to_bytes_be(), vec![0xFF; 257]] {
+            assert!(client.shared_secret(&bad).is_err());
+        }
+        assert!(client.shared_secret(&[2]).is_ok());
+    }
+}
@@ -0,0 +1,441 @@
+//! `bxssh diagnose`: connectivity checks from name resolution to login, each
+//! reported as a finding with advice on what to try when it isn't fine
+use crate::i18n::{tr, Msg};
+use crate::ssh_wire::{negotiate_algorithm, parse_packet, parse_server_version, KexInit, KEX_STRICT_S, MAX_PACKET_LENGTH};
+use anyhow::Result;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+/// Clock difference beyond which certificates and one-time passwords start failing
+pub const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+    /// Not run, because an earlier check failed or there was nothing to check
+    Skipped,
+}
+
+/// Outcome of one check
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub check: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to try next, for warnings and failures
+    pub advice: Option<String>,
+}
+
+impl Finding {
+    pub fn ok(check: &'static str, detail: impl Into<String>) -> Self {
+        Self { check, status: Status::Ok, detail: detail.into(), advice: None }
+    }
+
+    pub fn warn(check: &'static str, detail: impl Into<String>, advice: impl Into<String>) -> Self {
+        Self { check, status: Status::Warn, detail: detail.into(), advice: Some(advice.into()) }
+    }
+
+    pub fn fail(check: &'static str, detail: impl Into<String>, advice: impl Into<String>) -> Self {
+        Self { check, status: Status::Fail, detail: detail.into(), advice: Some(advice.into()) }
+    }
+
+    pub fn skipped(check: &'static str, detail: impl Into<String>) -> Self {
+        Self { check, status: Status::Skipped, detail: detail.into(), advice: None }
+    }
+}
+
+/// Display name of a check, in the user's language
+pub fn title(check: &str) -> String {
+    let msg = match check {
+        "dns" => Msg::DiagDns,
+        "tcp" => Msg::DiagTcp,
+        "banner" => Msg::DiagBanner,
+        "algorithms" => Msg::DiagAlgorithms,
+        "auth" => Msg::DiagAuth,
+        "key file" => Msg::DiagKeyFile,
+        "login" => Msg::DiagLogin,
+        "clock" => Msg::DiagClock,
+        "pre-connect" => Msg::DiagPreConnect,
+        _ => return check.to_string(),
+    };
+    tr(msg, &[])
+}
+
+/// Whether any finding is a failure
+pub fn has_failures(findings: &[Finding]) -> bool {
+    findings.iter().any(|finding| finding.status == Status::Fail)
+}
+
+/// The checks that need only a TCP connection: name resolution, reachability, the
+/// server's identification and its algorithm offer against `supported`. Stops at
+/// the first failure, since each check builds on the one before.
+pub fn probe(host: &str, port: u16, client_version: &str, supported: &KexInit, timeout: Duration) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let addrs = match resolve(host, port) {
+        Ok(addrs) => addrs,
+        Err(finding) => return vec![finding],
+    };
+    let list: Vec<String> = addrs.iter().map(|addr| addr.ip().to_string()).collect();
+    findings.push(Finding::ok("dns", tr(Msg::DiagResolves, &[&host, &list.join(", ")])));
+
+    let mut stream = match connect(&addrs, port, timeout) {
+        Ok(stream) => stream,
+        Err(finding) => {
+            findings.push(finding);
+            return findings;
+        }
+    };
+    let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| host.to_string());
+    findings.push(Finding::ok("tcp", tr(Msg::DiagConnected, &[&peer])));
+
+    let (version, kex_init) = match read_server_hello(&mut stream, client_version, timeout) {
+        Ok(hello) => hello,
+        Err((check, e)) => {
+            findings.push(Finding::fail(check, e.to_string(), tr(Msg::DiagOtherService, &[&port])));
+            return findings;
+        }
+    };
+    findings.push(banner_finding(&version));
+    findings.extend(algorithm_findings(supported, &kex_init));
+    findings
+}
+
+fn resolve(host: &str, port: u16) -> std::result::Result<Vec<SocketAddr>, Finding> {
+    let advice = tr(Msg::DiagCheckHostName, &[]);
+    match (host, port).to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<SocketAddr> = addrs.collect();
+            if addrs.is_empty() {
+                Err(Finding::fail("dns", tr(Msg::DiagNoAddresses, &[&host]), advice))
+            } else {
+                Ok(addrs)
+            }
+        }
+        Err(e) => Err(Finding::fail("dns", tr(Msg::DiagCannotResolve, &[&host, &e]), advice)),
+    }
+}
+
+/// Connect to the first address that answers
+fn connect(addrs: &[SocketAddr], port: u16, timeout: Duration) -> std::result::Result<TcpStream, Finding> {
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let error = last_error.expect("resolve returns at least one address");
+    Err(Finding::fail("tcp", tr(Msg::DiagCannotConnect, &[&error]), connect_advice(error.kind(), port, timeout)))
+}
+
+fn connect_advice(kind: std::io::ErrorKind, port: u16, timeout: Duration) -> String {
+    match kind {
+        std::io::ErrorKind::ConnectionRefused => tr(Msg::DiagConnectionRefused, &[&port]),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+            tr(Msg::DiagNoAnswer, &[&timeout.as_secs()])
+        }
+        _ => tr(Msg::DiagCheckRoute, &[]),
+    }
+}
+
+/// Exchange identification strings and read the server's first KEXINIT, naming
+/// the check that failed on error
+fn read_server_hello(
+    stream: &mut TcpStream,
+    client_version: &str,
+    timeout: Duration,
+) -> std::result::Result<(String, KexInit), (&'static str, anyhow::Error)> {
+    let banner = |e: std::io::Error| ("banner", anyhow::anyhow!("No identification from the server: {}", e));
+    stream.set_read_timeout(Some(timeout)).map_err(banner)?;
+    stream.write_all(format!("{}\r\n", client_version).as_bytes()).map_err(banner)?;
+
+    let mut buffer = Vec::new();
+    let (version, used) = loop {
+        if let Some(found) = parse_server_version(&buffer).map_err(|e| ("banner", e))? {
+            break found;
+        }
+        read_more(stream, &mut buffer).map_err(|e| ("banner", e))?;
+    };
+    buffer.drain(..used);
+
+    let algorithms = |e: anyhow::Error| ("algorithms", e);
+    while !packet_complete(&buffer).map_err(algorithms)? {
+        read_more(stream, &mut buffer).map_err(algorithms)?;
+    }
+    let (packet, _) = parse_packet(&buffer).map_err(algorithms)?;
+    let kex_init = KexInit::parse(packet.payload).map_err(algorithms)?;
+    Ok((crate::sanitize::strip_escape_sequences(&version), kex_init))
+}
+
+fn read_more(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Result<()> {
+    let mut chunk = [0u8; 4096];
+    let n = stream.read(&mut chunk)?;
+    if n == 0 {
+        return Err(anyhow::anyhow!("Server closed the connection"));
+    }
+    buffer.extend_from_slice(&chunk[..n]);
+    Ok(())
+}
+
+/// Whether `buffer` starts with a whole unencrypted packet
+fn packet_complete(buffer: &[u8]) -> Result<bool> {
+    let Some(length) = buffer.get(..4) else {
+        return Ok(false);
+    };
+    let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+    if length > MAX_PACKET_LENGTH {
+        return Err(anyhow::anyhow!("Server sent a {}-byte packet instead of KEXINIT", length));
+    }
+    Ok(buffer.len() >= 4 + length)
+}
+
+/// The server's identification, with a warning for servers that may predate
+/// the algorithms we need
+pub fn banner_finding(version: &str) -> Finding {
+    if version.starts_with("SSH-1.99-") {
+        return Finding::warn(
+            "banner",
+            tr(Msg::DiagServerVersion, &[&version]),
+            tr(Msg::DiagProtocol1, &[]),
+        );
+    }
+    Finding::ok("banner", tr(Msg::DiagServerVersion, &[&version]))
+}
+
+/// One finding per algorithm kind with no overlap between `supported` and the
+/// server's offer, or a single finding naming what would be used
+pub fn algorithm_findings(supported: &KexInit, server: &KexInit) -> Vec<Finding> {
+    let cipher = negotiate_algorithm("cipher", &supported.encryption_client_to_server, &server.encryption_client_to_server);
+    let mac_needed = match &cipher {
+        Ok(cipher) => !crate::cipher::is_aead(cipher),
+        Err(_) => false,
+    };
+    let mut checks = vec![
+        ("key exchange", negotiate_algorithm("key exchange", &supported.kex_algorithms, &server.kex_algorithms), &server.kex_algorithms),
+        (
+            "host key",
+            negotiate_algorithm("host key", &supported.server_host_key_algorithms, &server.server_host_key_algorithms),
+            &server.server_host_key_algorithms,
+        ),
+        ("cipher", cipher, &server.encryption_client_to_server),
+    ];
+    if mac_needed {
+        checks.push((
+            "MAC",
+            negotiate_algorithm("MAC", &supported.mac_client_to_server, &server.mac_client_to_server),
+            &server.mac_client_to_server,
+        ));
+    }
+
+    let mut chosen = Vec::new();
+    let mut findings = Vec::new();
+    for (kind, result, offered) in checks {
+        match result {
+            Ok(name) => chosen.push(name),
+            Err(_) => findings.push(Finding::fail(
+                "algorithms",
+                tr(Msg::DiagNoCommonAlgorithm, &[&kind, &offered.join(", ")]),
+                tr(Msg::DiagEnableAlgorithm, &[]),
+            )),
+        }
+    }
+    if !findings.is_empty() {
+        return findings;
+    }
+
+    findings.push(Finding::ok("algorithms", tr(Msg::DiagWouldUse, &[&chosen.join(", ")])));
+    if !server.kex_algorithms.iter().any(|name| name == KEX_STRICT_S) {
+        findings.push(Finding::warn(
+            "algorithms",
+            tr(Msg::DiagNoStrictKex, &[]),
+            tr(Msg::DiagUpdateServer, &[]),
+        ));
+    }
+    findings
+}
+
+/// Authentication methods the server offers `user`, judged against what bxssh can use
+pub fn auth_methods_finding(methods: &[String], use_password: bool) -> Finding {
+    let offers = |name: &str| methods.iter().any(|method| method == name);
+    let list = methods.join(", ");
+    if offers("publickey") || (use_password && offers("password")) {
+        return Finding::ok("auth", tr(Msg::DiagServerOffers, &[&list]));
+    }
+    if offers("password") {
+        return Finding::warn(
+            "auth",
+            tr(Msg::DiagOnlyPassword, &[&list]),
+            tr(Msg::DiagUsePassword, &[]),
+        );
+    }
+    Finding::fail(
+        "auth",
+        if list.is_empty() { tr(Msg::DiagNoMethods, &[]) } else { tr(Msg::DiagNoUsableMethod, &[&list]) },
+        tr(Msg::DiagEnableAuth, &[]),
+    )
+}
+
+/// Whether a private key file is missing or readable by other users
+pub fn key_permissions_finding(path: &Path) -> Finding {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return Finding::fail(
+                "key file",
+                tr(Msg::DiagCannotReadKey, &[&path.display(), &e]),
+                tr(Msg::DiagCheckKeyPath, &[]),
+            )
+        }
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Finding::warn(
+                "key file",
+                tr(Msg::DiagKeyShared, &[&path.display(), &format!("{:o}", mode)]),
+                tr(Msg::DiagChmod, &[&path.display()]),
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+    Finding::ok("key file", tr(Msg::DiagKeyPrivate, &[&path.display()]))
+}
+
+/// Compare the remote clock (`date +%s` output) with local time sampled before
+/// and after the command, allowing for the round trip
+pub fn clock_skew_finding(remote_output: &str, local_before: i64, local_after: i64) -> Finding {
+    let Ok(remote) = remote_output.trim().parse::<i64>() else {
+        return Finding::skipped("clock", tr(Msg::DiagNoTimestamp, &[]));
+    };
+    let skew = remote - (local_before + local_after) / 2;
+    let slack = (local_after - local_before) / 2 + 1;
+    if skew.abs() <= MAX_CLOCK_SKEW_SECS + slack {
+        return Finding::ok("clock", tr(Msg::DiagClocksAgree, &[&skew.abs().max(slack)]));
+    }
+    let detail = if skew > 0 { Msg::DiagClockAhead } else { Msg::DiagClockBehind };
+    Finding::warn(
+        "clock",
+        tr(detail, &[&skew.abs()]),
+        tr(Msg::DiagSyncClocks, &[]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn server_kex_init() -> KexInit {
+        let mut server = KexInit::client([0; 16], false);
+        server.kex_algorithms.push(KEX_STRICT_S.to_string());
+        server
+    }
+
+    #[test]
+    fn test_algorithm_findings() {
+        let supported = KexInit::client([0; 16], false);
+        let findings = algorithm_findings(&supported, &server_kex_init());
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].status, Status::Ok);
+        assert!(findings[0].detail.contains("curve25519-sha256"), "{}", findings[0].detail);
+
+        let mut old = server_kex_init();
+        old.kex_algorithms = vec!["diffie-hellman-group1-sha1".to_string()];
+        old.encryption_client_to_server = vec!["3des-cbc".to_string()];
+        let findings = algorithm_findings(&supported, &old);
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|finding| finding.status == Status::Fail));
+        assert!(findings[0].detail.contains("diffie-hellman-group1-sha1"));
+
+        let mut no_strict = server_kex_init();
+        no_strict.kex_algorithms.pop();
+        let findings = algorithm_findings(&supported, &no_strict);
+        assert_eq!(findings[1].status, Status::Warn);
+        assert!(findings[1].detail.contains("CVE-2023-48795"));
+    }
+
+    #[test]
+    fn test_auth_methods_finding() {
+        let methods = |list: &[&str]| list.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        assert_eq!(auth_methods_finding(&methods(&["publickey", "password"]), false).status, Status::Ok);
+        assert_eq!(auth_methods_finding(&methods(&["password"]), false).status, Status::Warn);
+        assert_eq!(auth_methods_finding(&methods(&["password"]), true).status, Status::Ok);
+        assert_eq!(auth_methods_finding(&methods(&["keyboard-interactive"]), false).status, Status::Fail);
+        assert_eq!(auth_methods_finding(&[], false).status, Status::Fail);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_permissions_finding() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::TempDir::new().unwrap();
+        let key = dir.path().join("id_ed25519");
+        crate::private_file::write(&key, b"key").unwrap();
+        assert_eq!(key_permissions_finding(&key).status, Status::Ok);
+
+        std::fs::set_permissions(&key, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let finding = key_permissions_finding(&key);
+        assert_eq!(finding.status, Status::Warn);
+        assert!(finding.advice.unwrap().starts_with("Run: chmod 600"));
+
+        assert_eq!(key_permissions_finding(&dir.path().join("missing")).status, Status::Fail);
+    }
+
+    #[test]
+    fn test_clock_skew_finding() {
+        assert_eq!(clock_skew_finding("1000\n", 1000, 1002).status, Status::Ok);
+        let finding = clock_skew_finding("1300", 1000, 1000);
+        assert_eq!(finding.status, Status::Warn);
+        assert!(finding.detail.contains("300s ahead of"));
+        assert!(clock_skew_finding("700", 1000, 1000).detail.contains("behind"));
+        assert_eq!(clock_skew_finding("date: not found", 1000, 1000).status, Status::Skipped);
+    }
+
+    #[test]
+    fn test_probe_reads_banner_and_kex_init() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut hello = b"Welcome\r\nSSH-2.0-TestServer\r\n".to_vec();
+            let payload = server_kex_init().encode();
+            let padding = 8 - (payload.len() + 5) % 8 + 8;
+            hello.extend_from_slice(&((payload.len() + padding + 1) as u32).to_be_bytes());
+            hello.push(padding as u8);
+            hello.extend_from_slice(&payload);
+            hello.resize(hello.len() + padding, 0);
+            stream.write_all(&hello).unwrap();
+            let mut version = [0u8; 32];
+            let _ = stream.read(&mut version);
+        });
+
+        let supported = KexInit::client([0; 16], false);
+        let findings = probe("127.0.0.1", port, "SSH-2.0-bxssh_test", &supported, Duration::from_secs(5));
+        server.join().unwrap();
+        let checks: Vec<_> = findings.iter().map(|finding| (finding.check, finding.status)).collect();
+        assert_eq!(
+            checks,
+            [("dns", Status::Ok), ("tcp", Status::Ok), ("banner", Status::Ok), ("algorithms", Status::Ok)]
+        );
+        assert_eq!(findings[2].detail, "Server identifies as SSH-2.0-TestServer");
+    }
+
+    #[test]
+    fn test_probe_stops_at_refused_connection() {
+        // Bind and drop to find a port nothing listens on
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let supported = KexInit::client([0; 16], false);
+        let findings = probe("127.0.0.1", port, "SSH-2.0-bxssh_test", &supported, Duration::from_secs(5));
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[1].status, Status::Fail);
+        assert!(findings[1].advice.as_ref().unwrap().contains("port"));
+    }
+}
@@ -0,0 +1,307 @@
+//! LAN host discovery for `bxssh discover`: one multicast DNS query for SSH
+//! services (RFC 6762, DNS-SD RFC 6763), collecting the servers that answer
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// DNS-SD service type SSH servers announce
+pub const SSH_SERVICE: &str = "_ssh._tcp.local";
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// Question class bit asking for a unicast reply (RFC 6762 section 5.4)
+const UNICAST_RESPONSE: u16 = 0x8000;
+
+/// Compression pointers followed per name before giving up on a looping packet
+const MAX_POINTERS: usize = 16;
+
+/// An SSH server that answered the query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredHost {
+    /// Service instance name, e.g. `nas`
+    pub name: String,
+    /// Host name from the SRV record, e.g. `nas.local`
+    pub host: String,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl DiscoveredHost {
+    /// What to dial: an IPv4 address if there is one, any address, else the host name
+    pub fn connect_host(&self) -> String {
+        self.addresses
+            .iter()
+            .find(|address| address.is_ipv4())
+            .or(self.addresses.first())
+            .map(IpAddr::to_string)
+            .unwrap_or_else(|| self.host.clone())
+    }
+}
+
+/// Records from responses that matter for browsing, keyed by lowercase name
+#[derive(Debug, Default)]
+struct Records {
+    /// Service instances, as full names like `nas._ssh._tcp.local`
+    instances: Vec<String>,
+    /// Instance name to (target host, port)
+    services: BTreeMap<String, (String, u16)>,
+    /// Host name to its addresses
+    addresses: BTreeMap<String, Vec<IpAddr>>,
+}
+
+/// Send the query and gather answers for `timeout`; hosts come back sorted by name
+pub fn browse(timeout: Duration) -> Result<Vec<DiscoveredHost>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to open UDP socket for mDNS")?;
+    socket.set_multicast_ttl_v4(255).context("Failed to set multicast TTL")?;
+    // From a port other than 5353 this is a one-shot query; responders answer us directly
+    socket
+        .send_to(&query(), SocketAddr::new(IpAddr::V4(MDNS_GROUP), MDNS_PORT))
+        .context("Failed to send mDNS query")?;
+
+    let mut records = Records::default();
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0u8; 9000];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((n, from)) => {
+                if let Err(e) = parse_response(&buffer[..n], &mut records) {
+                    log::debug!("Ignoring mDNS packet from {}: {}", from, e);
+                }
+            }
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e).context("Failed to receive mDNS responses"),
+        }
+    }
+    Ok(assemble(&records))
+}
+
+/// A DNS message asking for the PTR records of `SSH_SERVICE`
+fn query() -> Vec<u8> {
+    // ID 0, standard query, one question (RFC 6762 section 18)
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in SSH_SERVICE.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    packet.extend_from_slice(&(CLASS_IN | UNICAST_RESPONSE).to_be_bytes());
+    packet
+}
+
+/// Add the PTR, SRV, A and AAAA records of one response to `records`
+fn parse_response(packet: &[u8], records: &mut Records) -> Result<()> {
+    let header = packet.get(..12).ok_or_else(|| anyhow::anyhow!("Truncated DNS header"))?;
+    if header[2] & 0x80 == 0 {
+        return Ok(()); // A query, not a response
+    }
+    let count = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]) as usize;
+    let (questions, answers) = (count(4), count(6) + count(8) + count(10));
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(packet, pos)?.1 + 4;
+    }
+    for _ in 0..answers {
+        let (name, next) = read_name(packet, pos)?;
+        let fixed = packet.get(next..next + 10).ok_or_else(|| anyhow::anyhow!("Truncated record"))?;
+        let record_type = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let length = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let start = next + 10;
+        let data = packet.get(start..start + length).ok_or_else(|| anyhow::anyhow!("Truncated record data"))?;
+        let name = name.to_ascii_lowercase();
+
+        match record_type {
+            TYPE_PTR if name == SSH_SERVICE => {
+                let instance = read_name(packet, start)?.0;
+                if !records.instances.iter().any(|known| known.eq_ignore_ascii_case(&instance)) {
+                    records.instances.push(instance);
+                }
+            }
+            TYPE_SRV if length >= 6 => {
+                let port = u16::from_be_bytes([data[4], data[5]]);
+                let target = read_name(packet, start + 6)?.0.to_ascii_lowercase();
+                records.services.insert(name, (target, port));
+            }
+            TYPE_A if length == 4 => {
+                let address = IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+                add_address(records, name, address);
+            }
+            TYPE_AAAA if length == 16 => {
+                let octets: [u8; 16] = data.try_into().unwrap();
+                add_address(records, name, IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+        pos = start + length;
+    }
+    Ok(())
+}
+
+fn add_address(records: &mut Records, host: String, address: IpAddr) {
+    let addresses = records.addresses.entry(host).or_default();
+    if !addresses.contains(&address) {
+        addresses.push(address);
+    }
+}
+
+/// Read a possibly compressed domain name at `pos`. Returns it without the
+/// trailing dot, and the position after it in the record.
+fn read_name(packet: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *packet.get(pos).ok_or_else(|| anyhow::anyhow!("Truncated name"))? as usize;
+        match len {
+            0 => break,
+            _ if len & 0xC0 == 0xC0 => {
+                let low = *packet.get(pos + 1).ok_or_else(|| anyhow::anyhow!("Truncated name pointer"))? as usize;
+                end.get_or_insert(pos + 2);
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(anyhow::anyhow!("Name compression loop"));
+                }
+                pos = (len & 0x3F) << 8 | low;
+                continue;
+            }
+            _ => {
+                let label = packet.get(pos + 1..pos + 1 + len).ok_or_else(|| anyhow::anyhow!("Truncated label"))?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+        }
+    }
+    Ok((labels.join("."), end.unwrap_or(pos + 1)))
+}
+
+/// Join instances with their SRV and address records; instances without an SRV
+/// record are left out, as there is nothing to connect to
+fn assemble(records: &Records) -> Vec<DiscoveredHost> {
+    let suffix = format!(".{}", SSH_SERVICE);
+    let mut hosts: Vec<DiscoveredHost> = records
+        .instances
+        .iter()
+        .filter_map(|instance| {
+            let (host, port) = records.services.get(&instance.to_ascii_lowercase())?;
+            let name = instance.strip_suffix(&suffix).unwrap_or(instance).to_string();
+            Some(DiscoveredHost {
+                name,
+                host: host.clone(),
+                port: *port,
+                addresses: records.addresses.get(host).cloned().unwrap_or_default(),
+            })
+        })
+        .collect();
+    hosts.sort_by_key(|host| host.name.to_lowercase());
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(out: &mut Vec<u8>, name: &str) {
+        for label in name.split('.') {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(0);
+    }
+
+    fn record(out: &mut Vec<u8>, record_type: u16, data: &[u8]) {
+        out.extend_from_slice(&record_type.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&120u32.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+    }
+
+    /// A response as Avahi sends it: PTR answer, SRV and address records as
+    /// additionals, with the service name compressed into pointers
+    fn response() -> Vec<u8> {
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 3];
+        let service_at = packet.len();
+        name(&mut packet, SSH_SERVICE);
+        let instance_at = packet.len() + 10;
+        let mut instance = vec![3];
+        instance.extend_from_slice(b"NAS");
+        instance.extend_from_slice(&[0xC0, service_at as u8]);
+        record(&mut packet, TYPE_PTR, &instance);
+
+        packet.extend_from_slice(&[0xC0, instance_at as u8]);
+        let mut srv = vec![0, 0, 0, 0, 0x08, 0xAE];
+        name(&mut srv, "nas.local");
+        record(&mut packet, TYPE_SRV, &srv);
+
+        name(&mut packet, "nas.local");
+        record(&mut packet, TYPE_A, &[192, 168, 1, 10]);
+        name(&mut packet, "nas.local");
+        record(&mut packet, TYPE_AAAA, &Ipv6Addr::LOCALHOST.octets());
+        packet
+    }
+
+    #[test]
+    fn test_query() {
+        let packet = query();
+        assert_eq!(&packet[4..6], &[0, 1]);
+        assert_eq!(read_name(&packet, 12).unwrap(), (SSH_SERVICE.to_string(), 12 + 17));
+        assert_eq!(&packet[packet.len() - 4..], &[0, 12, 0x80, 1]);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let mut records = Records::default();
+        parse_response(&response(), &mut records).unwrap();
+        let hosts = assemble(&records);
+        assert_eq!(
+            hosts,
+            [DiscoveredHost {
+                name: "NAS".to_string(),
+                host: "nas.local".to_string(),
+                port: 2222,
+                addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), IpAddr::V6(Ipv6Addr::LOCALHOST)],
+            }]
+        );
+        assert_eq!(hosts[0].connect_host(), "192.168.1.10");
+
+        // Repeated announcements don't duplicate the host
+        parse_response(&response(), &mut records).unwrap();
+        assert_eq!(assemble(&records).len(), 1);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_packets() {
+        let mut records = Records::default();
+        assert!(parse_response(&[0, 0, 0x84], &mut records).is_err());
+        let packet = response();
+        assert!(parse_response(&packet[..packet.len() - 5], &mut records).is_err());
+
+        // A pointer to itself
+        let looping = [0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0xC0, 12];
+        assert!(parse_response(&looping, &mut records).is_err());
+
+        // Queries from other browsers are ignored
+        let mut records = Records::default();
+        assert!(parse_response(&query(), &mut records).is_ok());
+        assert!(records.instances.is_empty());
+    }
+
+    #[test]
+    fn test_connect_host_without_addresses() {
+        let host = DiscoveredHost { name: "pi".to_string(), host: "pi.local".to_string(), port: 22, addresses: vec![] };
+        assert_eq!(host.connect_host(), "pi.local");
+    }
+}
@@ -0,0 +1,299 @@
+//! `bxssh edit`: edit a remote file in the local editor. The file is downloaded
+//! to a private temp file, and each save is uploaded back over SFTP, or through
+//! `sudo tee` when SFTP may not write it. A file that changed on the server since
+//! it was last synced is not overwritten.
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::SystemTime;
+
+use crate::ssh_client::{SftpSession, SshClient};
+use crate::transfer::{self, shell_quote};
+
+/// How often the local copy is checked for saves while the editor runs
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// What [`RemoteEdit::sync`] did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sync {
+    /// The local copy is as last synced
+    Unchanged,
+    Uploaded {
+        bytes: u64,
+        /// Written with `sudo tee` since SFTP was denied
+        sudo: bool,
+    },
+    /// The remote file changed since the last sync; nothing was written
+    Conflict,
+}
+
+/// Size and modification time of the remote file; `None` if it doesn't exist
+type Stamp = Option<(u64, u64)>;
+
+/// A remote file checked out to a local temp file
+#[derive(Debug)]
+pub struct RemoteEdit {
+    path: String,
+    dir: PathBuf,
+    local: PathBuf,
+    remote: Stamp,
+    /// SHA-256 of the contents last downloaded or uploaded
+    synced: String,
+    /// Local size and mtime when last hashed, to skip rehashing an untouched file
+    checked: Option<(u64, SystemTime)>,
+    keep: bool,
+}
+
+impl RemoteEdit {
+    /// Download `path` to a new temp file with the same name, readable only by us.
+    /// A missing file starts out empty and is created on the first save.
+    pub fn open(sftp: &mut dyn SftpSession, path: &str) -> Result<Self> {
+        let remote = stamp(sftp, path)?;
+        let mut contents = Vec::new();
+        if remote.is_some() {
+            transfer::download(sftp, path, &mut contents)?;
+        }
+
+        let dir = temp_dir()?;
+        let name = Path::new(path).file_name().map(|name| name.to_os_string()).unwrap_or_else(|| "file".into());
+        let local = dir.join(name);
+        let edit = Self {
+            path: path.to_string(),
+            dir,
+            local,
+            remote,
+            synced: sha256(&contents),
+            checked: None,
+            keep: false,
+        };
+        crate::private_file::write(&edit.local, &contents)?;
+        Ok(edit)
+    }
+
+    pub fn local_path(&self) -> &Path {
+        &self.local
+    }
+
+    /// Leave the temp file in place when this is dropped
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+
+    /// Upload the local copy if it was saved since the last sync. Unless `force`,
+    /// a remote file that changed in the meantime is left alone.
+    pub fn sync(&mut self, client: &SshClient, sftp: &mut dyn SftpSession, force: bool) -> Result<Sync> {
+        let failed = || format!("Failed to read {}", self.local.display());
+        let metadata = std::fs::metadata(&self.local).with_context(failed)?;
+        let checked = Some((metadata.len(), metadata.modified()?));
+        if checked == self.checked {
+            return Ok(Sync::Unchanged);
+        }
+        let contents = std::fs::read(&self.local).with_context(failed)?;
+        let digest = sha256(&contents);
+        if digest == self.synced {
+            self.checked = checked;
+            return Ok(Sync::Unchanged);
+        }
+
+        if !force && stamp(sftp, &self.path)? != self.remote {
+            return Ok(Sync::Conflict);
+        }
+        let sudo = match transfer::upload(sftp, &mut contents.as_slice(), &self.path) {
+            Ok(_) => false,
+            Err(e) if is_permission_denied(&e) => {
+                log::info!("SFTP may not write {}, trying sudo: {:#}", self.path, e);
+                sudo_write(client, &self.path, &contents)?;
+                true
+            }
+            Err(e) => return Err(e),
+        };
+        self.remote = stamp(sftp, &self.path)?;
+        self.synced = digest;
+        self.checked = checked;
+        Ok(Sync::Uploaded { bytes: contents.len() as u64, sudo })
+    }
+}
+
+impl Drop for RemoteEdit {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Remote command writing its stdin to `path` as root, without prompting
+pub fn sudo_write_command(path: &str) -> String {
+    format!("sudo -n tee -- {} >/dev/null", shell_quote(path))
+}
+
+/// Write `contents` to `path` through `sudo tee` on an exec channel; needs
+/// passwordless sudo, as there is no terminal to ask on
+fn sudo_write(client: &SshClient, path: &str, contents: &[u8]) -> Result<()> {
+    let mut channel = client.open_exec(&sudo_write_command(path))?;
+    crate::stdio::write_all(channel.as_mut(), contents)?;
+    channel.send_eof()?;
+    match channel.wait_close()? {
+        0 => Ok(()),
+        status => Err(anyhow::anyhow!(
+            "Permission denied writing {}, and `sudo -n tee` failed with status {} (needs passwordless sudo)",
+            path,
+            status
+        )),
+    }
+}
+
+/// Start `$VISUAL` or `$EDITOR` (else `vi`, or `notepad` on Windows) on `file`.
+/// The variable may hold arguments, as in `code --wait`.
+pub fn spawn_editor(file: &Path) -> Result<Child> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.trim().is_empty()))
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(format!("{} \"{}\"", editor, file.display()));
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!("{} \"$1\"", editor)).arg("bxssh-edit").arg(file);
+        command
+    };
+    command.spawn().with_context(|| format!("Failed to start editor '{}'", editor))
+}
+
+fn stamp(sftp: &mut dyn SftpSession, path: &str) -> Result<Stamp> {
+    match sftp.stat(path) {
+        Ok(attr) if attr.kind == crate::ssh_client::RemoteFileKind::Directory => {
+            Err(anyhow::anyhow!("{} is a directory", path))
+        }
+        Ok(attr) => Ok(Some((attr.size, attr.mtime))),
+        Err(e) if has_io_kind(&e, std::io::ErrorKind::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_permission_denied(error: &anyhow::Error) -> bool {
+    has_io_kind(error, std::io::ErrorKind::PermissionDenied)
+}
+
+fn has_io_kind(error: &anyhow::Error, kind: std::io::ErrorKind) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|io| io.kind() == kind)
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// New private directory for one file's temp copy
+fn temp_dir() -> Result<PathBuf> {
+    static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("bxssh-edit-{}-{}", std::process::id(), n));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Script, ScriptedSshServer};
+
+    fn client(server: ScriptedSshServer) -> SshClient {
+        let mut client = SshClient::new(Box::new(server.accept_key("alice")));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("alice", "/tmp/key").unwrap();
+        client
+    }
+
+    #[test]
+    fn test_edit_uploads_saves() {
+        let server = ScriptedSshServer::new().file("/srv/app.conf", "port = 80\n");
+        let files = server.files();
+        let client = client(server);
+        let mut sftp = client.open_sftp().unwrap();
+
+        let mut edit = RemoteEdit::open(sftp.as_mut(), "/srv/app.conf").unwrap();
+        let local = edit.local_path().to_path_buf();
+        assert!(local.ends_with("app.conf"));
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "port = 80\n");
+        assert_eq!(edit.sync(&client, sftp.as_mut(), false).unwrap(), Sync::Unchanged);
+
+        std::fs::write(&local, "port = 8080\n").unwrap();
+        assert_eq!(edit.sync(&client, sftp.as_mut(), false).unwrap(), Sync::Uploaded { bytes: 12, sudo: false });
+        assert_eq!(files.lock().unwrap()["/srv/app.conf"], b"port = 8080\n");
+        assert_eq!(edit.sync(&client, sftp.as_mut(), false).unwrap(), Sync::Unchanged);
+
+        drop(edit);
+        assert!(!local.exists(), "the temp copy is removed");
+    }
+
+    #[test]
+    fn test_edit_detects_conflicts() {
+        let server = ScriptedSshServer::new().file("/srv/app.conf", "port = 80\n");
+        let files = server.files();
+        let client = client(server);
+        let mut sftp = client.open_sftp().unwrap();
+        let mut edit = RemoteEdit::open(sftp.as_mut(), "/srv/app.conf").unwrap();
+
+        files.lock().unwrap().insert("/srv/app.conf".into(), b"port = 443\n".to_vec());
+        std::fs::write(edit.local_path(), "port = 8080\n").unwrap();
+        assert_eq!(edit.sync(&client, sftp.as_mut(), false).unwrap(), Sync::Conflict);
+        assert_eq!(files.lock().unwrap()["/srv/app.conf"], b"port = 443\n");
+
+        assert!(matches!(edit.sync(&client, sftp.as_mut(), true).unwrap(), Sync::Uploaded { .. }));
+        assert_eq!(files.lock().unwrap()["/srv/app.conf"], b"port = 8080\n");
+    }
+
+    #[test]
+    fn test_edit_new_file() {
+        let server = ScriptedSshServer::new();
+        let files = server.files();
+        let client = client(server);
+        let mut sftp = client.open_sftp().unwrap();
+        let mut edit = RemoteEdit::open(sftp.as_mut(), "/srv/notes.md").unwrap();
+        assert_eq!(std::fs::read(edit.local_path()).unwrap(), b"");
+
+        std::fs::write(edit.local_path(), "# Notes\n").unwrap();
+        assert!(matches!(edit.sync(&client, sftp.as_mut(), false).unwrap(), Sync::Uploaded { .. }));
+        assert_eq!(files.lock().unwrap()["/srv/notes.md"], b"# Notes\n");
+    }
+
+    #[test]
+    fn test_edit_falls_back_to_sudo() {
+        let written = "127.0.0.1 localhost\n10.0.0.5 db\n";
+        let server = ScriptedSshServer::new()
+            .file("/etc/hosts", "127.0.0.1 localhost\n")
+            .read_only("/etc/hosts")
+            .command(&sudo_write_command("/etc/hosts"), Script::new().expect(written).exit_status(0).eof())
+            .command(&sudo_write_command("/etc/shadow"), Script::new().exit_status(1).eof())
+            .file("/etc/shadow", "")
+            .read_only("/etc/shadow");
+        let input_log = server.input_log();
+        let client = client(server);
+        let mut sftp = client.open_sftp().unwrap();
+
+        let mut edit = RemoteEdit::open(sftp.as_mut(), "/etc/hosts").unwrap();
+        std::fs::write(edit.local_path(), written).unwrap();
+        let synced = edit.sync(&client, sftp.as_mut(), false).unwrap();
+        assert_eq!(synced, Sync::Uploaded { bytes: written.len() as u64, sudo: true });
+        assert_eq!(input_log.lock().unwrap().as_slice(), written.as_bytes());
+
+        let mut edit = RemoteEdit::open(sftp.as_mut(), "/etc/shadow").unwrap();
+        std::fs::write(edit.local_path(), "x").unwrap();
+        let err = edit.sync(&client, sftp.as_mut(), false).unwrap_err();
+        assert!(err.to_string().contains("needs passwordless sudo"));
+    }
+
+    #[test]
+    fn test_sudo_write_command_quotes_path() {
+        assert_eq!(sudo_write_command("/etc/it's"), r"sudo -n tee -- '/etc/it'\''s' >/dev/null");
+    }
+}
@@ -0,0 +1,204 @@
+//! The forwards of an interactive session in one place: the local listeners of
+//! `-L`, `--udp-forward` and `--auto-forward` and the server's ports of `-R`. When the connection
+//! drops and is re-dialled (`--tmux`), they carry on over the new one.
+use anyhow::Result;
+use std::net::IpAddr;
+use std::rc::Rc;
+
+use crate::auto_forward::AutoForward;
+use crate::local_forward::LocalForward;
+use crate::remote_forward::RemoteForward;
+use crate::remote_target::unbracketed;
+use crate::udp_forward::UdpForward;
+use crate::ssh_client::SshClient;
+use crate::terminal::SessionHook;
+
+/// The fields of `-L` and `-R`: `[bind_address:]port:host:hostport`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardFields {
+    /// `*` stands for every interface, `0.0.0.0`
+    pub bind_address: Option<String>,
+    /// The listening side's port, 0 for any
+    pub port: u16,
+    pub host: String,
+    pub host_port: u16,
+}
+
+impl ForwardFields {
+    /// Parse `[bind_address:]port:host:hostport`; IPv6 addresses go in brackets
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("'{}': use '[bind_address:]port:host:hostport'", spec);
+        let fields = split_fields(spec).ok_or_else(invalid)?;
+        let (bind_address, port, host, host_port) = match fields.as_slice() {
+            [port, host, host_port] => (None, *port, *host, *host_port),
+            [bind, port, host, host_port] if !bind.is_empty() => (Some(*bind), *port, *host, *host_port),
+            _ => return Err(invalid()),
+        };
+        let host = unbracketed(host);
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            bind_address: bind_address.map(|bind| match unbracketed(bind) {
+                "*" => "0.0.0.0".to_string(),
+                bind => bind.to_string(),
+            }),
+            port: port.parse().map_err(|_| invalid())?,
+            host: host.to_string(),
+            host_port: host_port.parse().ok().filter(|port| *port != 0).ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// `spec` split at colons outside brackets; `None` when a bracket is unbalanced
+fn split_fields(spec: &str) -> Option<Vec<&str>> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '[' if depth == 0 => depth = 1,
+            ']' if depth == 1 => depth = 0,
+            '[' | ']' => return None,
+            ':' if depth == 0 => {
+                fields.push(&spec[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&spec[start..]);
+    (depth == 0).then_some(fields)
+}
+
+/// Whether listening on `bind_address` keeps a forward to this machine (or, for
+/// `-R`, the server)
+pub fn is_loopback(bind_address: &str) -> bool {
+    bind_address.eq_ignore_ascii_case("localhost")
+        || unbracketed(bind_address).parse::<IpAddr>().is_ok_and(|address| address.is_loopback())
+}
+
+/// Refuse to listen beyond loopback unless `--gateway-ports` says so, since anyone
+/// who can reach the address could then use the tunnel
+pub fn check_bind(bind_address: &str, gateway_ports: bool) -> Result<()> {
+    if gateway_ports || is_loopback(bind_address) {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "Listening on {} would open the forward to other machines; add --gateway-ports to allow it",
+        bind_address
+    ))
+}
+
+/// Every forward of a session, polled as one hook
+#[derive(Default)]
+pub struct ForwardRegistry {
+    local: Option<LocalForward>,
+    udp: Option<UdpForward>,
+    auto: Option<AutoForward>,
+    remote: Option<RemoteForward>,
+}
+
+impl ForwardRegistry {
+    pub fn with_local_forward(mut self, local: LocalForward) -> Self {
+        self.local = Some(local);
+        self
+    }
+
+    pub fn with_udp_forward(mut self, udp: UdpForward) -> Self {
+        self.udp = Some(udp);
+        self
+    }
+
+    pub fn with_auto_forward(mut self, auto: AutoForward) -> Self {
+        self.auto = Some(auto);
+        self
+    }
+
+    pub fn with_remote_forward(mut self, remote: RemoteForward) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.local.is_none() && self.udp.is_none() && self.auto.is_none() && self.remote.is_none()
+    }
+
+    /// Move every forward to the re-dialled `client`: local listeners stay bound and
+    /// the server is asked for its ports again. Returns why any forward was dropped.
+    pub fn reconnect(&mut self, client: &Rc<SshClient>) -> Vec<anyhow::Error> {
+        if let Some(local) = &mut self.local {
+            local.reconnect(Rc::clone(client));
+        }
+        if let Some(udp) = &mut self.udp {
+            udp.reconnect(Rc::clone(client));
+        }
+        if let Some(auto) = &mut self.auto {
+            auto.reconnect(Rc::clone(client));
+        }
+        match &mut self.remote {
+            Some(remote) => remote.reconnect(Rc::clone(client)),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl SessionHook for ForwardRegistry {
+    fn on_output(&mut self, data: &[u8]) {
+        if let Some(auto) = &mut self.auto {
+            auto.on_output(data);
+        }
+    }
+
+    fn poll(&mut self) -> bool {
+        let mut activity = false;
+        if let Some(local) = &mut self.local {
+            activity |= local.poll();
+        }
+        if let Some(udp) = &mut self.udp {
+            activity |= udp.poll();
+        }
+        if let Some(auto) = &mut self.auto {
+            activity |= auto.poll();
+        }
+        if let Some(remote) = &mut self.remote {
+            activity |= remote.poll();
+        }
+        activity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forward_fields() {
+        let fields = ForwardFields::parse("8080:db.internal:5432").unwrap();
+        assert_eq!(fields.bind_address, None);
+        assert_eq!((fields.port, fields.host.as_str(), fields.host_port), (8080, "db.internal", 5432));
+
+        let fields = ForwardFields::parse("*:0:[::1]:22").unwrap();
+        assert_eq!((fields.bind_address.as_deref(), fields.port, fields.host.as_str()), (Some("0.0.0.0"), 0, "::1"));
+        assert_eq!(ForwardFields::parse("[::]:9000:h:1").unwrap().bind_address.as_deref(), Some("::"));
+
+        for invalid in ["8080", "8080:localhost", "x:localhost:3000", "8080:localhost:0", "8080::3000", ":1:h:2",
+            "8080:[::1:22", "a:b:c:d:e"]
+        {
+            assert!(ForwardFields::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_check_bind() {
+        for loopback in ["localhost", "127.0.0.1", "127.0.1.1", "::1", "[::1]", "LOCALHOST"] {
+            assert!(is_loopback(loopback), "{}", loopback);
+            assert!(check_bind(loopback, false).is_ok());
+        }
+        for open in ["0.0.0.0", "::", "192.168.1.10", "myhost"] {
+            let error = check_bind(open, false).unwrap_err();
+            assert!(error.to_string().contains("--gateway-ports"), "{}", error);
+            assert!(check_bind(open, true).is_ok());
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use crate::ssh_wire::Reader;
+use anyhow::Result;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+/// Smallest RSA host key accepted, as in OpenSSH
+const RSA_MIN_BITS: usize = 1024;
+const RSA_MAX_BITS: usize = 16384;
+
+/// A server host key, parsed from its SSH wire blob (RFC 4253 section 6.6, RFC 5656 section 3.1)
+#[derive(Debug, Clone)]
+pub enum HostKey {
+    Ed25519(ed25519_dalek::VerifyingKey),
+    EcdsaP256(p256::ecdsa::VerifyingKey),
+    EcdsaP384(p384::ecdsa::VerifyingKey),
+    Rsa(rsa::RsaPublicKey),
+}
+
+impl HostKey {
+    pub fn parse(blob: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(blob);
+        let key_type = reader.string()?;
+        let key = match key_type {
+            b"ssh-ed25519" => {
+                let bytes: [u8; 32] = reader
+                    .string()?
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Ed25519 host key must be 32 bytes"))?;
+                HostKey::Ed25519(ed25519_dalek::VerifyingKey::from_bytes(&bytes)?)
+            }
+            b"ecdsa-sha2-nistp256" | b"ecdsa-sha2-nistp384" => {
+                let curve = reader.string()?;
+                if !key_type.ends_with(curve) {
+                    return Err(anyhow::anyhow!("ECDSA host key curve does not match its type"));
+                }
+                let point = reader.string()?;
+                let invalid = |_| anyhow::anyhow!("Invalid ECDSA host key point");
+                if curve == b"nistp256" {
+                    HostKey::EcdsaP256(p256::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(invalid)?)
+                } else {
+                    HostKey::EcdsaP384(p384::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(invalid)?)
+                }
+            }
+            b"ssh-rsa" => {
+                let e = rsa::BigUint::from_bytes_be(reader.string()?);
+                let n = rsa::BigUint::from_bytes_be(reader.string()?);
+                if n.bits() < RSA_MIN_BITS {
+                    return Err(anyhow::anyhow!("RSA host key is shorter than {} bits", RSA_MIN_BITS));
+                }
+                HostKey::Rsa(rsa::RsaPublicKey::new_with_max_size(n, e, RSA_MAX_BITS)?)
+            }
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported host key type '{}'",
+                    String::from_utf8_lossy(other)
+                ))
+            }
+        };
+        if reader.remaining() != 0 {
+            return Err(anyhow::anyhow!("Trailing data after host key"));
+        }
+        Ok(key)
+    }
+
+    /// Key type as it appears in the blob and in known_hosts
+    pub fn key_type(&self) -> &'static str {
+        match self {
+            HostKey::Ed25519(_) => "ssh-ed25519",
+            HostKey::EcdsaP256(_) => "ecdsa-sha2-nistp256",
+            HostKey::EcdsaP384(_) => "ecdsa-sha2-nistp384",
+            HostKey::Rsa(_) => "ssh-rsa",
+        }
+    }
+
+    /// Check the server's signature over the exchange hash. `algorithm` is the
+    /// negotiated host key algorithm; the signature must be made with it.
+    pub fn verify(&self, algorithm: &str, exchange_hash: &[u8], signature_blob: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(signature_blob);
+        let signature_type = reader.string()?;
+        let signature = reader.string()?;
+        if signature_type != algorithm.as_bytes() {
+            return Err(anyhow::anyhow!(
+                "Server signed with '{}' instead of the negotiated '{}'",
+                String::from_utf8_lossy(signature_type),
+                algorithm
+            ));
+        }
+
+        let valid = match (self, algorithm) {
+            (HostKey::Ed25519(key), "ssh-ed25519") => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verify_strict(exchange_hash, &signature).is_ok()),
+            (HostKey::EcdsaP256(key), "ecdsa-sha2-nistp256") => {
+                use p256::ecdsa::signature::Verifier;
+                ecdsa_signature(signature, 32)
+                    .and_then(|bytes| p256::ecdsa::Signature::from_slice(&bytes).ok())
+                    .is_some_and(|signature| key.verify(exchange_hash, &signature).is_ok())
+            }
+            (HostKey::EcdsaP384(key), "ecdsa-sha2-nistp384") => {
+                use p384::ecdsa::signature::Verifier;
+                ecdsa_signature(signature, 48)
+                    .and_then(|bytes| p384::ecdsa::Signature::from_slice(&bytes).ok())
+                    .is_some_and(|signature| key.verify(exchange_hash, &signature).is_ok())
+            }
+            (HostKey::Rsa(key), "rsa-sha2-256") => verify_rsa::<Sha256>(key, exchange_hash, signature),
+            (HostKey::Rsa(key), "rsa-sha2-512") => verify_rsa::<Sha512>(key, exchange_hash, signature),
+            (HostKey::Rsa(_), "ssh-rsa") => {
+                return Err(anyhow::anyhow!("SHA-1 'ssh-rsa' host key signatures are not accepted"))
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Host key algorithm '{}' does not match a {} key",
+                    algorithm,
+                    self.key_type()
+                ))
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Host key signature verification failed"))
+        }
+    }
+}
+
+/// OpenSSH-style `SHA256:` fingerprint of a host key blob
+pub fn fingerprint(blob: &[u8]) -> String {
+    format!(
+        "SHA256:{}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(blob))
+    )
+}
+
+/// `<type> <base64>` as the key appears in a known_hosts line, after the host names
+pub fn known_hosts_key(blob: &[u8]) -> Result<String> {
+    let key = HostKey::parse(blob)?;
+    Ok(format!("{} {}", key.key_type(), base64::engine::general_purpose::STANDARD.encode(blob)))
+}
+
+/// How known_hosts names a server: the bare host on port 22, else `[host]:port`,
+/// so `::1` on port 2222 is `[::1]:2222`
+pub fn known_hosts_host(host: &str, port: u16) -> String {
+    match port {
+        22 => host.to_string(),
+        port => format!("[{}]:{}", host, port),
+    }
+}
+
+/// The host field for `host` on `port` hashed with `salt`, as `ssh-keygen -H` writes it
+pub fn hashed_known_hosts_host(host: &str, port: u16, salt: &[u8]) -> String {
+    let engine = base64::engine::general_purpose::STANDARD;
+    let hash = known_hosts_hmac(salt, &known_hosts_host(host, port)).finalize().into_bytes();
+    format!("|1|{}|{}", engine.encode(salt), engine.encode(hash))
+}
+
+/// Whether a known_hosts host field names `host` on `port`: comma-separated names and
+/// `*`/`?` patterns with `!` negations, or one hashed `|1|salt|hash` entry
+pub fn known_hosts_matches(field: &str, host: &str, port: u16) -> bool {
+    let name = known_hosts_host(host, port);
+    if let Some(hashed) = field.strip_prefix("|1|") {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let Some((salt, hash)) = hashed.split_once('|') else {
+            return false;
+        };
+        return match (engine.decode(salt), engine.decode(hash)) {
+            (Ok(salt), Ok(hash)) => known_hosts_hmac(&salt, &name).verify_slice(&hash).is_ok(),
+            _ => false,
+        };
+    }
+    let mut matched = false;
+    for pattern in field.split(',') {
+        match pattern.strip_prefix('!') {
+            Some(negated) if crate::host_policy::glob_matches(negated, &name) => return false,
+            Some(_) => {}
+            None => matched |= crate::host_policy::glob_matches(pattern, &name),
+        }
+    }
+    matched
+}
+
+/// What a known_hosts file says about a server's host key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownHost {
+    /// A line names the host with this key
+    Known,
+    /// Lines name the host, but with another key of this type: it changed, or
+    /// someone is in the middle
+    Changed,
+    /// No line names the host with a key of this type
+    Unknown,
+}
+
+impl std::fmt::Display for KnownHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            KnownHost::Known => "known",
+            KnownHost::Changed => "changed",
+            KnownHost::Unknown => "unknown",
+        })
+    }
+}
+
+/// Look the host key `blob` of `host` on `port` up in the text of a known_hosts file.
+/// `@cert-authority` and `@revoked` lines are not host keys and are skipped.
+pub fn check_known_hosts(known_hosts: &str, host: &str, port: u16, blob: &[u8]) -> Result<KnownHost> {
+    let key_type = HostKey::parse(blob)?.key_type();
+    let engine = base64::engine::general_purpose::STANDARD;
+    let mut status = KnownHost::Unknown;
+    for line in known_hosts.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with('@') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(hosts), Some(line_type), Some(key)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if line_type != key_type || !known_hosts_matches(hosts, host, port) {
+            continue;
+        }
+        if engine.decode(key).is_ok_and(|key| key == blob) {
+            return Ok(KnownHost::Known);
+        }
+        status = KnownHost::Changed;
+    }
+    Ok(status)
+}
+
+/// A known_hosts line recording `blob` for `host` on `port`, the name hashed with
+/// `salt` when given
+pub fn known_hosts_line(host: &str, port: u16, blob: &[u8], salt: Option<&[u8]>) -> Result<String> {
+    let name = match salt {
+        Some(salt) => hashed_known_hosts_host(host, port, salt),
+        None => known_hosts_host(host, port),
+    };
+    Ok(format!("{} {}", name, known_hosts_key(blob)?))
+}
+
+fn known_hosts_hmac(salt: &[u8], name: &str) -> Hmac<sha1::Sha1> {
+    let mut mac = Hmac::<sha1::Sha1>::new_from_slice(salt).expect("HMAC takes keys of any length");
+    mac.update(name.as_bytes());
+    mac
+}
+
+/// ECDSA signatures are two mpints, r and s (RFC 5656 section 3.1.2); returns
+/// them as the fixed-width concatenation the curve crates expect
+fn ecdsa_signature(signature: &[u8], width: usize) -> Option<Vec<u8>> {
+    let mut reader = Reader::new(signature);
+    let mut bytes = Vec::with_capacity(2 * width);
+    for _ in 0..2 {
+        let mpint = reader.string().ok()?;
+        let start = mpint.iter().position(|&b| b != 0).unwrap_or(mpint.len());
+        let value = &mpint[start..];
+        if value.len() > width {
+            return None;
+        }
+        bytes.resize(bytes.len() + width - value.len(), 0);
+        bytes.extend_from_slice(value);
+    }
+    (reader.remaining() == 0).then_some(bytes)
+}
+
+/// RSASSA-PKCS1-v1_5 over the hashed exchange hash (RFC 8332)
+fn verify_rsa<D>(key: &rsa::RsaPublicKey, exchange_hash: &[u8], signature: &[u8]) -> bool
+where
+    D: Digest + rsa::pkcs8::AssociatedOid,
+{
+    use rsa::traits::PublicKeyParts;
+    // Some servers drop leading zero bytes from the signature
+    let size = key.size();
+    if signature.len() > size {
+        return false;
+    }
+    let mut padded = vec![0u8; size - signature.len()];
+    padded.extend_from_slice(signature);
+    key.verify(rsa::Pkcs1v15Sign::new::<D>(), &D::digest(exchange_hash), &padded)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_wire::put_string;
+
+    const MESSAGE: &[u8] = b"bxssh exchange hash";
+
+    // Generated with ssh-keygen and signed over MESSAGE with `openssl dgst -sign`
+    const RSA_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAABAQCp7D/8xTSGxu10pfVwmSoY0PVPjF9E+48klKuHyWcmLY4XgBiTRL+KUSaEQQBaWP7NfJl2SU2ixlCFbiw7+dZN3+AldBoC9ssW5YJbUW3YZkdVNk/7jh5IKv7+MnW1v4S2Fo6stLY34RAO3qY6yIvgrPxJyMp/U2AGGy5wLWu88XjnnvL/nInLfamlPi862z7INkjHiO4lzqeLv/ppOfeuTKV04nsw/wBSedVLLhXdoeXJrJnZAThVNPTEl2T34POCZUlspf+MjGTfEzcul8HUu3DwYkKyLMY/vDym6X34tY4ma0WjxpywsJ25uDvHJ+tATBs67fuu29LvonepNGGr";
+    const RSA_SHA256: &str = "B/iwfecbLGnW44Bm6qQNK1jz2m80lm1jtNxwExsFFqjAoIKs7Ns67E0cOfHvPhPpp8nmjWScu9LAhPCwpieJZc4EjGfOaDq0psnG8dDka4sDpqI1SOY9f1YSxqwGJ5a4Zx+10nTqSkO5arPzQrhi0X+hR3vFFyy+E9hy6zA+SNuzNllSUAK0luYOfBfPwloBXFcZY1U0JKjNWV2rQUwEQe/5sQ8chRbe+5DdMBDQ7nYk3evvhtnVnTrHLPMXyHkMS+SCZ9Q48MX+yqHEAVvdGOPybi9fyrIlIfaZmjSTbjiI/Gur1XKcveLElmr4fGDn5HkX2+Ooe6gODWQH/szy2Q==";
+    const RSA_SHA512: &str = "ePxM0JJLqtRfkAzw2HHrPvLK6dbq2ZAZ7jfwkl8HapWkjLLknAHI6BWDF717j0veG02jZpg7il7t0gVloaC5XdfhW3rx85p62jc6YsM8DHS2asmfaTM6CVYvkMTbZSqjmy58/pLwrziirMQXwX/dU8wRC4whtxKD/1PGwJyv3b3aS5gdyQWUTXIyZVv0ubbDX/B8etwbYlh7vz38F68mONXb9VlMdI3z8s4oeHkR0w8gbOcHFJbitpt/vT9OFVhYSq8UfrHocx91hXtfbQf7TsL6ZPvm8Nt7fLXZ19QQrB56m1hr5lG3H4UKmKX7+P2fdDD7lm0BNTwQOOCAEvWz3w==";
+    const P256_KEY: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBNVg8hVJEcd9KA+rg/oIoY2zWr0RmeMedawIt5A0uwXJjrFtuQiIsriodW3DlPya0m5Sc4nRiFpvN7iz2P0VpLc=";
+    const P256_SIGNATURE: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAABJAAAAIBOngETNmASkLqqqWigoy1d05M6zCqdT96+PAZ5skq1AAAAAIQDz/pYiYuXNvfRq//6cULzyh+GFcFuyYQtgddDZj4630g==";
+
+    fn decode(text: &str) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD.decode(text).unwrap()
+    }
+
+    fn signature_blob(algorithm: &str, signature: &[u8]) -> Vec<u8> {
+        let mut blob = Vec::new();
+        put_string(&mut blob, algorithm.as_bytes());
+        put_string(&mut blob, signature);
+        blob
+    }
+
+    #[test]
+    fn test_verify_rsa_sha2() {
+        let key = HostKey::parse(&decode(RSA_KEY)).unwrap();
+        assert_eq!(key.key_type(), "ssh-rsa");
+
+        let sha256 = signature_blob("rsa-sha2-256", &decode(RSA_SHA256));
+        let sha512 = signature_blob("rsa-sha2-512", &decode(RSA_SHA512));
+        key.verify("rsa-sha2-256", MESSAGE, &sha256).unwrap();
+        key.verify("rsa-sha2-512", MESSAGE, &sha512).unwrap();
+
+        assert!(key.verify("rsa-sha2-256", b"other hash", &sha256).is_err());
+        // Signature type must be the negotiated one
+        assert!(key.verify("rsa-sha2-512", MESSAGE, &sha256).is_err());
+        let sha1 = signature_blob("ssh-rsa", &decode(RSA_SHA256));
+        assert!(key.verify("ssh-rsa", MESSAGE, &sha1).is_err());
+    }
+
+    #[test]
+    fn test_verify_ecdsa() {
+        let key = HostKey::parse(&decode(P256_KEY)).unwrap();
+        let signature = decode(P256_SIGNATURE);
+        key.verify("ecdsa-sha2-nistp256", MESSAGE, &signature).unwrap();
+        assert!(key.verify("ecdsa-sha2-nistp256", b"other hash", &signature).is_err());
+        assert!(key.verify("ssh-ed25519", MESSAGE, &signature).is_err());
+
+        // P-384, signed here and encoded the way OpenSSH does
+        use p384::ecdsa::signature::Signer;
+        let signing_key = p384::ecdsa::SigningKey::from_slice(&[7u8; 48]).unwrap();
+        let mut blob = Vec::new();
+        put_string(&mut blob, b"ecdsa-sha2-nistp384");
+        put_string(&mut blob, b"nistp384");
+        put_string(&mut blob, signing_key.verifying_key().to_encoded_point(false).as_bytes());
+        let key = HostKey::parse(&blob).unwrap();
+        assert_eq!(key.key_type(), "ecdsa-sha2-nistp384");
+
+        let signature: p384::ecdsa::Signature = signing_key.sign(MESSAGE);
+        let (r, s) = signature.split_bytes();
+        let mut inner = Vec::new();
+        for scalar in [r, s] {
+            let mut mpint = vec![0u8];
+            mpint.extend_from_slice(&scalar);
+            put_string(&mut inner, &mpint);
+        }
+        let signature = signature_blob("ecdsa-sha2-nistp384", &inner);
+        key.verify("ecdsa-sha2-nistp384", MESSAGE, &signature).unwrap();
+        assert!(key.verify("ecdsa-sha2-nistp384", b"other hash", &signature).is_err());
+    }
+
+    #[test]
+    fn test_verify_ed25519() {
+        use ed25519_dalek::Signer;
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[9u8; 32]);
+        let mut blob = Vec::new();
+        put_string(&mut blob, b"ssh-ed25519");
+        put_string(&mut blob, signing_key.verifying_key().as_bytes());
+        let key = HostKey::parse(&blob).unwrap();
+
+        let signature = signature_blob("ssh-ed25519", &signing_key.sign(MESSAGE).to_bytes());
+        key.verify("ssh-ed25519", MESSAGE, &signature).unwrap();
+        assert!(key.verify("ssh-ed25519", b"other hash", &signature).is_err());
+        assert!(key.verify("ssh-ed25519", MESSAGE, &signature_blob("ssh-ed25519", &[0u8; 12])).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_keys() {
+        let mut blob = Vec::new();
+        put_string(&mut blob, b"ssh-dss");
+        assert!(HostKey::parse(&blob).is_err());
+
+        // Curve name must match the key type
+        let mut blob = decode(P256_KEY);
+        let at = blob.windows(8).position(|w| w == b"nistp256").unwrap();
+        blob[at..at + 8].copy_from_slice(b"nistp384");
+        assert!(HostKey::parse(&blob).is_err());
+
+        let mut blob = decode(RSA_KEY);
+        blob.push(0);
+        assert!(HostKey::parse(&blob).is_err());
+
+        let mut small = Vec::new();
+        put_string(&mut small, b"ssh-rsa");
+        put_string(&mut small, &[1, 0, 1]);
+        put_string(&mut small, &[0xC5; 64]);
+        assert!(HostKey::parse(&small).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        // Matches `ssh-keygen -lf` for the fixture key
+        assert_eq!(fingerprint(&decode(P256_KEY)), "SHA256:FTf29TFFBwGI+KzGQxVdp1NEfXLsptDpnXGlSozBdKA");
+        assert_eq!(known_hosts_key(&decode(P256_KEY)).unwrap(), format!("ecdsa-sha2-nistp256 {}", P256_KEY));
+        assert!(known_hosts_key(b"not a key").is_err());
+    }
+
+    #[test]
+    fn test_known_hosts_names() {
+        assert_eq!(known_hosts_host("example.com", 22), "example.com");
+        assert_eq!(known_hosts_host("::1", 22), "::1");
+        assert_eq!(known_hosts_host("::1", 2222), "[::1]:2222");
+        assert!(known_hosts_matches("example.com,[::1]:2222", "::1", 2222));
+        assert!(!known_hosts_matches("example.com,[::1]:2222", "::1", 22));
+        assert!(known_hosts_matches("*.example.com,!db.example.com", "web.example.com", 22));
+        assert!(!known_hosts_matches("*.example.com,!db.example.com", "db.example.com", 22));
+        assert!(known_hosts_matches("[fe80::*]:2222", "fe80::1%eth0", 2222));
+    }
+
+    #[test]
+    fn test_hashed_known_hosts() {
+        // Written by `ssh-keygen -H` for `[::1]:2222` and `fe80::1%eth0`
+        let loopback = "|1|nr5VIm10mKqOfbYty0uW16eiUd8=|P3Trxn+iDcxHhfcTsRKBRbtdvPU=";
+        let link_local = "|1|lucSmOXoQgF3kvyH2M+O+lxJ2qM=|/DHmq3zbWY+ultAaxPyihgqnuIs=";
+        assert!(known_hosts_matches(loopback, "::1", 2222));
+        assert!(!known_hosts_matches(loopback, "::1", 22));
+        assert!(known_hosts_matches(link_local, "fe80::1%eth0", 22));
+        assert!(!known_hosts_matches("|1|not base64|", "::1", 2222));
+
+        let salt = base64::engine::general_purpose::STANDARD.decode("nr5VIm10mKqOfbYty0uW16eiUd8=").unwrap();
+        assert_eq!(hashed_known_hosts_host("::1", 2222, &salt), loopback);
+    }
+
+    #[test]
+    fn test_check_known_hosts() {
+        let blob = decode(P256_KEY);
+        let other = decode(P256_KEY).iter().rev().copied().collect::<Vec<_>>();
+        let other = base64::engine::general_purpose::STANDARD.encode(other);
+        let line = known_hosts_line("::1", 2222, &blob, None).unwrap();
+        assert_eq!(line, format!("[::1]:2222 ecdsa-sha2-nistp256 {}", P256_KEY));
+
+        let file = format!("# comment\n@revoked * ecdsa-sha2-nistp256 {}\n{}\n", P256_KEY, line);
+        assert_eq!(check_known_hosts(&file, "::1", 2222, &blob).unwrap(), KnownHost::Known);
+        assert_eq!(check_known_hosts(&file, "::1", 22, &blob).unwrap(), KnownHost::Unknown);
+        let changed = format!("[::1]:2222 ecdsa-sha2-nistp256 {}\n", other);
+        assert_eq!(check_known_hosts(&changed, "::1", 2222, &blob).unwrap(), KnownHost::Changed);
+        // Another key type for the host says nothing about this one
+        let ed25519 = "[::1]:2222 ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl\n";
+        assert_eq!(check_known_hosts(ed25519, "::1", 2222, &blob).unwrap(), KnownHost::Unknown);
+
+        let hashed = known_hosts_line("::1", 2222, &blob, Some(b"0123456789abcdefghij")).unwrap();
+        assert!(hashed.starts_with("|1|"));
+        assert_eq!(check_known_hosts(&hashed, "::1", 2222, &blob).unwrap(), KnownHost::Known);
+    }
+}
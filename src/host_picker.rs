@@ -0,0 +1,312 @@
+//! Full-screen fuzzy picker over recent connections and the hosts named in
+//! profiles.toml and ~/.ssh/config; `bxssh` without a target shows it on a terminal
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, terminal};
+use std::io::{self, Write};
+
+use crate::i18n::{self, Msg};
+use crate::profiles::{Profile, Profiles};
+use crate::recent::RecentList;
+
+/// Something to connect to
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostEntry {
+    /// `user@host` or a profile name, used as the command-line target
+    pub target: String,
+    /// Port last used, for recent connections
+    pub port: Option<u16>,
+    /// Where it came from and what it resolves to, shown after the target
+    pub hint: String,
+}
+
+/// Recent connections, newest first, then the profile hosts none of them used
+pub fn entries(recent: &RecentList, profiles: &Profiles) -> Vec<HostEntry> {
+    let hosts = profiles.hosts();
+    let profile_hint = |name: &str| hosts.iter().find(|(host, _)| host == name).map(|(_, profile)| describe(profile));
+
+    let mut entries: Vec<HostEntry> = recent
+        .connections
+        .iter()
+        .map(|connection| {
+            let mut hint = format!("{} {}", i18n::tr(Msg::RecentLabel, &[]), connection.last_connected.format("%Y-%m-%d %H:%M"));
+            if let Some(profile) = profile_hint(&connection.host).filter(|details| !details.is_empty()) {
+                hint = format!("{}  {}", hint, profile);
+            }
+            HostEntry {
+                target: format!("{}@{}", connection.user, connection.host),
+                port: Some(connection.port),
+                hint,
+            }
+        })
+        .collect();
+    for (name, profile) in &hosts {
+        if recent.connections.iter().any(|connection| &connection.host == name) {
+            continue;
+        }
+        let hint = format!("{} {}", i18n::tr(Msg::ProfileLabel, &[]), describe(profile));
+        entries.push(HostEntry { target: name.clone(), port: None, hint: hint.trim_end().to_string() });
+    }
+    entries
+}
+
+/// `user@hostname:port key NAME`, leaving out what the profile doesn't set
+fn describe(profile: &Profile) -> String {
+    let mut parts = Vec::new();
+    let address = match (&profile.user, &profile.host_name) {
+        (Some(user), Some(host)) => format!("{}@{}", user, host),
+        (Some(user), None) => format!("{}@", user),
+        (None, Some(host)) => host.clone(),
+        (None, None) => String::new(),
+    };
+    match profile.port {
+        Some(port) => parts.push(format!("{}:{}", address, port)),
+        None if !address.is_empty() => parts.push(address),
+        None => {}
+    }
+    if !profile.identity_file.is_empty() {
+        parts.push(crate::ui::message(crate::ui::Icon::Key, &profile.identity_file.join(", ")));
+    }
+    parts.join("  ")
+}
+
+/// How well `query` matches `text` as a case-insensitive subsequence, higher being
+/// better; `None` when some query character is missing. Runs of adjacent characters
+/// and matches at word starts (after `@`, `.`, `-`, ...) count extra, gaps cost.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for wanted in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = next + text[next..].iter().position(|&c| c == wanted)?;
+        score += 10;
+        if previous.is_some_and(|previous| previous + 1 == found) {
+            score += 15;
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 10;
+        }
+        score -= (found - next) as i32;
+        previous = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}
+
+/// What a key press asks the picker to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    /// Connect to the entry at this index
+    Select(usize),
+    Cancel,
+}
+
+/// Query, filtered entries and selection, separate from the terminal
+#[derive(Debug)]
+pub struct Picker<'a> {
+    entries: &'a [HostEntry],
+    query: String,
+    /// Indexes into `entries`, best match first
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+impl<'a> Picker<'a> {
+    pub fn new(entries: &'a [HostEntry]) -> Self {
+        let mut picker = Self { entries, query: String::new(), matches: Vec::new(), selected: 0 };
+        picker.filter();
+        picker
+    }
+
+    /// Ties keep the list order, so recent connections stay first
+    fn filter(&mut self) {
+        let mut scored: Vec<(i32, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| fuzzy_score(&self.query, &format!("{} {}", entry.target, entry.hint)).map(|score| (score, i)))
+            .collect();
+        scored.sort_by_key(|&(score, i)| (std::cmp::Reverse(score), i));
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.selected = 0;
+    }
+
+    pub fn key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Cancel,
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => return Action::Cancel,
+            KeyCode::Enter => {
+                return self.matches.get(self.selected).map_or(Action::Continue, |&i| Action::Select(i));
+            }
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('p') if ctrl => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Tab => self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1)),
+            KeyCode::Char('n') if ctrl => self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1)),
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.filter();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.filter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    /// Screen contents for a `width` x `height` terminal: the query, as many
+    /// matches as fit with the selection in view, and the key hints
+    pub fn lines(&self, width: usize, height: usize) -> Vec<String> {
+        let rows = height.saturating_sub(2).max(1);
+        let first = self.selected.saturating_sub(rows - 1);
+        let target_width = self.entries.iter().map(|entry| entry.target.chars().count()).max().unwrap_or(0);
+
+        let mut lines = vec![format!("> {}", self.query)];
+        if self.matches.is_empty() {
+            lines.push(format!("  {}", i18n::tr(Msg::PickerNoMatch, &[])));
+        }
+        for (row, &i) in self.matches.iter().enumerate().skip(first).take(rows) {
+            let marker = if row == self.selected { '▶' } else { ' ' };
+            let entry = &self.entries[i];
+            lines.push(format!("{} {:<width$}  {}", marker, entry.target, entry.hint, width = target_width));
+        }
+        lines.push(i18n::tr(Msg::PickerHelp, &[&self.matches.len(), &self.entries.len()]));
+        lines.into_iter().map(|line| line.chars().take(width).collect()).collect()
+    }
+}
+
+/// Restores the terminal however the picker ends
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode().context("Failed to enable raw mode")?;
+        let screen = Self;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Show the picker until an entry is chosen (its index) or it is cancelled
+pub fn run(entries: &[HostEntry]) -> Result<Option<usize>> {
+    let _screen = Screen::enter()?;
+    let mut picker = Picker::new(entries);
+    let mut stdout = io::stdout();
+    loop {
+        let (width, height) = terminal::size().unwrap_or((80, 24));
+        execute!(stdout, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))?;
+        write!(stdout, "{}", picker.lines(width as usize, height as usize).join("\r\n"))?;
+        stdout.flush()?;
+
+        let key = match event::read().context("Failed to read from the terminal")? {
+            // Windows consoles report releases too
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue,
+        };
+        match picker.key(key) {
+            Action::Continue => {}
+            Action::Select(i) => return Ok(Some(i)),
+            Action::Cancel => return Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(target: &str, hint: &str) -> HostEntry {
+        HostEntry { target: target.to_string(), port: None, hint: hint.to_string() }
+    }
+
+    fn press(picker: &mut Picker, code: KeyCode) -> Action {
+        picker.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("", "anything").is_some());
+        assert!(fuzzy_score("prd", "deploy@prod").is_some());
+        assert!(fuzzy_score("xyz", "deploy@prod").is_none());
+        assert!(fuzzy_score("dp", "pd").is_none(), "order matters");
+        // Adjacent and word-start matches beat scattered ones
+        assert!(fuzzy_score("prod", "prod") > fuzzy_score("prod", "p-r-o-d"));
+        assert!(fuzzy_score("web", "deploy@web1") > fuzzy_score("web", "wide-eb"));
+        assert_eq!(fuzzy_score("PROD", "prod"), fuzzy_score("prod", "PROD"));
+    }
+
+    #[test]
+    fn test_entries_from_recent_and_profiles() {
+        let profiles = Profiles::parse(
+            "[prod]\nHostName = \"prod.example.com\"\nUser = \"deploy\"\nPort = 2222\nIdentityFile = \"work\"\n\n[db]\n",
+        )
+        .unwrap();
+        let mut recent = RecentList::default();
+        recent.record("prod", "deploy", 2222, chrono::Utc.with_ymd_and_hms(2026, 5, 1, 9, 30, 0).unwrap());
+
+        let entries = entries(&recent, &profiles);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].target, "deploy@prod");
+        assert_eq!(entries[0].port, Some(2222));
+        assert_eq!(entries[0].hint, "(recent) 2026-05-01 09:30  deploy@prod.example.com:2222  🔑 work");
+        assert_eq!(entries[1], entry("db", "(profile)"));
+    }
+
+    #[test]
+    fn test_picker_filters_and_selects() {
+        let entries = [entry("deploy@prod", "(recent)"), entry("web1", "(profile)"), entry("db", "(profile)")];
+        let mut picker = Picker::new(&entries);
+        assert_eq!(picker.matches, [0, 1, 2]);
+
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Down);
+        assert_eq!(press(&mut picker, KeyCode::Enter), Action::Select(2));
+
+        for c in "wb".chars() {
+            press(&mut picker, KeyCode::Char(c));
+        }
+        assert_eq!(picker.matches, [1]);
+        assert_eq!(press(&mut picker, KeyCode::Enter), Action::Select(1));
+
+        press(&mut picker, KeyCode::Char('z'));
+        assert!(picker.matches.is_empty());
+        assert_eq!(press(&mut picker, KeyCode::Enter), Action::Continue);
+        press(&mut picker, KeyCode::Backspace);
+        assert_eq!(picker.matches, [1]);
+
+        assert_eq!(picker.key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), Action::Cancel);
+        assert_eq!(press(&mut picker, KeyCode::Esc), Action::Cancel);
+    }
+
+    #[test]
+    fn test_picker_lines_scroll_to_selection() {
+        let entries: Vec<HostEntry> = (0..10).map(|i| entry(&format!("host{}", i), "")).collect();
+        let mut picker = Picker::new(&entries);
+        for _ in 0..6 {
+            press(&mut picker, KeyCode::Down);
+        }
+        let lines = picker.lines(12, 5);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "> ");
+        assert_eq!(lines[1], "  host4  ");
+        assert_eq!(lines[3], "▶ host6  ");
+        assert!(lines.iter().all(|line| line.chars().count() <= 12));
+    }
+}
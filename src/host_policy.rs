@@ -0,0 +1,326 @@
+//! Which servers may be connected to at all: allowed and denied host patterns
+//! and port ranges, for kiosk and embedded deployments. The CLI reads
+//! `~/.config/bxssh/policy.toml`, else the `[policy]` table of `~/.config/bxssh/config.toml`;
+//! WASM pages pass the same TOML to `setConnectionPolicy`.
+//!
+//! ```toml
+//! allow = ["*.corp.example.com", "10.0.0.0/8"]  # when set, nothing else
+//! deny = ["db-*.corp.example.com"]              # wins over allow
+//! ports = ["22", "2200-2299"]                   # when set, nothing else
+//! deny_ports = ["2222"]
+//! ```
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostPolicy {
+    /// Host patterns (`*` and `?` globs, or CIDR blocks for addresses); empty allows any
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Ports or `low-high` ranges; empty allows any
+    #[serde(default)]
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub deny_ports: Vec<String>,
+}
+
+/// A connection the policy refuses; find it with
+/// `e.chain().find_map(|c| c.downcast_ref::<PolicyViolation>())`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub host: String,
+    pub port: u16,
+    /// Which rule refused it
+    pub reason: String,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Connection to {}:{} blocked by policy: {}", self.host, self.port, self.reason)
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+impl HostPolicy {
+    /// Parse and check a policy, rejecting malformed port ranges and CIDR blocks
+    /// rather than letting them silently match nothing
+    pub fn parse(text: &str) -> Result<Self> {
+        let policy: Self = toml::from_str(text).context("Invalid connection policy")?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Check the port ranges and CIDR blocks of a policy read some other way
+    pub fn validate(&self) -> Result<()> {
+        for range in self.ports.iter().chain(&self.deny_ports) {
+            parse_port_range(range)?;
+        }
+        for pattern in self.allow.iter().chain(&self.deny) {
+            if pattern.contains('/') {
+                parse_cidr(pattern)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `policy.toml` in the config directory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn path() -> Result<std::path::PathBuf> {
+        Ok(crate::paths::config_dir()?.join("policy.toml"))
+    }
+
+    /// The policy in `path`; without the file everything is allowed
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_from(path: &std::path::Path) -> Result<Option<Self>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Self::parse(&content).with_context(|| format!("In {}", path.display())).map(Some)
+    }
+
+    /// Whether `host:port` may be connected to
+    pub fn check(&self, host: &str, port: u16) -> std::result::Result<(), PolicyViolation> {
+        let violation = |reason: String| PolicyViolation { host: host.to_string(), port, reason };
+        if let Some(pattern) = self.deny.iter().find(|pattern| host_matches(pattern, host)) {
+            return Err(violation(format!("host matches denied pattern '{}'", pattern)));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|pattern| host_matches(pattern, host)) {
+            return Err(violation("host is not on the allow list".to_string()));
+        }
+        if let Some(range) = self.deny_ports.iter().find(|range| port_in(range, port)) {
+            return Err(violation(format!("port is in denied range '{}'", range)));
+        }
+        if !self.ports.is_empty() && !self.ports.iter().any(|range| port_in(range, port)) {
+            return Err(violation(format!("port {} is not in the allowed ports", port)));
+        }
+        Ok(())
+    }
+
+    /// Whether the addresses `host` resolved to may be connected to: a name that
+    /// passed [`HostPolicy::check`] can still resolve into a denied CIDR block
+    pub fn check_addresses(
+        &self,
+        host: &str,
+        port: u16,
+        addresses: &[IpAddr],
+    ) -> std::result::Result<(), PolicyViolation> {
+        let blocks = || self.deny.iter().filter(|pattern| pattern.contains('/'));
+        for address in addresses {
+            let address = address.to_string();
+            if let Some(pattern) = blocks().find(|pattern| host_matches(pattern, &address)) {
+                let reason = format!("{} resolves to {}, in denied block '{}'", host, address, pattern);
+                return Err(PolicyViolation { host: host.to_string(), port, reason });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [`HostPolicy::check_addresses`] under the policy in force, once `host` has been resolved
+pub fn enforce_addresses(host: &str, port: u16, addresses: &[IpAddr]) -> Result<()> {
+    let Some(policy) = active()? else {
+        return Ok(());
+    };
+    policy.check_addresses(host, port, addresses)?;
+    Ok(())
+}
+
+/// Check `host:port` against the policy in force: `~/.config/bxssh/policy.toml` or the
+/// settings file's `[policy]` in the CLI, or the one the page set in WASM. An
+/// unreadable or invalid policy file refuses everything, so a broken kiosk setup
+/// fails closed.
+pub fn enforce(host: &str, port: u16) -> Result<()> {
+    let Some(policy) = active()? else {
+        return Ok(());
+    };
+    policy.check(host, port)?;
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn active() -> Result<Option<HostPolicy>> {
+    let path = HostPolicy::path()?;
+    let policy = match HostPolicy::load_from(&path) {
+        Ok(None) => crate::settings::Settings::load().map(|settings| settings.policy),
+        loaded => loaded,
+    };
+    policy.context("Refusing to connect until the connection policy is fixed")
+}
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+    static ACTIVE: std::cell::RefCell<Option<HostPolicy>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(target_arch = "wasm32")]
+fn active() -> Result<Option<HostPolicy>> {
+    Ok(ACTIVE.with(|active| active.borrow().clone()))
+}
+
+/// Put `policy` in force for this page's connections; `None` lifts it
+#[cfg(target_arch = "wasm32")]
+pub fn set_active(policy: Option<HostPolicy>) {
+    ACTIVE.with(|active| *active.borrow_mut() = policy);
+}
+
+/// `pattern` is a CIDR block when it has a `/`, otherwise a glob on the host as typed
+fn host_matches(pattern: &str, host: &str) -> bool {
+    if pattern.contains('/') {
+        let address = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+        return match (parse_cidr(pattern), address) {
+            (Ok((network, prefix)), Ok(address)) => in_network(address, network, prefix),
+            _ => false,
+        };
+    }
+    glob_matches(pattern, host)
+}
+
+pub(crate) fn parse_cidr(pattern: &str) -> Result<(IpAddr, u32)> {
+    let invalid = || anyhow::anyhow!("Invalid CIDR block '{}' (use e.g. 10.0.0.0/8 or fd00::/8)", pattern);
+    let (network, prefix) = pattern.split_once('/').ok_or_else(invalid)?;
+    let network: IpAddr = network.parse().map_err(|_| invalid())?;
+    let max = if network.is_ipv4() { 32 } else { 128 };
+    match prefix.parse::<u32>() {
+        Ok(prefix) if prefix <= max => Ok((network, prefix)),
+        _ => Err(invalid()),
+    }
+}
+
+pub(crate) fn in_network(address: IpAddr, network: IpAddr, prefix: u32) -> bool {
+    let mask = |bits: u32, width: u32| if bits == 0 { 0 } else { u128::MAX << (width - bits) };
+    match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = mask(prefix, 32) as u32;
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = mask(prefix, 128);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+fn parse_port_range(range: &str) -> Result<RangeInclusive<u16>> {
+    let invalid = || anyhow::anyhow!("Invalid port range '{}' (use e.g. 22 or 2200-2299)", range);
+    let (low, high) = range.split_once('-').unwrap_or((range, range));
+    let low: u16 = low.trim().parse().map_err(|_| invalid())?;
+    let high: u16 = high.trim().parse().map_err(|_| invalid())?;
+    if low > high {
+        return Err(invalid());
+    }
+    Ok(low..=high)
+}
+
+fn port_in(range: &str, port: u16) -> bool {
+    parse_port_range(range).is_ok_and(|range| range.contains(&port))
+}
+
+/// `*` matches any run of characters and `?` exactly one, ASCII case-insensitively
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase().into_bytes();
+    let text = text.to_ascii_lowercase().into_bytes();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_hosts() {
+        let policy = HostPolicy::parse(
+            r#"
+            allow = ["*.corp.example.com", "10.0.0.0/8", "fd00::/8"]
+            deny = ["db-*.corp.example.com", "10.0.9.0/24"]
+            "#,
+        )
+        .unwrap();
+        assert!(policy.check("web.corp.example.com", 22).is_ok());
+        assert!(policy.check("WEB.Corp.Example.com", 22).is_ok());
+        assert!(policy.check("10.1.2.3", 22).is_ok());
+        assert!(policy.check("[fd12::1]", 22).is_ok());
+
+        let denied = policy.check("db-1.corp.example.com", 22).unwrap_err();
+        assert_eq!(denied.reason, "host matches denied pattern 'db-*.corp.example.com'");
+        assert!(policy.check("10.0.9.7", 22).is_err());
+        assert!(policy.check("example.org", 22).unwrap_err().to_string().contains("not on the allow list"));
+        assert!(policy.check("11.0.0.1", 22).is_err());
+
+        // A name on the allow list that resolves into a denied block
+        let addresses = ["10.0.9.7".parse().unwrap()];
+        let denied = policy.check_addresses("app.corp.example.com", 22, &addresses).unwrap_err();
+        assert_eq!(denied.reason, "app.corp.example.com resolves to 10.0.9.7, in denied block '10.0.9.0/24'");
+        assert!(policy.check_addresses("app.corp.example.com", 22, &["10.1.0.1".parse().unwrap()]).is_ok());
+    }
+
+    #[test]
+    fn test_check_ports() {
+        let policy = HostPolicy::parse("ports = [\"22\", \"2200-2299\"]\ndeny_ports = [\"2222\"]").unwrap();
+        assert!(policy.check("anywhere", 22).is_ok());
+        assert!(policy.check("anywhere", 2250).is_ok());
+        assert!(policy.check("anywhere", 2222).is_err());
+        let refused = policy.check("anywhere", 2300).unwrap_err();
+        assert!(refused.to_string().contains("port 2300 is not in the allowed ports"));
+        assert!(HostPolicy::default().check("anywhere", 1).is_ok());
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_rules() {
+        for bad in [
+            r#"ports = ["22-"]"#,
+            r#"ports = ["30-20"]"#,
+            r#"deny_ports = ["ssh"]"#,
+            r#"allow = ["10.0.0.0/33"]"#,
+            r#"deny = ["host/8"]"#,
+            r#"alow = ["typo"]"#,
+        ] {
+            assert!(HostPolicy::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_load_from() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("policy.toml");
+        assert_eq!(HostPolicy::load_from(&path).unwrap(), None);
+
+        std::fs::write(&path, "deny = [\"*\"]\n").unwrap();
+        let policy = HostPolicy::load_from(&path).unwrap().unwrap();
+        assert!(policy.check("localhost", 22).is_err());
+
+        std::fs::write(&path, "allow = 1\n").unwrap();
+        assert!(HostPolicy::load_from(&path).is_err());
+    }
+}
@@ -0,0 +1,952 @@
+//! Catalog of the CLI's user-facing messages in English, Spanish and Japanese,
+//! and the color themes `ui` applies to them. The language comes from
+//! `BXSSH_LANG` and the theme from `BXSSH_THEME` (see `SshConfig`).
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Message language
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    Es,
+    Ja,
+}
+
+impl Lang {
+    /// Accepts a language code or locale name: `es`, `es_MX.UTF-8`, `ja-JP`, `C`
+    pub fn parse(value: &str) -> Option<Self> {
+        let code = value.split(['_', '-', '.', '@']).next().unwrap_or_default().to_ascii_lowercase();
+        match code.as_str() {
+            "en" | "c" | "posix" => Some(Lang::En),
+            "es" => Some(Lang::Es),
+            "ja" => Some(Lang::Ja),
+            _ => None,
+        }
+    }
+}
+
+/// Colors for message kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    /// Green success, yellow warnings, red errors, cyan hints
+    #[default]
+    Default,
+    /// Bold bright colors, for dark or low-contrast terminals
+    HighContrast,
+    /// No color at all
+    None,
+}
+
+impl Theme {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "default" => Some(Theme::Default),
+            "high-contrast" => Some(Theme::HighContrast),
+            "none" | "off" => Some(Theme::None),
+            _ => None,
+        }
+    }
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+/// Use `lang` for every message from now on
+pub fn set_lang(lang: Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+pub fn lang() -> Lang {
+    match LANG.load(Ordering::Relaxed) {
+        1 => Lang::Es,
+        2 => Lang::Ja,
+        _ => Lang::En,
+    }
+}
+
+/// Every message the CLI prints through `ui`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msg {
+    WarningPrefix,
+    ErrorPrefix,
+    InvalidUsage,
+    YouUsed,
+    CorrectUsage,
+    TargetRequired,
+    TargetUsage,
+    SeeHelp,
+    UsernameOverride,
+    PortOverride,
+    SessionBackground,
+    ReattachSession,
+    #[cfg_attr(not(all(feature = "fuse", unix)), allow(dead_code))] // only `bxssh mount`
+    Mounting,
+    #[cfg_attr(not(all(feature = "fuse", unix)), allow(dead_code))]
+    UnmountHint,
+    GeneratedKey,
+    PublicKey,
+    CopyPublicKeyHint,
+    KeyStored,
+    GenerateKeyFailed,
+    NoKeys,
+    GenerateKeyHint,
+    AvailableKeys,
+    KeyCreated,
+    KeyLastUsed,
+    KeyUses,
+    KeyExpires,
+    Never,
+    Unknown,
+    UseKeyHint,
+    RotatedKey,
+    NewPublicKey,
+    RotateHint,
+    NoSessions,
+    StartSessionHint,
+    DetachedSessions,
+    SessionEntry,
+    ReattachHint,
+    Attaching,
+    Relaying,
+    RelayWithoutToken,
+    Exported,
+    ImportHint,
+    Imported,
+    Skipped,
+    PasswordFallback,
+    Connected,
+    Disconnected,
+    Diagnosing,
+    NoProblems,
+    ProblemsFound,
+    RemoteSystem,
+    WakingHost,
+    #[cfg_attr(not(feature = "discovery"), allow(dead_code))] // only `bxssh discover`
+    NoHostsFound,
+    #[cfg_attr(not(feature = "discovery"), allow(dead_code))]
+    HostsFound,
+    #[cfg_attr(not(feature = "discovery"), allow(dead_code))]
+    DiscoverConnectHint,
+    RecentConnections,
+    NoRecent,
+    RecentPrompt,
+    RecentCleared,
+    ProfileLabel,
+    RecentLabel,
+    PickerNoMatch,
+    PickerHelp,
+    PasswordLoginWarning,
+    ChecksumVerified,
+    Watching,
+    PortForwarded,
+    Reattaching,
+    EditSaved,
+    EditUnchanged,
+    EditConflict,
+    SessionStats,
+    LatencySpike,
+    SessionCacheUnavailable,
+    PasswordChangeRequired,
+    PasswordsDiffer,
+    PasswordChanged,
+    PasswordRejected,
+    AuthLockoutWarning,
+    AuthBackoff,
+    KeyAuthorized,
+    KeyAlreadyAuthorized,
+    KeyRevoked,
+    KeyNotAuthorized,
+    IdentityAccepted,
+    KeyDefaultSet,
+    KeyDefaultSetFor,
+    KeyDefaultFor,
+    KeyLabelled,
+    SettingsValid,
+    SettingsMissing,
+    KeyAuthFailed,
+    Recording,
+    DumpingIo,
+    NoPty,
+    ShellRefused,
+    RemoteForwarded,
+    RemotePortAssigned,
+    LocalForwarded,
+    UdpForwarded,
+    TabsHelp,
+    ControlFifo,
+    JobFinished,
+    JobFailed,
+    JobStatus,
+    JobError,
+    WaitingForHost,
+    GoingDown,
+    HostBack,
+    ProxyIgnored,
+    DiagDns,
+    DiagTcp,
+    DiagBanner,
+    DiagAlgorithms,
+    DiagAuth,
+    DiagKeyFile,
+    DiagLogin,
+    DiagClock,
+    DiagPreConnect,
+    DiagResolves,
+    DiagNoAddresses,
+    DiagCannotResolve,
+    DiagCheckHostName,
+    DiagConnected,
+    DiagCannotConnect,
+    DiagConnectionRefused,
+    DiagNoAnswer,
+    DiagCheckRoute,
+    DiagOtherService,
+    DiagServerVersion,
+    DiagProtocol1,
+    DiagNoCommonAlgorithm,
+    DiagEnableAlgorithm,
+    DiagWouldUse,
+    DiagNoStrictKex,
+    DiagUpdateServer,
+    DiagServerOffers,
+    DiagOnlyPassword,
+    DiagUsePassword,
+    DiagNoUsableMethod,
+    DiagNoMethods,
+    DiagEnableAuth,
+    DiagCannotReadKey,
+    DiagCheckKeyPath,
+    DiagKeyShared,
+    DiagChmod,
+    DiagKeyPrivate,
+    DiagNoTimestamp,
+    DiagClocksAgree,
+    DiagClockAhead,
+    DiagClockBehind,
+    DiagSyncClocks,
+    DiagFixPreConnect,
+    DiagNoKey,
+    DiagDebugLog,
+    DiagNeedsKey,
+    DiagNeedsLogin,
+    DiagAuthorizeKey,
+    DiagLoggedIn,
+    DiagCannotRunDate,
+}
+
+impl Msg {
+    #[cfg(test)]
+    const ALL: &'static [Msg] = &[
+        Msg::WarningPrefix, Msg::ErrorPrefix, Msg::InvalidUsage, Msg::YouUsed, Msg::CorrectUsage,
+        Msg::TargetRequired, Msg::TargetUsage, Msg::SeeHelp, Msg::UsernameOverride, Msg::SessionBackground,
+        Msg::ReattachSession, Msg::Mounting, Msg::UnmountHint, Msg::GeneratedKey, Msg::PublicKey,
+        Msg::CopyPublicKeyHint, Msg::KeyStored, Msg::GenerateKeyFailed, Msg::NoKeys, Msg::GenerateKeyHint,
+        Msg::AvailableKeys, Msg::KeyCreated, Msg::KeyLastUsed, Msg::KeyUses, Msg::KeyExpires, Msg::Never,
+        Msg::Unknown, Msg::UseKeyHint, Msg::RotatedKey, Msg::NewPublicKey, Msg::RotateHint, Msg::NoSessions,
+        Msg::StartSessionHint, Msg::DetachedSessions, Msg::SessionEntry, Msg::ReattachHint, Msg::Attaching,
+        Msg::Relaying, Msg::RelayWithoutToken, Msg::Exported, Msg::ImportHint, Msg::Imported, Msg::Skipped,
+        Msg::PasswordFallback, Msg::Connected, Msg::Disconnected, Msg::Diagnosing, Msg::NoProblems,
+        Msg::ProblemsFound, Msg::RemoteSystem, Msg::WakingHost,
+        Msg::NoHostsFound, Msg::HostsFound, Msg::DiscoverConnectHint, Msg::RecentConnections, Msg::NoRecent,
+        Msg::RecentPrompt, Msg::RecentCleared, Msg::ProfileLabel, Msg::RecentLabel, Msg::PickerNoMatch,
+        Msg::PickerHelp, Msg::PasswordLoginWarning, Msg::ChecksumVerified, Msg::Watching, Msg::PortForwarded,
+        Msg::Reattaching, Msg::EditSaved, Msg::EditUnchanged, Msg::EditConflict, Msg::SessionStats,
+        Msg::LatencySpike,
+        Msg::SessionCacheUnavailable, Msg::PasswordChangeRequired, Msg::PasswordsDiffer, Msg::PasswordChanged,
+        Msg::PasswordRejected, Msg::AuthLockoutWarning, Msg::AuthBackoff,
+        Msg::KeyAuthorized, Msg::KeyAlreadyAuthorized, Msg::KeyRevoked, Msg::KeyNotAuthorized,
+        Msg::IdentityAccepted, Msg::KeyDefaultSet, Msg::KeyDefaultSetFor, Msg::KeyDefaultFor,
+        Msg::KeyLabelled,
+        Msg::SettingsValid,
+        Msg::SettingsMissing,
+        Msg::KeyAuthFailed,
+        Msg::Recording,
+        Msg::DumpingIo,
+        Msg::PortOverride,
+        Msg::NoPty,
+        Msg::ShellRefused,
+        Msg::RemoteForwarded,
+        Msg::RemotePortAssigned,
+        Msg::LocalForwarded,
+        Msg::UdpForwarded,
+        Msg::TabsHelp,
+        Msg::ControlFifo,
+        Msg::JobFinished,
+        Msg::JobFailed,
+        Msg::JobStatus,
+        Msg::JobError,
+        Msg::WaitingForHost,
+        Msg::GoingDown,
+        Msg::HostBack,
+        Msg::ProxyIgnored,
+        Msg::DiagDns, Msg::DiagTcp, Msg::DiagBanner, Msg::DiagAlgorithms, Msg::DiagAuth, Msg::DiagKeyFile,
+        Msg::DiagLogin, Msg::DiagClock, Msg::DiagPreConnect, Msg::DiagResolves, Msg::DiagNoAddresses,
+        Msg::DiagCannotResolve, Msg::DiagCheckHostName, Msg::DiagConnected, Msg::DiagCannotConnect,
+        Msg::DiagConnectionRefused, Msg::DiagNoAnswer, Msg::DiagCheckRoute, Msg::DiagOtherService,
+        Msg::DiagServerVersion, Msg::DiagProtocol1, Msg::DiagNoCommonAlgorithm, Msg::DiagEnableAlgorithm,
+        Msg::DiagWouldUse, Msg::DiagNoStrictKex, Msg::DiagUpdateServer, Msg::DiagServerOffers,
+        Msg::DiagOnlyPassword, Msg::DiagUsePassword, Msg::DiagNoUsableMethod, Msg::DiagNoMethods,
+        Msg::DiagEnableAuth, Msg::DiagCannotReadKey, Msg::DiagCheckKeyPath, Msg::DiagKeyShared, Msg::DiagChmod,
+        Msg::DiagKeyPrivate, Msg::DiagNoTimestamp, Msg::DiagClocksAgree, Msg::DiagClockAhead, Msg::DiagClockBehind,
+        Msg::DiagSyncClocks, Msg::DiagFixPreConnect, Msg::DiagNoKey, Msg::DiagDebugLog, Msg::DiagNeedsKey,
+        Msg::DiagNeedsLogin, Msg::DiagAuthorizeKey, Msg::DiagLoggedIn, Msg::DiagCannotRunDate,
+    ];
+
+    /// Templates in English, Spanish and Japanese; each `{}` takes the next argument
+    fn templates(self) -> [&'static str; 3] {
+        match self {
+            Msg::WarningPrefix => ["warning: ", "aviso: ", "警告: "],
+            Msg::ErrorPrefix => ["error: ", "error: ", "エラー: "],
+            Msg::InvalidUsage => ["Error: Invalid usage detected", "Error: uso no válido", "エラー: 使い方が正しくありません"],
+            Msg::YouUsed => ["You used: bxssh -u '{}'", "Usó: bxssh -u '{}'", "入力: bxssh -u '{}'"],
+            Msg::CorrectUsage => ["Correct usage:", "Uso correcto:", "正しい使い方:"],
+            Msg::TargetRequired => [
+                "Error: Target host is required for SSH connections",
+                "Error: se necesita un host de destino para conectar por SSH",
+                "エラー: SSH 接続には接続先ホストが必要です",
+            ],
+            Msg::TargetUsage => [
+                "Usage: bxssh user@host  OR  bxssh -u user host",
+                "Uso: bxssh usuario@host  O  bxssh -u usuario host",
+                "使い方: bxssh user@host  または  bxssh -u user host",
+            ],
+            Msg::SeeHelp => [
+                "Use --help for more information",
+                "Use --help para más información",
+                "詳しくは --help を参照してください",
+            ],
+            Msg::UsernameOverride => [
+                "Username from target '{}' overrides -u flag '{}'",
+                "El usuario del destino '{}' sustituye al de -u '{}'",
+                "接続先のユーザー名 '{}' が -u の '{}' より優先されます",
+            ],
+            Msg::PortOverride => [
+                "Port from target '{}' overrides -p flag '{}'",
+                "El puerto del destino '{}' sustituye al de -p '{}'",
+                "接続先のポート '{}' が -p の '{}' より優先されます",
+            ],
+            Msg::SessionBackground => [
+                "Session '{}' is running in the background",
+                "La sesión '{}' se ejecuta en segundo plano",
+                "セッション '{}' をバックグラウンドで実行中です",
+            ],
+            Msg::ReattachSession => [
+                "Reattach with: bxssh attach {}",
+                "Vuelva a conectarse con: bxssh attach {}",
+                "再接続するには: bxssh attach {}",
+            ],
+            Msg::Mounting => [
+                "Mounting {} at {} (read-only)",
+                "Montando {} en {} (solo lectura)",
+                "{} を {} に読み取り専用でマウントします",
+            ],
+            Msg::UnmountHint => [
+                "Unmount with: fusermount -u {}",
+                "Desmonte con: fusermount -u {}",
+                "アンマウントするには: fusermount -u {}",
+            ],
+            Msg::GeneratedKey => [
+                "Generated SSH key pair: {}",
+                "Par de claves SSH generado: {}",
+                "SSH 鍵ペアを生成しました: {}",
+            ],
+            Msg::PublicKey => ["Public key:", "Clave pública:", "公開鍵:"],
+            Msg::CopyPublicKeyHint => [
+                "Copy the public key above to your server's ~/.ssh/authorized_keys file",
+                "Copie la clave pública anterior en el archivo ~/.ssh/authorized_keys del servidor",
+                "上の公開鍵をサーバーの ~/.ssh/authorized_keys にコピーしてください",
+            ],
+            Msg::KeyStored => [
+                "Key stored securely in {}",
+                "Clave guardada de forma segura en {}",
+                "鍵は {} に安全に保存されました",
+            ],
+            Msg::GenerateKeyFailed => [
+                "Failed to generate key: {}",
+                "No se pudo generar la clave: {}",
+                "鍵の生成に失敗しました: {}",
+            ],
+            Msg::NoKeys => ["No SSH keys found", "No hay claves SSH", "SSH 鍵がありません"],
+            Msg::GenerateKeyHint => [
+                "Generate a new key with: bxssh --generate-key <name>",
+                "Genere una clave nueva con: bxssh --generate-key <nombre>",
+                "新しい鍵を生成するには: bxssh --generate-key <名前>",
+            ],
+            Msg::AvailableKeys => ["Available SSH keys:", "Claves SSH disponibles:", "利用できる SSH 鍵:"],
+            Msg::KeyCreated => ["created:   {}", "creada:    {}", "作成:       {}"],
+            Msg::KeyLastUsed => ["last used: {}", "último uso: {}", "最終使用:   {}"],
+            Msg::KeyUses => ["uses:      {}", "usos:      {}", "使用回数:   {}"],
+            Msg::KeyExpires => ["expires:   {}", "caduca:    {}", "有効期限:   {}"],
+            Msg::Never => ["never", "nunca", "なし"],
+            Msg::Unknown => ["unknown", "desconocido", "不明"],
+            Msg::UseKeyHint => [
+                "Use a key with: bxssh -i <key-name> user@hostname",
+                "Use una clave con: bxssh -i <nombre-clave> usuario@host",
+                "鍵を使うには: bxssh -i <鍵の名前> user@hostname",
+            ],
+            Msg::RotatedKey => ["Rotated SSH key: {}", "Clave SSH renovada: {}", "SSH 鍵を更新しました: {}"],
+            Msg::NewPublicKey => ["New public key:", "Nueva clave pública:", "新しい公開鍵:"],
+            Msg::RotateHint => [
+                "Add the new public key to ~/.ssh/authorized_keys on your servers, then remove the old one. \
+                 The previous key is kept as '{}'.",
+                "Añada la nueva clave pública a ~/.ssh/authorized_keys en sus servidores y luego quite la antigua. \
+                 La clave anterior se conserva como '{}'.",
+                "新しい公開鍵をサーバーの ~/.ssh/authorized_keys に追加してから、古い鍵を削除してください。\
+                 以前の鍵は '{}' として残ります。",
+            ],
+            Msg::NoSessions => ["No detached sessions", "No hay sesiones en segundo plano", "バックグラウンドのセッションはありません"],
+            Msg::StartSessionHint => [
+                "Start one with: bxssh --detach user@hostname",
+                "Inicie una con: bxssh --detach usuario@host",
+                "開始するには: bxssh --detach user@hostname",
+            ],
+            Msg::DetachedSessions => ["Detached sessions:", "Sesiones en segundo plano:", "バックグラウンドのセッション:"],
+            Msg::SessionEntry => ["{} ({}, started {})", "{} ({}, iniciada {})", "{} ({}、開始 {})"],
+            Msg::ReattachHint => [
+                "Reattach with: bxssh attach <name>",
+                "Vuelva a conectarse con: bxssh attach <nombre>",
+                "再接続するには: bxssh attach <名前>",
+            ],
+            Msg::Attaching => [
+                "Attaching to '{}' (Ctrl+C detaches)",
+                "Conectando a '{}' (Ctrl+C para desconectar)",
+                "'{}' に接続します (Ctrl+C で切り離し)",
+            ],
+            Msg::Relaying => [
+                "Relaying SSH connections on {}",
+                "Retransmitiendo conexiones SSH en {}",
+                "{} で SSH 接続を中継しています",
+            ],
+            Msg::RelayWithoutToken => [
+                "No {} set: any client that can reach the relay may use it",
+                "{} no está definido: cualquier cliente que alcance el relé puede usarlo",
+                "{} が未設定です: 中継に到達できるすべてのクライアントが利用できます",
+            ],
+            Msg::Exported => ["Exported {} to {}", "Exportado {} a {}", "{} を {} にエクスポートしました"],
+            Msg::ImportHint => [
+                "Import on another machine with: bxssh sync import {}",
+                "Impórtelo en otra máquina con: bxssh sync import {}",
+                "別のマシンでインポートするには: bxssh sync import {}",
+            ],
+            Msg::Imported => ["Imported {}", "Importado {}", "{} をインポートしました"],
+            Msg::Skipped => [
+                "Skipped {} (already exists; use --force to overwrite)",
+                "Omitido {} (ya existe; use --force para sobrescribir)",
+                "{} をスキップしました (既に存在します。上書きするには --force)",
+            ],
+            Msg::PasswordFallback => [
+                "Try a password instead? (y/N): ",
+                "¿Probar con una contraseña? (y/N): ",
+                "代わりにパスワードを試しますか? (y/N): ",
+            ],
+            Msg::Connected => [
+                "Connected to remote server. Use Ctrl+C to exit.",
+                "Conectado al servidor remoto. Pulse Ctrl+C para salir.",
+                "リモートサーバーに接続しました。Ctrl+C で終了します。",
+            ],
+            Msg::Disconnected => [
+                "Disconnected from remote server.",
+                "Desconectado del servidor remoto.",
+                "リモートサーバーから切断しました。",
+            ],
+            Msg::Diagnosing => ["Diagnosing {}", "Diagnosticando {}", "{} を診断しています"],
+            Msg::NoProblems => ["No problems found", "No se encontraron problemas", "問題は見つかりませんでした"],
+            Msg::ProblemsFound => [
+                "{} problem(s) and {} warning(s) found",
+                "Se encontraron {} problema(s) y {} aviso(s)",
+                "{} 件の問題と {} 件の警告が見つかりました",
+            ],
+            Msg::RemoteSystem => ["Remote system: {}", "Sistema remoto: {}", "リモートシステム: {}"],
+            Msg::WakingHost => [
+                "Sent Wake-on-LAN packet to {}; waiting for {} to come up",
+                "Paquete Wake-on-LAN enviado a {}; esperando a que {} arranque",
+                "{} に Wake-on-LAN パケットを送信しました。{} の起動を待っています",
+            ],
+            Msg::NoHostsFound => [
+                "No SSH servers announced themselves on this network",
+                "Ningún servidor SSH se anunció en esta red",
+                "このネットワークで SSH サーバーは見つかりませんでした",
+            ],
+            Msg::HostsFound => ["SSH servers on this network:", "Servidores SSH en esta red:", "このネットワークの SSH サーバー:"],
+            Msg::DiscoverConnectHint => [
+                "Connect with: bxssh discover --connect N",
+                "Conéctese con: bxssh discover --connect N",
+                "接続するには: bxssh discover --connect N",
+            ],
+            Msg::RecentConnections => [
+                "Recent connections and profiles:",
+                "Conexiones recientes y perfiles:",
+                "最近の接続とプロファイル:",
+            ],
+            Msg::NoRecent => [
+                "No recent connections or profiles yet",
+                "Aún no hay conexiones recientes ni perfiles",
+                "最近の接続やプロファイルはまだありません",
+            ],
+            Msg::RecentPrompt => [
+                "Connect to [1-{}] (Enter to cancel): ",
+                "Conectar a [1-{}] (Intro para cancelar): ",
+                "接続先 [1-{}] (Enter でキャンセル): ",
+            ],
+            Msg::RecentCleared => [
+                "Cleared the recent connections list",
+                "Lista de conexiones recientes borrada",
+                "最近の接続の一覧を消去しました",
+            ],
+            Msg::ProfileLabel => ["(profile)", "(perfil)", "(プロファイル)"],
+            Msg::RecentLabel => ["(recent)", "(reciente)", "(最近)"],
+            Msg::PickerNoMatch => ["No matching hosts", "Ningún host coincide", "一致するホストはありません"],
+            Msg::PasswordLoginWarning => [
+                "Logged in with a password; a key is safer (bxssh --generate-key)",
+                "Sesión iniciada con contraseña; una clave es más segura (bxssh --generate-key)",
+                "パスワードでログインしました。鍵の方が安全です (bxssh --generate-key)",
+            ],
+            Msg::ChecksumVerified => [
+                "Verified {} ({} bytes, SHA-256 {})",
+                "Verificado {} ({} bytes, SHA-256 {})",
+                "{} を検証しました ({} バイト, SHA-256 {})",
+            ],
+            Msg::Watching => [
+                "Watching {} for changes ({})",
+                "Vigilando cambios en {} ({})",
+                "{} の変更を監視しています ({})",
+            ],
+            Msg::PortForwarded => [
+                "Remote port {} is forwarded to localhost:{}",
+                "El puerto remoto {} se reenvía a localhost:{}",
+                "リモートのポート {} を localhost:{} に転送しています",
+            ],
+            Msg::Reattaching => [
+                "Connection lost ({}); reconnecting to session {}",
+                "Conexión perdida ({}); reconectando a la sesión {}",
+                "接続が切れました ({})。セッション {} に再接続しています",
+            ],
+            Msg::EditSaved => ["Saved {} on the server", "{} guardado en el servidor", "{} をサーバーに保存しました"],
+            Msg::EditUnchanged => ["No changes to {}", "Sin cambios en {}", "{} に変更はありません"],
+            Msg::EditConflict => [
+                "{} changed on the server while you edited it, so your last save was not uploaded. \
+                 Your version is in {}; merge it by hand or upload it with `bxssh put`",
+                "{} cambió en el servidor mientras lo editaba, así que su último guardado no se subió. \
+                 Su versión está en {}; combínela a mano o súbala con `bxssh put`",
+                "編集中にサーバー上の {} が変更されたため、最後の保存はアップロードされませんでした。\
+                 あなたの版は {} にあります。手動でマージするか `bxssh put` でアップロードしてください",
+            ],
+            Msg::SessionStats => [
+                "Session lasted {}: {} sent, {} received, {} channels, {} reconnects, average latency {}",
+                "La sesión duró {}: {} enviados, {} recibidos, {} canales, {} reconexiones, latencia media {}",
+                "セッション時間 {}、送信 {}、受信 {}、チャネル {}、再接続 {} 回、平均遅延 {}",
+            ],
+            Msg::LatencySpike => [
+                "High latency to {}: {} ms round trip (above {} ms)",
+                "Latencia alta con {}: {} ms de ida y vuelta (más de {} ms)",
+                "{} への遅延が大きくなっています: 往復 {} ms ({} ms 超)",
+            ],
+            Msg::SessionCacheUnavailable => [
+                "Session cache unavailable, connecting directly: {}",
+                "Caché de sesión no disponible, conectando directamente: {}",
+                "セッションキャッシュを使えないため直接接続します: {}",
+            ],
+            Msg::PasswordChangeRequired => [
+                "Your password has expired. Choose a new one to log in.",
+                "Su contraseña ha caducado. Elija una nueva para iniciar sesión.",
+                "パスワードの有効期限が切れています。ログインするには新しいパスワードを設定してください。",
+            ],
+            Msg::PasswordsDiffer => [
+                "Passwords do not match, try again",
+                "Las contraseñas no coinciden, inténtelo de nuevo",
+                "パスワードが一致しません。もう一度入力してください",
+            ],
+            Msg::PasswordChanged => [
+                "Password changed",
+                "Contraseña cambiada",
+                "パスワードを変更しました",
+            ],
+            Msg::PasswordRejected => [
+                "Permission denied, please try again",
+                "Permiso denegado, inténtelo de nuevo",
+                "拒否されました。もう一度入力してください",
+            ],
+            Msg::AuthLockoutWarning => [
+                "{} failed logins to {} in the last hour; servers commonly lock out after 5 or 6",
+                "{} inicios de sesión fallidos en {} en la última hora; los servidores suelen bloquear tras 5 o 6",
+                "過去1時間に {} 回 {} へのログインに失敗しています。多くのサーバーは 5〜6 回でロックします",
+            ],
+            Msg::AuthBackoff => [
+                "Waiting {}s after recent failed logins (Ctrl+C to cancel)",
+                "Esperando {}s tras inicios de sesión fallidos recientes (Ctrl+C para cancelar)",
+                "最近のログイン失敗のため {} 秒待機しています (Ctrl+C で中止)",
+            ],
+            Msg::KeyAuthorized => [
+                "Key '{}' added to authorized_keys on {}",
+                "Clave '{}' añadida a authorized_keys en {}",
+                "鍵 '{}' を {} の authorized_keys に追加しました",
+            ],
+            Msg::KeyAlreadyAuthorized => [
+                "Key '{}' is already in authorized_keys on {}",
+                "La clave '{}' ya está en authorized_keys en {}",
+                "鍵 '{}' は {} の authorized_keys に登録済みです",
+            ],
+            Msg::KeyRevoked => [
+                "Key '{}' removed from authorized_keys on {}",
+                "Clave '{}' eliminada de authorized_keys en {}",
+                "鍵 '{}' を {} の authorized_keys から削除しました",
+            ],
+            Msg::KeyNotAuthorized => [
+                "Key '{}' is not in authorized_keys on {}",
+                "La clave '{}' no está en authorized_keys en {}",
+                "鍵 '{}' は {} の authorized_keys にありません",
+            ],
+            Msg::IdentityAccepted => [
+                "Logged in with key {}",
+                "Sesión iniciada con la clave {}",
+                "鍵 {} でログインしました",
+            ],
+            Msg::KeyDefaultSet => [
+                "'{}' is now the default key",
+                "'{}' es ahora la clave predeterminada",
+                "'{}' を既定の鍵にしました",
+            ],
+            Msg::KeyDefaultSetFor => [
+                "'{}' is now the default key for {}",
+                "'{}' es ahora la clave predeterminada para {}",
+                "'{}' を {} の既定の鍵にしました",
+            ],
+            Msg::KeyDefaultFor => ["[default for {}]", "[predeterminada para {}]", "[{} の既定]"],
+            Msg::KeyLabelled => ["Updated key '{}'", "Clave '{}' actualizada", "鍵 '{}' を更新しました"],
+            Msg::SettingsValid => ["{} is valid", "{} es válido", "{} は有効です"],
+            Msg::SettingsMissing => [
+                "No {}; built-in defaults apply",
+                "No existe {}; se usan los valores predeterminados",
+                "{} はありません。既定値を使います",
+            ],
+            Msg::KeyAuthFailed => [
+                "Key authentication failed: {}",
+                "Falló la autenticación con clave: {}",
+                "鍵認証に失敗しました: {}",
+            ],
+            Msg::Recording => [
+                "Recording this session to {}",
+                "Grabando esta sesión en {}",
+                "このセッションを {} に記録しています",
+            ],
+            Msg::DumpingIo => [
+                "Dumping channel traffic to {}",
+                "Volcando el tráfico de los canales en {}",
+                "チャネルの通信を {} にダンプしています",
+            ],
+            Msg::NoPty => [
+                "The server gave no terminal (PTY): lines are edited here and sent on Enter, \
+                 and full-screen programs won't work",
+                "El servidor no asignó una terminal (PTY): las líneas se editan aquí y se envían con Intro, \
+                 y los programas de pantalla completa no funcionarán",
+                "サーバーが端末 (PTY) を割り当てませんでした。行はここで編集して Enter で送信します。\
+                 全画面のプログラムは動作しません",
+            ],
+            Msg::ShellRefused => [
+                "The server refused a shell; running {} instead",
+                "El servidor rechazó un shell; se ejecuta {} en su lugar",
+                "サーバーがシェルを拒否したため、代わりに {} を実行します",
+            ],
+            Msg::RemoteForwarded => [
+                "The server listens on {} and forwards connections to {}",
+                "El servidor escucha en {} y reenvía las conexiones a {}",
+                "サーバーが {} で待ち受け、接続を {} に転送しています",
+            ],
+            Msg::LocalForwarded => [
+                "Listening on {}; connections are forwarded to {} through the server",
+                "Escuchando en {}; las conexiones se reenvían a {} a través del servidor",
+                "{} で待ち受け、接続をサーバー経由で {} に転送しています",
+            ],
+            Msg::UdpForwarded => [
+                "Listening on UDP {}; datagrams are forwarded to {} through the server",
+                "Escuchando en UDP {}; los datagramas se reenvían a {} a través del servidor",
+                "UDP {} で待ち受け、データグラムをサーバー経由で {} に転送しています",
+            ],
+            Msg::TabsHelp => [
+                "{} sessions open: Ctrl+] then 1-9, n or p switches tabs, w lists them, b broadcasts",
+                "{} sesiones abiertas: Ctrl+] y luego 1-9, n o p cambia de pestaña, w las enumera, b difunde",
+                "{} 個のセッションを開きました: Ctrl+] に続けて 1-9・n・p でタブを切り替え、w で一覧表示、b で一斉入力",
+            ],
+            Msg::ControlFifo => [
+                "Writes to {} are typed into the session; {} carries its output",
+                "Lo que se escriba en {} se teclea en la sesión; {} lleva su salida",
+                "{} への書き込みはセッションに入力され、{} からその出力を読めます",
+            ],
+            Msg::JobFinished => [
+                "{} on {} finished",
+                "{} en {} terminó",
+                "{} ({}) が完了しました",
+            ],
+            Msg::JobFailed => [
+                "{} on {} failed",
+                "{} en {} falló",
+                "{} ({}) が失敗しました",
+            ],
+            Msg::JobStatus => [
+                "Exit status {} after {}",
+                "Código de salida {} tras {}",
+                "終了ステータス {}、所要時間 {}",
+            ],
+            Msg::JobError => [
+                "Stopped after {}: {}",
+                "Se detuvo tras {}: {}",
+                "{} 後に停止しました: {}",
+            ],
+            Msg::WaitingForHost => [
+                "Waiting for {} to accept connections (up to {}s)",
+                "Esperando a que {} acepte conexiones (hasta {}s)",
+                "{} が接続を受け付けるのを待っています (最大 {} 秒)",
+            ],
+            Msg::GoingDown => [
+                "{} is going down ({})",
+                "{} se está apagando ({})",
+                "{} を停止しています ({})",
+            ],
+            Msg::HostBack => [
+                "{} is back after {} of downtime",
+                "{} ha vuelto tras {} sin servicio",
+                "{} が復帰しました (停止時間 {})",
+            ],
+            Msg::ProxyIgnored => [
+                "Ignoring '{}' from ~/.ssh/config: bxssh connects to the host directly",
+                "Se ignora '{}' de ~/.ssh/config: bxssh se conecta directamente al host",
+                "~/.ssh/config の '{}' は無視されます: bxssh はホストに直接接続します",
+            ],
+            Msg::RemotePortAssigned => [
+                "{}; the server assigned port {} instead",
+                "{}; el servidor asignó el puerto {} en su lugar",
+                "{}。代わりにサーバーがポート {} を割り当てました",
+            ],
+            Msg::PickerHelp => [
+                "{}/{} hosts · type to filter · ↑↓ select · Enter connect · Esc cancel",
+                "{}/{} hosts · escriba para filtrar · ↑↓ elegir · Intro conectar · Esc cancelar",
+                "{}/{} ホスト · 入力で絞り込み · ↑↓ 選択 · Enter 接続 · Esc キャンセル",
+            ],
+            Msg::DiagDns => ["dns", "dns", "DNS"],
+            Msg::DiagTcp => ["tcp", "tcp", "TCP"],
+            Msg::DiagBanner => ["banner", "identificación", "バナー"],
+            Msg::DiagAlgorithms => ["algorithms", "algoritmos", "アルゴリズム"],
+            Msg::DiagAuth => ["auth", "autenticación", "認証"],
+            Msg::DiagKeyFile => ["key file", "archivo de clave", "鍵ファイル"],
+            Msg::DiagLogin => ["login", "inicio de sesión", "ログイン"],
+            Msg::DiagClock => ["clock", "reloj", "時計"],
+            Msg::DiagPreConnect => ["pre-connect", "pre-conexión", "接続前処理"],
+            Msg::DiagResolves => ["{} resolves to {}", "{} se resuelve como {}", "{} の解決結果: {}"],
+            Msg::DiagNoAddresses => ["{} has no addresses", "{} no tiene direcciones", "{} にはアドレスがありません"],
+            Msg::DiagCannotResolve => ["Cannot resolve {}: {}", "No se puede resolver {}: {}", "{} を解決できません: {}"],
+            Msg::DiagCheckHostName => [
+                "Check the host name, or HostName in profiles.toml and ~/.ssh/config",
+                "Compruebe el nombre del host, o HostName en profiles.toml y ~/.ssh/config",
+                "ホスト名、または profiles.toml と ~/.ssh/config の HostName を確認してください",
+            ],
+            Msg::DiagConnected => ["Connected to {}", "Conectado a {}", "{} に接続しました"],
+            Msg::DiagCannotConnect => ["Cannot connect: {}", "No se puede conectar: {}", "接続できません: {}"],
+            Msg::DiagConnectionRefused => [
+                "Nothing accepts connections on port {}; check the port (-p) and that sshd is running",
+                "Nada acepta conexiones en el puerto {}; compruebe el puerto (-p) y que sshd esté en marcha",
+                "ポート {} で接続を受け付けるものがありません。ポート (-p) と sshd が動作しているか確認してください",
+            ],
+            Msg::DiagNoAnswer => [
+                "No answer within {}s; a firewall may drop the traffic, or the host may need a port knock \
+                 (knock_sequence in profiles.toml)",
+                "Sin respuesta en {}s; un cortafuegos puede descartar el tráfico, o el host puede necesitar port \
+                 knocking (knock_sequence en profiles.toml)",
+                "{} 秒以内に応答がありません。ファイアウォールが通信を破棄しているか、ポートノック (profiles.toml の knock_sequence) が必要な可能性があります",
+            ],
+            Msg::DiagCheckRoute => [
+                "Check the network route to the host and any VPN it needs",
+                "Compruebe la ruta de red hasta el host y la VPN que necesite",
+                "ホストまでのネットワーク経路と必要な VPN を確認してください",
+            ],
+            Msg::DiagOtherService => [
+                "Port {} may belong to another service, or a proxy may cut SSH traffic",
+                "El puerto {} puede ser de otro servicio, o un proxy puede cortar el tráfico SSH",
+                "ポート {} は別のサービスのものか、プロキシが SSH 通信を遮断している可能性があります",
+            ],
+            Msg::DiagServerVersion => ["Server identifies as {}", "El servidor se identifica como {}", "サーバーの識別: {}"],
+            Msg::DiagProtocol1 => [
+                "The server still offers SSH protocol 1; disable it in its configuration",
+                "El servidor aún ofrece el protocolo SSH 1; desactívelo en su configuración",
+                "サーバーがまだ SSH プロトコル 1 を提供しています。設定で無効にしてください",
+            ],
+            Msg::DiagNoCommonAlgorithm => [
+                "No common {} algorithm; the server offers {}",
+                "Ningún algoritmo de {} en común; el servidor ofrece {}",
+                "共通の {} アルゴリズムがありません。サーバーの提供: {}",
+            ],
+            Msg::DiagEnableAlgorithm => [
+                "Enable a current algorithm in the server's sshd_config, or update its OpenSSH",
+                "Active un algoritmo actual en el sshd_config del servidor, o actualice su OpenSSH",
+                "サーバーの sshd_config で現行のアルゴリズムを有効にするか、OpenSSH を更新してください",
+            ],
+            Msg::DiagWouldUse => ["Would use {}", "Se usaría {}", "使用予定: {}"],
+            Msg::DiagNoStrictKex => [
+                "Server doesn't offer strict key exchange (Terrapin, CVE-2023-48795)",
+                "El servidor no ofrece intercambio de claves estricto (Terrapin, CVE-2023-48795)",
+                "サーバーが厳格な鍵交換を提供していません (Terrapin, CVE-2023-48795)",
+            ],
+            Msg::DiagUpdateServer => [
+                "Update the server to OpenSSH 9.6 or later",
+                "Actualice el servidor a OpenSSH 9.6 o posterior",
+                "サーバーを OpenSSH 9.6 以降に更新してください",
+            ],
+            Msg::DiagServerOffers => ["Server offers {}", "El servidor ofrece {}", "サーバーの提供: {}"],
+            Msg::DiagOnlyPassword => [
+                "Server doesn't accept keys, only {}",
+                "El servidor no acepta claves, solo {}",
+                "サーバーは鍵を受け付けません ({} のみ)",
+            ],
+            Msg::DiagUsePassword => [
+                "Log in with --password, or enable PubkeyAuthentication on the server",
+                "Inicie sesión con --password, o active PubkeyAuthentication en el servidor",
+                "--password でログインするか、サーバーで PubkeyAuthentication を有効にしてください",
+            ],
+            Msg::DiagNoUsableMethod => [
+                "Server offers only {}, which bxssh can't use",
+                "El servidor solo ofrece {}, que bxssh no puede usar",
+                "サーバーの提供は {} のみで、bxssh では使えません",
+            ],
+            Msg::DiagNoMethods => [
+                "Server offers no authentication methods",
+                "El servidor no ofrece métodos de autenticación",
+                "サーバーが認証方式を提供していません",
+            ],
+            Msg::DiagEnableAuth => [
+                "Enable PubkeyAuthentication or PasswordAuthentication in the server's sshd_config",
+                "Active PubkeyAuthentication o PasswordAuthentication en el sshd_config del servidor",
+                "サーバーの sshd_config で PubkeyAuthentication か PasswordAuthentication を有効にしてください",
+            ],
+            Msg::DiagCannotReadKey => ["Cannot read {}: {}", "No se puede leer {}: {}", "{} を読み取れません: {}"],
+            Msg::DiagCheckKeyPath => [
+                "Check the -i path, or IdentityFile in profiles.toml",
+                "Compruebe la ruta de -i, o IdentityFile en profiles.toml",
+                "-i のパス、または profiles.toml の IdentityFile を確認してください",
+            ],
+            Msg::DiagKeyShared => [
+                "{} is accessible by other users (mode {})",
+                "{} es accesible para otros usuarios (modo {})",
+                "{} は他のユーザーからアクセスできます (モード {})",
+            ],
+            Msg::DiagChmod => ["Run: chmod 600 {}", "Ejecute: chmod 600 {}", "実行してください: chmod 600 {}"],
+            Msg::DiagKeyPrivate => ["{} is private", "{} es privado", "{} は非公開です"],
+            Msg::DiagNoTimestamp => [
+                "The server's `date +%s` printed no timestamp",
+                "`date +%s` en el servidor no imprimió ninguna marca de tiempo",
+                "サーバーの `date +%s` がタイムスタンプを出力しませんでした",
+            ],
+            Msg::DiagClocksAgree => [
+                "Clocks agree within {}s",
+                "Los relojes coinciden con {}s de margen",
+                "時計の差は {} 秒以内です",
+            ],
+            Msg::DiagClockAhead => [
+                "The server's clock is {}s ahead of this machine's",
+                "El reloj del servidor va {}s por delante del de esta máquina",
+                "サーバーの時計はこのマシンより {} 秒進んでいます",
+            ],
+            Msg::DiagClockBehind => [
+                "The server's clock is {}s behind this machine's",
+                "El reloj del servidor va {}s por detrás del de esta máquina",
+                "サーバーの時計はこのマシンより {} 秒遅れています",
+            ],
+            Msg::DiagSyncClocks => [
+                "Sync both clocks with NTP; certificates and one-time passwords depend on them",
+                "Sincronice ambos relojes con NTP; los certificados y las contraseñas de un solo uso dependen de ellos",
+                "両方の時計を NTP で同期してください。証明書とワンタイムパスワードは時計に依存します",
+            ],
+            Msg::DiagFixPreConnect => [
+                "Fix pre_connect_command or knock_sequence in profiles.toml",
+                "Corrija pre_connect_command o knock_sequence en profiles.toml",
+                "profiles.toml の pre_connect_command か knock_sequence を修正してください",
+            ],
+            Msg::DiagNoKey => [
+                "No key configured; create one with `bxssh key generate NAME`",
+                "No hay ninguna clave configurada; cree una con `bxssh key generate NOMBRE`",
+                "鍵が設定されていません。`bxssh key generate 名前` で作成してください",
+            ],
+            Msg::DiagDebugLog => [
+                "Retry with RUST_LOG=debug for libssh2's view",
+                "Reintente con RUST_LOG=debug para ver el detalle de libssh2",
+                "libssh2 の詳細を見るには RUST_LOG=debug で再実行してください",
+            ],
+            Msg::DiagNeedsKey => [
+                "Needs a key from -i or the key store",
+                "Necesita una clave de -i o del almacén de claves",
+                "-i か鍵ストアの鍵が必要です",
+            ],
+            Msg::DiagNeedsLogin => ["Needs a login", "Necesita iniciar sesión", "ログインが必要です"],
+            Msg::DiagAuthorizeKey => [
+                "Add the public key to ~{}/.ssh/authorized_keys on the server",
+                "Añada la clave pública a ~{}/.ssh/authorized_keys en el servidor",
+                "サーバーの ~{}/.ssh/authorized_keys に公開鍵を追加してください",
+            ],
+            Msg::DiagLoggedIn => ["Logged in as {}", "Sesión iniciada como {}", "{} としてログインしました"],
+            Msg::DiagCannotRunDate => [
+                "Cannot run `date` on the server: {}",
+                "No se puede ejecutar `date` en el servidor: {}",
+                "サーバーで `date` を実行できません: {}",
+            ],
+        }
+    }
+}
+
+fn format_in(lang: Lang, msg: Msg, args: &[&dyn Display]) -> String {
+    let template = msg.templates()[lang as usize];
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut pieces = template.split("{}");
+    out.push_str(pieces.next().unwrap_or_default());
+    for piece in pieces {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(piece);
+    }
+    out
+}
+
+/// `msg` in the current language, with `args` filling its placeholders in order
+pub fn tr(msg: Msg, args: &[&dyn Display]) -> String {
+    format_in(lang(), msg, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lang() {
+        assert_eq!(Lang::parse("es"), Some(Lang::Es));
+        assert_eq!(Lang::parse("es_MX.UTF-8"), Some(Lang::Es));
+        assert_eq!(Lang::parse("ja-JP"), Some(Lang::Ja));
+        assert_eq!(Lang::parse("C"), Some(Lang::En));
+        assert_eq!(Lang::parse("fr_FR"), None);
+        assert_eq!(Lang::parse(""), None);
+    }
+
+    #[test]
+    fn test_parse_theme() {
+        assert_eq!(Theme::parse("high-contrast"), Some(Theme::HighContrast));
+        assert_eq!(Theme::parse("OFF"), Some(Theme::None));
+        assert_eq!(Theme::parse("neon"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_in(Lang::En, Msg::Exported, &[&"keys.json", &"out.age"]), "Exported keys.json to out.age");
+        assert_eq!(format_in(Lang::Es, Msg::Imported, &[&"known_hosts"]), "Importado known_hosts");
+        assert_eq!(format_in(Lang::Ja, Msg::GeneratedKey, &[&"work"]), "SSH 鍵ペアを生成しました: work");
+        // Missing arguments leave the placeholder empty rather than panicking
+        assert_eq!(format_in(Lang::En, Msg::Imported, &[]), "Imported ");
+    }
+
+    #[test]
+    fn test_every_translation_has_the_same_placeholders() {
+        for &msg in Msg::ALL {
+            let [en, es, ja] = msg.templates();
+            let count = en.matches("{}").count();
+            assert_eq!(es.matches("{}").count(), count, "{:?} (es)", msg);
+            assert_eq!(ja.matches("{}").count(), count, "{:?} (ja)", msg);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Round-trip monitoring for interactive sessions (`--latency`): the connection is
+//! timed every few seconds on the session loop's thread, each sample is handed to
+//! a callback (for the status line) and rises above a threshold are flagged as
+//! spikes.
+use log::debug;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::ssh_client::SshClient;
+use crate::terminal::SessionHook;
+
+/// How often the round trip is measured
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Round trips above this are spikes unless `--latency-warn` says otherwise
+pub const DEFAULT_WARN_ABOVE: Duration = Duration::from_millis(500);
+
+/// Select the host-writable status line (DECSSDT) and hide it again
+const SHOW_STATUS_LINE: &str = "\x1b[2$~";
+const HIDE_STATUS_LINE: &str = "\x1b[0$~";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyOptions {
+    /// Show each sample in the terminal's status line
+    pub status_line: bool,
+    pub warn_above: Duration,
+}
+
+/// One measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub round_trip: Duration,
+    /// The first sample above the threshold since latency was last below it
+    pub spike: bool,
+}
+
+/// Measures the round trip of a connection while its session runs
+pub struct LatencyMonitor {
+    client: Rc<SshClient>,
+    warn_above: Duration,
+    interval: Duration,
+    on_sample: Box<dyn FnMut(&Sample)>,
+    next: Instant,
+    high: bool,
+}
+
+impl LatencyMonitor {
+    /// Time `client` every [`DEFAULT_INTERVAL`], calling `on_sample` with each result
+    pub fn new(client: Rc<SshClient>, options: LatencyOptions, on_sample: impl FnMut(&Sample) + 'static) -> Self {
+        Self {
+            client,
+            warn_above: options.warn_above,
+            interval: DEFAULT_INTERVAL,
+            on_sample: Box::new(on_sample),
+            next: Instant::now(),
+            high: false,
+        }
+    }
+
+    /// Measure every `interval` instead
+    #[allow(dead_code)] // Library API
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl SessionHook for LatencyMonitor {
+    fn poll(&mut self) -> bool {
+        if Instant::now() < self.next {
+            return false;
+        }
+        self.next = Instant::now() + self.interval;
+        match self.client.measure_latency() {
+            Ok(round_trip) => {
+                let high = round_trip > self.warn_above;
+                let spike = high && !self.high;
+                self.high = high;
+                (self.on_sample)(&Sample { round_trip, spike });
+                true
+            }
+            Err(e) => {
+                debug!("Latency measurement failed: {:#}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Escape sequences writing `text` into the DEC status line and returning to the
+/// main display; terminals without one ignore them
+pub fn status_line(text: &str) -> String {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    format!("{}\x1b[1$}}\r\x1b[K{}\x1b[0$}}", SHOW_STATUS_LINE, text)
+}
+
+/// Escape sequence removing the status line again
+pub fn hide_status_line() -> &'static str {
+    HIDE_STATUS_LINE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::MockSshConnection;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_monitor_flags_spikes_once() {
+        let mut round_trips = [40, 700, 900, 60, 800].into_iter().map(Duration::from_millis);
+        let mut connection = MockSshConnection::new();
+        connection.expect_is_authenticated().returning(|| true);
+        connection.expect_round_trip().times(5).returning(move || Ok(round_trips.next().unwrap()));
+
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&samples);
+        let options = LatencyOptions { status_line: false, warn_above: DEFAULT_WARN_ABOVE };
+        let client = Rc::new(SshClient::new(Box::new(connection)));
+        let mut monitor = LatencyMonitor::new(Rc::clone(&client), options, move |sample| {
+            seen.borrow_mut().push((sample.round_trip.as_millis(), sample.spike));
+        })
+        .with_interval(Duration::ZERO);
+
+        for _ in 0..5 {
+            assert!(monitor.poll());
+        }
+        assert_eq!(*samples.borrow(), [(40, false), (700, true), (900, false), (60, false), (800, true)]);
+        assert_eq!(client.latency(), Some(Duration::from_millis(800)));
+    }
+
+    #[test]
+    fn test_monitor_waits_for_interval() {
+        let mut connection = MockSshConnection::new();
+        connection.expect_is_authenticated().returning(|| true);
+        connection.expect_round_trip().times(1).returning(|| Err(anyhow::anyhow!("channel open failed")));
+
+        let options = LatencyOptions { status_line: false, warn_above: DEFAULT_WARN_ABOVE };
+        let mut monitor = LatencyMonitor::new(Rc::new(SshClient::new(Box::new(connection))), options, |_| {
+            panic!("no sample expected")
+        });
+        assert!(!monitor.poll());
+        assert!(!monitor.poll());
+    }
+
+    #[test]
+    fn test_status_line() {
+        assert_eq!(status_line("web1 42 ms\n"), "\x1b[2$~\x1b[1$}\r\x1b[Kweb1 42 ms\x1b[0$}");
+        assert_eq!(hide_status_line(), "\x1b[0$~");
+    }
+}
@@ -1,34 +1,194 @@
 // Library interface for WASM bindings
 #![allow(clippy::missing_safety_doc)]
 
-#[cfg(target_arch = "wasm32")]
-pub mod wasm_exports;
-
-// Re-export core modules for library usage
+// Core client types, available on every target and with any feature set
 pub mod ssh_client;
+pub mod i18n;
 pub mod config;
+pub mod sync;
+pub mod sanitize;
+pub mod ssh_wire;
+pub mod agent;
+pub mod relay;
+pub mod log_filter;
+pub mod rekey;
+pub mod host_key;
+pub mod cipher;
+pub mod dh;
+pub mod packet;
+pub mod cancel;
+pub mod retry;
+pub mod host_policy;
+pub mod remote_target;
 
-#[cfg(target_arch = "wasm32")]
-pub mod wasm_ssh;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod key_manager;
 
-#[cfg(target_arch = "wasm32")]
-pub mod ssh_protocol;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod private_file;
 
 #[cfg(not(target_arch = "wasm32"))]
-pub mod ssh_impl;
+pub mod profiles;
 
 #[cfg(not(target_arch = "wasm32"))]
-pub mod native;
+pub mod ssh_config;
 
 #[cfg(not(target_arch = "wasm32"))]
-pub mod key_manager;
+pub mod pre_connect;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod power;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lifecycle_hooks;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod notify;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recent;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod auth_throttle;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod settings;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod paths;
+
+pub mod transfer;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watch;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod edit;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod auto_forward;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod remote_forward;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod local_forward;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod udp_forward;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod forwards;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod latency;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tabs;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod socket_options;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod script;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod multi_exec;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pool;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod terminal;
 
 #[cfg(not(target_arch = "wasm32"))]
+pub mod recording;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dump_io;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod stdio;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod progress;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ui;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod websocket;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod diagnose;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// Backend-independent facade: client, session and configuration types
+/// without pulling in ssh2, crossterm or wasm-bindgen
+pub mod core {
+    pub use crate::cancel::{CancelToken, Cancelled};
+    pub use crate::config::SshConfig;
+    pub use crate::host_policy::{HostPolicy, PolicyViolation};
+    pub use crate::retry::RetryPolicy;
+    pub use crate::ssh_client::{
+        BannerCallback, CommandOutput, ExitSignal, RemoteFileAttr, RemoteFileKind, SessionDetails, SftpSession, ShellSession,
+        Signal, SshClient, SshConnection, WriteOutcome,
+    };
+    pub use crate::sync::{decrypt as decrypt_sync_bundle, encrypt as encrypt_sync_bundle, SyncPayload};
+}
+
+// Native backend (libssh2 + crossterm)
+#[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
+pub mod ssh_impl;
+
+#[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
+pub mod native;
+
+#[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
 pub mod cli_terminal;
 
+#[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
+pub mod asyncapi;
+
+#[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
+pub mod host_picker;
+
+#[cfg(all(feature = "native-backend", unix))]
+pub mod daemon;
+
+#[cfg(all(feature = "native-backend", unix))]
+pub mod control_fifo;
+
+#[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
+pub mod session_cache;
+
+#[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
+pub mod authorized_keys;
+
+#[cfg(all(feature = "fuse", unix))]
+pub mod mount;
+
+#[cfg(all(feature = "discovery", not(target_arch = "wasm32")))]
+pub mod discovery;
+
+// WASM backend (wasm-bindgen + browser APIs)
+#[cfg(all(feature = "wasm-backend", target_arch = "wasm32"))]
+pub mod wasm_exports;
+
+#[cfg(all(feature = "wasm-backend", target_arch = "wasm32"))]
+pub mod wasm_ssh;
+
+#[cfg(all(feature = "wasm-backend", target_arch = "wasm32"))]
+pub mod ssh_protocol;
+
+#[cfg(all(feature = "wasm-backend", target_arch = "wasm32"))]
+pub mod webauthn;
+
+#[cfg(all(feature = "wasm-backend", target_arch = "wasm32"))]
+pub mod wasm_log;
+
 // WASM-specific exports
-#[cfg(target_arch = "wasm32")]
-pub use wasm_exports::*;
\ No newline at end of file
+#[cfg(all(feature = "wasm-backend", target_arch = "wasm32"))]
+pub use wasm_exports::*;
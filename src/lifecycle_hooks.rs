@@ -0,0 +1,121 @@
+//! Local commands a profile runs when a connection has logged in (`PostAuthCommand`)
+//! and when it closes (`DisconnectCommand`), e.g. to log sessions, take a VPN down
+//! again or send a notification. `PreConnectCommand` (see `crate::pre_connect`) runs
+//! before dialing. Each command runs through the shell with the session in its
+//! environment; unlike `PreConnectCommand`, a failing one only prints a warning.
+use crate::pre_connect::run_command;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// The connection a hook command runs for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HookSession {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    /// `SHA256:` fingerprint of the server's host key, when the backend reports it
+    pub fingerprint: Option<String>,
+}
+
+impl HookSession {
+    /// `BXSSH_HOST`, `BXSSH_PORT`, `BXSSH_USER`, `BXSSH_FINGERPRINT` and, once the
+    /// session is over, `BXSSH_DURATION` in whole seconds
+    fn env(&self, duration: Option<Duration>) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("BXSSH_HOST", self.host.clone()),
+            ("BXSSH_PORT", self.port.to_string()),
+            ("BXSSH_USER", self.user.clone()),
+            ("BXSSH_FINGERPRINT", self.fingerprint.clone().unwrap_or_default()),
+        ];
+        if let Some(duration) = duration {
+            env.push(("BXSSH_DURATION", duration.as_secs().to_string()));
+        }
+        env
+    }
+}
+
+/// A profile's `PostAuthCommand` and `DisconnectCommand`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LifecycleHooks {
+    pub post_auth: Option<String>,
+    pub disconnect: Option<String>,
+}
+
+impl LifecycleHooks {
+    pub fn from_profile(profile: &crate::profiles::Profile) -> Self {
+        Self { post_auth: profile.post_auth_command.clone(), disconnect: profile.disconnect_command.clone() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.post_auth.is_none() && self.disconnect.is_none()
+    }
+
+    /// Run `PostAuthCommand` for `session`, which has just logged in
+    pub fn post_auth(&self, session: &HookSession) {
+        if let Some(command) = &self.post_auth {
+            warn_on_failure(run_command("PostAuthCommand", command, &session.env(None)));
+        }
+    }
+
+    /// A callback running `DisconnectCommand` with the time since now, for
+    /// [`crate::ssh_client::SshClient::set_disconnect_callback`]
+    pub fn on_disconnect(&self, session: HookSession) -> Option<crate::ssh_client::DisconnectCallback> {
+        let command = self.disconnect.clone()?;
+        let started = Instant::now();
+        Some(Box::new(move || {
+            let env = session.env(Some(started.elapsed()));
+            warn_on_failure(run_command("DisconnectCommand", &command, &env));
+        }))
+    }
+}
+
+fn warn_on_failure(result: Result<()>) {
+    if let Err(e) = result {
+        crate::ui::warn(&format!("{:#}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> HookSession {
+        HookSession {
+            host: "prod.example.com".to_string(),
+            port: 2222,
+            user: "deploy".to_string(),
+            fingerprint: Some("SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_env_describes_the_session() {
+        let env = session().env(Some(Duration::from_millis(61_500)));
+        let value = |name| env.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str());
+        assert_eq!(value("BXSSH_HOST"), Some("prod.example.com"));
+        assert_eq!(value("BXSSH_PORT"), Some("2222"));
+        assert_eq!(value("BXSSH_USER"), Some("deploy"));
+        assert_eq!(value("BXSSH_FINGERPRINT"), Some("SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"));
+        assert_eq!(value("BXSSH_DURATION"), Some("61"));
+        assert_eq!(session().env(None).len(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks_run_with_the_session() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log = dir.path().join("hooks.log");
+        let hooks = LifecycleHooks {
+            post_auth: Some(format!("echo \"up $BXSSH_USER@$BXSSH_HOST\" >> {}", log.display())),
+            disconnect: Some(format!("echo \"down $BXSSH_DURATION\" >> {}", log.display())),
+        };
+        hooks.post_auth(&session());
+        let on_disconnect = hooks.on_disconnect(session()).unwrap();
+        on_disconnect();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "up deploy@prod.example.com\ndown 0\n");
+
+        assert!(LifecycleHooks::default().on_disconnect(session()).is_none());
+        assert!(run_command("DisconnectCommand", "exit 2", &[]).unwrap_err().to_string().contains("failed"));
+    }
+}
@@ -0,0 +1,251 @@
+//! Local forwards (`-L`): a listener on this machine whose connections are tunnelled
+//! over `direct-tcpip` channels to a host and port as seen from the server. They
+//! listen on localhost unless given a bind address, which must be loopback without
+//! `--gateway-ports`, and an allow list after `@` limits who may connect.
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::net::{IpAddr, TcpListener};
+use std::rc::Rc;
+
+use crate::auto_forward::Connection;
+use crate::forwards::ForwardFields;
+use crate::host_policy::{in_network, parse_cidr};
+use crate::remote_target::{bracketed, host_port};
+use crate::ssh_client::SshClient;
+use crate::terminal::SessionHook;
+
+/// Address a forward listens on when `-L` names none
+pub const DEFAULT_BIND_ADDRESS: &str = "localhost";
+
+/// `-L [bind_address:]port:host:hostport[@allowed,...]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalForwardSpec {
+    pub bind_address: String,
+    /// 0 for any free port
+    pub local_port: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+    /// Addresses and CIDR blocks that may connect; empty lets anyone who reaches the listener
+    pub allow: Vec<String>,
+}
+
+impl LocalForwardSpec {
+    /// Parse `[bind_address:]port:host:hostport`, optionally followed by `@` and a
+    /// comma-separated allow list such as `@10.0.0.0/8,192.168.1.7`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (forward, allow) = match spec.split_once('@') {
+            Some((forward, allow)) => (forward, allow.split(',').map(str::to_string).collect()),
+            None => (spec, Vec::new()),
+        };
+        let fields = ForwardFields::parse(forward).context("Invalid local forward")?;
+        for pattern in &allow {
+            parse_allowed(pattern).with_context(|| format!("Invalid allow list in local forward '{}'", spec))?;
+        }
+        Ok(Self {
+            bind_address: fields.bind_address.unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string()),
+            local_port: fields.port,
+            remote_host: fields.host,
+            remote_port: fields.host_port,
+            allow,
+        })
+    }
+
+    /// Where connections go from the server, as `host:port`
+    pub fn destination(&self) -> String {
+        host_port(&self.remote_host, self.remote_port)
+    }
+
+    /// Whether a connection from `address` may use this forward
+    pub fn allows(&self, address: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d
+        let address = address.to_canonical();
+        self.allow.is_empty()
+            || self.allow.iter().any(|pattern| {
+                parse_allowed(pattern).is_ok_and(|(network, prefix)| in_network(address, network, prefix))
+            })
+    }
+}
+
+impl std::fmt::Display for LocalForwardSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", bracketed(&self.bind_address), self.local_port, self.destination())?;
+        if !self.allow.is_empty() {
+            write!(f, "@{}", self.allow.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// An allow list entry as a network: a CIDR block, or one address
+fn parse_allowed(pattern: &str) -> Result<(IpAddr, u32)> {
+    if pattern.contains('/') {
+        return parse_cidr(pattern);
+    }
+    let address: IpAddr = pattern
+        .parse()
+        .map_err(|_| anyhow::anyhow!("'{}' is not an IP address or CIDR block", pattern))?;
+    Ok((address, if address.is_ipv4() { 32 } else { 128 }))
+}
+
+/// A forward's listener and the port it got
+struct Listening {
+    spec: LocalForwardSpec,
+    listener: TcpListener,
+    port: u16,
+}
+
+/// Serves `-L` forwards during an interactive session
+pub struct LocalForward {
+    client: Rc<SshClient>,
+    listening: Vec<Listening>,
+    connections: Vec<Connection>,
+}
+
+impl LocalForward {
+    /// Listen for every one of `specs`, tunnelling through `client`
+    pub fn bind(client: Rc<SshClient>, specs: &[LocalForwardSpec]) -> Result<Self> {
+        let mut listening = Vec::new();
+        for spec in specs {
+            crate::host_policy::enforce(&spec.remote_host, spec.remote_port)
+                .with_context(|| format!("Cannot forward {}", spec))?;
+            let address = host_port(&spec.bind_address, spec.local_port);
+            let listener = TcpListener::bind((spec.bind_address.as_str(), spec.local_port))
+                .with_context(|| format!("Cannot listen on {} for local forward {}", address, spec))?;
+            listener.set_nonblocking(true)?;
+            let port = listener.local_addr()?.port();
+            info!("Forwarding {} to {}", host_port(&spec.bind_address, port), spec.destination());
+            listening.push(Listening { spec: spec.clone(), listener, port });
+        }
+        Ok(Self { client, listening, connections: Vec::new() })
+    }
+
+    /// Each forward with the port it listens on
+    pub fn listening(&self) -> impl Iterator<Item = (&LocalForwardSpec, u16)> {
+        self.listening.iter().map(|listening| (&listening.spec, listening.port))
+    }
+
+    /// Carry on over `client` after the connection was re-dialled; the listeners stay bound
+    pub fn reconnect(&mut self, client: Rc<SshClient>) {
+        self.client = client;
+        self.connections.clear();
+    }
+
+    fn accept(&mut self) -> bool {
+        let mut accepted = false;
+        for Listening { spec, listener, port } in &self.listening {
+            while let Ok((stream, peer)) = listener.accept() {
+                accepted = true;
+                if !spec.allows(peer.ip()) {
+                    warn!("Refused connection from {} to port {}: not on its allow list", peer, port);
+                    continue;
+                }
+                debug!("Connection from {} for {}", peer, spec.destination());
+                if let Err(e) = stream.set_nonblocking(true) {
+                    debug!("Dropping connection from {}: {}", peer, e);
+                    continue;
+                }
+                match self.client.open_direct_tcpip(&spec.remote_host, spec.remote_port) {
+                    Ok(channel) => self.connections.push(Connection::new(stream, channel)),
+                    Err(e) => warn!("Cannot reach {} on the server: {:#}", spec.destination(), e),
+                }
+            }
+        }
+        accepted
+    }
+}
+
+impl SessionHook for LocalForward {
+    fn poll(&mut self) -> bool {
+        let mut activity = self.accept();
+        self.connections.retain_mut(|connection| match connection.pump() {
+            Ok(moved) => {
+                activity |= moved;
+                !connection.is_done()
+            }
+            Err(e) => {
+                debug!("Local forwarded connection failed: {:#}", e);
+                false
+            }
+        });
+        activity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::{MockShellSession, MockSshConnection};
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpStream};
+
+    #[test]
+    fn test_parse_local_forward() {
+        let forward = LocalForwardSpec::parse("5432:db.internal:5432").unwrap();
+        assert_eq!((forward.bind_address.as_str(), forward.local_port), ("localhost", 5432));
+        assert_eq!(forward.destination(), "db.internal:5432");
+        assert!(forward.allow.is_empty());
+
+        let forward = LocalForwardSpec::parse("0.0.0.0:8080:web:80@10.0.0.0/8,192.168.1.7").unwrap();
+        assert_eq!(forward.bind_address, "0.0.0.0");
+        assert_eq!(forward.allow, ["10.0.0.0/8", "192.168.1.7"]);
+        assert_eq!(forward.to_string(), "0.0.0.0:8080:web:80@10.0.0.0/8,192.168.1.7");
+
+        assert!(LocalForwardSpec::parse("8080:web:80@").is_err());
+        assert!(LocalForwardSpec::parse("8080:web:80@10.0.0.0/33").is_err());
+        assert!(LocalForwardSpec::parse("8080:web:80@example.com").is_err());
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let anyone = LocalForwardSpec::parse("8080:web:80").unwrap();
+        assert!(anyone.allows(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9))));
+
+        let forward = LocalForwardSpec::parse("*:8080:web:80@10.0.0.0/8,192.168.1.7,fd00::/8").unwrap();
+        for allowed in ["10.1.2.3", "192.168.1.7", "::ffff:10.9.9.9", "fd00::1"] {
+            assert!(forward.allows(allowed.parse().unwrap()), "{}", allowed);
+        }
+        for refused in ["192.168.1.8", "127.0.0.1", "fe80::1"] {
+            assert!(!forward.allows(refused.parse().unwrap()), "{}", refused);
+        }
+    }
+
+    #[test]
+    fn test_tunnels_allowed_connections() {
+        let mut channel = MockShellSession::new();
+        channel.expect_try_write().returning(|data| Ok(crate::ssh_client::WriteOutcome::Written(data.len())));
+        let mut replied = false;
+        channel.expect_read().returning(move |buf| {
+            if std::mem::replace(&mut replied, true) {
+                return Ok(0);
+            }
+            buf[..4].copy_from_slice(b"pong");
+            Ok(4)
+        });
+        channel.expect_is_eof().return_const(false);
+        let mut channel = Some(channel);
+
+        let mut connection = MockSshConnection::new();
+        connection.expect_is_authenticated().return_const(true);
+        connection
+            .expect_open_direct_tcpip()
+            .withf(|host, port| host == "web" && *port == 80)
+            .times(1)
+            .returning(move |_, _| Ok(Box::new(channel.take().unwrap())));
+        let client = Rc::new(SshClient::new(Box::new(connection)));
+
+        let spec = LocalForwardSpec::parse("127.0.0.1:0:web:80@127.0.0.1").unwrap();
+        let mut forward = LocalForward::bind(client, &[spec]).unwrap();
+        let (_, port) = forward.listening().next().unwrap();
+        let mut local = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        local.write_all(b"ping").unwrap();
+        local.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+
+        for _ in 0..50 {
+            forward.poll();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let mut reply = [0u8; 4];
+        local.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"pong");
+    }
+}
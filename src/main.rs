@@ -1,12 +1,73 @@
 #[cfg(not(target_arch = "wasm32"))]
-use clap::{Arg, Command};
+use clap::{parser::ValueSource, Arg, ArgGroup, Command};
 use anyhow::{Context, Result};
 use log::info;
+#[cfg(not(target_arch = "wasm32"))]
+use i18n::{tr, Msg};
 
 mod ssh_client;
+#[allow(dead_code)] // deadlines are for library embedders
+mod cancel;
+mod retry;
+mod host_policy;
+mod remote_target;
+mod i18n;
 mod config;
 mod key_manager;
+mod private_file;
 mod terminal;
+mod sync;
+mod sanitize;
+mod profiles;
+mod ssh_config;
+mod pre_connect;
+#[cfg(not(target_arch = "wasm32"))]
+mod power;
+#[cfg(not(target_arch = "wasm32"))]
+mod lifecycle_hooks;
+#[cfg(not(target_arch = "wasm32"))]
+mod notify;
+#[cfg(not(target_arch = "wasm32"))]
+mod recent;
+#[cfg(not(target_arch = "wasm32"))]
+mod auth_throttle;
+#[cfg(not(target_arch = "wasm32"))]
+mod settings;
+#[cfg(not(target_arch = "wasm32"))]
+mod paths;
+#[cfg(not(target_arch = "wasm32"))]
+mod transfer;
+#[cfg(not(target_arch = "wasm32"))]
+mod watch;
+#[cfg(not(target_arch = "wasm32"))]
+mod edit;
+#[cfg(not(target_arch = "wasm32"))]
+mod auto_forward;
+#[cfg(not(target_arch = "wasm32"))]
+mod remote_forward;
+#[cfg(not(target_arch = "wasm32"))]
+mod local_forward;
+#[cfg(not(target_arch = "wasm32"))]
+mod udp_forward;
+#[cfg(not(target_arch = "wasm32"))]
+mod forwards;
+#[cfg(not(target_arch = "wasm32"))]
+mod latency;
+#[cfg(not(target_arch = "wasm32"))]
+mod tabs;
+#[cfg(not(target_arch = "wasm32"))]
+mod socket_options;
+mod script;
+mod multi_exec;
+mod relay;
+#[cfg(not(target_arch = "wasm32"))]
+mod ui;
+#[cfg(not(target_arch = "wasm32"))]
+mod recording;
+#[cfg(not(target_arch = "wasm32"))]
+mod dump_io;
+#[cfg(test)]
+mod testing;
 
 #[cfg(not(target_arch = "wasm32"))]
 mod ssh;
@@ -16,6 +77,34 @@ mod ssh_impl;
 mod native;
 #[cfg(not(target_arch = "wasm32"))]
 mod cli_terminal;
+#[cfg(not(target_arch = "wasm32"))]
+mod host_picker;
+#[cfg(not(target_arch = "wasm32"))]
+mod stdio;
+#[cfg(unix)]
+mod daemon;
+#[cfg(unix)]
+mod control_fifo;
+#[cfg(not(target_arch = "wasm32"))]
+mod session_cache;
+#[cfg(not(target_arch = "wasm32"))]
+mod authorized_keys;
+#[cfg(all(feature = "fuse", unix))]
+mod mount;
+#[cfg(feature = "discovery")]
+mod discovery;
+#[cfg(not(target_arch = "wasm32"))]
+mod progress;
+#[cfg(not(target_arch = "wasm32"))]
+mod websocket;
+#[cfg(not(target_arch = "wasm32"))]
+mod diagnose;
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)] // `diagnose` reads the server's KEXINIT with the pure-Rust transport's parser
+mod ssh_wire;
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)] // needed by ssh_wire
+mod cipher;
 
 #[cfg(target_arch = "wasm32")]
 mod wasm_ssh;
@@ -25,7 +114,7 @@ mod wasm_terminal;
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> Result<()> {
-    env_logger::init();
+    init_logger();
     
     let matches = Command::new("bxssh")
         .version("0.1.0")
@@ -37,6 +126,15 @@ fn main() -> Result<()> {
                 .required(false)
                 .index(1),
         )
+        .arg(
+            Arg::new("remote-command")
+                .help("Command to run with stdin/stdout piped unmodified (OpenSSH style, e.g. for GIT_SSH_COMMAND)")
+                .index(2)
+                .num_args(1..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true)
+                .conflicts_with_all(["command", "stdio-forward"]),
+        )
         .arg(
             Arg::new("port")
                 .short('p')
@@ -55,7 +153,8 @@ fn main() -> Result<()> {
             Arg::new("identity")
                 .short('i')
                 .long("identity")
-                .help("Path to SSH private key file"),
+                .help("Private key file or stored key name; repeat to try several in order")
+                .action(clap::ArgAction::Append),
         )
         .arg(
             Arg::new("command")
@@ -63,12 +162,263 @@ fn main() -> Result<()> {
                 .long("command")
                 .help("Command to execute on remote host"),
         )
+        // The notification is for a command; an interactive shell would ignore it
+        .arg(notify_arg().requires("notify-job"))
+        .group(ArgGroup::new("notify-job").args(["command", "remote-command", "tty"]).multiple(true))
+        .arg(
+            Arg::new("tty")
+                .short('t')
+                .help("Run the command on a PTY sized to this terminal (for full-screen programs like top)")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("stdio-forward"),
+        )
+        .arg(
+            Arg::new("stdio-forward")
+                .short('W')
+                .value_name("HOST:PORT")
+                .help("Forward stdin/stdout to HOST:PORT through the remote host (for use as ProxyCommand)")
+                .conflicts_with("command"),
+        )
+        .arg(
+            Arg::new("option")
+                .short('o')
+                .value_name("KEY=VALUE")
+                .help("OpenSSH-style option (User, Port, IdentityFile, IdentitiesOnly; others are ignored)")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("print-config")
+                .short('G')
+                .help("Print the resolved connection settings and exit")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("password")
                 .long("password")
                 .help("Use password authentication instead of keys")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("rc-file")
+                .long("rc-file")
+                .value_name("FILE")
+                .help("Shell snippet to upload and source when the interactive shell starts (aliases, prompt, EDITOR)")
+                .conflicts_with_all(["command", "remote-command", "stdio-forward"]),
+        )
+        .arg(
+            Arg::new("tmux")
+                .long("tmux")
+                .value_name("SESSION")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("bxssh")
+                .help("Attach to a tmux (or screen) session, creating it, and reattach after a dropped connection")
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach", "tty"]),
+        )
+        .arg(
+            Arg::new("auto-forward")
+                .long("auto-forward")
+                .help("Forward ports that shell programs announce (e.g. \"listening on port 3000\") to localhost")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach"]),
+        )
+        .arg(
+            Arg::new("scan-ports")
+                .long("scan-ports")
+                .help("With --auto-forward, also forward new listening sockets the server reports (ss/netstat)")
+                .action(clap::ArgAction::SetTrue)
+                .requires("auto-forward"),
+        )
+        .arg(
+            Arg::new("local-forward")
+                .short('L')
+                .value_name("[BIND:]PORT:HOST:HOSTPORT[@ALLOW]")
+                .help("Listen on PORT here and tunnel its connections to HOST:HOSTPORT as seen from the server; \
+                       @ADDR,CIDR limits who may connect")
+                .action(clap::ArgAction::Append)
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach"]),
+        )
+        .arg(
+            Arg::new("udp-forward")
+                .long("udp-forward")
+                .value_name("[BIND:]PORT:HOST:HOSTPORT")
+                .help("Tunnel UDP datagrams to PORT here on to HOST:HOSTPORT from the server (needs bxssh there)")
+                .action(clap::ArgAction::Append)
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach"]),
+        )
+        .arg(
+            Arg::new("remote-forward")
+                .short('R')
+                .value_name("[BIND:]PORT:HOST:HOSTPORT")
+                .help("Have the server listen on PORT (0: any) and tunnel its connections to HOST:HOSTPORT from here")
+                .action(clap::ArgAction::Append)
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach"]),
+        )
+        .arg(
+            Arg::new("any-remote-port")
+                .long("any-remote-port")
+                .help("With -R, if the server refuses a port (e.g. it is in use), take the port it assigns")
+                .action(clap::ArgAction::SetTrue)
+                .requires("remote-forward"),
+        )
+        .arg(
+            Arg::new("gateway-ports")
+                .long("gateway-ports")
+                .help("Let -L, -R and --udp-forward listen on addresses other machines can reach, not only loopback")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("latency")
+                .long("latency")
+                .help("Measure the round trip every few seconds and show it in the terminal's status line")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach", "tmux"]),
+        )
+        .arg(
+            Arg::new("latency-warn")
+                .long("latency-warn")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Measure the round trip every few seconds and warn when it exceeds MS milliseconds (default 500)")
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach", "tmux"]),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .help("Save what the interactive session displays under ~/.local/state/bxssh/recordings")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach"]),
+        )
+        .arg(
+            Arg::new("control-fifo")
+                .long("control-fifo")
+                .value_name("DIR")
+                .help("Named pipes DIR/in and DIR/out that type into the interactive session and carry its output")
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "detach"]),
+        )
+        .arg(
+            Arg::new("dump-io")
+                .long("dump-io")
+                .value_name("DIR")
+                .help("Write a timestamped hex dump of each channel's traffic to a file in DIR, for debugging"),
+        )
+        .arg(
+            Arg::new("dump-io-limit")
+                .long("dump-io-limit")
+                .value_name("MB")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Largest --dump-io file per channel, in MiB (default 16)")
+                .requires("dump-io"),
+        )
+        .arg(
+            Arg::new("wol")
+                .long("wol")
+                .value_name("MAC[@BROADCAST]")
+                .help("Wake the host with a Wake-on-LAN packet and wait for its SSH port before connecting"),
+        )
+        .arg(
+            Arg::new("wol-timeout")
+                .long("wol-timeout")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("How long to wait for a woken host (default: 120)")
+                .requires("wol"),
+        )
+        .arg(
+            Arg::new("wait")
+                .long("wait")
+                .help("Wait for the host's SSH port to accept connections (e.g. after a reboot), then connect")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("wait-timeout")
+                .long("wait-timeout")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("How long --wait waits for the host (default: 300)")
+                .requires("wait"),
+        )
+        .arg(
+            Arg::new("wait-interval")
+                .long("wait-interval")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Pause between --wait checks (default: 2)")
+                .requires("wait"),
+        )
+        .arg(
+            Arg::new("wait-auth")
+                .long("wait-auth")
+                .help("With --wait, also retry the login until it succeeds")
+                .action(clap::ArgAction::SetTrue)
+                .requires("wait")
+                .conflicts_with("password"),
+        )
+        .arg(
+            Arg::new("remote-info")
+                .long("remote-info")
+                .help("After login, detect the remote OS, shell and tools and print them on stderr")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tos")
+                .long("tos")
+                .value_name("VALUE")
+                .help("IP type of service for the connection, as in ssh_config IPQoS (e.g. lowdelay, af21, ef, 0x10)"),
+        )
+        .arg(
+            Arg::new("no-banner")
+                .long("no-banner")
+                .help("Don't display the server's pre-authentication banner")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("detach")
+                .long("detach")
+                .help("Run the shell in a background session; reattach later with 'bxssh attach'")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["command", "remote-command", "stdio-forward", "password"]),
+        )
+        .arg(
+            Arg::new("session-name")
+                .long("session-name")
+                .value_name("NAME")
+                .help("Name for a --detach session (default: the host name)")
+                .requires("detach"),
+        )
+        .arg(
+            Arg::new("daemon-session")
+                .long("daemon-session")
+                .value_name("NAME")
+                .hide(true),
+        )
+        .arg(
+            Arg::new("session-cache-master")
+                .long("session-cache-master")
+                .value_name("NAME")
+                .hide(true),
+        )
+        .arg(
+            Arg::new("progress-json")
+                .long("progress-json")
+                .help("Emit NDJSON progress events on stderr (stdout stays remote output only)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Print only warnings, errors and requested output")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("plain")
+                .long("plain")
+                .help("No emoji, bullets or color, for pipes and limited terminals")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("generate-key")
                 .long("generate-key")
@@ -81,126 +431,1678 @@ fn main() -> Result<()> {
                 .help("List all available SSH keys")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(key_format_arg().requires("list-keys"))
+        .arg(key_tag_arg().requires("list-keys"))
+        .subcommand(
+            Command::new("key")
+                .about("Manage keys in the internal key store")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("generate")
+                        .about("Generate a new Ed25519 key pair")
+                        .arg(Arg::new("name").required(true))
+                        .arg(expires_arg())
+                        .args(key_label_args()),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List stored keys")
+                        .arg(
+                            Arg::new("verbose")
+                                .short('v')
+                                .long("verbose")
+                                .help("Show creation, usage and expiry details")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(key_format_arg())
+                        .arg(key_tag_arg()),
+                )
+                .subcommand(
+                    Command::new("label")
+                        .about("Change a key's comment or tags")
+                        .arg(Arg::new("name").required(true))
+                        .args(key_label_args())
+                        .arg(
+                            Arg::new("untag")
+                                .long("untag")
+                                .value_name("TAG")
+                                .help("Remove a tag (repeatable)")
+                                .action(clap::ArgAction::Append),
+                        ),
+                )
+                .subcommand(
+                    Command::new("rotate")
+                        .about("Replace a key with a new one, keeping the old key as <name>-previous[-N]")
+                        .arg(Arg::new("name").required(true))
+                        .arg(expires_arg()),
+                )
+                .subcommand(
+                    Command::new("set-default")
+                        .about("Use a key when no -i or profile IdentityFile names one")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("host")
+                                .long("host")
+                                .value_name("PATTERN")
+                                .help("Only for hosts matching this pattern list, e.g. '*.corp.example.com'"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("authorize")
+                        .about("Add a key to ~/.ssh/authorized_keys on a server, e.g. `bxssh key authorize ci web`")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("target").required(true).help("[user@]host to log in to"))
+                        .args(connection_args()),
+                )
+                .subcommand(
+                    Command::new("revoke")
+                        .about("Remove a key from ~/.ssh/authorized_keys on a server")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("target").required(true).help("[user@]host to log in to"))
+                        .args(connection_args()),
+                ),
+        )
+        .subcommand(
+            Command::new("attach")
+                .about("Reattach to a session started with --detach (lists sessions without a name)")
+                .arg(Arg::new("name")),
+        )
+        .subcommand(
+            Command::new("mount")
+                .about("Mount a remote directory read-only over SFTP (needs the `fuse` feature)")
+                .arg(Arg::new("remote").required(true).help("Remote directory as [user@]host:/path"))
+                .arg(Arg::new("mountpoint").required(true).help("Local directory to mount on"))
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("cat")
+                .about("Write a remote file to stdout, e.g. `bxssh cat host:/var/log/syslog | grep x`")
+                .arg(Arg::new("remote").required(true).help("Remote file as [user@]host:/path"))
+                .arg(verify_arg())
+                .arg(notify_arg())
+                .arg(session_cache_arg())
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("put")
+                .about("Write stdin to a remote file, e.g. `tar cz dir | bxssh put host:/tmp/dir.tgz`")
+                .arg(
+                    Arg::new("remote")
+                        .required(true)
+                        .help("Remote file as [user@]host:/path; with path -, a new temp file whose path is printed"),
+                )
+                .arg(verify_arg())
+                .arg(notify_arg())
+                .arg(session_cache_arg())
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Edit a remote file in $EDITOR, uploading each save, e.g. `bxssh edit host:/etc/hosts`")
+                .arg(Arg::new("remote").required(true).help("Remote file as [user@]host:/path; created if missing"))
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Upload even if the file changed on the server since it was opened"),
+                )
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Report changes under a remote path, e.g. `bxssh watch host:/srv/app --exec 'make reload'`")
+                .arg(Arg::new("remote").required(true).help("Remote file or directory as [user@]host:/path"))
+                .arg(
+                    Arg::new("exec")
+                        .long("exec")
+                        .value_name("COMMAND")
+                        .help("Local command to run on each batch of changes, with the paths in $BXSSH_CHANGED"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_name("SECS")
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .help("Seconds between scans when polling"),
+                )
+                .arg(
+                    Arg::new("poll")
+                        .long("poll")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Poll even if the server has inotifywait"),
+                )
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("exec")
+                .about("Run a command on several hosts in parallel")
+                .arg(
+                    Arg::new("host")
+                        .short('H')
+                        .long("host")
+                        .value_name("TARGET")
+                        .action(clap::ArgAction::Append)
+                        .help("Target as [user@]host (repeatable)"),
+                )
+                .arg(
+                    Arg::new("hosts-file")
+                        .short('f')
+                        .long("hosts-file")
+                        .value_name("FILE")
+                        .help("File with one target per line"),
+                )
+                .arg(
+                    Arg::new("username")
+                        .short('u')
+                        .long("username")
+                        .help("Username for targets without user@"),
+                )
+                .args(connection_args())
+                .args(parallel_args())
+                .arg(
+                    Arg::new("group-output")
+                        .long("group-output")
+                        .help("Print each host's output as one block when it finishes")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("quiet-hosts")
+                        .long("quiet-hosts")
+                        .help("Leave out the [host] prefix when there is only one host")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(session_cache_arg())
+                .arg(
+                    Arg::new("json-report")
+                        .long("json-report")
+                        .value_name("FILE")
+                        .help("Also write per-host results as JSON"),
+                )
+                .arg(
+                    Arg::new("command")
+                        .required(true)
+                        .num_args(1..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true)
+                        .help("Command to run on every host"),
+                ),
+        )
+        .subcommand(
+            Command::new("push")
+                .about("Upload a file to every host of a group in parallel, e.g. `bxssh push app.tgz @web:/srv/`")
+                .arg(Arg::new("file").required(true).help("Local file to upload"))
+                .arg(
+                    Arg::new("remote")
+                        .required(true)
+                        .help("Destination as @group:/path or [user@]host:/path; a path ending in / keeps the name"),
+                )
+                .arg(
+                    Arg::new("username")
+                        .short('u')
+                        .long("username")
+                        .help("Username for hosts without user@"),
+                )
+                .arg(verify_arg())
+                .args(connection_args())
+                .args(parallel_args()),
+        )
+        .subcommand(
+            Command::new("pull")
+                .about("Download a file from each host of a group at once, e.g. `bxssh pull @web:/app.log {host}.log`")
+                .arg(Arg::new("remote").required(true).help("Source as @group:/path or [user@]host:/path"))
+                .arg(
+                    Arg::new("local")
+                        .required(true)
+                        .help("Local file, with {host} replaced by each host's name; missing directories are created"),
+                )
+                .arg(
+                    Arg::new("username")
+                        .short('u')
+                        .long("username")
+                        .help("Username for hosts without user@"),
+                )
+                .arg(verify_arg())
+                .args(connection_args())
+                .args(parallel_args()),
+        )
+        .subcommand(
+            Command::new("tabs")
+                .about("Open a shell on several hosts in one terminal, one tab each; Ctrl+] then 1-9 switches")
+                .arg(
+                    Arg::new("targets")
+                        .required(true)
+                        .num_args(1..)
+                        .help("Targets as [user@]host, one tab each"),
+                )
+                .arg(
+                    Arg::new("broadcast")
+                        .long("broadcast")
+                        .help("Send what is typed to every tab from the start (toggle with Ctrl+] b)")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("reboot")
+                .about("Reboot a host (through sudo unless you log in as root) and optionally wait until it is back")
+                .arg(Arg::new("target").required(true).help("Target as [user@]host"))
+                .arg(
+                    Arg::new("poweroff")
+                        .long("poweroff")
+                        .help("Power the host off instead of rebooting it")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("wait"),
+                )
+                .arg(
+                    Arg::new("wait")
+                        .long("wait")
+                        .help("Log in again once the host is back and report how long it was down")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("wait-timeout")
+                        .long("wait-timeout")
+                        .value_name("SECONDS")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .help("How long --wait waits for the host (default: 300)")
+                        .requires("wait"),
+                )
+                .arg(
+                    Arg::new("wait-interval")
+                        .long("wait-interval")
+                        .value_name("SECONDS")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .help("Pause between --wait checks (default: 2)")
+                        .requires("wait"),
+                )
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("run-script")
+                .about("Drive an interactive program with expect/send steps from a YAML file")
+                .arg(Arg::new("file").required(true).help("Script file"))
+                .arg(Arg::new("target").required(true).help("Target as user@host"))
+                .args(connection_args()),
+        )
+        .subcommand(
+            Command::new("discover")
+                .about("List SSH servers announced on the local network over mDNS (needs the `discovery` feature)")
+                .arg(
+                    Arg::new("connect")
+                        .long("connect")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .help("Connect to the Nth host of the list"),
+                )
+                .arg(
+                    Arg::new("username")
+                        .short('u')
+                        .long("user")
+                        .help("Username for --connect (default: the local user)"),
+                )
+                .args(connection_args())
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .help("How long to collect answers"),
+                ),
+        )
+        .subcommand(
+            Command::new("diagnose")
+                .about("Check name resolution, reachability, algorithms, authentication and keys for a host")
+                .arg(Arg::new("target").required(true).help("Target as user@host"))
+                .args(connection_args())
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .default_value("5")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .help("Give up on each network step after this long"),
+                ),
+        )
+        .subcommand(
+            Command::new("recent")
+                .about("Pick a recent connection or a named profile to connect to")
+                .arg(
+                    Arg::new("number")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .help("Connect to this entry of the list without asking"),
+                )
+                .arg(
+                    Arg::new("username")
+                        .short('u')
+                        .long("user")
+                        .help("Log in as this user instead"),
+                )
+                .args(connection_args())
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .help("Only print the list")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("clear")
+                        .long("clear")
+                        .help("Forget all recent connections")
+                        .conflicts_with_all(["number", "list"])
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Inspect connection settings and check ~/.config/bxssh/config.toml")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("dump")
+                        .about("Print the effective settings for a host and the sections they came from")
+                        .arg(
+                            Arg::new("host")
+                                .long("host")
+                                .required(true)
+                                .help("Target as host or user@host"),
+                        )
+                        .arg(
+                            Arg::new("username")
+                                .short('u')
+                                .long("user")
+                                .help("Remote user, for Match User sections"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check ~/.config/bxssh/config.toml, or FILE, and report the first error by line")
+                        .arg(Arg::new("file").help("Settings file to check instead")),
+                ),
+        )
+        .subcommand(
+            Command::new("relay")
+                .about("Bridge browser SSH clients to servers (see \"Browsers without Direct Sockets\" in the README)")
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .value_name("ADDR")
+                        .default_value("127.0.0.1:8022")
+                        .help("Address to accept relay streams on"),
+                )
+                .arg(
+                    Arg::new("allow")
+                        .long("allow")
+                        .value_name("HOSTS")
+                        .required(true)
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Hosts clients may reach, as host patterns with an optional :port (default 22)"),
+                )
+                .arg(
+                    Arg::new("allow-origin")
+                        .long("allow-origin")
+                        .value_name("ORIGINS")
+                        .value_delimiter(',')
+                        .action(clap::ArgAction::Append)
+                        .help("Web pages browsers may connect from, e.g. https://app.example.com (default: same host)"),
+                ),
+        )
+        .subcommand(
+            Command::new("udp-endpoint")
+                .about("Relay framed UDP datagrams between stdin/stdout and HOST:PORT (run by --udp-forward)")
+                .arg(Arg::new("destination").required(true).value_name("HOST:PORT")),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Move keys and settings between machines as an encrypted bundle")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Write keys, profiles and known hosts to a passphrase-encrypted file")
+                        .arg(Arg::new("file").required(true)),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Restore an exported bundle (existing entries are kept unless --force)")
+                        .arg(Arg::new("file").required(true))
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .help("Overwrite existing keys and files")
+                                .action(clap::ArgAction::SetTrue),
+                        ),
+                ),
+        )
         .get_matches();
 
-    // Handle key management commands first
-    if let Some(key_name) = matches.get_one::<String>("generate-key") {
-        return handle_generate_key(key_name);
+    // Checking a broken settings file must not fail on loading it
+    if let Some(("config", config_matches)) = matches.subcommand() {
+        if let Some(("validate", sub)) = config_matches.subcommand() {
+            return handle_config_validate(sub.get_one::<String>("file"));
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let config = config::SshConfig::load()?;
+        i18n::set_lang(config.lang);
+        ui::configure(matches.get_flag("plain"), matches.get_flag("quiet"), config.theme);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if matches.get_flag("progress-json") {
+        progress::enable_stderr();
+    }
+
+    // Handle key management commands first
+    if let Some(("key", key_matches)) = matches.subcommand() {
+        return match key_matches.subcommand() {
+            Some(("generate", sub)) => {
+                let expires_at = parse_expiry_arg(sub)?;
+                handle_generate_key(sub.get_one::<String>("name").unwrap(), expires_at, &key_labels(sub))
+            }
+            Some(("list", sub)) => handle_list_keys(sub.get_flag("verbose"), key_format(sub), sub.get_one("tag")),
+            Some(("label", sub)) => {
+                let untag: Vec<String> = sub.get_many::<String>("untag").unwrap_or_default().cloned().collect();
+                handle_label_key(sub.get_one::<String>("name").unwrap(), &key_labels(sub), &untag)
+            }
+            Some(("rotate", sub)) => {
+                let expires_at = parse_expiry_arg(sub)?;
+                handle_rotate_key(sub.get_one::<String>("name").unwrap(), expires_at)
+            }
+            Some(("set-default", sub)) => {
+                handle_set_default_key(sub.get_one::<String>("name").unwrap(), sub.get_one::<String>("host"))
+            }
+            Some((action @ ("authorize" | "revoke"), sub)) => handle_authorize_key(sub, action == "revoke"),
+            _ => unreachable!("clap requires a key subcommand"),
+        };
+    }
+
+    #[cfg(unix)]
+    if let Some(("attach", sub)) = matches.subcommand() {
+        return handle_attach(sub.get_one::<String>("name"));
+    }
+
+    #[cfg(not(unix))]
+    if matches.subcommand_name() == Some("attach") || matches.get_flag("detach") {
+        return Err(anyhow::anyhow!("Detached sessions are only supported on Unix"));
+    }
+
+    #[cfg(not(unix))]
+    if matches.contains_id("control-fifo") {
+        return Err(anyhow::anyhow!("--control-fifo needs named pipes, which are only supported on Unix"));
+    }
+
+    if let Some(("mount", sub)) = matches.subcommand() {
+        return handle_mount(sub);
+    }
+
+    if let Some((name @ ("cat" | "put"), sub)) = matches.subcommand() {
+        let (target, path) = parse_remote_path(sub.get_one::<String>("remote").unwrap())?;
+        let options = subcommand_connect_options(&target, sub)?;
+        let verify = sub.get_flag("verify");
+        let job = sub.get_flag("notify").then(|| notify::Job::start(&path, &options.host));
+        let result = match name {
+            "cat" => native::cat(&options, &path, verify),
+            _ => native::put(&options, &path, verify),
+        };
+        finish_job(job, result.as_ref().map(|_| 0));
+        return result;
+    }
+
+    if let Some(("edit", sub)) = matches.subcommand() {
+        let (target, path) = parse_remote_path(sub.get_one::<String>("remote").unwrap())?;
+        let options = subcommand_connect_options(&target, sub)?;
+        return native::edit(&options, &path, sub.get_flag("force"));
+    }
+
+    if let Some(("watch", sub)) = matches.subcommand() {
+        let (target, path) = parse_remote_path(sub.get_one::<String>("remote").unwrap())?;
+        let options = subcommand_connect_options(&target, sub)?;
+        let interval = std::time::Duration::from_secs(*sub.get_one::<u64>("interval").unwrap());
+        let exec = sub.get_one::<String>("exec").map(String::as_str);
+        return native::watch(&options, &path, exec, interval, sub.get_flag("poll"));
+    }
+
+    if let Some(("exec", sub)) = matches.subcommand() {
+        return handle_multi_exec(sub);
+    }
+
+    if let Some(("push", sub)) = matches.subcommand() {
+        return handle_push(sub);
+    }
+
+    if let Some(("pull", sub)) = matches.subcommand() {
+        return handle_pull(sub);
+    }
+
+    if let Some(("tabs", sub)) = matches.subcommand() {
+        let targets = sub.get_many::<String>("targets").unwrap();
+        let options = targets.map(|target| subcommand_connect_options(target, sub)).collect::<Result<Vec<_>>>()?;
+        return native::run_tabs(&options, sub.get_flag("broadcast"));
+    }
+
+    if let Some(("reboot", sub)) = matches.subcommand() {
+        let options = subcommand_connect_options(sub.get_one::<String>("target").unwrap(), sub)?;
+        let action = if sub.get_flag("poweroff") { power::PowerAction::Poweroff } else { power::PowerAction::Reboot };
+        let seconds = |id| sub.get_one::<u64>(id).map(|secs| std::time::Duration::from_secs(*secs));
+        let wait = sub.get_flag("wait").then(|| pre_connect::WaitForHost {
+            timeout: seconds("wait-timeout").unwrap_or(pre_connect::DEFAULT_WAIT_TIMEOUT),
+            interval: seconds("wait-interval").unwrap_or(pre_connect::DEFAULT_WAIT_INTERVAL),
+            auth: true,
+        });
+        return native::reboot(&options, action, wait);
+    }
+
+    if let Some(("run-script", sub)) = matches.subcommand() {
+        let script = script::ExpectScript::load(std::path::Path::new(sub.get_one::<String>("file").unwrap()))?;
+        let options = subcommand_connect_options(sub.get_one::<String>("target").unwrap(), sub)?;
+        return native::run_script(&options, &script);
+    }
+
+    if let Some(("discover", sub)) = matches.subcommand() {
+        return handle_discover(sub);
+    }
+
+    if let Some(("diagnose", sub)) = matches.subcommand() {
+        return handle_diagnose(sub);
+    }
+
+    if let Some(("recent", sub)) = matches.subcommand() {
+        return handle_recent(sub);
+    }
+
+    if let Some(("config", config_matches)) = matches.subcommand() {
+        return match config_matches.subcommand() {
+            Some(("dump", sub)) => {
+                handle_config_dump(sub.get_one::<String>("host").unwrap(), sub.get_one::<String>("username"))
+            }
+            _ => unreachable!("clap requires a config subcommand"),
+        };
+    }
+
+    if let Some(("relay", sub)) = matches.subcommand() {
+        let allow: Vec<&String> = sub.get_many::<String>("allow").into_iter().flatten().collect();
+        let origins: Vec<String> = sub.get_many::<String>("allow-origin").into_iter().flatten().cloned().collect();
+        return handle_relay(sub.get_one::<String>("listen").unwrap(), &allow, origins);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(("udp-endpoint", sub)) = matches.subcommand() {
+        return udp_forward::run_endpoint(sub.get_one::<String>("destination").unwrap());
+    }
+
+    if let Some(("sync", sync_matches)) = matches.subcommand() {
+        return match sync_matches.subcommand() {
+            Some(("export", sub)) => handle_sync_export(sub.get_one::<String>("file").unwrap()),
+            Some(("import", sub)) => {
+                handle_sync_import(sub.get_one::<String>("file").unwrap(), sub.get_flag("force"))
+            }
+            _ => unreachable!("clap requires a sync subcommand"),
+        };
+    }
+
+    if let Some(key_name) = matches.get_one::<String>("generate-key") {
+        return handle_generate_key(key_name, None, &key_manager::KeyLabels::default());
+    }
+
+    if matches.get_flag("list-keys") {
+        return handle_list_keys(false, key_format(&matches), matches.get_one("tag"));
+    }
+
+    let ssh_options = parse_ssh_options(matches.get_many::<String>("option").into_iter().flatten())?;
+
+    // Parse connection target (user@host or host)
+    let explicit_user = matches.get_one::<String>("username").or(ssh_options.user.as_ref());
+    let picked = match matches.get_one::<String>("target") {
+        None if explicit_user.is_none() => match pick_host()? {
+            Some(Some(entry)) => Some(entry),
+            Some(None) => return Ok(()),
+            None => None,
+        },
+        _ => None,
+    };
+    let target = matches.get_one::<String>("target").or(picked.as_ref().map(|entry| &entry.target));
+    let profile = match target {
+        Some(target) => load_profile(target, explicit_user)?,
+        None => profiles::Profile::default(),
+    };
+    let username_arg = explicit_user.or(profile.user.as_ref());
+    
+    // Check for common mistake: using -u with user@host format
+    if target.is_none() {
+        if let Some(username) = username_arg {
+            if username.contains('@') {
+                eprintln!("{}", tr(Msg::InvalidUsage, &[]));
+                eprintln!("{}", tr(Msg::YouUsed, &[username]));
+                eprintln!("{}", tr(Msg::CorrectUsage, &[]));
+                eprintln!("  bxssh {}           (new format)", username);
+                eprintln!("  bxssh -u user host  (old format)");
+                std::process::exit(1);
+            }
+        }
+        
+        eprintln!("{}", tr(Msg::TargetRequired, &[]));
+        eprintln!("{}", tr(Msg::TargetUsage, &[]));
+        eprintln!("{}", tr(Msg::SeeHelp, &[]));
+        std::process::exit(1);
+    }
+    
+    let target = target.unwrap();
+    let (username, alias, port_in_target) = parse_target(target, username_arg)?;
+    let host = profile.host_name.clone().unwrap_or_else(|| alias.clone());
+    
+    // Debug log to show what was parsed
+    log::info!("Parsed target: username='{}', host='{}'", username, host);
+    let picked_port = picked.as_ref().and_then(|entry| entry.port);
+    let port_flag = matches.get_one::<String>("port").unwrap().parse::<u16>().context("Invalid port number")?;
+    let explicit_port = (matches.value_source("port") == Some(ValueSource::CommandLine)).then_some(port_flag);
+    let port = target_port(port_in_target, explicit_port)
+        .or(explicit_port)
+        .or(ssh_options.port.or(picked_port).or(profile.port))
+        .unwrap_or(port_flag);
+    let tokens = ssh_config::Tokens::new(&host, &alias, port, &username);
+    let identity_flags = matches.get_many::<String>("identity");
+    let identities = identities(identity_flags, &ssh_options.identity_files, &profile, &tokens)?;
+    let identities_only = ssh_options.identities_only.or(profile.identities_only).unwrap_or(false);
+    let command = matches.get_one::<String>("command");
+    let use_password = matches.get_flag("password");
+
+    if matches.get_flag("print-config") {
+        print_config(&username, &host, port, &identities, identities_only, &profile);
+        return Ok(());
+    }
+
+    info!("Connecting to {}@{}:{}", username, host, port);
+
+    let rc_file = matches
+        .get_one::<String>("rc-file")
+        .map(|path| std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path)))
+        .transpose()?;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut pre_connect = pre_connect::PreConnect::from_profile(&profile)?;
+    if let Some(wake) = matches.get_one::<String>("wol") {
+        pre_connect.wake = Some(wake.parse()?);
+        pre_connect.wake_timeout = matches.get_one::<u64>("wol-timeout").map(|secs| std::time::Duration::from_secs(*secs));
+    }
+    if matches.get_flag("wait") {
+        let seconds = |id| matches.get_one::<u64>(id).map(|secs| std::time::Duration::from_secs(*secs));
+        pre_connect.wait = Some(pre_connect::WaitForHost {
+            timeout: seconds("wait-timeout").unwrap_or(pre_connect::DEFAULT_WAIT_TIMEOUT),
+            interval: seconds("wait-interval").unwrap_or(pre_connect::DEFAULT_WAIT_INTERVAL),
+            auth: matches.get_flag("wait-auth"),
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut socket = profile.socket_options()?;
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(tos) = matches.get_one::<String>("tos").or(ssh_options.ip_qos.as_ref()) {
+        socket.tos = socket_options::parse_tos(tos)?;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut terminal = profile.terminal_overrides()?;
+    terminal.record |= matches.get_flag("record");
+    // Locale first, so RemoteInit commands already run under it
+    #[cfg(not(target_arch = "wasm32"))]
+    let remote_init = [profile.locale_exports()?, profile.remote_init.clone()].concat();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let forwards = forward_specs(&matches)?;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let options = native::ConnectOptions {
+        host,
+        port,
+        username,
+        identities,
+        identities_only,
+        use_password,
+        show_banner: !matches.get_flag("no-banner") && !ssh_options.suppress_banner,
+        pre_connect,
+        hooks: lifecycle_hooks::LifecycleHooks::from_profile(&profile),
+        shell_init: native::shell_init_script(rc_file.as_deref(), &remote_init)?,
+        probe_remote: matches.get_flag("remote-info"),
+        retry: match ssh_options.connection_attempts {
+            Some(attempts) => profile.retry_policy().with_attempts(attempts),
+            None => profile.retry_policy(),
+        },
+        auto_forward: matches.get_flag("auto-forward").then(|| auto_forward::AutoForwardOptions {
+            scan_interval: matches.get_flag("scan-ports").then_some(auto_forward::DEFAULT_SCAN_INTERVAL),
+        }),
+        local_forwards: forwards.local,
+        udp_forwards: forwards.udp,
+        remote_forwards: forwards.remote,
+        any_remote_port: matches.get_flag("any-remote-port"),
+        latency: (matches.get_flag("latency") || matches.contains_id("latency-warn")).then(|| latency::LatencyOptions {
+            status_line: matches.get_flag("latency"),
+            warn_above: matches
+                .get_one::<u64>("latency-warn")
+                .map_or(latency::DEFAULT_WARN_ABOVE, |ms| std::time::Duration::from_millis(*ms)),
+        }),
+        socket,
+        session_cache: None,
+        // Piped commands and -W are mostly other tools (git, ProxyCommand) connecting
+        record_recent: (matches.get_one::<String>("remote-command").is_none()
+            && matches.get_one::<String>("stdio-forward").is_none())
+        .then_some(alias),
+        terminal,
+        dump_io: matches.get_one::<String>("dump-io").map(|dir| dump_io::DumpIoOptions {
+            dir: dir.into(),
+            limit: matches.get_one::<u64>("dump-io-limit").map_or(dump_io::DEFAULT_LIMIT, |mb| mb * 1024 * 1024),
+        }),
+        control_fifo: matches.get_one::<String>("control-fifo").map(Into::into),
+        cancel: None,
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(name) = matches.get_one::<String>("tmux") {
+        let status = native::attach_multiplexer(&options, name)?;
+        std::process::exit(status);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if matches.get_flag("tty") {
+        let tty_command = match (command, matches.get_many::<String>("remote-command")) {
+            (Some(command), _) => Some(command.clone()),
+            (None, Some(words)) => Some(words.cloned().collect::<Vec<_>>().join(" ")),
+            (None, None) => None,
+        };
+        // Without a command, -t is the default interactive shell
+        if let Some(tty_command) = tty_command {
+            let job = matches.get_flag("notify").then(|| notify::Job::start(&tty_command, &options.host));
+            let status = native::exec_tty(&options, &tty_command);
+            finish_job(job, status.as_ref().copied());
+            std::process::exit(status?);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(words) = matches.get_many::<String>("remote-command") {
+        let remote_command = words.cloned().collect::<Vec<_>>().join(" ");
+        let job = matches.get_flag("notify").then(|| notify::Job::start(&remote_command, &options.host));
+        let status = native::exec_piped(&options, &remote_command);
+        finish_job(job, status.as_ref().copied());
+        std::process::exit(status?);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(spec) = matches.get_one::<String>("stdio-forward") {
+        let (forward_host, forward_port) = parse_forward_target(spec)?;
+        return native::forward_stdio(&options, &forward_host, forward_port);
+    }
+
+    #[cfg(unix)]
+    if let Some(name) = matches.get_one::<String>("daemon-session") {
+        return native::run_detached_session(&options, name);
+    }
+
+    #[cfg(unix)]
+    if let Some(name) = matches.get_one::<String>("session-cache-master") {
+        return native::run_session_cache(&options, name);
+    }
+
+    #[cfg(unix)]
+    if matches.get_flag("detach") {
+        let name = matches
+            .get_one::<String>("session-name")
+            .cloned()
+            .unwrap_or_else(|| options.host.clone());
+        let args = daemon_args(std::env::args().skip(1), &name);
+        daemon::spawn(&args, &daemon::run_dir()?, &name)?;
+        ui::status(ui::Icon::Session, &tr(Msg::SessionBackground, &[&name]));
+        ui::status(ui::Icon::Hint, &tr(Msg::ReattachSession, &[&name]));
+        return Ok(());
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        wasm::connect(host, port, username, identities.first(), command)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Some(command) = command {
+            let job = matches.get_flag("notify").then(|| notify::Job::start(command, &options.host));
+            let status = native::exec_command(&options, command);
+            finish_job(job, status.as_ref().copied());
+            std::process::exit(status?);
+        }
+        native::connect(&options)
+    }
+}
+
+/// The host picker, when bxssh runs on a terminal with no target and there is
+/// something to pick from. `Some(None)` when the user cancelled.
+fn pick_host() -> Result<Option<Option<host_picker::HostEntry>>> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Ok(None);
+    }
+    let recent = recent::RecentList::path()
+        .and_then(|path| recent::RecentList::load_from(&path))
+        .unwrap_or_else(|e| {
+            ui::warn(&format!("{:#}", e));
+            recent::RecentList::default()
+        });
+    let entries = host_picker::entries(&recent, &profiles::Profiles::load()?);
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(host_picker::run(&entries)?.map(|i| entries[i].clone())))
+}
+
+/// Profile sections matching the host part of `target` (and its user, or `user` when
+/// the target names none), with the host canonicalized when the profile asks for it
+fn load_profile(target: &str, user: Option<&String>) -> Result<profiles::Profile> {
+    let target = remote_target::Target::parse(target)?;
+    let user = target.user.as_deref().or(user.map(String::as_str));
+    let profile = profiles::Profiles::load()?.resolve_canonical(&target.host, user, &profiles::SystemEnv)?;
+    if let Some(proxy) = &profile.ignored_proxy {
+        ui::warn(&tr(Msg::ProxyIgnored, &[proxy]));
+    }
+    Ok(profile)
+}
+
+/// Effective settings in `ssh -G` style, one lowercase `key value` per line
+fn print_config(
+    username: &str,
+    host: &str,
+    port: u16,
+    identities: &[String],
+    identities_only: bool,
+    profile: &profiles::Profile,
+) {
+    println!("user {}", username);
+    println!("hostname {}", host);
+    println!("port {}", port);
+    for identity in identities {
+        println!("identityfile {}", identity);
+    }
+    if identities_only {
+        println!("identitiesonly yes");
+    }
+    if let Some(command) = &profile.pre_connect_command {
+        println!("preconnectcommand {}", command);
+    }
+    if let Some(command) = &profile.post_auth_command {
+        println!("postauthcommand {}", command);
+    }
+    if let Some(command) = &profile.disconnect_command {
+        println!("disconnectcommand {}", command);
+    }
+    if !profile.knock_sequence.is_empty() {
+        println!("knocksequence {}", profile.knock_sequence.join(","));
+    }
+    for command in &profile.remote_init {
+        println!("remoteinit {}", command);
+    }
+    if let Some(term) = &profile.term {
+        println!("term {}", term);
+    }
+    for setting in &profile.set_locale {
+        println!("setlocale {}", setting);
+    }
+    if let Some(attempts) = profile.connection_attempts {
+        println!("connectionattempts {}", attempts);
+    }
+    if let Some(qos) = &profile.ip_qos {
+        println!("ipqos {}", qos);
+    }
+    if profile.canonicalize_hostname == Some(true) {
+        println!("canonicalizehostname yes");
+        println!("canonicaldomains {}", profile.canonical_domains.join(" "));
+    }
+}
+
+/// `bxssh config dump`: the settings a connection to `target` would use, without
+/// command-line overrides, preceded by the sections that supplied them
+fn handle_config_dump(target: &str, user: Option<&String>) -> Result<()> {
+    let (target_user, alias) = match target.rsplit_once('@') {
+        Some((target_user, alias)) => (Some(target_user), alias),
+        None => (user.map(String::as_str), target),
+    };
+    let (profile, applied) = profiles::Profiles::load()?.resolve_traced(alias, target_user, &profiles::SystemEnv)?;
+
+    for section in &applied {
+        println!("# {}", section);
+    }
+    let local_user = std::env::var(config::USER_ENV).unwrap_or_default();
+    let username = target_user.or(profile.user.as_deref()).unwrap_or(&local_user);
+    let host = profile.host_name.as_deref().unwrap_or(alias);
+    let port = profile.port.unwrap_or(22);
+    print_config(username, host, port, &profile.identity_file, profile.identities_only.unwrap_or(false), &profile);
+    Ok(())
+}
+
+/// `bxssh config validate`: parse the settings file and say whether it is usable
+fn handle_config_validate(file: Option<&String>) -> Result<()> {
+    let path = match file {
+        Some(file) => std::path::PathBuf::from(file),
+        None => settings::Settings::path()?,
+    };
+    if !path.exists() {
+        if file.is_some() {
+            return Err(anyhow::anyhow!("No such file: {}", path.display()));
+        }
+        ui::status(ui::Icon::Empty, &tr(Msg::SettingsMissing, &[&path.display()]));
+        return Ok(());
+    }
+    settings::Settings::load_from(&path)?;
+    ui::status(ui::Icon::Success, &tr(Msg::SettingsValid, &[&path.display()]));
+    Ok(())
+}
+
+/// `env_logger` as usual, but with `[logging] level` from the settings file when
+/// `RUST_LOG` is unset. A broken file is reported later, once output is set up.
+fn init_logger() {
+    let mut logger = env_logger::Builder::from_default_env();
+    if std::env::var_os("RUST_LOG").is_none() {
+        if let Ok(settings::Settings { logging: settings::LoggingSettings { level: Some(level) }, .. }) =
+            settings::Settings::load()
+        {
+            logger.filter_level(level);
+        }
+    }
+    logger.init();
+}
+
+/// `--verify` of `cat` and `put`
+fn verify_arg() -> Arg {
+    Arg::new("verify")
+        .long("verify")
+        .action(clap::ArgAction::SetTrue)
+        .help("Compare the SHA-256 of the transferred data with the remote file's (needs sha256sum or shasum)")
+}
+
+/// `--notify` of `-c`, `cat` and `put`
+fn notify_arg() -> Arg {
+    Arg::new("notify")
+        .long("notify")
+        .action(clap::ArgAction::SetTrue)
+        .help("Show a desktop notification with the exit status and duration when the command or transfer finishes")
+}
+
+/// Report `outcome` of `job`, when `--notify` started one
+fn finish_job(job: Option<notify::Job>, outcome: Result<i32, &anyhow::Error>) {
+    if let Some(job) = job {
+        job.finish(&notify::DesktopNotifier, outcome);
+    }
+}
+
+/// `--session-cache` of `cat`, `put` and `exec`
+fn session_cache_arg() -> Arg {
+    Arg::new("session-cache")
+        .long("session-cache")
+        .action(clap::ArgAction::SetTrue)
+        .help("Reuse a background connection to the host, started by the first command (Unix)")
+}
+
+/// Keys to try, in order: every `-i`, else every `-o IdentityFile`, else the profile's;
+/// with `%h` and the other ssh_config tokens expanded
+fn identities(
+    flags: Option<clap::parser::ValuesRef<String>>,
+    options: &[String],
+    profile: &profiles::Profile,
+    tokens: &ssh_config::Tokens,
+) -> Result<Vec<String>> {
+    let identities = match flags {
+        Some(flags) => flags.cloned().collect(),
+        None if !options.is_empty() => options.to_vec(),
+        None => profile.identity_file.clone(),
+    };
+    identities.iter().map(|identity| ssh_config::expand_tokens(identity, tokens)).collect()
+}
+
+/// `--max-parallel`, `--timeout`, `--retries` and `--retry-delay` of `exec` and `push`
+fn parallel_args() -> [Arg; 4] {
+    [
+        Arg::new("max-parallel")
+            .long("max-parallel")
+            .value_name("N")
+            .default_value("10")
+            .value_parser(clap::value_parser!(u32).range(1..))
+            .help("Hosts to run at the same time"),
+        Arg::new("timeout")
+            .long("timeout")
+            .value_name("SECONDS")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .help("Give up on a host after this long"),
+        Arg::new("retries")
+            .long("retries")
+            .value_name("N")
+            .default_value("0")
+            .value_parser(clap::value_parser!(u32))
+            .help("Retry a host whose command never started, e.g. after a connection error"),
+        Arg::new("retry-delay")
+            .long("retry-delay")
+            .value_name("SECONDS")
+            .default_value("1")
+            .value_parser(clap::value_parser!(u64))
+            .help("Pause before each retry"),
+    ]
+}
+
+/// The settings of [`parallel_args`]
+fn exec_options(sub: &clap::ArgMatches) -> multi_exec::ExecOptions {
+    multi_exec::ExecOptions {
+        max_parallel: *sub.get_one::<u32>("max-parallel").unwrap() as usize,
+        timeout: sub.get_one::<u64>("timeout").map(|secs| std::time::Duration::from_secs(*secs)),
+        retries: *sub.get_one::<u32>("retries").unwrap(),
+        retry_delay: std::time::Duration::from_secs(*sub.get_one::<u64>("retry-delay").unwrap()),
+    }
+}
+
+/// `-p` and `-i` for subcommands that open their own connection
+fn connection_args() -> [Arg; 2] {
+    [
+        Arg::new("port")
+            .short('p')
+            .long("port")
+            .help("SSH port (default: 22)"),
+        Arg::new("identity")
+            .short('i')
+            .long("identity")
+            .help("Private key file or stored key name; repeat to try several in order")
+            .action(clap::ArgAction::Append),
+    ]
+}
+
+/// Connection settings for a subcommand: its `-p`/`-i` flags, then the target's profile
+fn subcommand_connect_options(target: &str, sub: &clap::ArgMatches) -> Result<native::ConnectOptions> {
+    let username_arg = sub.try_get_one::<String>("username").ok().flatten();
+    let profile = load_profile(target, username_arg)?;
+    let (username, alias, port_in_target) = parse_target(target, username_arg.or(profile.user.as_ref()))?;
+    let port_flag = sub.get_one::<String>("port").map(|port| port.parse::<u16>()).transpose();
+    let port_flag = port_flag.context("Invalid port number")?;
+    let port = target_port(port_in_target, port_flag).or(port_flag).or(profile.port).unwrap_or(22);
+    let host = profile.host_name.clone().unwrap_or_else(|| alias.clone());
+    let tokens = ssh_config::Tokens::new(&host, &alias, port, &username);
+    let identities = identities(sub.get_many::<String>("identity"), &[], &profile, &tokens)?;
+
+    Ok(native::ConnectOptions {
+        host,
+        port,
+        username,
+        identities,
+        identities_only: profile.identities_only.unwrap_or(false),
+        use_password: false,
+        show_banner: true,
+        pre_connect: pre_connect::PreConnect::from_profile(&profile)?,
+        hooks: lifecycle_hooks::LifecycleHooks::from_profile(&profile),
+        shell_init: None,
+        probe_remote: false,
+        retry: profile.retry_policy(),
+        auto_forward: None,
+        local_forwards: Vec::new(),
+        udp_forwards: Vec::new(),
+        remote_forwards: Vec::new(),
+        any_remote_port: false,
+        latency: None,
+        socket: profile.socket_options()?,
+        session_cache: (sub.try_get_one::<bool>("session-cache").ok().flatten() == Some(&true))
+            .then(|| target.to_string()),
+        record_recent: None,
+        terminal: terminal::TerminalOverrides::default(),
+        dump_io: None,
+        control_fifo: None,
+        cancel: None,
+    })
+}
+
+/// `bxssh diagnose`: print each finding with its advice; exits with status 1 when
+/// a check failed
+fn handle_diagnose(sub: &clap::ArgMatches) -> Result<()> {
+    let options = subcommand_connect_options(sub.get_one::<String>("target").unwrap(), sub)?;
+    let timeout = std::time::Duration::from_secs(*sub.get_one::<u64>("timeout").unwrap());
+    let target = format!("{}@{}", options.username, remote_target::host_port(&options.host, options.port));
+    ui::status(ui::Icon::Session, &tr(Msg::Diagnosing, &[&target]));
+
+    let findings = native::diagnose(&options, timeout)?;
+    for finding in &findings {
+        let icon = match finding.status {
+            diagnose::Status::Ok => ui::Icon::Success,
+            diagnose::Status::Warn => ui::Icon::Warning,
+            diagnose::Status::Fail => ui::Icon::Failure,
+            diagnose::Status::Skipped => ui::Icon::Skipped,
+        };
+        println!("{}", ui::message(icon, &format!("{}: {}", diagnose::title(finding.check), finding.detail)));
+        if let Some(advice) = &finding.advice {
+            println!("   {}", ui::message(ui::Icon::Hint, advice));
+        }
+    }
+
+    let count = |status| findings.iter().filter(|finding| finding.status == status).count();
+    let (failures, warnings) = (count(diagnose::Status::Fail), count(diagnose::Status::Warn));
+    if failures + warnings == 0 {
+        ui::status(ui::Icon::Success, &tr(Msg::NoProblems, &[]));
+    } else {
+        ui::status(ui::Icon::Hint, &tr(Msg::ProblemsFound, &[&failures, &warnings]));
+    }
+    if failures > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn handle_multi_exec(sub: &clap::ArgMatches) -> Result<()> {
+    let mut hosts: Vec<String> = sub.get_many::<String>("host").into_iter().flatten().cloned().collect();
+    if let Some(file) = sub.get_one::<String>("hosts-file") {
+        hosts.extend(multi_exec::read_hosts_file(std::path::Path::new(file))?);
+    }
+    if hosts.is_empty() {
+        return Err(anyhow::anyhow!("No hosts given. Use -H user@host or --hosts-file FILE"));
     }
 
-    if matches.get_flag("list-keys") {
-        return handle_list_keys();
+    let mut targets = std::collections::HashMap::new();
+    for host in &hosts {
+        let mut options = subcommand_connect_options(host, sub)?;
+        // One banner per host would drown the output
+        options.show_banner = false;
+        targets.insert(host.clone(), options);
     }
 
-    // Parse connection target (user@host or host)
-    let target = matches.get_one::<String>("target");
-    let username_arg = matches.get_one::<String>("username");
-    
-    // Check for common mistake: using -u with user@host format
-    if target.is_none() {
-        if let Some(username) = username_arg {
-            if username.contains('@') {
-                eprintln!("Error: Invalid usage detected");
-                eprintln!("You used: bxssh -u '{}'", username);
-                eprintln!("Correct usage:");
-                eprintln!("  bxssh {}           (new format)", username);
-                eprintln!("  bxssh -u user host  (old format)");
-                std::process::exit(1);
-            }
-        }
-        
-        eprintln!("Error: Target host is required for SSH connections");
-        eprintln!("Usage: bxssh user@host  OR  bxssh -u user host");
-        eprintln!("Use --help for more information");
+    let command = sub.get_many::<String>("command").unwrap().cloned().collect::<Vec<_>>().join(" ");
+    let results = multi_exec::run_all(
+        &hosts,
+        &command,
+        std::sync::Arc::new(native::SshHostRunner { targets }),
+        std::sync::Arc::new(multi_exec_output(sub, &hosts)),
+        &exec_options(sub),
+    );
+
+    eprint!("\n{}", multi_exec::summary_table(&results));
+    if let Some(path) = sub.get_one::<String>("json-report") {
+        multi_exec::write_report(std::path::Path::new(path), &command, &results)?;
+    }
+
+    if results.iter().any(|result| result.status != multi_exec::HostStatus::Ok) {
         std::process::exit(1);
     }
-    
-    let target = target.unwrap();
-    let (username, host) = parse_target(target, username_arg)?;
-    
-    // Debug log to show what was parsed
-    log::info!("Parsed target: username='{}', host='{}'", username, host);
-    let port = matches
-        .get_one::<String>("port")
-        .unwrap()
-        .parse::<u16>()
-        .context("Invalid port number")?;
-    let identity = matches.get_one::<String>("identity");
-    let command = matches.get_one::<String>("command");
-    let use_password = matches.get_flag("password");
+    Ok(())
+}
 
-    info!("Connecting to {}@{}:{}", username, host, port);
+/// `bxssh push`: upload one file to every host of a group at once
+fn handle_push(sub: &clap::ArgMatches) -> Result<()> {
+    let local = std::path::PathBuf::from(sub.get_one::<String>("file").unwrap());
+    anyhow::ensure!(local.is_file(), "{} is not a file", local.display());
+    let (hosts, mut path) = parse_group_path(sub.get_one::<String>("remote").unwrap())?;
+    // A directory keeps the file's name
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str(&local.file_name().unwrap_or_default().to_string_lossy());
+    }
 
-    #[cfg(target_arch = "wasm32")]
-    {
-        wasm::connect(host, port, username, identity, command)
+    let runner = native::PushRunner { targets: transfer_targets(&hosts, sub)?, local, verify: sub.get_flag("verify") };
+    run_transfers(sub, &hosts, &path, std::sync::Arc::new(runner))
+}
+
+/// `bxssh pull`: download the same file from every host of a group at once
+fn handle_pull(sub: &clap::ArgMatches) -> Result<()> {
+    let (hosts, path) = parse_group_path(sub.get_one::<String>("remote").unwrap())?;
+    let local = sub.get_one::<String>("local").unwrap().clone();
+    if hosts.len() > 1 && !local.contains("{host}") {
+        let hint = "e.g. ./logs/{host}.log";
+        return Err(anyhow::anyhow!("Put {{host}} in the local name ({}), or every host writes {}", hint, local));
     }
+    native::check_pull_destinations(&local, &hosts)?;
+    let runner = native::PullRunner { targets: transfer_targets(&hosts, sub)?, local, verify: sub.get_flag("verify") };
+    run_transfers(sub, &hosts, &path, std::sync::Arc::new(runner))
+}
 
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        native::connect(&host, port, &username, identity, command, use_password)
+/// Connection settings for each of `hosts` of `push` and `pull`
+fn transfer_targets(
+    hosts: &[String],
+    sub: &clap::ArgMatches,
+) -> Result<std::collections::HashMap<String, native::ConnectOptions>> {
+    let mut targets = std::collections::HashMap::new();
+    for host in hosts {
+        let mut options = subcommand_connect_options(host, sub)?;
+        options.show_banner = false;
+        targets.insert(host.clone(), options);
+    }
+    Ok(targets)
+}
+
+/// Transfer `path` on every host through `runner`, then print the summary table and
+/// exit non-zero if any host failed
+fn run_transfers(
+    sub: &clap::ArgMatches,
+    hosts: &[String],
+    path: &str,
+    runner: std::sync::Arc<dyn multi_exec::HostRunner>,
+) -> Result<()> {
+    let output = std::sync::Arc::new(multi_exec_output(sub, hosts));
+    let results = multi_exec::run_all(hosts, path, runner, output, &exec_options(sub));
+    eprint!("\n{}", multi_exec::summary_table(&results));
+    if results.iter().any(|result| result.status != multi_exec::HostStatus::Ok) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Hosts and path of `@group:path`, the hosts whose profiles list the group in `Groups`,
+/// or of a single `[user@]host:path`
+fn parse_group_path(remote: &str) -> Result<(Vec<String>, String)> {
+    let Some(spec) = remote.strip_prefix('@') else {
+        let (target, path) = parse_remote_path(remote)?;
+        return Ok((vec![target], path));
+    };
+    let (group, path) = spec
+        .split_once(':')
+        .filter(|(group, _)| !group.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Invalid remote '{}'. Use @group:/path", remote))?;
+    let hosts = profiles::Profiles::load()?.group_members(group);
+    if hosts.is_empty() {
+        return Err(anyhow::anyhow!("No hosts in group '{}'. Add Groups = [\"{}\"] to their profiles", group, group));
     }
+    Ok((hosts, path.to_string()))
 }
 
-/// Parse target string to extract username and host
-/// Supports both "user@host" and just "host" (with -u flag)
-fn parse_target(target: &str, username_arg: Option<&String>) -> Result<(String, String)> {
-    if target.contains('@') {
-        // Parse user@host format
-        let parts: Vec<&str> = target.split('@').collect();
-        if parts.len() != 2 {
-            return Err(anyhow::anyhow!("Invalid target format. Use 'user@host'"));
+/// Host-tagged stdout for `exec` and `push`, colored when stdout is a terminal,
+/// `NO_COLOR` is unset and `--plain` wasn't given
+fn multi_exec_output(sub: &clap::ArgMatches, hosts: &[String]) -> multi_exec::PrefixedOutput<std::io::Stdout> {
+    use std::io::IsTerminal;
+
+    let flag = |id| sub.try_get_one::<bool>(id).ok().flatten() == Some(&true);
+    let mut output = multi_exec::PrefixedOutput::new(std::io::stdout()).grouped(flag("group-output"));
+    if flag("quiet-hosts") && hosts.len() == 1 {
+        output = output.without_prefix();
+    }
+    if std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none() && !sub.get_flag("plain") {
+        output = output.colored(hosts);
+    }
+    output
+}
+
+/// Split `[user@]host:path` or `ssh://[user@]host[:port]/path` into target and remote path;
+/// an empty path is the login directory
+fn parse_remote_path(remote: &str) -> Result<(String, String)> {
+    let remote = remote_target::RemotePath::parse(remote)?;
+    Ok((remote.target.to_string(), remote.path))
+}
+
+#[cfg(all(feature = "fuse", unix))]
+fn handle_mount(sub: &clap::ArgMatches) -> Result<()> {
+    let (target, remote_path) = parse_remote_path(sub.get_one::<String>("remote").unwrap())?;
+    let mountpoint = std::path::Path::new(sub.get_one::<String>("mountpoint").unwrap());
+    if !mountpoint.is_dir() {
+        return Err(anyhow::anyhow!("Mount point {} is not a directory", mountpoint.display()));
+    }
+
+    let options = subcommand_connect_options(&target, sub)?;
+    let source = format!("{}:{}", remote_target::bracketed(&options.host), remote_path);
+    ui::status(ui::Icon::Mount, &tr(Msg::Mounting, &[&source, &mountpoint.display()]));
+    ui::status(ui::Icon::Hint, &tr(Msg::UnmountHint, &[&mountpoint.display()]));
+    native::mount(&options, &remote_path, mountpoint)
+}
+
+#[cfg(not(all(feature = "fuse", unix)))]
+fn handle_mount(_sub: &clap::ArgMatches) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "This bxssh was built without FUSE support; rebuild with `--features fuse`"
+    ))
+}
+
+/// `bxssh discover`: list SSH servers on the LAN, numbered for `--connect N`
+#[cfg(feature = "discovery")]
+fn handle_discover(sub: &clap::ArgMatches) -> Result<()> {
+    let timeout = std::time::Duration::from_secs(*sub.get_one::<u64>("timeout").unwrap());
+    let hosts = discovery::browse(timeout)?;
+
+    let Some(&index) = sub.get_one::<u32>("connect") else {
+        if hosts.is_empty() {
+            ui::status(ui::Icon::Empty, &tr(Msg::NoHostsFound, &[]));
+            return Ok(());
         }
-        
-        let username = parts[0].to_string();
-        let host = parts[1].to_string();
-        
-        if username.is_empty() || host.is_empty() {
-            return Err(anyhow::anyhow!("Username and host cannot be empty"));
+        ui::status(ui::Icon::Relay, &tr(Msg::HostsFound, &[]));
+        for (i, host) in hosts.iter().enumerate() {
+            let addresses: Vec<String> = host.addresses.iter().map(|address| address.to_string()).collect();
+            ui::item(&format!("{:>2}  {}  {}:{}  {}", i + 1, host.name, host.host, host.port, addresses.join(" ")));
         }
-        
-        // If -u flag was also provided, check for common mistakes
-        if let Some(flag_user) = username_arg {
-            if flag_user.contains('@') {
-                return Err(anyhow::anyhow!(
-                    "Invalid usage: Don't use -u flag with user@host format.\n\
-                     Use either: 'bxssh {}' OR 'bxssh -u {} {}'", 
-                    target, username, host
-                ));
+        ui::status(ui::Icon::Hint, &tr(Msg::DiscoverConnectHint, &[]));
+        return Ok(());
+    };
+
+    let host = hosts
+        .get(index as usize - 1)
+        .ok_or_else(|| anyhow::anyhow!("No host number {}; {} found", index, hosts.len()))?;
+    let username = match sub.get_one::<String>("username") {
+        Some(username) => username.clone(),
+        None => std::env::var(config::USER_ENV).context("No -u given and the local user name is unknown")?,
+    };
+    let mut options = subcommand_connect_options(&format!("{}@{}", username, host.connect_host()), sub)?;
+    if sub.get_one::<String>("port").is_none() {
+        options.port = host.port;
+    }
+    options.record_recent = Some(host.connect_host());
+    native::connect(&options)
+}
+
+#[cfg(not(feature = "discovery"))]
+fn handle_discover(_sub: &clap::ArgMatches) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "This bxssh was built without LAN discovery; rebuild with `--features discovery`"
+    ))
+}
+
+/// `bxssh recent`: recent connections, then named profiles, numbered; connects to
+/// the entry given or picked at the prompt
+fn handle_recent(sub: &clap::ArgMatches) -> Result<()> {
+    use std::io::{IsTerminal, Write};
+
+    let path = recent::RecentList::path()?;
+    if sub.get_flag("clear") {
+        recent::RecentList::default().save_to(&path)?;
+        ui::status(ui::Icon::Success, &tr(Msg::RecentCleared, &[]));
+        return Ok(());
+    }
+
+    let choices = recent::choices(&recent::RecentList::load_from(&path)?, &profiles::Profiles::load()?.host_names());
+    let number = match sub.get_one::<u32>("number") {
+        Some(&number) => number as usize,
+        None if choices.is_empty() => {
+            ui::status(ui::Icon::Empty, &tr(Msg::NoRecent, &[]));
+            return Ok(());
+        }
+        None => {
+            ui::status(ui::Icon::Session, &tr(Msg::RecentConnections, &[]));
+            for (i, choice) in choices.iter().enumerate() {
+                let (name, detail) = match choice {
+                    recent::Choice::Recent(connection) => {
+                        (connection.label(), connection.last_connected.format("%Y-%m-%d %H:%M UTC").to_string())
+                    }
+                    recent::Choice::Profile(name) => (name.clone(), tr(Msg::ProfileLabel, &[])),
+                };
+                ui::item(&format!("{:>2}  {}  {}", i + 1, name, detail));
+            }
+            if sub.get_flag("list") || !std::io::stdin().is_terminal() {
+                return Ok(());
             }
-            if flag_user != &username {
-                eprintln!("⚠️  Warning: Username from target '{}' overrides -u flag '{}'", username, flag_user);
+
+            print!("{}", tr(Msg::RecentPrompt, &[&choices.len()]));
+            std::io::stdout().flush()?;
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            match parse_pick(&input, choices.len())? {
+                Some(number) => number,
+                None => return Ok(()),
             }
         }
-        
-        Ok((username, host))
-    } else {
+    };
+
+    let choice = choices
+        .get(number - 1)
+        .ok_or_else(|| anyhow::anyhow!("No entry number {}; {} listed", number, choices.len()))?;
+    let (target, host, port) = match choice {
+        recent::Choice::Recent(connection) => {
+            let user = sub.get_one::<String>("username").unwrap_or(&connection.user);
+            (format!("{}@{}", user, connection.host), connection.host.clone(), Some(connection.port))
+        }
+        recent::Choice::Profile(name) => (name.clone(), name.clone(), None),
+    };
+    let mut options = subcommand_connect_options(&target, sub)?;
+    if let (Some(port), None) = (port, sub.get_one::<String>("port")) {
+        options.port = port;
+    }
+    options.record_recent = Some(host);
+    native::connect(&options)
+}
+
+/// The entry number typed at the `bxssh recent` prompt; `None` for an empty line
+fn parse_pick(input: &str, count: usize) -> Result<Option<usize>> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    match input.parse::<usize>() {
+        Ok(number) if (1..=count).contains(&number) => Ok(Some(number)),
+        _ => Err(anyhow::anyhow!("Not an entry number: '{}'. Pick 1 to {}", input, count)),
+    }
+}
+
+/// Parse target string to extract username, host and port. Supports "user@host",
+/// "host:port", "[::1]:2222" and "ssh://user@host:port", or just "host" (with -u flag)
+fn parse_target(target: &str, username_arg: Option<&String>) -> Result<(String, String, Option<u16>)> {
+    let parsed = remote_target::Target::parse(target)?;
+    match parsed.user {
+        Some(username) => {
+            let host = parsed.host;
+            // If -u flag was also provided, check for common mistakes
+            if let Some(flag_user) = username_arg {
+                if flag_user.contains('@') {
+                    return Err(anyhow::anyhow!(
+                        "Invalid usage: Don't use -u flag with user@host format.\n\
+                         Use either: 'bxssh {}' OR 'bxssh -u {} {}'",
+                        target, username, host
+                    ));
+                }
+                if flag_user != &username {
+                    ui::warn(&tr(Msg::UsernameOverride, &[&username, flag_user]));
+                }
+            }
+            Ok((username, host, parsed.port))
+        }
         // Just host provided, username must come from -u flag
-        if let Some(username) = username_arg {
-            Ok((username.clone(), target.to_string()))
-        } else {
-            Err(anyhow::anyhow!(
+        None => match username_arg {
+            Some(username) => Ok((username.clone(), parsed.host, parsed.port)),
+            None => Err(anyhow::anyhow!(
                 "Username is required. Use 'user@host' format or provide -u flag with hostname"
-            ))
+            )),
+        },
+    }
+}
+
+/// The port given in the target, which wins over a differing `-p`
+fn target_port(port: Option<u16>, flag_port: Option<u16>) -> Option<u16> {
+    if let (Some(port), Some(flag_port)) = (port, flag_port) {
+        if port != flag_port {
+            ui::warn(&tr(Msg::PortOverride, &[&port.to_string(), &flag_port.to_string()]));
+        }
+    }
+    port
+}
+
+/// Settings taken from OpenSSH-style `-o Key=Value` options
+/// Explicit bxssh flags (-u, -p, -i) take precedence over these
+#[derive(Debug, Default, PartialEq)]
+struct SshOptions {
+    user: Option<String>,
+    port: Option<u16>,
+    /// Every `IdentityFile`, in order
+    identity_files: Vec<String>,
+    identities_only: Option<bool>,
+    /// `LogLevel` of QUIET, FATAL or ERROR hides the server banner, as in OpenSSH
+    suppress_banner: bool,
+    connection_attempts: Option<u32>,
+    /// `IPQoS`, checked when the connection options are built
+    ip_qos: Option<String>,
+}
+
+/// Parse `-o` options in either `Key=Value` or `Key Value` form.
+/// Keys are case-insensitive; options bxssh has no equivalent for are ignored
+/// so tools like git can pass e.g. `-o SendEnv=GIT_PROTOCOL` unchanged.
+fn parse_ssh_options<'a>(options: impl IntoIterator<Item = &'a String>) -> Result<SshOptions> {
+    let mut parsed = SshOptions::default();
+
+    for option in options {
+        let (key, value) = option
+            .split_once('=')
+            .or_else(|| option.trim().split_once(char::is_whitespace))
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .ok_or_else(|| anyhow::anyhow!("Invalid option '{}'. Use 'Key=Value'", option))?;
+
+        if key.is_empty() || value.is_empty() {
+            return Err(anyhow::anyhow!("Invalid option '{}'. Use 'Key=Value'", option));
+        }
+
+        match key.to_ascii_lowercase().as_str() {
+            "user" => parsed.user = Some(value.to_string()),
+            "port" => {
+                parsed.port = Some(value.parse::<u16>().context("Invalid port number")?)
+            }
+            "identityfile" => parsed.identity_files.push(value.to_string()),
+            "identitiesonly" => parsed.identities_only = Some(value.eq_ignore_ascii_case("yes")),
+            "connectionattempts" => {
+                parsed.connection_attempts = Some(value.parse::<u32>().context("Invalid ConnectionAttempts")?)
+            }
+            "ipqos" => parsed.ip_qos = Some(value.to_string()),
+            "loglevel" => {
+                parsed.suppress_banner = matches!(
+                    value.to_ascii_lowercase().as_str(),
+                    "quiet" | "fatal" | "error"
+                )
+            }
+            _ => log::debug!("Ignoring unsupported option: {}", option),
         }
     }
+
+    Ok(parsed)
+}
+
+/// Parse a `host:port` forwarding destination as used by `-W`
+/// IPv6 literals must be bracketed, e.g. `[::1]:22` or `[fe80::1%eth0]:22`
+fn parse_forward_target(spec: &str) -> Result<(String, u16)> {
+    let invalid = || anyhow::anyhow!("Invalid forward target '{}'. Use 'host:port'", spec);
+    let target = remote_target::Target::parse(spec).with_context(invalid)?;
+    match (target.user, target.port) {
+        (None, Some(port)) if !spec.contains("://") => Ok((target.host, port)),
+        _ => Err(invalid()),
+    }
+}
+
+/// The forwards of the command line
+#[cfg(not(target_arch = "wasm32"))]
+struct ForwardSpecs {
+    local: Vec<local_forward::LocalForwardSpec>,
+    udp: Vec<udp_forward::UdpForwardSpec>,
+    remote: Vec<remote_forward::RemoteForwardSpec>,
+}
+
+/// `-L`, `--udp-forward` and `-R`, refusing binds beyond loopback without `--gateway-ports`
+#[cfg(not(target_arch = "wasm32"))]
+fn forward_specs(matches: &clap::ArgMatches) -> Result<ForwardSpecs> {
+    let gateway_ports = matches.get_flag("gateway-ports");
+    let specs = |id: &str| matches.get_many::<String>(id).into_iter().flatten();
+    let mut local = Vec::new();
+    for spec in specs("local-forward") {
+        let spec = local_forward::LocalForwardSpec::parse(spec)?;
+        forwards::check_bind(&spec.bind_address, gateway_ports).with_context(|| format!("In -L {}", spec))?;
+        local.push(spec);
+    }
+    let mut udp = Vec::new();
+    for spec in specs("udp-forward") {
+        let spec = udp_forward::UdpForwardSpec::parse(spec)?;
+        forwards::check_bind(&spec.bind_address, gateway_ports).with_context(|| format!("In --udp-forward {}", spec))?;
+        udp.push(spec);
+    }
+    let mut remote = Vec::new();
+    for spec in specs("remote-forward") {
+        let spec = remote_forward::RemoteForwardSpec::parse(spec)?;
+        forwards::check_bind(&spec.bind_address, gateway_ports).with_context(|| format!("In -R {}", spec))?;
+        remote.push(spec);
+    }
+    Ok(ForwardSpecs { local, udp, remote })
+}
+
+/// `--format text|json` for key listings
+fn key_format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .value_name("FORMAT")
+        .value_parser(["text", "json"])
+        .default_value("text")
+        .help("Key listing format: text, or json with fingerprints and authorized_keys lines")
+}
+
+/// How `key list` and `--list-keys` print keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyFormat {
+    Text,
+    /// An array of key summaries, for scripts
+    Json,
+}
+
+fn key_format(matches: &clap::ArgMatches) -> KeyFormat {
+    match matches.get_one::<String>("format").map(String::as_str) {
+        Some("json") => KeyFormat::Json,
+        _ => KeyFormat::Text,
+    }
+}
+
+/// `--tag` of `key list` and `--list-keys`
+fn key_tag_arg() -> Arg {
+    Arg::new("tag").long("tag").value_name("TAG").help("Only list keys with this tag")
+}
+
+/// `--comment` and `--tag` of `key generate` and `key label`
+fn key_label_args() -> [Arg; 2] {
+    [
+        Arg::new("comment")
+            .long("comment")
+            .value_name("TEXT")
+            .help("Comment on the public key, e.g. work-laptop (default: bxssh-generated)"),
+        Arg::new("tag")
+            .long("tag")
+            .value_name("TAG")
+            .help("Tag for filtering with `key list --tag` (repeatable)")
+            .action(clap::ArgAction::Append),
+    ]
+}
+
+fn key_labels(matches: &clap::ArgMatches) -> key_manager::KeyLabels {
+    key_manager::KeyLabels {
+        comment: matches.get_one::<String>("comment").cloned(),
+        tags: matches.get_many::<String>("tag").unwrap_or_default().cloned().collect(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn expires_arg() -> Arg {
+    Arg::new("expires")
+        .long("expires")
+        .value_name("YYYY-MM-DD")
+        .help("Date after which the key should no longer be used")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_expiry_arg(matches: &clap::ArgMatches) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+    matches
+        .get_one::<String>("expires")
+        .map(|date| parse_expiry(date))
+        .transpose()
 }
 
-fn handle_generate_key(key_name: &str) -> Result<()> {
+/// Parse a `YYYY-MM-DD` expiry date as midnight UTC
+fn parse_expiry(date: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .with_context(|| format!("Invalid expiry date '{}'. Use YYYY-MM-DD", date))?;
+    Ok(date.and_time(chrono::NaiveTime::MIN).and_utc())
+}
+
+fn handle_generate_key(
+    key_name: &str,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    labels: &key_manager::KeyLabels,
+) -> Result<()> {
     use key_manager::KeyManager;
     
     let mut key_manager = KeyManager::new()
         .context("Failed to initialize key manager")?;
     
-    match key_manager.generate_ed25519_key(key_name) {
+    match key_manager.generate_labelled_key(key_name, expires_at, labels) {
         Ok(key) => {
-            println!("✅ Generated SSH key pair: {}", key.name);
-            println!("📋 Public key:\n{}", key.public_key);
-            println!("\n💡 Copy the public key above to your server's ~/.ssh/authorized_keys file");
-            println!("🔑 Key stored securely in ~/.bxssh/keys.json");
+            ui::status(ui::Icon::Success, &tr(Msg::GeneratedKey, &[&key.name]));
+            ui::status(ui::Icon::PublicKey, &tr(Msg::PublicKey, &[]));
+            println!("{}", key.public_key);
+            ui::status(ui::Icon::Hint, &tr(Msg::CopyPublicKeyHint, &[]));
+            ui::status(ui::Icon::Key, &tr(Msg::KeyStored, &[&key_manager.key_path(key_name).display()]));
         }
         Err(e) => {
-            eprintln!("❌ Failed to generate key: {}", e);
+            ui::error(&tr(Msg::GenerateKeyFailed, &[&e]));
             std::process::exit(1);
         }
     }
@@ -208,28 +2110,250 @@ fn handle_generate_key(key_name: &str) -> Result<()> {
     Ok(())
 }
 
-fn handle_list_keys() -> Result<()> {
+fn handle_list_keys(verbose: bool, format: KeyFormat, tag: Option<&String>) -> Result<()> {
     use key_manager::KeyManager;
     
     let key_manager = KeyManager::new()
         .context("Failed to initialize key manager")?;
     
-    let keys = key_manager.list_keys();
+    let mut keys = key_manager.list_keys();
+    if let Some(tag) = tag {
+        keys.retain(|key| key.has_tag(tag));
+    }
+
+    if format == KeyFormat::Json {
+        // An unreadable key is left out, as the text listing leaves out its authorized_keys line
+        let summaries = keys
+            .iter()
+            .filter_map(|key| {
+                key.summary().map_err(|e| log::warn!("Skipping key '{}': {:#}", key.name, e)).ok()
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&summaries)?);
+        return Ok(());
+    }
     
     if keys.is_empty() {
-        println!("📭 No SSH keys found");
-        println!("💡 Generate a new key with: bxssh --generate-key <name>");
+        ui::status(ui::Icon::Empty, &tr(Msg::NoKeys, &[]));
+        ui::status(ui::Icon::Hint, &tr(Msg::GenerateKeyHint, &[]));
     } else {
-        println!("🔑 Available SSH keys:");
+        ui::status(ui::Icon::Key, &tr(Msg::AvailableKeys, &[]));
+        let max_age_days = config::SshConfig::load()?.key_max_age_days;
+        let date = |d: Option<chrono::DateTime<chrono::Utc>>| {
+            d.map_or(tr(Msg::Unknown, &[]), |d| d.format("%Y-%m-%d %H:%M UTC").to_string())
+        };
         for key in keys {
-            println!("  • {} ({})", key.name, format!("{:?}", key.key_type));
+            let created = key.created_at.map_or(tr(Msg::Unknown, &[]), |d| d.format("%Y-%m-%d").to_string());
+            let fingerprint = key.fingerprint().unwrap_or_else(|e| format!("{:#}", e));
+            let (kind, bits) = (&key.key_type, key.bits());
+            let mut entry = format!("{} ({:?}, {} bits) {} {}", key.name, kind, bits, fingerprint, created);
+            if !key.default_for.is_empty() {
+                entry = format!("{} {}", entry, tr(Msg::KeyDefaultFor, &[&key.default_for.join(", ")]));
+            }
+            for tag in &key.tags {
+                entry = format!("{} #{}", entry, tag);
+            }
+            ui::item(&entry);
+            if let Ok(line) = key.authorized_keys_line() {
+                println!("      {}", line);
+            }
+            if verbose {
+                let never = || tr(Msg::Never, &[]);
+                println!("      {}", tr(Msg::KeyCreated, &[&date(key.created_at)]));
+                println!("      {}", tr(Msg::KeyLastUsed, &[&key.last_used.map_or_else(never, |d| date(Some(d)))]));
+                println!("      {}", tr(Msg::KeyUses, &[&key.use_count]));
+                println!("      {}", tr(Msg::KeyExpires, &[&key.expires_at.map_or_else(never, |d| date(Some(d)))]));
+                for warning in key.warnings(chrono::Utc::now(), max_age_days) {
+                    println!("      {}", ui::message(ui::Icon::Warning, &warning));
+                }
+            }
         }
-        println!("\n💡 Use a key with: bxssh -i <key-name> user@hostname");
+        ui::status(ui::Icon::Hint, &tr(Msg::UseKeyHint, &[]));
     }
     
     Ok(())
 }
 
+fn handle_rotate_key(key_name: &str, expires_at: Option<chrono::DateTime<chrono::Utc>>) -> Result<()> {
+    use key_manager::KeyManager;
+
+    let mut key_manager = KeyManager::new()
+        .context("Failed to initialize key manager")?;
+
+    let previous_name = key_manager.previous_name(key_name);
+    let key = key_manager
+        .rotate_key(key_name, expires_at)
+        .with_context(|| format!("Failed to rotate key '{}'", key_name))?;
+
+    ui::status(ui::Icon::Rotated, &tr(Msg::RotatedKey, &[&key.name]));
+    ui::status(ui::Icon::PublicKey, &tr(Msg::NewPublicKey, &[]));
+    println!("{}", key.public_key);
+    ui::status(ui::Icon::Hint, &tr(Msg::RotateHint, &[&previous_name]));
+
+    Ok(())
+}
+
+/// `bxssh key label`: set a key's comment and add or remove tags
+fn handle_label_key(name: &str, labels: &key_manager::KeyLabels, untag: &[String]) -> Result<()> {
+    let mut key_manager = key_manager::KeyManager::new().context("Failed to initialize key manager")?;
+    let key = key_manager.label_key(name, labels, untag)?;
+    ui::status(ui::Icon::Success, &tr(Msg::KeyLabelled, &[&key.name]));
+    ui::status(ui::Icon::PublicKey, &tr(Msg::PublicKey, &[]));
+    println!("{}", key.authorized_keys_line()?);
+    Ok(())
+}
+
+/// `bxssh key set-default`: the key used for every host, or for hosts matching `host`
+fn handle_set_default_key(name: &str, host: Option<&String>) -> Result<()> {
+    let mut key_manager = key_manager::KeyManager::new().context("Failed to initialize key manager")?;
+    key_manager.set_default(name, host.map(String::as_str))?;
+    match host {
+        Some(host) => ui::status(ui::Icon::Success, &tr(Msg::KeyDefaultSetFor, &[&name, host])),
+        None => ui::status(ui::Icon::Success, &tr(Msg::KeyDefaultSet, &[&name])),
+    }
+    Ok(())
+}
+
+/// `bxssh key authorize` / `key revoke`: add or remove a stored key's line in the
+/// server's `authorized_keys`, logging in with the usual identity
+fn handle_authorize_key(sub: &clap::ArgMatches, revoke: bool) -> Result<()> {
+    use authorized_keys::Change;
+
+    let name = sub.get_one::<String>("name").unwrap();
+    let key_manager = key_manager::KeyManager::new().context("Failed to initialize key manager")?;
+    let key = key_manager.get_key(name).ok_or_else(|| anyhow::anyhow!("Key '{}' not found", name))?;
+    let line = key.authorized_keys_line()?;
+    let options = subcommand_connect_options(sub.get_one::<String>("target").unwrap(), sub)?;
+    let target = format!("{}@{}", options.username, options.host);
+
+    let change = native::edit_authorized_keys(&options, &line, revoke)?;
+    let (icon, msg) = match change {
+        Change::Added => (ui::Icon::Success, Msg::KeyAuthorized),
+        Change::AlreadyPresent => (ui::Icon::Skipped, Msg::KeyAlreadyAuthorized),
+        Change::Removed(_) => (ui::Icon::Success, Msg::KeyRevoked),
+        Change::NotPresent => (ui::Icon::Skipped, Msg::KeyNotAuthorized),
+    };
+    ui::status(icon, &tr(msg, &[name, &target]));
+    Ok(())
+}
+
+/// Arguments for the background process: the original command line with
+/// `--detach` and `--session-name` swapped for the hidden `--daemon-session NAME`
+#[cfg(unix)]
+fn daemon_args(mut args: impl Iterator<Item = String>, name: &str) -> Vec<String> {
+    let mut daemon_args = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--detach" => {}
+            // Requires --detach, so clap would refuse it in the daemon
+            "--session-name" => {
+                args.next();
+            }
+            _ if arg.starts_with("--session-name=") => {}
+            _ => daemon_args.push(arg),
+        }
+    }
+    daemon_args.push("--daemon-session".to_string());
+    daemon_args.push(name.to_string());
+    daemon_args
+}
+
+#[cfg(unix)]
+fn handle_attach(name: Option<&String>) -> Result<()> {
+    let dir = daemon::run_dir()?;
+    let sessions = daemon::list_sessions(&dir)?;
+
+    let name = match (name, sessions.as_slice()) {
+        (Some(name), _) => name.clone(),
+        (None, [only]) => only.name.clone(),
+        (None, []) => {
+            ui::status(ui::Icon::Empty, &tr(Msg::NoSessions, &[]));
+            ui::status(ui::Icon::Hint, &tr(Msg::StartSessionHint, &[]));
+            return Ok(());
+        }
+        (None, _) => {
+            ui::status(ui::Icon::Session, &tr(Msg::DetachedSessions, &[]));
+            for session in &sessions {
+                let started = session.started_at.format("%Y-%m-%d %H:%M UTC");
+                ui::item(&tr(Msg::SessionEntry, &[&session.name, &session.target, &started]));
+            }
+            ui::status(ui::Icon::Hint, &tr(Msg::ReattachHint, &[]));
+            return Ok(());
+        }
+    };
+
+    ui::status(ui::Icon::Session, &tr(Msg::Attaching, &[&name]));
+    native::attach_session(&name)
+}
+
+/// Environment variable supplying the sync passphrase non-interactively
+#[cfg(not(target_arch = "wasm32"))]
+const SYNC_PASSPHRASE_ENV: &str = "BXSSH_SYNC_PASSPHRASE";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn sync_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(SYNC_PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = rpassword::prompt_password("Bundle passphrase: ").context("Failed to read passphrase")?;
+    if confirm {
+        let again = rpassword::prompt_password("Confirm passphrase: ").context("Failed to read passphrase")?;
+        if again != passphrase {
+            return Err(anyhow::anyhow!("Passphrases do not match"));
+        }
+    }
+    Ok(passphrase)
+}
+
+/// Environment variable holding the token relay clients must present
+#[cfg(not(target_arch = "wasm32"))]
+const RELAY_TOKEN_ENV: &str = "BXSSH_RELAY_TOKEN";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_relay(listen: &str, allow: &[&String], origins: Vec<String>) -> Result<()> {
+    let token = std::env::var(RELAY_TOKEN_ENV).ok();
+    let policy = relay::RelayPolicy::new(allow, token.clone())?.with_origins(origins);
+    let listener = std::net::TcpListener::bind(listen).with_context(|| format!("Failed to listen on {}", listen))?;
+
+    ui::status(ui::Icon::Relay, &tr(Msg::Relaying, &[&listener.local_addr()?]));
+    if token.is_none_or(|token| token.is_empty()) {
+        ui::warn(&tr(Msg::RelayWithoutToken, &[&RELAY_TOKEN_ENV]));
+    }
+    relay::serve(listener, policy)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_sync_export(file: &str) -> Result<()> {
+    let payload = sync::collect(&paths::Dirs::resolve()?)?;
+    if payload.files.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to export: no keys, profiles or known hosts found"));
+    }
+
+    let bundle = sync::encrypt(&payload, &sync_passphrase(true)?)?;
+    std::fs::write(file, bundle).with_context(|| format!("Failed to write {}", file))?;
+
+    let exported = payload.files.keys().cloned().collect::<Vec<_>>().join(", ");
+    ui::status(ui::Icon::Export, &tr(Msg::Exported, &[&exported, &file]));
+    ui::status(ui::Icon::Hint, &tr(Msg::ImportHint, &[&file]));
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_sync_import(file: &str, force: bool) -> Result<()> {
+    let bundle = std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+    let payload = sync::decrypt(&bundle, &sync_passphrase(false)?)?;
+    let summary = sync::restore(&payload, &paths::Dirs::resolve()?, force)?;
+
+    for entry in &summary.written {
+        ui::status(ui::Icon::Success, &tr(Msg::Imported, &[entry]));
+    }
+    for entry in &summary.skipped {
+        ui::status(ui::Icon::Skipped, &tr(Msg::Skipped, &[entry]));
+    }
+    Ok(())
+}
+
 #[cfg(target_arch = "wasm32")]
 fn main() {
     // WASM doesn't use main, entry point is through wasm-bindgen
@@ -241,8 +2365,7 @@ use wasm_bindgen::prelude::*;
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
 pub fn wasm_main() {
-    // Initialize logging for WASM
-    web_sys::console::log_1(&"bxssh WebAssembly module loaded".into());
+    log::debug!("bxssh WebAssembly module loaded");
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -294,7 +2417,53 @@ mod tests {
         assert_eq!(result.0, "alice");
         assert_eq!(result.1, "example.com");
     }
+
+    #[test]
+    fn test_parse_target_port_and_ipv6() {
+        assert_eq!(parse_target("user@[::1]", None).unwrap(), ("user".to_string(), "::1".to_string(), None));
+        let username = "bob".to_string();
+        let result = parse_target("example.com:2222", Some(&username)).unwrap();
+        assert_eq!(result, ("bob".to_string(), "example.com".to_string(), Some(2222)));
+        let result = parse_target("ssh://alice@[fe80::1%25eth0]:22", None).unwrap();
+        assert_eq!(result, ("alice".to_string(), "fe80::1%eth0".to_string(), Some(22)));
+        assert_eq!(target_port(Some(2222), Some(22)), Some(2222));
+        assert_eq!(target_port(None, Some(22)), None);
+    }
     
+    #[test]
+    fn test_parse_pick() {
+        assert_eq!(parse_pick("2\n", 3).unwrap(), Some(2));
+        assert_eq!(parse_pick("  \n", 3).unwrap(), None);
+        assert!(parse_pick("4", 3).is_err());
+        assert!(parse_pick("0", 3).is_err());
+        assert!(parse_pick("prod", 3).is_err());
+    }
+
+    #[test]
+    fn test_parse_group_path() {
+        assert_eq!(
+            parse_group_path("deploy@web1:/srv/").unwrap(),
+            (vec!["deploy@web1".to_string()], "/srv/".to_string())
+        );
+        assert!(parse_group_path("@:/srv").unwrap_err().to_string().contains("Use @group:/path"));
+        assert!(parse_group_path("@web").is_err());
+    }
+
+    #[test]
+    fn test_parse_remote_path() {
+        assert_eq!(
+            parse_remote_path("alice@example.com:/srv/data").unwrap(),
+            ("alice@example.com".to_string(), "/srv/data".to_string())
+        );
+        assert_eq!(parse_remote_path("prod:").unwrap().1, ".");
+        assert_eq!(
+            parse_remote_path("ssh://alice@[::1]:2222//srv").unwrap(),
+            ("alice@[::1]:2222".to_string(), "/srv".to_string())
+        );
+        assert!(parse_remote_path("example.com").is_err());
+        assert!(parse_remote_path(":/srv").is_err());
+    }
+
     #[test]
     fn test_parse_target_host_only_with_username_flag() {
         let username = "bob".to_string();
@@ -325,6 +2494,103 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Invalid target format"));
     }
     
+    #[test]
+    fn test_parse_ssh_options() {
+        let options = vec![
+            "User=git".to_string(),
+            "port 2222".to_string(),
+            "IdentityFile=~/.ssh/deploy".to_string(),
+            "IdentityFile ~/.ssh/id_old".to_string(),
+            "IdentitiesOnly=yes".to_string(),
+            "SendEnv=GIT_PROTOCOL".to_string(),
+            "ConnectionAttempts=3".to_string(),
+            "IPQoS=af21 cs1".to_string(),
+        ];
+        let parsed = parse_ssh_options(&options).unwrap();
+
+        assert_eq!(parsed.user, Some("git".to_string()));
+        assert_eq!(parsed.port, Some(2222));
+        assert_eq!(parsed.identity_files, ["~/.ssh/deploy", "~/.ssh/id_old"]);
+        assert_eq!(parsed.identities_only, Some(true));
+        assert_eq!(parsed.connection_attempts, Some(3));
+        assert_eq!(parsed.ip_qos.as_deref(), Some("af21 cs1"));
+        assert!(!parsed.suppress_banner);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_daemon_args() {
+        let args = ["--detach", "-p", "2222", "alice@example.com"].map(String::from);
+        assert_eq!(
+            daemon_args(args.into_iter(), "work"),
+            ["-p", "2222", "alice@example.com", "--daemon-session", "work"].map(String::from)
+        );
+        let args = ["--detach", "--session-name", "work", "--session-name=other", "alice@example.com"].map(String::from);
+        assert_eq!(
+            daemon_args(args.into_iter(), "work"),
+            ["alice@example.com", "--daemon-session", "work"].map(String::from)
+        );
+    }
+
+    #[test]
+    fn test_parse_expiry() {
+        let expiry = parse_expiry("2030-01-31").unwrap();
+        assert_eq!(expiry.to_rfc3339(), "2030-01-31T00:00:00+00:00");
+
+        assert!(parse_expiry("31/01/2030").is_err());
+        assert!(parse_expiry("2030-02-30").is_err());
+    }
+
+    #[test]
+    fn test_parse_ssh_options_log_level() {
+        let parsed = parse_ssh_options(&vec!["LogLevel=QUIET".to_string()]).unwrap();
+        assert!(parsed.suppress_banner);
+
+        let parsed = parse_ssh_options(&vec!["LogLevel=ERROR".to_string()]).unwrap();
+        assert!(parsed.suppress_banner);
+
+        let parsed = parse_ssh_options(&vec!["LogLevel=INFO".to_string()]).unwrap();
+        assert!(!parsed.suppress_banner);
+    }
+
+    #[test]
+    fn test_parse_ssh_options_empty() {
+        let parsed = parse_ssh_options(&Vec::new()).unwrap();
+        assert_eq!(parsed, SshOptions::default());
+    }
+
+    #[test]
+    fn test_parse_ssh_options_invalid() {
+        assert!(parse_ssh_options(&vec!["BatchMode".to_string()]).is_err());
+        assert!(parse_ssh_options(&vec!["User=".to_string()]).is_err());
+
+        let result = parse_ssh_options(&vec!["Port=abc".to_string()]);
+        assert!(result.unwrap_err().to_string().contains("Invalid port number"));
+    }
+
+    #[test]
+    fn test_parse_forward_target() {
+        let (host, port) = parse_forward_target("internal.example.com:22").unwrap();
+        assert_eq!(host, "internal.example.com");
+        assert_eq!(port, 22);
+
+        let (host, port) = parse_forward_target("[::1]:2222").unwrap();
+        assert_eq!(host, "::1");
+        assert_eq!(port, 2222);
+        assert_eq!(parse_forward_target("[fe80::1%eth0]:22").unwrap(), ("fe80::1%eth0".to_string(), 22));
+    }
+
+    #[test]
+    fn test_parse_forward_target_invalid() {
+        assert!(parse_forward_target("no-port").is_err());
+        assert!(parse_forward_target(":22").is_err());
+        assert!(parse_forward_target("host:notaport").is_err());
+        assert!(parse_forward_target("host:70000").is_err());
+        assert!(parse_forward_target("::1:22").is_err());
+        assert!(parse_forward_target("user@host:22").is_err());
+        assert!(parse_forward_target("ssh://host:22").is_err());
+    }
+
     #[test]
     fn test_parse_target_empty_username_or_host() {
         let result = parse_target("@host", None);
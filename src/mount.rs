@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyOpen, Request,
+};
+use log::{debug, info};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::ssh_client::{RemoteFile, RemoteFileAttr, RemoteFileKind, SftpSession};
+
+const ROOT_INO: u64 = 1;
+/// How long the kernel may cache attributes before asking again
+const TTL: Duration = Duration::from_secs(1);
+const BLOCK_SIZE: u32 = 4096;
+
+/// Read-only FUSE filesystem serving a remote directory over SFTP.
+/// Inode numbers are handed out on first sight of a path and kept for the mount's lifetime.
+pub struct SftpFs {
+    sftp: Box<dyn SftpSession>,
+    /// Remote path of inode `n` at index `n - 1`
+    paths: Vec<String>,
+    inodes: HashMap<String, u64>,
+    /// Remote files by the handle the kernel got when opening them, kept until released
+    files: HashMap<u64, Box<dyn RemoteFile>>,
+    next_handle: u64,
+    uid: u32,
+    gid: u32,
+}
+
+impl SftpFs {
+    pub fn new(sftp: Box<dyn SftpSession>, root: &str) -> Self {
+        let root = match root.trim_end_matches('/') {
+            "" if root.starts_with('/') => "/".to_string(),
+            "" => ".".to_string(),
+            trimmed => trimmed.to_string(),
+        };
+
+        // Present remote files as owned by the mounting user, as sshfs does
+        // with idmap=user; remote uids mean nothing locally
+        // SAFETY: getuid/getgid have no preconditions and cannot fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+        Self {
+            sftp,
+            inodes: HashMap::from([(root.clone(), ROOT_INO)]),
+            paths: vec![root],
+            files: HashMap::new(),
+            next_handle: 0,
+            uid,
+            gid,
+        }
+    }
+
+    fn path(&self, ino: u64) -> Result<String, i32> {
+        let index = ino.checked_sub(1).ok_or(libc::ENOENT)? as usize;
+        self.paths.get(index).cloned().ok_or(libc::ENOENT)
+    }
+
+    fn inode(&mut self, path: &str) -> u64 {
+        if let Some(&ino) = self.inodes.get(path) {
+            return ino;
+        }
+        self.paths.push(path.to_string());
+        let ino = self.paths.len() as u64;
+        self.inodes.insert(path.to_string(), ino);
+        ino
+    }
+
+    fn child_path(parent: &str, name: &str) -> String {
+        if parent.ends_with('/') {
+            format!("{}{}", parent, name)
+        } else {
+            format!("{}/{}", parent, name)
+        }
+    }
+
+    fn attr(&self, ino: u64, attr: &RemoteFileAttr) -> FileAttr {
+        let mtime = UNIX_EPOCH + Duration::from_secs(attr.mtime);
+        FileAttr {
+            ino,
+            size: attr.size,
+            blocks: attr.size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind: file_type(attr.kind),
+            // Read-only mount: drop the write bits the server reports
+            perm: (attr.perm & 0o7555) as u16,
+            nlink: if attr.kind == RemoteFileKind::Directory { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        }
+    }
+
+    /// Fail early when the remote root is missing or not a directory
+    pub fn check_root(&mut self) -> Result<()> {
+        let root = self.paths[0].clone();
+        let attr = self.sftp.stat(&root)?;
+        if attr.kind != RemoteFileKind::Directory {
+            return Err(anyhow::anyhow!("Remote path '{}' is not a directory", root));
+        }
+        Ok(())
+    }
+
+    fn lookup_entry(&mut self, parent: u64, name: &str) -> Result<FileAttr, i32> {
+        let path = Self::child_path(&self.path(parent)?, name);
+        let remote = self.sftp.stat(&path).map_err(|e| errno(&e))?;
+        let ino = self.inode(&path);
+        Ok(self.attr(ino, &remote))
+    }
+
+    fn getattr_entry(&mut self, ino: u64) -> Result<FileAttr, i32> {
+        let path = self.path(ino)?;
+        let remote = self.sftp.stat(&path).map_err(|e| errno(&e))?;
+        Ok(self.attr(ino, &remote))
+    }
+
+    /// Directory listing including `.` and `..`, as (inode, kind, name)
+    fn dir_entries(&mut self, ino: u64) -> Result<Vec<(u64, FileType, String)>, i32> {
+        let path = self.path(ino)?;
+        let listing = self.sftp.read_dir(&path).map_err(|e| errno(&e))?;
+
+        let parent = match path.rsplit_once('/') {
+            _ if ino == ROOT_INO => ROOT_INO,
+            Some(("", _)) => self.inode("/"),
+            Some((parent, _)) => self.inode(parent),
+            None => ROOT_INO,
+        };
+        let mut entries = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (parent, FileType::Directory, "..".to_string()),
+        ];
+        for (name, attr) in listing {
+            let child = self.inode(&Self::child_path(&path, &name));
+            entries.push((child, file_type(attr.kind), name));
+        }
+        Ok(entries)
+    }
+
+    /// Open the remote file once for all the kernel's reads until it releases the handle
+    fn open_file(&mut self, ino: u64) -> Result<u64, i32> {
+        let path = self.path(ino)?;
+        let file = self.sftp.open_reader(&path).map_err(|e| errno(&e))?;
+        self.next_handle += 1;
+        self.files.insert(self.next_handle, file);
+        Ok(self.next_handle)
+    }
+
+    fn read_file(&mut self, handle: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+        let offset = u64::try_from(offset).map_err(|_| libc::EINVAL)?;
+        let file = self.files.get_mut(&handle).ok_or(libc::EBADF)?;
+        let mut data = Vec::with_capacity(size as usize);
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.by_ref().take(u64::from(size)).read_to_end(&mut data))
+            .map_err(|e| errno(&e.into()))?;
+        Ok(data)
+    }
+}
+
+fn file_type(kind: RemoteFileKind) -> FileType {
+    match kind {
+        RemoteFileKind::Directory => FileType::Directory,
+        // Lookups stat through links, so listing them as links would disagree with getattr
+        RemoteFileKind::File | RemoteFileKind::Symlink => FileType::RegularFile,
+    }
+}
+
+/// Map an SFTP error to the errno reported to the kernel
+fn errno(error: &anyhow::Error) -> i32 {
+    debug!("SFTP request failed: {:#}", error);
+    let kind = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .map(|io| io.kind());
+    match kind {
+        Some(std::io::ErrorKind::NotFound) => libc::ENOENT,
+        Some(std::io::ErrorKind::PermissionDenied) => libc::EACCES,
+        _ => libc::EIO,
+    }
+}
+
+impl Filesystem for SftpFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_entry(parent, &name.to_string_lossy()) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.getattr_entry(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+        match self.open_file(ino) {
+            Ok(handle) => reply.opened(handle, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.read_file(fh, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.files.remove(&fh);
+        reply.ok();
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let entries = match self.dir_entries(ino) {
+            Ok(entries) => entries,
+            Err(errno) => return reply.error(errno),
+        };
+
+        for (i, (child, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // The offset passed back to us is that of the next entry
+            if reply.add(child, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Mount `remote_root` read-only at `mountpoint`; blocks until it is unmounted
+pub fn mount(sftp: Box<dyn SftpSession>, remote_root: &str, mountpoint: &Path, source: &str) -> Result<()> {
+    let mut fs = SftpFs::new(sftp, remote_root);
+    fs.check_root()?;
+
+    let options = [
+        MountOption::RO,
+        MountOption::NoDev,
+        MountOption::NoSuid,
+        MountOption::FSName(source.to_string()),
+        MountOption::Subtype("bxssh".to_string()),
+    ];
+    info!("Mounting {} at {}", source, mountpoint.display());
+    fuser::mount2(fs, mountpoint, &options)
+        .with_context(|| format!("Failed to mount at {} (is fusermount installed?)", mountpoint.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::SshClient;
+    use crate::testing::ScriptedSshServer;
+
+    fn sftp_fs(root: &str) -> SftpFs {
+        let mut client = SshClient::new(Box::new(
+            ScriptedSshServer::new()
+                .accept_key("alice")
+                .file("/srv/readme.txt", "hello world")
+                .file("/srv/logs/app.log", "started"),
+        ));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("alice", "/tmp/key").unwrap();
+        SftpFs::new(client.open_sftp().unwrap(), root)
+    }
+
+    #[test]
+    fn test_root_normalization() {
+        assert_eq!(sftp_fs("/srv/").paths[0], "/srv");
+        assert_eq!(sftp_fs("/").paths[0], "/");
+        assert_eq!(sftp_fs("").paths[0], ".");
+    }
+
+    #[test]
+    fn test_check_root() {
+        assert!(sftp_fs("/srv").check_root().is_ok());
+        assert!(sftp_fs("/srv/readme.txt").check_root().is_err());
+        assert!(sftp_fs("/missing").check_root().is_err());
+    }
+
+    #[test]
+    fn test_lookup_and_getattr() {
+        let mut fs = sftp_fs("/srv");
+
+        let attr = fs.lookup_entry(ROOT_INO, "readme.txt").unwrap();
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(attr.size, 11);
+        assert_eq!(attr.perm, 0o444);
+        assert_eq!(fs.lookup_entry(ROOT_INO, "readme.txt").unwrap().ino, attr.ino);
+
+        let logs = fs.lookup_entry(ROOT_INO, "logs").unwrap();
+        assert_eq!(logs.kind, FileType::Directory);
+        assert_eq!(logs.perm, 0o555);
+
+        assert_eq!(fs.lookup_entry(ROOT_INO, "missing").unwrap_err(), libc::ENOENT);
+        assert_eq!(fs.getattr_entry(ROOT_INO).unwrap().kind, FileType::Directory);
+        assert_eq!(fs.getattr_entry(999).unwrap_err(), libc::ENOENT);
+    }
+
+    #[test]
+    fn test_dir_entries() {
+        let mut fs = sftp_fs("/srv");
+
+        let entries = fs.dir_entries(ROOT_INO).unwrap();
+        let names: Vec<_> = entries.iter().map(|(_, _, name)| name.as_str()).collect();
+        assert_eq!(names, vec![".", "..", "logs", "readme.txt"]);
+        assert_eq!(entries[2].1, FileType::Directory);
+
+        // Listing assigns the same inodes lookup would
+        let logs = fs.lookup_entry(ROOT_INO, "logs").unwrap();
+        assert_eq!(entries[2].0, logs.ino);
+
+        let nested = fs.dir_entries(logs.ino).unwrap();
+        assert_eq!(nested[1].0, ROOT_INO);
+        assert_eq!(nested[2].2, "app.log");
+    }
+
+    #[test]
+    fn test_read_file() {
+        let mut fs = sftp_fs("/srv");
+        let ino = fs.lookup_entry(ROOT_INO, "readme.txt").unwrap().ino;
+        let handle = fs.open_file(ino).unwrap();
+
+        assert_eq!(fs.read_file(handle, 0, 5).unwrap(), b"hello");
+        assert_eq!(fs.read_file(handle, 6, 4096).unwrap(), b"world");
+        assert!(fs.read_file(handle, 64, 10).unwrap().is_empty());
+        assert_eq!(fs.read_file(handle, -1, 10).unwrap_err(), libc::EINVAL);
+        assert_eq!(fs.read_file(handle + 1, 0, 5).unwrap_err(), libc::EBADF);
+
+        let missing = fs.inode("/srv/missing");
+        assert_eq!(fs.open_file(missing).unwrap_err(), libc::ENOENT);
+    }
+}
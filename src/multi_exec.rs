@@ -0,0 +1,633 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancelToken;
+use crate::retry::RetryPolicy;
+
+/// How long a timed-out attempt gets to stop once cancelled; one still running after
+/// that isn't retried, so a host never runs the command twice at once
+const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+/// Runs one command on one host; implemented over SSH by the native backend
+pub trait HostRunner: Send + Sync + 'static {
+    /// Run `command` on `host`, passing output to `output` as it arrives, and return its exit
+    /// status. `cancel` fires when the attempt times out; the runner should then close its
+    /// connection and return.
+    fn run(&self, host: &str, command: &str, cancel: &CancelToken, output: &mut dyn FnMut(&[u8])) -> Result<i32>;
+}
+
+/// Attached by [`HostRunner`]s to errors from before the command went out, such as
+/// a failed connection or login; only such attempts are repeated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotStarted;
+
+impl std::fmt::Display for NotStarted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Command not started")
+    }
+}
+
+impl std::error::Error for NotStarted {}
+
+/// An attempt ran past [`ExecOptions::timeout`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimedOut;
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Receives output and results while hosts run
+pub trait OutputSink: Send + Sync {
+    fn output(&self, host: &str, data: &[u8]);
+    fn finished(&self, _result: &HostResult) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecOptions {
+    pub max_parallel: usize,
+    /// Per-attempt limit; a host that exceeds it is reported as timed out. The attempt
+    /// is cancelled and, before any retry, waited for.
+    pub timeout: Option<Duration>,
+    /// Extra attempts after an attempt that failed before the command started (see
+    /// [`NotStarted`]), e.g. on a connection error or a timeout while connecting
+    pub retries: u32,
+    pub retry_delay: Duration,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            max_parallel: 10,
+            timeout: None,
+            retries: 0,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostStatus {
+    Ok,
+    Failed,
+    Timeout,
+}
+
+impl std::fmt::Display for HostStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HostStatus::Ok => "ok",
+            HostStatus::Failed => "failed",
+            HostStatus::Timeout => "timeout",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HostResult {
+    pub host: String,
+    pub status: HostStatus,
+    pub exit_code: Option<i32>,
+    pub attempts: u32,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Run `command` on every host, at most `options.max_parallel` at a time.
+/// Results come back in the order of `hosts`.
+pub fn run_all(
+    hosts: &[String],
+    command: &str,
+    runner: Arc<dyn HostRunner>,
+    sink: Arc<dyn OutputSink>,
+    options: &ExecOptions,
+) -> Vec<HostResult> {
+    let queue = Arc::new(Mutex::new(hosts.iter().cloned().enumerate().collect::<VecDeque<_>>()));
+    let (results_tx, results_rx) = mpsc::channel();
+
+    let workers = options.max_parallel.clamp(1, hosts.len().max(1));
+    for _ in 0..workers {
+        let queue = Arc::clone(&queue);
+        let results_tx = results_tx.clone();
+        let runner = Arc::clone(&runner);
+        let sink = Arc::clone(&sink);
+        let command = command.to_string();
+        let options = options.clone();
+
+        std::thread::spawn(move || {
+            while let Some((index, host)) = queue.lock().ok().and_then(|mut queue| queue.pop_front()) {
+                let result = run_host(&host, &command, &runner, &sink, &options);
+                sink.finished(&result);
+                if results_tx.send((index, result)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(results_tx);
+
+    let mut results: Vec<_> = results_rx.iter().collect();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn run_host(
+    host: &str,
+    command: &str,
+    runner: &Arc<dyn HostRunner>,
+    sink: &Arc<dyn OutputSink>,
+    options: &ExecOptions,
+) -> HostResult {
+    let started = Instant::now();
+    let policy = RetryPolicy {
+        max_attempts: options.retries.saturating_add(1),
+        initial_delay: options.retry_delay,
+        multiplier: 1,
+        max_delay: options.retry_delay,
+        retryable: not_started,
+    };
+    let mut attempts = 0;
+    let result = policy.run(None, || {
+        attempts += 1;
+        run_attempt(host, command, runner, sink, options.timeout)
+    });
+
+    let (status, exit_code, error) = match result {
+        Ok(0) => (HostStatus::Ok, Some(0), None),
+        Ok(code) => (HostStatus::Failed, Some(code), None),
+        Err(e) if e.downcast_ref::<TimedOut>().is_some() => (HostStatus::Timeout, None, Some(e.to_string())),
+        Err(e) => (HostStatus::Failed, None, Some(format!("{:#}", e))),
+    };
+    HostResult {
+        host: host.to_string(),
+        status,
+        exit_code,
+        attempts,
+        duration_ms: started.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+/// Retry an attempt only when the command never started: once it has gone out, even a
+/// dropped connection may have left it running
+fn not_started(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<NotStarted>().is_some()
+}
+
+/// One attempt on its own thread, cancelled when it runs past `timeout`. The error
+/// then carries [`TimedOut`], over the runner's own when it stopped within [`CANCEL_GRACE`].
+fn run_attempt(
+    host: &str,
+    command: &str,
+    runner: &Arc<dyn HostRunner>,
+    sink: &Arc<dyn OutputSink>,
+    timeout: Option<Duration>,
+) -> Result<i32> {
+    let (tx, rx) = mpsc::channel();
+    let cancel = CancelToken::new();
+    // Silence whatever a timed-out attempt prints while it winds down
+    let abandoned = Arc::new(AtomicBool::new(false));
+    {
+        let (host, command, cancel) = (host.to_string(), command.to_string(), cancel.clone());
+        let (runner, sink, abandoned) = (Arc::clone(runner), Arc::clone(sink), Arc::clone(&abandoned));
+        std::thread::spawn(move || {
+            let result = runner.run(&host, &command, &cancel, &mut |data| {
+                if !abandoned.load(Ordering::SeqCst) {
+                    sink.output(&host, data);
+                }
+            });
+            let _ = tx.send(result);
+        });
+    }
+
+    let result = match timeout {
+        Some(timeout) => rx.recv_timeout(timeout),
+        None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+    };
+    match result {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(anyhow::anyhow!("Attempt on {} panicked", host)),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            abandoned.store(true, Ordering::SeqCst);
+            cancel.cancel();
+            match rx.recv_timeout(CANCEL_GRACE) {
+                Ok(Err(e)) => Err(e.context(TimedOut)),
+                Ok(Ok(_)) => Err(TimedOut.into()),
+                Err(_) => {
+                    log::warn!("Attempt on {} is still running after being cancelled", host);
+                    Err(TimedOut.into())
+                }
+            }
+        }
+    }
+}
+
+/// ANSI foreground colors cycled through for host prefixes
+const HOST_COLORS: [&str; 6] = ["\x1b[36m", "\x1b[33m", "\x1b[35m", "\x1b[32m", "\x1b[34m", "\x1b[31m"];
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// Prints each host's output line by line with a `[host]` prefix
+pub struct PrefixedOutput<W: Write + Send> {
+    out: Mutex<W>,
+    partial: Mutex<HashMap<String, Vec<u8>>>,
+    prefix: bool,
+    /// Hold each host's output until it finishes, then print it as one block
+    group: bool,
+    colors: HashMap<String, &'static str>,
+}
+
+impl<W: Write + Send> PrefixedOutput<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            partial: Mutex::new(HashMap::new()),
+            prefix: true,
+            group: false,
+            colors: HashMap::new(),
+        }
+    }
+
+    /// Give each of `hosts` its own prefix color, in order
+    pub fn colored(mut self, hosts: &[String]) -> Self {
+        self.colors = hosts
+            .iter()
+            .zip(HOST_COLORS.iter().cycle())
+            .map(|(host, color)| (host.clone(), *color))
+            .collect();
+        self
+    }
+
+    pub fn grouped(mut self, group: bool) -> Self {
+        self.group = group;
+        self
+    }
+
+    /// Print bare output lines without the `[host]` tag
+    pub fn without_prefix(mut self) -> Self {
+        self.prefix = false;
+        self
+    }
+
+    #[allow(dead_code)] // Used in tests
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write `lines` (newline-separated, no trailing newline) together so other hosts cannot interleave
+    fn write_lines(&self, host: &str, lines: &[u8]) {
+        let Ok(mut out) = self.out.lock() else {
+            return;
+        };
+        for line in lines.split(|&b| b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if self.prefix {
+                let _ = match self.colors.get(host) {
+                    Some(color) => write!(out, "{}[{}]{} ", color, host, COLOR_RESET),
+                    None => write!(out, "[{}] ", host),
+                };
+            }
+            let _ = out.write_all(line);
+            let _ = out.write_all(b"\n");
+        }
+        let _ = out.flush();
+    }
+}
+
+impl<W: Write + Send> OutputSink for PrefixedOutput<W> {
+    fn output(&self, host: &str, data: &[u8]) {
+        let lines = {
+            let Ok(mut partial) = self.partial.lock() else {
+                return;
+            };
+            let buffer = partial.entry(host.to_string()).or_default();
+            buffer.extend_from_slice(data);
+            if self.group {
+                return;
+            }
+
+            let Some(end) = buffer.iter().rposition(|&b| b == b'\n') else {
+                return;
+            };
+            let complete: Vec<u8> = buffer.drain(..=end).collect();
+            complete
+        };
+
+        self.write_lines(host, &lines[..lines.len() - 1]);
+    }
+
+    fn finished(&self, result: &HostResult) {
+        let rest = self
+            .partial
+            .lock()
+            .ok()
+            .and_then(|mut partial| partial.remove(&result.host))
+            .unwrap_or_default();
+        let rest = rest.strip_suffix(b"\n").unwrap_or(&rest);
+        if !rest.is_empty() {
+            self.write_lines(&result.host, rest);
+        }
+    }
+}
+
+/// Summary table with one row per host
+pub fn summary_table(results: &[HostResult]) -> String {
+    let width = results.iter().map(|r| r.host.len()).chain(["HOST".len()]).max().unwrap_or(4);
+    let mut table = format!("{:<width$}  {:<7}  {:>4}  {:>8}  {:>8}\n", "HOST", "STATUS", "EXIT", "ATTEMPTS", "TIME");
+
+    for result in results {
+        let exit_code = result.exit_code.map(|code| code.to_string()).unwrap_or_else(|| "-".to_string());
+        table.push_str(&format!(
+            "{:<width$}  {:<7}  {:>4}  {:>8}  {:>7.1}s\n",
+            result.host,
+            result.status.to_string(),
+            exit_code,
+            result.attempts,
+            result.duration_ms as f64 / 1000.0,
+        ));
+    }
+
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    table.push_str(&format!(
+        "\n{} ok, {} failed, {} timed out\n",
+        count(HostStatus::Ok),
+        count(HostStatus::Failed),
+        count(HostStatus::Timeout)
+    ));
+    table
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    command: &'a str,
+    results: &'a [HostResult],
+}
+
+/// Write results as JSON: `{"command": ..., "results": [{"host", "status", ...}]}`
+pub fn write_report(path: &Path, command: &str, results: &[HostResult]) -> Result<()> {
+    let json = serde_json::to_string_pretty(&Report { command, results })?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write report to {}", path.display()))
+}
+
+/// Hosts from a file, one target per line; blank lines and `#` comments are skipped
+pub fn read_hosts_file(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read hosts file {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use tempfile::TempDir;
+
+    /// Hosts named `fail-*` refuse connections, `drop-*` lose it mid-command, `slow-*` hang
+    /// for 2s, `hang-*` connect until cancelled, `exit3-*` exit with 3; `flaky-*` fail their
+    /// first attempt
+    #[derive(Default)]
+    struct FakeRunner {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        attempts: Mutex<HashMap<String, u32>>,
+    }
+
+    impl HostRunner for FakeRunner {
+        fn run(&self, host: &str, command: &str, cancel: &CancelToken, output: &mut dyn FnMut(&[u8])) -> Result<i32> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            let attempt = {
+                let mut attempts = self.attempts.lock().unwrap();
+                let attempt = attempts.entry(host.to_string()).or_default();
+                *attempt += 1;
+                *attempt
+            };
+
+            std::thread::sleep(Duration::from_millis(20));
+            let result = if host.starts_with("fail-") || (host.starts_with("flaky-") && attempt == 1) {
+                Err(anyhow::anyhow!("Connection refused").context(NotStarted))
+            } else if host.starts_with("drop-") {
+                Err(anyhow::anyhow!("Connection reset"))
+            } else if host.starts_with("slow-") {
+                std::thread::sleep(Duration::from_secs(2));
+                output(b"too late\n");
+                Ok(0)
+            } else if host.starts_with("hang-") {
+                while !cancel.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                cancel.check().map(|_| 0).context(NotStarted)
+            } else {
+                output(format!("{} on {}\nsecond", command, host).as_bytes());
+                Ok(if host.starts_with("exit3-") { 3 } else { 0 })
+            };
+
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+    }
+
+    fn hosts(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn options(max_parallel: usize) -> ExecOptions {
+        ExecOptions {
+            max_parallel,
+            timeout: Some(Duration::from_millis(500)),
+            retries: 0,
+            retry_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_statuses_and_order() {
+        let runner = Arc::new(FakeRunner::default());
+        let sink = Arc::new(PrefixedOutput::new(Vec::new()));
+        let results = run_all(
+            &hosts(&["web1", "fail-db", "slow-cache", "exit3-worker"]),
+            "uptime",
+            runner,
+            sink.clone(),
+            &options(4),
+        );
+
+        let statuses: Vec<_> = results.iter().map(|r| (r.host.as_str(), r.status, r.exit_code)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("web1", HostStatus::Ok, Some(0)),
+                ("fail-db", HostStatus::Failed, None),
+                ("slow-cache", HostStatus::Timeout, None),
+                ("exit3-worker", HostStatus::Failed, Some(3)),
+            ]
+        );
+        assert!(results[1].error.as_deref().unwrap().contains("Connection refused"));
+
+        // Abandoned attempts stay quiet
+        std::thread::sleep(Duration::from_secs(2));
+        let output = String::from_utf8(Arc::try_unwrap(sink).ok().unwrap().into_inner()).unwrap();
+        assert!(output.contains("[web1] uptime on web1\n"));
+        assert!(output.contains("[web1] second\n"));
+        assert!(!output.contains("too late"));
+    }
+
+    #[test]
+    fn test_max_parallel_is_respected() {
+        let runner = Arc::new(FakeRunner::default());
+        let names: Vec<String> = (0..8).map(|i| format!("host{}", i)).collect();
+        let results = run_all(&names, "true", runner.clone(), Arc::new(PrefixedOutput::new(Vec::new())), &options(3));
+
+        assert_eq!(results.len(), 8);
+        assert!(results.iter().all(|r| r.status == HostStatus::Ok));
+        let max_running = runner.max_running.load(Ordering::SeqCst);
+        assert!(max_running > 1 && max_running <= 3, "ran {} at once", max_running);
+    }
+
+    #[test]
+    fn test_retries_connection_errors_only() {
+        let runner = Arc::new(FakeRunner::default());
+        let results = run_all(
+            &hosts(&["flaky-web", "exit3-job", "drop-db"]),
+            "deploy",
+            runner,
+            Arc::new(PrefixedOutput::new(Vec::new())),
+            &ExecOptions { retries: 2, ..options(3) },
+        );
+
+        assert_eq!(results[0].status, HostStatus::Ok);
+        assert_eq!(results[0].attempts, 2);
+        assert_eq!(results[1].status, HostStatus::Failed);
+        assert_eq!(results[1].attempts, 1);
+        // The command may have run before the connection dropped
+        assert_eq!(results[2].status, HostStatus::Failed);
+        assert_eq!(results[2].attempts, 1);
+    }
+
+    #[test]
+    fn test_timed_out_attempt_ends_before_the_retry() {
+        let runner = Arc::new(FakeRunner::default());
+        let results = run_all(
+            &hosts(&["hang-web"]),
+            "deploy",
+            runner.clone(),
+            Arc::new(PrefixedOutput::new(Vec::new())),
+            &ExecOptions { retries: 1, timeout: Some(Duration::from_millis(100)), ..options(1) },
+        );
+
+        assert_eq!(results[0].status, HostStatus::Timeout);
+        assert_eq!(results[0].attempts, 2);
+        assert_eq!(runner.max_running.load(Ordering::SeqCst), 1);
+        assert_eq!(runner.running.load(Ordering::SeqCst), 0);
+    }
+
+    fn host_result(host: &str) -> HostResult {
+        HostResult {
+            host: host.to_string(),
+            status: HostStatus::Ok,
+            exit_code: Some(0),
+            attempts: 1,
+            duration_ms: 0,
+            error: None,
+        }
+    }
+
+    fn printed(sink: PrefixedOutput<Vec<u8>>) -> String {
+        String::from_utf8(sink.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_prefixed_output_interleaves_lines() {
+        let sink = PrefixedOutput::new(Vec::new());
+        sink.output("web1", b"one\r\ntw");
+        sink.output("web2", b"alpha\n");
+        sink.output("web1", b"o\n");
+        sink.output("web2", b"no newline");
+        sink.finished(&host_result("web2"));
+        sink.finished(&host_result("web1"));
+
+        assert_eq!(printed(sink), "[web1] one\n[web2] alpha\n[web1] two\n[web2] no newline\n");
+    }
+
+    #[test]
+    fn test_grouped_output() {
+        let sink = PrefixedOutput::new(Vec::new()).grouped(true);
+        sink.output("web1", b"one\n");
+        sink.output("web2", b"alpha\n");
+        sink.output("web1", b"two\n");
+        sink.finished(&host_result("web1"));
+        sink.finished(&host_result("web2"));
+
+        assert_eq!(printed(sink), "[web1] one\n[web1] two\n[web2] alpha\n");
+    }
+
+    #[test]
+    fn test_colored_and_bare_output() {
+        let sink = PrefixedOutput::new(Vec::new()).colored(&hosts(&["web1", "web2"]));
+        sink.output("web1", b"up\n");
+        sink.output("web2", b"up\n");
+        assert_eq!(printed(sink), "\x1b[36m[web1]\x1b[0m up\n\x1b[33m[web2]\x1b[0m up\n");
+
+        let sink = PrefixedOutput::new(Vec::new()).without_prefix();
+        sink.output("web1", b"up\n");
+        assert_eq!(printed(sink), "up\n");
+    }
+
+    #[test]
+    fn test_summary_and_report() {
+        let results = vec![
+            HostResult {
+                host: "web1".to_string(),
+                status: HostStatus::Ok,
+                exit_code: Some(0),
+                attempts: 1,
+                duration_ms: 1500,
+                error: None,
+            },
+            HostResult {
+                host: "db".to_string(),
+                status: HostStatus::Timeout,
+                exit_code: None,
+                attempts: 2,
+                duration_ms: 30000,
+                error: Some("Timed out".to_string()),
+            },
+        ];
+
+        let table = summary_table(&results);
+        assert!(table.contains("web1  ok"));
+        assert!(table.contains("db    timeout     -"));
+        assert!(table.contains("1 ok, 0 failed, 1 timed out"));
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("report.json");
+        write_report(&path, "uptime", &results).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(report["command"], "uptime");
+        assert_eq!(report["results"][1]["status"], "timeout");
+        assert_eq!(report["results"][0]["exit_code"], 0);
+    }
+
+    #[test]
+    fn test_read_hosts_file() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("hosts");
+        std::fs::write(&path, "# web tier\nweb1\n  deploy@web2  # canary\n\n").unwrap();
+        assert_eq!(read_hosts_file(&path).unwrap(), vec!["web1", "deploy@web2"]);
+    }
+}
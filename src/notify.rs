@@ -0,0 +1,141 @@
+//! `--notify`: a desktop notification when a remote command or a transfer finishes,
+//! with its exit status and how long it took, for jobs left running in another
+//! window. Sent with the platform's own tool: `notify-send` on Linux and the BSDs,
+//! `osascript` on macOS and PowerShell on Windows.
+use anyhow::{Context, Result};
+use log::debug;
+use std::time::{Duration, Instant};
+
+use crate::i18n::{tr, Msg};
+
+/// Shows a notification on the desktop
+#[cfg_attr(test, mockall::automock)]
+pub trait Notifier {
+    fn notify(&self, title: &str, body: &str) -> Result<()>;
+}
+
+/// [`Notifier`] using the desktop's notification service
+pub struct DesktopNotifier;
+
+impl Notifier for DesktopNotifier {
+    fn notify(&self, title: &str, body: &str) -> Result<()> {
+        // The texts go in the environment, so they need no quoting in the scripts
+        let mut command = if cfg!(target_os = "macos") {
+            let script = concat!(
+                r#"display notification (system attribute "BXSSH_BODY") "#,
+                r#"with title (system attribute "BXSSH_TITLE")"#,
+            );
+            let mut command = std::process::Command::new("osascript");
+            command.arg("-e").arg(script);
+            command
+        } else if cfg!(windows) {
+            let script = "Add-Type -AssemblyName System.Windows.Forms; \
+                $icon = New-Object System.Windows.Forms.NotifyIcon; \
+                $icon.Icon = [System.Drawing.SystemIcons]::Information; $icon.Visible = $true; \
+                $icon.ShowBalloonTip(10000, $env:BXSSH_TITLE, $env:BXSSH_BODY, 'Info'); \
+                Start-Sleep 10; $icon.Dispose()";
+            let mut command = std::process::Command::new("powershell");
+            command.args(["-NoProfile", "-Command", script]);
+            command
+        } else {
+            let mut command = std::process::Command::new("notify-send");
+            // A title or body naming a command that starts with `-` is not taken for an option
+            command.args(["--app-name=bxssh", "--", title, body]);
+            command
+        };
+        let program = command.get_program().to_string_lossy().into_owned();
+        command.env("BXSSH_TITLE", title).env("BXSSH_BODY", body).stdin(std::process::Stdio::null());
+        let not_run = || format!("Cannot send a desktop notification: {} did not run", program);
+        if cfg!(windows) {
+            // The balloon lasts only while PowerShell runs; it is left to close
+            // on its own rather than keeping bxssh from exiting
+            command.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null());
+            let child = command.spawn().with_context(not_run)?;
+            debug!("Notification shown by PowerShell (pid {})", child.id());
+            return Ok(());
+        }
+        let status = command.status().with_context(not_run)?;
+        anyhow::ensure!(status.success(), "{} failed to show the notification ({})", program, status);
+        Ok(())
+    }
+}
+
+/// A command or transfer to report on once it finishes
+pub struct Job {
+    what: String,
+    host: String,
+    started: Instant,
+}
+
+impl Job {
+    /// Start timing `what` (a command line or a remote path) on `host`
+    pub fn start(what: &str, host: &str) -> Self {
+        Self { what: what.to_string(), host: host.to_string(), started: Instant::now() }
+    }
+
+    /// Title and body for `outcome`, the exit status or the error, after `elapsed`
+    pub fn message(&self, outcome: Result<i32, &anyhow::Error>, elapsed: Duration) -> (String, String) {
+        // Long command lines would be cut off by the notification anyway
+        let what: String = match self.what.chars().count() {
+            0..=60 => self.what.clone(),
+            _ => format!("{}…", self.what.chars().take(59).collect::<String>()),
+        };
+        let elapsed = crate::terminal::format_duration(elapsed);
+        match outcome {
+            Ok(status) => {
+                let args: [&dyn std::fmt::Display; 2] = [&what, &self.host];
+                let title = tr(if status == 0 { Msg::JobFinished } else { Msg::JobFailed }, &args);
+                (title, tr(Msg::JobStatus, &[&status, &elapsed]))
+            }
+            Err(e) => (tr(Msg::JobFailed, &[&what, &self.host]), tr(Msg::JobError, &[&elapsed, &format!("{:#}", e)])),
+        }
+    }
+
+    /// Tell the desktop through `notifier` how the job ended; a notification that
+    /// cannot be shown only costs a warning
+    pub fn finish(self, notifier: &dyn Notifier, outcome: Result<i32, &anyhow::Error>) {
+        let (title, body) = self.message(outcome, self.started.elapsed());
+        debug!("Notifying: {}: {}", title, body);
+        if let Err(e) = notifier.notify(&title, &body) {
+            crate::ui::warn(&format!("{:#}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_has_status_and_duration() {
+        let job = Job::start("make release", "build1");
+        let (title, body) = job.message(Ok(0), Duration::from_secs(724));
+        assert_eq!(title, "make release on build1 finished");
+        assert_eq!(body, "Exit status 0 after 12m 04s");
+
+        let (title, body) = job.message(Ok(2), Duration::from_secs(5));
+        assert_eq!((title.as_str(), body.as_str()), ("make release on build1 failed", "Exit status 2 after 5s"));
+
+        let error = anyhow::anyhow!("Connection reset");
+        let (_, body) = job.message(Err(&error), Duration::from_secs(61));
+        assert_eq!(body, "Stopped after 1m 01s: Connection reset");
+
+        let long = Job::start(&"x".repeat(80), "build1");
+        assert!(long.message(Ok(0), Duration::ZERO).0.starts_with(&format!("{}… on", "x".repeat(59))));
+    }
+
+    #[test]
+    fn test_finish_notifies() {
+        let mut notifier = MockNotifier::new();
+        notifier
+            .expect_notify()
+            .withf(|title, body| title == "/srv/backup.tgz on nas finished" && body.starts_with("Exit status 0"))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        Job::start("/srv/backup.tgz", "nas").finish(&notifier, Ok(0));
+
+        let mut failing = MockNotifier::new();
+        failing.expect_notify().times(1).returning(|_, _| Err(anyhow::anyhow!("notify-send did not run")));
+        Job::start("true", "nas").finish(&failing, Ok(0));
+    }
+}
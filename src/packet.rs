@@ -0,0 +1,255 @@
+//! SSH binary packet codec (RFC 4253 section 6): framing with random padding,
+//! sequence numbers, encryption and MAC, and a size limit, shared by every
+//! message the pure-Rust transport sends or receives
+use crate::cipher::PacketCipher;
+use crate::ssh_wire::{NegotiatedAlgorithms, TransportSequence, MAX_PACKET_LENGTH, SSH_MSG_NEWKEYS};
+use anyhow::Result;
+
+/// Smallest packet every implementation must accept (RFC 4253 section 6.1)
+pub const MIN_MAX_PACKET_LENGTH: usize = 35000;
+
+/// Turns payloads into protected packets and back. Received bytes may arrive in
+/// any split; they are buffered until a whole packet is available.
+pub struct PacketCodec {
+    sequence: TransportSequence,
+    outgoing: PacketCipher,
+    incoming: PacketCipher,
+    /// Keys from the latest exchange, taking effect at each direction's NEWKEYS
+    pending_outgoing: Option<PacketCipher>,
+    pending_incoming: Option<PacketCipher>,
+    max_packet_length: usize,
+    buffer: Vec<u8>,
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketCodec {
+    /// A codec for the unencrypted start of a connection
+    pub fn new() -> Self {
+        Self {
+            sequence: TransportSequence::new(),
+            outgoing: PacketCipher::none(),
+            incoming: PacketCipher::none(),
+            pending_outgoing: None,
+            pending_incoming: None,
+            max_packet_length: MAX_PACKET_LENGTH,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Limit the `packet_length` field of packets in both directions
+    pub fn set_max_packet_length(&mut self, max: usize) -> Result<()> {
+        if !(MIN_MAX_PACKET_LENGTH..=MAX_PACKET_LENGTH).contains(&max) {
+            return Err(anyhow::anyhow!(
+                "Maximum packet length must be between {} and {} bytes",
+                MIN_MAX_PACKET_LENGTH,
+                MAX_PACKET_LENGTH
+            ));
+        }
+        self.max_packet_length = max;
+        Ok(())
+    }
+
+    /// Frame, encrypt and authenticate `payload` as the next outgoing packet.
+    /// Sending NEWKEYS switches to the pending outgoing keys.
+    pub fn encode(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let frame = self.outgoing.frame(payload);
+        let packet_length = frame.len() - 4;
+        if packet_length > self.max_packet_length {
+            return Err(anyhow::anyhow!("Packet length {} exceeds maximum", packet_length));
+        }
+        let seq = self.sequence.next_send();
+        let packet = self.outgoing.seal(seq, &frame);
+        if payload.first() == Some(&SSH_MSG_NEWKEYS) {
+            self.sequence.sent_newkeys();
+            if let Some(outgoing) = self.pending_outgoing.take() {
+                self.outgoing = outgoing;
+            }
+        }
+        Ok(packet)
+    }
+
+    /// Buffer bytes received from the server
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Authenticate and decrypt the next buffered packet, or `None` until all of it
+    /// has arrived. Receiving NEWKEYS switches to the pending incoming keys.
+    pub fn decode(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+        let seq = self.sequence.next_receive();
+        let size = self.incoming.packet_size(seq, &self.buffer)?;
+        if size - 4 - self.incoming.tag_len() > self.max_packet_length {
+            return Err(anyhow::anyhow!("Packet from server exceeds {} bytes", self.max_packet_length));
+        }
+        if self.buffer.len() < size {
+            return Ok(None);
+        }
+        let packet: Vec<u8> = self.buffer.drain(..size).collect();
+        let payload = self.incoming.open(seq, &packet)?;
+        let message_type = *payload.first().ok_or_else(|| anyhow::anyhow!("Empty packet from server"))?;
+        self.sequence.receive(message_type)?;
+        if message_type == SSH_MSG_NEWKEYS {
+            self.sequence.received_newkeys();
+            if let Some(incoming) = self.pending_incoming.take() {
+                self.incoming = incoming;
+            }
+        }
+        Ok(Some(payload))
+    }
+
+    /// Install keys from a completed exchange, used after the next NEWKEYS each way
+    pub fn set_pending_keys(&mut self, outgoing: PacketCipher, incoming: PacketCipher) {
+        self.pending_outgoing = Some(outgoing);
+        self.pending_incoming = Some(incoming);
+    }
+
+    /// Record the negotiated algorithms, enabling strict key exchange if agreed
+    pub fn negotiated(&mut self, algorithms: &NegotiatedAlgorithms) -> Result<()> {
+        self.sequence.negotiated(algorithms)
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.sequence.is_strict()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::key_lengths;
+    use crate::ssh_wire::{expect_new_keys, service_request, KexInit, SSH_MSG_KEXINIT, SSH_MSG_SERVICE_ACCEPT};
+
+    fn keys(cipher: &str, mac: &str) -> PacketCipher {
+        let lengths = key_lengths(cipher, mac).unwrap();
+        PacketCipher::new(cipher, mac, &vec![7; lengths.key], &vec![9; lengths.iv], &vec![11; lengths.mac]).unwrap()
+    }
+
+    /// A client and server codec pair, already switched to `cipher` with `mac`
+    fn connected(cipher: &str, mac: &str) -> (PacketCodec, PacketCodec) {
+        let (mut client, mut server) = (PacketCodec::new(), PacketCodec::new());
+        client.set_pending_keys(keys(cipher, mac), keys(cipher, mac));
+        server.set_pending_keys(keys(cipher, mac), keys(cipher, mac));
+        server.feed(&client.encode(&[SSH_MSG_NEWKEYS]).unwrap());
+        assert_eq!(server.decode().unwrap().unwrap(), [SSH_MSG_NEWKEYS]);
+        client.feed(&server.encode(&[SSH_MSG_NEWKEYS]).unwrap());
+        assert_eq!(client.decode().unwrap().unwrap(), [SSH_MSG_NEWKEYS]);
+        (client, server)
+    }
+
+    #[test]
+    fn test_round_trip_across_newkeys() {
+        for (cipher, mac) in [
+            ("chacha20-poly1305@openssh.com", ""),
+            ("aes128-gcm@openssh.com", ""),
+            ("aes256-ctr", "hmac-sha2-256-etm@openssh.com"),
+            ("aes128-ctr", "hmac-sha2-512"),
+        ] {
+            let (mut client, mut server) = connected(cipher, mac);
+            for size in [1, 100, 5000] {
+                let payload = vec![94; size];
+                server.feed(&client.encode(&payload).unwrap());
+                assert_eq!(server.decode().unwrap().unwrap(), payload, "{}", cipher);
+            }
+            assert_eq!(server.decode().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_kex_then_service_request() {
+        let (mut client, mut server) = (PacketCodec::new(), PacketCodec::new());
+        let cipher = "chacha20-poly1305@openssh.com";
+        client.set_pending_keys(keys(cipher, ""), keys(cipher, ""));
+        server.set_pending_keys(keys(cipher, ""), keys(cipher, ""));
+
+        // The client sends NEWKEYS and its service request straight after
+        let mut wire = client.encode(&[SSH_MSG_NEWKEYS]).unwrap();
+        wire.extend(client.encode(&service_request("ssh-userauth")).unwrap());
+        server.feed(&wire);
+        assert_eq!(server.decode().unwrap().unwrap(), [SSH_MSG_NEWKEYS]);
+        assert_eq!(server.decode().unwrap().unwrap(), service_request("ssh-userauth"));
+
+        // The server's NEWKEYS comes before its answer, and has to be read first
+        let mut wire = server.encode(&[SSH_MSG_NEWKEYS]).unwrap();
+        wire.extend(server.encode(&[SSH_MSG_SERVICE_ACCEPT]).unwrap());
+        client.feed(&wire);
+        expect_new_keys(&client.decode().unwrap().unwrap()).unwrap();
+        assert_eq!(client.decode().unwrap().unwrap(), [SSH_MSG_SERVICE_ACCEPT]);
+        assert!(expect_new_keys(&[SSH_MSG_SERVICE_ACCEPT]).is_err());
+    }
+
+    #[test]
+    fn test_partial_and_coalesced_packets() {
+        let (mut client, mut server) = connected("aes256-gcm@openssh.com", "");
+        let mut wire = client.encode(b"\x5efirst").unwrap();
+        wire.extend(client.encode(b"\x5esecond").unwrap());
+
+        // One byte at a time: nothing decodes until a packet is complete
+        let mut decoded = Vec::new();
+        for byte in &wire {
+            server.feed(std::slice::from_ref(byte));
+            while let Some(payload) = server.decode().unwrap() {
+                decoded.push(payload);
+            }
+        }
+        assert_eq!(decoded, [b"\x5efirst".to_vec(), b"\x5esecond".to_vec()]);
+    }
+
+    #[test]
+    fn test_corrupt_and_reordered_packets_are_rejected() {
+        let (mut client, mut server) = connected("aes128-ctr", "hmac-sha2-256");
+        let mut packet = client.encode(b"\x5edata").unwrap();
+        let last = packet.len() - 1;
+        packet[last] ^= 1;
+        server.feed(&packet);
+        assert!(server.decode().is_err());
+
+        // Skipping a packet leaves the sequence numbers out of step, so the MAC fails
+        let (mut client, mut server) = connected("aes128-ctr", "hmac-sha2-256-etm@openssh.com");
+        client.encode(b"\x5elost").unwrap();
+        server.feed(&client.encode(b"\x5edata").unwrap());
+        assert!(server.decode().is_err());
+    }
+
+    #[test]
+    fn test_max_packet_length() {
+        let mut codec = PacketCodec::new();
+        assert!(codec.set_max_packet_length(1000).is_err());
+        assert!(codec.set_max_packet_length(MAX_PACKET_LENGTH + 1).is_err());
+        codec.set_max_packet_length(MIN_MAX_PACKET_LENGTH).unwrap();
+        assert!(codec.encode(&vec![0; MIN_MAX_PACKET_LENGTH]).is_err());
+        codec.encode(&vec![0; MIN_MAX_PACKET_LENGTH - 100]).unwrap();
+
+        // An oversized length field is refused before the packet body arrives
+        let mut header = ((MIN_MAX_PACKET_LENGTH + 16) as u32).to_be_bytes().to_vec();
+        header.push(4);
+        codec.feed(&header);
+        assert!(codec.decode().is_err());
+    }
+
+    #[test]
+    fn test_strict_kex_rejects_early_messages() {
+        let mut client = PacketCodec::new();
+        let mut server = PacketCodec::new();
+        let kex_init = KexInit::client([0; 16], true).encode();
+        client.feed(&server.encode(&kex_init).unwrap());
+        let payload = client.decode().unwrap().unwrap();
+        assert_eq!(payload[0], SSH_MSG_KEXINIT);
+
+        let mut server_init = KexInit::parse(&kex_init).unwrap();
+        server_init.kex_algorithms.push("kex-strict-s-v00@openssh.com".to_string());
+        let negotiated = KexInit::parse(&kex_init).unwrap().negotiate(&server_init).unwrap();
+        client.negotiated(&negotiated).unwrap();
+        assert!(client.is_strict());
+
+        client.feed(&server.encode(&[2, 0, 0, 0, 0]).unwrap());
+        assert!(client.decode().is_err());
+    }
+}
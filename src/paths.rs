@@ -0,0 +1,225 @@
+//! Where bxssh keeps its files. Settings and profiles go in `$XDG_CONFIG_HOME/bxssh`,
+//! keys in `$XDG_DATA_HOME/bxssh` and history, login failures and session sockets
+//! in `$XDG_STATE_HOME/bxssh`. `BXSSH_HOME` puts everything in one directory, as
+//! the old `~/.bxssh` did and as it still does on Windows.
+//!
+//! An existing `~/.bxssh` is moved into the XDG directories once, leaving a note
+//! behind. If that fails part way, the moves are undone, the note is removed and
+//! `~/.bxssh` stays in use.
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// One directory for everything, overriding the XDG layout
+pub const HOME_ENV: &str = "BXSSH_HOME";
+
+const CONFIG_HOME_ENV: &str = "XDG_CONFIG_HOME";
+const DATA_HOME_ENV: &str = "XDG_DATA_HOME";
+const STATE_HOME_ENV: &str = "XDG_STATE_HOME";
+
+/// Left in `~/.bxssh` once its files have moved, so they are only moved once
+pub const MOVED_NOTE: &str = "MOVED.txt";
+
+/// Files that belong in the config directory; anything else unknown is data
+const CONFIG_FILES: &[&str] = &["config.toml", "profiles.toml", "policy.toml"];
+const STATE_FILES: &[&str] = &["recent.json", "auth_failures.json", "run", "recordings"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirs {
+    pub config: PathBuf,
+    pub data: PathBuf,
+    pub state: PathBuf,
+}
+
+impl Dirs {
+    /// Everything in `dir`
+    pub fn single(dir: PathBuf) -> Self {
+        Self { config: dir.clone(), data: dir.clone(), state: dir }
+    }
+
+    /// The directories in force for this user, moving `~/.bxssh` first if needed
+    pub fn resolve() -> Result<Self> {
+        let home = crate::config::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+        Ok(Self::resolve_with(&home, |name| std::env::var_os(name).map(PathBuf::from), cfg!(windows)))
+    }
+
+    /// [`Self::resolve`] with the environment passed in. Relative XDG paths are
+    /// ignored, as the spec asks.
+    pub fn resolve_with(home: &Path, var: impl Fn(&str) -> Option<PathBuf>, legacy_only: bool) -> Self {
+        if let Some(dir) = var(HOME_ENV).filter(|dir| !dir.as_os_str().is_empty()) {
+            return Self::single(dir);
+        }
+        let legacy = home.join(".bxssh");
+        if legacy_only {
+            return Self::single(legacy);
+        }
+        let xdg = |name: &str, default: &str| {
+            var(name).filter(|dir| dir.is_absolute()).unwrap_or_else(|| home.join(default)).join("bxssh")
+        };
+        let dirs = Self {
+            config: xdg(CONFIG_HOME_ENV, ".config"),
+            data: xdg(DATA_HOME_ENV, ".local/share"),
+            state: xdg(STATE_HOME_ENV, ".local/state"),
+        };
+        if legacy.is_dir() && !legacy.join(MOVED_NOTE).exists() {
+            if let Err(e) = dirs.move_from(&legacy) {
+                log::warn!("Still using {}: {:#} (or set {} to keep it)", legacy.display(), e, HOME_ENV);
+                return Self::single(legacy);
+            }
+        }
+        dirs
+    }
+
+    /// Which of the directories the file `name` (`profiles.toml`, `keys`, ...) belongs in
+    pub fn dir_for(&self, name: &str) -> &Path {
+        if CONFIG_FILES.contains(&name) {
+            &self.config
+        } else if STATE_FILES.contains(&name) {
+            &self.state
+        } else {
+            &self.data
+        }
+    }
+
+    /// Move each entry of `legacy` to its directory, skipping ones already there
+    fn move_from(&self, legacy: &Path) -> Result<()> {
+        let entries = std::fs::read_dir(legacy).with_context(|| format!("Failed to read {}", legacy.display()))?;
+        let mut moves = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let to = self.dir_for(&name.to_string_lossy()).join(&name);
+            if to.exists() {
+                log::warn!("Leaving {}: {} already exists", legacy.join(&name).display(), to.display());
+            } else {
+                moves.push((legacy.join(&name), to));
+            }
+        }
+
+        // The note goes first: once files have moved, failing to write it would leave
+        // them where the old directory no longer sees them
+        let note = format!(
+            "bxssh now keeps its files in the XDG directories:\n  config: {}\n  data:   {}\n  state:  {}\n\
+             Set {} to this directory to go back to it.\n",
+            self.config.display(),
+            self.data.display(),
+            self.state.display(),
+            HOME_ENV,
+        );
+        let note_path = legacy.join(MOVED_NOTE);
+        std::fs::write(&note_path, note).context("Failed to leave a note in the old directory")?;
+
+        let mut moved: Vec<&(PathBuf, PathBuf)> = Vec::new();
+        for step in &moves {
+            let (from, to) = step;
+            let result = to
+                .parent()
+                .map_or(Ok(()), crate::private_file::create_dir)
+                .and_then(|()| std::fs::rename(from, to).map_err(anyhow::Error::from));
+            if let Err(e) = result {
+                for (from, to) in moved.iter().rev() {
+                    let _ = std::fs::rename(to, from);
+                }
+                let _ = std::fs::remove_file(&note_path);
+                return Err(e).with_context(|| format!("Could not move {} to {}", from.display(), to.display()));
+            }
+            moved.push(step);
+        }
+
+        if !moves.is_empty() {
+            log::info!("Moved {} files from {} to the XDG directories", moves.len(), legacy.display());
+        }
+        Ok(())
+    }
+}
+
+/// Settings and profiles (`$XDG_CONFIG_HOME/bxssh`)
+pub fn config_dir() -> Result<PathBuf> {
+    Ok(Dirs::resolve()?.config)
+}
+
+/// Keys (`$XDG_DATA_HOME/bxssh`)
+pub fn data_dir() -> Result<PathBuf> {
+    Ok(Dirs::resolve()?.data)
+}
+
+/// History, login failures, session sockets and recordings (`$XDG_STATE_HOME/bxssh`)
+pub fn state_dir() -> Result<PathBuf> {
+    Ok(Dirs::resolve()?.state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn resolve(home: &Path, vars: &[(&str, PathBuf)]) -> Dirs {
+        let vars: HashMap<&str, PathBuf> = vars.iter().cloned().collect();
+        Dirs::resolve_with(home, |name| vars.get(name).cloned(), false)
+    }
+
+    #[test]
+    fn test_xdg_defaults_and_overrides() {
+        let home = TempDir::new().unwrap();
+        let dirs = resolve(home.path(), &[]);
+        assert_eq!(dirs.config, home.path().join(".config/bxssh"));
+        assert_eq!(dirs.data, home.path().join(".local/share/bxssh"));
+        assert_eq!(dirs.state, home.path().join(".local/state/bxssh"));
+
+        let custom = home.path().join("xdg");
+        let dirs = resolve(home.path(), &[(CONFIG_HOME_ENV, custom.clone()), (DATA_HOME_ENV, "relative".into())]);
+        assert_eq!(dirs.config, custom.join("bxssh"));
+        assert_eq!(dirs.data, home.path().join(".local/share/bxssh"));
+
+        let single = home.path().join("portable");
+        assert_eq!(resolve(home.path(), &[(HOME_ENV, single.clone())]), Dirs::single(single));
+        let windows = Dirs::resolve_with(home.path(), |_| None, true);
+        assert_eq!(windows, Dirs::single(home.path().join(".bxssh")));
+    }
+
+    #[test]
+    fn test_moves_the_old_directory_once() {
+        let home = TempDir::new().unwrap();
+        let legacy = home.path().join(".bxssh");
+        std::fs::create_dir_all(legacy.join("keys")).unwrap();
+        std::fs::write(legacy.join("keys").join("a.json"), "{}").unwrap();
+        std::fs::write(legacy.join("profiles.toml"), "[web]\n").unwrap();
+        std::fs::write(legacy.join("recent.json"), "{}").unwrap();
+
+        let dirs = resolve(home.path(), &[]);
+        assert!(dirs.data.join("keys").join("a.json").exists());
+        assert!(dirs.config.join("profiles.toml").exists());
+        assert!(dirs.state.join("recent.json").exists());
+        assert!(!legacy.join("profiles.toml").exists());
+        assert!(legacy.join(MOVED_NOTE).exists());
+
+        // A file that reappears in the old directory is left alone
+        std::fs::write(legacy.join("profiles.toml"), "[other]\n").unwrap();
+        assert_eq!(resolve(home.path(), &[]), dirs);
+        assert_eq!(std::fs::read_to_string(dirs.config.join("profiles.toml")).unwrap(), "[web]\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_move_keeps_the_old_directory() {
+        use std::os::unix::fs::PermissionsExt;
+        let home = TempDir::new().unwrap();
+        let legacy = home.path().join(".bxssh");
+        std::fs::create_dir(&legacy).unwrap();
+        std::fs::write(legacy.join("profiles.toml"), "[web]\n").unwrap();
+        std::fs::write(legacy.join("recent.json"), "{}").unwrap();
+        // The state directory can't be created under a read-only parent
+        let state_home = home.path().join("state");
+        std::fs::create_dir(&state_home).unwrap();
+        std::fs::set_permissions(&state_home, std::fs::Permissions::from_mode(0o500)).unwrap();
+        if std::fs::create_dir(state_home.join("probe")).is_ok() {
+            return; // running as root; permissions don't stop us
+        }
+
+        let dirs = resolve(home.path(), &[(STATE_HOME_ENV, state_home.clone())]);
+        assert_eq!(dirs, Dirs::single(legacy.clone()));
+        assert!(legacy.join("profiles.toml").exists());
+        assert!(legacy.join("recent.json").exists());
+        assert!(!legacy.join(MOVED_NOTE).exists());
+        std::fs::set_permissions(&state_home, std::fs::Permissions::from_mode(0o700)).unwrap();
+    }
+}
@@ -0,0 +1,327 @@
+use anyhow::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ssh_client::SshClient;
+
+/// Command used to check that an idle session still works
+const HEALTH_CHECK_COMMAND: &str = "true";
+
+/// Opens a new authenticated client for a key; called on every pool miss
+pub type Connector = Box<dyn Fn(&PoolKey) -> Result<SshClient> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+}
+
+impl PoolKey {
+    pub fn new(host: &str, port: u16, user: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            user: user.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Idle sessions older than this are closed instead of reused
+    pub max_idle: Duration,
+    /// Sessions idle longer than this run a no-op command before being handed out
+    pub check_after: Duration,
+    /// Idle sessions kept per key; extra ones are closed on release
+    pub max_idle_per_key: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle: Duration::from_secs(300),
+            check_after: Duration::from_secs(30),
+            max_idle_per_key: 4,
+        }
+    }
+}
+
+struct IdleClient {
+    client: SshClient,
+    since: Instant,
+}
+
+/// Caches authenticated sessions keyed by (host, port, user) so repeated commands
+/// skip the handshake and authentication:
+///
+/// ```no_run
+/// # use bxssh::pool::{PoolKey, SshPool};
+/// # use bxssh::ssh_client::SshClient;
+/// # use bxssh::ssh_impl::RealSshConnection;
+/// let pool = SshPool::new(Box::new(|key: &PoolKey| {
+///     let mut client = SshClient::new(Box::new(RealSshConnection::new()));
+///     client.connect(&key.host, key.port)?;
+///     client.authenticate_with_key(&key.user, "/home/app/.ssh/id_ed25519")?;
+///     Ok(client)
+/// }));
+///
+/// let key = PoolKey::new("db1.internal", 22, "app");
+/// let uptime = pool.execute_command(&key, "uptime")?;
+/// let load = pool.execute_command(&key, "cat /proc/loadavg")?; // same session
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct SshPool {
+    connector: Connector,
+    config: PoolConfig,
+    idle: Mutex<HashMap<PoolKey, Vec<IdleClient>>>,
+}
+
+impl SshPool {
+    pub fn new(connector: Connector) -> Self {
+        Self::with_config(connector, PoolConfig::default())
+    }
+
+    pub fn with_config(connector: Connector, config: PoolConfig) -> Self {
+        Self {
+            connector,
+            config,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check out a healthy session for `key`, connecting if none is idle.
+    /// It returns to the pool when the guard is dropped.
+    pub fn get(&self, key: &PoolKey) -> Result<PooledClient<'_>> {
+        self.evict_idle();
+
+        while let Some(idle) = self.take_idle(key) {
+            if self.is_healthy(&idle) {
+                debug!("Reusing pooled session for {}@{}:{}", key.user, key.host, key.port);
+                return Ok(self.guard(key, idle.client));
+            }
+            debug!("Dropping dead pooled session for {}@{}:{}", key.user, key.host, key.port);
+        }
+
+        debug!("Opening new pooled session for {}@{}:{}", key.user, key.host, key.port);
+        let client = (self.connector)(key)?;
+        Ok(self.guard(key, client))
+    }
+
+    /// Run `command` on a pooled session for `key`. If the command fails, the
+    /// session is only kept when a health check shows the connection survived.
+    pub fn execute_command(&self, key: &PoolKey, command: &str) -> Result<String> {
+        let mut client = self.get(key)?;
+        let result = client.execute_command(command);
+        if result.is_err() && client.execute_command(HEALTH_CHECK_COMMAND).is_err() {
+            client.discard();
+        }
+        result
+    }
+
+    /// Close sessions idle longer than `max_idle`; returns how many were closed
+    pub fn evict_idle(&self) -> usize {
+        let Ok(mut idle) = self.idle.lock() else {
+            return 0;
+        };
+        let max_idle = self.config.max_idle;
+        let mut evicted = 0;
+        idle.retain(|_, clients| {
+            let before = clients.len();
+            clients.retain(|client| client.since.elapsed() < max_idle);
+            evicted += before - clients.len();
+            !clients.is_empty()
+        });
+        evicted
+    }
+
+    /// Number of idle sessions across all keys
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().map(|idle| idle.values().map(Vec::len).sum()).unwrap_or(0)
+    }
+
+    fn guard(&self, key: &PoolKey, client: SshClient) -> PooledClient<'_> {
+        PooledClient {
+            pool: self,
+            key: key.clone(),
+            client: Some(client),
+        }
+    }
+
+    /// Most recently used idle session, which is the most likely to still be alive
+    fn take_idle(&self, key: &PoolKey) -> Option<IdleClient> {
+        self.idle.lock().ok()?.get_mut(key)?.pop()
+    }
+
+    fn is_healthy(&self, idle: &IdleClient) -> bool {
+        if !idle.client.is_authenticated() {
+            return false;
+        }
+        idle.since.elapsed() < self.config.check_after
+            || idle.client.execute_command(HEALTH_CHECK_COMMAND).is_ok()
+    }
+
+    fn release(&self, key: PoolKey, client: SshClient) {
+        if !client.is_authenticated() {
+            return;
+        }
+        if let Ok(mut idle) = self.idle.lock() {
+            let clients = idle.entry(key).or_default();
+            clients.push(IdleClient { client, since: Instant::now() });
+            if clients.len() > self.config.max_idle_per_key {
+                clients.remove(0);
+            }
+        }
+    }
+}
+
+/// A session checked out of an [`SshPool`]; derefs to [`SshClient`]
+pub struct PooledClient<'a> {
+    pool: &'a SshPool,
+    key: PoolKey,
+    client: Option<SshClient>,
+}
+
+impl PooledClient<'_> {
+    /// Close the session instead of returning it to the pool
+    pub fn discard(&mut self) {
+        self.client = None;
+    }
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = SshClient;
+
+    fn deref(&self) -> &SshClient {
+        self.client.as_ref().expect("pooled client used after discard")
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.release(self.key.clone(), client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Script, ScriptedSshServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Pool over scripted servers that answer `uptime` and, unless `healthy` is false, the health check
+    fn scripted_pool(config: PoolConfig, healthy: bool) -> (SshPool, Arc<AtomicUsize>) {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connects);
+        let connector: Connector = Box::new(move |key: &PoolKey| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut server = ScriptedSshServer::new()
+                .accept_key(&key.user)
+                .command("uptime", Script::new().send(format!("up on {}\n", key.host)).eof());
+            if healthy {
+                server = server.command(HEALTH_CHECK_COMMAND, Script::new().eof());
+            }
+            let mut client = SshClient::new(Box::new(server));
+            client.connect(&key.host, key.port)?;
+            client.authenticate_with_key(&key.user, "/tmp/key")?;
+            Ok(client)
+        });
+        (SshPool::with_config(connector, config), connects)
+    }
+
+    #[test]
+    fn test_pool_is_shareable_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SshPool>();
+    }
+
+    #[test]
+    fn test_reuses_session_per_key() {
+        let (pool, connects) = scripted_pool(PoolConfig::default(), true);
+        let web = PoolKey::new("web1", 22, "app");
+        let db = PoolKey::new("db1", 22, "app");
+
+        assert_eq!(pool.execute_command(&web, "uptime").unwrap(), "up on web1\n");
+        assert_eq!(pool.execute_command(&web, "uptime").unwrap(), "up on web1\n");
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        assert_eq!(pool.execute_command(&db, "uptime").unwrap(), "up on db1\n");
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.idle_count(), 2);
+    }
+
+    #[test]
+    fn test_concurrent_checkouts_open_separate_sessions() {
+        let (pool, connects) = scripted_pool(PoolConfig { max_idle_per_key: 1, ..Default::default() }, true);
+        let key = PoolKey::new("web1", 22, "app");
+
+        let first = pool.get(&key).unwrap();
+        let second = pool.get(&key).unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        drop(first);
+        drop(second);
+
+        // Only one idle session is kept per key
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn test_max_idle_eviction() {
+        let (pool, connects) = scripted_pool(PoolConfig { max_idle: Duration::ZERO, ..Default::default() }, true);
+        let key = PoolKey::new("web1", 22, "app");
+
+        pool.execute_command(&key, "uptime").unwrap();
+        assert_eq!(pool.evict_idle(), 1);
+        assert_eq!(pool.idle_count(), 0);
+
+        pool.execute_command(&key, "uptime").unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_health_check_before_reuse() {
+        let config = PoolConfig { check_after: Duration::ZERO, ..Default::default() };
+        let key = PoolKey::new("web1", 22, "app");
+
+        let (pool, connects) = scripted_pool(config.clone(), true);
+        pool.execute_command(&key, "uptime").unwrap();
+        pool.execute_command(&key, "uptime").unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        // Sessions failing the check are replaced
+        let (pool, connects) = scripted_pool(config, false);
+        pool.execute_command(&key, "uptime").unwrap();
+        pool.execute_command(&key, "uptime").unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_command_error_keeps_healthy_session() {
+        let (pool, connects) = scripted_pool(PoolConfig::default(), true);
+        let key = PoolKey::new("web1", 22, "app");
+
+        assert!(pool.execute_command(&key, "no-such-command").is_err());
+        assert_eq!(pool.idle_count(), 1);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        // A failed command on a dead connection drops the session
+        let (pool, _) = scripted_pool(PoolConfig::default(), false);
+        assert!(pool.execute_command(&key, "no-such-command").is_err());
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[test]
+    fn test_connector_errors_propagate() {
+        let pool = SshPool::new(Box::new(|key: &PoolKey| {
+            Err(anyhow::anyhow!("Connection to {} refused", key.host))
+        }));
+        let err = pool.execute_command(&PoolKey::new("down", 22, "app"), "uptime").unwrap_err();
+        assert!(err.to_string().contains("refused"));
+    }
+}
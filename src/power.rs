@@ -0,0 +1,106 @@
+//! `bxssh reboot`: reboot (or power off) a host, through sudo unless logged in as
+//! root, and with `--wait` log in again once it is back, reporting the downtime.
+//! A host counts as back when it reports a new boot id; on systems without one,
+//! when it has stopped accepting connections and then logs us in again.
+use anyhow::Result;
+use log::debug;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Prints an identifier that changes with every boot: Linux's boot id, or the boot
+/// time on the BSDs and macOS
+pub const BOOT_ID_COMMAND: &str =
+    "cat /proc/sys/kernel/random/boot_id 2>/dev/null || sysctl -n kern.boottime 2>/dev/null";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Reboot,
+    Poweroff,
+}
+
+impl PowerAction {
+    fn program(self) -> &'static str {
+        match self {
+            PowerAction::Reboot => "reboot",
+            PowerAction::Poweroff => "poweroff",
+        }
+    }
+}
+
+impl std::fmt::Display for PowerAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.program())
+    }
+}
+
+/// Remote command for `action`: run directly as root, otherwise through sudo, which
+/// may only ask for a password when `interactive`
+pub fn power_command(action: PowerAction, interactive: bool) -> String {
+    let program = action.program();
+    let sudo = if interactive { "sudo" } else { "sudo -n" };
+    format!("if [ \"$(id -u)\" -eq 0 ]; then exec {program}; else exec {sudo} {program}; fi")
+}
+
+/// The boot id in `BOOT_ID_COMMAND`'s output, if it printed one
+pub fn parse_boot_id(output: &str) -> Option<String> {
+    Some(output.trim()).filter(|id| !id.is_empty()).map(str::to_string)
+}
+
+/// Fails while the host still runs the boot it had before the reboot
+pub fn check_rebooted(before: Option<&str>, now: Option<&str>) -> Result<()> {
+    match (before, now) {
+        (Some(before), Some(now)) if before == now => Err(anyhow::anyhow!("The host has not rebooted yet")),
+        _ => Ok(()),
+    }
+}
+
+/// Poll `host:port` every `interval` until it stops accepting connections or `timeout`
+/// has passed; returns whether it went down
+pub fn wait_until_down(host: &str, port: u16, timeout: Duration, interval: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        let addrs = (host, port).to_socket_addrs().map(Iterator::collect::<Vec<_>>).unwrap_or_default();
+        if !addrs.iter().any(|addr| TcpStream::connect_timeout(addr, interval).is_ok()) {
+            return true;
+        }
+        debug!("{}:{} still accepts connections", host, port);
+        std::thread::sleep(interval);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_power_command_uses_sudo_unless_root() {
+        assert_eq!(
+            power_command(PowerAction::Reboot, true),
+            r#"if [ "$(id -u)" -eq 0 ]; then exec reboot; else exec sudo reboot; fi"#
+        );
+        assert!(power_command(PowerAction::Poweroff, false).ends_with("else exec sudo -n poweroff; fi"));
+    }
+
+    #[test]
+    fn test_reboot_is_seen_by_boot_id() {
+        let before = parse_boot_id("1f2e3d4c-0000-4000-8000-000000000001\n");
+        assert_eq!(before.as_deref(), Some("1f2e3d4c-0000-4000-8000-000000000001"));
+        assert_eq!(parse_boot_id("  \n"), None);
+
+        assert!(check_rebooted(before.as_deref(), before.as_deref()).is_err());
+        assert!(check_rebooted(before.as_deref(), Some("6a7b8c9d-0000-4000-8000-000000000002")).is_ok());
+        assert!(check_rebooted(None, None).is_ok());
+    }
+
+    #[test]
+    fn test_wait_until_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let interval = Duration::from_millis(20);
+        assert!(!wait_until_down("127.0.0.1", port, Duration::from_millis(100), interval));
+        drop(listener);
+        assert!(wait_until_down("127.0.0.1", port, Duration::from_secs(5), interval));
+    }
+}
@@ -0,0 +1,495 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Default pause between knocks, long enough for knockd to register each one in order
+const DEFAULT_KNOCK_DELAY: Duration = Duration::from_millis(100);
+/// TCP knocks only need the SYN to reach the firewall, so don't wait for a handshake
+const TCP_KNOCK_TIMEOUT: Duration = Duration::from_millis(300);
+/// Discard port, where Wake-on-LAN magic packets are conventionally sent
+const WOL_PORT: u16 = 9;
+/// How long a woken host gets to start sshd, unless `--wol-timeout` says otherwise
+pub const DEFAULT_WAKE_TIMEOUT: Duration = Duration::from_secs(120);
+/// Pause between checks whether a woken host accepts connections
+const WAKE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How long `--wait` waits for a host, unless `--wait-timeout` says otherwise
+pub const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(300);
+/// Pause between `--wait` checks, unless `--wait-interval` says otherwise
+pub const DEFAULT_WAIT_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KnockProtocol {
+    Tcp,
+    Udp,
+}
+
+/// One port in a knock sequence, written `tcp:PORT`, `udp:PORT` or just `PORT` (TCP)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KnockStep {
+    pub protocol: KnockProtocol,
+    pub port: u16,
+}
+
+impl FromStr for KnockStep {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (protocol, port) = match spec.split_once(':') {
+            Some((protocol, port)) => {
+                let protocol = match protocol.to_ascii_lowercase().as_str() {
+                    "tcp" => KnockProtocol::Tcp,
+                    "udp" => KnockProtocol::Udp,
+                    _ => return Err(anyhow::anyhow!("Invalid knock '{}'. Use tcp:PORT or udp:PORT", spec)),
+                };
+                (protocol, port)
+            }
+            None => (KnockProtocol::Tcp, spec),
+        };
+
+        let port = port
+            .parse::<u16>()
+            .ok()
+            .filter(|port| *port != 0)
+            .ok_or_else(|| anyhow::anyhow!("Invalid port in knock '{}'", spec))?;
+        Ok(Self { protocol, port })
+    }
+}
+
+/// A machine to wake with a Wake-on-LAN magic packet, written `MAC` or
+/// `MAC@BROADCAST` (default 255.255.255.255)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WakeOnLan {
+    pub mac: [u8; 6],
+    pub broadcast: IpAddr,
+}
+
+impl FromStr for WakeOnLan {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (mac, broadcast) = match spec.split_once('@') {
+            Some((mac, broadcast)) => {
+                let broadcast = broadcast
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid broadcast address in '{}'", spec))?;
+                (mac, broadcast)
+            }
+            None => (spec, IpAddr::V4(Ipv4Addr::BROADCAST)),
+        };
+
+        let invalid = || anyhow::anyhow!("Invalid MAC address '{}'. Use e.g. 00:11:22:33:44:55", mac);
+        let hex: String = mac.chars().filter(|c| *c != ':' && *c != '-').collect();
+        if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; 6];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self { mac: bytes, broadcast })
+    }
+}
+
+impl WakeOnLan {
+    /// Six 0xFF bytes, then the MAC address sixteen times
+    pub fn magic_packet(&self) -> Vec<u8> {
+        let mut packet = vec![0xFF; 6];
+        for _ in 0..16 {
+            packet.extend_from_slice(&self.mac);
+        }
+        packet
+    }
+
+    fn mac_string(&self) -> String {
+        self.mac.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":")
+    }
+
+    fn send_to(&self, target: SocketAddr) -> Result<()> {
+        let bind = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind).context("Failed to open UDP socket for Wake-on-LAN")?;
+        socket.set_broadcast(true).context("Failed to enable UDP broadcast")?;
+        socket
+            .send_to(&self.magic_packet(), target)
+            .with_context(|| format!("Failed to send Wake-on-LAN packet to {}", target))?;
+        Ok(())
+    }
+}
+
+/// `--wait`: poll the SSH port until the host is back, e.g. after a reboot
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WaitForHost {
+    pub timeout: Duration,
+    pub interval: Duration,
+    /// Also retry the whole login until it succeeds (`--wait-auth`), for hosts whose
+    /// sshd accepts connections before it can log anyone in
+    pub auth: bool,
+}
+
+impl Default for WaitForHost {
+    fn default() -> Self {
+        Self { timeout: DEFAULT_WAIT_TIMEOUT, interval: DEFAULT_WAIT_INTERVAL, auth: false }
+    }
+}
+
+impl WaitForHost {
+    /// Run `login` until it succeeds, pausing `interval` after each failure, until
+    /// `deadline`, normally `timeout` after the wait began; then fail with the last error.
+    /// An error `is_final` says trying again can't fix, such as the server turning the
+    /// credentials down, fails at once.
+    pub fn until_logged_in<T>(
+        &self,
+        target: &str,
+        deadline: Instant,
+        is_final: impl Fn(&anyhow::Error) -> bool,
+        mut login: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        loop {
+            let error = match login() {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            if is_final(&error) {
+                return Err(error);
+            }
+            if Instant::now() + self.interval >= deadline {
+                let context = format!("Could not log in to {} within {}s", target, self.timeout.as_secs());
+                return Err(error.context(context));
+            }
+            debug!("Login to {} failed, trying again: {:#}", target, error);
+            std::thread::sleep(self.interval);
+        }
+    }
+}
+
+/// Steps run by the connection layer before dialing the SSH server
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PreConnect {
+    /// Run through `sh -c` (`cmd /C` on Windows) with `BXSSH_HOST`, `BXSSH_PORT` and `BXSSH_USER` set; must exit 0
+    pub command: Option<String>,
+    pub knock: Vec<KnockStep>,
+    pub knock_delay: Option<Duration>,
+    /// Wake the host, then wait for the SSH port to accept connections, knocking before each check
+    pub wake: Option<WakeOnLan>,
+    pub wake_timeout: Option<Duration>,
+    /// Wait for the SSH port to accept connections, knocking before each check
+    pub wait: Option<WaitForHost>,
+}
+
+impl PreConnect {
+    /// Build from profile settings, validating the knock sequence
+    pub fn from_profile(profile: &crate::profiles::Profile) -> Result<Self> {
+        let knock = profile
+            .knock_sequence
+            .iter()
+            .map(|spec| spec.parse())
+            .collect::<Result<Vec<KnockStep>>>()?;
+
+        Ok(Self {
+            command: profile.pre_connect_command.clone(),
+            knock,
+            knock_delay: profile.knock_delay_ms.map(Duration::from_millis),
+            ..Default::default()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.command.is_none() && self.knock.is_empty() && self.wake.is_none() && self.wait.is_none()
+    }
+
+    /// Run the pre-connect command, then wake and wait for the host, sending the knock
+    /// sequence before every check, against `host:port`, logging in as `user`
+    pub fn run(&self, host: &str, port: u16, user: &str) -> Result<()> {
+        if let Some(command) = &self.command {
+            let env = [
+                ("BXSSH_HOST", host.to_string()),
+                ("BXSSH_PORT", port.to_string()),
+                ("BXSSH_USER", user.to_string()),
+            ];
+            run_command("PreConnectCommand", command, &env).map_err(|e| anyhow::anyhow!("{:#}; not connecting", e))?;
+        }
+        let knock_delay = self.knock_delay.unwrap_or(DEFAULT_KNOCK_DELAY);
+        if !self.knock.is_empty() && self.wake.is_none() && self.wait.is_none() {
+            knock(host, &self.knock, knock_delay)?;
+        }
+        // The port stays closed until knocked, and a host that is still starting up
+        // misses the knocks, so knock before every check while waiting
+        let knock_again = || {
+            if !self.knock.is_empty() {
+                if let Err(e) = knock(host, &self.knock, knock_delay) {
+                    debug!("Knocking {} failed: {:#}", host, e);
+                }
+            }
+        };
+        if let Some(wake) = &self.wake {
+            crate::ui::status(
+                crate::ui::Icon::Wake,
+                &crate::i18n::tr(crate::i18n::Msg::WakingHost, &[&wake.mac_string(), &host]),
+            );
+            wake.send_to(SocketAddr::new(wake.broadcast, WOL_PORT))?;
+            let timeout = self.wake_timeout.unwrap_or(DEFAULT_WAKE_TIMEOUT);
+            wait_for_port(host, port, timeout, WAKE_POLL_INTERVAL, knock_again)?;
+        }
+        if let Some(wait) = &self.wait {
+            let target = crate::remote_target::host_port(host, port);
+            let args: [&dyn std::fmt::Display; 2] = [&target, &wait.timeout.as_secs()];
+            crate::ui::status(crate::ui::Icon::Wake, &crate::i18n::tr(crate::i18n::Msg::WaitingForHost, &args));
+            wait_for_port(host, port, wait.timeout, wait.interval, knock_again)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `command` through the shell with `env` set, naming it by its profile `key`
+/// in logs and errors; also runs the lifecycle hooks (see `crate::lifecycle_hooks`)
+pub fn run_command(key: &str, command: &str, env: &[(&str, String)]) -> Result<()> {
+    info!("Running {}: {}", key, command);
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let status = std::process::Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .status()
+        .with_context(|| format!("Failed to run {} '{}'", key, command))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("{} '{}' failed ({})", key, command, status));
+    }
+    Ok(())
+}
+
+/// Send each knock to `host` in order, pausing `delay` between them
+pub fn knock(host: &str, steps: &[KnockStep], delay: Duration) -> Result<()> {
+    let address = (host, 0)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {} for port knocking", host))?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No address found for {}", host))?;
+
+    for (i, step) in steps.iter().enumerate() {
+        if i > 0 {
+            std::thread::sleep(delay);
+        }
+        let target = SocketAddr::new(address.ip(), step.port);
+        debug!("Knocking {:?} {}", step.protocol, target);
+
+        match step.protocol {
+            // Refused or timed-out connections are expected: the firewall only watches for the SYN
+            KnockProtocol::Tcp => {
+                let _ = TcpStream::connect_timeout(&target, TCP_KNOCK_TIMEOUT);
+            }
+            KnockProtocol::Udp => {
+                let bind = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(bind).context("Failed to open UDP socket for knocking")?;
+                socket
+                    .send_to(&[0], target)
+                    .with_context(|| format!("Failed to send UDP knock to {}", target))?;
+            }
+        }
+    }
+
+    info!("Sent {} knock(s) to {}", steps.len(), host);
+    Ok(())
+}
+
+/// Poll `host:port` every `interval` until it accepts a TCP connection or `timeout` has
+/// passed, calling `before_check` ahead of every attempt
+fn wait_for_port(host: &str, port: u16, timeout: Duration, interval: Duration, before_check: impl Fn()) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        before_check();
+        // The name may only resolve once the host is back on the network
+        let addrs = (host, port).to_socket_addrs().map(Iterator::collect::<Vec<_>>).unwrap_or_default();
+        if addrs.iter().any(|addr| TcpStream::connect_timeout(addr, interval).is_ok()) {
+            info!("{}:{} is up", host, port);
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow::anyhow!(
+                "{}:{} did not accept connections within {}s",
+                host,
+                port,
+                timeout.as_secs()
+            ));
+        }
+        debug!("Waiting for {}:{}", host, port);
+        std::thread::sleep(interval.min(deadline.saturating_duration_since(Instant::now())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::Profile;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_knock_step() {
+        assert_eq!(
+            "tcp:7000".parse::<KnockStep>().unwrap(),
+            KnockStep { protocol: KnockProtocol::Tcp, port: 7000 }
+        );
+        assert_eq!(
+            "UDP:8000".parse::<KnockStep>().unwrap(),
+            KnockStep { protocol: KnockProtocol::Udp, port: 8000 }
+        );
+        assert_eq!("9000".parse::<KnockStep>().unwrap().protocol, KnockProtocol::Tcp);
+
+        assert!("icmp:1".parse::<KnockStep>().is_err());
+        assert!("tcp:0".parse::<KnockStep>().is_err());
+        assert!("tcp:70000".parse::<KnockStep>().is_err());
+        assert!("tcp:".parse::<KnockStep>().is_err());
+    }
+
+    #[test]
+    fn test_from_profile() {
+        let profile = Profile {
+            pre_connect_command: Some("true".to_string()),
+            knock_sequence: vec!["tcp:1".to_string(), "udp:2".to_string()],
+            knock_delay_ms: Some(5),
+            ..Default::default()
+        };
+
+        let pre_connect = PreConnect::from_profile(&profile).unwrap();
+        assert_eq!(pre_connect.knock.len(), 2);
+        assert_eq!(pre_connect.knock_delay, Some(Duration::from_millis(5)));
+        assert!(!pre_connect.is_empty());
+
+        let invalid = Profile {
+            knock_sequence: vec!["bogus:1".to_string()],
+            ..Default::default()
+        };
+        assert!(PreConnect::from_profile(&invalid).is_err());
+        assert!(PreConnect::default().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_gets_target_and_failure_aborts() {
+        let ok = PreConnect {
+            command: Some(r#"test "$BXSSH_USER@$BXSSH_HOST:$BXSSH_PORT" = "deploy@example.com:2222""#.to_string()),
+            ..Default::default()
+        };
+        assert!(ok.run("example.com", 2222, "deploy").is_ok());
+
+        let failing = PreConnect {
+            command: Some("exit 3".to_string()),
+            ..Default::default()
+        };
+        let err = failing.run("example.com", 22, "deploy").unwrap_err();
+        assert!(err.to_string().contains("not connecting"));
+    }
+
+    #[test]
+    fn test_knock_sequence_reaches_ports() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        let steps = [
+            KnockStep { protocol: KnockProtocol::Udp, port: udp.local_addr().unwrap().port() },
+            KnockStep { protocol: KnockProtocol::Tcp, port: tcp.local_addr().unwrap().port() },
+        ];
+        knock("127.0.0.1", &steps, Duration::from_millis(1)).unwrap();
+
+        let mut buf = [0u8; 8];
+        assert_eq!(udp.recv(&mut buf).unwrap(), 1);
+        tcp.set_nonblocking(true).unwrap();
+        assert!(tcp.accept().is_ok());
+    }
+
+    #[test]
+    fn test_parse_wake_on_lan() {
+        let wake: WakeOnLan = "00:11:22:AA:bb:cc".parse().unwrap();
+        assert_eq!(wake.mac, [0x00, 0x11, 0x22, 0xaa, 0xbb, 0xcc]);
+        assert_eq!(wake.broadcast, IpAddr::V4(Ipv4Addr::BROADCAST));
+        assert_eq!(wake.mac_string(), "00:11:22:aa:bb:cc");
+
+        let wake: WakeOnLan = "00-11-22-33-44-55@192.168.1.255".parse().unwrap();
+        assert_eq!(wake.broadcast, "192.168.1.255".parse::<IpAddr>().unwrap());
+        assert_eq!("001122334455".parse::<WakeOnLan>().unwrap().mac[5], 0x55);
+
+        assert!("00:11:22:33:44".parse::<WakeOnLan>().is_err());
+        assert!("00:11:22:33:44:zz".parse::<WakeOnLan>().is_err());
+        assert!("00:11:22:33:44:55@nowhere".parse::<WakeOnLan>().is_err());
+    }
+
+    #[test]
+    fn test_magic_packet_reaches_listener() {
+        let wake: WakeOnLan = "01:02:03:04:05:06".parse().unwrap();
+        let packet = wake.magic_packet();
+        assert_eq!(packet.len(), 102);
+        assert_eq!(&packet[..6], &[0xFF; 6]);
+        assert_eq!(&packet[96..], &[1, 2, 3, 4, 5, 6]);
+
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        wake.send_to(listener.local_addr().unwrap()).unwrap();
+        let mut buf = [0u8; 128];
+        assert_eq!(listener.recv(&mut buf).unwrap(), 102);
+    }
+
+    #[test]
+    fn test_wait_for_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        wait_for_port("127.0.0.1", port, Duration::from_secs(5), WAKE_POLL_INTERVAL, || {}).unwrap();
+
+        drop(listener);
+        let interval = Duration::from_millis(50);
+        let err = wait_for_port("127.0.0.1", port, Duration::from_millis(200), interval, || {}).unwrap_err();
+        assert!(err.to_string().contains("did not accept connections"));
+
+        // Knocks go out before every check
+        let checks = std::cell::Cell::new(0);
+        let _ = wait_for_port("127.0.0.1", port, Duration::from_millis(200), interval, || checks.set(checks.get() + 1));
+        assert!(checks.get() > 1);
+    }
+
+    fn deadline(wait: &WaitForHost) -> Instant {
+        Instant::now() + wait.timeout
+    }
+
+    #[test]
+    fn test_wait_retries_login_until_timeout() {
+        let wait = WaitForHost { timeout: Duration::from_secs(5), interval: Duration::from_millis(10), auth: true };
+        let mut attempts = 0;
+        let result = wait.until_logged_in("web1:22", deadline(&wait), |_| false, || {
+            attempts += 1;
+            if attempts < 3 {
+                return Err(anyhow::anyhow!("Connection reset by peer"));
+            }
+            Ok(attempts)
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let wait = WaitForHost { timeout: Duration::from_millis(100), interval: Duration::from_millis(30), auth: true };
+        let reset = || Err::<(), _>(anyhow::anyhow!("Connection reset by peer"));
+        let err = wait.until_logged_in("web1:22", deadline(&wait), |_| false, reset).unwrap_err();
+        assert_eq!(format!("{:#}", err), "Could not log in to web1:22 within 0s: Connection reset by peer");
+
+        // Waiting for the port used up the time: one try still goes out
+        let mut attempts = 0;
+        let err = wait.until_logged_in("web1:22", Instant::now(), |_| false, || {
+            attempts += 1;
+            reset()
+        });
+        assert!(err.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_wait_stops_on_final_error() {
+        let wait = WaitForHost { timeout: Duration::from_secs(5), interval: Duration::from_millis(10), auth: true };
+        let mut attempts = 0;
+        let refused = || {
+            attempts += 1;
+            Err::<(), _>(anyhow::anyhow!("Authentication failed"))
+        };
+        let is_final = |e: &anyhow::Error| e.to_string() == "Authentication failed";
+        let err = wait.until_logged_in("web1:22", deadline(&wait), is_final, refused).unwrap_err();
+        assert_eq!(err.to_string(), "Authentication failed");
+        assert_eq!(attempts, 1);
+    }
+}
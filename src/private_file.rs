@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::Path;
+
+/// Write `content` to `path` so only the current user can read it: mode 0600 on
+/// Unix, an ACL granting just the current user on Windows. Existing files are
+/// truncated and have their permissions tightened.
+pub fn write(path: &Path, content: &[u8]) -> Result<()> {
+    let mut file = create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    restrict(path).with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+    file.write_all(content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// An empty file at `path` for the caller to write, private as with [`write`]
+pub fn create_file(path: &Path) -> Result<std::fs::File> {
+    let file = create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    restrict(path).with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+    Ok(file)
+}
+
+/// Like [`write`], but through a temporary file beside `path` that is synced and
+/// renamed over it, so a crash leaves the old content or the new, never a mix
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let name = path.file_name().ok_or_else(|| anyhow::anyhow!("Not a file path: {}", path.display()))?;
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), std::process::id()));
+    let written = (|| {
+        let mut file = create(&temp).with_context(|| format!("Failed to create {}", temp.display()))?;
+        restrict(&temp).with_context(|| format!("Failed to set permissions on {}", temp.display()))?;
+        file.write_all(content).with_context(|| format!("Failed to write {}", temp.display()))?;
+        file.sync_all().with_context(|| format!("Failed to flush {}", temp.display()))?;
+        std::fs::rename(&temp, path).with_context(|| format!("Failed to replace {}", path.display()))
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Create `path` and its parents; on Unix `path` itself is mode 0700
+pub fn create_dir(path: &Path) -> Result<()> {
+    std::fs::create_dir_all(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))
+            .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    // New files never exist with wider permissions, even briefly
+    std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::File::create(path)
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+/// Replace the inherited ACL with full control for the current user only,
+/// the same fix OpenSSH for Windows documents for private key files
+#[cfg(windows)]
+fn restrict(path: &Path) -> Result<()> {
+    let user = std::env::var("USERNAME").context("USERNAME is not set")?;
+    let domain = std::env::var("USERDOMAIN").ok();
+    let account = match domain {
+        Some(domain) => format!("{}\\{}", domain, user),
+        None => user,
+    };
+
+    let output = std::process::Command::new("icacls")
+        .arg(path)
+        .args(["/inheritance:r", "/grant:r"])
+        .arg(format!("{}:F", account))
+        .output()
+        .context("Failed to run icacls")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("icacls failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn restrict(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_overwrite() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("secret");
+
+        write(&path, b"first version").unwrap();
+        write(&path, b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tightens_existing_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("keys.json");
+        std::fs::write(&path, "{}").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write(&path, b"{}").unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_without_leftovers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("key.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,1133 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use crate::host_policy::glob_matches;
+
+/// Named connection settings from `~/.config/bxssh/profiles.toml`.
+/// Keys follow ssh_config naming, one table per profile. Like ssh_config `Host`,
+/// a table name may be a pattern list (`*`, `?`, `!negated`, comma-separated);
+/// every matching table applies, and the first to set a key wins:
+///
+/// ```toml
+/// [prod]
+/// HostName = "prod.example.com"
+/// User = "deploy"
+/// Port = 2222
+/// PreConnectCommand = "vpn-check"
+/// PostAuthCommand = "logger bxssh: $BXSSH_USER@$BXSSH_HOST"
+/// DisconnectCommand = "notify-send \"left $BXSSH_HOST after ${BXSSH_DURATION}s\""
+/// KnockSequence = ["tcp:7000", "udp:8000", "tcp:9000"]
+/// RemoteInit = ["export EDITOR=vim", "alias ll='ls -la'"]
+/// Term = "screen-256color"
+/// AltScreen = false
+/// KeyBindings = "passthrough"
+/// Record = true
+/// SetLocale = ["LANG=en_US.UTF-8", "LC_TIME=C"]
+/// Groups = ["prod", "eu"]
+///
+/// ["*"]
+/// CanonicalizeHostname = true
+/// CanonicalDomains = ["corp.example.com", "example.com"]
+/// ```
+///
+/// A table with a `Match` key applies only when all of its criteria hold; its name is
+/// then just a label:
+///
+/// ```toml
+/// [off-site]
+/// Match = { Host = "*.corp.example.com", Exec = "! office-network-check" }
+/// PreConnectCommand = "vpn-up"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct Profile {
+    /// Conditions for this section; without them the section name is a host pattern
+    #[serde(rename = "Match", skip_serializing_if = "Option::is_none")]
+    pub when: Option<MatchCriteria>,
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Keys tried in order: a path or stored key name, or a list of them
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub identity_file: Vec<String>,
+    /// Offer only `IdentityFile` keys, not the default key store and system keys
+    pub identities_only: Option<bool>,
+    /// Shell command run before dialing; a non-zero exit aborts the connection
+    pub pre_connect_command: Option<String>,
+    /// Shell command run once logged in
+    pub post_auth_command: Option<String>,
+    /// Shell command run when the connection closes
+    pub disconnect_command: Option<String>,
+    /// Ports knocked in order before dialing, as `tcp:PORT` or `udp:PORT`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub knock_sequence: Vec<String>,
+    /// Pause between knocks (default 100ms)
+    pub knock_delay_ms: Option<u64>,
+    /// Commands typed into interactive shells before the user takes over
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remote_init: Vec<String>,
+    /// TERM of the remote terminal (default `xterm-256color`)
+    pub term: Option<String>,
+    /// Run the session on the alternate screen (true), or keep remote programs off it (false)
+    pub alt_screen: Option<bool>,
+    /// `default` (Ctrl+C ends the session) or `passthrough` (every key goes to the server)
+    pub key_bindings: Option<crate::terminal::KeyBindings>,
+    /// Record interactive sessions as with `--record`
+    pub record: Option<bool>,
+    /// `LANG`, `LANGUAGE` and `LC_*` settings exported in interactive shells
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub set_locale: Vec<String>,
+    /// Try `CanonicalDomains` suffixes on short host names before connecting
+    pub canonicalize_hostname: Option<bool>,
+    /// Search domains appended, in order, until one resolves
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub canonical_domains: Vec<String>,
+    /// Only names with at most this many dots are canonicalized (default 1)
+    pub canonicalize_max_dots: Option<usize>,
+    /// Connect to the name as given when no domain resolves (default true)
+    pub canonicalize_fallback_local: Option<bool>,
+    /// Tries at connecting when the failure may be transient (default 1)
+    pub connection_attempts: Option<u32>,
+    /// Pause before the first retry, doubling after each (default 1000ms)
+    pub retry_delay_ms: Option<u64>,
+    /// Disable Nagle's algorithm (default true)
+    pub tcp_no_delay: Option<bool>,
+    /// Idle seconds before TCP keepalive probes, and between them
+    pub tcp_keep_alive_secs: Option<u64>,
+    /// Socket buffer sizes in bytes (default: the OS's)
+    pub send_buffer_size: Option<usize>,
+    pub receive_buffer_size: Option<usize>,
+    /// Type of service as in ssh_config, e.g. `lowdelay` or `af21`
+    #[serde(rename = "IPQoS")]
+    pub ip_qos: Option<String>,
+    /// Groups the hosts this section names belong to, for `@group` targets; not
+    /// inherited from pattern sections
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// An ssh_config `ProxyJump` or `ProxyCommand` line, which bxssh doesn't follow
+    #[serde(skip)]
+    pub ignored_proxy: Option<String>,
+}
+
+/// Criteria of a `Match` section, as in ssh_config; every one given must hold
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct MatchCriteria {
+    /// Pattern list checked against the target host, after `HostName`
+    pub host: Option<String>,
+    /// Pattern list checked against the remote user name
+    pub user: Option<String>,
+    /// Networks (`10.0.0.0/8,fd00::/8`), one of which holds an address of this machine
+    pub local_network: Option<String>,
+    /// Shell command that must exit 0; it sees `BXSSH_HOST` and `BXSSH_USER`
+    pub exec: Option<String>,
+}
+
+impl MatchCriteria {
+    fn matches(&self, host: &str, user: &str, env: &dyn HostEnv) -> Result<bool> {
+        if self.host.as_deref().is_some_and(|patterns| !host_matches(patterns, host)) {
+            return Ok(false);
+        }
+        if self.user.as_deref().is_some_and(|patterns| !host_matches(patterns, user)) {
+            return Ok(false);
+        }
+        if let Some(networks) = &self.local_network {
+            let addresses = env.local_addresses();
+            let mut on_network = false;
+            for network in networks.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                for address in &addresses {
+                    on_network |= network_contains(network, *address)?;
+                }
+            }
+            if !on_network {
+                return Ok(false);
+            }
+        }
+        // Last, since it runs a process
+        Ok(self.exec.as_deref().is_none_or(|command| env.exec(command, host, user)))
+    }
+}
+
+/// Whether `address` lies in `network`, written `ADDR/PREFIX` or as a single address
+fn network_contains(network: &str, address: IpAddr) -> Result<bool> {
+    let invalid = || anyhow::anyhow!("Invalid network '{}' (use e.g. 10.0.0.0/8)", network);
+    let (base, prefix) = match network.split_once('/') {
+        Some((base, prefix)) => (base, Some(prefix.parse::<u32>().map_err(|_| invalid())?)),
+        None => (network, None),
+    };
+    let base: IpAddr = base.parse().map_err(|_| invalid())?;
+    let (base, address, bits) = match (base, address) {
+        (IpAddr::V4(base), IpAddr::V4(address)) => (u32::from(base) as u128, u32::from(address) as u128, 32),
+        (IpAddr::V6(base), IpAddr::V6(address)) => (u128::from(base), u128::from(address), 128),
+        (IpAddr::V4(_), _) | (IpAddr::V6(_), _) => return Ok(false),
+    };
+    let prefix = prefix.unwrap_or(bits);
+    if prefix > bits {
+        return Err(invalid());
+    }
+    let shift = bits - prefix;
+    Ok(shift == bits || base >> shift == address >> shift)
+}
+
+/// The local facts `Match` sections and canonicalization depend on
+pub trait HostEnv {
+    /// Whether `name` resolves in DNS
+    fn resolves(&self, name: &str) -> bool;
+    /// Addresses of this machine
+    fn local_addresses(&self) -> Vec<IpAddr>;
+    /// Run a `Match Exec` command for `user@host`; true when it exits 0
+    fn exec(&self, command: &str, host: &str, user: &str) -> bool;
+}
+
+/// `HostEnv` backed by the system resolver and shell
+pub struct SystemEnv;
+
+impl HostEnv for SystemEnv {
+    fn resolves(&self, name: &str) -> bool {
+        use std::net::ToSocketAddrs;
+        (name, 22).to_socket_addrs().map(|mut addrs| addrs.next().is_some()).unwrap_or(false)
+    }
+
+    /// The source addresses the OS picks for the default IPv4 and IPv6 routes.
+    /// Connecting a UDP socket only selects a route; nothing is sent.
+    fn local_addresses(&self) -> Vec<IpAddr> {
+        [("0.0.0.0:0", "192.0.2.1:9"), ("[::]:0", "[2001:db8::1]:9")]
+            .into_iter()
+            .filter_map(|(bind, peer)| {
+                let socket = std::net::UdpSocket::bind(bind).ok()?;
+                socket.connect(peer).ok()?;
+                socket.local_addr().ok().map(|addr| addr.ip())
+            })
+            .collect()
+    }
+
+    fn exec(&self, command: &str, host: &str, user: &str) -> bool {
+        let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let status = std::process::Command::new(shell)
+            .arg(flag)
+            .arg(command)
+            .env("BXSSH_HOST", host)
+            .env("BXSSH_USER", user)
+            .status();
+        match status {
+            Ok(status) => status.success(),
+            Err(e) => {
+                log::warn!("Failed to run Match Exec command '{}': {}", command, e);
+                false
+            }
+        }
+    }
+}
+
+/// `env` answering each `Match Exec` command for a user once: the pass over the
+/// canonical name reuses what the commands said about the name as typed
+struct ExecOnce<'a> {
+    env: &'a dyn HostEnv,
+    results: std::cell::RefCell<std::collections::HashMap<(String, String), bool>>,
+}
+
+impl HostEnv for ExecOnce<'_> {
+    fn resolves(&self, name: &str) -> bool {
+        self.env.resolves(name)
+    }
+
+    fn local_addresses(&self) -> Vec<IpAddr> {
+        self.env.local_addresses()
+    }
+
+    fn exec(&self, command: &str, host: &str, user: &str) -> bool {
+        let key = (command.to_string(), user.to_string());
+        if let Some(&result) = self.results.borrow().get(&key) {
+            return result;
+        }
+        let result = self.env.exec(command, host, user);
+        self.results.borrow_mut().insert(key, result);
+        result
+    }
+}
+
+impl Profile {
+    /// Fill every key not yet set from `other`
+    fn merge(&mut self, other: &Profile) {
+        fn fill<T: Clone>(value: &mut Option<T>, other: &Option<T>) {
+            if value.is_none() {
+                value.clone_from(other);
+            }
+        }
+        fill(&mut self.host_name, &other.host_name);
+        fill(&mut self.user, &other.user);
+        fill(&mut self.port, &other.port);
+        if self.identity_file.is_empty() {
+            self.identity_file.clone_from(&other.identity_file);
+        }
+        fill(&mut self.identities_only, &other.identities_only);
+        fill(&mut self.pre_connect_command, &other.pre_connect_command);
+        fill(&mut self.post_auth_command, &other.post_auth_command);
+        fill(&mut self.disconnect_command, &other.disconnect_command);
+        if self.knock_sequence.is_empty() {
+            self.knock_sequence.clone_from(&other.knock_sequence);
+        }
+        fill(&mut self.knock_delay_ms, &other.knock_delay_ms);
+        if self.remote_init.is_empty() {
+            self.remote_init.clone_from(&other.remote_init);
+        }
+        fill(&mut self.term, &other.term);
+        fill(&mut self.alt_screen, &other.alt_screen);
+        fill(&mut self.key_bindings, &other.key_bindings);
+        fill(&mut self.record, &other.record);
+        if self.set_locale.is_empty() {
+            self.set_locale.clone_from(&other.set_locale);
+        }
+        fill(&mut self.canonicalize_hostname, &other.canonicalize_hostname);
+        if self.canonical_domains.is_empty() {
+            self.canonical_domains.clone_from(&other.canonical_domains);
+        }
+        fill(&mut self.canonicalize_max_dots, &other.canonicalize_max_dots);
+        fill(&mut self.canonicalize_fallback_local, &other.canonicalize_fallback_local);
+        fill(&mut self.connection_attempts, &other.connection_attempts);
+        fill(&mut self.retry_delay_ms, &other.retry_delay_ms);
+        fill(&mut self.tcp_no_delay, &other.tcp_no_delay);
+        fill(&mut self.tcp_keep_alive_secs, &other.tcp_keep_alive_secs);
+        fill(&mut self.send_buffer_size, &other.send_buffer_size);
+        fill(&mut self.receive_buffer_size, &other.receive_buffer_size);
+        fill(&mut self.ip_qos, &other.ip_qos);
+        fill(&mut self.ignored_proxy, &other.ignored_proxy);
+    }
+
+    /// Retries under `ConnectionAttempts` and `RetryDelayMs`
+    pub fn retry_policy(&self) -> crate::retry::RetryPolicy {
+        let policy = crate::retry::RetryPolicy::default().with_attempts(self.connection_attempts.unwrap_or(1));
+        match self.retry_delay_ms {
+            Some(ms) => policy.with_delay(std::time::Duration::from_millis(ms)),
+            None => policy,
+        }
+    }
+
+    /// TCP settings under `TcpNoDelay`, `TcpKeepAliveSecs`, `SendBufferSize`,
+    /// `ReceiveBufferSize` and `IPQoS`
+    pub fn socket_options(&self) -> Result<crate::socket_options::SocketOptions> {
+        Ok(crate::socket_options::SocketOptions {
+            nodelay: self.tcp_no_delay,
+            keepalive: self.tcp_keep_alive_secs.map(std::time::Duration::from_secs),
+            send_buffer_size: self.send_buffer_size,
+            recv_buffer_size: self.receive_buffer_size,
+            tos: self.ip_qos.as_deref().map(crate::socket_options::parse_tos).transpose()?.flatten(),
+        })
+    }
+
+    /// Terminal settings under `Term`, `AltScreen`, `KeyBindings` and `Record`
+    pub fn terminal_overrides(&self) -> Result<crate::terminal::TerminalOverrides> {
+        if let Some(term) = &self.term {
+            if term.is_empty() || !term.chars().all(|c| c.is_ascii_graphic()) {
+                return Err(anyhow::anyhow!("Invalid Term '{}' (use e.g. xterm-256color)", term.escape_default()));
+            }
+        }
+        Ok(crate::terminal::TerminalOverrides {
+            term: self.term.clone(),
+            alt_screen: self.alt_screen,
+            key_bindings: self.key_bindings.unwrap_or_default(),
+            record: self.record.unwrap_or(false),
+            ..Default::default()
+        })
+    }
+
+    /// `export` commands for `SetLocale`, to run before `RemoteInit`
+    pub fn locale_exports(&self) -> Result<Vec<String>> {
+        self.set_locale
+            .iter()
+            .map(|setting| {
+                let invalid = || anyhow::anyhow!("Invalid SetLocale '{}' (use e.g. LANG=en_US.UTF-8)", setting);
+                let (name, value) = setting.split_once('=').ok_or_else(invalid)?;
+                let locale_name = name == "LANG"
+                    || name == "LANGUAGE"
+                    || name.strip_prefix("LC_").is_some_and(|rest| {
+                        !rest.is_empty() && rest.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+                    });
+                // Locale names never need quoting, so anything else is refused rather than escaped
+                let plain_value = value.chars().all(|c| c.is_ascii_alphanumeric() || "._-@:".contains(c));
+                if !locale_name || !plain_value {
+                    return Err(invalid());
+                }
+                Ok(format!("export {}={}", name, value))
+            })
+            .collect()
+    }
+
+    /// The fully-qualified name to use for `host` under this profile's canonicalization
+    /// settings, or `None` to use `host` unchanged. `resolves` reports whether a name
+    /// exists in DNS.
+    pub fn canonicalize(&self, host: &str, resolves: impl Fn(&str) -> bool) -> Result<Option<String>> {
+        if self.canonicalize_hostname != Some(true) || host.parse::<std::net::IpAddr>().is_ok() {
+            return Ok(None);
+        }
+        // A trailing dot marks a name as already fully qualified
+        if let Some(fqdn) = host.strip_suffix('.') {
+            return Ok(Some(fqdn.to_string()));
+        }
+        if host.matches('.').count() > self.canonicalize_max_dots.unwrap_or(1) {
+            return Ok(None);
+        }
+        for domain in &self.canonical_domains {
+            let candidate = format!("{}.{}", host, domain.trim_matches('.'));
+            if resolves(&candidate) {
+                return Ok(Some(candidate));
+            }
+            log::debug!("Canonical candidate {} does not resolve", candidate);
+        }
+        if self.canonicalize_fallback_local == Some(false) {
+            return Err(anyhow::anyhow!(
+                "Could not canonicalize hostname '{}' (tried domains: {})",
+                host,
+                self.canonical_domains.join(", ")
+            ));
+        }
+        Ok(None)
+    }
+}
+
+/// A string or a list of strings, as `IdentityFile = "a"` or `IdentityFile = ["a", "b"]`
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Whether `host` matches an ssh_config-style pattern list such as `*.corp,!bastion.corp`
+pub fn host_matches(patterns: &str, host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+        // `[::1]` and `::1` name the same host
+        match pattern.strip_prefix('!').map(crate::remote_target::unbracketed) {
+            Some(negated) if glob_matches(negated, host) => return false,
+            Some(_) => {}
+            None => matched |= glob_matches(crate::remote_target::unbracketed(pattern), host),
+        }
+    }
+    matched
+}
+
+/// Most nested `Include` levels followed, as in OpenSSH
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// One table of `profiles.toml` or one `Host`/`Match` block of an ssh_config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// Table name or host pattern list
+    pub name: String,
+    pub profile: Profile,
+    /// File the section was read from
+    pub source: Option<PathBuf>,
+}
+
+impl Section {
+    /// Precedence tier for `host`: 0 for the section named exactly `host`, 1 for
+    /// host patterns and `Match` blocks, 2 for the catch-all `*`
+    fn tier(&self, host: &str) -> usize {
+        if self.profile.when.is_none() && self.name == host {
+            0
+        } else if self.profile.when.is_none() && self.name.trim() == "*" {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn label(&self) -> String {
+        match &self.source {
+            Some(source) => format!("[{}] {}", self.name, source.display()),
+            None => format!("[{}]", self.name),
+        }
+    }
+}
+
+/// Files matched by an `Include` argument. `~` is the home directory and relative
+/// paths are taken from `base`; `*` and `?` may appear in the file name.
+pub(crate) fn include_paths(pattern: &str, base: &Path) -> Result<Vec<PathBuf>> {
+    let path = match pattern.strip_prefix("~/") {
+        Some(rest) => crate::config::home_dir()
+            .ok_or_else(|| anyhow::anyhow!("Cannot expand '{}' without a home directory", pattern))?
+            .join(rest),
+        None => base.join(pattern),
+    };
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if !file_name.contains(['*', '?']) {
+        return Ok(if path.exists() { vec![path] } else { Vec::new() });
+    }
+    let dir = path.parent().unwrap_or(base);
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && path.file_name().is_some_and(|name| glob_matches(&file_name, &name.to_string_lossy()))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Connection settings from `~/.config/bxssh/profiles.toml` followed by `~/.ssh/config`.
+///
+/// For a given host, sections apply in three tiers, and within a tier in file order:
+/// the profile named exactly like the host, then host patterns and `Match` blocks,
+/// then `*`. The first value found for a key wins; command-line flags override all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profiles {
+    sections: Vec<Section>,
+}
+
+impl Profiles {
+    /// Load `~/.config/bxssh/profiles.toml` and `~/.ssh/config`; missing files mean no profiles
+    pub fn load() -> Result<Self> {
+        let Some(home) = crate::config::home_dir() else {
+            return Ok(Self::default());
+        };
+        let mut profiles = Self::load_from(&crate::paths::config_dir()?.join("profiles.toml"))?;
+        let ssh_config = home.join(".ssh").join("config");
+        if ssh_config.exists() {
+            profiles.sections.extend(crate::ssh_config::load(&ssh_config)?);
+        }
+        Ok(profiles)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut profiles = Self::default();
+        profiles.read_file(path, 0)?;
+        Ok(profiles)
+    }
+
+    fn read_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(anyhow::anyhow!("Too many nested includes at {}", path.display()));
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let table = parse_table(&content).with_context(|| format!("Invalid profiles file {}", path.display()))?;
+        for (name, value) in table {
+            if name == "Include" {
+                for pattern in include_patterns(value).with_context(|| format!("Invalid Include in {}", path.display()))? {
+                    for included in include_paths(&pattern, base)? {
+                        self.read_file(&included, depth + 1)?;
+                    }
+                }
+                continue;
+            }
+            let profile = section_profile(&name, value).with_context(|| format!("Invalid profiles file {}", path.display()))?;
+            self.sections.push(Section { name, profile, source: Some(path.to_path_buf()) });
+        }
+        Ok(())
+    }
+
+    #[allow(dead_code)] // Library API; the CLI reads files with load
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut sections = Vec::new();
+        for (name, value) in parse_table(content)? {
+            if name == "Include" {
+                return Err(anyhow::anyhow!("Include is only supported in profile files"));
+            }
+            let profile = section_profile(&name, value)?;
+            sections.push(Section { name, profile, source: None });
+        }
+        Ok(Self { sections })
+    }
+
+    /// The section named exactly `name`
+    #[allow(dead_code)] // Library API; the CLI matches patterns with resolve
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.sections.iter().find(|section| section.name == name).map(|section| &section.profile)
+    }
+
+    /// Hosts named literally by a section (no wildcards, negations or `Match`), in
+    /// file order without repeats, with the first section naming each; what the
+    /// host picker and `bxssh recent` offer
+    pub fn hosts(&self) -> Vec<(String, &Profile)> {
+        let mut hosts: Vec<(String, &Profile)> = Vec::new();
+        for section in self.sections.iter().filter(|section| section.profile.when.is_none()) {
+            let patterns = section.name.split(|c: char| c == ',' || c.is_whitespace());
+            for name in patterns.filter(|p| !p.is_empty() && !p.contains(['*', '?', '!'])) {
+                if !hosts.iter().any(|(known, _)| known == name) {
+                    hosts.push((name.to_string(), &section.profile));
+                }
+            }
+        }
+        hosts
+    }
+
+    /// Hosts named literally by a section listing `group` in `Groups`, in file order
+    pub fn group_members(&self, group: &str) -> Vec<String> {
+        let mut members: Vec<String> = Vec::new();
+        for section in self.sections.iter().filter(|section| section.profile.groups.iter().any(|g| g == group)) {
+            let patterns = section.name.split(|c: char| c == ',' || c.is_whitespace());
+            for name in patterns.filter(|p| !p.is_empty() && !p.contains(['*', '?', '!'])) {
+                if section.profile.when.is_none() && !members.iter().any(|known| known == name) {
+                    members.push(name.to_string());
+                }
+            }
+        }
+        members
+    }
+
+    /// Just the names from [`Profiles::hosts`]
+    pub fn host_names(&self) -> Vec<String> {
+        self.hosts().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Settings for `host` as `user` (the remote user when known), from every section
+    /// whose pattern or `Match` criteria hold
+    #[allow(dead_code)] // Library API; the CLI canonicalizes with resolve_canonical
+    pub fn resolve(&self, host: &str, user: Option<&str>, env: &dyn HostEnv) -> Result<Profile> {
+        let mut resolved = Profile::default();
+        self.apply(&mut resolved, host, user, env, &mut Vec::new())?;
+        Ok(resolved)
+    }
+
+    fn apply(
+        &self,
+        resolved: &mut Profile,
+        host: &str,
+        user: Option<&str>,
+        env: &dyn HostEnv,
+        applied: &mut Vec<String>,
+    ) -> Result<()> {
+        for tier in 0..3 {
+            for section in self.sections.iter().filter(|section| section.tier(host) == tier) {
+                let applies = match &section.profile.when {
+                    Some(criteria) => {
+                        let target = resolved.host_name.as_deref().unwrap_or(host);
+                        let local_user = std::env::var(crate::config::USER_ENV).unwrap_or_default();
+                        let user = user.or(resolved.user.as_deref()).unwrap_or(&local_user);
+                        criteria
+                            .matches(target, user, env)
+                            .with_context(|| format!("In profile {}", section.label()))?
+                    }
+                    None => host_matches(&section.name, host),
+                };
+                if applies {
+                    log::debug!("Applying profile {} to {}", section.label(), host);
+                    resolved.merge(&section.profile);
+                    let label = section.label();
+                    if !applied.contains(&label) {
+                        applied.push(label);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `resolve`, then canonicalize the target and apply the sections matching
+    /// the canonical name too. `host_name` holds the canonical name when one was found.
+    pub fn resolve_canonical(&self, alias: &str, user: Option<&str>, env: &dyn HostEnv) -> Result<Profile> {
+        self.resolve_traced(alias, user, env).map(|(profile, _)| profile)
+    }
+
+    /// `resolve_canonical`, also returning the sections applied, in order
+    pub fn resolve_traced(&self, alias: &str, user: Option<&str>, env: &dyn HostEnv) -> Result<(Profile, Vec<String>)> {
+        let env = &ExecOnce { env, results: Default::default() };
+        let mut applied = Vec::new();
+        let mut profile = Profile::default();
+        self.apply(&mut profile, alias, user, env, &mut applied)?;
+        if let Some(host_name) = &mut profile.host_name {
+            *host_name = crate::remote_target::unbracketed(host_name).to_string();
+        }
+        let host = profile.host_name.clone().unwrap_or_else(|| alias.to_string());
+        if let Some(canonical) = profile.canonicalize(&host, |name| env.resolves(name))? {
+            log::info!("Canonicalized hostname \"{}\" => \"{}\"", host, canonical);
+            profile.host_name = Some(canonical.clone());
+            self.apply(&mut profile, &canonical, user, env, &mut applied)?;
+        }
+        Ok((profile, applied))
+    }
+}
+
+fn parse_table(content: &str) -> Result<toml::Table> {
+    toml::from_str(content).context("Failed to parse profiles")
+}
+
+fn section_profile(name: &str, value: toml::Value) -> Result<Profile> {
+    value.try_into().with_context(|| format!("Invalid profile [{}]", name))
+}
+
+/// `Include = "path"` or `Include = ["path", ...]`
+fn include_patterns(value: toml::Value) -> Result<Vec<String>> {
+    match value {
+        toml::Value::String(pattern) => Ok(vec![pattern]),
+        toml::Value::Array(patterns) => patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                toml::Value::String(pattern) => Ok(pattern),
+                other => Err(anyhow::anyhow!("Expected a path, found {}", other)),
+            })
+            .collect(),
+        other => Err(anyhow::anyhow!("Expected a path or list of paths, found {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use tempfile::TempDir;
+
+    /// Canned answers for DNS, local addresses and `Match Exec` (a command
+    /// "succeeds" when listed in `passing`); records the commands it ran
+    #[derive(Default)]
+    struct FakeEnv {
+        resolvable: Vec<&'static str>,
+        addresses: Vec<IpAddr>,
+        passing: Vec<&'static str>,
+        ran: RefCell<Vec<String>>,
+    }
+
+    impl HostEnv for FakeEnv {
+        fn resolves(&self, name: &str) -> bool {
+            self.resolvable.contains(&name)
+        }
+
+        fn local_addresses(&self) -> Vec<IpAddr> {
+            self.addresses.clone()
+        }
+
+        fn exec(&self, command: &str, host: &str, user: &str) -> bool {
+            self.ran.borrow_mut().push(format!("{} {}@{}", command, user, host));
+            self.passing.contains(&command)
+        }
+    }
+
+    const SAMPLE: &str = r#"
+[prod]
+HostName = "prod.example.com"
+User = "deploy"
+Port = 2222
+IdentityFile = "deploy"
+PreConnectCommand = "vpn-check"
+KnockSequence = ["tcp:7000", "udp:8000"]
+KnockDelayMs = 250
+RemoteInit = ["export EDITOR=vim"]
+
+[bare]
+"#;
+
+    #[test]
+    fn test_parse_profiles() {
+        let profiles = Profiles::parse(SAMPLE).unwrap();
+
+        let prod = profiles.get("prod").unwrap();
+        assert_eq!(prod.host_name.as_deref(), Some("prod.example.com"));
+        assert_eq!(prod.user.as_deref(), Some("deploy"));
+        assert_eq!(prod.port, Some(2222));
+        assert_eq!(prod.pre_connect_command.as_deref(), Some("vpn-check"));
+        assert_eq!(prod.knock_sequence, vec!["tcp:7000", "udp:8000"]);
+        assert_eq!(prod.knock_delay_ms, Some(250));
+        assert_eq!(prod.remote_init, vec!["export EDITOR=vim"]);
+
+        assert_eq!(profiles.get("bare"), Some(&Profile::default()));
+        assert!(profiles.get("missing").is_none());
+    }
+
+    #[test]
+    fn test_host_names() {
+        let mut profiles = Profiles::parse(SAMPLE).unwrap();
+        profiles.sections.extend(
+            Profiles::parse("[\"*.corp\"]\n[\"web1, web2 !web3\"]\n[prod]\n").unwrap().sections,
+        );
+        assert_eq!(profiles.host_names(), vec!["prod", "bare", "web1", "web2"]);
+        assert_eq!(profiles.hosts()[0].1.port, Some(2222));
+    }
+
+    #[test]
+    fn test_group_members() {
+        let profiles = Profiles::parse(
+            "[\"web1, web2\"]\nGroups = [\"web\", \"eu\"]\n[db1]\nGroups = \"eu\"\n\
+             [\"*.corp\"]\nGroups = \"eu\"\n[web2]\nGroups = \"web\"\n",
+        )
+        .unwrap();
+        assert_eq!(profiles.group_members("web"), vec!["web1", "web2"]);
+        assert_eq!(profiles.group_members("eu"), vec!["web1", "web2", "db1"]);
+        assert!(profiles.group_members("us").is_empty());
+        // Membership is not a setting a host picks up from other sections
+        let resolved = profiles.resolve("db1", None, &FakeEnv::default()).unwrap();
+        assert!(resolved.groups.is_empty());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let profiles = Profiles::parse("[flaky]\nConnectionAttempts = 4\nRetryDelayMs = 200\n[zero]\nConnectionAttempts = 0\n").unwrap();
+        let policy = profiles.get("flaky").unwrap().retry_policy();
+        assert_eq!(policy.max_attempts, 4);
+        assert_eq!(policy.delay(2), std::time::Duration::from_millis(400));
+        assert_eq!(profiles.get("zero").unwrap().retry_policy().max_attempts, 1);
+        assert_eq!(Profile::default().retry_policy().max_attempts, 1);
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let profiles = Profiles::parse(
+            "[lan]\nTcpNoDelay = false\nTcpKeepAliveSecs = 30\nReceiveBufferSize = 1048576\nIPQoS = \"af21 cs1\"\n\
+             [bad]\nIPQoS = \"fast\"\n",
+        )
+        .unwrap();
+        let options = profiles.get("lan").unwrap().socket_options().unwrap();
+        assert_eq!(options.nodelay, Some(false));
+        assert_eq!(options.keepalive, Some(std::time::Duration::from_secs(30)));
+        assert_eq!((options.send_buffer_size, options.recv_buffer_size), (None, Some(1048576)));
+        assert_eq!(options.tos, Some(0x48));
+        assert!(profiles.get("bad").unwrap().socket_options().is_err());
+        assert_eq!(Profile::default().socket_options().unwrap(), Default::default());
+    }
+
+    #[test]
+    fn test_terminal_overrides() {
+        let profiles = Profiles::parse(
+            "[tmux-box]\nTerm = \"screen-256color\"\nAltScreen = false\nKeyBindings = \"passthrough\"\nRecord = true\n\
+             SetLocale = [\"LANG=en_US.UTF-8\", \"LC_TIME=C\"]\n\
+             [bad]\nTerm = \"xterm 256\"\nSetLocale = [\"PATH=/tmp\", \"LANG=$(reboot)\"]\n",
+        )
+        .unwrap();
+        let profile = profiles.get("tmux-box").unwrap();
+        let overrides = profile.terminal_overrides().unwrap();
+        assert_eq!(overrides.term.as_deref(), Some("screen-256color"));
+        assert_eq!(overrides.alt_screen, Some(false));
+        assert_eq!(overrides.key_bindings, crate::terminal::KeyBindings::Passthrough);
+        assert!(overrides.record);
+        assert_eq!(profile.locale_exports().unwrap(), ["export LANG=en_US.UTF-8", "export LC_TIME=C"]);
+
+        let bad = profiles.get("bad").unwrap();
+        assert!(bad.terminal_overrides().is_err());
+        assert!(bad.locale_exports().is_err());
+        for setting in ["LANG=$(reboot)", "LC_=C", "LANG"] {
+            let profile = Profile { set_locale: vec![setting.to_string()], ..Default::default() };
+            assert!(profile.locale_exports().is_err(), "{}", setting);
+        }
+        assert_eq!(Profile::default().terminal_overrides().unwrap(), Default::default());
+        assert!(Profiles::parse("[x]\nKeyBindings = \"vi\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        let err = Profiles::parse("[prod]\nHostname = \"typo\"\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown field"));
+    }
+
+    #[test]
+    fn test_parse_reports_invalid_section() {
+        let err = Profiles::parse("[prod]\nPort = \"ssh\"\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid profile [prod]"));
+    }
+
+    #[test]
+    fn test_host_matches_patterns() {
+        assert!(host_matches("*", "anything"));
+        assert!(host_matches("*.corp", "build.corp"));
+        assert!(host_matches("web?", "WEB1"));
+        assert!(host_matches("db, *.corp", "db"));
+        assert!(!host_matches("*.corp", "corp"));
+        assert!(!host_matches("*.corp,!bastion.corp", "bastion.corp"));
+        assert!(!host_matches("!bastion.corp", "web.corp"));
+        assert!(host_matches("a*b*c", "axxbyyc"));
+        assert!(!host_matches("a*b*c", "axxbyy"));
+        assert!(host_matches("[::1]", "::1"));
+        assert!(host_matches("fe80::*,!fe80::9", "fe80::1%eth0"));
+        assert!(!host_matches("fe80::*,![fe80::9]", "fe80::9"));
+    }
+
+    #[test]
+    fn test_resolve_ipv6_host_name() {
+        let profiles = Profiles::parse("[lab]\nHostName = \"[fd00::5]\"\n\n[\"fd00::*\"]\nUser = \"lab\"\n").unwrap();
+        let lab = profiles.resolve_canonical("lab", None, &FakeEnv::default()).unwrap();
+        assert_eq!(lab.host_name.as_deref(), Some("fd00::5"));
+        let other = profiles.resolve_canonical("fd00::7", None, &FakeEnv::default()).unwrap();
+        assert_eq!(other.user.as_deref(), Some("lab"));
+    }
+
+    #[test]
+    fn test_resolve_merges_matching_sections_in_order() {
+        let profiles = Profiles::parse(
+            r#"
+["*.corp"]
+User = "corp-user"
+Port = 2200
+
+[build]
+HostName = "build.corp"
+Port = 2222
+IdentityFile = ["build", "~/.ssh/id_build"]
+IdentitiesOnly = true
+
+["*"]
+User = "fallback"
+IdentityFile = "default"
+"#,
+        )
+        .unwrap();
+
+        let corp = profiles.resolve("web.corp", None, &FakeEnv::default()).unwrap();
+        assert_eq!(corp.user.as_deref(), Some("corp-user"));
+        assert_eq!(corp.port, Some(2200));
+        assert_eq!(corp.identity_file, ["default"]);
+
+        let build = profiles.resolve("build", None, &FakeEnv::default()).unwrap();
+        assert_eq!(build.host_name.as_deref(), Some("build.corp"));
+        assert_eq!(build.user.as_deref(), Some("fallback"));
+        assert_eq!(build.port, Some(2222));
+        assert_eq!(build.identity_file, ["build", "~/.ssh/id_build"]);
+        assert_eq!(build.identities_only, Some(true));
+    }
+
+    const CANONICAL: &str = r#"
+["*"]
+CanonicalizeHostname = true
+CanonicalDomains = ["eu.corp.example", "corp.example"]
+
+["*.corp.example"]
+User = "corp"
+
+[db]
+HostName = "db01"
+"#;
+
+    #[test]
+    fn test_resolve_canonical_appends_first_resolving_domain() {
+        let profiles = Profiles::parse(CANONICAL).unwrap();
+        let env = FakeEnv { resolvable: vec!["web.corp.example", "db01.eu.corp.example"], ..Default::default() };
+
+        let web = profiles.resolve_canonical("web", None, &env).unwrap();
+        assert_eq!(web.host_name.as_deref(), Some("web.corp.example"));
+        assert_eq!(web.user.as_deref(), Some("corp"));
+
+        // HostName rewrites apply before canonicalization
+        let db = profiles.resolve_canonical("db", None, &env).unwrap();
+        assert_eq!(db.host_name.as_deref(), Some("db01.eu.corp.example"));
+    }
+
+    #[test]
+    fn test_resolve_canonical_runs_match_exec_once() {
+        let profiles = Profiles::parse(
+            r#"
+["*"]
+CanonicalizeHostname = true
+CanonicalDomains = ["corp"]
+
+[off-site]
+Match = { Exec = "on-vpn" }
+PreConnectCommand = "vpn-up"
+"#,
+        )
+        .unwrap();
+        let env = FakeEnv { resolvable: vec!["web.corp"], passing: vec!["on-vpn"], ..Default::default() };
+
+        let profile = profiles.resolve_canonical("web", Some("alice"), &env).unwrap();
+        assert_eq!(profile.host_name.as_deref(), Some("web.corp"));
+        assert_eq!(profile.pre_connect_command.as_deref(), Some("vpn-up"));
+        assert_eq!(env.ran.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_canonicalize_skips_qualified_names_and_addresses() {
+        let profile = Profiles::parse(CANONICAL).unwrap().resolve("x", None, &FakeEnv::default()).unwrap();
+        let resolves = |_: &str| true;
+
+        assert_eq!(profile.canonicalize("a.b.c", resolves).unwrap(), None);
+        assert_eq!(profile.canonicalize("10.0.0.1", resolves).unwrap(), None);
+        assert_eq!(profile.canonicalize("::1", resolves).unwrap(), None);
+        assert_eq!(profile.canonicalize("web.", resolves).unwrap().as_deref(), Some("web"));
+        assert_eq!(profile.canonicalize("web.eu", resolves).unwrap().as_deref(), Some("web.eu.eu.corp.example"));
+        assert_eq!(Profile::default().canonicalize("web", resolves).unwrap(), None);
+    }
+
+    #[test]
+    fn test_canonicalize_fallback_local() {
+        let mut profile = Profiles::parse(CANONICAL).unwrap().resolve("x", None, &FakeEnv::default()).unwrap();
+        assert_eq!(profile.canonicalize("web", |_| false).unwrap(), None);
+
+        profile.canonicalize_fallback_local = Some(false);
+        let err = profile.canonicalize("web", |_| false).unwrap_err();
+        assert!(err.to_string().contains("Could not canonicalize hostname 'web'"));
+    }
+
+    const MATCHES: &str = r#"
+[office]
+Match = { LocalNetwork = "10.1.0.0/16, fd00:1::/32" }
+IdentityFile = "office"
+
+[deploys]
+Match = { Host = "*.corp", User = "deploy" }
+Port = 2222
+
+[off-site]
+Match = { Host = "*.corp", Exec = "on-vpn" }
+PreConnectCommand = "vpn-up"
+
+["*"]
+IdentityFile = "personal"
+"#;
+
+    #[test]
+    fn test_match_local_network() {
+        let profiles = Profiles::parse(MATCHES).unwrap();
+
+        let at_office = FakeEnv { addresses: vec!["10.1.4.20".parse().unwrap()], ..Default::default() };
+        let profile = profiles.resolve("home-nas", None, &at_office).unwrap();
+        assert_eq!(profile.identity_file, ["office"]);
+
+        let v6_office = FakeEnv { addresses: vec!["fd00:1:2::9".parse().unwrap()], ..Default::default() };
+        let profile = profiles.resolve("home-nas", None, &v6_office).unwrap();
+        assert_eq!(profile.identity_file, ["office"]);
+
+        let elsewhere = FakeEnv { addresses: vec!["192.168.1.5".parse().unwrap()], ..Default::default() };
+        let profile = profiles.resolve("home-nas", None, &elsewhere).unwrap();
+        assert_eq!(profile.identity_file, ["personal"]);
+    }
+
+    #[test]
+    fn test_match_host_and_user() {
+        let profiles = Profiles::parse(MATCHES).unwrap();
+        let env = FakeEnv::default();
+
+        assert_eq!(profiles.resolve("web.corp", Some("deploy"), &env).unwrap().port, Some(2222));
+        assert_eq!(profiles.resolve("web.corp", Some("alice"), &env).unwrap().port, None);
+        assert_eq!(profiles.resolve("web.home", Some("deploy"), &env).unwrap().port, None);
+    }
+
+    #[test]
+    fn test_match_exec_runs_only_when_other_criteria_hold() {
+        let profiles = Profiles::parse(MATCHES).unwrap();
+
+        let env = FakeEnv { passing: vec!["on-vpn"], ..Default::default() };
+        let profile = profiles.resolve("web.corp", Some("alice"), &env).unwrap();
+        assert_eq!(profile.pre_connect_command.as_deref(), Some("vpn-up"));
+        assert_eq!(env.ran.borrow().as_slice(), ["on-vpn alice@web.corp"]);
+
+        let env = FakeEnv::default();
+        let profile = profiles.resolve("web.home", Some("alice"), &env).unwrap();
+        assert_eq!(profile.pre_connect_command, None);
+        assert!(env.ran.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_match_sees_earlier_host_name_and_user() {
+        let profiles = Profiles::parse(
+            r#"
+[db]
+HostName = "db.corp"
+User = "deploy"
+
+[deploys]
+Match = { Host = "*.corp", User = "deploy" }
+Port = 2222
+"#,
+        )
+        .unwrap();
+
+        let profile = profiles.resolve("db", None, &FakeEnv::default()).unwrap();
+        assert_eq!(profile.port, Some(2222));
+    }
+
+    #[test]
+    fn test_match_rejects_invalid_network() {
+        let profiles = Profiles::parse("[bad]\nMatch = { LocalNetwork = \"10.0.0.0/33\" }\n").unwrap();
+        let env = FakeEnv { addresses: vec!["10.0.0.1".parse().unwrap()], ..Default::default() };
+        let err = profiles.resolve("host", None, &env).unwrap_err();
+        assert!(format!("{:#}", err).contains("In profile [bad]: Invalid network '10.0.0.0/33'"));
+    }
+
+    #[test]
+    fn test_network_contains() {
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(network_contains("10.0.0.0/8", addr("10.200.3.4")).unwrap());
+        assert!(!network_contains("10.0.0.0/8", addr("11.0.0.1")).unwrap());
+        assert!(network_contains("0.0.0.0/0", addr("8.8.8.8")).unwrap());
+        assert!(network_contains("192.168.1.7", addr("192.168.1.7")).unwrap());
+        assert!(!network_contains("10.0.0.0/8", addr("::ffff:10.0.0.1")).unwrap());
+        assert!(network_contains("fe80::/10", addr("fe80::1")).unwrap());
+        assert!(network_contains("not-a-network", addr("10.0.0.1")).is_err());
+    }
+
+    #[test]
+    fn test_resolve_precedence_profile_then_patterns_then_global() {
+        let profiles = Profiles::parse(
+            r#"
+["*"]
+User = "global"
+Port = 1000
+
+["prod*"]
+User = "pattern"
+IdentityFile = "pattern"
+
+[prod]
+User = "profile"
+"#,
+        )
+        .unwrap();
+
+        let (profile, applied) = profiles.resolve_traced("prod", None, &FakeEnv::default()).unwrap();
+        assert_eq!(profile.user.as_deref(), Some("profile"));
+        assert_eq!(profile.identity_file, ["pattern"]);
+        assert_eq!(profile.port, Some(1000));
+        assert_eq!(applied, ["[prod]", "[prod*]", "[*]"]);
+    }
+
+    #[test]
+    fn test_load_follows_includes() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("profiles.toml");
+        std::fs::create_dir(temp.path().join("profiles.d")).unwrap();
+        std::fs::write(temp.path().join("profiles.d").join("a.toml"), "[a]\nPort = 1\n").unwrap();
+        std::fs::write(temp.path().join("profiles.d").join("b.toml"), "[b]\nPort = 2\n").unwrap();
+        std::fs::write(&path, "Include = [\"profiles.d/*.toml\", \"missing.toml\"]\n\n[main]\nPort = 3\n").unwrap();
+
+        let profiles = Profiles::load_from(&path).unwrap();
+
+        let names: Vec<&str> = profiles.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "main"]);
+        assert_eq!(profiles.sections[0].source.as_deref(), Some(temp.path().join("profiles.d").join("a.toml").as_path()));
+        assert_eq!(profiles.get("b").unwrap().port, Some(2));
+    }
+
+    #[test]
+    fn test_include_rejected_outside_files() {
+        let err = Profiles::parse("Include = \"other.toml\"\n").unwrap_err();
+        assert!(err.to_string().contains("Include is only supported in profile files"));
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("profiles.toml");
+        std::fs::write(&path, "Include = 3\n").unwrap();
+        let err = Profiles::load_from(&path).unwrap_err();
+        assert!(format!("{:#}", err).contains("Expected a path or list of paths"));
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let temp = TempDir::new().unwrap();
+        let profiles = Profiles::load_from(&temp.path().join("profiles.toml")).unwrap();
+        assert_eq!(profiles, Profiles::default());
+    }
+
+    #[test]
+    fn test_load_from_file() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("profiles.toml");
+        std::fs::write(&path, SAMPLE).unwrap();
+
+        let profiles = Profiles::load_from(&path).unwrap();
+        assert!(profiles.get("prod").is_some());
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Machine-readable lifecycle events emitted with `--progress-json`
+/// Serialized as one JSON object per line with an `event` tag, e.g.
+/// `{"event":"connecting","host":"example.com","port":22,"timestamp":1700000000.5}`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ProgressEvent {
+    Connecting { host: String, port: u16 },
+    Authenticated { user: String, method: String },
+    ExecStarted { command: String },
+    BytesTransferred { sent: u64, received: u64 },
+    Closed { exit_status: Option<i32> },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(flatten)]
+    event: &'a ProgressEvent,
+    timestamp: f64,
+}
+
+/// Writes progress events as NDJSON to any writer
+pub struct ProgressEmitter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl ProgressEmitter {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self { writer }
+    }
+
+    pub fn emit(&mut self, event: &ProgressEvent) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+
+        let line = serde_json::to_string(&Record { event, timestamp })
+            .context("Failed to serialize progress event")?;
+        writeln!(self.writer, "{}", line).context("Failed to write progress event")?;
+        self.writer.flush().context("Failed to flush progress event")
+    }
+}
+
+static EMITTER: OnceLock<Mutex<ProgressEmitter>> = OnceLock::new();
+
+/// Route progress events to stderr, keeping stdout free for remote output
+pub fn enable_stderr() {
+    let _ = EMITTER.set(Mutex::new(ProgressEmitter::new(Box::new(std::io::stderr()))));
+}
+
+/// Emit an event if progress reporting is enabled; failures are logged, never fatal
+pub fn emit(event: ProgressEvent) {
+    if let Some(emitter) = EMITTER.get() {
+        if let Ok(mut emitter) = emitter.lock() {
+            if let Err(e) = emitter.emit(&event) {
+                log::debug!("Failed to emit progress event: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn emit_to_string(events: &[ProgressEvent]) -> String {
+        let buffer = SharedBuffer::default();
+        let mut emitter = ProgressEmitter::new(Box::new(buffer.clone()));
+        for event in events {
+            emitter.emit(event).unwrap();
+        }
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_emit_connecting_event() {
+        let output = emit_to_string(&[ProgressEvent::Connecting {
+            host: "example.com".to_string(),
+            port: 22,
+        }]);
+
+        let value: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(value["event"], "connecting");
+        assert_eq!(value["host"], "example.com");
+        assert_eq!(value["port"], 22);
+        assert!(value["timestamp"].as_f64().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_emit_is_newline_delimited() {
+        let output = emit_to_string(&[
+            ProgressEvent::ExecStarted { command: "ls".to_string() },
+            ProgressEvent::BytesTransferred { sent: 10, received: 2048 },
+            ProgressEvent::Closed { exit_status: Some(0) },
+        ]);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+
+        let events: Vec<serde_json::Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events[0]["event"], "exec-started");
+        assert_eq!(events[1]["event"], "bytes-transferred");
+        assert_eq!(events[1]["received"], 2048);
+        assert_eq!(events[2]["event"], "closed");
+        assert_eq!(events[2]["exit_status"], 0);
+    }
+
+    #[test]
+    fn test_emit_without_exit_status() {
+        let output = emit_to_string(&[ProgressEvent::Closed { exit_status: None }]);
+        let value: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert!(value["exit_status"].is_null());
+    }
+}
@@ -0,0 +1,160 @@
+//! Recently used connections (`~/.local/state/bxssh/recent.json`), newest first, and the
+//! list `bxssh recent` picks from
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Connections kept; the oldest drop off
+pub const MAX_RECENT: usize = 20;
+
+/// One host logged into, as it was typed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentConnection {
+    /// Host or profile name before profile resolution, so reconnecting applies
+    /// the profile as it is then
+    pub host: String,
+    pub user: String,
+    pub port: u16,
+    pub last_connected: DateTime<Utc>,
+}
+
+impl RecentConnection {
+    /// `user@host`, with `:port` when it is not 22
+    pub fn label(&self) -> String {
+        match self.port {
+            22 => format!("{}@{}", self.user, self.host),
+            port => format!("{}@{}", self.user, crate::remote_target::host_port(&self.host, port)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecentList {
+    pub connections: Vec<RecentConnection>,
+}
+
+impl RecentList {
+    /// `~/.local/state/bxssh/recent.json`
+    pub fn path() -> Result<PathBuf> {
+        Ok(crate::paths::state_dir()?.join("recent.json"))
+    }
+
+    /// The list in `path`; a missing file is an empty list
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&content).with_context(|| format!("Invalid recent connections file {}", path.display()))
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        crate::private_file::write(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Move `user@host:port` to the front, stamped `now`, keeping at most [`MAX_RECENT`]
+    pub fn record(&mut self, host: &str, user: &str, port: u16, now: DateTime<Utc>) {
+        self.connections
+            .retain(|connection| !(connection.host == host && connection.user == user && connection.port == port));
+        self.connections.insert(
+            0,
+            RecentConnection { host: host.to_string(), user: user.to_string(), port, last_connected: now },
+        );
+        self.connections.truncate(MAX_RECENT);
+    }
+}
+
+/// Record a login in `~/.local/state/bxssh/recent.json`. Failures are only logged: a
+/// read-only home must not stop a connection.
+pub fn remember(host: &str, user: &str, port: u16) {
+    let recorded = RecentList::path().and_then(|path| {
+        let mut list = RecentList::load_from(&path)?;
+        list.record(host, user, port, Utc::now());
+        list.save_to(&path)
+    });
+    if let Err(e) = recorded {
+        log::debug!("Failed to record recent connection: {:#}", e);
+    }
+}
+
+/// An entry of the `bxssh recent` picker
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choice {
+    Recent(RecentConnection),
+    /// Named profile not connected to recently
+    Profile(String),
+}
+
+/// Recent connections, newest first, then the named profiles none of them used
+pub fn choices(recent: &RecentList, profile_names: &[String]) -> Vec<Choice> {
+    let mut choices: Vec<Choice> = recent.connections.iter().cloned().map(Choice::Recent).collect();
+    for name in profile_names {
+        if !recent.connections.iter().any(|connection| &connection.host == name) {
+            choices.push(Choice::Profile(name.clone()));
+        }
+    }
+    choices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 5, 1, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_record_moves_to_front_and_caps() {
+        let mut list = RecentList::default();
+        list.record("prod", "deploy", 22, at(1));
+        list.record("db", "admin", 2222, at(2));
+        list.record("prod", "deploy", 22, at(3));
+        assert_eq!(list.connections.len(), 2);
+        assert_eq!(list.connections[0].label(), "deploy@prod");
+        assert_eq!(list.connections[0].last_connected, at(3));
+        assert_eq!(list.connections[1].label(), "admin@db:2222");
+
+        // Another user or port is another entry
+        list.record("prod", "root", 22, at(4));
+        assert_eq!(list.connections.len(), 3);
+
+        for i in 0..MAX_RECENT {
+            list.record(&format!("host{}", i), "me", 22, at(5));
+        }
+        assert_eq!(list.connections.len(), MAX_RECENT);
+        assert_eq!(list.connections[0].host, format!("host{}", MAX_RECENT - 1));
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(".bxssh").join("recent.json");
+        assert_eq!(RecentList::load_from(&path).unwrap(), RecentList::default());
+
+        let mut list = RecentList::default();
+        list.record("prod", "deploy", 22, at(1));
+        list.save_to(&path).unwrap();
+        assert_eq!(RecentList::load_from(&path).unwrap(), list);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(RecentList::load_from(&path).is_err());
+    }
+
+    #[test]
+    fn test_choices_add_unused_profiles() {
+        let mut list = RecentList::default();
+        list.record("prod", "deploy", 22, at(1));
+        let profiles = vec!["prod".to_string(), "staging".to_string()];
+        let choices = choices(&list, &profiles);
+        assert_eq!(choices.len(), 2);
+        assert!(matches!(&choices[0], Choice::Recent(connection) if connection.host == "prod"));
+        assert_eq!(choices[1], Choice::Profile("staging".to_string()));
+    }
+}
@@ -0,0 +1,85 @@
+//! `--record` and the profile key `Record`: everything an interactive session
+//! displays, saved to `~/.local/state/bxssh/recordings/HOST-YYYYMMDD-HHMMSS.log`.
+//! `cat` a recording in a terminal of the same size to see the session again.
+use anyhow::Result;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::terminal::SessionHook;
+
+/// Directory under the state directory that holds recordings
+pub const RECORDINGS_DIR: &str = "recordings";
+
+/// Session hook appending the output to a file only the user can read
+pub struct Recording {
+    file: std::io::BufWriter<std::fs::File>,
+    path: PathBuf,
+    failed: bool,
+}
+
+impl Recording {
+    /// Start a recording of a session with `host` in `dir`
+    pub fn create(dir: &Path, host: &str) -> Result<Self> {
+        crate::private_file::create_dir(dir)?;
+        let path = dir.join(file_name(host, chrono::Local::now().naive_local()));
+        let file = crate::private_file::create_file(&path)?;
+        Ok(Self { file: std::io::BufWriter::new(file), path, failed: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// `HOST-YYYYMMDD-HHMMSS.log`, with characters that don't belong in a file name replaced
+fn file_name(host: &str, started: chrono::NaiveDateTime) -> String {
+    let host: String = host
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    format!("{}-{}.log", host, started.format("%Y%m%d-%H%M%S"))
+}
+
+impl SessionHook for Recording {
+    fn on_output(&mut self, data: &[u8]) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.file.write_all(data) {
+            // Once is enough; the session itself carries on
+            log::warn!("Stopped recording to {}: {}", self.path.display(), e);
+            self.failed = true;
+        }
+    }
+
+    fn poll(&mut self) -> bool {
+        if let Err(e) = self.file.flush() {
+            log::debug!("Failed to flush {}: {}", self.path.display(), e);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let started = chrono::NaiveDate::from_ymd_opt(2026, 3, 9).unwrap().and_hms_opt(14, 5, 7).unwrap();
+        assert_eq!(file_name("db-1.example.com", started), "db-1.example.com-20260309-140507.log");
+        assert_eq!(file_name("[fe80::1]", started), "_fe80__1_-20260309-140507.log");
+    }
+
+    #[test]
+    fn test_records_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut recording = Recording::create(&dir.path().join(RECORDINGS_DIR), "web").unwrap();
+        recording.on_output(b"$ ls\r\n");
+        recording.on_output(b"README.md\r\n");
+        assert!(!recording.poll());
+        let path = recording.path().to_path_buf();
+        assert!(path.starts_with(dir.path().join(RECORDINGS_DIR)));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "$ ls\r\nREADME.md\r\n");
+    }
+}
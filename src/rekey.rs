@@ -0,0 +1,217 @@
+use anyhow::Result;
+
+/// RFC 4253 section 9: re-exchange keys after each gigabyte of traffic...
+pub const DEFAULT_MAX_BYTES: u64 = 1 << 30;
+
+/// ...or each hour, whichever comes first
+pub const DEFAULT_MAX_INTERVAL_MS: f64 = 3_600_000.0;
+
+/// Packets per direction before keys must change. RFC 4344 section 3.1 limits a
+/// 128-bit block cipher to 2^32 blocks per key; a cap well below that also keeps
+/// the 32-bit sequence numbers from wrapping.
+pub const MAX_PACKETS: u64 = 1 << 31;
+
+/// When the transport should start a new key exchange
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RekeyPolicy {
+    /// Bytes in either direction since the last exchange
+    pub max_bytes: u64,
+    /// Milliseconds since the last exchange; `None` never rekeys on time alone
+    pub max_interval_ms: Option<f64>,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_interval_ms: Some(DEFAULT_MAX_INTERVAL_MS),
+        }
+    }
+}
+
+impl RekeyPolicy {
+    /// Parse an OpenSSH `RekeyLimit` value: a data amount (`500M`, `1G`, `default`)
+    /// optionally followed by a time (`30m`, `1h`, `none`, `default`)
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut fields = spec.split_whitespace();
+        let amount = fields.next().ok_or_else(|| anyhow::anyhow!("Empty RekeyLimit"))?;
+        let mut policy = Self::default();
+
+        if amount != "default" {
+            policy.max_bytes = parse_amount(amount)?;
+        }
+        match fields.next() {
+            None | Some("default") => {}
+            Some("none") => policy.max_interval_ms = None,
+            Some(time) => policy.max_interval_ms = Some(parse_interval_ms(time)?),
+        }
+        if let Some(extra) = fields.next() {
+            return Err(anyhow::anyhow!("Unexpected '{}' in RekeyLimit", extra));
+        }
+        Ok(policy)
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<u64> {
+    let (digits, multiplier) = match amount.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&amount[..i], 1u64 << 10),
+        Some((i, 'M' | 'm')) => (&amount[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&amount[..i], 1 << 30),
+        _ => (amount, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        // OpenSSH's lower bound: rekeying more often than this is pure overhead
+        .filter(|bytes| *bytes >= 16)
+        .ok_or_else(|| anyhow::anyhow!("Invalid RekeyLimit amount '{}'", amount))
+}
+
+/// OpenSSH time format: a number with an optional s/m/h/d/w unit, or several
+/// concatenated (`1h30m`)
+fn parse_interval_ms(time: &str) -> Result<f64> {
+    let invalid = || anyhow::anyhow!("Invalid RekeyLimit time '{}'", time);
+    let mut total = 0u64;
+    let mut digits = String::new();
+    for c in time.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3_600,
+            'd' => 86_400,
+            'w' => 604_800,
+            _ => return Err(invalid()),
+        };
+        total += digits.parse::<u64>().map_err(|_| invalid())? * unit;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        total += digits.parse::<u64>().map_err(|_| invalid())?;
+    }
+    if total == 0 {
+        return Err(invalid());
+    }
+    Ok(total as f64 * 1000.0)
+}
+
+/// Why a rekey is due
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RekeyReason {
+    Bytes,
+    Packets,
+    Time,
+}
+
+/// Counts traffic since the last key exchange and says when the policy wants a
+/// new one. Time is passed in as milliseconds so the browser clock can drive it.
+#[derive(Debug, Clone)]
+pub struct RekeyTracker {
+    policy: RekeyPolicy,
+    started_ms: f64,
+    bytes: u64,
+    packets_sent: u64,
+    packets_received: u64,
+}
+
+impl RekeyTracker {
+    pub fn new(policy: RekeyPolicy, now_ms: f64) -> Self {
+        Self {
+            policy,
+            started_ms: now_ms,
+            bytes: 0,
+            packets_sent: 0,
+            packets_received: 0,
+        }
+    }
+
+    pub fn policy(&self) -> RekeyPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: RekeyPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.bytes = self.bytes.saturating_add(bytes as u64);
+        self.packets_sent += 1;
+    }
+
+    pub fn record_received(&mut self, bytes: usize) {
+        self.bytes = self.bytes.saturating_add(bytes as u64);
+        self.packets_received += 1;
+    }
+
+    /// Whether a new exchange should start before more data is sent
+    pub fn due(&self, now_ms: f64) -> Option<RekeyReason> {
+        if self.bytes >= self.policy.max_bytes {
+            Some(RekeyReason::Bytes)
+        } else if self.packets_sent.max(self.packets_received) >= MAX_PACKETS {
+            Some(RekeyReason::Packets)
+        } else if self.policy.max_interval_ms.is_some_and(|max| now_ms - self.started_ms >= max) {
+            Some(RekeyReason::Time)
+        } else {
+            None
+        }
+    }
+
+    /// Start counting afresh after new keys are in use
+    pub fn reset(&mut self, now_ms: f64) {
+        *self = Self::new(self.policy, now_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rekey_limit() {
+        assert_eq!(RekeyPolicy::parse("default").unwrap(), RekeyPolicy::default());
+        let policy = RekeyPolicy::parse("500M 30m").unwrap();
+        assert_eq!(policy.max_bytes, 500 << 20);
+        assert_eq!(policy.max_interval_ms, Some(1_800_000.0));
+
+        let policy = RekeyPolicy::parse("4G none").unwrap();
+        assert_eq!(policy.max_bytes, 4 << 30);
+        assert_eq!(policy.max_interval_ms, None);
+
+        assert_eq!(RekeyPolicy::parse("default 1h30m").unwrap().max_interval_ms, Some(5_400_000.0));
+        assert_eq!(RekeyPolicy::parse("64K 90").unwrap().max_interval_ms, Some(90_000.0));
+
+        for bad in ["", "lots", "1X", "8", "1G soon", "1G 0", "1G 1h extra", "99999999999999999999G"] {
+            assert!(RekeyPolicy::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_tracker_thresholds() {
+        let policy = RekeyPolicy {
+            max_bytes: 1000,
+            max_interval_ms: Some(60_000.0),
+        };
+        let mut tracker = RekeyTracker::new(policy, 0.0);
+        tracker.record_sent(400);
+        tracker.record_received(500);
+        assert_eq!(tracker.due(1.0), None);
+        tracker.record_sent(100);
+        assert_eq!(tracker.due(1.0), Some(RekeyReason::Bytes));
+
+        tracker.reset(10_000.0);
+        assert_eq!(tracker.due(69_999.0), None);
+        assert_eq!(tracker.due(70_000.0), Some(RekeyReason::Time));
+
+        tracker.set_policy(RekeyPolicy {
+            max_bytes: u64::MAX,
+            max_interval_ms: None,
+        });
+        assert_eq!(tracker.due(f64::MAX), None);
+        tracker.packets_received = MAX_PACKETS;
+        assert_eq!(tracker.due(0.0), Some(RekeyReason::Packets));
+    }
+}
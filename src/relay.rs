@@ -0,0 +1,480 @@
+use anyhow::Result;
+
+/// First word of every relay request; bumped if the handshake ever changes
+pub const PROTOCOL: &str = "BXSSH-RELAY/1";
+
+/// Longest request line the relay reads before giving up on a client
+pub const MAX_REQUEST_LINE: usize = 1024;
+
+/// Opening line of a relayed stream: `BXSSH-RELAY/1 CONNECT <host> <port> [<token>]`.
+/// The relay answers `OK` or `ERR <reason>` on a line of its own, then the stream
+/// carries the raw SSH connection in both directions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectRequest {
+    pub host: String,
+    pub port: u16,
+    pub token: Option<String>,
+}
+
+impl ConnectRequest {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// The request line, newline included
+    #[allow(dead_code)] // Client side; used by the WASM relay transport
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        for (what, value) in [("host", Some(&self.host)), ("token", self.token.as_ref())] {
+            if value.is_some_and(|value| value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control())) {
+                return Err(anyhow::anyhow!("Relay {} must be a single non-empty word", what));
+            }
+        }
+
+        let mut line = format!("{} CONNECT {} {}", PROTOCOL, self.host, self.port);
+        if let Some(token) = &self.token {
+            line.push(' ');
+            line.push_str(token);
+        }
+        line.push('\n');
+        Ok(line.into_bytes())
+    }
+
+    /// Parse a request line, with or without its trailing newline
+    pub fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split(' ').collect();
+        match fields.as_slice() {
+            [PROTOCOL, "CONNECT", host, port, rest @ ..] if !host.is_empty() && rest.len() <= 1 => {
+                let port = port
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid port '{}'", port))?;
+                let token = rest.first().filter(|token| !token.is_empty()).map(|token| token.to_string());
+                Ok(Self::new(host, port).with_token(token))
+            }
+            [protocol, ..] if protocol.starts_with("BXSSH-RELAY/") && *protocol != PROTOCOL => {
+                Err(anyhow::anyhow!("Unsupported relay protocol '{}'", protocol))
+            }
+            _ => Err(anyhow::anyhow!("Malformed relay request")),
+        }
+    }
+}
+
+/// The relay's answer to a request, newline included
+pub fn encode_reply(result: &Result<()>) -> Vec<u8> {
+    match result {
+        Ok(()) => b"OK\n".to_vec(),
+        // Keep the reason on one line whatever the underlying error says
+        Err(error) => format!("ERR {}\n", format!("{:#}", error).replace(['\r', '\n'], " ")).into_bytes(),
+    }
+}
+
+/// Check the relay's answer line
+#[allow(dead_code)] // Client side; used by the WASM relay transport
+pub fn parse_reply(line: &str) -> Result<()> {
+    match line.trim_end_matches(['\r', '\n']) {
+        "OK" => Ok(()),
+        line => match line.strip_prefix("ERR ") {
+            Some(reason) => Err(anyhow::anyhow!("Relay refused connection: {}", reason)),
+            None => Err(anyhow::anyhow!("Unexpected relay reply '{}'", line)),
+        },
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use server::{serve, RelayPolicy};
+
+/// The `bxssh relay` daemon: accepts relay streams over WebSocket or plain TCP and
+/// bridges each to the allow-listed SSH server it names
+#[cfg(not(target_arch = "wasm32"))]
+mod server {
+    use super::{encode_reply, ConnectRequest, MAX_REQUEST_LINE};
+    use crate::websocket::WebSocket;
+    use anyhow::{Context, Result};
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// How long a client has to send its request, and the relay to reach the target
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Port allow-list entries without one refer to
+    const SSH_PORT: u16 = 22;
+
+    /// Which targets the relay connects to, the token clients must present and
+    /// the pages browsers may connect from
+    #[derive(Debug, Clone)]
+    pub struct RelayPolicy {
+        allow: Vec<(String, u16)>,
+        token: Option<String>,
+        origins: Vec<String>,
+    }
+
+    impl RelayPolicy {
+        /// `allow` entries are host patterns as in profiles.toml (`*.internal`,
+        /// `db-?`), optionally with `:port`; without one only port 22 is allowed
+        pub fn new<S: AsRef<str>>(allow: &[S], token: Option<String>) -> Result<Self> {
+            let allow = allow
+                .iter()
+                .map(|entry| parse_allow_entry(entry.as_ref()))
+                .collect::<Result<Vec<_>>>()?;
+            if allow.is_empty() {
+                return Err(anyhow::anyhow!("The relay needs at least one allowed host"));
+            }
+            Ok(Self {
+                allow,
+                token: token.filter(|token| !token.is_empty()),
+                origins: Vec::new(),
+            })
+        }
+
+        /// Let browser pages from `origins` (`https://app.example.com`) open WebSockets;
+        /// without any, only pages served by the relay's own host may
+        pub fn with_origins(mut self, origins: Vec<String>) -> Self {
+            self.origins = origins;
+            self
+        }
+
+        pub fn check(&self, request: &ConnectRequest) -> Result<()> {
+            if let Some(expected) = &self.token {
+                let presented = request.token.as_deref().unwrap_or_default();
+                if !constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+                    return Err(anyhow::anyhow!("Invalid relay token"));
+                }
+            }
+            let allowed = self
+                .allow
+                .iter()
+                .any(|(pattern, port)| *port == request.port && crate::profiles::host_matches(pattern, &request.host));
+            if !allowed {
+                return Err(anyhow::anyhow!("{}:{} is not allowed", request.host, request.port));
+            }
+            Ok(())
+        }
+    }
+
+    fn parse_allow_entry(entry: &str) -> Result<(String, u16)> {
+        let entry = entry.trim();
+        let (host, port) = match entry.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+            Some((host, rest)) => (host, rest.strip_prefix(':')),
+            None => match entry.rsplit_once(':') {
+                Some((host, port)) if !host.contains(':') => (host, Some(port)),
+                _ => (entry, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("Invalid allowed host '{}'", entry));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port != 0)
+                .ok_or_else(|| anyhow::anyhow!("Invalid port in allowed host '{}'", entry))?,
+            None => SSH_PORT,
+        };
+        Ok((host.to_string(), port))
+    }
+
+    /// Compare tokens without leaking how long a matching prefix was
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+
+    /// Serve relay clients until the listener fails; each runs on its own thread
+    pub fn serve(listener: TcpListener, policy: RelayPolicy) -> Result<()> {
+        let policy = Arc::new(policy);
+        for stream in listener.incoming() {
+            let stream = stream.context("Failed to accept relay client")?;
+            let policy = Arc::clone(&policy);
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                if let Err(e) = handle(stream, &peer, &policy) {
+                    log::warn!("Relay client {}: {:#}", peer, e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Browsers open a WebSocket and send the request as the first message;
+    /// other clients send the request line directly on the TCP stream
+    fn handle(mut client: TcpStream, peer: &str, policy: &RelayPolicy) -> Result<()> {
+        client.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let first_line = read_line(&mut client)?;
+
+        if first_line.starts_with("GET ") {
+            let mut socket = WebSocket::accept(client, &first_line, &policy.origins)?;
+            let request = socket
+                .recv()?
+                .ok_or_else(|| anyhow::anyhow!("Client closed before sending a request"))?;
+            let target = String::from_utf8(request)
+                .context("Relay request is not UTF-8")
+                .and_then(|line| open_target(&line, peer, policy));
+            socket.send(&encode_reply(&target.as_ref().map(|_| ()).map_err(|e| anyhow::anyhow!("{:#}", e))))?;
+            socket.set_read_timeout(None)?;
+            return bridge_websocket(socket, target?);
+        }
+
+        let target = open_target(&first_line, peer, policy);
+        client.write_all(&encode_reply(&target.as_ref().map(|_| ()).map_err(|e| anyhow::anyhow!("{:#}", e))))?;
+        client.set_read_timeout(None)?;
+        bridge(client, target?)
+    }
+
+    fn open_target(line: &str, peer: &str, policy: &RelayPolicy) -> Result<TcpStream> {
+        let request = ConnectRequest::parse(line)?;
+        policy.check(&request)?;
+        log::info!("Relaying {} to {}:{}", peer, request.host, request.port);
+        connect(&request)
+    }
+
+    /// Read one line one byte at a time, so nothing the client sends after it is
+    /// consumed before the target connection exists
+    fn read_line(client: &mut TcpStream) -> Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        while line.len() < MAX_REQUEST_LINE {
+            if client.read(&mut byte).context("Failed to read relay request")? == 0 {
+                return Err(anyhow::anyhow!("Client closed before sending a request"));
+            }
+            if byte[0] == b'\n' {
+                let line = String::from_utf8(line).context("Relay request is not UTF-8")?;
+                return Ok(line.trim_end_matches('\r').to_string());
+            }
+            line.push(byte[0]);
+        }
+        Err(anyhow::anyhow!("Relay request longer than {} bytes", MAX_REQUEST_LINE))
+    }
+
+    fn connect(request: &ConnectRequest) -> Result<TcpStream> {
+        let addrs = (request.host.as_str(), request.port)
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", request.host))?;
+        let mut last_error = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(match last_error {
+            Some(e) => anyhow::Error::new(e).context(format!("Failed to connect to {}:{}", request.host, request.port)),
+            None => anyhow::anyhow!("{} has no addresses", request.host),
+        })
+    }
+
+    /// Copy bytes both ways until either side closes, passing half-closes through
+    fn bridge(client: TcpStream, target: TcpStream) -> Result<()> {
+        let (mut client_reader, mut target_writer) = (client.try_clone()?, target.try_clone()?);
+        let upstream = std::thread::spawn(move || {
+            let result = std::io::copy(&mut client_reader, &mut target_writer);
+            let _ = target_writer.shutdown(Shutdown::Write);
+            result
+        });
+
+        let (mut target_reader, mut client_writer) = (target, client);
+        let downstream = std::io::copy(&mut target_reader, &mut client_writer);
+        let _ = client_writer.shutdown(Shutdown::Write);
+
+        let upstream = upstream.join().map_err(|_| anyhow::anyhow!("Relay thread panicked"))?;
+        upstream.and(downstream).map(|_| ()).context("Relay stream failed")
+    }
+
+    /// As [`bridge`], with each WebSocket message carrying a chunk of the stream
+    fn bridge_websocket(socket: WebSocket, target: TcpStream) -> Result<()> {
+        let (mut socket_reader, mut target_writer) = (socket.try_clone()?, target.try_clone()?);
+        let upstream = std::thread::spawn(move || -> Result<()> {
+            let result = (|| {
+                while let Some(message) = socket_reader.recv()? {
+                    target_writer.write_all(&message)?;
+                }
+                Ok(())
+            })();
+            let _ = target_writer.shutdown(Shutdown::Write);
+            result
+        });
+
+        let mut target_reader = target;
+        let mut buf = [0u8; 16 * 1024];
+        let downstream = (|| loop {
+            match target_reader.read(&mut buf)? {
+                0 => return socket.close(),
+                n => socket.send(&buf[..n])?,
+            }
+        })();
+
+        let upstream = upstream.join().map_err(|_| anyhow::anyhow!("Relay thread panicked"))?;
+        upstream.and(downstream).context("Relay stream failed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_round_trip() {
+        let request = ConnectRequest::new("db.internal", 22);
+        assert_eq!(request.encode().unwrap(), b"BXSSH-RELAY/1 CONNECT db.internal 22\n");
+        assert_eq!(ConnectRequest::parse("BXSSH-RELAY/1 CONNECT db.internal 22\n").unwrap(), request);
+
+        let request = request.with_token(Some("s3cret".to_string()));
+        let line = String::from_utf8(request.encode().unwrap()).unwrap();
+        assert_eq!(ConnectRequest::parse(&line).unwrap(), request);
+    }
+
+    #[test]
+    fn test_request_rejects_malformed_lines() {
+        for line in [
+            "",
+            "CONNECT host 22",
+            "BXSSH-RELAY/1 CONNECT host",
+            "BXSSH-RELAY/1 CONNECT host 0",
+            "BXSSH-RELAY/1 CONNECT host 70000",
+            "BXSSH-RELAY/1 CONNECT host 22 token extra",
+            "BXSSH-RELAY/1 CONNECT  22",
+        ] {
+            assert!(ConnectRequest::parse(line).is_err(), "{:?}", line);
+        }
+        let err = ConnectRequest::parse("BXSSH-RELAY/2 CONNECT host 22").unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported relay protocol 'BXSSH-RELAY/2'");
+
+        assert!(ConnectRequest::new("evil host", 22).encode().is_err());
+        assert!(ConnectRequest::new("host", 22).with_token(Some("a\nb".to_string())).encode().is_err());
+    }
+
+    #[test]
+    fn test_replies() {
+        assert!(parse_reply("OK\n").is_ok());
+        let refused = encode_reply(&Err(anyhow::anyhow!("no route\nto host")));
+        assert_eq!(refused, b"ERR no route to host\n");
+        let err = parse_reply(std::str::from_utf8(&refused).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "Relay refused connection: no route to host");
+        assert!(parse_reply("HTTP/1.1 400 Bad Request").is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    mod server {
+        use super::super::*;
+        use crate::websocket::tests::{client_frame, client_handshake, server_frame};
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::{SocketAddr, TcpListener, TcpStream};
+
+        fn start_relay(allow: &[String], token: Option<&str>) -> SocketAddr {
+            let policy = RelayPolicy::new(allow, token.map(str::to_string)).unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            std::thread::spawn(move || serve(listener, policy));
+            addr
+        }
+
+        /// A target that greets, then echoes the next four bytes back
+        fn start_target() -> u16 {
+            let target = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = target.local_addr().unwrap().port();
+            std::thread::spawn(move || {
+                let (mut stream, _) = target.accept().unwrap();
+                stream.write_all(b"SSH-2.0-test\r\n").unwrap();
+                let mut echo = [0u8; 4];
+                stream.read_exact(&mut echo).unwrap();
+                stream.write_all(&echo).unwrap();
+            });
+            port
+        }
+
+        fn open(relay: SocketAddr, request: &ConnectRequest) -> (BufReader<TcpStream>, String) {
+            let mut stream = TcpStream::connect(relay).unwrap();
+            stream.write_all(&request.encode().unwrap()).unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut reply = String::new();
+            reader.read_line(&mut reply).unwrap();
+            (reader, reply)
+        }
+
+        #[test]
+        fn test_policy() {
+            let policy = RelayPolicy::new(&["bastion", "*.internal:2222", "[::1]:22"], Some("s3cret".to_string())).unwrap();
+            let request = |host: &str, port: u16, token: &str| {
+                policy.check(&ConnectRequest::new(host, port).with_token(Some(token.to_string())))
+            };
+
+            assert!(request("bastion", 22, "s3cret").is_ok());
+            assert!(request("BASTION", 22, "s3cret").is_ok());
+            assert!(request("db.internal", 2222, "s3cret").is_ok());
+            assert!(request("::1", 22, "s3cret").is_ok());
+            assert_eq!(request("bastion", 2222, "s3cret").unwrap_err().to_string(), "bastion:2222 is not allowed");
+            assert!(request("db.internal", 22, "s3cret").is_err());
+            assert!(request("other", 22, "s3cret").is_err());
+            assert_eq!(request("bastion", 22, "s3cre").unwrap_err().to_string(), "Invalid relay token");
+            assert!(policy.check(&ConnectRequest::new("bastion", 22)).is_err());
+
+            assert!(RelayPolicy::new(&["bastion"], None).unwrap().check(&ConnectRequest::new("bastion", 22)).is_ok());
+            assert!(RelayPolicy::new::<&str>(&[], None).is_err());
+            assert!(RelayPolicy::new(&["host:0"], None).is_err());
+            assert!(RelayPolicy::new(&[":22"], None).is_err());
+        }
+
+        #[test]
+        fn test_relay_bridges_to_target() {
+            let port = start_target();
+            let relay = start_relay(&[format!("127.0.0.1:{}", port)], None);
+
+            let (mut reader, reply) = open(relay, &ConnectRequest::new("127.0.0.1", port));
+            assert!(parse_reply(&reply).is_ok());
+            let mut banner = String::new();
+            reader.read_line(&mut banner).unwrap();
+            assert_eq!(banner, "SSH-2.0-test\r\n");
+
+            reader.get_mut().write_all(b"ping").unwrap();
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, b"ping");
+        }
+
+        #[test]
+        fn test_relay_refuses_unlisted_targets() {
+            let relay = start_relay(&["bastion".to_string()], Some("s3cret"));
+            let (_, reply) = open(relay, &ConnectRequest::new("127.0.0.1", 22).with_token(Some("s3cret".to_string())));
+            assert_eq!(parse_reply(&reply).unwrap_err().to_string(), "Relay refused connection: 127.0.0.1:22 is not allowed");
+
+            let (_, reply) = open(relay, &ConnectRequest::new("bastion", 22));
+            assert_eq!(parse_reply(&reply).unwrap_err().to_string(), "Relay refused connection: Invalid relay token");
+        }
+
+        #[test]
+        fn test_relay_reports_unreachable_target() {
+            let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+            let relay = start_relay(&[format!("127.0.0.1:{}", closed)], None);
+            let (_, reply) = open(relay, &ConnectRequest::new("127.0.0.1", closed));
+            let err = parse_reply(&reply).unwrap_err().to_string();
+            assert!(err.starts_with("Relay refused connection: Failed to connect to 127.0.0.1:"), "{}", err);
+        }
+
+        #[test]
+        fn test_relay_over_websocket() {
+            let port = start_target();
+            let relay = start_relay(&[format!("127.0.0.1:{}", port)], Some("s3cret"));
+
+            let mut client = client_handshake(relay);
+            let request = ConnectRequest::new("127.0.0.1", port).with_token(Some("s3cret".to_string()));
+            client.get_mut().write_all(&client_frame(true, 0x2, &request.encode().unwrap())).unwrap();
+            assert_eq!(server_frame(&mut client), (0x2, b"OK\n".to_vec()));
+            assert_eq!(server_frame(&mut client), (0x2, b"SSH-2.0-test\r\n".to_vec()));
+
+            client.get_mut().write_all(&client_frame(true, 0x2, b"ping")).unwrap();
+            assert_eq!(server_frame(&mut client), (0x2, b"ping".to_vec()));
+            assert_eq!(server_frame(&mut client).0, 0x8);
+        }
+    }
+}
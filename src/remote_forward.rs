@@ -0,0 +1,370 @@
+//! Remote forwards (`-R`): the server listens on a port and hands each connection
+//! to it back over a `forwarded-tcpip` channel, which is tunnelled to a host and
+//! port reachable from here. Ports are requested before the shell starts; one the
+//! server refuses is looked up among its listening sockets to tell a port in use
+//! from forwarding not being allowed, and `--any-remote-port` takes the port the
+//! server assigns instead. Connections run on the session loop's thread.
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::auto_forward::{parse_listening, Connection, SCAN_COMMAND};
+use crate::forwards::ForwardFields;
+use crate::remote_target::{bracketed, host_port};
+use crate::ssh_client::{ForwardListener, RequestRefused, SshClient};
+use crate::terminal::SessionHook;
+
+/// Address the server listens on when `-R` names none
+pub const DEFAULT_BIND_ADDRESS: &str = "localhost";
+/// How long a forwarded connection may take to reach its destination; the shell
+/// waits meanwhile, since connections run on its loop's thread
+const LOCAL_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `-R [bind_address:]port:host:hostport`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteForwardSpec {
+    pub bind_address: String,
+    /// 0 for a port the server picks
+    pub remote_port: u16,
+    pub local_host: String,
+    pub local_port: u16,
+}
+
+impl RemoteForwardSpec {
+    /// Parse `[bind_address:]port:host:hostport`; IPv6 addresses go in brackets
+    pub fn parse(spec: &str) -> Result<Self> {
+        let fields = ForwardFields::parse(spec).context("Invalid remote forward")?;
+        Ok(Self {
+            bind_address: fields.bind_address.unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string()),
+            remote_port: fields.port,
+            local_host: fields.host,
+            local_port: fields.host_port,
+        })
+    }
+
+    /// Where connections go, as `host:port`
+    pub fn destination(&self) -> String {
+        host_port(&self.local_host, self.local_port)
+    }
+}
+
+impl std::fmt::Display for RemoteForwardSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", bracketed(&self.bind_address), self.remote_port, self.destination())
+    }
+}
+
+/// Why the server refused a remote port, from its listening sockets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// Something on the server already listens there
+    InUse,
+    /// Nothing listens there, so forwarding it is not allowed (`AllowTcpForwarding`,
+    /// `PermitListen`, or a privileged port)
+    NotAllowed,
+    /// The listening sockets couldn't be read
+    Unknown,
+}
+
+impl Refusal {
+    pub fn describe(self, port: u16) -> String {
+        match self {
+            Refusal::InUse => format!("Remote port {} is already in use on the server", port),
+            Refusal::NotAllowed => format!("The server does not allow forwarding remote port {}", port),
+            Refusal::Unknown => format!("The server refused remote port {}: it is in use or not allowed", port),
+        }
+    }
+}
+
+/// A remote port the server listens on
+pub struct Established {
+    pub spec: RemoteForwardSpec,
+    /// The port bound, which differs from the one asked for when the server picked it
+    pub port: u16,
+    /// Why the port asked for wasn't used
+    pub refused: Option<Refusal>,
+    listener: Box<dyn ForwardListener>,
+}
+
+/// Ask the server to listen for `spec`. When it refuses the port, find out why
+/// and, with `any_port`, take whatever port it assigns instead.
+pub fn establish(client: &SshClient, spec: &RemoteForwardSpec, any_port: bool) -> Result<Established> {
+    let error = match client.forward_listen(&spec.bind_address, spec.remote_port) {
+        Ok((listener, port)) => return Ok(Established { spec: spec.clone(), port, refused: None, listener }),
+        Err(e) => e,
+    };
+    if spec.remote_port == 0 || !error.chain().any(|cause| cause.is::<RequestRefused>()) {
+        return Err(error);
+    }
+
+    let refusal = check_port(client, spec.remote_port);
+    if !any_port {
+        return Err(error.context(refusal.describe(spec.remote_port)));
+    }
+    warn!("{}; asking for any port", refusal.describe(spec.remote_port));
+    let (listener, port) = client
+        .forward_listen(&spec.bind_address, 0)
+        .with_context(|| refusal.describe(spec.remote_port))?;
+    Ok(Established { spec: spec.clone(), port, refused: Some(refusal), listener })
+}
+
+/// Connect to the first address of `host:port` that answers within [`LOCAL_CONNECT_TIMEOUT`]
+fn connect_local(host: &str, port: u16) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, LOCAL_CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses")))
+}
+
+/// Look for `port` among the server's listening sockets
+fn check_port(client: &SshClient, port: u16) -> Refusal {
+    match client.run_command(SCAN_COMMAND) {
+        Ok(output) if output.success() && !output.stdout.trim().is_empty() => {
+            if parse_listening(&output.stdout).iter().any(|(_, listening)| *listening == port) {
+                Refusal::InUse
+            } else if port < crate::auto_forward::MIN_PORT {
+                // Not listed either way
+                Refusal::Unknown
+            } else {
+                Refusal::NotAllowed
+            }
+        }
+        Ok(_) => Refusal::Unknown,
+        Err(e) => {
+            debug!("Cannot list the server's listening sockets: {:#}", e);
+            Refusal::Unknown
+        }
+    }
+}
+
+/// Told the port a forward had and the forward, now on another port
+pub type MovedCallback = Box<dyn FnMut(u16, &Established)>;
+
+/// Serves established remote forwards during an interactive session
+pub struct RemoteForward {
+    client: Rc<SshClient>,
+    forwards: Vec<Established>,
+    connections: Vec<Connection>,
+    any_port: bool,
+    on_moved: MovedCallback,
+}
+
+impl RemoteForward {
+    /// Serve `forwards` through `client`. After a reconnect, `on_moved` is called with
+    /// the port it had for each forward that had to take another (`any_port`).
+    pub fn new(
+        client: Rc<SshClient>,
+        forwards: Vec<Established>,
+        any_port: bool,
+        on_moved: impl FnMut(u16, &Established) + 'static,
+    ) -> Self {
+        Self { client, forwards, connections: Vec::new(), any_port, on_moved: Box::new(on_moved) }
+    }
+
+    /// Ask the server on the re-dialled `client` for every port again, the same one
+    /// each had, so clients can keep using it. A forward that can't be had back is
+    /// dropped and its error returned.
+    pub fn reconnect(&mut self, client: Rc<SshClient>) -> Vec<anyhow::Error> {
+        self.client = client;
+        self.connections.clear();
+        let mut errors = Vec::new();
+        for forward in std::mem::take(&mut self.forwards) {
+            let same_port = RemoteForwardSpec { remote_port: forward.port, ..forward.spec.clone() };
+            match establish(&self.client, &same_port, self.any_port) {
+                Ok(mut again) => {
+                    again.spec = forward.spec;
+                    if again.port != forward.port {
+                        (self.on_moved)(forward.port, &again);
+                    }
+                    self.forwards.push(again);
+                }
+                Err(e) => errors.push(e.context(format!("Remote forward {} was not re-established", forward.spec))),
+            }
+        }
+        errors
+    }
+
+    fn accept(&mut self) -> bool {
+        let mut accepted = false;
+        for forward in &mut self.forwards {
+            let label = host_port(&forward.spec.bind_address, forward.port);
+            loop {
+                let channel = match self.client.accept_forwarded(forward.listener.as_mut(), &label) {
+                    Ok(Some(channel)) => channel,
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("Accepting on remote port {} failed: {:#}", forward.port, e);
+                        break;
+                    }
+                };
+                accepted = true;
+                let destination = forward.spec.destination();
+                info!("Connection on remote port {} for {}", forward.port, destination);
+                let local = connect_local(&forward.spec.local_host, forward.spec.local_port)
+                    .and_then(|stream| stream.set_nonblocking(true).map(|_| stream));
+                match local {
+                    Ok(stream) => self.connections.push(Connection::new(stream, channel)),
+                    // Dropping the channel closes the connection on the server
+                    Err(e) => warn!("Cannot reach {} for remote port {}: {}", destination, forward.port, e),
+                }
+            }
+        }
+        accepted
+    }
+}
+
+impl SessionHook for RemoteForward {
+    fn poll(&mut self) -> bool {
+        let mut activity = self.accept();
+        self.connections.retain_mut(|connection| match connection.pump() {
+            Ok(moved) => {
+                activity |= moved;
+                !connection.is_done()
+            }
+            Err(e) => {
+                debug!("Remote forwarded connection failed: {:#}", e);
+                false
+            }
+        });
+        activity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::{CommandOutput, MockForwardListener, MockSshConnection};
+
+    fn spec(remote_port: u16) -> RemoteForwardSpec {
+        RemoteForwardSpec::parse(&format!("{}:localhost:3000", remote_port)).unwrap()
+    }
+
+    fn refused() -> anyhow::Error {
+        anyhow::Error::new(RequestRefused { request: "tcpip-forward" })
+    }
+
+    fn listening(ports: &str) -> CommandOutput {
+        CommandOutput { stdout: ports.to_string(), exit_status: 0, exit_signal: None }
+    }
+
+    fn client(connection: MockSshConnection) -> SshClient {
+        SshClient::new(Box::new(connection))
+    }
+
+    #[test]
+    fn test_parse_remote_forward() {
+        let forward = RemoteForwardSpec::parse("8080:localhost:3000").unwrap();
+        assert_eq!(forward.bind_address, "localhost");
+        assert_eq!((forward.remote_port, forward.local_host.as_str(), forward.local_port), (8080, "localhost", 3000));
+
+        let forward = RemoteForwardSpec::parse("0.0.0.0:0:[::1]:22").unwrap();
+        assert_eq!((forward.bind_address.as_str(), forward.remote_port), ("0.0.0.0", 0));
+        assert_eq!(forward.local_host, "::1");
+        assert_eq!(forward.to_string(), "0.0.0.0:0:[::1]:22");
+
+        let forward = RemoteForwardSpec::parse("[::]:9000:db.internal:5432").unwrap();
+        assert_eq!(forward.bind_address, "::");
+        assert_eq!(forward.to_string(), "[::]:9000:db.internal:5432");
+        assert!(RemoteForwardSpec::parse("8080:localhost").is_err());
+    }
+
+    #[test]
+    fn test_establish_gets_the_port_asked_for() {
+        let mut connection = MockSshConnection::new();
+        connection.expect_is_authenticated().return_const(true);
+        connection
+            .expect_forward_listen()
+            .withf(|bind, port| bind == "localhost" && *port == 8080)
+            .returning(|_, port| Ok((Box::new(MockForwardListener::new()), port)));
+
+        let established = establish(&client(connection), &spec(8080), true).unwrap();
+        assert_eq!((established.port, established.refused), (8080, None));
+    }
+
+    #[test]
+    fn test_establish_reports_a_port_in_use() {
+        let mut connection = MockSshConnection::new();
+        connection.expect_is_authenticated().return_const(true);
+        connection.expect_forward_listen().times(1).returning(|_, _| Err(refused()));
+        connection
+            .expect_run_command()
+            .returning(|_| Ok(listening("LISTEN 0 4096 127.0.0.1:8080 0.0.0.0:*\n")));
+
+        let error = establish(&client(connection), &spec(8080), false).err().unwrap();
+        assert!(format!("{:#}", error).contains("Remote port 8080 is already in use"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_establish_takes_an_assigned_port() {
+        let mut connection = MockSshConnection::new();
+        connection.expect_is_authenticated().return_const(true);
+        connection.expect_forward_listen().withf(|_, port| *port == 8080).returning(|_, _| Err(refused()));
+        connection
+            .expect_forward_listen()
+            .withf(|_, port| *port == 0)
+            .returning(|_, _| Ok((Box::new(MockForwardListener::new()), 41234)));
+        connection.expect_run_command().returning(|_| Ok(listening("LISTEN 0 4096 *:22 *:*\n")));
+
+        let established = establish(&client(connection), &spec(8080), true).unwrap();
+        assert_eq!((established.port, established.refused), (41234, Some(Refusal::NotAllowed)));
+    }
+
+    #[test]
+    fn test_reconnect_asks_for_the_same_port() {
+        let mut first = MockSshConnection::new();
+        first.expect_is_authenticated().return_const(true);
+        first.expect_forward_listen().returning(|_, _| Ok((Box::new(MockForwardListener::new()), 41234)));
+        let first = Rc::new(client(first));
+        let established = establish(&first, &spec(0), false).unwrap();
+        let moved = Rc::new(std::cell::Cell::new(false));
+        let seen = Rc::clone(&moved);
+        let mut forward = RemoteForward::new(first, vec![established], false, move |_, _| seen.set(true));
+
+        let mut second = MockSshConnection::new();
+        second.expect_is_authenticated().return_const(true);
+        second
+            .expect_forward_listen()
+            .withf(|_, port| *port == 41234)
+            .times(1)
+            .returning(|_, port| Ok((Box::new(MockForwardListener::new()), port)));
+        assert!(forward.reconnect(Rc::new(client(second))).is_empty());
+        assert_eq!(forward.forwards[0].port, 41234);
+        assert_eq!(forward.forwards[0].spec.remote_port, 0);
+        assert!(!moved.get());
+
+        let mut third = MockSshConnection::new();
+        third.expect_is_authenticated().return_const(true);
+        third.expect_forward_listen().returning(|_, _| Err(refused()));
+        third.expect_run_command().returning(|_| Ok(listening("LISTEN 0 4096 127.0.0.1:41234 0.0.0.0:*\n")));
+        let errors = forward.reconnect(Rc::new(client(third)));
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:#}", errors[0]).contains("was not re-established"), "{:#}", errors[0]);
+        assert!(forward.forwards.is_empty());
+    }
+
+    #[test]
+    fn test_establish_passes_other_errors_through() {
+        let mut connection = MockSshConnection::new();
+        connection.expect_is_authenticated().return_const(true);
+        connection.expect_forward_listen().times(1).returning(|_, _| Err(anyhow::anyhow!("connection reset")));
+
+        let error = establish(&client(connection), &spec(8080), true).err().unwrap();
+        assert!(format!("{:#}", error).contains("connection reset"));
+    }
+
+    #[test]
+    fn test_connect_local() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(connect_local("127.0.0.1", port).is_ok());
+
+        drop(listener);
+        assert!(connect_local("127.0.0.1", port).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Retrying operations that fail for transient reasons: a refused or reset
+//! connection, a server dropping new connections under load, a channel it has
+//! no room for yet
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::cancel::{cancellable, CancelToken};
+
+/// `max_attempts` for polling that goes on until the operation stops blocking
+pub const UNLIMITED: u32 = u32::MAX;
+
+/// How often and how long to keep trying, and which errors are worth another try
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Tries in all, including the first; 1 never retries
+    pub max_attempts: u32,
+    /// Pause before the first retry
+    pub initial_delay: Duration,
+    /// Each pause is this many times the one before (1 keeps them equal)
+    pub multiplier: u32,
+    pub max_delay: Duration,
+    /// Whether an error is worth another try; [`is_transient`] when deserialized
+    #[serde(skip, default = "transient")]
+    pub retryable: fn(&anyhow::Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    /// One attempt; `with_attempts` turns on retries of transient errors
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2,
+            max_delay: Duration::from_secs(30),
+            retryable: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Retry for as long as `retryable` holds, every `interval`
+    pub const fn poll(interval: Duration, retryable: fn(&anyhow::Error) -> bool) -> Self {
+        Self {
+            max_attempts: UNLIMITED,
+            initial_delay: interval,
+            multiplier: 1,
+            max_delay: interval,
+            retryable,
+        }
+    }
+
+    pub fn with_attempts(self, max_attempts: u32) -> Self {
+        Self { max_attempts: max_attempts.max(1), ..self }
+    }
+
+    pub fn with_delay(self, initial_delay: Duration) -> Self {
+        Self { initial_delay, ..self }
+    }
+
+    /// Pause before retry number `retry` (1 for the first)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.max(1).saturating_pow(retry.saturating_sub(1));
+        self.initial_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Run `operation` until it succeeds, fails with an error that isn't retryable
+    /// or runs out of attempts. `cancel` is checked before each attempt and during
+    /// the pauses, as with [`cancellable`].
+    pub fn run<T>(&self, cancel: Option<&CancelToken>, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            let error = match cancellable(cancel, &mut operation) {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            let cancelled = cancel.is_some_and(CancelToken::is_cancelled);
+            if attempt >= self.max_attempts || cancelled || !(self.retryable)(&error) {
+                return Err(error);
+            }
+            let delay = self.delay(attempt);
+            // Unlimited retries are polling, too frequent to log
+            if self.max_attempts != UNLIMITED {
+                log::info!("Attempt {} of {} failed, retrying in {:?}: {:#}", attempt, self.max_attempts, delay, error);
+            }
+            pause(delay, cancel);
+            attempt += 1;
+        }
+    }
+}
+
+/// Sleep for `delay`, waking early when `cancel` fires
+pub fn pause(delay: Duration, cancel: Option<&CancelToken>) {
+    const SLICE: Duration = Duration::from_millis(50);
+    let Some(token) = cancel else {
+        std::thread::sleep(delay);
+        return;
+    };
+    let mut left = delay;
+    while !left.is_zero() && !token.is_cancelled() {
+        let slice = left.min(SLICE);
+        std::thread::sleep(slice);
+        left -= slice;
+    }
+}
+
+fn transient() -> fn(&anyhow::Error) -> bool {
+    is_transient
+}
+
+/// Network errors that may not happen again: refused, reset or timed-out
+/// connections, and the backend's would-block and dropped-handshake errors.
+/// Cancellation, DNS failures and authentication errors are final.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+
+    for cause in error.chain() {
+        if cause.is::<crate::cancel::Cancelled>() {
+            return false;
+        }
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::AddrNotAvailable
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::TimedOut
+                    | ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::UnexpectedEof
+            );
+        }
+        #[cfg(all(feature = "native-backend", not(target_arch = "wasm32")))]
+        if let Some(ssh) = cause.downcast_ref::<ssh2::Error>() {
+            return crate::ssh_impl::is_transient(ssh);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    fn instant() -> RetryPolicy {
+        RetryPolicy::default().with_delay(Duration::ZERO)
+    }
+
+    #[test]
+    fn test_delay_backs_off_up_to_max() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+        assert_eq!(policy.delay(10), Duration::from_secs(30));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(30));
+
+        let poll = RetryPolicy::poll(Duration::from_millis(10), is_transient);
+        assert_eq!(poll.delay(1), Duration::from_millis(10));
+        assert_eq!(poll.delay(50), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_run_retries_transient_errors() {
+        let mut calls = 0;
+        let result = instant().with_attempts(3).run(None, || {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(Error::from(ErrorKind::ConnectionRefused).into()),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Out of attempts: the last error is returned
+        let mut calls = 0;
+        let err = instant()
+            .with_attempts(2)
+            .run::<()>(None, || {
+                calls += 1;
+                Err(anyhow::Error::from(Error::from(ErrorKind::ConnectionReset)).context("Failed to connect to host"))
+            })
+            .unwrap_err();
+        assert_eq!(calls, 2);
+        assert_eq!(err.to_string(), "Failed to connect to host");
+    }
+
+    #[test]
+    fn test_run_stops_on_final_errors() {
+        let mut calls = 0;
+        let result = instant().with_attempts(5).run::<()>(None, || {
+            calls += 1;
+            Err(anyhow::anyhow!("Authentication failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        // Cancelling ends the retries, even during a pause
+        let token = CancelToken::new();
+        let mut calls = 0;
+        let err = RetryPolicy::default()
+            .with_attempts(5)
+            .with_delay(Duration::from_secs(60))
+            .run::<()>(Some(&token), || {
+                calls += 1;
+                token.cancel();
+                Err(Error::from(ErrorKind::ConnectionRefused).into())
+            })
+            .unwrap_err();
+        assert_eq!(calls, 1);
+        assert!(err.downcast_ref::<crate::cancel::Cancelled>().is_some());
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&Error::from(ErrorKind::ConnectionRefused).into()));
+        assert!(is_transient(&anyhow::Error::from(Error::from(ErrorKind::TimedOut)).context("SSH handshake failed")));
+        assert!(!is_transient(&Error::from(ErrorKind::PermissionDenied).into()));
+        assert!(!is_transient(&Error::other("failed to lookup address information").into()));
+        assert!(!is_transient(&anyhow::anyhow!("Authentication failed")));
+        assert!(!is_transient(&crate::cancel::Cancelled { timed_out: true }.into()));
+    }
+}
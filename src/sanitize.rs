@@ -0,0 +1,92 @@
+/// Remove terminal escape sequences and control characters from untrusted text
+/// (server banners, remote error messages) so it cannot drive the local terminal.
+/// CSI, OSC, DCS/SOS/PM/APC strings and their 8-bit forms are dropped whole;
+/// `\n` and `\t` are kept and `\r\n` becomes `\n`.
+pub fn strip_escape_sequences(text: &str) -> String {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Text,
+        Escape,
+        Csi,
+        /// OSC/DCS/SOS/PM/APC: runs until BEL or ST
+        String,
+        StringEscape,
+    }
+
+    let mut clean = String::with_capacity(text.len());
+    let mut state = State::Text;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        state = match state {
+            State::Text => match c {
+                '\x1b' => State::Escape,
+                '\u{9b}' => State::Csi,
+                '\u{90}' | '\u{98}' | '\u{9d}' | '\u{9e}' | '\u{9f}' => State::String,
+                '\r' if chars.peek() == Some(&'\n') => State::Text,
+                '\n' | '\t' => {
+                    clean.push(c);
+                    State::Text
+                }
+                c if c.is_control() => State::Text,
+                c => {
+                    clean.push(c);
+                    State::Text
+                }
+            },
+            State::Escape => match c {
+                '[' => State::Csi,
+                ']' | 'P' | 'X' | '^' | '_' => State::String,
+                // Intermediate bytes (e.g. ESC ( B) are followed by one final byte
+                '\x20'..='\x2f' => State::Escape,
+                _ => State::Text,
+            },
+            State::Csi => match c {
+                '\x40'..='\x7e' => State::Text,
+                _ => State::Csi,
+            },
+            State::String => match c {
+                '\x07' | '\u{9c}' => State::Text,
+                '\x1b' => State::StringEscape,
+                _ => State::String,
+            },
+            State::StringEscape => match c {
+                '\\' => State::Text,
+                _ => State::String,
+            },
+        };
+    }
+
+    clean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text_unchanged() {
+        assert_eq!(strip_escape_sequences("Hello,\tworld\n"), "Hello,\tworld\n");
+        assert_eq!(strip_escape_sequences("héllo ✓"), "héllo ✓");
+    }
+
+    #[test]
+    fn test_line_endings() {
+        assert_eq!(strip_escape_sequences("a\r\nb\rc"), "a\nbc");
+    }
+
+    #[test]
+    fn test_strips_csi_and_osc() {
+        assert_eq!(strip_escape_sequences("\x1b[31mred\x1b[0m"), "red");
+        assert_eq!(strip_escape_sequences("\x1b]0;pwned\x07Hi"), "Hi");
+        assert_eq!(strip_escape_sequences("\x1b]52;c;ZXZpbA==\x1b\\Hi"), "Hi");
+        assert_eq!(strip_escape_sequences("\u{9b}2Jcleared"), "cleared");
+        assert_eq!(strip_escape_sequences("\x1b(Bcharset"), "charset");
+    }
+
+    #[test]
+    fn test_unterminated_sequences_drop_rest() {
+        assert_eq!(strip_escape_sequences("ok\x1b]0;never ends"), "ok");
+        assert_eq!(strip_escape_sequences("ok\x1b["), "ok");
+    }
+}
@@ -0,0 +1,295 @@
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+use serde::Deserialize;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::ssh_client::ShellSession;
+
+/// Expect timeout when neither the step nor the script sets one
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Pause between polls while waiting for output
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How much recent output to quote when an expect times out
+const ERROR_CONTEXT_BYTES: usize = 200;
+
+/// One step as written in the YAML file; exactly one action key must be set
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepSpec {
+    expect: Option<String>,
+    send: Option<String>,
+    sleep_ms: Option<u64>,
+    eof: Option<bool>,
+    /// Seconds, for `expect` and `eof` steps
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptSpec {
+    /// Default timeout in seconds for every `expect` and `eof` step
+    timeout: Option<u64>,
+    steps: Vec<StepSpec>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// Wait until the output matches the regex
+    Expect { pattern: Regex, timeout: Duration },
+    Send(Vec<u8>),
+    Sleep(Duration),
+    /// Wait for the remote side to close the session
+    Eof { timeout: Duration },
+}
+
+/// A parsed `run-script` file:
+///
+/// ```yaml
+/// timeout: 60
+/// steps:
+///   - expect: "Install to \\[/opt\\]\\?"
+///   - send: "/srv/app\n"
+///   - expect: "Done|Complete"
+///     timeout: 600
+///   - send: "exit\n"
+///   - eof: true
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExpectScript {
+    pub steps: Vec<Step>,
+}
+
+impl ExpectScript {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read script {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid script {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let spec: ScriptSpec = serde_yaml::from_str(content).context("Failed to parse script")?;
+        let default_timeout = spec.timeout.map(Duration::from_secs).unwrap_or(DEFAULT_TIMEOUT);
+
+        let steps = spec
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(i, step)| Self::step(step, default_timeout).with_context(|| format!("Step {}", i + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { steps })
+    }
+
+    fn step(spec: StepSpec, default_timeout: Duration) -> Result<Step> {
+        let timeout = spec.timeout.map(Duration::from_secs).unwrap_or(default_timeout);
+        let step = match (spec.expect, spec.send, spec.sleep_ms, spec.eof) {
+            (Some(pattern), None, None, None) => Step::Expect {
+                pattern: Regex::new(&pattern).with_context(|| format!("Invalid regex '{}'", pattern))?,
+                timeout,
+            },
+            (None, Some(text), None, None) => Step::Send(text.into_bytes()),
+            (None, None, Some(ms), None) => Step::Sleep(Duration::from_millis(ms)),
+            (None, None, None, Some(true)) => Step::Eof { timeout },
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Each step needs exactly one of expect, send, sleep_ms or eof: true"
+                ))
+            }
+        };
+
+        if spec.timeout.is_some() && !matches!(step, Step::Expect { .. } | Step::Eof { .. }) {
+            return Err(anyhow::anyhow!("timeout only applies to expect and eof steps"));
+        }
+        Ok(step)
+    }
+}
+
+/// Plays an [`ExpectScript`] against a session, echoing remote output to `output`
+pub struct ScriptRunner<'a> {
+    session: &'a mut dyn ShellSession,
+    output: &'a mut dyn Write,
+    /// Output received but not yet consumed by a match
+    pending: Vec<u8>,
+}
+
+impl<'a> ScriptRunner<'a> {
+    pub fn new(session: &'a mut dyn ShellSession, output: &'a mut dyn Write) -> Self {
+        Self { session, output, pending: Vec::new() }
+    }
+
+    pub fn run(&mut self, script: &ExpectScript) -> Result<()> {
+        for (i, step) in script.steps.iter().enumerate() {
+            self.run_step(step).with_context(|| format!("Script failed at step {}", i + 1))?;
+        }
+        Ok(())
+    }
+
+    fn run_step(&mut self, step: &Step) -> Result<()> {
+        match step {
+            Step::Expect { pattern, timeout } => self.expect(pattern, *timeout),
+            Step::Send(data) => self.send(data),
+            Step::Sleep(duration) => {
+                let deadline = Instant::now() + *duration;
+                while Instant::now() < deadline {
+                    self.poll()?;
+                }
+                Ok(())
+            }
+            Step::Eof { timeout } => self.wait_eof(*timeout),
+        }
+    }
+
+    /// Read whatever output is available; returns false once the session has closed
+    fn poll(&mut self) -> Result<bool> {
+        let mut buf = [0u8; 4096];
+        let n = self.session.read(&mut buf)?;
+        if n > 0 {
+            self.output.write_all(&buf[..n])?;
+            self.output.flush()?;
+            self.pending.extend_from_slice(&buf[..n]);
+            return Ok(true);
+        }
+        if self.session.is_eof() {
+            return Ok(false);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+        Ok(true)
+    }
+
+    fn expect(&mut self, pattern: &Regex, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(found) = pattern.find(&self.pending) {
+                self.pending.drain(..found.end());
+                return Ok(());
+            }
+            if !self.poll()? {
+                return Err(anyhow::anyhow!(
+                    "Session closed while waiting for /{}/{}",
+                    pattern,
+                    self.recent_output()
+                ));
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow::anyhow!(
+                    "Timed out after {}s waiting for /{}/{}",
+                    timeout.as_secs(),
+                    pattern,
+                    self.recent_output()
+                ));
+            }
+        }
+    }
+
+    fn send(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let n = self.session.write(data)?;
+            if n == 0 {
+                self.poll()?;
+            }
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    fn wait_eof(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        while self.poll()? {
+            if Instant::now() >= deadline {
+                return Err(anyhow::anyhow!("Timed out after {}s waiting for the session to close", timeout.as_secs()));
+            }
+        }
+        Ok(())
+    }
+
+    fn recent_output(&self) -> String {
+        if self.pending.is_empty() {
+            return String::new();
+        }
+        let start = self.pending.len().saturating_sub(ERROR_CONTEXT_BYTES);
+        format!("; last output: {:?}", String::from_utf8_lossy(&self.pending[start..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Script, ScriptedShellSession};
+    use std::sync::{Arc, Mutex};
+
+    fn run(yaml: &str, server: Script) -> (Result<()>, String, Vec<u8>) {
+        let input_log = Arc::new(Mutex::new(Vec::new()));
+        let mut session = ScriptedShellSession::new(server, Arc::clone(&input_log));
+        let mut output = Vec::new();
+
+        let script = ExpectScript::parse(yaml).unwrap();
+        let result = ScriptRunner::new(&mut session, &mut output).run(&script);
+        let input = input_log.lock().unwrap().clone();
+        (result, String::from_utf8(output).unwrap(), input)
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let script = ExpectScript::parse(
+            "timeout: 5\nsteps:\n  - expect: 'Name\\?'\n  - send: \"bob\\n\"\n  - sleep_ms: 10\n  - eof: true\n    timeout: 1\n",
+        )
+        .unwrap();
+
+        assert_eq!(script.steps.len(), 4);
+        assert!(matches!(&script.steps[0], Step::Expect { timeout, .. } if *timeout == Duration::from_secs(5)));
+        assert!(matches!(&script.steps[1], Step::Send(data) if data == b"bob\n"));
+        assert!(matches!(&script.steps[3], Step::Eof { timeout } if *timeout == Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_steps() {
+        assert!(ExpectScript::parse("steps:\n  - expect: 'a'\n    send: 'b'\n").is_err());
+        assert!(ExpectScript::parse("steps:\n  - {}\n").is_err());
+        assert!(ExpectScript::parse("steps:\n  - expect: '('\n").is_err());
+        assert!(ExpectScript::parse("steps:\n  - send: 'x'\n    timeout: 3\n").is_err());
+        assert!(ExpectScript::parse("steps:\n  - expct: 'typo'\n").is_err());
+    }
+
+    #[test]
+    fn test_installer_dialogue() {
+        let server = Script::new()
+            .send("Welcome\r\nInstall to [/opt]? ")
+            .expect("/srv/app\n")
+            .send("Installing...\r\nDone.\r\n$ ")
+            .expect("exit\n")
+            .eof();
+        let yaml = r#"
+steps:
+  - expect: 'Install to \[/opt\]\? $'
+  - send: "/srv/app\n"
+  - expect: 'Done|Failed'
+  - expect: '\$ $'
+  - send: "exit\n"
+  - eof: true
+"#;
+
+        let (result, output, input) = run(yaml, server);
+        result.unwrap();
+        assert!(output.contains("Installing..."));
+        assert_eq!(input, b"/srv/app\nexit\n");
+    }
+
+    #[test]
+    fn test_expect_timeout_reports_output() {
+        let server = Script::new().send("Password: ").delay(Duration::from_secs(5));
+        let (result, _, _) = run("steps:\n  - expect: 'Continue'\n    timeout: 0\n", server);
+
+        let message = format!("{:#}", result.unwrap_err());
+        assert!(message.contains("step 1"));
+        assert!(message.contains("Timed out"));
+        assert!(message.contains("Password: "));
+    }
+
+    #[test]
+    fn test_expect_fails_when_session_closes() {
+        let server = Script::new().send("bye\n").eof();
+        let (result, _, _) = run("steps:\n  - expect: 'never'\n", server);
+        assert!(format!("{:#}", result.unwrap_err()).contains("Session closed"));
+    }
+}
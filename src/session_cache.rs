@@ -0,0 +1,510 @@
+//! Session cache (`--session-cache`): the first `cat`, `put` or `exec` to a host
+//! starts a background master that keeps its authenticated connection open, and
+//! later commands send their work over a Unix socket instead of logging in again.
+//! The master serves one request at a time and exits after [`IDLE_TIMEOUT`].
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::ssh_client::SshClient;
+use crate::transfer::{self, TransferReport};
+
+/// How long a master stays up without requests, like OpenSSH's `ControlPersist 10m`
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// What `cat`, `put` and `exec` need from a connection, made directly or through
+/// a master
+pub trait Remote {
+    /// Run `command` and return its output; a non-zero exit is an error
+    fn execute_command(&self, command: &str) -> Result<String>;
+    /// Run `command` with its stdout and stderr streamed to `output`, returning the
+    /// status to exit with (128 + the signal number when killed)
+    fn exec(&self, command: &str, output: &mut dyn Write) -> Result<i32>;
+    fn download(&self, path: &str, output: &mut dyn Write) -> Result<TransferReport>;
+    fn upload(&self, input: &mut dyn Read, path: &str) -> Result<TransferReport>;
+}
+
+impl Remote for SshClient {
+    fn execute_command(&self, command: &str) -> Result<String> {
+        SshClient::execute_command(self, command)
+    }
+
+    fn exec(&self, command: &str, output: &mut dyn Write) -> Result<i32> {
+        let mut channel = self.open_exec_merged(command)?;
+        crate::stdio::pipe(channel.as_mut(), std::io::empty(), output)?;
+        let status = channel.wait_close()?;
+        Ok(channel.exit_signal().map_or(status, |signal| signal.exit_code()))
+    }
+
+    fn download(&self, path: &str, output: &mut dyn Write) -> Result<TransferReport> {
+        let mut sftp = self.open_sftp()?;
+        transfer::download(sftp.as_mut(), path, output)
+    }
+
+    fn upload(&self, input: &mut dyn Read, path: &str) -> Result<TransferReport> {
+        let mut sftp = self.open_sftp()?;
+        transfer::upload(sftp.as_mut(), input, path)
+    }
+}
+
+#[cfg(unix)]
+pub use master::{cache_dir, master_name, options_path, run_master, CachedSession};
+
+/// Masters and their clients, over Unix sockets
+#[cfg(unix)]
+mod master {
+    use super::*;
+    use log::{debug, info};
+    use std::io::BufRead;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::time::Instant;
+
+    use crate::daemon;
+
+    /// First line of every request; the request itself follows as a frame
+    const CONTROL_HELLO: &str = "BXSSH-CONTROL 1";
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+    /// A client that sends or reads nothing for this long mid-request, e.g. an
+    /// upload whose stdin stalled, is dropped so the next one can be served
+    const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+    /// Largest frame payload accepted
+    const MAX_FRAME: usize = 1024 * 1024;
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    // Frames are a kind byte, a big-endian u32 length and the payload.
+    // Requests, from the client; a ping is answered with an empty ping
+    const PING: u8 = b'P';
+    const EXEC: u8 = b'E';
+    const DOWNLOAD: u8 = b'D';
+    const UPLOAD: u8 = b'U';
+    // Either way: command output, file contents or upload data; empty ends an upload
+    const DATA: u8 = b'd';
+    // Replies ending a request: exit status (i32), transfer result ("BYTES SHA256"), error
+    const EXIT: u8 = b'x';
+    const DONE: u8 = b'r';
+    const ERROR: u8 = b'!';
+
+    /// Where masters keep their sockets and logs (`~/.local/state/bxssh/run/cache`)
+    pub fn cache_dir() -> Result<PathBuf> {
+        Ok(daemon::run_dir()?.join("cache"))
+    }
+
+    /// Where the command starting master `name` leaves its connection options
+    pub fn options_path(dir: &Path, name: &str) -> PathBuf {
+        dir.join(format!("{}.options.json", name))
+    }
+
+    /// Master name for a connection; names too long for a socket file are hashed
+    pub fn master_name(user: &str, host: &str, port: u16) -> String {
+        let name: String = format!("{}@{}-{}", user, host, port)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@') { c } else { '_' })
+            .collect();
+        if daemon::validate_name(&name).is_ok() {
+            return name;
+        }
+        use sha2::{Digest, Sha256};
+        let digest = Sha256::digest(name.as_bytes());
+        digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Listen on `name`'s socket in `dir` and serve `remote` until it has been idle
+    /// for `idle`
+    pub fn run_master(remote: &dyn Remote, dir: &Path, name: &str, idle: Duration) -> Result<()> {
+        daemon::validate_name(name)?;
+        daemon::create_run_dir(dir)?;
+        let socket = daemon::socket_path(dir, name);
+        if UnixStream::connect(&socket).is_ok() {
+            return Err(anyhow::anyhow!("Session cache '{}' is already running", name));
+        }
+        let _ = std::fs::remove_file(&socket);
+
+        let listener = UnixListener::bind(&socket).context("Failed to create session cache socket")?;
+        info!("Session cache '{}' ready at {}", name, socket.display());
+        let result = serve(remote, &listener, idle);
+        let _ = std::fs::remove_file(&socket);
+        result
+    }
+
+    /// Answer requests one at a time until none arrives for `idle`, or until the
+    /// connection to the server is found dead after a failed request
+    fn serve(remote: &dyn Remote, listener: &UnixListener, idle: Duration) -> Result<()> {
+        listener.set_nonblocking(true).context("Failed to configure session cache socket")?;
+        let mut last_request = Instant::now();
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => match handle(remote, stream) {
+                    Ok(true) => last_request = Instant::now(),
+                    // Pings and probes don't keep the master alive
+                    Ok(false) => {}
+                    Err(e) => {
+                        debug!("Request failed: {:#}", e);
+                        remote.execute_command("true").context("Connection to the server was lost")?;
+                        last_request = Instant::now();
+                    }
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    if last_request.elapsed() >= idle {
+                        info!("Idle for {:?}; closing", idle);
+                        return Ok(());
+                    }
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => return Err(e).context("Failed to accept session cache request"),
+            }
+        }
+    }
+
+    /// Serve one request, returning whether it was one that used the connection. A
+    /// client that closes without a request, as `daemon::spawn` does checking that
+    /// the master is up, or that only pings, is not. An error from `remote` is sent
+    /// back to the client and returned so the caller can check the connection.
+    fn handle(remote: &dyn Remote, stream: UnixStream) -> Result<bool> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut reader = std::io::BufReader::new(&stream);
+        let mut hello = String::new();
+        if reader.read_line(&mut hello).unwrap_or(0) == 0 {
+            return Ok(false);
+        }
+        if hello.trim_end() != CONTROL_HELLO {
+            let _ = write_frame(&mut &stream, ERROR, b"unsupported protocol");
+            return Ok(false);
+        }
+        let (kind, argument) = match read_frame(&mut reader) {
+            Ok((PING, _)) => {
+                let _ = write_frame(&mut &stream, PING, &[]);
+                return Ok(false);
+            }
+            Ok(request) => request,
+            Err(e) => {
+                debug!("Incomplete request: {:#}", e);
+                return Ok(false);
+            }
+        };
+        let argument = String::from_utf8(argument).context("Request is not UTF-8")?;
+        // An upload waits on the client's stdin, which may be slow, but not forever
+        stream.set_read_timeout(Some(STALL_TIMEOUT))?;
+        stream.set_write_timeout(Some(STALL_TIMEOUT))?;
+
+        let mut output = FrameWriter(&stream);
+        let result = match kind {
+            EXEC => remote.exec(&argument, &mut output).map(|status| (EXIT, status.to_be_bytes().to_vec())),
+            DOWNLOAD => remote.download(&argument, &mut output).map(|report| (DONE, report_payload(&report))),
+            UPLOAD => {
+                let mut input = FrameReader { inner: &mut reader, pending: Vec::new(), offset: 0, done: false };
+                remote.upload(&mut input, &argument).map(|report| (DONE, report_payload(&report)))
+            }
+            other => Err(anyhow::anyhow!("Unknown request {:?}", other as char)),
+        };
+        match result {
+            Ok((kind, payload)) => {
+                write_frame(&mut &stream, kind, &payload)?;
+                Ok(true)
+            }
+            Err(e) => {
+                let _ = write_frame(&mut &stream, ERROR, format!("{:#}", e).as_bytes());
+                Err(e)
+            }
+        }
+    }
+
+    fn report_payload(report: &TransferReport) -> Vec<u8> {
+        format!("{} {}", report.bytes, report.sha256).into_bytes()
+    }
+
+    fn write_frame(stream: &mut impl Write, kind: u8, payload: &[u8]) -> std::io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 5);
+        frame.push(kind);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        stream.write_all(&frame)
+    }
+
+    fn read_frame(stream: &mut impl Read) -> Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).context("Session cache closed the connection")?;
+        let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if length > MAX_FRAME {
+            return Err(anyhow::anyhow!("Session cache frame too large ({} bytes)", length));
+        }
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload).context("Session cache closed the connection")?;
+        Ok((header[0], payload))
+    }
+
+    /// Sends everything written as data frames
+    struct FrameWriter<W: Write>(W);
+
+    impl<W: Write> Write for FrameWriter<W> {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            let data = &data[..data.len().min(CHUNK_SIZE)];
+            if !data.is_empty() {
+                write_frame(&mut self.0, DATA, data)?;
+            }
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    /// Reads data frames until the empty one that ends them
+    struct FrameReader<'a, R: Read> {
+        inner: &'a mut R,
+        pending: Vec<u8>,
+        offset: usize,
+        done: bool,
+    }
+
+    impl<R: Read> Read for FrameReader<'_, R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            while self.offset == self.pending.len() && !self.done {
+                let (kind, payload) = read_frame(self.inner).map_err(std::io::Error::other)?;
+                if kind != DATA {
+                    return Err(std::io::Error::other(format!("Unexpected frame {:?} in upload", kind as char)));
+                }
+                self.done = payload.is_empty();
+                self.pending = payload;
+                self.offset = 0;
+            }
+            let n = buf.len().min(self.pending.len() - self.offset);
+            buf[..n].copy_from_slice(&self.pending[self.offset..self.offset + n]);
+            self.offset += n;
+            Ok(n)
+        }
+    }
+
+    /// Client side: a running master, used wherever a [`Remote`] is
+    #[derive(Debug)]
+    pub struct CachedSession {
+        socket: PathBuf,
+    }
+
+    impl CachedSession {
+        /// The master for `name` in `dir`, if one is running and answers a ping
+        pub fn connect(dir: &Path, name: &str) -> Option<Self> {
+            let session = Self { socket: daemon::socket_path(dir, name) };
+            match session.ping() {
+                Ok(()) => Some(session),
+                Err(e) => {
+                    debug!("Session cache '{}' is not answering: {:#}", name, e);
+                    None
+                }
+            }
+        }
+
+        fn ping(&self) -> Result<()> {
+            let mut stream = self.request(PING, "")?;
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+            match read_frame(&mut stream)? {
+                (PING, _) => Ok(()),
+                (kind, _) => Err(anyhow::anyhow!("Unexpected reply {:?} to a ping", kind as char)),
+            }
+        }
+
+        /// Start a master with `args` (this executable's command line) and connect to it
+        pub fn start(args: &[String], dir: &Path, name: &str) -> Result<Self> {
+            daemon::spawn(args, dir, name)?;
+            Self::connect(dir, name)
+                .ok_or_else(|| anyhow::anyhow!("Session cache '{}' exited right after starting", name))
+        }
+
+        fn request(&self, kind: u8, argument: &str) -> Result<UnixStream> {
+            let mut stream = UnixStream::connect(&self.socket).context("Session cache is not running")?;
+            stream.write_all(format!("{}\n", CONTROL_HELLO).as_bytes())?;
+            write_frame(&mut stream, kind, argument.as_bytes())?;
+            Ok(stream)
+        }
+
+        /// Copy data frames to `output` until the reply; `Err` for an error reply
+        fn reply(stream: &mut UnixStream, output: &mut dyn Write) -> Result<(u8, Vec<u8>)> {
+            loop {
+                match read_frame(stream)? {
+                    (DATA, data) => output.write_all(&data)?,
+                    (ERROR, message) => return Err(anyhow::anyhow!("{}", String::from_utf8_lossy(&message))),
+                    reply => return Ok(reply),
+                }
+            }
+        }
+
+        fn report(path: &str, reply: (u8, Vec<u8>)) -> Result<TransferReport> {
+            let invalid = || anyhow::anyhow!("Invalid transfer result from session cache");
+            let (DONE, payload) = reply else {
+                return Err(invalid());
+            };
+            let payload = String::from_utf8(payload).map_err(|_| invalid())?;
+            let (bytes, sha256) = payload.split_once(' ').ok_or_else(invalid)?;
+            Ok(TransferReport {
+                path: path.to_string(),
+                bytes: bytes.parse().map_err(|_| invalid())?,
+                sha256: sha256.to_string(),
+                verified: false,
+            })
+        }
+    }
+
+    impl Remote for CachedSession {
+        fn execute_command(&self, command: &str) -> Result<String> {
+            let mut output = Vec::new();
+            match self.exec(command, &mut output)? {
+                0 => Ok(String::from_utf8_lossy(&output).into_owned()),
+                status => Err(anyhow::anyhow!("Command exited with status {}", status)),
+            }
+        }
+
+        fn exec(&self, command: &str, output: &mut dyn Write) -> Result<i32> {
+            let mut stream = self.request(EXEC, command)?;
+            match Self::reply(&mut stream, output)? {
+                (EXIT, status) => status
+                    .try_into()
+                    .map(i32::from_be_bytes)
+                    .map_err(|_| anyhow::anyhow!("Invalid exit status from session cache")),
+                _ => Err(anyhow::anyhow!("Invalid exit status from session cache")),
+            }
+        }
+
+        fn download(&self, path: &str, output: &mut dyn Write) -> Result<TransferReport> {
+            let mut stream = self.request(DOWNLOAD, path)?;
+            let reply = Self::reply(&mut stream, output).with_context(|| format!("Failed to download {}", path))?;
+            Self::report(path, reply)
+        }
+
+        fn upload(&self, input: &mut dyn Read, path: &str) -> Result<TransferReport> {
+            let mut stream = self.request(UPLOAD, path)?;
+            let sent = std::io::copy(input, &mut FrameWriter(&stream))
+                .and_then(|_| write_frame(&mut stream, DATA, &[]))
+                .with_context(|| format!("Failed to upload to {}", path));
+            // The master's reply explains a refused upload better than the broken pipe
+            match Self::reply(&mut stream, &mut std::io::sink()) {
+                Ok(reply) => {
+                    sent?;
+                    Self::report(path, reply)
+                }
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::sync::Mutex;
+        use tempfile::TempDir;
+
+        /// Files and command results kept in memory
+        #[derive(Default)]
+        struct FakeRemote {
+            files: Mutex<std::collections::HashMap<String, Vec<u8>>>,
+        }
+
+        impl Remote for FakeRemote {
+            fn execute_command(&self, command: &str) -> Result<String> {
+                assert_eq!(command, "true", "only the connection check runs directly");
+                Ok(String::new())
+            }
+
+            fn exec(&self, command: &str, output: &mut dyn Write) -> Result<i32> {
+                let (text, status) = command.split_once(" && exit ").unwrap();
+                output.write_all(text.as_bytes())?;
+                Ok(status.parse()?)
+            }
+
+            fn download(&self, path: &str, output: &mut dyn Write) -> Result<TransferReport> {
+                let files = self.files.lock().unwrap();
+                let data = files.get(path).ok_or_else(|| anyhow::anyhow!("No such file"))?;
+                output.write_all(data)?;
+                let bytes = data.len() as u64;
+                Ok(TransferReport { path: path.to_string(), bytes, sha256: "abc".into(), verified: false })
+            }
+
+            fn upload(&self, input: &mut dyn Read, path: &str) -> Result<TransferReport> {
+                let mut data = Vec::new();
+                input.read_to_end(&mut data)?;
+                let bytes = data.len() as u64;
+                self.files.lock().unwrap().insert(path.to_string(), data);
+                Ok(TransferReport { path: path.to_string(), bytes, sha256: "def".into(), verified: false })
+            }
+        }
+
+        #[test]
+        fn test_master_name() {
+            assert_eq!(master_name("alice", "db.example.com", 22), "alice@db.example.com-22");
+            assert_eq!(master_name("bob", "fe80::1%eth0", 2222), "bob@fe80__1_eth0-2222");
+            let long = master_name("carol", &"a".repeat(80), 22);
+            assert_eq!(long.len(), 32);
+            assert!(daemon::validate_name(&long).is_ok());
+        }
+
+        #[test]
+        fn test_requests_through_master() {
+            let temp = TempDir::new().unwrap();
+            let dir = temp.path().to_path_buf();
+            let server_dir = dir.clone();
+            let server = std::thread::spawn(move || {
+                run_master(&FakeRemote::default(), &server_dir, "web", Duration::from_millis(300))
+            });
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let session = loop {
+                if let Some(session) = CachedSession::connect(&dir, "web") {
+                    break session;
+                }
+                assert!(Instant::now() < deadline, "master never started");
+                std::thread::sleep(Duration::from_millis(10));
+            };
+
+            let mut output = Vec::new();
+            assert_eq!(session.exec("hello && exit 3", &mut output).unwrap(), 3);
+            assert_eq!(output, b"hello");
+            assert_eq!(session.execute_command("done && exit 0").unwrap(), "done");
+            assert!(session.execute_command("nope && exit 1").unwrap_err().to_string().contains("status 1"));
+
+            let data = vec![7u8; CHUNK_SIZE * 2 + 10];
+            let report = session.upload(&mut data.as_slice(), "/tmp/blob").unwrap();
+            assert_eq!((report.path.as_str(), report.bytes), ("/tmp/blob", data.len() as u64));
+            assert_eq!(report.sha256, "def");
+
+            let mut downloaded = Vec::new();
+            let report = session.download("/tmp/blob", &mut downloaded).unwrap();
+            assert_eq!(downloaded, data);
+            assert_eq!(report.sha256, "abc");
+            let err = session.download("/missing", &mut Vec::new()).unwrap_err();
+            assert!(format!("{:#}", err).contains("No such file"));
+
+            // The failed download made the master check its connection, which still works
+            assert_eq!(session.exec("still here && exit 0", &mut Vec::new()).unwrap(), 0);
+            server.join().unwrap().unwrap();
+            assert!(CachedSession::connect(&dir, "web").is_none());
+        }
+
+        #[test]
+        fn test_pings_and_probes_do_not_keep_the_master_alive() {
+            let temp = TempDir::new().unwrap();
+            let dir = temp.path().to_path_buf();
+            let server_dir = dir.clone();
+            let server = std::thread::spawn(move || {
+                run_master(&FakeRemote::default(), &server_dir, "pinged", Duration::from_millis(300))
+            });
+            let started = Instant::now();
+            let mut pinged = false;
+            while !server.is_finished() {
+                assert!(started.elapsed() < Duration::from_secs(5), "master kept alive by pings");
+                pinged |= CachedSession::connect(&dir, "pinged").is_some();
+                // A bare connection, as `daemon::spawn` makes, is not a failed request
+                drop(UnixStream::connect(daemon::socket_path(&dir, "pinged")));
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            assert!(pinged);
+            server.join().unwrap().unwrap();
+        }
+
+        #[test]
+        fn test_master_exits_when_idle() {
+            let temp = TempDir::new().unwrap();
+            run_master(&FakeRemote::default(), temp.path(), "idle", Duration::from_millis(50)).unwrap();
+            assert!(!daemon::socket_path(temp.path(), "idle").exists());
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! bxssh's own settings in `~/.config/bxssh/config.toml`. Every key is optional, unknown
+//! keys are rejected, and errors name the line and column at fault. Environment
+//! variables such as `BXSSH_THEME` still override the file.
+//!
+//! ```toml
+//! [terminal]
+//! lang = "es"
+//! theme = "high-contrast"
+//! title = true
+//! session_header = true
+//! read_buffer_size = 16384
+//!
+//! [logging]
+//! level = "info"            # when RUST_LOG is unset
+//!
+//! [auth]
+//! backoff_secs = 2
+//! warn_after = 3
+//! password_attempts = 3
+//! password_fallback = "ask"   # or "never", "auto"
+//!
+//! [defaults]
+//! identity_file = "~/.ssh/id_work"
+//! client_version = "SSH-2.0-OpenSSH_9.6"
+//! key_max_age_days = 180
+//!
+//! [policy]                  # as in policy.toml, which wins when present
+//! allow = ["*.corp.example.com"]
+//! ```
+use anyhow::{Context, Result};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
+use crate::config::{PasswordFallback, SshConfig};
+use crate::host_policy::HostPolicy;
+use crate::i18n::{Lang, Theme};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(default)]
+    pub terminal: TerminalSettings,
+    #[serde(default)]
+    pub logging: LoggingSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub defaults: DefaultSettings,
+    #[serde(default, deserialize_with = "policy")]
+    pub policy: Option<HostPolicy>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TerminalSettings {
+    #[serde(default, deserialize_with = "lang")]
+    pub lang: Option<Lang>,
+    #[serde(default, deserialize_with = "theme")]
+    pub theme: Option<Theme>,
+    pub title: Option<bool>,
+    pub session_header: Option<bool>,
+    pub read_buffer_size: Option<NonZeroUsize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingSettings {
+    #[serde(default, deserialize_with = "level")]
+    pub level: Option<log::LevelFilter>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthSettings {
+    pub backoff_secs: Option<u64>,
+    pub warn_after: Option<u32>,
+    pub password_attempts: Option<NonZeroU32>,
+    #[serde(default, deserialize_with = "password_fallback")]
+    pub password_fallback: Option<PasswordFallback>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefaultSettings {
+    pub identity_file: Option<String>,
+    #[serde(default, deserialize_with = "client_version")]
+    pub client_version: Option<String>,
+    pub key_max_age_days: Option<u32>,
+}
+
+impl Settings {
+    /// `config.toml` in the config directory
+    pub fn path() -> Result<PathBuf> {
+        Ok(crate::paths::config_dir()?.join("config.toml"))
+    }
+
+    /// The settings in `~/.config/bxssh/config.toml`; without a home directory or the file, none
+    pub fn load() -> Result<Self> {
+        match crate::config::home_dir() {
+            Some(_) => Self::load_from(&Self::path()?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// The settings in `path`; a missing file has none
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Self::parse(&content).with_context(|| format!("Invalid settings file {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Put the file's values into `config`, over the built-in defaults
+    pub fn apply(&self, config: &mut SshConfig) {
+        let terminal = &self.terminal;
+        config.lang = terminal.lang.unwrap_or(config.lang);
+        config.theme = terminal.theme.unwrap_or(config.theme);
+        config.terminal_title = terminal.title.unwrap_or(config.terminal_title);
+        config.session_header = terminal.session_header.unwrap_or(config.session_header);
+        config.read_buffer_size = terminal.read_buffer_size.map_or(config.read_buffer_size, NonZeroUsize::get);
+
+        let auth = &self.auth;
+        config.auth_backoff_secs = auth.backoff_secs.unwrap_or(config.auth_backoff_secs);
+        config.auth_warn_after = auth.warn_after.unwrap_or(config.auth_warn_after);
+        config.password_attempts = auth.password_attempts.map_or(config.password_attempts, NonZeroU32::get);
+        config.password_fallback = auth.password_fallback.unwrap_or(config.password_fallback);
+
+        let defaults = &self.defaults;
+        if let Some(identity_file) = &defaults.identity_file {
+            config.identity_file = Some(identity_file.clone());
+        }
+        if let Some(client_version) = &defaults.client_version {
+            config.client_version = client_version.clone();
+        }
+        config.key_max_age_days = defaults.key_max_age_days.unwrap_or(config.key_max_age_days);
+    }
+}
+
+/// A string field checked by `parse`, so a bad value is reported at its line
+fn parsed<'de, D, T>(deserializer: D, parse: impl FnOnce(&str) -> Result<T, String>) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    parse(&value).map(Some).map_err(D::Error::custom)
+}
+
+fn lang<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Lang>, D::Error> {
+    parsed(deserializer, |value| {
+        Lang::parse(value).ok_or_else(|| format!("unsupported language '{}' (use en, es or ja)", value))
+    })
+}
+
+fn theme<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Theme>, D::Error> {
+    parsed(deserializer, |value| {
+        Theme::parse(value).ok_or_else(|| format!("unknown theme '{}' (use default, high-contrast or none)", value))
+    })
+}
+
+fn password_fallback<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PasswordFallback>, D::Error> {
+    parsed(deserializer, |value| {
+        PasswordFallback::parse(value).ok_or_else(|| format!("unknown fallback '{}' (use never, ask or auto)", value))
+    })
+}
+
+fn level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<log::LevelFilter>, D::Error> {
+    parsed(deserializer, |value| {
+        value
+            .parse()
+            .map_err(|_| format!("unknown log level '{}' (use off, error, warn, info, debug or trace)", value))
+    })
+}
+
+fn client_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    parsed(deserializer, |value| {
+        crate::config::validate_client_version(value).map(|()| value.to_string()).map_err(|e| e.to_string())
+    })
+}
+
+fn policy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<HostPolicy>, D::Error> {
+    let policy = HostPolicy::deserialize(deserializer)?;
+    policy.validate().map_err(|e| D::Error::custom(format!("{:#}", e)))?;
+    Ok(Some(policy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_apply() {
+        let settings = Settings::parse(
+            r#"
+            [terminal]
+            lang = "ja"
+            theme = "none"
+            title = true
+            read_buffer_size = 4096
+
+            [logging]
+            level = "debug"
+
+            [auth]
+            password_attempts = 1
+            password_fallback = "auto"
+
+            [defaults]
+            identity_file = "~/.ssh/id_work"
+            key_max_age_days = 0
+
+            [policy]
+            deny = ["*.prod"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.logging.level, Some(log::LevelFilter::Debug));
+        assert!(settings.policy.as_ref().unwrap().check("db.prod", 22).is_err());
+
+        let mut config = SshConfig::default();
+        settings.apply(&mut config);
+        assert_eq!((config.lang, config.theme), (Lang::Ja, Theme::None));
+        assert!(config.terminal_title && !config.session_header);
+        assert_eq!(config.read_buffer_size, 4096);
+        assert_eq!((config.password_attempts, config.auth_warn_after), (1, 3));
+        assert_eq!(config.password_fallback, PasswordFallback::Auto);
+        assert_eq!(config.identity_file.as_deref(), Some("~/.ssh/id_work"));
+        assert_eq!(config.key_max_age_days, 0);
+        assert_eq!(config.client_version, crate::config::CLIENT_VERSION);
+    }
+
+    #[test]
+    fn test_errors_point_at_the_line() {
+        for (bad, line, message) in [
+            ("[terminal]\ntitel = true\n", 2, "unknown field `titel`"),
+            ("[terminal]\n\ntheme = \"pink\"\n", 3, "unknown theme 'pink'"),
+            ("[auth]\npassword_attempts = 0\n", 2, "nonzero"),
+            ("[logging]\nlevel = \"loud\"\n", 2, "unknown log level"),
+            ("[defaults]\nclient_version = \"OpenSSH\"\n", 2, "must start with 'SSH-2.0-'"),
+            ("[policy]\nports = [\"30-20\"]\n", 1, "Invalid port range '30-20'"),
+            ("[colors]\n", 1, "unknown field `colors`"),
+        ] {
+            let error = Settings::parse(bad).unwrap_err().to_string();
+            assert!(error.contains(&format!("line {}", line)), "{}: {}", bad, error);
+            assert!(error.contains(message), "{}: {}", bad, error);
+        }
+    }
+
+    #[test]
+    fn test_load_from() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        assert_eq!(Settings::load_from(&path).unwrap(), Settings::default());
+
+        std::fs::write(&path, "[terminal]\nsession_header = \"yes\"\n").unwrap();
+        let error = format!("{:#}", Settings::load_from(&path).unwrap_err());
+        assert!(error.contains(&path.display().to_string()), "{}", error);
+        assert!(error.contains("line 2"), "{}", error);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_numbers_apply_or_name_their_line(size in 0..100_000usize, attempts in 0..10u32) {
+            let text = format!("[terminal]\nread_buffer_size = {}\n[auth]\npassword_attempts = {}\n", size, attempts);
+            match Settings::parse(&text) {
+                Ok(settings) => {
+                    let mut config = SshConfig::default();
+                    settings.apply(&mut config);
+                    proptest::prop_assert_eq!((config.read_buffer_size, config.password_attempts), (size, attempts));
+                }
+                Err(e) => {
+                    let line = if size == 0 { "line 2" } else { "line 4" };
+                    proptest::prop_assert!(e.to_string().contains(line), "{}", e);
+                }
+            }
+        }
+
+        #[test]
+        fn prop_parse_never_panics(text in "(\\[[a-z]{1,8}\\]\n|[a-z_]{1,12} = (\"[a-z-]*\"|[0-9]{1,6}|true)\n){0,6}") {
+            let _ = Settings::parse(&text);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! TCP settings for the connection to the server, from `--tos`, `-o IPQoS=...`
+//! and profile keys (`TcpNoDelay`, `TcpKeepAliveSecs`, `SendBufferSize`,
+//! `ReceiveBufferSize`, `IPQoS`). Whatever is left unset keeps the OS default,
+//! except Nagle's algorithm, which is off as in OpenSSH's interactive sessions.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocketOptions {
+    /// `TCP_NODELAY`; on unless set to false
+    pub nodelay: Option<bool>,
+    /// Idle time before TCP keepalive probes, and the time between them
+    pub keepalive: Option<Duration>,
+    /// `SO_SNDBUF` and `SO_RCVBUF`, in bytes
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// IP type of service / IPv6 traffic class byte
+    pub tos: Option<u32>,
+}
+
+/// The TOS byte for an ssh_config `IPQoS` value: a DSCP class (`af11`..`af43`,
+/// `cs0`..`cs7`, `ef`, `le`), `lowdelay`, `throughput`, `reliability` or a
+/// number. `none` leaves the OS default. Of OpenSSH's interactive/bulk pair only
+/// the first is used, since one connection carries both.
+pub fn parse_tos(value: &str) -> Result<Option<u32>> {
+    let value = value.split_whitespace().next().unwrap_or_default().to_ascii_lowercase();
+    let invalid = || anyhow::anyhow!("Invalid IPQoS '{}' (use e.g. lowdelay, af21, cs1, ef or 0-255)", value);
+    let dscp = |class: u32| Ok(Some(class << 2));
+    match value.as_str() {
+        "none" => Ok(None),
+        "lowdelay" => Ok(Some(0x10)),
+        "throughput" => Ok(Some(0x08)),
+        "reliability" => Ok(Some(0x04)),
+        "ef" => dscp(46),
+        "le" => dscp(1),
+        _ => {
+            if let Some(class) = value.strip_prefix("cs") {
+                return match class.parse::<u32>() {
+                    Ok(class @ 0..=7) => dscp(class * 8),
+                    _ => Err(invalid()),
+                };
+            }
+            if let Some(class) = value.strip_prefix("af") {
+                let digits: Vec<u32> = class.chars().filter_map(|c| c.to_digit(10)).collect();
+                return match digits[..] {
+                    [x @ 1..=4, y @ 1..=3] if class.len() == 2 => dscp(x * 8 + y * 2),
+                    _ => Err(invalid()),
+                };
+            }
+            let number = match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => value.parse::<u32>(),
+            };
+            match number {
+                Ok(tos) if tos <= 0xff => Ok(Some(tos)),
+                _ => Err(invalid()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tos() {
+        assert_eq!(parse_tos("lowdelay").unwrap(), Some(0x10));
+        assert_eq!(parse_tos("AF21 cs1").unwrap(), Some(0x48));
+        assert_eq!(parse_tos("af11").unwrap(), Some(0x28));
+        assert_eq!(parse_tos("af43").unwrap(), Some(0x98));
+        assert_eq!(parse_tos("cs0").unwrap(), Some(0));
+        assert_eq!(parse_tos("cs7").unwrap(), Some(0xe0));
+        assert_eq!(parse_tos("ef").unwrap(), Some(0xb8));
+        assert_eq!(parse_tos("0x10").unwrap(), Some(0x10));
+        assert_eq!(parse_tos("184").unwrap(), Some(184));
+        assert_eq!(parse_tos("none").unwrap(), None);
+        for bad in ["", "af5", "af14", "af111", "cs8", "256", "fast"] {
+            assert!(parse_tos(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+}
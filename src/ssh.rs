@@ -12,7 +12,7 @@ pub struct SshClient {
 #[allow(dead_code)] // Legacy implementation kept for reference
 impl SshClient {
     pub fn connect(host: &str, port: u16, _username: &str) -> Result<Self> {
-        let tcp = TcpStream::connect(format!("{}:{}", host, port))
+        let tcp = TcpStream::connect((host, port))
             .context("Failed to connect to host")?;
         
         let mut session = Session::new().context("Failed to create SSH session")?;
@@ -1,33 +1,570 @@
 use anyhow::{Context, Result};
 
+use crate::cancel::{cancellable, CancelToken};
+use crate::retry::RetryPolicy;
+
 #[cfg_attr(test, mockall::automock)]
-pub trait SshConnection {
+pub trait SshConnection: Send {
     fn connect(&mut self, host: &str, port: u16) -> Result<()>;
+    /// Pre-auth banner (SSH_MSG_USERAUTH_BANNER) sent by the server, if any
+    fn auth_banner(&mut self, username: &str) -> Result<Option<String>>;
     fn authenticate_with_key(&mut self, username: &str, private_key_path: &str) -> Result<()>;
     fn authenticate_with_password(&mut self, username: &str, password: &str) -> Result<()>;
+    /// Answer the server's password change request (SSH_MSG_USERAUTH_PASSWD_CHANGEREQ)
+    /// after `authenticate_with_password` failed with [`PasswordChangeRequired`]
+    fn change_password(&mut self, _username: &str, _old_password: &str, _new_password: &str) -> Result<()> {
+        Err(anyhow::anyhow!("This backend cannot change passwords"))
+    }
+    #[allow(dead_code)] // Library API; the CLI uses run_command
     fn execute_command(&self, command: &str) -> Result<String>;
+    /// Run `command` to completion; a non-zero exit or signal is reported, not an error
+    fn run_command(&self, command: &str) -> Result<CommandOutput>;
     fn start_shell(&self) -> Result<Box<dyn ShellSession>>;
+    /// Like `start_shell`, without asking for a PTY, for servers that refuse one
+    fn start_shell_without_pty(&self) -> Result<Box<dyn ShellSession>> {
+        Err(anyhow::anyhow!("This backend cannot start a shell without a PTY"))
+    }
+    fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<Box<dyn ShellSession>>;
+    /// Ask the server to listen on `bind_address:port` (`tcpip-forward`), 0 for a port
+    /// it picks; returns the listener and the port it bound
+    fn forward_listen(&self, _bind_address: &str, _port: u16) -> Result<(Box<dyn ForwardListener>, u16)> {
+        Err(anyhow::anyhow!("This backend cannot forward remote ports"))
+    }
+    fn open_exec(&self, command: &str) -> Result<Box<dyn ShellSession>>;
+    /// Like `open_exec`, but with the command's stderr merged into its output;
+    /// backends that can't merge leave stderr as `open_exec` does
+    fn open_exec_merged(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        self.open_exec(command)
+    }
+    /// Like `open_exec`, but on a PTY sized to the local terminal and kept in sync on resize
+    fn open_exec_pty(&self, command: &str) -> Result<Box<dyn ShellSession>>;
+    #[cfg_attr(not(feature = "fuse"), allow(dead_code))] // SFTP is only used by `bxssh mount`
+    fn open_sftp(&self) -> Result<Box<dyn SftpSession>>;
     fn is_authenticated(&self) -> bool;
+    /// What the transport negotiated, once connected; `None` when the backend can't tell
+    fn session_details(&self) -> Option<SessionDetails> {
+        None
+    }
+    /// Make blocking calls give up when `token` fires. Without this, cancellation
+    /// only takes effect between calls.
+    fn set_cancel_token(&mut self, _token: CancelToken) {}
+    /// Time one request/reply exchange with the server
+    fn round_trip(&self) -> Result<std::time::Duration> {
+        Err(anyhow::anyhow!("This backend cannot measure round trips"))
+    }
 }
 
 #[cfg_attr(test, mockall::automock)]
 pub trait ShellSession: std::fmt::Debug + Send + Sync {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
     fn write(&mut self, data: &[u8]) -> Result<usize>;
+    /// Write as much of `data` as the channel will take right now, reporting
+    /// `Blocked` instead of `Written(0)` when the peer's window is exhausted
+    fn try_write(&mut self, data: &[u8]) -> Result<WriteOutcome> {
+        match self.write(data)? {
+            0 if !data.is_empty() => Ok(WriteOutcome::Blocked),
+            n => Ok(WriteOutcome::Written(n)),
+        }
+    }
+    fn send_eof(&mut self) -> Result<()>;
     fn is_eof(&self) -> bool;
+    /// Wait for the remote side to close the channel and return its exit status
+    /// (0 when the server reported none)
+    fn wait_close(&mut self) -> Result<i32>;
+    /// The signal that terminated the remote command, once `wait_close` has returned
+    fn exit_signal(&self) -> Option<ExitSignal> {
+        None
+    }
+    /// Deliver `signal` to the remote process with a `signal` channel request.
+    /// Servers may refuse; OpenSSH honours it for exec and shell channels.
+    fn send_signal(&mut self, signal: Signal) -> Result<()> {
+        Err(anyhow::anyhow!("Cannot send SIG{} on this channel", signal.name()))
+    }
+}
+
+/// A port the server listens on for us (`-R`); connections to it arrive as channels
+#[cfg_attr(test, mockall::automock)]
+pub trait ForwardListener: Send {
+    /// The next connection waiting, without blocking
+    fn accept(&mut self) -> Result<Option<Box<dyn ShellSession>>>;
+}
+
+/// The server accepted the password but wants a new one before letting us in;
+/// find it with `e.chain().find_map(|c| c.downcast_ref::<PasswordChangeRequired>())`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordChangeRequired {
+    /// The server's explanation, often empty
+    pub prompt: String,
+}
+
+impl std::fmt::Display for PasswordChangeRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password has expired and must be changed")
+    }
+}
+
+impl std::error::Error for PasswordChangeRequired {}
+
+/// The server turned down a channel request, as accounts limited to git or to a
+/// forced command do with `pty-req` or `shell`; find it with
+/// `e.chain().find_map(|c| c.downcast_ref::<RequestRefused>())`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestRefused {
+    /// `pty-req`, `shell`, `exec` or `tcpip-forward`
+    pub request: &'static str,
+}
+
+impl std::fmt::Display for RequestRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The server refused the {} request", self.request)
+    }
+}
+
+impl std::error::Error for RequestRefused {}
+
+/// The server never opened the channel, so no request went out on it and opening
+/// it again can't run a command twice; attached as context by [`SshConnection`]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelNotOpened;
+
+impl std::fmt::Display for ChannelNotOpened {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Failed to create channel")
+    }
+}
+
+/// Retry an exec only when its channel failed to open, for transient reasons:
+/// once `exec` has been sent the command may already be running on the server
+fn channel_not_opened(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ChannelNotOpened>().is_some() && crate::retry::is_transient(error)
+}
+
+/// What [`SshClient::open_shell`] settled for when the server refused part of a shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellFallback {
+    /// A shell without a PTY, so lines are edited locally and full-screen programs won't work
+    NoPty,
+    /// No shell at all; this command runs instead
+    Exec(String),
+}
+
+/// Run when the server refuses a shell. An account with a forced command runs
+/// that instead, and its message is shown.
+pub const FALLBACK_COMMAND: &str = "exec \"${SHELL:-/bin/sh}\" -i";
+
+/// The request the server refused, when that is why `result` failed
+fn refused(result: &Result<Box<dyn ShellSession>>) -> Option<&'static str> {
+    let error = result.as_ref().err()?;
+    error.chain().find_map(|cause| cause.downcast_ref::<RequestRefused>()).map(|refused| refused.request)
+}
+
+/// Result of a non-blocking write to a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// This many leading bytes were accepted
+    Written(usize),
+    /// Nothing was accepted; retry once the remote side has read some data
+    Blocked,
+}
+
+/// Signals a client can send to the remote process (RFC 4254 section 6.9)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Int,
+    Term,
+    Hup,
+    Kill,
+}
+
+impl Signal {
+    /// Name as sent on the wire, without the `SIG` prefix
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::Int => "INT",
+            Signal::Term => "TERM",
+            Signal::Hup => "HUP",
+            Signal::Kill => "KILL",
+        }
+    }
+}
+
+impl std::str::FromStr for Signal {
+    type Err = anyhow::Error;
+
+    /// Accepts `INT`, `SIGINT` or `int`
+    fn from_str(s: &str) -> Result<Self> {
+        let upper = s.to_ascii_uppercase();
+        match upper.strip_prefix("SIG").unwrap_or(&upper) {
+            "INT" => Ok(Signal::Int),
+            "TERM" => Ok(Signal::Term),
+            "HUP" => Ok(Signal::Hup),
+            "KILL" => Ok(Signal::Kill),
+            _ => Err(anyhow::anyhow!("Unsupported signal '{}' (use INT, TERM, HUP or KILL)", s)),
+        }
+    }
+}
+
+/// An `exit-signal` channel request: the remote command was terminated by a signal
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExitSignal {
+    /// Signal name without the `SIG` prefix, e.g. `KILL`
+    pub name: String,
+    pub core_dumped: bool,
+    /// Error message sent along with the signal, if any
+    pub message: Option<String>,
+}
+
+impl ExitSignal {
+    /// Shell-style exit code: 128 plus the signal number, or 255 for unknown signals
+    pub fn exit_code(&self) -> i32 {
+        // Numbers of the signals named in RFC 4254, as on Linux
+        let number = match self.name.as_str() {
+            "HUP" => 1,
+            "INT" => 2,
+            "QUIT" => 3,
+            "ILL" => 4,
+            "ABRT" => 6,
+            "FPE" => 8,
+            "KILL" => 9,
+            "USR1" => 10,
+            "SEGV" => 11,
+            "USR2" => 12,
+            "PIPE" => 13,
+            "ALRM" => 14,
+            "TERM" => 15,
+            _ => return 255,
+        };
+        128 + number
+    }
+}
+
+impl std::fmt::Display for ExitSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "killed by SIG{}", self.name)?;
+        if self.core_dumped {
+            write!(f, " (core dumped)")?;
+        }
+        if let Some(message) = self.message.as_deref().filter(|m| !m.is_empty()) {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Output and termination of a command run with [`SshClient::run_command`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    /// Exit status reported by the server (0 when it sent none)
+    pub exit_status: i32,
+    pub exit_signal: Option<ExitSignal>,
+}
+
+#[allow(dead_code)] // Library API
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_status == 0 && self.exit_signal.is_none()
+    }
+
+    /// Why the command did not succeed, e.g. "killed by SIGKILL" or "failed with exit status 2"
+    pub fn failure(&self) -> Option<String> {
+        match &self.exit_signal {
+            Some(signal) => Some(signal.to_string()),
+            None if self.exit_status != 0 => Some(format!("failed with exit status {}", self.exit_status)),
+            None => None,
+        }
+    }
+
+    /// Status for the local process to exit with
+    pub fn exit_code(&self) -> i32 {
+        self.exit_signal.as_ref().map(ExitSignal::exit_code).unwrap_or(self.exit_status)
+    }
+}
+
+/// Tools looked for by [`SshClient::probe_remote`], for features that pick a
+/// strategy by what the server has (scp when SFTP is missing, sudo, rsync)
+pub const PROBED_COMMANDS: &[&str] = &["scp", "sftp", "rsync", "sudo", "tar", "python3", "sha256sum", "tmux"];
+
+/// The remote system as seen by [`SshClient::probe_remote`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteInfo {
+    /// `uname -s`, e.g. `Linux` or `Darwin`
+    pub os: String,
+    pub release: String,
+    pub arch: String,
+    /// The login shell from `$SHELL`
+    pub shell: String,
+    /// Entries of `PROBED_COMMANDS` found on the `PATH`
+    pub commands: Vec<String>,
+}
+
+#[allow(dead_code)] // Library API
+impl RemoteInfo {
+    /// Shell script printing `key=value` lines; run through `sh` so it works
+    /// whatever the login shell is
+    fn probe_command() -> String {
+        format!(
+            "sh -c 'echo \"os=$(uname -s)\"; echo \"release=$(uname -r)\"; echo \"arch=$(uname -m)\"; \
+             echo \"shell=$SHELL\"; for c in {}; do command -v $c >/dev/null 2>&1 && echo \"command=$c\"; done; true'",
+            PROBED_COMMANDS.join(" ")
+        )
+    }
+
+    /// Parse the probe's output; fails when the server has no POSIX shell to run it
+    fn parse(output: &str) -> Result<Self> {
+        let mut info = RemoteInfo::default();
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match key {
+                "os" => info.os = value,
+                "release" => info.release = value,
+                "arch" => info.arch = value,
+                "shell" => info.shell = value,
+                "command" => info.commands.push(value),
+                _ => {}
+            }
+        }
+        if info.os.is_empty() {
+            return Err(anyhow::anyhow!("Remote host gave no system information (no POSIX sh?)"));
+        }
+        Ok(info)
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.commands.iter().any(|command| command == name)
+    }
+
+    /// One line for the CLI, e.g. `Linux 6.1.0 x86_64, shell /bin/bash, tools: scp sudo`
+    pub fn summary(&self) -> String {
+        let mut summary = [self.os.as_str(), &self.release, &self.arch]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !self.shell.is_empty() {
+            summary.push_str(&format!(", shell {}", self.shell));
+        }
+        if !self.commands.is_empty() {
+            summary.push_str(&format!(", tools: {}", self.commands.join(" ")));
+        }
+        summary
+    }
+}
+
+/// Host key, algorithms and round trip of an established connection, for the
+/// session header
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionDetails {
+    /// Host key algorithm, e.g. `ssh-ed25519`
+    pub host_key_type: Option<String>,
+    /// `SHA256:` fingerprint of the host key, as OpenSSH prints it
+    pub host_key_fingerprint: Option<String>,
+    pub kex: Option<String>,
+    /// Client-to-server cipher
+    pub cipher: Option<String>,
+    /// How long the TCP connect took, about one network round trip
+    pub latency: Option<std::time::Duration>,
+    /// Mean round trip over the TCP connect and every channel opened since
+    pub average_latency: Option<std::time::Duration>,
+}
+
+impl SessionDetails {
+    /// Fingerprint characters kept in the header, enough to spot a change
+    pub const SHORT_FINGERPRINT: usize = 12;
+
+    /// One line, e.g. `ssh-ed25519 SHA256:uNiVztksCsDh · curve25519-sha256 · aes256-ctr · 12 ms`;
+    /// what the backend didn't report is left out
+    pub fn summary(&self) -> String {
+        let host_key = match (&self.host_key_type, &self.host_key_fingerprint) {
+            (Some(kind), Some(fingerprint)) => Some(format!("{} {}", kind, short_fingerprint(fingerprint))),
+            (Some(kind), None) => Some(kind.clone()),
+            (None, Some(fingerprint)) => Some(short_fingerprint(fingerprint)),
+            (None, None) => None,
+        };
+        let latency = self.latency.map(|latency| format!("{} ms", latency.as_millis()));
+        [host_key, self.kex.clone(), self.cipher.clone(), latency].into_iter().flatten().collect::<Vec<_>>().join(" · ")
+    }
+}
+
+/// `SHA256:` and the first [`SessionDetails::SHORT_FINGERPRINT`] characters of the hash
+fn short_fingerprint(fingerprint: &str) -> String {
+    let (prefix, hash) = fingerprint.split_once(':').unwrap_or(("", fingerprint));
+    let hash: String = hash.chars().take(SessionDetails::SHORT_FINGERPRINT).collect();
+    if prefix.is_empty() {
+        hash
+    } else {
+        format!("{}:{}", prefix, hash)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "fuse"), allow(dead_code))] // SFTP is only used by `bxssh mount`
+pub enum RemoteFileKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// File metadata as reported by the SFTP server
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "fuse"), allow(dead_code))] // SFTP is only used by `bxssh mount`
+pub struct RemoteFileAttr {
+    pub kind: RemoteFileKind,
+    pub size: u64,
+    /// Permission bits (without the file type bits)
+    pub perm: u32,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+/// A remote file open for reading; seeking is cheap, reopening is a round trip
+pub trait RemoteFile: std::io::Read + std::io::Seek + Send {}
+
+impl<T: std::io::Read + std::io::Seek + Send> RemoteFile for T {}
+
+/// The remote filesystem over the SFTP subsystem. Missing paths fail with an
+/// `io::ErrorKind::NotFound` error in the chain, unwritable ones with `PermissionDenied`.
+#[cfg_attr(test, mockall::automock)]
+#[cfg_attr(not(feature = "fuse"), allow(dead_code))] // SFTP is only used by `bxssh mount`
+pub trait SftpSession: std::fmt::Debug + Send {
+    fn stat(&mut self, path: &str) -> Result<RemoteFileAttr>;
+    /// Entries of the directory at `path` as (file name, attributes), without `.` and `..`
+    fn read_dir(&mut self, path: &str) -> Result<Vec<(String, RemoteFileAttr)>>;
+    /// Open the file at `path` for reading, from the start or at any offset
+    fn open_reader(&mut self, path: &str) -> Result<Box<dyn RemoteFile>>;
+    /// Create or truncate the file at `path` and stream into it
+    fn create(&mut self, path: &str) -> Result<Box<dyn std::io::Write + Send>>;
 }
 
+/// Receives the server's pre-auth banner before the first authentication attempt.
+/// Returning `false` declines the banner and aborts authentication.
+pub type BannerCallback = Box<dyn FnMut(&str) -> bool + Send>;
+
+/// Stands between every channel the client opens and its user, given the channel
+/// and a label such as `shell` or `exec`; e.g. to dump the traffic (`--dump-io`)
+pub type ChannelWrapper = Box<dyn Fn(Box<dyn ShellSession>, &str) -> Box<dyn ShellSession> + Send>;
+
+/// Called once when the client is dropped, which ends its connection
+pub type DisconnectCallback = Box<dyn FnOnce() + Send>;
+
 pub struct SshClient {
     connection: Box<dyn SshConnection>,
+    banner_callback: Option<BannerCallback>,
+    banner_checked: bool,
+    remote_info: Option<RemoteInfo>,
+    cancel: Option<CancelToken>,
+    retry: RetryPolicy,
+    channels_opened: std::sync::atomic::AtomicU32,
+    /// Last round trip `measure_latency` timed
+    latency: std::sync::Mutex<Option<std::time::Duration>>,
+    channel_wrapper: Option<ChannelWrapper>,
+    disconnect_callback: Option<DisconnectCallback>,
 }
 
 impl SshClient {
     pub fn new(connection: Box<dyn SshConnection>) -> Self {
-        Self { connection }
+        Self {
+            connection,
+            banner_callback: None,
+            banner_checked: false,
+            remote_info: None,
+            cancel: None,
+            retry: RetryPolicy::default(),
+            channels_opened: Default::default(),
+            latency: Default::default(),
+            channel_wrapper: None,
+            disconnect_callback: None,
+        }
+    }
+
+    /// Count a channel opened by `result`
+    fn opened<T>(&self, result: Result<T>) -> Result<T> {
+        if result.is_ok() {
+            self.channels_opened.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Count the channel opened by `result` and pass it through the channel wrapper
+    fn opened_channel(&self, result: Result<Box<dyn ShellSession>>, label: &str) -> Result<Box<dyn ShellSession>> {
+        let channel = self.opened(result)?;
+        Ok(match &self.channel_wrapper {
+            Some(wrap) => wrap(channel, label),
+            None => channel,
+        })
+    }
+
+    /// Hand every shell, exec and forwarding channel opened from now on to `wrapper`
+    pub fn set_channel_wrapper(&mut self, wrapper: ChannelWrapper) {
+        self.channel_wrapper = Some(wrapper);
+    }
+
+    /// Run `callback` when this client is dropped, e.g. a profile's `DisconnectCommand`
+    pub fn set_disconnect_callback(&mut self, callback: DisconnectCallback) {
+        self.disconnect_callback = Some(callback);
+    }
+
+    /// Channels opened on this connection so far: shells, commands, forwards and SFTP
+    pub fn channels_opened(&self) -> u32 {
+        self.channels_opened.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Stop the connect, authentication, commands and transfers of this client
+    /// when `token` fires; they then fail with [`crate::cancel::Cancelled`]
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.connection.set_cancel_token(token.clone());
+        self.cancel = Some(token);
+    }
+
+    /// Retry connecting and opening exec and SFTP channels under `policy`.
+    /// Authentication, and commands the server may have started, are never retried.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// The retry policy narrowed to exec channels that never opened
+    fn exec_retry(&self) -> RetryPolicy {
+        RetryPolicy { retryable: channel_not_opened, ..self.retry.clone() }
+    }
+
+    /// Register a callback for the server's pre-auth banner (e.g. a compliance
+    /// notice the user must accept). Without one the banner is not requested.
+    pub fn set_banner_callback(&mut self, callback: BannerCallback) {
+        self.banner_callback = Some(callback);
+    }
+
+    fn check_banner(&mut self, username: &str) -> Result<()> {
+        if self.banner_checked {
+            return Ok(());
+        }
+        self.banner_checked = true;
+
+        let Some(callback) = self.banner_callback.as_mut() else {
+            return Ok(());
+        };
+
+        let banner = match self.connection.auth_banner(username) {
+            Ok(banner) => banner,
+            Err(e) => {
+                log::debug!("Failed to fetch server banner: {}", e);
+                None
+            }
+        };
+
+        if let Some(banner) = banner.filter(|b| !b.is_empty()) {
+            if !callback(&banner) {
+                return Err(anyhow::anyhow!("Server banner was declined"));
+            }
+        }
+
+        Ok(())
     }
 
     pub fn connect(&mut self, host: &str, port: u16) -> Result<()> {
-        self.connection.connect(host, port)
+        self.retry.run(self.cancel.as_ref(), || self.connection.connect(host, port))
             .context("Failed to establish SSH connection")
     }
 
@@ -35,8 +572,10 @@ impl SshClient {
         if private_key_path.is_empty() {
             return Err(anyhow::anyhow!("Private key path cannot be empty"));
         }
+
+        self.check_banner(username)?;
         
-        self.connection.authenticate_with_key(username, private_key_path)
+        cancellable(self.cancel.as_ref(), || self.connection.authenticate_with_key(username, private_key_path))
             .context("SSH key authentication failed")
     }
 
@@ -44,11 +583,26 @@ impl SshClient {
         if password.is_empty() {
             return Err(anyhow::anyhow!("Password cannot be empty"));
         }
+
+        self.check_banner(username)?;
         
-        self.connection.authenticate_with_password(username, password)
+        cancellable(self.cancel.as_ref(), || self.connection.authenticate_with_password(username, password))
             .context("SSH password authentication failed")
     }
 
+    pub fn change_password(&mut self, username: &str, old_password: &str, new_password: &str) -> Result<()> {
+        if new_password.is_empty() {
+            return Err(anyhow::anyhow!("New password cannot be empty"));
+        }
+        if new_password == old_password {
+            return Err(anyhow::anyhow!("New password must differ from the old one"));
+        }
+
+        cancellable(self.cancel.as_ref(), || self.connection.change_password(username, old_password, new_password))
+            .context("Failed to change the password")
+    }
+
+    #[allow(dead_code)] // Library API; the CLI uses run_command
     pub fn execute_command(&self, command: &str) -> Result<String> {
         if command.trim().is_empty() {
             return Err(anyhow::anyhow!("Command cannot be empty"));
@@ -58,22 +612,202 @@ impl SshClient {
             return Err(anyhow::anyhow!("Not authenticated"));
         }
 
-        self.connection.execute_command(command)
+        self.opened(self.exec_retry().run(self.cancel.as_ref(), || self.connection.execute_command(command)))
+            .context("Failed to execute remote command")
+    }
+
+    /// Run `command` to completion, reporting its exit status or signal
+    pub fn run_command(&self, command: &str) -> Result<CommandOutput> {
+        if command.trim().is_empty() {
+            return Err(anyhow::anyhow!("Command cannot be empty"));
+        }
+
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        self.opened(self.exec_retry().run(self.cancel.as_ref(), || self.connection.run_command(command)))
             .context("Failed to execute remote command")
     }
 
     pub fn start_shell(&self) -> Result<Box<dyn ShellSession>> {
+        self.open_shell().map(|(shell, _)| shell)
+    }
+
+    /// Start a shell on a PTY. When the server refuses the PTY, start one without;
+    /// when it refuses the shell, run [`FALLBACK_COMMAND`].
+    pub fn open_shell(&self) -> Result<(Box<dyn ShellSession>, Option<ShellFallback>)> {
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        let cancel = self.cancel.as_ref();
+        let mut shell = cancellable(cancel, || self.connection.start_shell());
+        let mut fallback = None;
+        if refused(&shell) == Some("pty-req") {
+            log::warn!("The server refused a PTY; starting the shell without one");
+            shell = cancellable(cancel, || self.connection.start_shell_without_pty());
+            fallback = Some(ShellFallback::NoPty);
+        }
+        if refused(&shell) == Some("shell") {
+            log::warn!("The server refused a shell; running {}", FALLBACK_COMMAND);
+            shell = cancellable(cancel, || self.connection.open_exec(FALLBACK_COMMAND));
+            fallback = Some(ShellFallback::Exec(FALLBACK_COMMAND.to_string()));
+        }
+        if refused(&shell).is_some() {
+            shell = shell.context("The server allows no shell for this account; try a command with -c");
+        }
+        let shell = self.opened_channel(shell, "shell").context("Failed to start interactive shell")?;
+        Ok((shell, fallback))
+    }
+
+    /// Open a `direct-tcpip` channel to `host:port` as seen from the remote server
+    pub fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<Box<dyn ShellSession>> {
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("Forward host cannot be empty"));
+        }
+
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        let opened = cancellable(self.cancel.as_ref(), || self.connection.open_direct_tcpip(host, port));
+        let destination = crate::remote_target::host_port(host, port);
+        self.opened_channel(opened, &format!("direct-tcpip {}", destination))
+            .with_context(|| format!("Failed to open forwarding channel to {}", destination))
+    }
+
+    /// Ask the server to listen on `bind_address:port` for a remote forward (`-R`), 0
+    /// for a port it picks; returns the listener and the port bound. A port that is
+    /// taken or not allowed fails with [`RequestRefused`] in the chain.
+    pub fn forward_listen(&self, bind_address: &str, port: u16) -> Result<(Box<dyn ForwardListener>, u16)> {
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        let address = crate::remote_target::host_port(bind_address, port);
+        cancellable(self.cancel.as_ref(), || self.connection.forward_listen(bind_address, port))
+            .with_context(|| format!("Failed to listen on {} on the server", address))
+    }
+
+    /// The next connection to a remote forward's port, counted and wrapped like other channels
+    pub fn accept_forwarded(
+        &self,
+        listener: &mut dyn ForwardListener,
+        label: &str,
+    ) -> Result<Option<Box<dyn ShellSession>>> {
+        match listener.accept()? {
+            Some(channel) => self.opened_channel(Ok(channel), &format!("forwarded-tcpip {}", label)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Start `command` on an exec channel whose stdin/stdout are streamed rather than collected
+    pub fn open_exec(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        if command.trim().is_empty() {
+            return Err(anyhow::anyhow!("Command cannot be empty"));
+        }
+
         if !self.connection.is_authenticated() {
             return Err(anyhow::anyhow!("Not authenticated"));
         }
 
-        self.connection.start_shell()
-            .context("Failed to start interactive shell")
+        let opened = self.exec_retry().run(self.cancel.as_ref(), || self.connection.open_exec(command));
+        self.opened_channel(opened, "exec")
+            .context("Failed to start remote command")
+    }
+
+    /// Like [`open_exec`](Self::open_exec), with stderr arriving in the channel's output
+    pub fn open_exec_merged(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        if command.trim().is_empty() {
+            return Err(anyhow::anyhow!("Command cannot be empty"));
+        }
+
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        let opened = self.exec_retry().run(self.cancel.as_ref(), || self.connection.open_exec_merged(command));
+        self.opened_channel(opened, "exec")
+            .context("Failed to start remote command")
+    }
+
+    /// Start `command` on a PTY, for full-screen programs such as `top` (`-t -c`)
+    pub fn open_exec_pty(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        if command.trim().is_empty() {
+            return Err(anyhow::anyhow!("Command cannot be empty"));
+        }
+
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        let opened = self.exec_retry().run(self.cancel.as_ref(), || self.connection.open_exec_pty(command));
+        self.opened_channel(opened, "exec-pty")
+            .context("Failed to start remote command on a PTY")
+    }
+
+    /// Start the SFTP subsystem
+    #[cfg_attr(not(feature = "fuse"), allow(dead_code))] // SFTP is only used by `bxssh mount`
+    pub fn open_sftp(&self) -> Result<Box<dyn SftpSession>> {
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        self.opened(self.retry.run(self.cancel.as_ref(), || self.connection.open_sftp()))
+            .context("Failed to start SFTP subsystem")
     }
 
     pub fn is_authenticated(&self) -> bool {
         self.connection.is_authenticated()
     }
+
+    /// Learn the remote OS, login shell and available tools with one command.
+    /// The result is kept for [`SshClient::remote_info`].
+    pub fn probe_remote(&mut self) -> Result<&RemoteInfo> {
+        let output = self.run_command(&RemoteInfo::probe_command())?;
+        let info = RemoteInfo::parse(&output.stdout)?;
+        Ok(self.remote_info.insert(info))
+    }
+
+    /// What `probe_remote` found, if it ran
+    #[allow(dead_code)] // Library API
+    pub fn remote_info(&self) -> Option<&RemoteInfo> {
+        self.remote_info.as_ref()
+    }
+
+    /// Negotiated algorithms, host key and latency, when the backend reports them
+    pub fn session_details(&self) -> Option<SessionDetails> {
+        self.connection.session_details()
+    }
+
+    /// Time a round trip to the server now; the result is kept for [`SshClient::latency`]
+    pub fn measure_latency(&self) -> Result<std::time::Duration> {
+        if !self.connection.is_authenticated() {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        let round_trip = self.connection.round_trip().context("Failed to measure latency")?;
+        if let Ok(mut latency) = self.latency.lock() {
+            *latency = Some(round_trip);
+        }
+        Ok(round_trip)
+    }
+
+    /// The last round trip `measure_latency` timed, or before that the TCP connect time
+    #[allow(dead_code)] // Library API
+    pub fn latency(&self) -> Option<std::time::Duration> {
+        let measured = self.latency.lock().ok().and_then(|latency| *latency);
+        measured.or_else(|| self.session_details().and_then(|details| details.latency))
+    }
+}
+
+impl Drop for SshClient {
+    fn drop(&mut self) {
+        if let Some(callback) = self.disconnect_callback.take() {
+            callback();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -129,6 +863,89 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Failed to establish SSH connection"));
     }
 
+    #[test]
+    fn test_cancel_token_stops_operations() {
+        let token = CancelToken::new();
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_set_cancel_token()
+            .times(1)
+            .returning(|_| ());
+        let canceller = token.clone();
+        mock_connection
+            .expect_connect()
+            .times(1)
+            .returning(move |_, _| {
+                // The user gives up while the connection is being made
+                canceller.cancel();
+                Err(anyhow::anyhow!("Connection reset by peer"))
+            });
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection.expect_run_command().never();
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        client.set_cancel_token(token);
+        let err = client.connect("example.com", 22).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<crate::cancel::Cancelled>(),
+            Some(&crate::cancel::Cancelled { timed_out: false })
+        );
+
+        let err = client.run_command("uptime").unwrap_err();
+        assert!(err.downcast_ref::<crate::cancel::Cancelled>().is_some());
+    }
+
+    #[test]
+    fn test_retry_policy_retries_transient_connect_errors() {
+        let mut mock_connection = setup_mock_connection();
+        let mut seq = mockall::Sequence::new();
+        mock_connection
+            .expect_connect()
+            .times(2)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into()));
+        mock_connection
+            .expect_connect()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        mock_connection
+            .expect_authenticate_with_password()
+            .times(1)
+            .returning(|_, _| Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into()));
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        client.set_retry_policy(RetryPolicy::default().with_attempts(3).with_delay(std::time::Duration::ZERO));
+        assert!(client.connect("example.com", 22).is_ok());
+        // Authentication is never repeated
+        assert!(client.authenticate_with_password("user", "secret").is_err());
+    }
+
+    #[test]
+    fn test_retry_policy_repeats_commands_only_before_the_channel_opened() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        let mut seq = mockall::Sequence::new();
+        mock_connection
+            .expect_run_command()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| {
+                Err(anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::ConnectionReset))
+                    .context(ChannelNotOpened))
+            });
+        mock_connection
+            .expect_run_command()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into()));
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        client.set_retry_policy(RetryPolicy::default().with_attempts(3).with_delay(std::time::Duration::ZERO));
+        // The second failure came after `exec` may have been sent, so there is no third try
+        assert!(client.run_command("uptime").is_err());
+    }
+
     #[test]
     fn test_authenticate_with_key_success() {
         let mut mock_connection = setup_mock_connection();
@@ -170,6 +987,71 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("SSH key authentication failed"));
     }
 
+    #[test]
+    fn test_banner_callback_receives_banner() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_auth_banner()
+            .with(eq("testuser"))
+            .times(1)
+            .returning(|_| Ok(Some("Authorized use only".to_string())));
+        mock_connection
+            .expect_authenticate_with_key()
+            .times(2)
+            .returning(|_, _| Ok(()));
+
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        client.set_banner_callback(Box::new(move |banner| {
+            seen_clone.lock().unwrap().push(banner.to_string());
+            true
+        }));
+
+        assert!(client.authenticate_with_key("testuser", "/path/to/key").is_ok());
+        // The banner is only fetched once per connection
+        assert!(client.authenticate_with_key("testuser", "/path/to/key").is_ok());
+        assert_eq!(*seen.lock().unwrap(), vec!["Authorized use only".to_string()]);
+    }
+
+    #[test]
+    fn test_banner_declined_aborts_authentication() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_auth_banner()
+            .times(1)
+            .returning(|_| Ok(Some("Accept the policy?".to_string())));
+        mock_connection
+            .expect_authenticate_with_password()
+            .times(0);
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        client.set_banner_callback(Box::new(|_| false));
+        let result = client.authenticate_with_password("testuser", "password123");
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Server banner was declined"));
+    }
+
+    #[test]
+    fn test_banner_fetch_error_is_not_fatal() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_auth_banner()
+            .times(1)
+            .returning(|_| Err(anyhow::anyhow!("missing banner")));
+        mock_connection
+            .expect_authenticate_with_key()
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        client.set_banner_callback(Box::new(|_| panic!("no banner expected")));
+
+        assert!(client.authenticate_with_key("testuser", "/path/to/key").is_ok());
+    }
+
     #[test]
     fn test_authenticate_with_password_success() {
         let mut mock_connection = setup_mock_connection();
@@ -195,6 +1077,29 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Password cannot be empty"));
     }
 
+    #[test]
+    fn test_expired_password_is_changed() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_authenticate_with_password()
+            .times(1)
+            .returning(|_, _| Err(PasswordChangeRequired { prompt: "Expired".into() }.into()));
+        mock_connection
+            .expect_change_password()
+            .with(eq("testuser"), eq("old"), eq("new"))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        let error = client.authenticate_with_password("testuser", "old").unwrap_err();
+        let request = error.chain().find_map(|c| c.downcast_ref::<PasswordChangeRequired>());
+        assert_eq!(request.map(|r| r.prompt.as_str()), Some("Expired"));
+
+        assert!(client.change_password("testuser", "old", "").is_err());
+        assert!(client.change_password("testuser", "old", "old").is_err());
+        assert!(client.change_password("testuser", "old", "new").is_ok());
+    }
+
     #[test]
     fn test_execute_command_success() {
         let mut mock_connection = setup_mock_connection();
@@ -250,6 +1155,36 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Command cannot be empty"));
     }
 
+    #[test]
+    fn test_command_output_failure_text() {
+        let signal = ExitSignal { name: "KILL".to_string(), core_dumped: false, message: None };
+        let killed = CommandOutput { stdout: String::new(), exit_status: 0, exit_signal: Some(signal) };
+        assert!(!killed.success());
+        assert_eq!(killed.failure().unwrap(), "killed by SIGKILL");
+        assert_eq!(killed.exit_code(), 137);
+
+        let dumped = ExitSignal {
+            name: "SEGV".to_string(),
+            core_dumped: true,
+            message: Some("segfault at 0".to_string()),
+        };
+        assert_eq!(dumped.to_string(), "killed by SIGSEGV (core dumped): segfault at 0");
+        assert_eq!(ExitSignal { name: "XCPU".to_string(), ..dumped }.exit_code(), 255);
+
+        let failed = CommandOutput { stdout: String::new(), exit_status: 2, exit_signal: None };
+        assert_eq!(failed.failure().unwrap(), "failed with exit status 2");
+        assert_eq!(failed.exit_code(), 2);
+        assert!(CommandOutput { exit_status: 0, ..failed }.success());
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!("INT".parse::<Signal>().unwrap(), Signal::Int);
+        assert_eq!("sigterm".parse::<Signal>().unwrap(), Signal::Term);
+        assert_eq!("Hup".parse::<Signal>().unwrap().name(), "HUP");
+        assert!("USR1".parse::<Signal>().is_err());
+    }
+
     #[test]
     fn test_start_shell_success() {
         let mut mock_connection = setup_mock_connection();
@@ -292,6 +1227,234 @@ mod tests {
         assert!(result.is_err());
     }
 
+    fn refusal(request: &'static str) -> anyhow::Error {
+        anyhow::Error::new(RequestRefused { request }).context("Failed to start shell")
+    }
+
+    #[test]
+    fn test_open_shell_without_pty() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection.expect_start_shell().times(1).returning(|| Err(refusal("pty-req")));
+        mock_connection
+            .expect_start_shell_without_pty()
+            .times(1)
+            .returning(|| Ok(Box::new(MockShellSession::new())));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let (_, fallback) = client.open_shell().unwrap();
+        assert_eq!(fallback, Some(ShellFallback::NoPty));
+    }
+
+    #[test]
+    fn test_open_shell_runs_fallback_command() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection.expect_start_shell().times(1).returning(|| Err(refusal("pty-req")));
+        mock_connection.expect_start_shell_without_pty().times(1).returning(|| Err(refusal("shell")));
+        mock_connection
+            .expect_open_exec()
+            .with(eq(FALLBACK_COMMAND))
+            .times(1)
+            .returning(|_| Ok(Box::new(MockShellSession::new())));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let (_, fallback) = client.open_shell().unwrap();
+        assert_eq!(fallback, Some(ShellFallback::Exec(FALLBACK_COMMAND.to_string())));
+        assert_eq!(client.channels_opened(), 1);
+    }
+
+    #[test]
+    fn test_open_shell_refused_outright() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection.expect_start_shell().returning(|| Err(refusal("shell")));
+        mock_connection.expect_open_exec().returning(|_| Err(refusal("exec")));
+        mock_connection.expect_start_shell_without_pty().never();
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let error = format!("{:#}", client.open_shell().unwrap_err());
+        assert!(error.contains("allows no shell for this account"), "{}", error);
+        assert!(error.contains("refused the exec request"), "{}", error);
+    }
+
+    #[test]
+    fn test_open_direct_tcpip_success() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| true);
+        mock_connection
+            .expect_open_direct_tcpip()
+            .with(eq("internal.host"), eq(22))
+            .times(1)
+            .returning(|_, _| Ok(Box::new(MockShellSession::new())));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_direct_tcpip("internal.host", 22);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_channel_wrapper_sees_each_channel() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection.expect_start_shell().returning(|| Ok(Box::new(MockShellSession::new())));
+        mock_connection.expect_open_direct_tcpip().returning(|_, _| Ok(Box::new(MockShellSession::new())));
+
+        let labels = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = labels.clone();
+        let mut client = SshClient::new(Box::new(mock_connection));
+        client.set_channel_wrapper(Box::new(move |channel, label| {
+            seen.lock().unwrap().push(label.to_string());
+            channel
+        }));
+        client.start_shell().unwrap();
+        client.open_direct_tcpip("db", 5432).unwrap();
+        assert_eq!(*labels.lock().unwrap(), ["shell", "direct-tcpip db:5432"]);
+        assert_eq!(client.channels_opened(), 2);
+    }
+
+    #[test]
+    fn test_open_direct_tcpip_not_authenticated() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| false);
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_direct_tcpip("internal.host", 22);
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Not authenticated"));
+    }
+
+    #[test]
+    fn test_open_direct_tcpip_empty_host() {
+        let mock_connection = setup_mock_connection();
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_direct_tcpip("", 22);
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Forward host cannot be empty"));
+    }
+
+    #[test]
+    fn test_open_direct_tcpip_failure() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| true);
+        mock_connection
+            .expect_open_direct_tcpip()
+            .times(1)
+            .returning(|_, _| Err(anyhow::anyhow!("administratively prohibited")));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_direct_tcpip("internal.host", 2222);
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("internal.host:2222"));
+    }
+
+    #[test]
+    fn test_open_exec_success() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| true);
+        mock_connection
+            .expect_open_exec()
+            .with(eq("git-upload-pack 'repo.git'"))
+            .times(1)
+            .returning(|_| Ok(Box::new(MockShellSession::new())));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_exec("git-upload-pack 'repo.git'");
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_open_exec_pty_success() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| true);
+        mock_connection
+            .expect_open_exec_pty()
+            .with(eq("top"))
+            .times(1)
+            .returning(|_| Ok(Box::new(MockShellSession::new())));
+        mock_connection.expect_open_exec().never();
+
+        let client = SshClient::new(Box::new(mock_connection));
+        assert!(client.open_exec_pty("top").is_ok());
+        assert!(client.open_exec_pty(" ").unwrap_err().to_string().contains("Command cannot be empty"));
+    }
+
+    #[test]
+    fn test_open_exec_empty_command() {
+        let mock_connection = setup_mock_connection();
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_exec("  ");
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Command cannot be empty"));
+    }
+
+    #[test]
+    fn test_open_exec_not_authenticated() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| false);
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_exec("uptime");
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Not authenticated"));
+    }
+
+    #[test]
+    fn test_open_sftp_success() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| true);
+        mock_connection
+            .expect_open_sftp()
+            .times(1)
+            .returning(|| Ok(Box::new(MockSftpSession::new())));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        assert!(client.open_sftp().is_ok());
+    }
+
+    #[test]
+    fn test_open_sftp_not_authenticated() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection
+            .expect_is_authenticated()
+            .times(1)
+            .returning(|| false);
+
+        let client = SshClient::new(Box::new(mock_connection));
+        let result = client.open_sftp();
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Not authenticated"));
+    }
+
     #[test]
     fn test_is_authenticated_true() {
         let mut mock_connection = setup_mock_connection();
@@ -315,4 +1478,102 @@ mod tests {
         let client = SshClient::new(Box::new(mock_connection));
         assert!(!client.is_authenticated());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_probe_remote() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection
+            .expect_run_command()
+            .withf(|command| command.starts_with("sh -c ") && command.contains("uname -s"))
+            .times(1)
+            .returning(|_| {
+                Ok(CommandOutput {
+                    stdout: "os=Linux\nrelease=6.1.0\narch=x86_64\nshell=/bin/bash\ncommand=scp\ncommand=sudo\n".to_string(),
+                    exit_status: 0,
+                    exit_signal: None,
+                })
+            });
+
+        let mut client = SshClient::new(Box::new(mock_connection));
+        assert!(client.remote_info().is_none());
+        let info = client.probe_remote().unwrap().clone();
+        assert_eq!(info.os, "Linux");
+        assert!(info.has_command("sudo"));
+        assert!(!info.has_command("rsync"));
+        assert_eq!(info.summary(), "Linux 6.1.0 x86_64, shell /bin/bash, tools: scp sudo");
+        assert_eq!(client.remote_info(), Some(&info));
+    }
+
+    #[test]
+    fn test_session_details_summary() {
+        let details = SessionDetails {
+            host_key_type: Some("ssh-ed25519".to_string()),
+            host_key_fingerprint: Some("SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s".to_string()),
+            kex: Some("curve25519-sha256".to_string()),
+            cipher: Some("aes256-ctr".to_string()),
+            latency: Some(std::time::Duration::from_micros(12_400)),
+            average_latency: None,
+        };
+        assert_eq!(details.summary(), "ssh-ed25519 SHA256:uNiVztksCsDh · curve25519-sha256 · aes256-ctr · 12 ms");
+        assert_eq!(SessionDetails { cipher: Some("aes128-ctr".to_string()), ..Default::default() }.summary(), "aes128-ctr");
+
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_session_details().returning(move || Some(details.clone()));
+        let client = SshClient::new(Box::new(mock_connection));
+        assert_eq!(client.session_details().unwrap().kex.as_deref(), Some("curve25519-sha256"));
+    }
+
+    #[test]
+    fn test_channels_opened_counts_successes() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection.expect_run_command().returning(|_| {
+            Ok(CommandOutput { stdout: String::new(), exit_status: 1, exit_signal: None })
+        });
+        mock_connection
+            .expect_open_direct_tcpip()
+            .returning(|_, _| Err(anyhow::anyhow!("administratively prohibited")));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        assert_eq!(client.channels_opened(), 0);
+        client.run_command("false").unwrap();
+        client.run_command("true").unwrap();
+        assert!(client.open_direct_tcpip("db", 5432).is_err());
+        assert_eq!(client.channels_opened(), 2);
+    }
+
+    #[test]
+    fn test_disconnect_callback_runs_once_on_drop() {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let mut client = SshClient::new(Box::new(setup_mock_connection()));
+        let counter = std::sync::Arc::clone(&calls);
+        client.set_disconnect_callback(Box::new(move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 0);
+        drop(client);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_latency_prefers_measured_round_trip() {
+        let mut mock_connection = setup_mock_connection();
+        mock_connection.expect_is_authenticated().returning(|| true);
+        mock_connection.expect_session_details().returning(|| {
+            Some(SessionDetails { latency: Some(std::time::Duration::from_millis(30)), ..Default::default() })
+        });
+        mock_connection.expect_round_trip().times(1).returning(|| Ok(std::time::Duration::from_millis(45)));
+
+        let client = SshClient::new(Box::new(mock_connection));
+        assert_eq!(client.latency(), Some(std::time::Duration::from_millis(30)));
+        assert_eq!(client.measure_latency().unwrap(), std::time::Duration::from_millis(45));
+        assert_eq!(client.latency(), Some(std::time::Duration::from_millis(45)));
+    }
+
+    #[test]
+    fn test_remote_info_needs_posix_shell() {
+        assert!(RemoteInfo::parse("'sh' is not recognized as an internal or external command\r\n").is_err());
+        assert_eq!(RemoteInfo::parse("os=FreeBSD\n").unwrap().summary(), "FreeBSD");
+    }
+}
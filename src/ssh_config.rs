@@ -0,0 +1,390 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::path::{Path, PathBuf};
+
+use crate::profiles::{include_paths, MatchCriteria, Profile, Section, MAX_INCLUDE_DEPTH};
+
+/// Read an OpenSSH client config into profile sections: one per `Host` or `Match`
+/// block, plus a `*` section for options before the first block. Only the options
+/// bxssh understands are kept; the rest are skipped. `Include` paths are relative to
+/// the directory of `path`, as `~/.ssh` is for OpenSSH.
+pub fn load(path: &Path) -> Result<Vec<Section>> {
+    let mut reader = Reader { sections: Vec::new(), current: None, base: path.parent().unwrap_or(Path::new(".")).to_path_buf() };
+    reader.read_file(path, 0)?;
+    Ok(reader.sections)
+}
+
+/// Values of the `%` tokens OpenSSH expands in `IdentityFile`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tokens<'a> {
+    /// `%h`, the host connected to, after `HostName`
+    pub host: &'a str,
+    /// `%n`, the host as given
+    pub alias: &'a str,
+    /// `%p`
+    pub port: u16,
+    /// `%r`, the remote user
+    pub remote_user: &'a str,
+    /// `%u`, the local user
+    pub local_user: String,
+    /// `%d`, the local home directory
+    pub home: String,
+}
+
+impl<'a> Tokens<'a> {
+    /// Tokens for a connection, with the local user and home directory of this process
+    pub fn new(host: &'a str, alias: &'a str, port: u16, remote_user: &'a str) -> Self {
+        Self {
+            host,
+            alias,
+            port,
+            remote_user,
+            local_user: std::env::var(crate::config::USER_ENV).unwrap_or_default(),
+            home: crate::config::home_dir().map(|home| home.to_string_lossy().into_owned()).unwrap_or_default(),
+        }
+    }
+}
+
+/// Expand `%%`, `%d`, `%h`, `%n`, `%p`, `%r` and `%u` in `value`, as OpenSSH does in
+/// `IdentityFile` paths; any other token is an error
+pub fn expand_tokens(value: &str, tokens: &Tokens) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('d') => expanded.push_str(&tokens.home),
+            Some('h') => expanded.push_str(tokens.host),
+            Some('n') => expanded.push_str(tokens.alias),
+            Some('p') => expanded.push_str(&tokens.port.to_string()),
+            Some('r') => expanded.push_str(tokens.remote_user),
+            Some('u') => expanded.push_str(&tokens.local_user),
+            Some(other) => return Err(anyhow::anyhow!("Unknown token %{} in '{}'", other, value)),
+            None => return Err(anyhow::anyhow!("Incomplete token at the end of '{}'", value)),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Parse config text that has no `Include` lines
+#[allow(dead_code)] // Library API; the CLI reads files with load
+pub fn parse(content: &str) -> Result<Vec<Section>> {
+    let mut reader = Reader { sections: Vec::new(), current: None, base: PathBuf::from(".") };
+    reader.read(content, None, 0)?;
+    Ok(reader.sections)
+}
+
+struct Reader {
+    sections: Vec<Section>,
+    /// Block options are added to; `None` before the first block. A `Match` block
+    /// with criteria bxssh cannot evaluate has no section and its options are dropped.
+    current: Option<Option<usize>>,
+    base: PathBuf,
+}
+
+impl Reader {
+    fn read_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(anyhow::anyhow!("Too many nested includes at {}", path.display()));
+        }
+        let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        self.read(&content, Some(path), depth)
+    }
+
+    fn read(&mut self, content: &str, source: Option<&Path>, depth: usize) -> Result<()> {
+        let location = |number: usize| match source {
+            Some(path) => format!("{} line {}", path.display(), number + 1),
+            None => format!("line {}", number + 1),
+        };
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = split_keyword(line);
+            let args = split_args(rest).with_context(|| location(number))?;
+            self.option(&keyword.to_ascii_lowercase(), args, source, depth)
+                .with_context(|| location(number))?;
+        }
+        Ok(())
+    }
+
+    fn option(&mut self, keyword: &str, args: Vec<String>, source: Option<&Path>, depth: usize) -> Result<()> {
+        let value = || args.first().cloned().ok_or_else(|| anyhow::anyhow!("Missing argument to {}", keyword));
+        match keyword {
+            "host" => {
+                let name = args.join(" ");
+                self.start_section(name, None, source);
+            }
+            "match" => match parse_match(&args)? {
+                Some(None) => self.start_section("*".to_string(), None, source),
+                Some(Some(criteria)) => self.start_section(format!("Match {}", args.join(" ")), Some(criteria), source),
+                None => {
+                    log::warn!("Skipping unsupported 'Match {}' block", args.join(" "));
+                    self.current = Some(None);
+                }
+            },
+            "include" => {
+                // Each included file starts out in the including block
+                let outer = self.current;
+                for pattern in &args {
+                    for path in include_paths(pattern, &self.base)? {
+                        self.read_file(&path, depth + 1)?;
+                        self.current = outer;
+                    }
+                }
+            }
+            _ => {
+                let Some(profile) = self.profile(source) else {
+                    return Ok(());
+                };
+                match keyword {
+                    "hostname" => set(&mut profile.host_name, value()?),
+                    "user" => set(&mut profile.user, value()?),
+                    "port" => set(&mut profile.port, value()?.parse().context("Invalid port number")?),
+                    // Repeated within a block, each is tried in turn
+                    "identityfile" => profile.identity_file.push(value()?),
+                    "identitiesonly" => set(&mut profile.identities_only, value()?.eq_ignore_ascii_case("yes")),
+                    "canonicalizehostname" => set(&mut profile.canonicalize_hostname, !value()?.eq_ignore_ascii_case("no")),
+                    "canonicaldomains" => {
+                        if profile.canonical_domains.is_empty() {
+                            profile.canonical_domains = args;
+                        }
+                    }
+                    "canonicalizemaxdots" => {
+                        set(&mut profile.canonicalize_max_dots, value()?.parse().context("Invalid CanonicalizeMaxDots")?)
+                    }
+                    "connectionattempts" => {
+                        set(&mut profile.connection_attempts, value()?.parse().context("Invalid ConnectionAttempts")?)
+                    }
+                    "ipqos" => {
+                        let value = args.join(" ");
+                        crate::socket_options::parse_tos(&value)?;
+                        set(&mut profile.ip_qos, value)
+                    }
+                    "canonicalizefallbacklocal" => set(&mut profile.canonicalize_fallback_local, !value()?.eq_ignore_ascii_case("no")),
+                    // Kept only to warn that they are ignored, for hosts they apply to
+                    "proxyjump" | "proxycommand" if !value()?.eq_ignore_ascii_case("none") => {
+                        let name = if keyword == "proxyjump" { "ProxyJump" } else { "ProxyCommand" };
+                        set(&mut profile.ignored_proxy, format!("{} {}", name, args.join(" ")));
+                    }
+                    _ => debug!("Ignoring unsupported ssh_config option: {}", keyword),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start_section(&mut self, name: String, when: Option<MatchCriteria>, source: Option<&Path>) {
+        self.sections.push(Section {
+            name,
+            profile: Profile { when, ..Profile::default() },
+            source: source.map(Path::to_path_buf),
+        });
+        self.current = Some(Some(self.sections.len() - 1));
+    }
+
+    /// Profile of the current block, opening the leading `*` section if needed
+    fn profile(&mut self, source: Option<&Path>) -> Option<&mut Profile> {
+        let index = match self.current {
+            Some(index) => index?,
+            None => {
+                self.start_section("*".to_string(), None, source);
+                self.sections.len() - 1
+            }
+        };
+        Some(&mut self.sections[index].profile)
+    }
+}
+
+/// Like OpenSSH, the first value given for an option wins
+fn set<T>(slot: &mut Option<T>, value: T) {
+    if slot.is_none() {
+        *slot = Some(value);
+    }
+}
+
+/// Split `Keyword value` or `Keyword=value`
+fn split_keyword(line: &str) -> (&str, &str) {
+    let end = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+    (&line[..end], rest)
+}
+
+/// Whitespace-separated arguments; double quotes group words
+fn split_args(rest: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut started = false;
+    for c in rest.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                started = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if started {
+                    args.push(std::mem::take(&mut current));
+                    started = false;
+                }
+            }
+            c => {
+                current.push(c);
+                started = true;
+            }
+        }
+    }
+    if in_quotes {
+        return Err(anyhow::anyhow!("Unterminated quote"));
+    }
+    if started {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Criteria of a `Match` line: `Some(None)` for `Match all`, `None` when it uses
+/// criteria bxssh cannot evaluate
+fn parse_match(args: &[String]) -> Result<Option<Option<MatchCriteria>>> {
+    if args.len() == 1 && args[0].eq_ignore_ascii_case("all") {
+        return Ok(Some(None));
+    }
+    let mut criteria = MatchCriteria::default();
+    let mut args = args.iter();
+    while let Some(criterion) = args.next() {
+        let value = args.next().cloned().ok_or_else(|| anyhow::anyhow!("Match {} needs an argument", criterion));
+        match criterion.to_ascii_lowercase().as_str() {
+            "host" => criteria.host = Some(value?),
+            "user" => criteria.user = Some(value?),
+            "localnetwork" => criteria.local_network = Some(value?),
+            "exec" => criteria.exec = Some(value?),
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(Some(criteria)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_host_blocks() {
+        let sections = parse(
+            "# global\n\
+             User everyone\n\
+             \n\
+             Host prod prod-*\n\
+             \tHostName prod.example.com\n\
+             \tPort=2222\n\
+             \tUser deploy\n\
+             \tUser ignored\n\
+             \tForwardAgent yes\n\
+             \tProxyJump bastion\n\
+             \tConnectionAttempts 3\n\
+             \tIPQoS lowdelay throughput\n\
+             Host *\n\
+             \tIdentityFile \"~/.ssh/id work\"\n\
+             \tIdentityFile ~/.ssh/id_old\n\
+             \tIdentitiesOnly yes\n\
+             \tCanonicalDomains corp.example example.com\n",
+        )
+        .unwrap();
+
+        let names: Vec<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["*", "prod prod-*", "*"]);
+        assert_eq!(sections[0].profile.user.as_deref(), Some("everyone"));
+        let prod = &sections[1].profile;
+        assert_eq!(prod.host_name.as_deref(), Some("prod.example.com"));
+        assert_eq!(prod.port, Some(2222));
+        assert_eq!(prod.user.as_deref(), Some("deploy"));
+        assert_eq!(prod.connection_attempts, Some(3));
+        assert_eq!(prod.ip_qos.as_deref(), Some("lowdelay throughput"));
+        assert_eq!(prod.ignored_proxy.as_deref(), Some("ProxyJump bastion"));
+        assert_eq!(sections[2].profile.identity_file, ["~/.ssh/id work", "~/.ssh/id_old"]);
+        assert_eq!(sections[2].profile.identities_only, Some(true));
+        assert_eq!(sections[2].profile.canonical_domains, ["corp.example", "example.com"]);
+    }
+
+    #[test]
+    fn test_parse_match_blocks() {
+        let sections = parse(
+            "Match host *.corp exec \"test -f /tmp/vpn\"\n  Port 2200\n\
+             Match canonical host *.corp\n  Port 1\n\
+             Match all\n  User fallback\n",
+        )
+        .unwrap();
+
+        assert_eq!(sections.len(), 2);
+        let criteria = sections[0].profile.when.as_ref().unwrap();
+        assert_eq!(criteria.host.as_deref(), Some("*.corp"));
+        assert_eq!(criteria.exec.as_deref(), Some("test -f /tmp/vpn"));
+        assert_eq!(sections[0].profile.port, Some(2200));
+        assert_eq!(sections[1].name, "*");
+        assert_eq!(sections[1].profile.user.as_deref(), Some("fallback"));
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_line() {
+        let err = parse("Host a\n  Port ssh\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("line 2: Invalid port number"));
+
+        let err = parse("Host \"a\n").unwrap_err();
+        assert!(format!("{:#}", err).contains("line 1: Unterminated quote"));
+    }
+
+    #[test]
+    fn test_include_globs_and_restores_block() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir(temp.path().join("config.d")).unwrap();
+        std::fs::write(temp.path().join("config.d").join("10-work"), "Host work\n  Port 2201\n").unwrap();
+        std::fs::write(temp.path().join("config.d").join("20-home"), "Port 2202\nHost home\n").unwrap();
+        std::fs::write(temp.path().join("config.d").join("notes.txt"), "Host notes\n").unwrap();
+        let config = temp.path().join("config");
+        std::fs::write(&config, "Host base\n  Include config.d/??-*\n  User base-user\n").unwrap();
+
+        let sections = load(&config).unwrap();
+
+        let names: Vec<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["base", "work", "home"]);
+        // Leading lines of an included file, and lines after the Include, belong to `base`
+        assert_eq!(sections[0].profile.port, Some(2202));
+        assert_eq!(sections[0].profile.user.as_deref(), Some("base-user"));
+        assert_eq!(sections[1].profile.port, Some(2201));
+        assert_eq!(sections[1].source.as_deref(), Some(temp.path().join("config.d").join("10-work").as_path()));
+    }
+
+    #[test]
+    fn test_expand_tokens() {
+        let tokens = Tokens {
+            host: "prod.example.com",
+            alias: "prod",
+            port: 2222,
+            remote_user: "deploy",
+            local_user: "alice".to_string(),
+            home: "/home/alice".to_string(),
+        };
+        assert_eq!(expand_tokens("%d/.ssh/id_%h", &tokens).unwrap(), "/home/alice/.ssh/id_prod.example.com");
+        assert_eq!(expand_tokens("~/.ssh/%r@%n:%p-%u 100%%", &tokens).unwrap(), "~/.ssh/deploy@prod:2222-alice 100%");
+        assert_eq!(expand_tokens("work", &tokens).unwrap(), "work");
+        assert!(expand_tokens("%C", &tokens).is_err());
+        assert!(expand_tokens("id_%", &tokens).is_err());
+    }
+
+    #[test]
+    fn test_include_loop_is_an_error() {
+        let temp = TempDir::new().unwrap();
+        let config = temp.path().join("config");
+        std::fs::write(&config, "Include config\n").unwrap();
+
+        let err = load(&config).unwrap_err();
+        assert!(format!("{:#}", err).contains("Too many nested includes"));
+    }
+}
@@ -1,13 +1,79 @@
 use anyhow::{Context, Result};
-use ssh2::{Channel, Session};
+use base64::Engine;
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+use ssh2::{Channel, DisconnectCode, ErrorCode, FileStat, HashType, Listener, MethodType, Session, Sftp};
 use std::io::Read;
-use std::net::TcpStream;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::ssh_client::{SshConnection, ShellSession};
+use crate::cancel::CancelToken;
+use crate::retry::RetryPolicy;
+use crate::socket_options::SocketOptions;
+use crate::ssh_client::{
+    ChannelNotOpened, CommandOutput, ExitSignal, ForwardListener, PasswordChangeRequired, RemoteFile, RemoteFileAttr,
+    RemoteFileKind, SessionDetails, SftpSession, RequestRefused, Signal, SshConnection, ShellSession, WriteOutcome,
+};
+
+/// libssh2 return code for "would block" on a non-blocking session
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+
+/// libssh2 return code for a connection the server closed, usually with SSH_MSG_DISCONNECT
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+
+/// libssh2 return codes for a server that stopped answering, or dropped the
+/// connection before the key exchange (as sshd does past `MaxStartups`)
+const LIBSSH2_ERROR_BANNER_RECV: i32 = -2;
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+
+/// libssh2 return code for SSH_MSG_USERAUTH_PASSWD_CHANGEREQ without a callback to answer it
+const LIBSSH2_ERROR_PASSWORD_EXPIRED: i32 = libssh2_sys::LIBSSH2_ERROR_PASSWORD_EXPIRED;
+
+/// libssh2 return codes for a password or key the server turned down
+const LIBSSH2_ERROR_AUTHENTICATION_FAILED: i32 = -18;
+const LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED: i32 = -19;
+
+/// libssh2 return code for a key file it can't use: encrypted with a passphrase it
+/// wasn't given, or not a private key at all
+const LIBSSH2_ERROR_FILE: i32 = -16;
+
+/// libssh2 return code for a channel the server refused, e.g. past `MaxSessions`
+const LIBSSH2_ERROR_CHANNEL_FAILURE: i32 = -21;
+
+/// libssh2 return code for a channel request (`pty-req`, `shell`, `exec`) the server turned down
+const LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED: i32 = -22;
+
+/// libssh2 return code for a global request the server turned down, such as a
+/// `tcpip-forward` for a port that is taken or not allowed
+const LIBSSH2_ERROR_REQUEST_DENIED: i32 = -32;
+
+/// SFTP status code for a file the server won't let us open as asked
+const LIBSSH2_FX_PERMISSION_DENIED: i32 = 3;
+
+/// Waiting out EAGAIN on a non-blocking session
+const WOULD_BLOCK: RetryPolicy = RetryPolicy::poll(Duration::from_millis(10), would_block);
+
+/// TERM for PTYs, which vim and most full-screen programs handle fully
+const DEFAULT_TERM: &str = "xterm-256color";
+
+/// How often a TCP connect in progress checks its cancel token
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 pub struct RealSshConnection {
     session: Option<Session>,
-    _stream: Option<TcpStream>,
+    _stream: Option<Arc<TcpStream>>,
+    client_version: String,
+    /// Time the TCP connect took
+    latency: Option<Duration>,
+    /// Count and total time of the round trips timed so far: the TCP connect and
+    /// every channel open
+    round_trips: Mutex<(u32, Duration)>,
+    socket_options: SocketOptions,
+    /// TERM for PTY requests
+    term: String,
+    cancel: Option<CancelToken>,
 }
 
 impl RealSshConnection {
@@ -15,16 +81,353 @@ impl RealSshConnection {
         Self {
             session: None,
             _stream: None,
+            client_version: crate::config::CLIENT_VERSION.to_string(),
+            latency: None,
+            round_trips: Mutex::new((0, Duration::ZERO)),
+            socket_options: SocketOptions::default(),
+            term: DEFAULT_TERM.to_string(),
+            cancel: None,
+        }
+    }
+
+    /// Run `operation`, one network round trip such as a channel open, and add its
+    /// time to the average latency when it succeeds
+    fn timed<T, E>(&self, operation: impl FnOnce() -> std::result::Result<T, E>) -> std::result::Result<T, E> {
+        let started = Instant::now();
+        let result = operation();
+        if result.is_ok() {
+            self.record_round_trip(started.elapsed());
+        }
+        result
+    }
+
+    fn record_round_trip(&self, elapsed: Duration) {
+        if let Ok(mut round_trips) = self.round_trips.lock() {
+            round_trips.0 += 1;
+            round_trips.1 += elapsed;
+        }
+    }
+
+    /// Exec channel for `command`. Its stderr is merged into stdout by libssh2 with
+    /// `merge_stderr`, and otherwise goes straight to our stderr (e.g. git progress)
+    /// so it never mixes with the binary stdout stream.
+    fn exec_channel(&self, command: &str, merge_stderr: bool) -> Result<Box<dyn ShellSession>> {
+        let session = self.session.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        let channel = blocking(session, || {
+            let mut channel = self.timed(|| session.channel_session()).context(ChannelNotOpened)?;
+            if merge_stderr {
+                channel.handle_extended_data(ssh2::ExtendedData::Merge).context("Failed to merge stderr")?;
+            }
+            channel.exec(command).map_err(|e| request_error(e, "exec")).context("Failed to execute command")?;
+            Ok(channel)
+        })?;
+
+        session.set_blocking(false);
+        Ok(Box::new(RealShellSession::new(channel, None, !merge_stderr)))
+    }
+
+    /// Identification string to send instead of `SSH-2.0-bxssh_<version>`
+    pub fn set_client_version(&mut self, version: &str) {
+        self.client_version = version.to_string();
+    }
+
+    /// TCP settings for the next connect
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    /// TERM to request PTYs with instead of `xterm-256color`
+    pub fn set_term(&mut self, term: &str) {
+        self.term = term.to_string();
+    }
+
+    /// Authentication methods the server offers `username`, for `bxssh diagnose`
+    pub fn auth_methods(&mut self, username: &str) -> Result<Vec<String>> {
+        let session = self.session.as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+        let methods = session.auth_methods(username).context("Failed to query authentication methods")?;
+        Ok(methods.split(',').filter(|method| !method.is_empty()).map(String::from).collect())
+    }
+
+    /// Algorithms libssh2 can negotiate, as a client KEXINIT offer
+    pub fn supported_algorithms() -> Result<crate::ssh_wire::KexInit> {
+        let session = Session::new().context("Failed to create SSH session")?;
+        let list = |method| -> Result<Vec<String>> {
+            Ok(session.supported_algs(method)?.into_iter().map(String::from).collect())
+        };
+        Ok(crate::ssh_wire::KexInit {
+            kex_algorithms: list(MethodType::Kex)?,
+            server_host_key_algorithms: list(MethodType::HostKey)?,
+            encryption_client_to_server: list(MethodType::CryptCs)?,
+            encryption_server_to_client: list(MethodType::CryptSc)?,
+            mac_client_to_server: list(MethodType::MacCs)?,
+            mac_server_to_client: list(MethodType::MacSc)?,
+            ..Default::default()
+        })
+    }
+}
+
+impl Default for RealSshConnection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RealSshConnection {
+    /// Tell the server we are leaving (SSH_MSG_DISCONNECT, by application), on
+    /// errors as well as on a normal exit
+    fn drop(&mut self) {
+        if let Some(session) = &self.session {
+            let _ = session.disconnect(Some(DisconnectCode::ByApplication), "Disconnected by user", None);
+        }
+    }
+}
+
+/// TCP connect that gives up when `token` fires. The connect runs on its own
+/// thread, which is left to finish by itself when the token wins.
+fn connect_tcp(addresses: &[SocketAddr], options: SocketOptions, token: Option<&CancelToken>) -> Result<TcpStream> {
+    let Some(token) = token else {
+        return Ok(connect_addresses(addresses, options)?);
+    };
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let addresses = addresses.to_vec();
+    std::thread::spawn(move || {
+        let _ = sender.send(connect_addresses(&addresses, options));
+    });
+    loop {
+        token.check()?;
+        match receiver.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(result) => return Ok(result?),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(anyhow::anyhow!("TCP connect thread exited without a result"))
+            }
+        }
+    }
+}
+
+/// Try each address in turn like `TcpStream::connect`, with `options` applied to
+/// the socket first so buffer sizes take part in the window negotiation
+fn connect_addresses(addresses: &[SocketAddr], options: SocketOptions) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for address in addresses {
+        match connect_socket(address, options) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+    }))
+}
+
+fn connect_socket(address: &SocketAddr, options: SocketOptions) -> std::io::Result<TcpStream> {
+    let socket = Socket::new(Domain::for_address(*address), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_nodelay(options.nodelay.unwrap_or(true))?;
+    if let Some(time) = options.keepalive {
+        let keepalive = TcpKeepalive::new().with_time(time);
+        #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+        let keepalive = keepalive.with_interval(time);
+        socket.set_keepalive(true)?;
+        socket.set_tcp_keepalive(&keepalive)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(tos) = options.tos {
+        set_tos(&socket, address, tos);
+    }
+    socket.connect(&(*address).into())?;
+    Ok(socket.into())
+}
+
+/// Mark outgoing packets like OpenSSH's `IPQoS`. Best effort: networks and
+/// platforms that don't allow it still get a working connection.
+fn set_tos(socket: &Socket, address: &SocketAddr, tos: u32) {
+    let result = match address {
+        SocketAddr::V4(_) => socket.set_tos(tos),
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        SocketAddr::V6(_) => socket.set_tclass_v6(tos),
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        SocketAddr::V6(_) => Err(std::io::Error::from(std::io::ErrorKind::Unsupported)),
+    };
+    if let Err(e) = result {
+        log::debug!("Could not set IP type of service {:#x}: {}", tos, e);
+    }
+}
+
+/// Unblock libssh2 when `token` fires: cancelling shuts the socket down, and a
+/// deadline becomes the session's timeout for blocking calls
+fn watch_cancel(token: &CancelToken, session: &Session, stream: &Arc<TcpStream>) {
+    if let Some(remaining) = token.remaining() {
+        // Rounded up, so a timed-out call finds the deadline passed
+        session.set_timeout((remaining.as_millis() + 1).min(u32::MAX as u128) as u32);
+    }
+    // Weak, so a long-lived token doesn't keep the connection open
+    let stream = Arc::downgrade(stream);
+    token.on_cancel(move || {
+        if let Some(stream) = stream.upgrade() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    });
+}
+
+/// Wrap an authentication error; libssh2 drops the server's DISCONNECT reason,
+/// but a server hanging up mid-authentication almost always means too many failures
+fn auth_error(error: ssh2::Error, message: &'static str) -> anyhow::Error {
+    if error.code() == ErrorCode::Session(LIBSSH2_ERROR_PASSWORD_EXPIRED) {
+        anyhow::Error::new(PasswordChangeRequired { prompt: String::new() }).context(message)
+    } else if error.code() == ErrorCode::Session(LIBSSH2_ERROR_SOCKET_DISCONNECT) {
+        anyhow::anyhow!("{}: server disconnected (too many authentication failures?)", message)
+    } else {
+        anyhow::Error::new(error).context(message)
+    }
+}
+
+thread_local! {
+    /// The answer `supply_new_password` hands libssh2, set for one `change_password` call
+    static NEW_PASSWORD: std::cell::RefCell<Option<Vec<u8>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// libssh2's password change callback. Answers once; a second request within the
+/// same call gets no password, which libssh2 reports as PASSWORD_EXPIRED.
+extern "C" fn supply_new_password(
+    _session: *mut libssh2_sys::LIBSSH2_SESSION,
+    newpw: *mut *mut libc::c_char,
+    newpw_len: *mut libc::c_int,
+    _abstract: *mut *mut libc::c_void,
+) {
+    let Some(password) = NEW_PASSWORD.with(|slot| slot.borrow_mut().take()) else {
+        return;
+    };
+    // SAFETY: libssh2 passes valid out-pointers and takes ownership of the buffer
+    unsafe {
+        let buffer = libc::malloc(password.len().max(1)).cast::<libc::c_char>();
+        if buffer.is_null() {
+            return;
         }
+        std::ptr::copy_nonoverlapping(password.as_ptr().cast(), buffer, password.len());
+        *newpw = buffer;
+        *newpw_len = password.len() as libc::c_int;
     }
 }
 
+/// libssh2 errors that may not happen again; see [`crate::retry::is_transient`]
+pub fn is_transient(error: &ssh2::Error) -> bool {
+    matches!(
+        error.code(),
+        ErrorCode::Session(
+            LIBSSH2_ERROR_EAGAIN
+                | LIBSSH2_ERROR_BANNER_RECV
+                | LIBSSH2_ERROR_SOCKET_DISCONNECT
+                | LIBSSH2_ERROR_TIMEOUT
+                | LIBSSH2_ERROR_SOCKET_TIMEOUT
+                | LIBSSH2_ERROR_CHANNEL_FAILURE
+        )
+    )
+}
+
+/// The server turned the password or key down, as opposed to the login failing
+/// for some other reason (network, cancellation, unreadable key)
+pub fn is_auth_rejected(error: &anyhow::Error) -> bool {
+    error.chain().filter_map(|cause| cause.downcast_ref::<ssh2::Error>()).any(|e| {
+        matches!(
+            e.code(),
+            ErrorCode::Session(LIBSSH2_ERROR_AUTHENTICATION_FAILED | LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED)
+        )
+    })
+}
+
+/// A [`RequestRefused`] when the server turned `request` down, else `error` as it is
+fn request_error(error: ssh2::Error, request: &'static str) -> anyhow::Error {
+    match error.code() {
+        ErrorCode::Session(LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED | LIBSSH2_ERROR_REQUEST_DENIED) => {
+            anyhow::Error::new(RequestRefused { request })
+        }
+        _ => error.into(),
+    }
+}
+
+/// The key file couldn't be used, before the server had a say
+pub fn is_key_unreadable(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<ssh2::Error>())
+        .any(|e| e.code() == ErrorCode::Session(LIBSSH2_ERROR_FILE))
+}
+
+fn would_block(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<ssh2::Error>()
+        .is_some_and(|e| e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN))
+}
+
+/// Run `operation` with `session` in blocking mode, then restore its mode. Channels
+/// of a running session are polled non-blocking, but opening another one from the
+/// same loop (e.g. a forward) must wait for the server's reply.
+fn blocking<T>(session: &Session, operation: impl FnOnce() -> Result<T>) -> Result<T> {
+    let was_blocking = session.is_blocking();
+    session.set_blocking(true);
+    let result = operation();
+    session.set_blocking(was_blocking);
+    result
+}
+
+/// An SFTP error as `io::Error`, with permission errors kept apart from the rest
+/// (ssh2 only maps missing files)
+fn sftp_error(error: ssh2::Error) -> std::io::Error {
+    if error.code() == ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED) {
+        return std::io::Error::new(std::io::ErrorKind::PermissionDenied, error.message().to_string());
+    }
+    error.into()
+}
+
+/// Request an xterm PTY sized to the local terminal (80x24 when there is none);
+/// returns the size sent so later resizes can be detected
+fn request_terminal_pty(channel: &mut Channel, term: &str) -> Result<(u32, u32)> {
+    // Get terminal size for vim and other full-screen applications
+    let (width, height) = match crossterm::terminal::size() {
+        Ok((w, h)) => (w as u32, h as u32),
+        Err(_) => (80, 24), // fallback
+    };
+
+    // Request PTY with proper terminal capabilities for vim
+    // Note: ssh2 crate doesn't expose all terminal mode constants, so we'll rely on
+    // proper TERM environment variable and focus on filtering problematic sequences
+    channel.request_pty(term, None, None)
+        .map_err(|e| request_error(e, "pty-req"))
+        .context("Failed to request PTY")?;
+
+    // Set the window size after PTY creation
+    channel.request_pty_size(width, height, Some(0), Some(0))?;
+    Ok((width, height))
+}
+
 impl SshConnection for RealSshConnection {
     fn connect(&mut self, host: &str, port: u16) -> Result<()> {
-        let tcp = TcpStream::connect(format!("{}:{}", host, port))
-            .context("Failed to connect to host")?;
+        crate::host_policy::enforce(host, port)?;
+        // Resolve first so the connect time measures the network, not DNS
+        let addresses: Vec<SocketAddr> = (host, port).to_socket_addrs()
+            .context("Failed to connect to host")?
+            .collect();
+        let ips: Vec<_> = addresses.iter().map(SocketAddr::ip).collect();
+        crate::host_policy::enforce_addresses(host, port, &ips)?;
+        let started = Instant::now();
+        let tcp = Arc::new(connect_tcp(&addresses, self.socket_options, self.cancel.as_ref())
+            .context("Failed to connect to host")?);
+        self.latency = Some(started.elapsed());
+        self.record_round_trip(started.elapsed());
         
         let mut session = Session::new().context("Failed to create SSH session")?;
+        if let Some(token) = &self.cancel {
+            watch_cancel(token, &session, &tcp);
+        }
+        session.set_banner(&self.client_version).context("Invalid client version string")?;
         session.set_tcp_stream(tcp.try_clone().context("Failed to clone TCP stream")?);
         session.handshake().context("SSH handshake failed")?;
 
@@ -33,13 +436,29 @@ impl SshConnection for RealSshConnection {
         Ok(())
     }
 
+    fn auth_banner(&mut self, username: &str) -> Result<Option<String>> {
+        let session = self.session.as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        // libssh2 only captures the banner while querying the auth methods
+        session.auth_methods(username).context("Failed to query authentication methods")?;
+
+        match session.userauth_banner() {
+            Ok(banner) => Ok(banner.map(|b| b.to_string())),
+            Err(e) => {
+                log::debug!("No userauth banner: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
     fn authenticate_with_key(&mut self, username: &str, private_key_path: &str) -> Result<()> {
         let session = self.session.as_mut()
             .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
         
         session
             .userauth_pubkey_file(username, None, std::path::Path::new(private_key_path), None)
-            .context("SSH key authentication failed")
+            .map_err(|e| auth_error(e, "SSH key authentication failed"))
     }
 
     fn authenticate_with_password(&mut self, username: &str, password: &str) -> Result<()> {
@@ -48,61 +467,170 @@ impl SshConnection for RealSshConnection {
         
         session
             .userauth_password(username, password)
-            .context("SSH password authentication failed")
+            .map_err(|e| auth_error(e, "SSH password authentication failed"))
+    }
+
+    fn change_password(&mut self, username: &str, old_password: &str, new_password: &str) -> Result<()> {
+        let session = self.session.as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        NEW_PASSWORD.with(|slot| *slot.borrow_mut() = Some(new_password.as_bytes().to_vec()));
+        let rc = {
+            let mut raw = session.raw();
+            let raw: *mut libssh2_sys::LIBSSH2_SESSION = &mut *raw;
+            // SAFETY: `raw` stays locked for the call; the strings outlive it and
+            // libssh2 frees what the callback allocates with its default free()
+            let rc = unsafe {
+                libssh2_sys::libssh2_userauth_password_ex(
+                    raw,
+                    username.as_ptr().cast(),
+                    username.len() as libc::c_uint,
+                    old_password.as_ptr().cast(),
+                    old_password.len() as libc::c_uint,
+                    Some(supply_new_password),
+                )
+            };
+            (rc != 0).then(|| ssh2::Error::from_session_error_raw(raw, rc))
+        };
+        NEW_PASSWORD.with(|slot| slot.borrow_mut().take());
+
+        match rc {
+            None => Ok(()),
+            // The server refused the new password and asked again
+            Some(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_PASSWORD_EXPIRED) => {
+                Err(anyhow::anyhow!("Server rejected the new password"))
+            }
+            Some(e) => Err(auth_error(e, "Password change failed")),
+        }
     }
 
     fn execute_command(&self, command: &str) -> Result<String> {
+        let output = self.run_command(command)?;
+        match output.failure() {
+            Some(reason) => Err(anyhow::anyhow!("Command {}", reason)),
+            None => Ok(output.stdout),
+        }
+    }
+
+    fn run_command(&self, command: &str) -> Result<CommandOutput> {
         let session = self.session.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
 
-        let mut channel = session.channel_session().context("Failed to create channel")?;
-        channel.exec(command).context("Failed to execute command")?;
+        blocking(session, || {
+            let mut channel = self.timed(|| session.channel_session()).context(ChannelNotOpened)?;
+            channel.exec(command).context("Failed to execute command")?;
 
-        let mut output = String::new();
-        channel.read_to_string(&mut output).context("Failed to read command output")?;
-        
-        channel.wait_close().context("Failed to close channel")?;
-        let exit_status = channel.exit_status().context("Failed to get exit status")?;
-        
-        if exit_status != 0 {
-            return Err(anyhow::anyhow!("Command failed with exit status {}", exit_status));
-        }
+            let mut stdout = String::new();
+            channel.read_to_string(&mut stdout).context("Failed to read command output")?;
 
-        Ok(output)
+            channel.wait_close().context("Failed to close channel")?;
+            let exit_status = channel.exit_status().context("Failed to get exit status")?;
+
+            Ok(CommandOutput {
+                stdout,
+                exit_status,
+                exit_signal: exit_signal(&channel),
+            })
+        })
     }
 
     fn start_shell(&self) -> Result<Box<dyn ShellSession>> {
         let session = self.session.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
 
-        let mut channel = session.channel_session().context("Failed to create channel")?;
-        
-        // Get terminal size for vim and other full-screen applications
-        let (width, height) = match crossterm::terminal::size() {
-            Ok((w, h)) => (w as u32, h as u32),
-            Err(_) => (80, 24), // fallback
-        };
-        
-        // Request PTY with proper terminal capabilities for vim
-        // Use xterm-256color which vim expects for full functionality
-        // Note: ssh2 crate doesn't expose all terminal mode constants, so we'll rely on
-        // proper TERM environment variable and focus on filtering problematic sequences
-        channel.request_pty("xterm-256color", None, None)
-            .context("Failed to request PTY")?;
-        
-        // Set the window size after PTY creation
-        channel.request_pty_size(width, height, Some(0), Some(0))?;
-        
-        // Start the shell
-        channel.shell().context("Failed to start shell")?;
+        let (channel, size) = blocking(session, || {
+            let mut channel = self.timed(|| session.channel_session()).context(ChannelNotOpened)?;
+            let size = request_terminal_pty(&mut channel, &self.term)?;
+            channel.shell().map_err(|e| request_error(e, "shell")).context("Failed to start shell")?;
+            Ok((channel, size))
+        })?;
         
         // Set the channel to non-blocking mode for better I/O handling
         session.set_blocking(false);
         
-        Ok(Box::new(RealShellSession { 
-            channel,
-            last_size: Some((width, height)),
-        }))
+        Ok(Box::new(RealShellSession::new(channel, Some(size), false)))
+    }
+
+    fn start_shell_without_pty(&self) -> Result<Box<dyn ShellSession>> {
+        let session = self.session.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        let channel = blocking(session, || {
+            let mut channel = self.timed(|| session.channel_session()).context(ChannelNotOpened)?;
+            channel.shell().map_err(|e| request_error(e, "shell")).context("Failed to start shell")?;
+            Ok(channel)
+        })?;
+
+        session.set_blocking(false);
+
+        // Without a PTY the server keeps stderr apart, and that is where restricted
+        // accounts explain themselves
+        Ok(Box::new(RealShellSession::new(channel, None, true)))
+    }
+
+    fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<Box<dyn ShellSession>> {
+        let session = self.session.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        let channel = blocking(session, || {
+            self.timed(|| session.channel_direct_tcpip(host, port, None))
+                .context("Failed to open direct-tcpip channel")
+        })?;
+
+        // Same non-blocking mode as the shell so the stdio pump can poll both directions
+        session.set_blocking(false);
+
+        // No PTY on a forwarding channel, so never send window-change requests
+        Ok(Box::new(RealShellSession::new(channel, None, false)))
+    }
+
+    fn forward_listen(&self, bind_address: &str, port: u16) -> Result<(Box<dyn ForwardListener>, u16)> {
+        let session = self.session.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        let (listener, bound) = blocking(session, || {
+            self.timed(|| session.channel_forward_listen(port, Some(bind_address), None))
+                .map_err(|e| request_error(e, "tcpip-forward"))
+        })?;
+
+        session.set_blocking(false);
+        Ok((Box::new(RealForwardListener { listener }), bound))
+    }
+
+    fn open_exec(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        self.exec_channel(command, false)
+    }
+
+    fn open_exec_merged(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        self.exec_channel(command, true)
+    }
+
+    fn open_exec_pty(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        let session = self.session.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        let (channel, size) = blocking(session, || {
+            let mut channel = self.timed(|| session.channel_session()).context(ChannelNotOpened)?;
+            let size = request_terminal_pty(&mut channel, &self.term)?;
+            channel.exec(command).context("Failed to execute command")?;
+            Ok((channel, size))
+        })?;
+
+        session.set_blocking(false);
+
+        // A PTY merges stderr into stdout; the size is tracked like the shell's
+        Ok(Box::new(RealShellSession::new(channel, Some(size), false)))
+    }
+
+    fn open_sftp(&self) -> Result<Box<dyn SftpSession>> {
+        let session = self.session.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        // SFTP requests are request/response, so use blocking mode even if an
+        // exec channel switched the session to non-blocking
+        session.set_blocking(true);
+        let sftp = session.sftp().context("Failed to open SFTP channel")?;
+        Ok(Box::new(RealSftpSession { sftp }))
     }
 
     fn is_authenticated(&self) -> bool {
@@ -110,11 +638,123 @@ impl SshConnection for RealSshConnection {
             .map(|s| s.authenticated())
             .unwrap_or(false)
     }
+
+    fn set_cancel_token(&mut self, token: CancelToken) {
+        if let (Some(session), Some(stream)) = (&self.session, &self._stream) {
+            watch_cancel(&token, session, stream);
+        }
+        self.cancel = Some(token);
+    }
+
+    fn round_trip(&self) -> Result<Duration> {
+        let session = self.session.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not connected"))?;
+
+        // libssh2 swallows keepalive replies, so time a channel open instead: the
+        // server answers CHANNEL_OPEN with a confirmation, one round trip
+        blocking(session, || {
+            let started = Instant::now();
+            let mut channel = session.channel_session().context("Failed to create channel")?;
+            let round_trip = started.elapsed();
+            self.record_round_trip(round_trip);
+            let _ = channel.close();
+            Ok(round_trip)
+        })
+    }
+
+    fn session_details(&self) -> Option<SessionDetails> {
+        let session = self.session.as_ref()?;
+        let fingerprint = session.host_key_hash(HashType::Sha256).map(|hash| {
+            format!("SHA256:{}", base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash))
+        });
+        Some(SessionDetails {
+            host_key_type: session.methods(MethodType::HostKey).map(String::from),
+            host_key_fingerprint: fingerprint,
+            kex: session.methods(MethodType::Kex).map(String::from),
+            cipher: session.methods(MethodType::CryptCs).map(String::from),
+            latency: self.latency,
+            average_latency: self.round_trips.lock().ok().and_then(|round_trips| match *round_trips {
+                (0, _) => None,
+                (count, total) => Some(total / count),
+            }),
+        })
+    }
+}
+
+#[cfg_attr(not(feature = "fuse"), allow(dead_code))] // SFTP is only used by `bxssh mount`
+pub struct RealSftpSession {
+    sftp: Sftp,
+}
+
+impl std::fmt::Debug for RealSftpSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RealSftpSession").finish_non_exhaustive()
+    }
+}
+
+impl RealSftpSession {
+    fn attr(stat: &FileStat) -> RemoteFileAttr {
+        let file_type = stat.file_type();
+        let kind = if file_type.is_dir() {
+            RemoteFileKind::Directory
+        } else if file_type.is_symlink() {
+            RemoteFileKind::Symlink
+        } else {
+            RemoteFileKind::File
+        };
+
+        RemoteFileAttr {
+            kind,
+            size: stat.size.unwrap_or(0),
+            perm: stat.perm.unwrap_or(0) & 0o7777,
+            mtime: stat.mtime.unwrap_or(0),
+        }
+    }
+}
+
+impl SftpSession for RealSftpSession {
+    fn stat(&mut self, path: &str) -> Result<RemoteFileAttr> {
+        let stat = self.sftp.stat(Path::new(path))
+            .map_err(sftp_error)
+            .with_context(|| format!("Failed to stat {}", path))?;
+        Ok(Self::attr(&stat))
+    }
+
+    fn read_dir(&mut self, path: &str) -> Result<Vec<(String, RemoteFileAttr)>> {
+        let entries = self.sftp.readdir(Path::new(path))
+            .map_err(sftp_error)
+            .with_context(|| format!("Failed to list {}", path))?;
+
+        Ok(entries
+            .iter()
+            .filter_map(|(entry, stat)| {
+                let name = entry.file_name()?.to_string_lossy().into_owned();
+                Some((name, Self::attr(stat)))
+            })
+            .collect())
+    }
+
+    fn open_reader(&mut self, path: &str) -> Result<Box<dyn RemoteFile>> {
+        let file = self.sftp.open(Path::new(path))
+            .map_err(sftp_error)
+            .with_context(|| format!("Failed to open {}", path))?;
+        Ok(Box::new(file))
+    }
+
+    fn create(&mut self, path: &str) -> Result<Box<dyn std::io::Write + Send>> {
+        let file = self.sftp.create(Path::new(path))
+            .map_err(sftp_error)
+            .with_context(|| format!("Failed to create {}", path))?;
+        Ok(Box::new(file))
+    }
 }
 
 pub struct RealShellSession {
     channel: Channel,
+    /// Last PTY size sent to the server; `None` for channels without a PTY
     last_size: Option<(u32, u32)>,
+    /// Copy the channel's extended data (stderr) to the local stderr
+    forward_stderr: bool,
 }
 
 impl std::fmt::Debug for RealShellSession {
@@ -124,7 +764,41 @@ impl std::fmt::Debug for RealShellSession {
 }
 
 impl RealShellSession {
+    fn new(channel: Channel, last_size: Option<(u32, u32)>, forward_stderr: bool) -> Self {
+        Self {
+            channel,
+            last_size,
+            forward_stderr,
+        }
+    }
+
+    fn drain_stderr(&mut self) {
+        use std::io::Write;
+
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.channel.stderr().read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let mut stderr = std::io::stderr();
+                    let _ = stderr.write_all(&buf[..n]);
+                    let _ = stderr.flush();
+                }
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::WouldBlock {
+                        log::debug!("SSH stderr read error: {}", e);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
     fn check_terminal_resize(&mut self) {
+        if self.last_size.is_none() {
+            return;
+        }
+
         if let Ok((width, height)) = crossterm::terminal::size() {
             let current_size = (width as u32, height as u32);
             
@@ -139,45 +813,21 @@ impl RealShellSession {
             }
         }
     }
-    
-    fn write_chunked(&mut self, data: &[u8]) -> Result<usize> {
-        use std::io::Write;
-        
-        const CHUNK_SIZE: usize = 512;
-        let mut total_written = 0;
-        
-        for chunk in data.chunks(CHUNK_SIZE) {
-            let mut retries = 0;
-            const MAX_RETRIES: usize = 3;
-            
-            loop {
-                match self.channel.write(chunk) {
-                    Ok(n) => {
-                        total_written += n;
-                        if n < chunk.len() {
-                            // Partial write, wait a bit and continue with remaining data
-                            std::thread::sleep(std::time::Duration::from_millis(10));
-                            // For simplicity, we'll consider this a successful partial write
-                            // In a more sophisticated implementation, we'd handle the remaining bytes
-                        }
-                        break;
-                    },
-                    Err(e) if e.to_string().contains("draining incoming flow") && retries < MAX_RETRIES => {
-                        log::debug!("Flow control during chunked write, retry {}/{}", retries + 1, MAX_RETRIES);
-                        retries += 1;
-                        std::thread::sleep(std::time::Duration::from_millis((100 * retries) as u64));
-                    },
-                    Err(e) => {
-                        return Err(anyhow::anyhow!("Failed to write chunk: {}", e));
-                    }
-                }
-            }
-            
-            // Small delay between chunks to prevent overwhelming the channel
-            std::thread::sleep(std::time::Duration::from_millis(5));
+}
+
+/// A remote forward's listener; polled on the non-blocking session of the shell
+pub struct RealForwardListener {
+    listener: Listener,
+}
+
+impl ForwardListener for RealForwardListener {
+    fn accept(&mut self) -> Result<Option<Box<dyn ShellSession>>> {
+        match self.listener.accept() {
+            // No PTY and no stderr on a forwarded connection
+            Ok(channel) => Ok(Some(Box::new(RealShellSession::new(channel, None, false)))),
+            Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => Ok(None),
+            Err(e) => Err(e).context("Failed to accept a forwarded connection"),
         }
-        
-        Ok(total_written)
     }
 }
 
@@ -185,6 +835,10 @@ impl ShellSession for RealShellSession {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         // Check for terminal size changes before reading
         self.check_terminal_resize();
+
+        if self.forward_stderr {
+            self.drain_stderr();
+        }
         
         // Try to read data, handle various error conditions gracefully
         match self.channel.read(buf) {
@@ -194,11 +848,6 @@ impl ShellSession for RealShellSession {
                 log::debug!("SSH channel EOF during read");
                 Ok(0)
             },
-            Err(e) if e.to_string().contains("draining incoming flow") => {
-                log::debug!("SSH channel flow control issue, waiting...");
-                std::thread::sleep(std::time::Duration::from_millis(50));
-                Ok(0) // Return 0 bytes read, don't error
-            },
             Err(e) => {
                 log::debug!("SSH read error: {}", e);
                 Err(anyhow::anyhow!("Failed to read from shell: {}", e))
@@ -207,35 +856,77 @@ impl ShellSession for RealShellSession {
     }
 
     fn write(&mut self, data: &[u8]) -> Result<usize> {
+        match self.try_write(data)? {
+            WriteOutcome::Written(n) => Ok(n),
+            WriteOutcome::Blocked => Ok(0),
+        }
+    }
+
+    fn try_write(&mut self, data: &[u8]) -> Result<WriteOutcome> {
         use std::io::Write;
-        
-        // Handle large writes by chunking them
-        if data.len() > 1024 {
-            return self.write_chunked(data);
+
+        if data.is_empty() {
+            return Ok(WriteOutcome::Written(0));
         }
-        
-        match self.channel.write(data) {
-            Ok(n) => {
-                // Don't flush immediately for small writes to improve performance
-                // The SSH library will handle batching
-                Ok(n)
-            },
-            Err(e) if e.to_string().contains("draining incoming flow") => {
-                log::debug!("SSH channel flow control during write, retrying...");
-                std::thread::sleep(std::time::Duration::from_millis(50));
-                // Retry the write
-                match self.channel.write(data) {
-                    Ok(n) => Ok(n),
-                    Err(e2) => Err(anyhow::anyhow!("Failed to write to shell after retry: {}", e2))
-                }
-            },
-            Err(e) => Err(anyhow::anyhow!("Failed to write to shell: {}", e))
+        // Offer no more than the peer's window so libssh2 never has to queue
+        // the excess. With the window closed the write still goes through:
+        // libssh2 reads pending packets first, which is how a window adjust
+        // from the server gets noticed.
+        let window = self.channel.write_window().remaining as usize;
+        let len = if window == 0 { data.len() } else { data.len().min(window) };
+        match self.channel.write(&data[..len]) {
+            Ok(0) => Ok(WriteOutcome::Blocked),
+            Ok(n) => Ok(WriteOutcome::Written(n)),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(WriteOutcome::Blocked),
+            Err(e) => Err(anyhow::anyhow!("Failed to write to shell: {}", e)),
         }
     }
 
+    fn send_eof(&mut self) -> Result<()> {
+        WOULD_BLOCK
+            .run(None, || Ok(self.channel.send_eof()?))
+            .map_err(|e| anyhow::anyhow!("Failed to send EOF: {}", e))
+    }
+
     fn is_eof(&self) -> bool {
         self.channel.eof()
     }
+
+    fn wait_close(&mut self) -> Result<i32> {
+        WOULD_BLOCK
+            .run(None, || {
+                if self.forward_stderr {
+                    self.drain_stderr();
+                }
+                Ok(self.channel.wait_close()?)
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to close channel: {}", e))?;
+
+        self.channel.exit_status().context("Failed to get exit status")
+    }
+
+    fn exit_signal(&self) -> Option<ExitSignal> {
+        exit_signal(&self.channel)
+    }
+
+    fn send_signal(&mut self, signal: Signal) -> Result<()> {
+        // ssh2 has no signal API, but a "signal" request has the same shape as
+        // "exec": the request name followed by one string
+        WOULD_BLOCK
+            .run(None, || Ok(self.channel.process_startup("signal", Some(signal.name()))?))
+            .map_err(|e| anyhow::anyhow!("Server refused SIG{}: {}", signal.name(), e))
+    }
+}
+
+/// The channel's `exit-signal`, if the server sent one
+fn exit_signal(channel: &Channel) -> Option<ExitSignal> {
+    let signal = channel.exit_signal().ok()?;
+    Some(ExitSignal {
+        name: signal.exit_signal?,
+        // libssh2 parses but does not expose the core-dumped flag
+        core_dumped: false,
+        message: signal.error_message,
+    })
 }
 
 #[cfg(test)]
@@ -248,6 +939,69 @@ mod tests {
         assert!(!connection.is_authenticated());
     }
 
+    /// A server that accepts connections and never answers
+    fn silent_server() -> (std::net::TcpListener, u16) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
+    }
+
+    #[test]
+    fn test_cancel_interrupts_handshake() {
+        let (_listener, port) = silent_server();
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            canceller.cancel();
+        });
+
+        let mut client = crate::ssh_client::SshClient::new(Box::new(RealSshConnection::new()));
+        client.set_cancel_token(token);
+        let started = Instant::now();
+        let err = client.connect("127.0.0.1", port).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<crate::cancel::Cancelled>(),
+            Some(&crate::cancel::Cancelled { timed_out: false })
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_deadline_times_out_handshake() {
+        let (_listener, port) = silent_server();
+        let mut client = crate::ssh_client::SshClient::new(Box::new(RealSshConnection::new()));
+        client.set_cancel_token(CancelToken::with_timeout(Duration::from_millis(300)));
+        let started = Instant::now();
+        let err = client.connect("127.0.0.1", port).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<crate::cancel::Cancelled>(),
+            Some(&crate::cancel::Cancelled { timed_out: true })
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_auth_error_explains_server_disconnect() {
+        let hung_up = ssh2::Error::new(ErrorCode::Session(LIBSSH2_ERROR_SOCKET_DISCONNECT), "socket disconnect");
+        let message = auth_error(hung_up, "SSH key authentication failed").to_string();
+        assert!(message.contains("too many authentication failures"), "{}", message);
+
+        let denied = ssh2::Error::new(ErrorCode::Session(-18), "Authentication failed");
+        assert_eq!(auth_error(denied, "SSH key authentication failed").to_string(), "SSH key authentication failed");
+    }
+
+    #[test]
+    fn test_request_error_names_refused_request() {
+        let code = ErrorCode::Session(LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED);
+        let denied = ssh2::Error::new(code, "Unable to complete request");
+        let error = request_error(denied, "pty-req");
+        assert_eq!(error.downcast_ref::<RequestRefused>(), Some(&RequestRefused { request: "pty-req" }));
+
+        let other = ssh2::Error::new(ErrorCode::Session(LIBSSH2_ERROR_SOCKET_DISCONNECT), "socket disconnect");
+        assert!(request_error(other, "shell").downcast_ref::<RequestRefused>().is_none());
+    }
+
     #[test]
     fn test_connect_to_invalid_host() {
         let mut connection = RealSshConnection::new();
@@ -255,6 +1009,13 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_auth_banner_without_connection() {
+        let mut connection = RealSshConnection::new();
+        let result = connection.auth_banner("user");
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_authenticate_without_connection() {
         let mut connection = RealSshConnection::new();
@@ -278,4 +1039,34 @@ mod tests {
         assert!(result.is_err());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_open_exec_without_connection() {
+        let connection = RealSshConnection::new();
+        let result = connection.open_exec("git-upload-pack 'repo.git'");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Not connected"));
+    }
+
+    #[test]
+    fn test_open_exec_pty_without_connection() {
+        let connection = RealSshConnection::new();
+        let result = connection.open_exec_pty("top");
+        assert!(result.unwrap_err().to_string().contains("Not connected"));
+    }
+
+    #[test]
+    fn test_open_sftp_without_connection() {
+        let conn = RealSshConnection::new();
+        let result = conn.open_sftp();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_open_direct_tcpip_without_connection() {
+        let connection = RealSshConnection::new();
+        let result = connection.open_direct_tcpip("localhost", 22);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Not connected"));
+    }
 }
\ No newline at end of file
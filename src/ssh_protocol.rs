@@ -3,23 +3,29 @@
 //! This module implements SSH-2.0 protocol components using WASM-compatible cryptographic libraries.
 //! It handles key exchange, encryption, and authentication using the Direct Socket API for network I/O.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use wasm_bindgen::prelude::*;
 use js_sys::Uint8Array;
 use rand::RngCore;
+use crate::rekey::{RekeyPolicy, RekeyTracker};
+use crate::cipher::{self, PacketCipher};
+use crate::config;
+use crate::packet::PacketCodec;
+use crate::dh::{DhGroup, DhKeyPair};
+use crate::host_key::{self, HostKey};
+use crate::ssh_wire::{
+    self, Disconnect, ExchangeHashInput, ExtInfo, GlobalRequest, KexHash, KexInit, NegotiatedAlgorithms,
+    PasswordReply, PasswordRequest, Reader, DISCONNECT_BY_APPLICATION, DISCONNECT_KEY_EXCHANGE_FAILED,
+    HOSTKEYS_REQUEST, MAX_VERSION_LINE, SSH_MSG_DEBUG, SSH_MSG_DISCONNECT, SSH_MSG_EXT_INFO, SSH_MSG_GLOBAL_REQUEST,
+    SSH_MSG_IGNORE, SSH_MSG_KEXINIT, SSH_MSG_NEWKEYS, SSH_MSG_SERVICE_ACCEPT, USERAUTH_SERVICE,
+};
 
 #[cfg(target_arch = "wasm32")]
-use {
-    x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey},
-    sha2::{Sha256, Digest},
-};
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey};
 
 // External JavaScript functions for Direct Socket API
 #[wasm_bindgen]
 extern "C" {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
-    
     #[wasm_bindgen(js_name = js_tcp_send, catch)]
     async fn js_tcp_send(data: &[u8]) -> Result<JsValue, JsValue>;
     
@@ -27,28 +33,47 @@ extern "C" {
     async fn js_tcp_receive(max_len: usize) -> Result<JsValue, JsValue>;
 }
 
-// Macro for logging from WASM
-macro_rules! console_log {
-    ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
-}
-
 /// SSH Protocol Constants
-const SSH_MSG_KEXINIT: u8 = 20;
 const SSH_MSG_KEXDH_INIT: u8 = 30;
 const SSH_MSG_KEXDH_REPLY: u8 = 31;
-const SSH_MSG_NEWKEYS: u8 = 21;
-const SSH_MSG_SERVICE_REQUEST: u8 = 5;
-const SSH_MSG_SERVICE_ACCEPT: u8 = 6;
+
+/// Bytes requested from the socket per read while a packet is incomplete
+const RECEIVE_CHUNK: usize = 16384;
+
+/// Our half of a key exchange in progress
+enum Ephemeral {
+    X25519(EphemeralSecret),
+    Dh(DhKeyPair),
+}
 
 /// SSH Key Exchange Implementation
 #[wasm_bindgen]
 pub struct SshKeyExchange {
-    client_random: Vec<u8>,
-    server_random: Vec<u8>,
+    /// Identification strings, covered by the exchange hash
+    client_version: String,
+    server_version: String,
+    /// KEXINIT payloads of the current exchange, covered by the exchange hash
+    client_kex_init_payload: Vec<u8>,
+    server_kex_init_payload: Vec<u8>,
+    negotiated: Option<NegotiatedAlgorithms>,
+    /// Host key blob from the last exchange, verified against its signature
+    host_key: Vec<u8>,
     shared_secret: Vec<u8>,
+    exchange_hash: Vec<u8>,
+    /// Exchange hash of the first key exchange; unchanged by rekeying (RFC 4253 section 7.2)
     session_id: Vec<u8>,
-    encryption_key: Vec<u8>,
-    mac_key: Vec<u8>,
+    /// Framing, packet protection and sequence numbers for every message
+    codec: PacketCodec,
+    rekey: RekeyTracker,
+    client_kex_init: Option<KexInit>,
+    /// `server-sig-algs` from the server's EXT_INFO, if it sent one
+    server_sig_algs: Option<Vec<String>>,
+    /// Host keys from the server's latest `hostkeys-00@openssh.com` announcement
+    announced_host_keys: Vec<Vec<u8>>,
+    /// The server accepted our `ssh-userauth` service request
+    userauth_started: bool,
+    /// Why the server wants a new password, from its last PASSWD_CHANGEREQ
+    password_change_prompt: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -56,137 +81,355 @@ impl SshKeyExchange {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
         Self {
-            client_random: Vec::new(),
-            server_random: Vec::new(),
+            client_version: config::CLIENT_VERSION.to_string(),
+            server_version: String::new(),
+            client_kex_init_payload: Vec::new(),
+            server_kex_init_payload: Vec::new(),
+            negotiated: None,
+            host_key: Vec::new(),
             shared_secret: Vec::new(),
+            exchange_hash: Vec::new(),
             session_id: Vec::new(),
-            encryption_key: Vec::new(),
-            mac_key: Vec::new(),
+            codec: PacketCodec::new(),
+            rekey: RekeyTracker::new(RekeyPolicy::default(), js_sys::Date::now()),
+            client_kex_init: None,
+            server_sig_algs: None,
+            announced_host_keys: Vec::new(),
+            userauth_started: false,
+            password_change_prompt: None,
         }
     }
 
-    /// Perform complete SSH key exchange using Curve25519
+    /// Identification string to send instead of `SSH-2.0-bxssh_<version>`, for
+    /// compatibility testing; call before `exchange_versions`
     #[wasm_bindgen]
-    pub async fn perform_key_exchange(&mut self) -> Result<bool, JsValue> {
-        console_log!("[SSH Protocol] Starting Curve25519 key exchange in WASM");
-        
-        // Step 1: Send SSH_MSG_KEXINIT
-        match self.send_kex_init().await {
-            Ok(_) => console_log!("[SSH Protocol] KEXINIT sent successfully"),
-            Err(e) => {
-                console_log!("[SSH Protocol] Failed to send KEXINIT: {:?}", e);
-                return Err(JsValue::from_str(&format!("KEXINIT failed: {:?}", e)));
+    pub fn set_client_version(&mut self, version: &str) -> Result<(), JsValue> {
+        config::validate_client_version(version).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.client_version = version.to_string();
+        Ok(())
+    }
+
+    /// The server's identification line (`SSH-2.0-...`), when the page exchanged
+    /// versions itself instead of calling `exchange_versions`
+    #[wasm_bindgen]
+    pub fn set_server_version(&mut self, version: &str) {
+        self.server_version = version.trim_end_matches(['\r', '\n']).to_string();
+    }
+
+    /// Send our identification string and read the server's, skipping any lines
+    /// it sends first (RFC 4253 section 4.2). Returns the server's version.
+    #[wasm_bindgen]
+    pub async fn exchange_versions(&mut self) -> Result<String, JsValue> {
+        js_tcp_send(format!("{}\r\n", self.client_version).as_bytes()).await?;
+
+        let mut data = Vec::new();
+        loop {
+            let chunk = self.receive(MAX_VERSION_LINE).await?;
+            if chunk.is_empty() {
+                return Err(JsValue::from_str("Connection closed before the server identified itself"));
+            }
+            data.extend_from_slice(&chunk);
+            let parsed = ssh_wire::parse_server_version(&data).map_err(|e| JsValue::from_str(&e.to_string()))?;
+            if let Some((version, used)) = parsed {
+                log::info!("[SSH Protocol] Server version: {}", version);
+                self.codec.feed(&data[used..]);
+                self.server_version = version.clone();
+                return Ok(version);
             }
         }
-        
-        // Step 2: Receive server's KEXINIT
-        match self.receive_server_kex_init().await {
-            Ok(_) => console_log!("[SSH Protocol] Server KEXINIT received"),
-            Err(e) => {
-                console_log!("[SSH Protocol] Failed to receive server KEXINIT: {:?}", e);
-                return Err(JsValue::from_str(&format!("Server KEXINIT failed: {:?}", e)));
+    }
+
+    /// `SHA256:` fingerprint of the server's verified host key, once key exchange has run
+    #[wasm_bindgen]
+    pub fn host_key_fingerprint(&self) -> Option<String> {
+        (!self.host_key.is_empty()).then(|| host_key::fingerprint(&self.host_key))
+    }
+
+    /// Look the verified host key up in the text of a known_hosts file: "known",
+    /// "changed" when the host is listed with another key of its type, or "unknown"
+    #[wasm_bindgen]
+    pub fn check_known_hosts(&self, known_hosts: &str, host: &str, port: u16) -> Result<String, JsValue> {
+        host_key::check_known_hosts(known_hosts, host, port, &self.host_key)
+            .map(|status| status.to_string())
+            .map_err(|e| JsValue::from_str(&format!("No verified host key: {}", e)))
+    }
+
+    /// The known_hosts line recording the verified host key for `host` on `port`,
+    /// the name hashed as `ssh-keygen -H` does when `hashed`
+    #[wasm_bindgen]
+    pub fn known_hosts_line(&self, host: &str, port: u16, hashed: bool) -> Result<String, JsValue> {
+        let mut salt = [0u8; 20];
+        rand::thread_rng().fill_bytes(&mut salt);
+        host_key::known_hosts_line(host, port, &self.host_key, hashed.then_some(&salt[..]))
+            .map_err(|e| JsValue::from_str(&format!("No verified host key: {}", e)))
+    }
+
+    /// Whether strict key exchange (the Terrapin countermeasure) is in effect
+    #[wasm_bindgen]
+    pub fn strict_kex(&self) -> bool {
+        self.codec.is_strict()
+    }
+
+    /// Record the server's SSH_MSG_EXT_INFO payload, sent after the first NEWKEYS
+    #[wasm_bindgen]
+    pub fn process_ext_info(&mut self, payload: &[u8]) -> Result<(), JsValue> {
+        let ext_info = ExtInfo::parse(payload).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.server_sig_algs = ext_info.server_sig_algs();
+        log::debug!("[SSH Protocol] Server signature algorithms: {:?}", self.server_sig_algs);
+        Ok(())
+    }
+
+    /// Host keys the server announced with `hostkeys-00@openssh.com`, as
+    /// `<type> <base64>` known_hosts keys, so a rotated key can be recorded
+    /// before the old one is retired. Unsupported key types are left out.
+    #[wasm_bindgen]
+    pub fn announced_host_keys(&self) -> Vec<String> {
+        self.announced_host_keys.iter().filter_map(|blob| host_key::known_hosts_key(blob).ok()).collect()
+    }
+
+    /// Signature algorithm to use for an RSA user key: rsa-sha2-512 or -256 when
+    /// the server lists them in `server-sig-algs`, ssh-rsa when it sent no list
+    #[wasm_bindgen]
+    pub fn rsa_signature_algorithm(&self) -> Result<String, JsValue> {
+        ssh_wire::rsa_signature_algorithm(self.server_sig_algs.as_deref())
+            .map(str::to_string)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// When to re-exchange keys, in OpenSSH `RekeyLimit` syntax (`"1G 1h"`, `"500M none"`)
+    #[wasm_bindgen]
+    pub fn set_rekey_limit(&mut self, spec: &str) -> Result<(), JsValue> {
+        let policy = RekeyPolicy::parse(spec).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.rekey.set_policy(policy);
+        Ok(())
+    }
+
+    /// Whether traffic or time since the last exchange has reached the rekey limit
+    #[wasm_bindgen]
+    pub fn needs_rekey(&self) -> bool {
+        self.rekey.due(js_sys::Date::now()).is_some()
+    }
+
+    /// Run a new key exchange if the rekey limit has been reached; call before
+    /// sending more data. Returns whether keys were changed.
+    #[wasm_bindgen]
+    pub async fn rekey_if_needed(&mut self) -> Result<bool, JsValue> {
+        match self.rekey.due(js_sys::Date::now()) {
+            Some(reason) => {
+                log::info!("[SSH Protocol] Rekeying ({:?} limit reached)", reason);
+                self.perform_key_exchange().await
             }
+            None => Ok(false),
         }
+    }
+
+    /// Perform complete SSH key exchange: Curve25519, or Diffie-Hellman group 16/14
+    /// for servers without it
+    #[wasm_bindgen]
+    pub async fn perform_key_exchange(&mut self) -> Result<bool, JsValue> {
+        log::debug!("[SSH Protocol] Starting key exchange in WASM");
         
-        // Step 3: Perform Curve25519 key exchange
-        match self.perform_curve25519_exchange().await {
-            Ok(_) => console_log!("[SSH Protocol] Curve25519 exchange completed"),
-            Err(e) => {
-                console_log!("[SSH Protocol] Curve25519 exchange failed: {:?}", e);
-                return Err(JsValue::from_str(&format!("Curve25519 exchange failed: {:?}", e)));
+        if let Err(e) = self.run_key_exchange().await {
+            log::warn!("[SSH Protocol] Key exchange failed: {:#}", e);
+            // Tell the server why, unless it was the server that hung up
+            if e.downcast_ref::<Disconnect>().is_none() {
+                self.send_disconnect(DISCONNECT_KEY_EXCHANGE_FAILED, "Key exchange failed").await;
             }
+            return Err(JsValue::from_str(&format!("Key exchange failed: {:#}", e)));
+        }
+        
+        self.rekey.reset(js_sys::Date::now());
+        log::info!("[SSH Protocol] ✅ Key exchange completed successfully in WASM");
+        Ok(true)
+    }
+
+    /// Close the connection politely: send SSH_MSG_DISCONNECT (by application)
+    /// with `description` for the server's logs
+    #[wasm_bindgen]
+    pub async fn disconnect(&mut self, description: &str) {
+        self.send_disconnect(DISCONNECT_BY_APPLICATION, description).await;
+    }
+
+    /// Log in with a password (RFC 4252 section 8) after the key exchange. Resolves
+    /// to "success", "failure", or "change-password" when the password has expired:
+    /// ask for a new one twice, then call `change_password`. The server's reason is
+    /// in `password_change_prompt`.
+    #[wasm_bindgen]
+    pub async fn authenticate_password(&mut self, username: &str, password: &str) -> Result<String, JsValue> {
+        let request = PasswordRequest { username, password, new_password: None };
+        self.password_auth(request)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Password authentication failed: {:#}", e)))
+    }
+
+    /// Answer a "change-password" result: log in with `old_password`, replacing it
+    /// with `new_password`. Resolves like `authenticate_password`; "change-password"
+    /// again means the server refused the new password.
+    #[wasm_bindgen]
+    pub async fn change_password(
+        &mut self,
+        username: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<String, JsValue> {
+        if new_password.is_empty() {
+            return Err(JsValue::from_str("New password cannot be empty"));
         }
+        let request = PasswordRequest { username, password: old_password, new_password: Some(new_password) };
+        self.password_auth(request)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Password change failed: {:#}", e)))
+    }
+
+    /// Log in with key `key_name` held by `agent` (RFC 4252 section 7) after the key
+    /// exchange; the agent signs the request for this session. Resolves to "success"
+    /// or "failure".
+    #[wasm_bindgen]
+    pub async fn authenticate_with_agent(
+        &mut self,
+        username: &str,
+        agent: &crate::wasm_exports::JsSshAgent,
+        key_name: &str,
+    ) -> Result<String, JsValue> {
+        let request = agent
+            .with_agent(|agent| agent.userauth_request(key_name, &self.session_id, username))
+            .map_err(|e| JsValue::from_str(&format!("Agent authentication failed: {:#}", e)))?;
+        self.publickey_auth(&request)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Agent authentication failed: {:#}", e)))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn password_change_prompt(&self) -> Option<String> {
+        self.password_change_prompt.clone()
+    }
+}
+
+impl SshKeyExchange {
+    async fn run_key_exchange(&mut self) -> Result<()> {
+        // Step 1: Send SSH_MSG_KEXINIT
+        self.send_kex_init().await.context("KEXINIT failed")?;
+        log::debug!("[SSH Protocol] KEXINIT sent successfully");
+        
+        // Step 2: Receive server's KEXINIT
+        self.receive_server_kex_init().await.context("Server KEXINIT failed")?;
+        log::debug!("[SSH Protocol] Server KEXINIT received");
+        
+        // Step 3: Perform the negotiated key exchange
+        self.perform_dh_exchange().await?;
+        log::debug!("[SSH Protocol] Key exchange method completed");
         
         // Step 4: Derive session keys
-        self.derive_session_keys();
-        console_log!("[SSH Protocol] Session keys derived");
+        self.derive_session_keys().context("Key derivation failed")?;
+        log::debug!("[SSH Protocol] Session keys derived");
         
         // Step 5: Send SSH_MSG_NEWKEYS
-        match self.send_new_keys().await {
-            Ok(_) => console_log!("[SSH Protocol] NEWKEYS sent"),
-            Err(e) => {
-                console_log!("[SSH Protocol] Failed to send NEWKEYS: {:?}", e);
-                return Err(JsValue::from_str(&format!("NEWKEYS failed: {:?}", e)));
+        self.send_new_keys().await.context("NEWKEYS failed")?;
+        log::debug!("[SSH Protocol] NEWKEYS sent");
+
+        // Step 6: Receive the server's SSH_MSG_NEWKEYS, so the next reply is the first under the new keys
+        ssh_wire::expect_new_keys(&self.receive_reply().await?).context("Server NEWKEYS failed")?;
+        log::debug!("[SSH Protocol] NEWKEYS received");
+        Ok(())
+    }
+
+    /// Send a password request, starting the `ssh-userauth` service first if needed,
+    /// and return the outcome for `authenticate_password`
+    async fn password_auth(&mut self, request: PasswordRequest<'_>) -> Result<String> {
+        self.start_userauth().await?;
+        self.send_packet(&request.encode()).await
+            .map_err(|e| anyhow::anyhow!("Failed to send password: {:?}", e))?;
+        loop {
+            match PasswordReply::parse(&self.receive_reply().await?)? {
+                PasswordReply::Banner(text) => log::info!("[SSH Protocol] Server banner: {}", text),
+                PasswordReply::Success => {
+                    self.password_change_prompt = None;
+                    return Ok("success".to_string());
+                }
+                PasswordReply::Failure { methods, .. } => {
+                    log::debug!("[SSH Protocol] Password rejected; server offers {}", methods.join(","));
+                    return Ok("failure".to_string());
+                }
+                PasswordReply::ChangeRequest { prompt } => {
+                    log::info!("[SSH Protocol] Server requires a new password");
+                    self.password_change_prompt = Some(prompt);
+                    return Ok("change-password".to_string());
+                }
             }
         }
-        
-        console_log!("[SSH Protocol] ✅ Key exchange completed successfully in WASM");
-        Ok(true)
+    }
+
+    /// Send a signed publickey request and return the outcome for `authenticate_with_agent`
+    async fn publickey_auth(&mut self, request: &[u8]) -> Result<String> {
+        if self.session_id.is_empty() {
+            return Err(anyhow::anyhow!("Key exchange has not run"));
+        }
+        self.start_userauth().await?;
+        self.send_packet(request).await
+            .map_err(|e| anyhow::anyhow!("Failed to send publickey request: {:?}", e))?;
+        loop {
+            match PasswordReply::parse(&self.receive_reply().await?)? {
+                PasswordReply::Banner(text) => log::info!("[SSH Protocol] Server banner: {}", text),
+                PasswordReply::Success => return Ok("success".to_string()),
+                PasswordReply::Failure { methods, .. } => {
+                    log::debug!("[SSH Protocol] Key rejected; server offers {}", methods.join(","));
+                    return Ok("failure".to_string());
+                }
+                PasswordReply::ChangeRequest { .. } => {
+                    return Err(anyhow::anyhow!("Unexpected reply to publickey authentication"));
+                }
+            }
+        }
+    }
+
+    /// Start the `ssh-userauth` service, unless an earlier attempt did
+    async fn start_userauth(&mut self) -> Result<()> {
+        if !self.userauth_started {
+            self.send_packet(&ssh_wire::service_request(USERAUTH_SERVICE)).await
+                .map_err(|e| anyhow::anyhow!("Failed to request user authentication: {:?}", e))?;
+            if self.receive_reply().await?.first() != Some(&SSH_MSG_SERVICE_ACCEPT) {
+                return Err(anyhow::anyhow!("Server refused the ssh-userauth service"));
+            }
+            self.userauth_started = true;
+        }
+        Ok(())
+    }
+
+    /// The next packet that isn't IGNORE, DEBUG or EXT_INFO
+    async fn receive_reply(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let payload = self.receive_packet().await?;
+            if !matches!(payload.first(), Some(&SSH_MSG_IGNORE | &SSH_MSG_DEBUG | &SSH_MSG_EXT_INFO)) {
+                return Ok(payload);
+            }
+        }
+    }
+
+    /// Best-effort SSH_MSG_DISCONNECT; the connection is going away either way
+    async fn send_disconnect(&mut self, reason: u32, description: &str) {
+        let message = Disconnect::new(reason, description);
+        if let Err(e) = self.send_packet(&message.encode()).await {
+            log::debug!("[SSH Protocol] Could not send DISCONNECT: {:?}", e);
+        }
     }
 
     /// Send SSH_MSG_KEXINIT packet
     async fn send_kex_init(&mut self) -> Result<()> {
-        console_log!("[SSH Protocol] Creating KEXINIT packet with WASM crypto");
+        log::debug!("[SSH Protocol] Creating KEXINIT packet with WASM crypto");
         
         // Generate 16 random bytes for this exchange
         let mut rng = rand::thread_rng();
         let mut random_bytes = [0u8; 16];
         rng.fill_bytes(&mut random_bytes);
-        self.client_random = random_bytes.to_vec();
-        
-        // Build KEXINIT packet
-        let mut packet = Vec::new();
-        
-        // Message type
-        packet.push(SSH_MSG_KEXINIT);
-        
-        // Random bytes
-        packet.extend_from_slice(&random_bytes);
-        
-        // Key exchange algorithms (Curve25519 preferred)
-        let kex_algs = b"curve25519-sha256,curve25519-sha256@libssh.org,diffie-hellman-group14-sha256";
-        packet.extend_from_slice(&(kex_algs.len() as u32).to_be_bytes());
-        packet.extend_from_slice(kex_algs);
-        
-        // Host key algorithms
-        let host_key_algs = b"ssh-ed25519,ssh-rsa,ecdsa-sha2-nistp256";
-        packet.extend_from_slice(&(host_key_algs.len() as u32).to_be_bytes());
-        packet.extend_from_slice(host_key_algs);
-        
-        // Encryption algorithms (client to server)
-        let enc_c2s = b"aes256-ctr,aes192-ctr,aes128-ctr";
-        packet.extend_from_slice(&(enc_c2s.len() as u32).to_be_bytes());
-        packet.extend_from_slice(enc_c2s);
-        
-        // Encryption algorithms (server to client)
-        packet.extend_from_slice(&(enc_c2s.len() as u32).to_be_bytes());
-        packet.extend_from_slice(enc_c2s);
-        
-        // MAC algorithms (client to server)
-        let mac_algs = b"hmac-sha2-256,hmac-sha2-512,hmac-sha1";
-        packet.extend_from_slice(&(mac_algs.len() as u32).to_be_bytes());
-        packet.extend_from_slice(mac_algs);
-        
-        // MAC algorithms (server to client) 
-        packet.extend_from_slice(&(mac_algs.len() as u32).to_be_bytes());
-        packet.extend_from_slice(mac_algs);
-        
-        // Compression algorithms (both directions)
-        let comp_algs = b"none";
-        packet.extend_from_slice(&(comp_algs.len() as u32).to_be_bytes());
-        packet.extend_from_slice(comp_algs);
-        packet.extend_from_slice(&(comp_algs.len() as u32).to_be_bytes());
-        packet.extend_from_slice(comp_algs);
-        
-        // Languages (both directions) - empty
-        packet.extend_from_slice(&[0u8; 8]); // Two empty name-lists
-        
-        // First KEX packet follows + reserved
-        packet.push(0); // boolean false
-        packet.extend_from_slice(&[0u8; 4]); // reserved
-        
-        // Add SSH packet framing
-        let mut framed_packet = Vec::new();
-        let payload_len = packet.len() + 1; // +1 for padding length
-        let padding_len = 4u8; // Minimum padding
-        
-        framed_packet.extend_from_slice(&(payload_len + padding_len as usize).to_be_bytes());
-        framed_packet.push(padding_len);
-        framed_packet.extend_from_slice(&packet);
-        framed_packet.extend_from_slice(&vec![0u8; padding_len as usize]);
-        
+
+        // ext-info-c and kex-strict-c are only offered in the first exchange
+        let kex_init = KexInit::client(random_bytes, self.session_id.is_empty());
+        self.client_kex_init_payload = kex_init.encode();
+        self.client_kex_init = Some(kex_init);
+
         // Send via Direct Socket API
-        js_tcp_send(&framed_packet).await
+        let payload = self.client_kex_init_payload.clone();
+        self.send_packet(&payload).await
             .map_err(|e| anyhow::anyhow!("Failed to send KEXINIT: {:?}", e))?;
         
         Ok(())
@@ -194,113 +437,232 @@ impl SshKeyExchange {
 
     /// Receive server's SSH_MSG_KEXINIT
     async fn receive_server_kex_init(&mut self) -> Result<()> {
-        console_log!("[SSH Protocol] Receiving server KEXINIT");
-        
-        let js_data = js_tcp_receive(2048).await
-            .map_err(|e| anyhow::anyhow!("Failed to receive server KEXINIT: {:?}", e))?;
+        log::debug!("[SSH Protocol] Receiving server KEXINIT");
         
-        let data = Uint8Array::new(&js_data).to_vec();
-        console_log!("[SSH Protocol] Received {} bytes from server", data.len());
-        
-        // Parse the packet (simplified - just extract random bytes for now)
-        if data.len() >= 21 && data[5] == SSH_MSG_KEXINIT {
-            self.server_random = data[6..22].to_vec();
-            console_log!("[SSH Protocol] Extracted server random bytes");
+        // The codec validates framing and every length field before we trust the contents
+        let payload = self.receive_packet().await?;
+        if payload.first() != Some(&SSH_MSG_KEXINIT) {
+            return Err(anyhow::anyhow!("Expected KEXINIT, got message type {:?}", payload.first()));
         }
+        let server_kex_init = KexInit::parse(&payload)?;
+        self.server_kex_init_payload = payload;
+        log::debug!("[SSH Protocol] Server offers key exchange: {:?}", server_kex_init.kex_algorithms);
+
+        let client_kex_init = self.client_kex_init.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("KEXINIT has not been sent"))?;
+        let negotiated = client_kex_init.negotiate(&server_kex_init)?;
+        self.codec.negotiated(&negotiated)?;
+        log::debug!("[SSH Protocol] Negotiated {} (strict key exchange: {})", negotiated.kex, negotiated.strict_kex);
+        self.negotiated = Some(negotiated);
         
         Ok(())
     }
 
-    /// Perform Curve25519 key exchange
-    async fn perform_curve25519_exchange(&mut self) -> Result<()> {
-        console_log!("[SSH Protocol] Performing Curve25519 key exchange");
-        
-        // Generate our ephemeral key pair
-        let our_secret = EphemeralSecret::random_from_rng(&mut rand::thread_rng());
-        let our_public = X25519PublicKey::from(&our_secret);
-        
-        // Send SSH_MSG_KEX_ECDH_INIT with our public key
-        let mut init_packet = Vec::new();
-        
-        // Packet framing
-        let payload_len = 1 + 4 + 32 + 1; // msg_type + key_len + key + padding_len
-        let padding_len = 3u8;
-        
-        init_packet.extend_from_slice(&(payload_len + padding_len as usize).to_be_bytes());
-        init_packet.push(padding_len);
-        init_packet.push(SSH_MSG_KEXDH_INIT);
-        init_packet.extend_from_slice(&32u32.to_be_bytes()); // Key length
-        init_packet.extend_from_slice(our_public.as_bytes());
-        init_packet.extend_from_slice(&vec![0u8; padding_len as usize]);
+    /// Run the negotiated exchange: Curve25519 (RFC 8731) or finite-field
+    /// Diffie-Hellman (RFC 4253 section 8), then verify the server's host key signature
+    async fn perform_dh_exchange(&mut self) -> Result<()> {
+        let negotiated = self.negotiated.clone()
+            .ok_or_else(|| anyhow::anyhow!("Key exchange has not been negotiated"))?;
+        log::debug!("[SSH Protocol] Performing {} key exchange", negotiated.kex);
+        
+        // Generate our ephemeral key pair; the server's KEXINIT decided which kind
+        let ephemeral = if negotiated.kex.starts_with("curve25519-sha256") {
+            Ephemeral::X25519(EphemeralSecret::random_from_rng(&mut rand::thread_rng()))
+        } else if let Some(group) = DhGroup::for_kex(&negotiated.kex) {
+            Ephemeral::Dh(DhKeyPair::generate(group))
+        } else {
+            return Err(anyhow::anyhow!("Key exchange method '{}' is not supported", negotiated.kex));
+        };
+        
+        // Send SSH_MSG_KEX_ECDH_INIT (Q_C as a string) or SSH_MSG_KEXDH_INIT (e as an mpint)
+        let mut init_payload = vec![SSH_MSG_KEXDH_INIT];
+        let our_public = match &ephemeral {
+            Ephemeral::X25519(secret) => {
+                let public = X25519PublicKey::from(secret).as_bytes().to_vec();
+                ssh_wire::put_string(&mut init_payload, &public);
+                public
+            }
+            Ephemeral::Dh(pair) => {
+                ssh_wire::put_mpint(&mut init_payload, pair.public());
+                pair.public().to_vec()
+            }
+        };
         
-        console_log!("[SSH Protocol] Sending our Curve25519 public key");
-        js_tcp_send(&init_packet).await
+        log::debug!("[SSH Protocol] Sending our ephemeral public key");
+        self.send_packet(&init_payload).await
             .map_err(|e| anyhow::anyhow!("Failed to send KEXDH_INIT: {:?}", e))?;
         
-        // Receive server's response
-        console_log!("[SSH Protocol] Waiting for server's Curve25519 response");
-        let js_response = js_tcp_receive(2048).await
-            .map_err(|e| anyhow::anyhow!("Failed to receive KEXDH_REPLY: {:?}", e))?;
-        
-        let response_data = Uint8Array::new(&js_response).to_vec();
-        console_log!("[SSH Protocol] Received server key exchange response: {} bytes", response_data.len());
-        
-        // For now, we'll simulate the shared secret computation
-        // In a complete implementation, we would:
-        // 1. Parse server's public key from the response
-        // 2. Compute shared secret using our_secret.diffie_hellman(&server_public)
-        // 3. Extract and verify server's host key and signature
-        
-        // Simulate shared secret (32 random bytes for now)
-        let mut shared_secret = vec![0u8; 32];
-        rand::thread_rng().fill_bytes(&mut shared_secret);
-        self.shared_secret = shared_secret;
+        // Receive server's response, skipping IGNORE and DEBUG messages before it
+        log::debug!("[SSH Protocol] Waiting for server's key exchange reply");
+        let reply = loop {
+            let payload = self.receive_packet().await?;
+            match payload.first() {
+                Some(&SSH_MSG_KEXDH_REPLY) => break payload,
+                Some(&SSH_MSG_IGNORE | &SSH_MSG_DEBUG) => continue,
+                other => return Err(anyhow::anyhow!("Expected key exchange reply, got message type {:?}", other)),
+            }
+        };
+        
+        // string K_S, Q_S (string) or f (mpint), string signature of H
+        let mut reader = Reader::new(&reply[1..]);
+        let host_key_blob = reader.string()?.to_vec();
+        let server_public = reader.string()?.to_vec();
+        let signature = reader.string()?;
+        
+        self.shared_secret = match ephemeral {
+            Ephemeral::X25519(secret) => {
+                let server_public: [u8; 32] = server_public.as_slice().try_into()
+                    .map_err(|_| anyhow::anyhow!("Server Curve25519 key must be 32 bytes"))?;
+                let shared_secret = secret.diffie_hellman(&X25519PublicKey::from(server_public));
+                if !shared_secret.was_contributory() {
+                    return Err(anyhow::anyhow!("Server sent a low-order Curve25519 key"));
+                }
+                shared_secret.as_bytes().to_vec()
+            }
+            Ephemeral::Dh(pair) => {
+                if server_public.first().is_some_and(|b| b & 0x80 != 0) {
+                    return Err(anyhow::anyhow!("Server sent a negative Diffie-Hellman value"));
+                }
+                pair.shared_secret(&server_public)?
+            }
+        };
+        
+        self.exchange_hash = ssh_wire::exchange_hash(&negotiated.kex, &ExchangeHashInput {
+            client_version: &self.client_version,
+            server_version: &self.server_version,
+            client_kex_init: &self.client_kex_init_payload,
+            server_kex_init: &self.server_kex_init_payload,
+            host_key: &host_key_blob,
+            client_public: &our_public,
+            server_public: &server_public,
+            shared_secret: &self.shared_secret,
+        })?;
+        
+        let host_key = HostKey::parse(&host_key_blob)?;
+        host_key.verify(&negotiated.host_key, &self.exchange_hash, signature)?;
+        if !self.host_key.is_empty() && self.host_key != host_key_blob {
+            return Err(anyhow::anyhow!("Server host key changed during rekey"));
+        }
+        log::info!("[SSH Protocol] Verified {} host key {}", negotiated.host_key, host_key::fingerprint(&host_key_blob));
+        self.host_key = host_key_blob;
         
-        console_log!("[SSH Protocol] Shared secret computed");
         Ok(())
     }
 
-    /// Derive session keys from shared secret
-    fn derive_session_keys(&mut self) {
-        console_log!("[SSH Protocol] Deriving session keys using SHA-256");
-        
-        // Create session ID (hash of key exchange data)
-        let mut hasher = Sha256::new();
-        hasher.update(&self.client_random);
-        hasher.update(&self.server_random);
-        hasher.update(&self.shared_secret);
-        self.session_id = hasher.finalize().to_vec();
-        
-        // Derive encryption key (simplified)
-        let mut key_hasher = Sha256::new();
-        key_hasher.update(&self.shared_secret);
-        key_hasher.update(&self.session_id);
-        key_hasher.update(b"A"); // Key derivation identifier for encryption key
-        self.encryption_key = key_hasher.finalize().to_vec();
-        
-        // Derive MAC key
-        let mut mac_hasher = Sha256::new();
-        mac_hasher.update(&self.shared_secret);
-        mac_hasher.update(&self.session_id);
-        mac_hasher.update(b"E"); // Key derivation identifier for MAC key
-        self.mac_key = mac_hasher.finalize().to_vec();
+    /// Derive session keys from the shared secret and exchange hash; they take
+    /// effect in each direction at that direction's NEWKEYS
+    fn derive_session_keys(&mut self) -> Result<()> {
+        let negotiated = self.negotiated.clone()
+            .ok_or_else(|| anyhow::anyhow!("Key exchange has not been negotiated"))?;
+        let hash = KexHash::for_kex(&negotiated.kex)?;
+        log::debug!("[SSH Protocol] Deriving session keys using {:?}", hash);
+        
+        // The first exchange hash becomes the session ID
+        if self.session_id.is_empty() {
+            self.session_id = self.exchange_hash.clone();
+        }
         
-        console_log!("[SSH Protocol] Session keys derived successfully");
+        // Letters A-F: IVs, encryption keys and MAC keys, client to server first (RFC 4253 section 7.2)
+        let derive = |letter: u8, len: usize| {
+            hash.derive_key(&self.shared_secret, &self.exchange_hash, letter, &self.session_id, len)
+        };
+        let direction = |cipher: &str, mac: &str, letters: [u8; 3]| {
+            let lengths = cipher::key_lengths(cipher, mac)?;
+            PacketCipher::new(
+                cipher,
+                mac,
+                &derive(letters[1], lengths.key),
+                &derive(letters[0], lengths.iv),
+                &derive(letters[2], lengths.mac),
+            )
+        };
+        let outgoing = direction(
+            &negotiated.encryption_client_to_server,
+            &negotiated.mac_client_to_server,
+            *b"ACE",
+        )?;
+        let incoming = direction(
+            &negotiated.encryption_server_to_client,
+            &negotiated.mac_server_to_client,
+            *b"BDF",
+        )?;
+        self.codec.set_pending_keys(outgoing, incoming);
+        
+        log::debug!(
+            "[SSH Protocol] Session keys derived for {} / {}",
+            negotiated.encryption_client_to_server,
+            negotiated.encryption_server_to_client
+        );
+        Ok(())
     }
 
     /// Send SSH_MSG_NEWKEYS
-    async fn send_new_keys(&self) -> Result<()> {
-        console_log!("[SSH Protocol] Sending NEWKEYS message");
+    async fn send_new_keys(&mut self) -> Result<()> {
+        log::debug!("[SSH Protocol] Sending NEWKEYS message");
         
-        let mut packet = Vec::new();
-        packet.extend_from_slice(&6u32.to_be_bytes()); // Packet length
-        packet.push(4); // Padding length
-        packet.push(SSH_MSG_NEWKEYS);
-        packet.extend_from_slice(&[0u8; 4]); // Padding
-        
-        js_tcp_send(&packet).await
+        self.send_packet(&[SSH_MSG_NEWKEYS]).await
             .map_err(|e| anyhow::anyhow!("Failed to send NEWKEYS: {:?}", e))?;
         
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Receive the next whole packet and return its payload, reading from the
+    /// socket as needed. The server's EXT_INFO is recorded on the way through,
+    /// global requests are answered without being returned, and a DISCONNECT
+    /// becomes a `Disconnect` error carrying the server's reason.
+    async fn receive_packet(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(payload) = self.codec.decode()? {
+                match payload.first() {
+                    Some(&SSH_MSG_EXT_INFO) => {
+                        self.server_sig_algs = ExtInfo::parse(&payload)?.server_sig_algs();
+                    }
+                    Some(&SSH_MSG_DISCONNECT) => return Err(Disconnect::parse(&payload)?.into()),
+                    Some(&SSH_MSG_GLOBAL_REQUEST) => {
+                        self.handle_global_request(&payload).await?;
+                        continue;
+                    }
+                    _ => {}
+                }
+                return Ok(payload);
+            }
+            let data = self.receive(RECEIVE_CHUNK).await
+                .map_err(|e| anyhow::anyhow!("Failed to receive from server: {:?}", e))?;
+            if data.is_empty() {
+                return Err(anyhow::anyhow!("Connection closed by server"));
+            }
+            self.codec.feed(&data);
+        }
+    }
+
+    /// Answer a global request: keepalives succeed, anything else we don't
+    /// support fails, and host key announcements are kept for `announced_host_keys`
+    async fn handle_global_request(&mut self, payload: &[u8]) -> Result<()> {
+        let request = GlobalRequest::parse(payload)?;
+        log::debug!("[SSH Protocol] Global request {} (want reply: {})", request.name, request.want_reply);
+        if request.name == HOSTKEYS_REQUEST {
+            self.announced_host_keys = request.host_keys()?;
+            log::info!("[SSH Protocol] Server announced {} host keys", self.announced_host_keys.len());
+        }
+        if let Some(reply) = request.reply() {
+            self.send_packet(&reply).await
+                .map_err(|e| anyhow::anyhow!("Failed to answer global request: {:?}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Frame, encrypt and send one packet, counting it toward the rekey limit
+    async fn send_packet(&mut self, payload: &[u8]) -> Result<(), JsValue> {
+        let packet = self.codec.encode(payload).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        js_tcp_send(&packet).await?;
+        self.rekey.record_sent(packet.len());
+        Ok(())
+    }
+
+    /// Receive up to `max_len` bytes, counting them toward the rekey limit
+    async fn receive(&mut self, max_len: usize) -> Result<Vec<u8>, JsValue> {
+        let data = Uint8Array::new(&js_tcp_receive(max_len).await?).to_vec();
+        self.rekey.record_received(data.len());
+        Ok(data)
+    }
+}
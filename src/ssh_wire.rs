@@ -0,0 +1,1187 @@
+use anyhow::Result;
+
+/// Message number of SSH_MSG_KEXINIT (RFC 4253 section 7.1)
+pub const SSH_MSG_KEXINIT: u8 = 20;
+
+/// SSH_MSG_NEWKEYS (RFC 4253 section 7.3)
+pub const SSH_MSG_NEWKEYS: u8 = 21;
+
+/// SSH_MSG_EXT_INFO (RFC 8308 section 2.3)
+pub const SSH_MSG_EXT_INFO: u8 = 7;
+
+/// SSH_MSG_DISCONNECT (RFC 4253 section 11.1)
+pub const SSH_MSG_DISCONNECT: u8 = 1;
+
+/// Disconnect reason codes we send (RFC 4253 section 11.1)
+pub const DISCONNECT_KEY_EXCHANGE_FAILED: u32 = 3;
+pub const DISCONNECT_BY_APPLICATION: u32 = 11;
+
+/// SSH_MSG_IGNORE and SSH_MSG_DEBUG, which may arrive at any time (RFC 4253 section 11)
+pub const SSH_MSG_IGNORE: u8 = 2;
+pub const SSH_MSG_DEBUG: u8 = 4;
+
+/// SSH_MSG_GLOBAL_REQUEST and its replies (RFC 4254 section 4)
+pub const SSH_MSG_GLOBAL_REQUEST: u8 = 80;
+pub const SSH_MSG_REQUEST_SUCCESS: u8 = 81;
+pub const SSH_MSG_REQUEST_FAILURE: u8 = 82;
+
+/// Liveness probe OpenSSH servers send with `ClientAliveInterval`
+pub const KEEPALIVE_REQUEST: &str = "keepalive@openssh.com";
+
+/// OpenSSH's announcement of all the server's host keys, for key rotation
+pub const HOSTKEYS_REQUEST: &str = "hostkeys-00@openssh.com";
+
+/// Pseudo-algorithm asking the server for SSH_MSG_EXT_INFO (RFC 8308 section 2.1)
+pub const EXT_INFO_C: &str = "ext-info-c";
+
+/// Pseudo-algorithms announcing strict key exchange, OpenSSH's Terrapin
+/// (CVE-2023-48795) countermeasure
+pub const KEX_STRICT_C: &str = "kex-strict-c-v00@openssh.com";
+pub const KEX_STRICT_S: &str = "kex-strict-s-v00@openssh.com";
+
+/// Largest packet_length we accept; RFC 4253 requires support for 35000-byte packets
+pub const MAX_PACKET_LENGTH: usize = 256 * 1024;
+
+/// An unencrypted binary packet (RFC 4253 section 6)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet<'a> {
+    pub payload: &'a [u8],
+    pub padding: &'a [u8],
+}
+
+impl Packet<'_> {
+    pub fn message_type(&self) -> Option<u8> {
+        self.payload.first().copied()
+    }
+}
+
+/// Parse one unencrypted packet from the front of `data`, returning it and the
+/// number of bytes consumed. Fails if `data` is truncated or the framing is invalid.
+pub fn parse_packet(data: &[u8]) -> Result<(Packet<'_>, usize)> {
+    let mut reader = Reader::new(data);
+    let packet_length = reader.u32()? as usize;
+    if packet_length > MAX_PACKET_LENGTH {
+        return Err(anyhow::anyhow!("Packet length {} exceeds maximum", packet_length));
+    }
+
+    let body = reader.bytes(packet_length)?;
+    let (&padding_length, rest) = body
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("Packet has no padding length"))?;
+    let padding_length = padding_length as usize;
+    if padding_length > rest.len() {
+        return Err(anyhow::anyhow!("Padding length {} exceeds packet", padding_length));
+    }
+
+    let (payload, padding) = rest.split_at(rest.len() - padding_length);
+    Ok((Packet { payload, padding }, 4 + packet_length))
+}
+
+/// Longest identification line, CR LF included (RFC 4253 section 4.2)
+pub const MAX_VERSION_LINE: usize = 255;
+
+/// Lines a server may send before its identification string, as OpenSSH allows
+const MAX_PRE_BANNER_LINES: usize = 1024;
+
+/// Find the server's identification string at the start of `data`, skipping any
+/// lines the server sends before it. Returns the version line without CR LF and
+/// the bytes it and the skipped lines used, or `None` until the line is complete.
+pub fn parse_server_version(data: &[u8]) -> Result<Option<(String, usize)>> {
+    let mut start = 0;
+    for _ in 0..MAX_PRE_BANNER_LINES {
+        let rest = &data[start..];
+        let Some(newline) = rest.iter().position(|&b| b == b'\n') else {
+            if rest.len() >= MAX_VERSION_LINE {
+                return Err(anyhow::anyhow!("Server identification line is too long"));
+            }
+            return Ok(None);
+        };
+        if newline >= MAX_VERSION_LINE {
+            return Err(anyhow::anyhow!("Server identification line is too long"));
+        }
+        let line = &rest[..newline];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        start += newline + 1;
+
+        if !line.starts_with(b"SSH-") {
+            continue;
+        }
+        let version = std::str::from_utf8(line)
+            .map_err(|_| anyhow::anyhow!("Server identification string is not valid UTF-8"))?;
+        // 1.99 announces a server that also speaks 2.0 (RFC 4253 section 5.1)
+        if !version.starts_with("SSH-2.0-") && !version.starts_with("SSH-1.99-") {
+            return Err(anyhow::anyhow!("Unsupported SSH protocol version: {}", version));
+        }
+        return Ok(Some((version.to_string(), start)));
+    }
+    Err(anyhow::anyhow!("Server sent no identification string"))
+}
+
+/// Contents of SSH_MSG_KEXINIT
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KexInit {
+    pub cookie: [u8; 16],
+    pub kex_algorithms: Vec<String>,
+    pub server_host_key_algorithms: Vec<String>,
+    pub encryption_client_to_server: Vec<String>,
+    pub encryption_server_to_client: Vec<String>,
+    pub mac_client_to_server: Vec<String>,
+    pub mac_server_to_client: Vec<String>,
+    pub compression_client_to_server: Vec<String>,
+    pub compression_server_to_client: Vec<String>,
+    pub languages_client_to_server: Vec<String>,
+    pub languages_server_to_client: Vec<String>,
+    pub first_kex_packet_follows: bool,
+}
+
+/// Algorithms agreed for one key exchange
+#[derive(Debug, Clone, PartialEq)]
+pub struct NegotiatedAlgorithms {
+    pub kex: String,
+    pub host_key: String,
+    pub encryption_client_to_server: String,
+    pub encryption_server_to_client: String,
+    pub mac_client_to_server: String,
+    pub mac_server_to_client: String,
+    pub compression_client_to_server: String,
+    pub compression_server_to_client: String,
+    /// Both sides announced strict key exchange
+    pub strict_kex: bool,
+}
+
+/// Algorithms the client offers, most preferred first
+const CLIENT_KEX: &[&str] = &[
+    "curve25519-sha256",
+    "curve25519-sha256@libssh.org",
+    "diffie-hellman-group16-sha512",
+    "diffie-hellman-group14-sha256",
+];
+const CLIENT_HOST_KEY: &[&str] = &["ssh-ed25519", "rsa-sha2-512", "rsa-sha2-256", "ecdsa-sha2-nistp256"];
+const CLIENT_CIPHERS: &[&str] = &[
+    "chacha20-poly1305@openssh.com",
+    "aes256-gcm@openssh.com",
+    "aes128-gcm@openssh.com",
+    "aes256-ctr",
+    "aes192-ctr",
+    "aes128-ctr",
+];
+const CLIENT_MACS: &[&str] = &[
+    "hmac-sha2-256-etm@openssh.com",
+    "hmac-sha2-512-etm@openssh.com",
+    "hmac-sha2-256",
+    "hmac-sha2-512",
+];
+
+impl KexInit {
+    /// The client's offer. The first exchange also asks for ext-info and strict
+    /// key exchange; those markers must not be repeated when rekeying.
+    pub fn client(cookie: [u8; 16], initial: bool) -> Self {
+        let names = |list: &[&str]| list.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let mut kex_algorithms = names(CLIENT_KEX);
+        if initial {
+            kex_algorithms.push(EXT_INFO_C.to_string());
+            kex_algorithms.push(KEX_STRICT_C.to_string());
+        }
+        KexInit {
+            cookie,
+            kex_algorithms,
+            server_host_key_algorithms: names(CLIENT_HOST_KEY),
+            encryption_client_to_server: names(CLIENT_CIPHERS),
+            encryption_server_to_client: names(CLIENT_CIPHERS),
+            mac_client_to_server: names(CLIENT_MACS),
+            mac_server_to_client: names(CLIENT_MACS),
+            compression_client_to_server: names(&["none"]),
+            compression_server_to_client: names(&["none"]),
+            ..KexInit::default()
+        }
+    }
+
+    /// The KEXINIT payload, message type byte included
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![SSH_MSG_KEXINIT];
+        payload.extend_from_slice(&self.cookie);
+        for list in [
+            &self.kex_algorithms,
+            &self.server_host_key_algorithms,
+            &self.encryption_client_to_server,
+            &self.encryption_server_to_client,
+            &self.mac_client_to_server,
+            &self.mac_server_to_client,
+            &self.compression_client_to_server,
+            &self.compression_server_to_client,
+            &self.languages_client_to_server,
+            &self.languages_server_to_client,
+        ] {
+            put_string(&mut payload, list.join(",").as_bytes());
+        }
+        payload.push(self.first_kex_packet_follows as u8);
+        payload.extend_from_slice(&[0u8; 4]);
+        payload
+    }
+
+    /// Parse a KEXINIT payload, including its message type byte
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(payload);
+        if reader.u8()? != SSH_MSG_KEXINIT {
+            return Err(anyhow::anyhow!("Not a KEXINIT message"));
+        }
+
+        let mut kex_init = KexInit::default();
+        kex_init.cookie.copy_from_slice(reader.bytes(16)?);
+        kex_init.kex_algorithms = reader.name_list()?;
+        kex_init.server_host_key_algorithms = reader.name_list()?;
+        kex_init.encryption_client_to_server = reader.name_list()?;
+        kex_init.encryption_server_to_client = reader.name_list()?;
+        kex_init.mac_client_to_server = reader.name_list()?;
+        kex_init.mac_server_to_client = reader.name_list()?;
+        kex_init.compression_client_to_server = reader.name_list()?;
+        kex_init.compression_server_to_client = reader.name_list()?;
+        kex_init.languages_client_to_server = reader.name_list()?;
+        kex_init.languages_server_to_client = reader.name_list()?;
+        kex_init.first_kex_packet_follows = reader.u8()? != 0;
+        reader.u32()?; // reserved
+
+        Ok(kex_init)
+    }
+
+    /// Pick algorithms as the client (`self`) against the server's offer
+    pub fn negotiate(&self, server: &KexInit) -> Result<NegotiatedAlgorithms> {
+        // Pseudo-algorithms only signal features and are never chosen
+        let real_kex: Vec<String> = self
+            .kex_algorithms
+            .iter()
+            .filter(|name| ![EXT_INFO_C, KEX_STRICT_C].contains(&name.as_str()))
+            .cloned()
+            .collect();
+
+        let encryption_client_to_server = negotiate_algorithm(
+            "encryption",
+            &self.encryption_client_to_server,
+            &server.encryption_client_to_server,
+        )?;
+        let encryption_server_to_client = negotiate_algorithm(
+            "encryption",
+            &self.encryption_server_to_client,
+            &server.encryption_server_to_client,
+        )?;
+        // AEAD ciphers carry their own integrity; no MAC is chosen for them
+        let mac = |cipher: &str, client: &[String], server: &[String]| {
+            if crate::cipher::is_aead(cipher) {
+                Ok(String::new())
+            } else {
+                negotiate_algorithm("MAC", client, server)
+            }
+        };
+
+        Ok(NegotiatedAlgorithms {
+            kex: negotiate_algorithm("key exchange", &real_kex, &server.kex_algorithms)?,
+            host_key: negotiate_algorithm(
+                "host key",
+                &self.server_host_key_algorithms,
+                &server.server_host_key_algorithms,
+            )?,
+            mac_client_to_server: mac(
+                &encryption_client_to_server,
+                &self.mac_client_to_server,
+                &server.mac_client_to_server,
+            )?,
+            mac_server_to_client: mac(
+                &encryption_server_to_client,
+                &self.mac_server_to_client,
+                &server.mac_server_to_client,
+            )?,
+            encryption_client_to_server,
+            encryption_server_to_client,
+            compression_client_to_server: negotiate_algorithm(
+                "compression",
+                &self.compression_client_to_server,
+                &server.compression_client_to_server,
+            )?,
+            compression_server_to_client: negotiate_algorithm(
+                "compression",
+                &self.compression_server_to_client,
+                &server.compression_server_to_client,
+            )?,
+            strict_kex: self.kex_algorithms.iter().any(|name| name == KEX_STRICT_C)
+                && server.kex_algorithms.iter().any(|name| name == KEX_STRICT_S),
+        })
+    }
+}
+
+/// Contents of SSH_MSG_EXT_INFO (RFC 8308 section 2.3)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtInfo {
+    pub extensions: Vec<(String, Vec<u8>)>,
+}
+
+impl ExtInfo {
+    /// Parse an EXT_INFO payload, including its message type byte
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(payload);
+        if reader.u8()? != SSH_MSG_EXT_INFO {
+            return Err(anyhow::anyhow!("Not an EXT_INFO message"));
+        }
+        let count = reader.u32()?;
+        let mut extensions = Vec::new();
+        for _ in 0..count {
+            let name = std::str::from_utf8(reader.string()?)
+                .map_err(|_| anyhow::anyhow!("Extension name is not valid UTF-8"))?
+                .to_string();
+            extensions.push((name, reader.string()?.to_vec()));
+        }
+        Ok(Self { extensions })
+    }
+
+    /// Signature algorithms the server accepts for publickey authentication
+    pub fn server_sig_algs(&self) -> Option<Vec<String>> {
+        let (_, value) = self.extensions.iter().find(|(name, _)| name == "server-sig-algs")?;
+        let text = std::str::from_utf8(value).ok()?;
+        Some(text.split(',').filter(|name| !name.is_empty()).map(str::to_string).collect())
+    }
+}
+
+/// SSH_MSG_DISCONNECT: why a side is closing the connection. As an error, it reads
+/// like "Server disconnected: Too many authentication failures (no more auth methods available)".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disconnect {
+    pub reason: u32,
+    pub description: String,
+}
+
+impl Disconnect {
+    pub fn new(reason: u32, description: &str) -> Self {
+        Self { reason, description: description.to_string() }
+    }
+
+    /// Parse a DISCONNECT payload, including its message type byte
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(payload);
+        if reader.u8()? != SSH_MSG_DISCONNECT {
+            return Err(anyhow::anyhow!("Not a DISCONNECT message"));
+        }
+        let reason = reader.u32()?;
+        let description = String::from_utf8_lossy(reader.string()?).into_owned();
+        Ok(Self { reason, description })
+    }
+
+    /// The DISCONNECT payload, with an empty language tag
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![SSH_MSG_DISCONNECT];
+        payload.extend_from_slice(&self.reason.to_be_bytes());
+        put_string(&mut payload, self.description.as_bytes());
+        put_string(&mut payload, b"");
+        payload
+    }
+
+    /// Readable name of the reason code
+    pub fn reason_name(&self) -> &'static str {
+        match self.reason {
+            1 => "host not allowed to connect",
+            2 => "protocol error",
+            3 => "key exchange failed",
+            5 => "MAC error",
+            6 => "compression error",
+            7 => "service not available",
+            8 => "protocol version not supported",
+            9 => "host key not verifiable",
+            10 => "connection lost",
+            11 => "closed by application",
+            12 => "too many connections",
+            13 => "authentication cancelled by user",
+            14 => "no more auth methods available",
+            15 => "illegal user name",
+            _ => "unknown reason",
+        }
+    }
+}
+
+impl std::fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The description is server-controlled; keep terminal control characters out
+        let description: String = self.description.chars().filter(|c| !c.is_control()).collect();
+        if description.is_empty() {
+            write!(f, "Server disconnected: {}", self.reason_name())
+        } else {
+            write!(f, "Server disconnected: {} ({})", description, self.reason_name())
+        }
+    }
+}
+
+impl std::error::Error for Disconnect {}
+
+/// A global request from the server (RFC 4254 section 4)
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalRequest {
+    pub name: String,
+    pub want_reply: bool,
+    /// Request-specific data after `want_reply`
+    pub data: Vec<u8>,
+}
+
+impl GlobalRequest {
+    /// Parse a GLOBAL_REQUEST payload, including its message type byte
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(payload);
+        if reader.u8()? != SSH_MSG_GLOBAL_REQUEST {
+            return Err(anyhow::anyhow!("Not a GLOBAL_REQUEST message"));
+        }
+        let name = std::str::from_utf8(reader.string()?)
+            .map_err(|_| anyhow::anyhow!("Global request name is not valid UTF-8"))?
+            .to_string();
+        let want_reply = reader.u8()? != 0;
+        let data = reader.bytes(reader.remaining())?.to_vec();
+        Ok(Self { name, want_reply, data })
+    }
+
+    /// Our reply, if the server asked for one: success for keepalives, which only
+    /// need an answer, and failure for anything we don't support
+    pub fn reply(&self) -> Option<Vec<u8>> {
+        if !self.want_reply {
+            return None;
+        }
+        Some(vec![if self.name == KEEPALIVE_REQUEST { SSH_MSG_REQUEST_SUCCESS } else { SSH_MSG_REQUEST_FAILURE }])
+    }
+
+    /// Host key blobs listed by a `hostkeys-00@openssh.com` announcement
+    pub fn host_keys(&self) -> Result<Vec<Vec<u8>>> {
+        if self.name != HOSTKEYS_REQUEST {
+            return Err(anyhow::anyhow!("Not a host key announcement: {}", self.name));
+        }
+        let mut reader = Reader::new(&self.data);
+        let mut keys = Vec::new();
+        while reader.remaining() > 0 {
+            keys.push(reader.string()?.to_vec());
+        }
+        Ok(keys)
+    }
+}
+
+/// User authentication (RFC 4252): the service that carries it and its messages
+pub const USERAUTH_SERVICE: &str = "ssh-userauth";
+pub const CONNECTION_SERVICE: &str = "ssh-connection";
+pub const SSH_MSG_SERVICE_REQUEST: u8 = 5;
+pub const SSH_MSG_SERVICE_ACCEPT: u8 = 6;
+pub const SSH_MSG_USERAUTH_REQUEST: u8 = 50;
+pub const SSH_MSG_USERAUTH_FAILURE: u8 = 51;
+pub const SSH_MSG_USERAUTH_SUCCESS: u8 = 52;
+pub const SSH_MSG_USERAUTH_BANNER: u8 = 53;
+/// Shares its number with other methods' replies; in answer to a password request
+/// it means the password has expired (RFC 4252 section 8)
+pub const SSH_MSG_USERAUTH_PASSWD_CHANGEREQ: u8 = 60;
+
+/// Check that `payload` is the server's NEWKEYS, which ends its half of a key exchange;
+/// its packets after that use the new keys
+pub fn expect_new_keys(payload: &[u8]) -> Result<()> {
+    match payload.first() {
+        Some(&SSH_MSG_NEWKEYS) => Ok(()),
+        other => Err(anyhow::anyhow!("Expected NEWKEYS, got message type {:?}", other)),
+    }
+}
+
+/// SERVICE_REQUEST payload for `service`
+pub fn service_request(service: &str) -> Vec<u8> {
+    let mut payload = vec![SSH_MSG_SERVICE_REQUEST];
+    put_string(&mut payload, service.as_bytes());
+    payload
+}
+
+/// A "password" method USERAUTH_REQUEST, changing the password to `new_password`
+/// when given (RFC 4252 section 8)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordRequest<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub new_password: Option<&'a str>,
+}
+
+impl PasswordRequest<'_> {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![SSH_MSG_USERAUTH_REQUEST];
+        put_string(&mut payload, self.username.as_bytes());
+        put_string(&mut payload, CONNECTION_SERVICE.as_bytes());
+        put_string(&mut payload, b"password");
+        payload.push(self.new_password.is_some() as u8);
+        put_string(&mut payload, self.password.as_bytes());
+        if let Some(new_password) = self.new_password {
+            put_string(&mut payload, new_password.as_bytes());
+        }
+        payload
+    }
+}
+
+/// The server's answer to a [`PasswordRequest`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasswordReply {
+    Success,
+    /// Wrong password, or a new password the server refused; `methods` can continue
+    Failure { methods: Vec<String>, partial_success: bool },
+    /// The password has expired; send it again with a new one
+    ChangeRequest { prompt: String },
+    /// Text to show the user; the real reply follows
+    Banner(String),
+}
+
+impl PasswordReply {
+    /// Parse a reply payload, including its message type byte
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(payload);
+        let text = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes).chars().filter(|c| *c == '\n' || !c.is_control()).collect()
+        };
+        match reader.u8()? {
+            SSH_MSG_USERAUTH_SUCCESS => Ok(Self::Success),
+            SSH_MSG_USERAUTH_FAILURE => {
+                let methods = reader.name_list()?;
+                Ok(Self::Failure { methods, partial_success: reader.u8()? != 0 })
+            }
+            SSH_MSG_USERAUTH_PASSWD_CHANGEREQ => Ok(Self::ChangeRequest { prompt: text(reader.string()?) }),
+            SSH_MSG_USERAUTH_BANNER => Ok(Self::Banner(text(reader.string()?))),
+            other => Err(anyhow::anyhow!("Unexpected reply to password authentication: message {}", other)),
+        }
+    }
+}
+
+/// Signature algorithm for an RSA key (RFC 8332). Prefers SHA-512, then SHA-256,
+/// among what `server-sig-algs` lists. Without that extension the server may only
+/// know the SHA-1 `ssh-rsa`.
+pub fn rsa_signature_algorithm(server_sig_algs: Option<&[String]>) -> Result<&'static str> {
+    let Some(accepted) = server_sig_algs else {
+        return Ok("ssh-rsa");
+    };
+    ["rsa-sha2-512", "rsa-sha2-256", "ssh-rsa"]
+        .into_iter()
+        .find(|algorithm| accepted.iter().any(|name| name == algorithm))
+        .ok_or_else(|| anyhow::anyhow!("Server accepts no RSA signature algorithm"))
+}
+
+/// Key exchange messages: KEXINIT, NEWKEYS and the method-specific range 30-49
+pub fn is_kex_message(message_type: u8) -> bool {
+    matches!(message_type, SSH_MSG_KEXINIT | SSH_MSG_NEWKEYS | 30..=49)
+}
+
+/// Packet sequence numbers for both directions (RFC 4253 section 6.4), with
+/// strict key exchange's extra rules: the server's first packet is its KEXINIT,
+/// nothing but key exchange messages arrive until the first NEWKEYS, and each
+/// NEWKEYS resets the sequence number of its direction to zero
+#[derive(Debug, Clone, Default)]
+pub struct TransportSequence {
+    send: u32,
+    receive: u32,
+    strict: bool,
+    initial_kex_done: bool,
+}
+
+impl TransportSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sequence number for the next packet sent
+    pub fn next_send(&mut self) -> u32 {
+        let seq = self.send;
+        self.send = self.send.wrapping_add(1);
+        seq
+    }
+
+    /// Sequence number the next received packet will have, needed to decrypt it
+    pub fn next_receive(&self) -> u32 {
+        self.receive
+    }
+
+    /// Account for a received packet, returning its sequence number
+    pub fn receive(&mut self, message_type: u8) -> Result<u32> {
+        if self.strict && !self.initial_kex_done && !is_kex_message(message_type) {
+            return Err(anyhow::anyhow!(
+                "Strict key exchange violated: message {} during initial key exchange",
+                message_type
+            ));
+        }
+        let seq = self.receive;
+        self.receive = self.receive.wrapping_add(1);
+        Ok(seq)
+    }
+
+    /// Record the outcome of negotiation, after the server's KEXINIT was received
+    pub fn negotiated(&mut self, algorithms: &NegotiatedAlgorithms) -> Result<()> {
+        if self.initial_kex_done {
+            return Ok(());
+        }
+        if algorithms.strict_kex && self.receive != 1 {
+            return Err(anyhow::anyhow!("Strict key exchange violated: KEXINIT was not the server's first packet"));
+        }
+        self.strict = algorithms.strict_kex;
+        Ok(())
+    }
+
+    pub fn sent_newkeys(&mut self) {
+        if self.strict {
+            self.send = 0;
+        }
+    }
+
+    pub fn received_newkeys(&mut self) {
+        if self.strict {
+            self.receive = 0;
+        }
+        self.initial_kex_done = true;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+}
+
+/// The first client algorithm the server also supports (RFC 4253 section 7.1)
+pub fn negotiate_algorithm(kind: &str, client: &[String], server: &[String]) -> Result<String> {
+    client
+        .iter()
+        .find(|algorithm| server.contains(algorithm))
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("No common {} algorithm", kind))
+}
+
+/// Append `data` as an SSH `string`: a u32 length, then the bytes (RFC 4251 section 5)
+pub fn put_string(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Append an mpint (RFC 4251 section 5) holding the unsigned big-endian `value`
+pub fn put_mpint(out: &mut Vec<u8>, value: &[u8]) {
+    let start = value.iter().position(|&b| b != 0).unwrap_or(value.len());
+    let value = &value[start..];
+    if value.first().is_some_and(|b| b & 0x80 != 0) {
+        out.extend_from_slice(&(value.len() as u32 + 1).to_be_bytes());
+        out.push(0);
+        out.extend_from_slice(value);
+    } else {
+        put_string(out, value);
+    }
+}
+
+/// Hash function of a key exchange method, used for the exchange hash and key derivation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KexHash {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl KexHash {
+    pub fn for_kex(kex: &str) -> Result<Self> {
+        match kex {
+            "curve25519-sha256" | "curve25519-sha256@libssh.org" | "ecdh-sha2-nistp256" | "diffie-hellman-group14-sha256" => {
+                Ok(KexHash::Sha256)
+            }
+            "ecdh-sha2-nistp384" => Ok(KexHash::Sha384),
+            "ecdh-sha2-nistp521" | "diffie-hellman-group16-sha512" | "diffie-hellman-group18-sha512" => {
+                Ok(KexHash::Sha512)
+            }
+            other => Err(anyhow::anyhow!("Unsupported key exchange method '{}'", other)),
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        use sha2::Digest;
+        match self {
+            KexHash::Sha256 => sha2::Sha256::digest(data).to_vec(),
+            KexHash::Sha384 => sha2::Sha384::digest(data).to_vec(),
+            KexHash::Sha512 => sha2::Sha512::digest(data).to_vec(),
+        }
+    }
+
+    /// `len` bytes of key material for `letter` (RFC 4253 section 7.2):
+    /// HASH(K || H || letter || session_id), extended with HASH(K || H || key so far)
+    pub fn derive_key(self, shared_secret: &[u8], exchange_hash: &[u8], letter: u8, session_id: &[u8], len: usize) -> Vec<u8> {
+        let mut prefix = Vec::new();
+        put_mpint(&mut prefix, shared_secret);
+        prefix.extend_from_slice(exchange_hash);
+
+        let mut key = self.digest(&[&prefix[..], &[letter], session_id].concat());
+        while key.len() < len {
+            let more = self.digest(&[&prefix[..], &key].concat());
+            key.extend_from_slice(&more);
+        }
+        key.truncate(len);
+        key
+    }
+}
+
+/// Everything the exchange hash H covers
+pub struct ExchangeHashInput<'a> {
+    /// Version lines without the trailing CR LF
+    pub client_version: &'a str,
+    pub server_version: &'a str,
+    /// KEXINIT payloads, message type byte included
+    pub client_kex_init: &'a [u8],
+    pub server_kex_init: &'a [u8],
+    pub host_key: &'a [u8],
+    /// e/f for Diffie-Hellman, Q_C/Q_S for ECDH and Curve25519
+    pub client_public: &'a [u8],
+    pub server_public: &'a [u8],
+    /// K as unsigned big-endian bytes
+    pub shared_secret: &'a [u8],
+}
+
+/// The exchange hash H for `kex`: RFC 4253 section 8 for Diffie-Hellman, where the
+/// public values are mpints, and RFC 5656 section 4 / RFC 8731 for ECDH and
+/// Curve25519, where they are strings
+pub fn exchange_hash(kex: &str, input: &ExchangeHashInput) -> Result<Vec<u8>> {
+    let hash = KexHash::for_kex(kex)?;
+    let mut data = Vec::new();
+    put_string(&mut data, input.client_version.as_bytes());
+    put_string(&mut data, input.server_version.as_bytes());
+    put_string(&mut data, input.client_kex_init);
+    put_string(&mut data, input.server_kex_init);
+    put_string(&mut data, input.host_key);
+    if kex.starts_with("diffie-hellman-") {
+        put_mpint(&mut data, input.client_public);
+        put_mpint(&mut data, input.server_public);
+    } else {
+        put_string(&mut data, input.client_public);
+        put_string(&mut data, input.server_public);
+    }
+    put_mpint(&mut data, input.shared_secret);
+    Ok(hash.digest(&data))
+}
+
+/// Bounds-checked reader for SSH wire types (RFC 4251 section 5)
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(anyhow::anyhow!(
+                "Truncated data: need {} bytes, have {}",
+                len,
+                self.data.len()
+            ));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    pub fn name_list(&mut self) -> Result<Vec<String>> {
+        let raw = self.string()?;
+        if raw.is_empty() {
+            return Ok(Vec::new());
+        }
+        let text = std::str::from_utf8(raw).map_err(|_| anyhow::anyhow!("Name-list is not valid UTF-8"))?;
+        Ok(text.split(',').map(str::to_string).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_list(out: &mut Vec<u8>, names: &str) {
+        out.extend_from_slice(&(names.len() as u32).to_be_bytes());
+        out.extend_from_slice(names.as_bytes());
+    }
+
+    fn kex_init_payload(kex: &str, cipher: &str) -> Vec<u8> {
+        let mut payload = vec![SSH_MSG_KEXINIT];
+        payload.extend_from_slice(&[7u8; 16]);
+        name_list(&mut payload, kex);
+        name_list(&mut payload, "ssh-ed25519");
+        name_list(&mut payload, cipher);
+        name_list(&mut payload, cipher);
+        name_list(&mut payload, "hmac-sha2-256");
+        name_list(&mut payload, "hmac-sha2-256");
+        name_list(&mut payload, "none");
+        name_list(&mut payload, "none");
+        name_list(&mut payload, "");
+        name_list(&mut payload, "");
+        payload.push(0);
+        payload.extend_from_slice(&[0u8; 4]);
+        payload
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let padding = 4;
+        let mut packet = ((payload.len() + 1 + padding) as u32).to_be_bytes().to_vec();
+        packet.push(padding as u8);
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(&vec![0u8; padding]);
+        packet
+    }
+
+    #[test]
+    fn test_parse_packet() {
+        let data = frame(&[SSH_MSG_KEXINIT, 1, 2, 3]);
+        let (packet, consumed) = parse_packet(&data).unwrap();
+
+        assert_eq!(consumed, data.len());
+        assert_eq!(packet.payload, &[SSH_MSG_KEXINIT, 1, 2, 3]);
+        assert_eq!(packet.padding.len(), 4);
+        assert_eq!(packet.message_type(), Some(SSH_MSG_KEXINIT));
+    }
+
+    #[test]
+    fn test_parse_packet_rejects_bad_framing() {
+        assert!(parse_packet(&[0, 0]).is_err());
+        assert!(parse_packet(&[0, 0, 0, 10, 4, 20]).is_err());
+        assert!(parse_packet(&[0, 0, 0, 2, 9, 20]).is_err());
+        assert!(parse_packet(&[0, 0, 0, 0]).is_err());
+        assert!(parse_packet(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_parse_kex_init() {
+        let payload = kex_init_payload("curve25519-sha256,diffie-hellman-group14-sha256", "aes256-ctr");
+        let kex_init = KexInit::parse(&payload).unwrap();
+
+        assert_eq!(kex_init.cookie, [7u8; 16]);
+        assert_eq!(kex_init.kex_algorithms, vec!["curve25519-sha256", "diffie-hellman-group14-sha256"]);
+        assert!(kex_init.languages_client_to_server.is_empty());
+        assert!(!kex_init.first_kex_packet_follows);
+    }
+
+    #[test]
+    fn test_parse_kex_init_truncated() {
+        let payload = kex_init_payload("curve25519-sha256", "aes256-ctr");
+        for len in 0..payload.len() {
+            assert!(KexInit::parse(&payload[..len]).is_err(), "accepted {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_parse_kex_init_oversized_name_list() {
+        let mut payload = vec![SSH_MSG_KEXINIT];
+        payload.extend_from_slice(&[0u8; 16]);
+        payload.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(KexInit::parse(&payload).is_err());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let client = KexInit::parse(&kex_init_payload(
+            "curve25519-sha256,diffie-hellman-group14-sha256",
+            "aes256-ctr,aes128-ctr",
+        ))
+        .unwrap();
+        let server = KexInit::parse(&kex_init_payload(
+            "diffie-hellman-group14-sha256,curve25519-sha256",
+            "aes128-ctr",
+        ))
+        .unwrap();
+
+        let negotiated = client.negotiate(&server).unwrap();
+        assert_eq!(negotiated.kex, "curve25519-sha256");
+        assert_eq!(negotiated.encryption_client_to_server, "aes128-ctr");
+
+        let incompatible = KexInit::parse(&kex_init_payload("sntrup761x25519-sha512", "aes128-ctr")).unwrap();
+        let err = client.negotiate(&incompatible).unwrap_err();
+        assert!(err.to_string().contains("key exchange"));
+    }
+
+    #[test]
+    fn test_client_kex_init_round_trip() {
+        let initial = KexInit::client([3u8; 16], true);
+        assert_eq!(KexInit::parse(&initial.encode()).unwrap(), initial);
+        assert!(initial.kex_algorithms.iter().any(|name| name == EXT_INFO_C));
+        assert!(initial.kex_algorithms.iter().any(|name| name == KEX_STRICT_C));
+
+        let rekey = KexInit::client([3u8; 16], false);
+        assert!(!rekey.kex_algorithms.iter().any(|name| name == EXT_INFO_C || name == KEX_STRICT_C));
+    }
+
+    #[test]
+    fn test_negotiate_strict_kex() {
+        let client = KexInit::client([0u8; 16], true);
+        let mut server = KexInit::client([1u8; 16], false);
+        server.kex_algorithms = vec![KEX_STRICT_C.to_string(), "curve25519-sha256".to_string()];
+        // A server echoing the client marker is not strict, and markers are never chosen
+        let negotiated = client.negotiate(&server).unwrap();
+        assert_eq!(negotiated.kex, "curve25519-sha256");
+        assert!(!negotiated.strict_kex);
+
+        server.kex_algorithms.push(KEX_STRICT_S.to_string());
+        assert!(client.negotiate(&server).unwrap().strict_kex);
+        assert!(!KexInit::client([0u8; 16], false).negotiate(&server).unwrap().strict_kex);
+
+        server.kex_algorithms = vec![EXT_INFO_C.to_string()];
+        assert!(client.negotiate(&server).is_err());
+    }
+
+    fn strict() -> NegotiatedAlgorithms {
+        let mut server = KexInit::client([1u8; 16], false);
+        server.kex_algorithms.push(KEX_STRICT_S.to_string());
+        KexInit::client([0u8; 16], true).negotiate(&server).unwrap()
+    }
+
+    #[test]
+    fn test_strict_sequence_numbers() {
+        let mut sequence = TransportSequence::new();
+        assert_eq!(sequence.next_send(), 0);
+        assert_eq!(sequence.receive(SSH_MSG_KEXINIT).unwrap(), 0);
+        sequence.negotiated(&strict()).unwrap();
+        assert!(sequence.is_strict());
+
+        // Terrapin injects an IGNORE during the handshake to shift sequence numbers
+        assert!(sequence.receive(2).is_err());
+        assert_eq!(sequence.receive(31).unwrap(), 1);
+
+        assert_eq!(sequence.next_send(), 1);
+        sequence.sent_newkeys();
+        assert_eq!(sequence.next_send(), 0);
+        assert_eq!(sequence.receive(SSH_MSG_NEWKEYS).unwrap(), 2);
+        sequence.received_newkeys();
+        assert_eq!(sequence.receive(SSH_MSG_EXT_INFO).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_strict_kex_requires_kexinit_first() {
+        let mut sequence = TransportSequence::new();
+        sequence.receive(2).unwrap();
+        sequence.receive(SSH_MSG_KEXINIT).unwrap();
+        assert!(sequence.negotiated(&strict()).is_err());
+
+        // Without strict KEX, sequence numbers run on across NEWKEYS
+        let mut sequence = TransportSequence::new();
+        sequence.receive(SSH_MSG_KEXINIT).unwrap();
+        sequence.negotiated(&KexInit::client([0u8; 16], true).negotiate(&KexInit::client([1u8; 16], false)).unwrap()).unwrap();
+        sequence.receive(2).unwrap();
+        sequence.received_newkeys();
+        assert_eq!(sequence.receive(2).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_ext_info_and_rsa_signatures() {
+        let mut payload = vec![SSH_MSG_EXT_INFO];
+        payload.extend_from_slice(&2u32.to_be_bytes());
+        put_string(&mut payload, b"server-sig-algs");
+        put_string(&mut payload, b"ssh-ed25519,rsa-sha2-256,ssh-rsa");
+        put_string(&mut payload, b"ping@openssh.com");
+        put_string(&mut payload, b"0");
+
+        let ext_info = ExtInfo::parse(&payload).unwrap();
+        let algs = ext_info.server_sig_algs().unwrap();
+        assert_eq!(algs, ["ssh-ed25519", "rsa-sha2-256", "ssh-rsa"]);
+        assert_eq!(rsa_signature_algorithm(Some(&algs)).unwrap(), "rsa-sha2-256");
+        assert_eq!(rsa_signature_algorithm(None).unwrap(), "ssh-rsa");
+        assert_eq!(
+            rsa_signature_algorithm(Some(&["rsa-sha2-512".to_string(), "rsa-sha2-256".to_string()])).unwrap(),
+            "rsa-sha2-512"
+        );
+        assert!(rsa_signature_algorithm(Some(&["ssh-ed25519".to_string()])).is_err());
+
+        assert!(ExtInfo::parse(&payload[..payload.len() - 1]).is_err());
+        assert!(ExtInfo::default().server_sig_algs().is_none());
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let sent = Disconnect::new(DISCONNECT_BY_APPLICATION, "bye");
+        let payload = sent.encode();
+        assert_eq!(payload[0], SSH_MSG_DISCONNECT);
+        assert_eq!(Disconnect::parse(&payload).unwrap(), sent);
+
+        let mut payload = vec![SSH_MSG_DISCONNECT];
+        payload.extend_from_slice(&14u32.to_be_bytes());
+        put_string(&mut payload, b"Too many authentication failures\x1b[2J");
+        put_string(&mut payload, b"");
+        assert_eq!(
+            Disconnect::parse(&payload).unwrap().to_string(),
+            "Server disconnected: Too many authentication failures[2J (no more auth methods available)"
+        );
+        assert_eq!(Disconnect::new(99, "").to_string(), "Server disconnected: unknown reason");
+        assert!(Disconnect::parse(&payload[..6]).is_err());
+    }
+
+    #[test]
+    fn test_password_request() {
+        let request = PasswordRequest { username: "alice", password: "old", new_password: None };
+        let mut expected = vec![SSH_MSG_USERAUTH_REQUEST];
+        for field in ["alice", "ssh-connection", "password"] {
+            put_string(&mut expected, field.as_bytes());
+        }
+        expected.push(0);
+        put_string(&mut expected, b"old");
+        assert_eq!(request.encode(), expected);
+
+        let change = PasswordRequest { new_password: Some("new"), ..request };
+        expected[1 + 9 + 18 + 12] = 1;
+        put_string(&mut expected, b"new");
+        assert_eq!(change.encode(), expected);
+
+        assert_eq!(service_request(USERAUTH_SERVICE)[0], SSH_MSG_SERVICE_REQUEST);
+    }
+
+    #[test]
+    fn test_password_replies() {
+        assert_eq!(PasswordReply::parse(&[SSH_MSG_USERAUTH_SUCCESS]).unwrap(), PasswordReply::Success);
+
+        let mut failure = vec![SSH_MSG_USERAUTH_FAILURE];
+        put_string(&mut failure, b"publickey,password");
+        failure.push(0);
+        assert_eq!(
+            PasswordReply::parse(&failure).unwrap(),
+            PasswordReply::Failure { methods: vec!["publickey".into(), "password".into()], partial_success: false }
+        );
+
+        let mut change = vec![SSH_MSG_USERAUTH_PASSWD_CHANGEREQ];
+        put_string(&mut change, b"Password expired\x1b[2J\n");
+        put_string(&mut change, b"");
+        assert_eq!(
+            PasswordReply::parse(&change).unwrap(),
+            PasswordReply::ChangeRequest { prompt: "Password expired[2J\n".into() }
+        );
+
+        assert!(PasswordReply::parse(&[SSH_MSG_USERAUTH_PASSWD_CHANGEREQ]).is_err());
+        assert!(PasswordReply::parse(&[SSH_MSG_SERVICE_ACCEPT]).is_err());
+    }
+
+    #[test]
+    fn test_global_requests() {
+        let request = |name: &str, want_reply: bool, data: &[u8]| {
+            let mut payload = vec![SSH_MSG_GLOBAL_REQUEST];
+            put_string(&mut payload, name.as_bytes());
+            payload.push(want_reply as u8);
+            payload.extend_from_slice(data);
+            GlobalRequest::parse(&payload).unwrap()
+        };
+
+        assert_eq!(request(KEEPALIVE_REQUEST, true, b"").reply(), Some(vec![SSH_MSG_REQUEST_SUCCESS]));
+        assert_eq!(request("tcpip-forward", true, b"").reply(), Some(vec![SSH_MSG_REQUEST_FAILURE]));
+        assert_eq!(request(KEEPALIVE_REQUEST, false, b"").reply(), None);
+
+        let mut keys = Vec::new();
+        put_string(&mut keys, b"first key");
+        put_string(&mut keys, b"second");
+        let announcement = request(HOSTKEYS_REQUEST, false, &keys);
+        assert_eq!(announcement.host_keys().unwrap(), [b"first key".to_vec(), b"second".to_vec()]);
+        assert_eq!(announcement.reply(), None);
+        assert!(request(HOSTKEYS_REQUEST, false, &keys[..keys.len() - 1]).host_keys().is_err());
+        assert!(request(KEEPALIVE_REQUEST, false, b"").host_keys().is_err());
+
+        assert!(GlobalRequest::parse(&[SSH_MSG_GLOBAL_REQUEST, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_put_mpint() {
+        // RFC 4251 section 5 examples
+        let encode = |value: &[u8]| {
+            let mut out = Vec::new();
+            put_mpint(&mut out, value);
+            out
+        };
+        assert_eq!(encode(&[]), [0, 0, 0, 0]);
+        assert_eq!(encode(&[0, 0]), [0, 0, 0, 0]);
+        assert_eq!(encode(&[0x80]), [0, 0, 0, 2, 0, 0x80]);
+        assert_eq!(encode(&[0, 0x12, 0x34]), [0, 0, 0, 2, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_exchange_hash() {
+        let input = ExchangeHashInput {
+            client_version: "SSH-2.0-client",
+            server_version: "SSH-2.0-server",
+            client_kex_init: &[SSH_MSG_KEXINIT, 1],
+            server_kex_init: &[SSH_MSG_KEXINIT, 2],
+            host_key: b"key",
+            client_public: &[0x80; 4],
+            server_public: &[0x01; 4],
+            shared_secret: &[0, 0x7f],
+        };
+
+        let mut expected = Vec::new();
+        put_string(&mut expected, b"SSH-2.0-client");
+        put_string(&mut expected, b"SSH-2.0-server");
+        put_string(&mut expected, &[SSH_MSG_KEXINIT, 1]);
+        put_string(&mut expected, &[SSH_MSG_KEXINIT, 2]);
+        put_string(&mut expected, b"key");
+        let mut curve = expected.clone();
+        put_string(&mut curve, &[0x80; 4]);
+        put_string(&mut curve, &[0x01; 4]);
+        curve.extend_from_slice(&[0, 0, 0, 1, 0x7f]);
+        assert_eq!(exchange_hash("curve25519-sha256", &input).unwrap(), KexHash::Sha256.digest(&curve));
+        assert_eq!(exchange_hash("ecdh-sha2-nistp384", &input).unwrap().len(), 48);
+
+        // Diffie-Hellman publics are mpints, so e gains a sign byte
+        let mut dh = expected;
+        dh.extend_from_slice(&[0, 0, 0, 5, 0, 0x80, 0x80, 0x80, 0x80]);
+        put_string(&mut dh, &[0x01; 4]);
+        dh.extend_from_slice(&[0, 0, 0, 1, 0x7f]);
+        assert_eq!(exchange_hash("diffie-hellman-group16-sha512", &input).unwrap(), KexHash::Sha512.digest(&dh));
+
+        assert!(exchange_hash("diffie-hellman-group1-sha1", &input).is_err());
+    }
+
+    #[test]
+    fn test_negotiate_aead_skips_mac() {
+        let client = KexInit::client([0u8; 16], true);
+        let mut server = KexInit::client([1u8; 16], false);
+        server.mac_client_to_server = vec!["hmac-md5".to_string()];
+        server.mac_server_to_client = vec!["hmac-md5".to_string()];
+        let negotiated = client.negotiate(&server).unwrap();
+        assert_eq!(negotiated.encryption_client_to_server, "chacha20-poly1305@openssh.com");
+        assert_eq!(negotiated.mac_client_to_server, "");
+
+        // aes-ctr still needs a MAC both sides know
+        server.encryption_client_to_server = vec!["aes128-ctr".to_string()];
+        assert!(client.negotiate(&server).is_err());
+        server.mac_client_to_server = vec!["hmac-sha2-512".to_string(), "hmac-sha2-256-etm@openssh.com".to_string()];
+        assert_eq!(client.negotiate(&server).unwrap().mac_client_to_server, "hmac-sha2-256-etm@openssh.com");
+    }
+
+    #[test]
+    fn test_derive_key_extends() {
+        let hash = KexHash::Sha256;
+        let key = hash.derive_key(&[0x80, 1], b"H", b'C', b"session", 64);
+        assert_eq!(key.len(), 64);
+
+        let mut prefix = Vec::new();
+        put_mpint(&mut prefix, &[0x80, 1]);
+        prefix.extend_from_slice(b"H");
+        let first = hash.digest(&[&prefix[..], b"Csession"].concat());
+        assert_eq!(key[..32], first[..]);
+        assert_eq!(key[32..], hash.digest(&[&prefix[..], &first].concat())[..]);
+        assert_eq!(hash.derive_key(&[0x80, 1], b"H", b'C', b"session", 16), first[..16]);
+    }
+
+    #[test]
+    fn test_parse_server_version() {
+        let data = b"SSH-2.0-OpenSSH_9.6\r\n\0\0\0\x0c";
+        let (version, used) = parse_server_version(data).unwrap().unwrap();
+        assert_eq!(version, "SSH-2.0-OpenSSH_9.6");
+        assert_eq!(&data[used..], b"\0\0\0\x0c");
+
+        // Lines before the version are skipped; bare LF is accepted
+        let data = b"Welcome\r\nauthorized use only\nSSH-1.99-legacy\n";
+        assert_eq!(parse_server_version(data).unwrap(), Some(("SSH-1.99-legacy".to_string(), data.len())));
+
+        assert_eq!(parse_server_version(b"").unwrap(), None);
+        assert_eq!(parse_server_version(b"Welcome\r\nSSH-2.0-Open").unwrap(), None);
+
+        assert!(parse_server_version(b"SSH-1.5-ancient\r\n").is_err());
+        assert!(parse_server_version(&[b'x'; 300]).is_err());
+        assert!(parse_server_version(&b"noise\n".repeat(MAX_PRE_BANNER_LINES)).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use crate::progress::{self, ProgressEvent};
+use crate::ssh_client::{ShellSession, Signal, WriteOutcome};
+
+/// Minimum time between `bytes-transferred` progress events
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pump raw bytes between this process's stdin/stdout and an SSH channel
+/// Nothing is filtered or translated, so the stream is safe for binary protocols
+pub fn pipe_stdio(session: &mut dyn ShellSession) -> Result<()> {
+    pipe(session, std::io::stdin(), std::io::stdout())
+}
+
+/// Pump bytes from `input` into the channel and from the channel into `output`
+/// until the remote side closes. Local EOF is forwarded as a channel EOF.
+pub fn pipe<R, W>(session: &mut dyn ShellSession, input: R, output: W) -> Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    pump(session, input, output, None)
+}
+
+/// Like [`pipe`], but each time `interrupt` is raised (e.g. by a Ctrl+C handler) it is
+/// cleared and SIGINT is sent to the remote process. A second interrupt, or a server
+/// refusing the signal, stops the pump with an error.
+pub fn pipe_interruptible<R, W>(session: &mut dyn ShellSession, input: R, output: W, interrupt: &AtomicBool) -> Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    pump(session, input, output, Some(interrupt))
+}
+
+fn pump<R, W>(session: &mut dyn ShellSession, input: R, mut output: W, interrupt: Option<&AtomicBool>) -> Result<()>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    let input_rx = spawn_reader(input);
+    let mut buffer = [0u8; 32768];
+    let mut input_open = true;
+    let mut sent: u64 = 0;
+    let mut received: u64 = 0;
+    let mut last_progress = Instant::now();
+    let mut signalled = false;
+
+    loop {
+        let mut had_activity = false;
+
+        if interrupt.is_some_and(|flag| flag.swap(false, Ordering::SeqCst)) {
+            if signalled {
+                return Err(anyhow::anyhow!("Interrupted"));
+            }
+            debug!("Interrupt received, sending SIGINT to the remote process");
+            session.send_signal(Signal::Int).context("Interrupted")?;
+            signalled = true;
+        }
+
+        // Local input -> channel
+        while input_open {
+            match input_rx.try_recv() {
+                Ok(data) => {
+                    write_all(session, &data)?;
+                    sent += data.len() as u64;
+                    had_activity = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    debug!("Local input closed, sending EOF to channel");
+                    session.send_eof()?;
+                    input_open = false;
+                }
+            }
+        }
+
+        // Channel -> local output
+        let n = session.read(&mut buffer)?;
+        if n > 0 {
+            output.write_all(&buffer[..n]).context("Failed to write to output")?;
+            output.flush().context("Failed to flush output")?;
+            received += n as u64;
+            had_activity = true;
+        } else if session.is_eof() {
+            debug!("Channel reached EOF");
+            break;
+        }
+
+        if had_activity && last_progress.elapsed() >= PROGRESS_INTERVAL {
+            progress::emit(ProgressEvent::BytesTransferred { sent, received });
+            last_progress = Instant::now();
+        }
+
+        if !had_activity {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    progress::emit(ProgressEvent::BytesTransferred { sent, received });
+    Ok(())
+}
+
+/// Read `input` on a background thread since stdin reads cannot be polled portably.
+/// The channel disconnects when the reader hits EOF or an error.
+fn spawn_reader<R: Read + Send + 'static>(mut input: R) -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 32768];
+        loop {
+            match input.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(buffer[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("Error reading local input: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+/// Write the whole buffer, waiting while the channel applies backpressure
+pub fn write_all(session: &mut dyn ShellSession, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        match session.try_write(data)? {
+            WriteOutcome::Written(n) => data = &data[n..],
+            WriteOutcome::Blocked => {
+                if session.is_eof() {
+                    return Err(anyhow::anyhow!("Channel closed while writing"));
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Echoes everything written back to the reader and reports EOF once
+    /// the writer side has closed and the echo buffer is drained
+    #[derive(Debug, Default)]
+    struct LoopbackSession {
+        pending: Vec<u8>,
+        eof_sent: bool,
+        max_write: Option<usize>,
+    }
+
+    impl ShellSession for LoopbackSession {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = std::cmp::min(buf.len(), self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<usize> {
+            let n = self.max_write.map_or(data.len(), |max| std::cmp::min(max, data.len()));
+            self.pending.extend_from_slice(&data[..n]);
+            Ok(n)
+        }
+
+        fn send_eof(&mut self) -> Result<()> {
+            self.eof_sent = true;
+            Ok(())
+        }
+
+        fn is_eof(&self) -> bool {
+            self.eof_sent && self.pending.is_empty()
+        }
+
+        fn wait_close(&mut self) -> Result<i32> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_pipe_round_trip() {
+        let mut session = LoopbackSession::default();
+        let mut output = Vec::new();
+
+        let result = pipe(&mut session, Cursor::new(b"SSH-2.0-OpenSSH\r\n".to_vec()), &mut output);
+
+        assert!(result.is_ok());
+        assert_eq!(output, b"SSH-2.0-OpenSSH\r\n");
+        assert!(session.eof_sent);
+    }
+
+    #[test]
+    fn test_pipe_binary_data_unmodified() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(100_000).collect();
+        let mut session = LoopbackSession::default();
+        let mut output = Vec::new();
+
+        pipe(&mut session, Cursor::new(data.clone()), &mut output).unwrap();
+
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_pipe_handles_partial_writes() {
+        let mut session = LoopbackSession {
+            max_write: Some(3),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+
+        pipe(&mut session, Cursor::new(b"partial writes".to_vec()), &mut output).unwrap();
+
+        assert_eq!(output, b"partial writes");
+    }
+
+    #[test]
+    fn test_interrupt_sends_sigint() {
+        use crate::testing::{Script, ScriptedShellSession};
+
+        let script = Script::new().send("compiling...\n").delay(Duration::from_secs(30)).eof();
+        let mut session = ScriptedShellSession::new(script, Default::default());
+        let interrupt = AtomicBool::new(true);
+        let mut output = Vec::new();
+
+        pipe_interruptible(&mut session, Cursor::new(Vec::new()), &mut output, &interrupt).unwrap();
+
+        assert_eq!(session.signals_received(), [Signal::Int]);
+        assert_eq!(session.wait_close().unwrap(), 0);
+        assert_eq!(session.exit_signal().unwrap().name, "INT");
+        assert!(!interrupt.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_interrupt_fails_when_signal_is_refused() {
+        let mut session = LoopbackSession::default();
+        let interrupt = AtomicBool::new(true);
+        let err = pipe_interruptible(&mut session, Cursor::new(Vec::new()), Vec::new(), &interrupt).unwrap_err();
+        assert!(format!("{:#}", err).contains("Cannot send SIGINT"));
+    }
+
+    #[test]
+    fn test_pipe_empty_input_sends_eof() {
+        let mut session = LoopbackSession::default();
+        let mut output = Vec::new();
+
+        pipe(&mut session, Cursor::new(Vec::new()), &mut output).unwrap();
+
+        assert!(output.is_empty());
+        assert!(session.eof_sent);
+    }
+}
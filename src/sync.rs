@@ -0,0 +1,368 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Files of the bxssh directories that make up a user's environment. The per-key
+/// files of the key store travel together as one `keys.json` object.
+pub const BUNDLED_FILES: &[&str] = &["keys.json", "profiles.toml", "known_hosts"];
+
+#[cfg(not(target_arch = "wasm32"))]
+const KEYS: &str = "keys.json";
+const BUNDLE_FORMAT: &str = "bxssh-sync";
+const BUNDLE_VERSION: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = 310_000;
+/// Reject bundles whose KDF is too weak to be one of ours
+const MIN_PBKDF2_ITERATIONS: u32 = 1_000;
+/// Reject bundles whose KDF would keep the CPU busy for minutes before the MAC is checked
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// Decrypted bundle contents: file name -> file contents
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncPayload {
+    pub files: BTreeMap<String, String>,
+}
+
+/// On-disk bundle: PBKDF2-HMAC-SHA256 key derivation, AES-256-CTR, HMAC-SHA256 over iv||ciphertext
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncBundle {
+    format: String,
+    version: u32,
+    iterations: u32,
+    salt: String,
+    iv: String,
+    ciphertext: String,
+    mac: String,
+}
+
+/// Encrypt a payload into a JSON bundle protected by `passphrase`
+pub fn encrypt(payload: &SyncPayload, passphrase: &str) -> Result<String> {
+    encrypt_with_iterations(payload, passphrase, PBKDF2_ITERATIONS)
+}
+
+pub(crate) fn encrypt_with_iterations(payload: &SyncPayload, passphrase: &str, iterations: u32) -> Result<String> {
+    if passphrase.is_empty() {
+        return Err(anyhow::anyhow!("Passphrase cannot be empty"));
+    }
+
+    let mut salt = [0u8; 16];
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut iv);
+
+    let (enc_key, mac_key) = derive_keys(passphrase, &salt, iterations);
+
+    let mut ciphertext = serde_json::to_vec(payload).context("Failed to serialize sync payload")?;
+    Aes256Ctr::new(&enc_key.into(), &iv.into()).apply_keystream(&mut ciphertext);
+
+    let mut mac = HmacSha256::new_from_slice(&mac_key).expect("HMAC accepts any key length");
+    mac.update(&iv);
+    mac.update(&ciphertext);
+
+    let bundle = SyncBundle {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        iterations,
+        salt: general_purpose::STANDARD.encode(salt),
+        iv: general_purpose::STANDARD.encode(iv),
+        ciphertext: general_purpose::STANDARD.encode(&ciphertext),
+        mac: general_purpose::STANDARD.encode(mac.finalize().into_bytes()),
+    };
+
+    serde_json::to_string_pretty(&bundle).context("Failed to serialize sync bundle")
+}
+
+/// Decrypt a bundle produced by [`encrypt`]
+pub fn decrypt(bundle: &str, passphrase: &str) -> Result<SyncPayload> {
+    let bundle: SyncBundle = serde_json::from_str(bundle).context("Not a bxssh sync bundle")?;
+
+    if bundle.format != BUNDLE_FORMAT {
+        return Err(anyhow::anyhow!("Not a bxssh sync bundle"));
+    }
+    if bundle.version != BUNDLE_VERSION {
+        return Err(anyhow::anyhow!("Unsupported sync bundle version {}", bundle.version));
+    }
+    if bundle.iterations < MIN_PBKDF2_ITERATIONS {
+        return Err(anyhow::anyhow!("Sync bundle key derivation is too weak"));
+    }
+    if bundle.iterations > MAX_PBKDF2_ITERATIONS {
+        return Err(anyhow::anyhow!("Sync bundle key derivation is unreasonably slow"));
+    }
+
+    let decode = |field: &str, value: &str| {
+        general_purpose::STANDARD
+            .decode(value)
+            .with_context(|| format!("Corrupt sync bundle field '{}'", field))
+    };
+    let salt = decode("salt", &bundle.salt)?;
+    let iv: [u8; 16] = decode("iv", &bundle.iv)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Corrupt sync bundle field 'iv'"))?;
+    let mut ciphertext = decode("ciphertext", &bundle.ciphertext)?;
+    let expected_mac = decode("mac", &bundle.mac)?;
+
+    let (enc_key, mac_key) = derive_keys(passphrase, &salt, bundle.iterations);
+
+    let mut mac = HmacSha256::new_from_slice(&mac_key).expect("HMAC accepts any key length");
+    mac.update(&iv);
+    mac.update(&ciphertext);
+    mac.verify_slice(&expected_mac)
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase or corrupted sync bundle"))?;
+
+    Aes256Ctr::new(&enc_key.into(), &iv.into()).apply_keystream(&mut ciphertext);
+    serde_json::from_slice(&ciphertext).context("Failed to parse sync payload")
+}
+
+fn derive_keys(passphrase: &str, salt: &[u8], iterations: u32) -> ([u8; 32], [u8; 32]) {
+    let mut okm = [0u8; 64];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut okm);
+
+    let mut enc_key = [0u8; 32];
+    let mut mac_key = [0u8; 32];
+    enc_key.copy_from_slice(&okm[..32]);
+    mac_key.copy_from_slice(&okm[32..]);
+    (enc_key, mac_key)
+}
+
+/// What [`restore`] did with each bundled entry
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default, PartialEq)]
+pub struct RestoreSummary {
+    pub written: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// Gather the bundled files that exist in `dirs`
+#[cfg(not(target_arch = "wasm32"))]
+pub fn collect(dirs: &crate::paths::Dirs) -> Result<SyncPayload> {
+    let mut payload = SyncPayload::default();
+
+    let keys = crate::key_manager::KeyStore::open(&dirs.data)?.load()?;
+    if !keys.is_empty() {
+        let content = serde_json::to_string_pretty(&keys).context("Failed to serialize keys")?;
+        payload.files.insert(KEYS.to_string(), content);
+    }
+
+    for name in BUNDLED_FILES.iter().filter(|name| **name != KEYS) {
+        let path = dirs.dir_for(name).join(name);
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            payload.files.insert(name.to_string(), content);
+        }
+    }
+
+    Ok(payload)
+}
+
+/// Write a payload into `dirs`. Keys go to the key store and are merged by name; other
+/// files are only replaced when `force` is set. Unknown file names are never written.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn restore(payload: &SyncPayload, dirs: &crate::paths::Dirs, force: bool) -> Result<RestoreSummary> {
+    let mut summary = RestoreSummary::default();
+
+    for (name, content) in &payload.files {
+        if !BUNDLED_FILES.contains(&name.as_str()) {
+            log::warn!("Ignoring unexpected file '{}' in sync bundle", name);
+            summary.skipped.push(name.clone());
+            continue;
+        }
+
+        let dir = dirs.dir_for(name);
+        let path = dir.join(name);
+        if name == KEYS {
+            restore_keys(&dirs.data, content, force, &mut summary)?;
+        } else if path.exists() && !force {
+            summary.skipped.push(name.clone());
+        } else {
+            crate::private_file::create_dir(dir)?;
+            write_private(&path, content)?;
+            summary.written.push(name.clone());
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Write each bundled key to the key store under the name it carries, which is the
+/// one the store loads it by; into an empty store the bundle is restored whole,
+/// otherwise keys already there are kept unless `force` is set
+#[cfg(not(target_arch = "wasm32"))]
+fn restore_keys(dir: &std::path::Path, incoming: &str, force: bool, summary: &mut RestoreSummary) -> Result<()> {
+    let store = crate::key_manager::KeyStore::open(dir)?;
+    let merging = !store.load()?.is_empty();
+    let incoming: BTreeMap<String, serde_json::Value> =
+        serde_json::from_str(incoming).context("Failed to parse keys in sync bundle")?;
+
+    for (entry_name, key) in incoming {
+        let Some(name) = key.get("name").and_then(|name| name.as_str()).map(str::to_string) else {
+            log::warn!("Skipping key '{}' in sync bundle: no key name", entry_name);
+            summary.skipped.push(format!("key '{}'", entry_name));
+            continue;
+        };
+        let entry = format!("key '{}'", name);
+        if merging && store.contains(&name) && !force {
+            summary.skipped.push(entry);
+        } else {
+            store.save(&name, &key)?;
+            if merging {
+                summary.written.push(entry);
+            }
+        }
+    }
+    if !merging {
+        summary.written.push(KEYS.to_string());
+    }
+    Ok(())
+}
+
+/// Write a file readable only by the current user, since bundles carry private keys
+#[cfg(not(target_arch = "wasm32"))]
+fn write_private(path: &std::path::Path, content: &str) -> Result<()> {
+    crate::private_file::write(path, content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ITERATIONS: u32 = 1_000;
+
+    fn sample_payload() -> SyncPayload {
+        let mut payload = SyncPayload::default();
+        payload.files.insert(
+            "keys.json".to_string(),
+            r#"{"laptop":{"name":"laptop"}}"#.to_string(),
+        );
+        payload.files.insert("known_hosts".to_string(), "example.com ssh-ed25519 AAAA\n".to_string());
+        payload
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_round_trip() {
+        let payload = sample_payload();
+        let bundle = encrypt_with_iterations(&payload, "correct horse", TEST_ITERATIONS).unwrap();
+
+        assert!(!bundle.contains("laptop"));
+        assert_eq!(decrypt(&bundle, "correct horse").unwrap(), payload);
+    }
+
+    #[test]
+    fn test_decrypt_wrong_passphrase() {
+        let bundle = encrypt_with_iterations(&sample_payload(), "correct horse", TEST_ITERATIONS).unwrap();
+
+        let result = decrypt(&bundle, "battery staple");
+        assert!(result.unwrap_err().to_string().contains("Wrong passphrase"));
+    }
+
+    #[test]
+    fn test_decrypt_detects_tampering() {
+        let bundle = encrypt_with_iterations(&sample_payload(), "pw", TEST_ITERATIONS).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&bundle).unwrap();
+        let mut ciphertext = general_purpose::STANDARD
+            .decode(value["ciphertext"].as_str().unwrap())
+            .unwrap();
+        ciphertext[0] ^= 1;
+        value["ciphertext"] = general_purpose::STANDARD.encode(ciphertext).into();
+
+        assert!(decrypt(&value.to_string(), "pw").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_rejects_foreign_input() {
+        assert!(decrypt("not json", "pw").is_err());
+        assert!(decrypt(r#"{"format":"other"}"#, "pw").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_rejects_excessive_iterations() {
+        let bundle = encrypt_with_iterations(&sample_payload(), "pw", TEST_ITERATIONS).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&bundle).unwrap();
+        value["iterations"] = (MAX_PBKDF2_ITERATIONS + 1).into();
+
+        let err = decrypt(&value.to_string(), "pw").unwrap_err();
+        assert!(err.to_string().contains("unreasonably slow"));
+    }
+
+    #[test]
+    fn test_encrypt_empty_passphrase() {
+        assert!(encrypt(&sample_payload(), "").is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_collect_and_restore() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(source.path().join("keys")).unwrap();
+        std::fs::write(source.path().join("keys").join("a.json"), r#"{"name":"a"}"#).unwrap();
+        std::fs::write(source.path().join("unrelated.txt"), "ignored").unwrap();
+
+        let payload = collect(&crate::paths::Dirs::single(source.path().to_path_buf())).unwrap();
+        assert_eq!(payload.files.len(), 1);
+        let keys: serde_json::Value = serde_json::from_str(&payload.files["keys.json"]).unwrap();
+        assert_eq!(keys, serde_json::json!({"a": {"name": "a"}}));
+
+        let target = tempfile::TempDir::new().unwrap();
+        let summary = restore(&payload, &crate::paths::Dirs::single(target.path().to_path_buf()), false).unwrap();
+        assert_eq!(summary.written, vec!["keys.json".to_string()]);
+        let key: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(target.path().join("keys").join("a.json")).unwrap())
+                .unwrap();
+        assert_eq!(key, serde_json::json!({"name": "a"}));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_restore_merges_keys_and_respects_force() {
+        let target = tempfile::TempDir::new().unwrap();
+        std::fs::write(target.path().join("keys.json"), r#"{"a":{"name":"a","v":1}}"#).unwrap();
+        std::fs::write(target.path().join("known_hosts"), "old\n").unwrap();
+
+        let mut payload = SyncPayload::default();
+        payload.files.insert(
+            "keys.json".to_string(),
+            r#"{"a":{"name":"a","v":2},"b":{"name":"b"}}"#.to_string(),
+        );
+        payload.files.insert("known_hosts".to_string(), "new\n".to_string());
+        payload.files.insert("../escape".to_string(), "x".to_string());
+
+        let dirs = crate::paths::Dirs::single(target.path().to_path_buf());
+        let summary = restore(&payload, &dirs, false).unwrap();
+        assert_eq!(summary.written, vec!["key 'b'".to_string()]);
+        assert!(summary.skipped.contains(&"key 'a'".to_string()));
+        assert!(summary.skipped.contains(&"known_hosts".to_string()));
+        assert!(summary.skipped.contains(&"../escape".to_string()));
+
+        let keys = crate::key_manager::KeyStore::open(target.path()).unwrap().load().unwrap();
+        assert_eq!(keys["a"]["v"], 1);
+        assert_eq!(keys["b"]["name"], "b");
+
+        restore(&payload, &dirs, true).unwrap();
+        assert_eq!(std::fs::read_to_string(target.path().join("known_hosts")).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn test_restore_stores_keys_under_their_own_name() {
+        let target = tempfile::TempDir::new().unwrap();
+        let mut payload = SyncPayload::default();
+        payload.files.insert(
+            "keys.json".to_string(),
+            r#"{"old":{"name":"renamed","v":1},"bare":{"v":2}}"#.to_string(),
+        );
+
+        let dirs = crate::paths::Dirs::single(target.path().to_path_buf());
+        let summary = restore(&payload, &dirs, false).unwrap();
+        assert_eq!(summary.skipped, vec!["key 'bare'".to_string()]);
+
+        let store = crate::key_manager::KeyStore::open(target.path()).unwrap();
+        assert!(store.contains("renamed"));
+        assert_eq!(store.load().unwrap().keys().collect::<Vec<_>>(), vec!["renamed"]);
+    }
+}
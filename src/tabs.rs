@@ -0,0 +1,610 @@
+//! Several interactive sessions in one terminal (`bxssh tabs`), one tab per host.
+//! Ctrl+] starts a command: 1-9 switch to that tab, `n` and `p` to the next and
+//! previous one, `w` lists the sessions, and a second Ctrl+] sends it to the shell.
+//! `b` turns on broadcasting, which sends what is typed to every selected tab (`s`
+//! selects or deselects the active one), or to all of them when none is selected.
+//! Only the active tab's output reaches the screen; the others keep a scrollback
+//! that is replayed when they are switched to.
+use anyhow::Result;
+use log::{debug, info};
+use std::collections::VecDeque;
+
+use crate::ssh_client::{ShellSession, WriteOutcome};
+use crate::terminal::{Compositor, LineInput, TerminalIO};
+
+/// Ctrl+], the key that starts a tab command
+pub const TAB_PREFIX: u8 = 0x1d;
+
+/// Output kept per tab for redrawing it
+const SCROLLBACK_BYTES: usize = 64 * 1024;
+
+/// What a keystroke does in tab mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TabInput {
+    /// Input for the active tab's shell
+    Send(Vec<u8>),
+    /// Switch to the tab at this index
+    Switch(usize),
+    Next,
+    Previous,
+    /// Show the session list
+    List,
+    /// Turn sending input to several tabs on or off
+    ToggleBroadcast,
+    /// Add the active tab to the broadcast, or take it out
+    ToggleSelected,
+}
+
+/// Splits typed input into shell input and Ctrl+] commands, which may arrive in
+/// separate reads
+#[derive(Debug, Default)]
+pub struct TabKeys {
+    prefixed: bool,
+}
+
+impl TabKeys {
+    pub fn feed(&mut self, input: &[u8]) -> Vec<TabInput> {
+        let mut actions = Vec::new();
+        let mut send = Vec::new();
+        for &byte in input {
+            if !std::mem::take(&mut self.prefixed) {
+                if byte == TAB_PREFIX {
+                    self.prefixed = true;
+                } else {
+                    send.push(byte);
+                }
+                continue;
+            }
+            let action = match byte {
+                TAB_PREFIX => {
+                    send.push(TAB_PREFIX);
+                    continue;
+                }
+                b'1'..=b'9' => TabInput::Switch(usize::from(byte - b'1')),
+                b'n' => TabInput::Next,
+                b'p' => TabInput::Previous,
+                b'w' => TabInput::List,
+                b'b' => TabInput::ToggleBroadcast,
+                b's' => TabInput::ToggleSelected,
+                _ => {
+                    debug!("Ignoring unknown tab command {:?}", byte as char);
+                    continue;
+                }
+            };
+            if !send.is_empty() {
+                actions.push(TabInput::Send(std::mem::take(&mut send)));
+            }
+            actions.push(action);
+        }
+        if !send.is_empty() {
+            actions.push(TabInput::Send(send));
+        }
+        actions
+    }
+}
+
+/// The last output of a tab, trimmed to start on a line
+#[derive(Debug)]
+pub struct Scrollback {
+    data: VecDeque<u8>,
+    limit: usize,
+}
+
+impl Scrollback {
+    pub fn new(limit: usize) -> Self {
+        Self { data: VecDeque::new(), limit }
+    }
+
+    pub fn push(&mut self, output: &[u8]) {
+        self.data.extend(output);
+        if self.data.len() > self.limit {
+            self.data.drain(..self.data.len() - self.limit);
+            let line_start = self.data.iter().position(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+            self.data.drain(..line_start);
+        }
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.data.iter().copied().collect()
+    }
+}
+
+struct Tab {
+    name: String,
+    session: Box<dyn ShellSession>,
+    scrollback: Scrollback,
+    pending_input: VecDeque<u8>,
+    /// Output arrived while another tab was shown
+    unseen: bool,
+    /// Receives broadcast input
+    selected: bool,
+    ended: bool,
+    /// The line being typed, when lines are edited locally
+    line: Option<String>,
+    /// Ctrl+D ended the edited input; EOF is sent once the queued input is
+    eof_pending: bool,
+}
+
+/// Runs several sessions on one terminal, showing one at a time
+pub struct TabbedSession {
+    tabs: Vec<Tab>,
+    active: usize,
+    terminal_io: Box<dyn TerminalIO>,
+    compositor: Compositor,
+    keys: TabKeys,
+    /// The session list is drawn over the active tab
+    listing: bool,
+    /// Typed input goes to the selected tabs, not only the active one
+    broadcast: bool,
+}
+
+impl TabbedSession {
+    pub fn new(terminal_io: Box<dyn TerminalIO>, columns: u16) -> Self {
+        Self {
+            tabs: Vec::new(),
+            active: 0,
+            terminal_io,
+            compositor: Compositor::new(columns),
+            keys: TabKeys::default(),
+            listing: false,
+            broadcast: false,
+        }
+    }
+
+    /// Start with broadcasting on (`--broadcast`)
+    pub fn with_broadcast(mut self, broadcast: bool) -> Self {
+        self.broadcast = broadcast;
+        self
+    }
+
+    /// Add a tab titled `name`; the first one added is shown first. With `line_editing`,
+    /// lines typed into it are edited here and sent on Enter, for a shell without a remote PTY.
+    pub fn with_tab(mut self, name: impl Into<String>, session: Box<dyn ShellSession>, line_editing: bool) -> Self {
+        self.tabs.push(Tab {
+            name: name.into(),
+            session,
+            scrollback: Scrollback::new(SCROLLBACK_BYTES),
+            pending_input: VecDeque::new(),
+            unseen: false,
+            selected: false,
+            ended: false,
+            line: line_editing.then(String::new),
+            eof_pending: false,
+        });
+        self
+    }
+
+    /// `[1 web]  2 db*  3 cache (ended)`: the active tab in brackets, `*` on tabs with
+    /// output not yet seen, `+` on tabs selected for broadcasting and `BROADCAST` first while it is on
+    pub fn tab_bar(&self) -> String {
+        let labels: Vec<String> = self
+            .tabs
+            .iter()
+            .enumerate()
+            .map(|(index, tab)| {
+                let mut label = format!("{} {}", index + 1, tab.name);
+                if tab.selected {
+                    label.push('+');
+                }
+                if tab.unseen {
+                    label.push('*');
+                }
+                if tab.ended {
+                    label.push_str(" (ended)");
+                }
+                if index == self.active {
+                    label = format!("[{}]", label);
+                }
+                label
+            })
+            .collect();
+        let bar = labels.join("  ");
+        if self.broadcast {
+            format!("BROADCAST  {}", bar)
+        } else {
+            bar
+        }
+    }
+
+    /// Tabs typed input goes to: with broadcasting on, the live selected tabs, or every
+    /// live tab when none is selected; otherwise the active one
+    fn input_targets(&self) -> Vec<usize> {
+        if !self.broadcast {
+            return vec![self.active];
+        }
+        let live = || (0..self.tabs.len()).filter(|&index| !self.tabs[index].ended);
+        let selected: Vec<usize> = live().filter(|&index| self.tabs[index].selected).collect();
+        if selected.is_empty() {
+            live().collect()
+        } else {
+            selected
+        }
+    }
+
+    /// Run until every tab's session has ended or the terminal is closed
+    pub fn run(&mut self) -> Result<()> {
+        self.terminal_io.initialize()?;
+        let result = self.session_loop();
+        let _ = self.terminal_io.write_output(crate::latency::hide_status_line().as_bytes());
+        self.terminal_io.cleanup()?;
+        result
+    }
+
+    fn session_loop(&mut self) -> Result<()> {
+        let mut buffer = vec![0u8; crate::config::DEFAULT_READ_BUFFER_SIZE];
+        info!("Starting tabbed session with {} tabs", self.tabs.len());
+        self.draw_bar()?;
+        while self.terminal_io.should_continue() {
+            let mut had_activity = false;
+            if let Some(input) = self.terminal_io.read_input()? {
+                had_activity = true;
+                self.handle_input(&input)?;
+            }
+            for index in 0..self.tabs.len() {
+                had_activity |= self.pump(index, &mut buffer)?;
+            }
+            if self.tabs[self.active].ended {
+                match self.next_live(self.active, 1) {
+                    Some(next) => self.switch(next)?,
+                    None => break,
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(if had_activity { 5 } else { 10 }));
+        }
+        info!("Tabbed session completed");
+        Ok(())
+    }
+
+    fn handle_input(&mut self, input: &[u8]) -> Result<()> {
+        if std::mem::take(&mut self.listing) {
+            // A digit picks a session from the list; any other key just closes it
+            let chosen = match input {
+                [digit @ b'1'..=b'9'] => usize::from(digit - b'1'),
+                _ => self.active,
+            };
+            return self.switch(chosen);
+        }
+        for action in self.keys.feed(input) {
+            match action {
+                TabInput::Send(data) => {
+                    for index in self.input_targets() {
+                        self.send(index, &data)?;
+                    }
+                }
+                TabInput::Switch(index) => self.switch(index)?,
+                TabInput::Next => {
+                    if let Some(next) = self.next_live(self.active, 1) {
+                        self.switch(next)?;
+                    }
+                }
+                TabInput::Previous => {
+                    if let Some(previous) = self.next_live(self.active, self.tabs.len() - 1) {
+                        self.switch(previous)?;
+                    }
+                }
+                TabInput::List => {
+                    let lines: Vec<String> = self
+                        .tabs
+                        .iter()
+                        .enumerate()
+                        .map(|(index, tab)| {
+                            let state = if tab.ended { "ended" } else if tab.unseen { "new output" } else { "" };
+                            let selected = if tab.selected { "+" } else { " " };
+                            format!("{}{} {}  {}", index + 1, selected, tab.name, state)
+                        })
+                        .collect();
+                    self.terminal_io.write_output(&self.compositor.overlay("bxssh sessions", &lines))?;
+                    self.listing = true;
+                }
+                TabInput::ToggleBroadcast => {
+                    self.broadcast = !self.broadcast;
+                    info!("Broadcasting input {}", if self.broadcast { "on" } else { "off" });
+                    self.draw_bar()?;
+                }
+                TabInput::ToggleSelected => {
+                    let tab = &mut self.tabs[self.active];
+                    tab.selected = !tab.selected;
+                    self.draw_bar()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue typed `data` for tab `index`, echoing it there first when its lines are edited locally
+    fn send(&mut self, index: usize, data: &[u8]) -> Result<()> {
+        let shown = index == self.active;
+        let tab = &mut self.tabs[index];
+        let Some(line) = &mut tab.line else {
+            tab.pending_input.extend(data);
+            return Ok(());
+        };
+        let (echo, send) = crate::terminal::edit_line(line, data);
+        match send {
+            Some(LineInput::Line(line)) => tab.pending_input.extend(line),
+            Some(LineInput::Eof) => tab.eof_pending = true,
+            None => {}
+        }
+        if !echo.is_empty() {
+            tab.scrollback.push(echo.as_bytes());
+            if shown {
+                self.terminal_io.write_output(echo.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The first tab after `from`, stepping by `step`, whose session is still open
+    fn next_live(&self, from: usize, step: usize) -> Option<usize> {
+        (1..=self.tabs.len())
+            .map(|offset| (from + offset * step) % self.tabs.len())
+            .find(|&index| !self.tabs[index].ended)
+    }
+
+    /// Show tab `index` with its scrollback; out of range or ended tabs only redraw the current one
+    fn switch(&mut self, index: usize) -> Result<()> {
+        if index < self.tabs.len() && !self.tabs[index].ended {
+            debug!("Switching to tab {} ({})", index + 1, self.tabs[index].name);
+            self.active = index;
+        }
+        let tab = &mut self.tabs[self.active];
+        tab.unseen = false;
+        self.terminal_io.write_output(&self.compositor.redraw(&tab.scrollback.contents()))?;
+        self.draw_bar()
+    }
+
+    fn draw_bar(&mut self) -> Result<()> {
+        let bar = crate::latency::status_line(&format!("bxssh  {}", self.tab_bar()));
+        self.terminal_io.write_output(bar.as_bytes())
+    }
+
+    /// Send tab `index` its queued input and take its output; returns whether anything moved
+    fn pump(&mut self, index: usize, buffer: &mut [u8]) -> Result<bool> {
+        let shown = index == self.active && !self.listing;
+        let tab = &mut self.tabs[index];
+        if tab.ended {
+            return Ok(false);
+        }
+        let mut moved = false;
+        while !tab.pending_input.is_empty() {
+            match tab.session.try_write(tab.pending_input.make_contiguous()) {
+                Ok(WriteOutcome::Written(n)) => {
+                    tab.pending_input.drain(..n);
+                    moved = true;
+                }
+                Ok(WriteOutcome::Blocked) => break,
+                Err(e) => {
+                    debug!("Write to tab {} failed: {:#}", tab.name, e);
+                    tab.ended = true;
+                    break;
+                }
+            }
+        }
+        if tab.eof_pending && tab.pending_input.is_empty() && !tab.ended {
+            tab.eof_pending = false;
+            moved = true;
+            if let Err(e) = tab.session.send_eof() {
+                debug!("Sending EOF to tab {} failed: {:#}", tab.name, e);
+                tab.ended = true;
+            }
+        }
+        let mut newly_unseen = false;
+        while !tab.ended {
+            match tab.session.read(buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    moved = true;
+                    // Without a remote PTY nothing turns newlines into the CRLF a raw terminal needs
+                    let output = match tab.line {
+                        Some(_) => crate::terminal::with_carriage_returns(&buffer[..n]),
+                        None => buffer[..n].to_vec(),
+                    };
+                    tab.scrollback.push(&output);
+                    if shown {
+                        self.terminal_io.write_output(&output)?;
+                    } else {
+                        newly_unseen |= !std::mem::replace(&mut tab.unseen, true);
+                    }
+                }
+                Err(e) => {
+                    let error = e.to_string();
+                    if !error.contains("WouldBlock") && !error.contains("Resource temporarily unavailable") {
+                        debug!("Read from tab {} failed: {:#}", tab.name, e);
+                        tab.ended = true;
+                    }
+                    break;
+                }
+            }
+        }
+        if !tab.ended && tab.session.is_eof() {
+            info!("Session in tab {} ended", tab.name);
+            tab.ended = true;
+        }
+        if tab.ended || newly_unseen {
+            self.draw_bar()?;
+        }
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::MockShellSession;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_tab_keys() {
+        let mut keys = TabKeys::default();
+        assert_eq!(keys.feed(b"ls\r"), [TabInput::Send(b"ls\r".to_vec())]);
+        assert_eq!(
+            keys.feed(b"a\x1d2b\x1dn\x1d\x1d"),
+            [
+                TabInput::Send(b"a".to_vec()),
+                TabInput::Switch(1),
+                TabInput::Send(b"b".to_vec()),
+                TabInput::Next,
+                TabInput::Send(vec![TAB_PREFIX]),
+            ]
+        );
+        // The prefix and its command may come in separate reads
+        assert!(keys.feed(b"\x1d").is_empty());
+        assert_eq!(keys.feed(b"w"), [TabInput::List]);
+        assert!(keys.feed(b"\x1dx").is_empty());
+        assert_eq!(keys.feed(b"\x1dp"), [TabInput::Previous]);
+    }
+
+    #[test]
+    fn test_broadcast_goes_to_selected_tabs() {
+        let typed: Vec<_> = (0..3).map(|_| Arc::new(Mutex::new(Vec::new()))).collect();
+        let inputs = [
+            b"\x1db".to_vec(),
+            b"uptime\r".to_vec(),
+            b"\x1d2\x1ds\x1d3\x1ds".to_vec(),
+            b"id\r".to_vec(),
+            b"\x1db\x1d1".to_vec(),
+            b"exit\r".to_vec(),
+        ];
+        let terminal = ScriptedTerminal { inputs: inputs.into(), output: Arc::new(Mutex::new(Vec::new())) };
+        let mut tabs = TabbedSession::new(Box::new(terminal), 80)
+            .with_tab("web1", shell(b"$ ", Arc::clone(&typed[0]), 10), false)
+            .with_tab("web2", shell(b"$ ", Arc::clone(&typed[1]), 10), false)
+            .with_tab("web3", shell(b"$ ", Arc::clone(&typed[2]), 10), false);
+        tabs.run().unwrap();
+
+        assert_eq!(&*typed[0].lock().unwrap(), b"uptime\rexit\r");
+        assert_eq!(&*typed[1].lock().unwrap(), b"uptime\rid\r");
+        assert_eq!(&*typed[2].lock().unwrap(), b"uptime\rid\r");
+        assert_eq!(tabs.tab_bar(), "[1 web1 (ended)]  2 web2+ (ended)  3 web3+ (ended)");
+    }
+
+    #[test]
+    fn test_scrollback_starts_on_a_line() {
+        let mut scrollback = Scrollback::new(10);
+        scrollback.push(b"one\r\n");
+        assert_eq!(scrollback.contents(), b"one\r\n");
+        scrollback.push(b"two\r\nthree\r\n");
+        assert_eq!(scrollback.contents(), b"three\r\n");
+    }
+
+    /// Replays `inputs`, then keeps the session going until the test's sessions end
+    struct ScriptedTerminal {
+        inputs: VecDeque<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl TerminalIO for ScriptedTerminal {
+        fn read_input(&mut self) -> Result<Option<Vec<u8>>> {
+            Ok(self.inputs.pop_front())
+        }
+
+        fn write_output(&mut self, data: &[u8]) -> Result<()> {
+            self.output.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+
+        fn should_continue(&self) -> bool {
+            true
+        }
+
+        fn initialize(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn cleanup(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A shell printing `output` once, recording what it is sent, and ending after `lives` polls
+    fn shell(output: &'static [u8], typed: Arc<Mutex<Vec<u8>>>, lives: usize) -> Box<dyn ShellSession> {
+        let mut session = MockShellSession::new();
+        let mut printed = false;
+        session.expect_read().returning(move |buf| {
+            if std::mem::replace(&mut printed, true) {
+                return Ok(0);
+            }
+            buf[..output.len()].copy_from_slice(output);
+            Ok(output.len())
+        });
+        session.expect_try_write().returning(move |data| {
+            typed.lock().unwrap().extend_from_slice(data);
+            Ok(WriteOutcome::Written(data.len()))
+        });
+        let mut polls = 0;
+        session.expect_is_eof().returning(move || {
+            polls += 1;
+            polls > lives
+        });
+        Box::new(session)
+    }
+
+    #[test]
+    fn test_tabbed_session_switches_and_ends_with_the_last_tab() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let (web_typed, db_typed) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let inputs = [b"uptime\r".to_vec(), b"\x1d2".to_vec(), b"\\dt\r".to_vec()];
+        let terminal = ScriptedTerminal { inputs: inputs.into(), output: Arc::clone(&output) };
+        let mut tabs = TabbedSession::new(Box::new(terminal), 80)
+            .with_tab("web", shell(b"web$ ", Arc::clone(&web_typed), 5), false)
+            .with_tab("db", shell(b"db=# ", Arc::clone(&db_typed), 10), false);
+        assert_eq!(tabs.tab_bar(), "[1 web]  2 db");
+        tabs.run().unwrap();
+
+        assert_eq!(&*web_typed.lock().unwrap(), b"uptime\r");
+        assert_eq!(&*db_typed.lock().unwrap(), b"\\dt\r");
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("1 web  [2 db]"));
+        // db's output was held back until its tab was shown, then replayed
+        let shown_db = output.find("\x1b[H\x1b[2Jdb=# ").unwrap();
+        assert!(output.find("web$ ").unwrap() < shown_db);
+        assert!(output.contains("1 web (ended)  [2 db]"));
+        assert!(output.ends_with(crate::latency::hide_status_line()));
+    }
+
+    #[test]
+    fn test_line_edited_tab_echoes_and_sends_whole_lines() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let typed = Arc::new(Mutex::new(Vec::new()));
+        let inputs = [b"l".to_vec(), b"x".to_vec(), b"\x7f".to_vec(), b"s".to_vec(), b"\r".to_vec()];
+        let terminal = ScriptedTerminal { inputs: inputs.into(), output: Arc::clone(&output) };
+        TabbedSession::new(Box::new(terminal), 80)
+            .with_tab("router", shell(b"a\nb\n", Arc::clone(&typed), 8), true)
+            .run()
+            .unwrap();
+
+        assert_eq!(&*typed.lock().unwrap(), b"ls\n");
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("a\r\nb\r\n"));
+        // The shell's output may land between the echoed keys
+        assert!(output.replace("a\r\nb\r\n", "").contains("lx\x08 \x08s\r\n"));
+    }
+
+    #[test]
+    fn test_line_edited_tab_sends_eof_on_ctrl_d() {
+        let typed = Arc::new(Mutex::new(Vec::new()));
+        let eof = Arc::new(Mutex::new(false));
+        let mut session = MockShellSession::new();
+        session.expect_read().returning(|_| Ok(0));
+        let sink = Arc::clone(&typed);
+        session.expect_try_write().returning(move |data| {
+            sink.lock().unwrap().extend_from_slice(data);
+            Ok(WriteOutcome::Written(data.len()))
+        });
+        let sent = Arc::clone(&eof);
+        session.expect_send_eof().times(1).returning(move || {
+            *sent.lock().unwrap() = true;
+            Ok(())
+        });
+        let ended = Arc::clone(&eof);
+        session.expect_is_eof().returning(move || *ended.lock().unwrap());
+
+        // Ctrl+D on a line with text in it is ignored, as in a shell
+        let inputs = [b"x".to_vec(), b"\x04".to_vec(), b"\r".to_vec(), b"\x04".to_vec()];
+        let terminal = ScriptedTerminal { inputs: inputs.into(), output: Arc::new(Mutex::new(Vec::new())) };
+        TabbedSession::new(Box::new(terminal), 80).with_tab("router", Box::new(session), true).run().unwrap();
+
+        assert_eq!(&*typed.lock().unwrap(), b"x\n");
+        assert!(*eof.lock().unwrap());
+    }
+}
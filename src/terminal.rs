@@ -19,6 +19,12 @@ pub trait TerminalIO: Send + Sync {
     
     /// Cleanup and restore terminal state
     fn cleanup(&mut self) -> Result<()>;
+
+    /// Whether the user ended their input since the last call, by Ctrl+D on an
+    /// empty locally edited line; the session then sends EOF to the server
+    fn take_eof(&mut self) -> bool {
+        false
+    }
 }
 
 /// WASM-compatible version without Send + Sync bounds
@@ -39,14 +45,153 @@ pub trait TerminalIO {
     
     /// Cleanup and restore terminal state
     fn cleanup(&mut self) -> Result<()>;
+
+    /// Whether the user ended their input since the last call, by Ctrl+D on an
+    /// empty locally edited line; the session then sends EOF to the server
+    fn take_eof(&mut self) -> bool {
+        false
+    }
+}
+
+/// Work done on the session loop's thread alongside an interactive session
+pub trait SessionHook {
+    /// Output the session just displayed
+    fn on_output(&mut self, _data: &[u8]) {}
+
+    /// Input to send to the server as if it had been typed
+    fn take_input(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Called on every loop iteration; returns whether anything happened
+    fn poll(&mut self) -> bool;
+}
+
+/// A hook that outlives one session, e.g. forwards kept across reconnects
+impl<T: SessionHook> SessionHook for std::rc::Rc<std::cell::RefCell<T>> {
+    fn on_output(&mut self, data: &[u8]) {
+        self.borrow_mut().on_output(data);
+    }
+
+    fn take_input(&mut self) -> Option<Vec<u8>> {
+        self.borrow_mut().take_input()
+    }
+
+    fn poll(&mut self) -> bool {
+        self.borrow_mut().poll()
+    }
+}
+
+/// Which keys bxssh keeps for itself during an interactive session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyBindings {
+    /// Ctrl+C ends the session
+    #[default]
+    Default,
+    /// Every key goes to the server, Ctrl+C included; the session ends with the remote shell
+    Passthrough,
+}
+
+/// Terminal settings for one host, from its profile's `Term`, `AltScreen`,
+/// `KeyBindings` and `Record`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalOverrides {
+    /// TERM sent with the PTY request instead of `xterm-256color`
+    pub term: Option<String>,
+    /// `Some(true)` runs the whole session on the alternate screen, `Some(false)`
+    /// keeps remote programs off it so their output stays in the scrollback
+    pub alt_screen: Option<bool>,
+    pub key_bindings: KeyBindings,
+    /// Save what the session displays (see `crate::recording`)
+    pub record: bool,
+    /// Echo and edit lines locally, set when the server gave the shell no PTY
+    pub local_line_editing: bool,
+}
+
+/// What an interactive session did, for the summary printed when it ends.
+/// [`SessionManager::stats`] fills in the duration and traffic; the connection
+/// fields come from the client.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionStats {
+    pub duration: std::time::Duration,
+    /// Keystrokes written to the channel
+    pub bytes_sent: u64,
+    /// Output read from the channel
+    pub bytes_received: u64,
+    /// Mean network round trip, when the transport measured one
+    pub average_latency: Option<std::time::Duration>,
+    /// Times the connection was re-established
+    pub reconnects: u32,
+    pub channels_opened: u32,
+}
+
+impl SessionStats {
+    /// Add the duration and traffic of `other`, a later part of the same session
+    pub fn add(&mut self, other: &SessionStats) {
+        self.duration += other.duration;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+    }
+
+    /// Arguments for [`crate::i18n::Msg::SessionStats`]: duration, sent, received,
+    /// channels, reconnects and latency
+    pub fn summary_args(&self) -> [String; 6] {
+        let latency = match self.average_latency {
+            Some(latency) => format!("{} ms", latency.as_millis()),
+            None => "-".to_string(),
+        };
+        [
+            format_duration(self.duration),
+            format_bytes(self.bytes_sent),
+            format_bytes(self.bytes_received),
+            self.channels_opened.to_string(),
+            self.reconnects.to_string(),
+            latency,
+        ]
+    }
+}
+
+/// `45s`, `12m 04s` or `3h 07m`
+pub(crate) fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+/// `512 B`, `3.2 KiB`, `1.1 MiB`, ...
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
 }
 
 /// Session manager that coordinates between SSH and Terminal I/O
 pub struct SessionManager {
     ssh_session: Box<dyn crate::ssh_client::ShellSession>,
     terminal_io: Box<dyn TerminalIO>,
+    read_buffer_size: usize,
+    hooks: Vec<Box<dyn SessionHook>>,
+    started: Option<std::time::Instant>,
+    duration: std::time::Duration,
+    bytes_sent: u64,
+    bytes_received: u64,
 }
 
+/// Upper bound on back-to-back channel reads merged into one terminal write
+const MAX_COALESCED_READS: usize = 16;
+
 impl SessionManager {
     pub fn new(
         ssh_session: Box<dyn crate::ssh_client::ShellSession>, 
@@ -55,24 +200,84 @@ impl SessionManager {
         Self {
             ssh_session,
             terminal_io,
+            read_buffer_size: crate::config::DEFAULT_READ_BUFFER_SIZE,
+            hooks: Vec::new(),
+            started: None,
+            duration: std::time::Duration::ZERO,
+            bytes_sent: 0,
+            bytes_received: 0,
         }
     }
+
+    /// Run `hook` for as long as the session
+    pub fn with_hook(mut self, hook: Box<dyn SessionHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Read up to `size` bytes from the channel per terminal write
+    pub fn with_read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = size.max(1);
+        self
+    }
     
+    /// The SSH session, e.g. to collect its exit status after `run_session`
+    pub fn into_session(self) -> Box<dyn crate::ssh_client::ShellSession> {
+        self.ssh_session
+    }
+
+    /// Duration and traffic of the session so far
+    pub fn stats(&self) -> SessionStats {
+        let running = self.started.map(|started| started.elapsed()).unwrap_or_default();
+        SessionStats {
+            duration: self.duration + running,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            ..Default::default()
+        }
+    }
+
     /// Run the interactive session loop
     pub fn run_session(&mut self) -> Result<()> {
         self.terminal_io.initialize()?;
         
+        let started = std::time::Instant::now();
+        self.started = Some(started);
         let result = self.session_loop();
+        self.started = None;
+        self.duration += started.elapsed();
         
         self.terminal_io.cleanup()?;
         result
     }
     
+    /// Top up `buf[..filled]` with whatever else the channel has ready, so output
+    /// emitted in many small chunks (progress bars) reaches the terminal in one write
+    fn coalesce_reads(&mut self, buf: &mut [u8], mut filled: usize) -> usize {
+        for _ in 1..MAX_COALESCED_READS {
+            if filled == buf.len() {
+                break;
+            }
+            match self.ssh_session.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) => {
+                    // Surfaces again on the next read once this output is shown
+                    log::debug!("SSH read error while coalescing output: {}", e);
+                    break;
+                }
+            }
+        }
+        filled
+    }
+
     fn session_loop(&mut self) -> Result<()> {
+        use crate::ssh_client::WriteOutcome;
         use log::{debug, info};
+        use std::collections::VecDeque;
         use std::time::{Duration, Instant};
         
-        let mut ssh_buffer = [0u8; 8192];
+        let mut ssh_buffer = vec![0u8; self.read_buffer_size];
         info!("Starting session loop");
         
         // Wait for initial prompt/output from SSH server
@@ -89,6 +294,10 @@ impl SessionManager {
                     info!("Received initial SSH output: {} bytes", n);
                     debug!("Initial output: {:?}", String::from_utf8_lossy(&ssh_buffer[..n]));
                     self.terminal_io.write_output(&ssh_buffer[..n])?;
+                    self.bytes_received += n as u64;
+                    for hook in &mut self.hooks {
+                        hook.on_output(&ssh_buffer[..n]);
+                    }
                     got_initial_output = true;
                     break;
                 }
@@ -113,33 +322,44 @@ impl SessionManager {
         // Main session loop
         let mut consecutive_empty_reads = 0;
         const MAX_EMPTY_READS: usize = 100;
+        let mut pending_input: VecDeque<u8> = VecDeque::new();
+        let mut eof_pending = false;
         
         while self.terminal_io.should_continue() {
             let mut had_activity = false;
             
-            // Handle user input -> SSH
+            // Handle user input -> SSH. Keystrokes are queued and flushed only as
+            // fast as the channel's window allows, so nothing is dropped under load.
             if let Some(input_data) = self.terminal_io.read_input()? {
-                if !input_data.is_empty() {
-                    debug!("Sending input to SSH: {:?}", String::from_utf8_lossy(&input_data));
-                    match self.ssh_session.write(&input_data) {
-                        Ok(bytes_written) => {
-                            debug!("Wrote {} bytes to SSH session", bytes_written);
-                            had_activity = true;
-                        }
-                        Err(e) => {
-                            // Handle flow control errors gracefully
-                            if e.to_string().contains("draining incoming flow") {
-                                debug!("SSH flow control error during input, continuing...");
-                                std::thread::sleep(std::time::Duration::from_millis(100));
-                                continue;
-                            } else {
-                                debug!("Failed to write to SSH session: {}", e);
-                                return Err(e);
-                            }
-                        }
+                pending_input.extend(input_data);
+            }
+            eof_pending |= self.terminal_io.take_eof();
+            for hook in &mut self.hooks {
+                if let Some(input_data) = hook.take_input() {
+                    pending_input.extend(input_data);
+                }
+            }
+            while !pending_input.is_empty() {
+                match self.ssh_session.try_write(pending_input.make_contiguous())? {
+                    WriteOutcome::Written(n) => {
+                        debug!("Wrote {} bytes to SSH session", n);
+                        pending_input.drain(..n);
+                        self.bytes_sent += n as u64;
+                        had_activity = true;
+                    }
+                    WriteOutcome::Blocked => {
+                        debug!("SSH channel window full, holding {} bytes of input", pending_input.len());
+                        break;
                     }
                 }
             }
+            // EOF goes only after everything typed before it
+            if eof_pending && pending_input.is_empty() {
+                debug!("Sending EOF to SSH session");
+                self.ssh_session.send_eof()?;
+                eof_pending = false;
+                had_activity = true;
+            }
             
             // Handle SSH output -> user display
             match self.ssh_session.read(&mut ssh_buffer) {
@@ -158,6 +378,8 @@ impl SessionManager {
                 }
                 Ok(n) => {
                     // Got data from SSH, display to user
+                    let n = self.coalesce_reads(&mut ssh_buffer, n);
+                    self.bytes_received += n as u64;
                     consecutive_empty_reads = 0;
                     had_activity = true;
                     debug!("Received {} bytes from SSH", n);
@@ -188,6 +410,9 @@ impl SessionManager {
                             // Don't return error, just log and continue
                         }
                     }
+                    for hook in &mut self.hooks {
+                        hook.on_output(&ssh_buffer[..n]);
+                    }
                 }
                 Err(e) => {
                     // Check if it's a would-block error (non-blocking I/O)
@@ -195,10 +420,6 @@ impl SessionManager {
                     if error_msg.contains("WouldBlock") || 
                        error_msg.contains("Resource temporarily unavailable") {
                         // Normal for non-blocking I/O
-                    } else if error_msg.contains("draining incoming flow") {
-                        debug!("SSH channel flow control, backing off...");
-                        std::thread::sleep(std::time::Duration::from_millis(50));
-                        continue;
                     } else {
                         debug!("SSH read error: {}", e);
                         return Err(e);
@@ -206,6 +427,10 @@ impl SessionManager {
                 }
             }
             
+            for hook in &mut self.hooks {
+                had_activity |= hook.poll();
+            }
+
             // Adaptive sleep based on activity
             if had_activity {
                 // High activity, shorter sleep for responsiveness
@@ -227,16 +452,112 @@ impl SessionManager {
     }
 }
 
+/// Draws bxssh's own output over a session's: a redraw of the screen from saved
+/// output, and a boxed overlay that leaves the session's cursor where it was
+pub struct Compositor {
+    columns: usize,
+}
+
+impl Compositor {
+    /// For a terminal `columns` wide
+    pub fn new(columns: u16) -> Self {
+        Self { columns: usize::from(columns).max(8) }
+    }
+
+    /// Clear the screen and replay `output` on it
+    pub fn redraw(&self, output: &[u8]) -> Vec<u8> {
+        let mut frame = b"\x1b[H\x1b[2J".to_vec();
+        frame.extend_from_slice(output);
+        frame
+    }
+
+    /// `lines` in a box titled `title` at the top left, cut to the terminal's width
+    pub fn overlay(&self, title: &str, lines: &[String]) -> Vec<u8> {
+        let clip = |text: &str, width: usize| -> String {
+            text.chars().filter(|c| !c.is_control()).take(width).collect()
+        };
+        let longest = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        let inner = longest.max(title.chars().count() + 2).min(self.columns - 4);
+        let title = clip(title, inner - 2);
+        let mut rows = vec![format!("┌─ {} {}┐", title, "─".repeat(inner - title.chars().count() - 1))];
+        for line in lines {
+            let line = clip(line, inner);
+            rows.push(format!("│ {}{} │", line, " ".repeat(inner - line.chars().count())));
+        }
+        rows.push(format!("└{}┘", "─".repeat(inner + 2)));
+
+        // Save the cursor, draw each row from the first column, then restore it
+        let mut frame = String::from("\x1b7");
+        for (row, text) in rows.iter().enumerate() {
+            frame.push_str(&format!("\x1b[{};1H{}", row + 1, text));
+        }
+        frame.push_str("\x1b8");
+        frame.into_bytes()
+    }
+}
+
+/// What a locally edited line sends to the server
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LineInput {
+    /// The finished line, on Enter
+    Line(Vec<u8>),
+    /// End of input, on Ctrl+D at an empty line
+    Eof,
+}
+
+/// Local line editing: what to echo for `input` and what to send on Enter or Ctrl+D.
+/// Backspace and Ctrl+U erase; other control keys and escape sequences are dropped.
+pub(crate) fn edit_line(line: &mut String, input: &[u8]) -> (String, Option<LineInput>) {
+    match input {
+        b"\r" | b"\n" => {
+            let sent = format!("{}\n", std::mem::take(line)).into_bytes();
+            ("\r\n".to_string(), Some(LineInput::Line(sent)))
+        }
+        b"\x04" if line.is_empty() => (String::new(), Some(LineInput::Eof)),
+        b"\x7f" | b"\x08" => match line.pop() {
+            Some(_) => ("\x08 \x08".to_string(), None),
+            None => (String::new(), None),
+        },
+        b"\x15" => ("\x08 \x08".repeat(std::mem::take(line).chars().count()), None),
+        b"\t" => {
+            line.push('\t');
+            ("\t".to_string(), None)
+        }
+        _ if input.first().is_some_and(u8::is_ascii_control) => (String::new(), None),
+        _ => {
+            let text = String::from_utf8_lossy(input);
+            line.push_str(&text);
+            (text.into_owned(), None)
+        }
+    }
+}
+
+/// `data` with a `\r` before each `\n` that lacks one
+pub(crate) fn with_carriage_returns(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            output.push(b'\r');
+        }
+        output.push(byte);
+    }
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ssh_client::MockShellSession;
+    use crate::ssh_client::{MockShellSession, WriteOutcome};
+    use std::collections::VecDeque;
     use std::sync::{Arc, Mutex};
     
     struct MockTerminalIO {
         input_data: Arc<Mutex<Vec<Vec<u8>>>>,
         output_data: Arc<Mutex<Vec<u8>>>,
+        output_writes: Arc<Mutex<usize>>,
         should_continue: Arc<Mutex<bool>>,
+        /// Report Ctrl+D along with the last input
+        eof_after_input: bool,
     }
     
     impl MockTerminalIO {
@@ -244,7 +565,9 @@ mod tests {
             Self {
                 input_data: Arc::new(Mutex::new(vec![])),
                 output_data: Arc::new(Mutex::new(vec![])),
+                output_writes: Arc::new(Mutex::new(0)),
                 should_continue: Arc::new(Mutex::new(true)),
+                eof_after_input: false,
             }
         }
         
@@ -276,6 +599,7 @@ mod tests {
         
         fn write_output(&mut self, data: &[u8]) -> Result<()> {
             self.output_data.lock().unwrap().extend_from_slice(data);
+            *self.output_writes.lock().unwrap() += 1;
             Ok(())
         }
         
@@ -290,6 +614,13 @@ mod tests {
         fn cleanup(&mut self) -> Result<()> {
             Ok(())
         }
+
+        fn take_eof(&mut self) -> bool {
+            if !self.input_data.lock().unwrap().is_empty() {
+                return false;
+            }
+            std::mem::take(&mut self.eof_after_input)
+        }
     }
     
     #[test]
@@ -336,4 +667,247 @@ mod tests {
         let result = manager.run_session();
         assert!(result.is_ok());
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_session_holds_input_while_blocked() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut mock_session = MockShellSession::new();
+        mock_session.expect_read().returning(|_| Ok(0));
+        let mut blocked_once = false;
+        let sink = Arc::clone(&written);
+        mock_session.expect_try_write().returning(move |data| {
+            if !blocked_once {
+                blocked_once = true;
+                return Ok(WriteOutcome::Blocked);
+            }
+            // Accept one byte at a time, like a nearly full window
+            sink.lock().unwrap().push(data[0]);
+            Ok(WriteOutcome::Written(1))
+        });
+        let done = Arc::clone(&written);
+        mock_session.expect_is_eof().returning(move || done.lock().unwrap().len() == 3);
+        
+        let mock_terminal = MockTerminalIO::new();
+        mock_terminal.add_input(b"ls\n".to_vec());
+        
+        let mut manager = SessionManager::new(Box::new(mock_session), Box::new(mock_terminal));
+        manager.run_session().unwrap();
+        
+        assert_eq!(written.lock().unwrap().as_slice(), b"ls\n");
+        assert_eq!(manager.stats().bytes_sent, 3);
+    }
+    
+    #[test]
+    fn test_session_sends_eof_after_queued_input() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut mock_session = MockShellSession::new();
+        mock_session.expect_read().returning(|_| Ok(0));
+        let sink = Arc::clone(&events);
+        mock_session.expect_try_write().returning(move |data| {
+            sink.lock().unwrap().push(String::from_utf8_lossy(data).into_owned());
+            Ok(WriteOutcome::Written(data.len()))
+        });
+        let sink = Arc::clone(&events);
+        mock_session.expect_send_eof().times(1).returning(move || {
+            sink.lock().unwrap().push("EOF".to_string());
+            Ok(())
+        });
+        let done = Arc::clone(&events);
+        mock_session.expect_is_eof().returning(move || done.lock().unwrap().len() == 2);
+
+        let mut mock_terminal = MockTerminalIO::new();
+        mock_terminal.add_input(b"exit\n".to_vec());
+        mock_terminal.eof_after_input = true;
+
+        let mut manager = SessionManager::new(Box::new(mock_session), Box::new(mock_terminal));
+        manager.run_session().unwrap();
+
+        assert_eq!(*events.lock().unwrap(), ["exit\n", "EOF"]);
+    }
+
+    /// A session that hands out `chunks` one read at a time, then reports EOF
+    fn chunked_session(chunks: &[&[u8]]) -> MockShellSession {
+        let mut queue: VecDeque<Vec<u8>> = chunks.iter().map(|c| c.to_vec()).collect();
+        let drained = Arc::new(Mutex::new(false));
+        let done = Arc::clone(&drained);
+        let mut session = MockShellSession::new();
+        session.expect_read().returning(move |buf| {
+            let Some(mut chunk) = queue.pop_front() else {
+                *done.lock().unwrap() = true;
+                return Ok(0);
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                queue.push_front(chunk.split_off(n));
+            }
+            Ok(n)
+        });
+        session.expect_is_eof().returning(move || *drained.lock().unwrap());
+        session
+    }
+
+    #[test]
+    fn test_session_coalesces_small_reads() {
+        let session = chunked_session(&[b"$ ", b"[#   ]", b"\r[##  ]", b"\r[### ]"]);
+        let mock_terminal = MockTerminalIO::new();
+        let output = Arc::clone(&mock_terminal.output_data);
+        let writes = Arc::clone(&mock_terminal.output_writes);
+        
+        let mut manager = SessionManager::new(Box::new(session), Box::new(mock_terminal));
+        manager.run_session().unwrap();
+        
+        // The first chunk is shown on its own while waiting for the prompt
+        assert_eq!(output.lock().unwrap().as_slice(), b"$ [#   ]\r[##  ]\r[### ]");
+        assert_eq!(*writes.lock().unwrap(), 2);
+    }
+    
+    #[test]
+    fn test_session_read_buffer_size_caps_each_write() {
+        let session = chunked_session(&[b"abcdef"]);
+        let mock_terminal = MockTerminalIO::new();
+        let output = Arc::clone(&mock_terminal.output_data);
+        let writes = Arc::clone(&mock_terminal.output_writes);
+        
+        let mut manager = SessionManager::new(Box::new(session), Box::new(mock_terminal)).with_read_buffer_size(4);
+        manager.run_session().unwrap();
+        
+        assert_eq!(output.lock().unwrap().as_slice(), b"abcdef");
+        assert_eq!(*writes.lock().unwrap(), 2);
+    }
+    
+    #[test]
+    fn test_session_with_scripted_shell() {
+        use crate::testing::{Script, ScriptedShellSession};
+        
+        let script = Script::new()
+            .send("$ ")
+            .expect("whoami\n")
+            .send("alice\n$ ")
+            .eof();
+        let input_log = Arc::new(Mutex::new(Vec::new()));
+        let session = ScriptedShellSession::new(script, Arc::clone(&input_log));
+        
+        let mock_terminal = MockTerminalIO::new();
+        mock_terminal.add_input(b"whoami\n".to_vec());
+        let output = Arc::clone(&mock_terminal.output_data);
+        
+        let mut manager = SessionManager::new(Box::new(session), Box::new(mock_terminal));
+        manager.run_session().unwrap();
+        
+        assert_eq!(output.lock().unwrap().as_slice(), b"$ alice\n$ ");
+        assert_eq!(input_log.lock().unwrap().as_slice(), b"whoami\n");
+    }
+
+    #[test]
+    fn test_session_hooks_see_output() {
+        struct Recorder(Arc<Mutex<(Vec<u8>, usize)>>);
+
+        impl SessionHook for Recorder {
+            fn on_output(&mut self, data: &[u8]) {
+                self.0.lock().unwrap().0.extend_from_slice(data);
+            }
+
+            fn poll(&mut self) -> bool {
+                self.0.lock().unwrap().1 += 1;
+                false
+            }
+        }
+
+        let seen = Arc::new(Mutex::new((Vec::new(), 0)));
+        let session = chunked_session(&[b"$ ", b"npm run dev\r\n", b"listening on port 3000\r\n"]);
+        let mut manager = SessionManager::new(Box::new(session), Box::new(MockTerminalIO::new()))
+            .with_hook(Box::new(Recorder(Arc::clone(&seen))));
+        manager.run_session().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.0, b"$ npm run dev\r\nlistening on port 3000\r\n");
+        assert!(seen.1 > 0);
+        assert_eq!(manager.stats().bytes_received, seen.0.len() as u64);
+    }
+
+    #[test]
+    fn test_session_sends_hook_input() {
+        struct Typist(Option<Vec<u8>>);
+
+        impl SessionHook for Typist {
+            fn take_input(&mut self) -> Option<Vec<u8>> {
+                self.0.take()
+            }
+
+            fn poll(&mut self) -> bool {
+                false
+            }
+        }
+
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut session = MockShellSession::new();
+        session.expect_read().returning(|_| Ok(0));
+        let sink = Arc::clone(&written);
+        session.expect_try_write().returning(move |data| {
+            sink.lock().unwrap().extend_from_slice(data);
+            Ok(WriteOutcome::Written(data.len()))
+        });
+        let done = Arc::clone(&written);
+        session.expect_is_eof().returning(move || !done.lock().unwrap().is_empty());
+
+        let terminal = MockTerminalIO::new();
+        terminal.add_input(b"id\n".to_vec());
+        let mut manager = SessionManager::new(Box::new(session), Box::new(terminal))
+            .with_hook(Box::new(Typist(Some(b"uptime\n".to_vec()))));
+        manager.run_session().unwrap();
+        assert_eq!(written.lock().unwrap().as_slice(), b"id\nuptime\n");
+    }
+
+    #[test]
+    fn test_session_stats_summary() {
+        let mut stats = SessionStats {
+            duration: std::time::Duration::from_secs(724),
+            bytes_sent: 3277,
+            bytes_received: 1_153_434,
+            average_latency: Some(std::time::Duration::from_micros(14_700)),
+            reconnects: 1,
+            channels_opened: 2,
+        };
+        assert_eq!(stats.summary_args(), ["12m 04s", "3.2 KiB", "1.1 MiB", "2", "1", "14 ms"]);
+
+        let later = SessionStats { duration: std::time::Duration::from_secs(3000), bytes_sent: 1, ..Default::default() };
+        stats.add(&later);
+        assert_eq!(stats.bytes_sent, 3278);
+        assert_eq!(SessionStats { duration: stats.duration, ..Default::default() }.summary_args(),
+            ["1h 02m", "0 B", "0 B", "0", "0", "-"]);
+        assert_eq!(format_duration(std::time::Duration::from_millis(45_900)), "45s");
+    }
+
+    #[test]
+    fn test_compositor_overlay() {
+        let compositor = Compositor::new(20);
+        let lines = ["1 web".to_string(), "2 a-very-long-host-name".to_string()];
+        let frame = String::from_utf8(compositor.overlay("Tabs", &lines)).unwrap();
+        assert!(frame.starts_with("\x1b7\x1b[1;1H┌─ Tabs ───────────┐"));
+        assert!(frame.contains("\x1b[2;1H│ 1 web            │"));
+        assert!(frame.contains("\x1b[3;1H│ 2 a-very-long-ho │"));
+        assert!(frame.ends_with("\x1b[4;1H└──────────────────┘\x1b8"));
+        assert_eq!(compositor.redraw(b"$ "), b"\x1b[H\x1b[2J$ ");
+    }
+
+    #[test]
+    fn test_edit_line() {
+        let mut line = String::new();
+        let mut echoed = String::new();
+        for key in ["l", "x", "\x7f", "s", "\x1b[A", "\x03"] {
+            let (echo, send) = edit_line(&mut line, key.as_bytes());
+            assert_eq!(send, None);
+            echoed.push_str(&echo);
+        }
+        assert_eq!(echoed, "lx\x08 \x08s");
+        assert_eq!(edit_line(&mut line, b"\x04"), (String::new(), None));
+        assert_eq!(edit_line(&mut line, b"\r"), ("\r\n".to_string(), Some(LineInput::Line(b"ls\n".to_vec()))));
+        assert!(line.is_empty());
+        assert_eq!(edit_line(&mut line, b"\x04"), (String::new(), Some(LineInput::Eof)));
+
+        line.push_str("rm -rf");
+        assert_eq!(edit_line(&mut line, b"\x15").0, "\x08 \x08".repeat(6));
+        assert!(line.is_empty());
+    }
+}
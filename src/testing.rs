@@ -0,0 +1,693 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ssh_client::{
+    CommandOutput, ExitSignal, RemoteFile, RemoteFileAttr, RemoteFileKind, SftpSession, ShellSession, Signal,
+    SshConnection,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Send(Vec<u8>),
+    Expect(Vec<u8>),
+    Delay(Duration),
+    Eof,
+    ExitStatus(i32),
+    ExitSignal(ExitSignal),
+}
+
+/// A sequence of server-side interactions, played back in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emit `data` to the client
+    pub fn send(mut self, data: impl AsRef<[u8]>) -> Self {
+        self.steps.push(Step::Send(data.as_ref().to_vec()));
+        self
+    }
+
+    /// Block the script until the client has written `data`; anything the client
+    /// wrote up to and including the match is consumed
+    pub fn expect(mut self, data: impl AsRef<[u8]>) -> Self {
+        self.steps.push(Step::Expect(data.as_ref().to_vec()));
+        self
+    }
+
+    /// Produce no output for `duration`
+    pub fn delay(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Delay(duration));
+        self
+    }
+
+    /// Set the exit status returned by `wait_close`
+    pub fn exit_status(mut self, status: i32) -> Self {
+        self.steps.push(Step::ExitStatus(status));
+        self
+    }
+
+    /// Report termination by signal `name` (without `SIG`) from `wait_close`
+    pub fn exit_signal(mut self, name: &str, core_dumped: bool) -> Self {
+        self.steps.push(Step::ExitSignal(ExitSignal {
+            name: name.to_string(),
+            core_dumped,
+            message: None,
+        }));
+        self
+    }
+
+    /// Close the channel once all earlier output has been read
+    pub fn eof(mut self) -> Self {
+        self.steps.push(Step::Eof);
+        self
+    }
+}
+
+/// Files served over SFTP, by absolute path
+type Files = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+/// In-memory fake server for deterministic tests: implements `SshConnection` and
+/// hands out sessions that play back a [`Script`] of output, expected input, delays and EOF
+#[derive(Debug, Default)]
+pub struct ScriptedSshServer {
+    passwords: HashMap<String, String>,
+    key_users: Vec<String>,
+    banner: Option<String>,
+    commands: HashMap<String, Script>,
+    forwards: HashMap<(String, u16), Script>,
+    shell: Option<Script>,
+    files: Files,
+    read_only: HashSet<String>,
+    refuse_connections: bool,
+    connected: bool,
+    authenticated: bool,
+    input_log: Arc<Mutex<Vec<u8>>>,
+}
+
+impl ScriptedSshServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept password authentication for `username`
+    pub fn accept_password(mut self, username: &str, password: &str) -> Self {
+        self.passwords.insert(username.to_string(), password.to_string());
+        self
+    }
+
+    /// Accept key authentication for `username` with any key
+    pub fn accept_key(mut self, username: &str) -> Self {
+        self.key_users.push(username.to_string());
+        self
+    }
+
+    /// Pre-auth banner returned by `auth_banner`
+    pub fn banner(mut self, banner: &str) -> Self {
+        self.banner = Some(banner.to_string());
+        self
+    }
+
+    /// Script played back for `command` via `open_exec`; its output also
+    /// answers `execute_command`
+    pub fn command(mut self, command: &str, script: Script) -> Self {
+        self.commands.insert(command.to_string(), script);
+        self
+    }
+
+    /// Script played back by a `direct-tcpip` channel to `host:port`, as the
+    /// service there would answer
+    pub fn forward(mut self, host: &str, port: u16, script: Script) -> Self {
+        self.forwards.insert((host.to_string(), port), script);
+        self
+    }
+
+    /// Script played back by `start_shell`
+    pub fn shell(mut self, script: Script) -> Self {
+        self.shell = Some(script);
+        self
+    }
+
+    /// Serve a file with `contents` at absolute `path` over SFTP; parent
+    /// directories exist implicitly
+    pub fn file(self, path: &str, contents: impl AsRef<[u8]>) -> Self {
+        self.files.lock().unwrap().insert(path.to_string(), contents.as_ref().to_vec());
+        self
+    }
+
+    /// Make creating the file at `path` over SFTP fail with permission denied,
+    /// as for a root-owned file
+    pub fn read_only(mut self, path: &str) -> Self {
+        self.read_only.insert(path.to_string());
+        self
+    }
+
+    /// Make `connect` fail, as for an unreachable host
+    pub fn refuse_connections(mut self) -> Self {
+        self.refuse_connections = true;
+        self
+    }
+
+    /// Everything clients have written to sessions from this server.
+    /// Grab the handle before boxing the server into an `SshClient`.
+    pub fn input_log(&self) -> Arc<Mutex<Vec<u8>>> {
+        Arc::clone(&self.input_log)
+    }
+
+    /// The files served over SFTP, including those clients created.
+    /// Grab the handle before boxing the server into an `SshClient`.
+    pub fn files(&self) -> Files {
+        Arc::clone(&self.files)
+    }
+
+    fn require_auth(&self) -> Result<()> {
+        if !self.authenticated {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+        Ok(())
+    }
+}
+
+impl SshConnection for ScriptedSshServer {
+    fn connect(&mut self, host: &str, port: u16) -> Result<()> {
+        if self.refuse_connections {
+            return Err(anyhow::anyhow!("Connection to {}:{} refused", host, port));
+        }
+        self.connected = true;
+        Ok(())
+    }
+
+    fn auth_banner(&mut self, _username: &str) -> Result<Option<String>> {
+        if !self.connected {
+            return Err(anyhow::anyhow!("Not connected"));
+        }
+        Ok(self.banner.clone())
+    }
+
+    fn authenticate_with_key(&mut self, username: &str, _private_key_path: &str) -> Result<()> {
+        if !self.connected {
+            return Err(anyhow::anyhow!("Not connected"));
+        }
+        if !self.key_users.iter().any(|u| u == username) {
+            return Err(anyhow::anyhow!("Key authentication rejected for {}", username));
+        }
+        self.authenticated = true;
+        Ok(())
+    }
+
+    fn authenticate_with_password(&mut self, username: &str, password: &str) -> Result<()> {
+        if !self.connected {
+            return Err(anyhow::anyhow!("Not connected"));
+        }
+        if self.passwords.get(username).map(String::as_str) != Some(password) {
+            return Err(anyhow::anyhow!("Password authentication rejected for {}", username));
+        }
+        self.authenticated = true;
+        Ok(())
+    }
+
+    fn execute_command(&self, command: &str) -> Result<String> {
+        let output = self.run_command(command)?;
+        match output.failure() {
+            Some(reason) => Err(anyhow::anyhow!("Command {}", reason)),
+            None => Ok(output.stdout),
+        }
+    }
+
+    fn run_command(&self, command: &str) -> Result<CommandOutput> {
+        self.require_auth()?;
+        let script = self
+            .commands
+            .get(command)
+            .ok_or_else(|| anyhow::anyhow!("No script for command '{}'", command))?;
+
+        let mut stdout = Vec::new();
+        let mut exit_status = 0;
+        let mut exit_signal = None;
+        for step in &script.steps {
+            match step {
+                Step::Send(data) => stdout.extend_from_slice(data),
+                Step::ExitStatus(status) => exit_status = *status,
+                Step::ExitSignal(signal) => exit_signal = Some(signal.clone()),
+                _ => {}
+            }
+        }
+        Ok(CommandOutput {
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            exit_status,
+            exit_signal,
+        })
+    }
+
+    fn start_shell(&self) -> Result<Box<dyn ShellSession>> {
+        self.require_auth()?;
+        let script = self.shell.clone().ok_or_else(|| anyhow::anyhow!("No shell script"))?;
+        Ok(Box::new(ScriptedShellSession::new(script, self.input_log())))
+    }
+
+    fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<Box<dyn ShellSession>> {
+        self.require_auth()?;
+        let script = self
+            .forwards
+            .get(&(host.to_string(), port))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Forwarding to {}:{} is not scripted", host, port))?;
+        Ok(Box::new(ScriptedShellSession::new(script, self.input_log())))
+    }
+
+    fn open_exec(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        self.require_auth()?;
+        let script = self
+            .commands
+            .get(command)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No script for command '{}'", command))?;
+        Ok(Box::new(ScriptedShellSession::new(script, self.input_log())))
+    }
+
+    fn open_exec_pty(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        self.open_exec(command)
+    }
+
+    fn open_sftp(&self) -> Result<Box<dyn SftpSession>> {
+        self.require_auth()?;
+        Ok(Box::new(ScriptedSftpSession { files: Arc::clone(&self.files), read_only: self.read_only.clone() }))
+    }
+
+    fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+}
+
+/// SFTP view of the files registered with [`ScriptedSshServer::file`]
+#[derive(Debug)]
+pub struct ScriptedSftpSession {
+    files: Files,
+    read_only: HashSet<String>,
+}
+
+fn not_found(path: &str) -> anyhow::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, format!("No such file: {}", path)).into()
+}
+
+impl ScriptedSftpSession {
+    fn attr(kind: RemoteFileKind, size: usize) -> RemoteFileAttr {
+        let perm = if kind == RemoteFileKind::Directory { 0o755 } else { 0o644 };
+        RemoteFileAttr { kind, size: size as u64, perm, mtime: 0 }
+    }
+
+    fn dir_prefix(path: &str) -> String {
+        format!("{}/", path.trim_end_matches('/'))
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        let prefix = Self::dir_prefix(path);
+        prefix == "/" || self.files.lock().unwrap().keys().any(|file| file.starts_with(&prefix))
+    }
+}
+
+impl SftpSession for ScriptedSftpSession {
+    fn stat(&mut self, path: &str) -> Result<RemoteFileAttr> {
+        if let Some(contents) = self.files.lock().unwrap().get(path) {
+            return Ok(Self::attr(RemoteFileKind::File, contents.len()));
+        }
+        if self.is_dir(path) {
+            return Ok(Self::attr(RemoteFileKind::Directory, 0));
+        }
+        Err(not_found(path))
+    }
+
+    fn read_dir(&mut self, path: &str) -> Result<Vec<(String, RemoteFileAttr)>> {
+        if !self.is_dir(path) {
+            return Err(anyhow::anyhow!("Not a directory: {}", path));
+        }
+
+        let prefix = Self::dir_prefix(path);
+        let mut entries = BTreeMap::new();
+        for (file, contents) in self.files.lock().unwrap().range(prefix.clone()..) {
+            let Some(rest) = file.strip_prefix(&prefix) else {
+                break;
+            };
+            let attr = match rest.split_once('/') {
+                Some((dir, _)) => (dir, Self::attr(RemoteFileKind::Directory, 0)),
+                None => (rest, Self::attr(RemoteFileKind::File, contents.len())),
+            };
+            entries.entry(attr.0.to_string()).or_insert(attr.1);
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn open_reader(&mut self, path: &str) -> Result<Box<dyn RemoteFile>> {
+        let contents = self.files.lock().unwrap().get(path).cloned().ok_or_else(|| not_found(path))?;
+        Ok(Box::new(std::io::Cursor::new(contents)))
+    }
+
+    fn create(&mut self, path: &str) -> Result<Box<dyn std::io::Write + Send>> {
+        if self.read_only.contains(path) {
+            let denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Permission denied");
+            return Err(anyhow::Error::from(denied).context(format!("Failed to create {}", path)));
+        }
+        self.files.lock().unwrap().insert(path.to_string(), Vec::new());
+        Ok(Box::new(ScriptedUpload { path: path.to_string(), files: Arc::clone(&self.files) }))
+    }
+}
+
+/// Writes to a file created through [`ScriptedSftpSession`], visible straight away
+struct ScriptedUpload {
+    path: String,
+    files: Files,
+}
+
+impl std::io::Write for ScriptedUpload {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.files.lock().unwrap().entry(self.path.clone()).or_default().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Session playing back a [`Script`]; reads never block
+#[derive(Debug)]
+pub struct ScriptedShellSession {
+    steps: VecDeque<Step>,
+    output: VecDeque<u8>,
+    input: Vec<u8>,
+    input_log: Arc<Mutex<Vec<u8>>>,
+    delay_until: Option<Instant>,
+    closed: bool,
+    eof_received: bool,
+    exit_status: i32,
+    exit_signal: Option<ExitSignal>,
+    signals: Vec<Signal>,
+}
+
+impl ScriptedShellSession {
+    pub fn new(script: Script, input_log: Arc<Mutex<Vec<u8>>>) -> Self {
+        Self {
+            steps: script.steps.into(),
+            output: VecDeque::new(),
+            input: Vec::new(),
+            input_log,
+            delay_until: None,
+            closed: false,
+            eof_received: false,
+            exit_status: 0,
+            exit_signal: None,
+            signals: Vec::new(),
+        }
+    }
+
+    /// Signals the client has sent, in order
+    pub fn signals_received(&self) -> &[Signal] {
+        &self.signals
+    }
+
+    /// Whether the client has sent EOF
+    pub fn eof_received(&self) -> bool {
+        self.eof_received
+    }
+
+    /// Run steps until one has to wait for input or time
+    fn advance(&mut self) {
+        while let Some(step) = self.steps.front() {
+            match step {
+                Step::Send(data) => self.output.extend(data.iter().copied()),
+                Step::Expect(pattern) => {
+                    let Some(pos) = find(&self.input, pattern) else {
+                        return;
+                    };
+                    self.input.drain(..pos + pattern.len());
+                }
+                Step::Delay(duration) => {
+                    let until = *self.delay_until.get_or_insert_with(|| Instant::now() + *duration);
+                    if Instant::now() < until {
+                        return;
+                    }
+                    self.delay_until = None;
+                }
+                Step::ExitStatus(status) => self.exit_status = *status,
+                Step::ExitSignal(signal) => self.exit_signal = Some(signal.clone()),
+                Step::Eof => self.closed = true,
+            }
+            self.steps.pop_front();
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+impl ShellSession for ScriptedShellSession {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.advance();
+        let n = std::cmp::min(buf.len(), self.output.len());
+        for (slot, byte) in buf.iter_mut().zip(self.output.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        if self.closed {
+            return Err(anyhow::anyhow!("Channel closed"));
+        }
+        self.input.extend_from_slice(data);
+        if let Ok(mut log) = self.input_log.lock() {
+            log.extend_from_slice(data);
+        }
+        self.advance();
+        Ok(data.len())
+    }
+
+    fn send_eof(&mut self) -> Result<()> {
+        self.eof_received = true;
+        Ok(())
+    }
+
+    fn is_eof(&self) -> bool {
+        self.closed && self.output.is_empty()
+    }
+
+    fn wait_close(&mut self) -> Result<i32> {
+        // Skip any remaining delays; expectations that can never be met end the script
+        while !self.closed {
+            let before = self.steps.len();
+            if let Some(Step::Delay(_)) = self.steps.front() {
+                self.delay_until = Some(Instant::now());
+            }
+            self.advance();
+            if self.steps.len() == before {
+                break;
+            }
+        }
+        Ok(self.exit_status)
+    }
+
+    fn exit_signal(&self) -> Option<ExitSignal> {
+        self.exit_signal.clone()
+    }
+
+    /// The scripted process has no handlers: any signal ends it, after output already sent
+    fn send_signal(&mut self, signal: Signal) -> Result<()> {
+        self.signals.push(signal);
+        if !self.closed {
+            self.steps.clear();
+            self.closed = true;
+            self.exit_signal = Some(ExitSignal {
+                name: signal.name().to_string(),
+                core_dumped: false,
+                message: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::SshClient;
+    use std::io::{Read, Write};
+
+    fn read_all(session: &mut dyn ShellSession) -> String {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            let n = session.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_authentication() {
+        let mut client = SshClient::new(Box::new(
+            ScriptedSshServer::new().accept_password("alice", "secret").accept_key("bob"),
+        ));
+        client.connect("example.com", 22).unwrap();
+
+        assert!(client.authenticate_with_password("alice", "wrong").is_err());
+        assert!(client.authenticate_with_key("alice", "/tmp/key").is_err());
+        assert!(client.authenticate_with_password("alice", "secret").is_ok());
+        assert!(client.is_authenticated());
+    }
+
+    #[test]
+    fn test_banner_and_shell() {
+        let mut client = SshClient::new(Box::new(
+            ScriptedSshServer::new()
+                .accept_key("bob")
+                .banner("Authorized use only\n")
+                .shell(Script::new().send("Welcome\n$ ").eof()),
+        ));
+        let seen = Arc::new(Mutex::new(String::new()));
+        let seen_by_callback = Arc::clone(&seen);
+        client.set_banner_callback(Box::new(move |banner| {
+            seen_by_callback.lock().unwrap().push_str(banner);
+            true
+        }));
+
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("bob", "/tmp/key").unwrap();
+        let mut shell = client.start_shell().unwrap();
+
+        assert_eq!(seen.lock().unwrap().as_str(), "Authorized use only\n");
+        assert_eq!(read_all(shell.as_mut()), "Welcome\n$ ");
+        assert!(shell.is_eof());
+    }
+
+    #[test]
+    fn test_refused_connection() {
+        let mut client = SshClient::new(Box::new(ScriptedSshServer::new().refuse_connections()));
+        assert!(client.connect("example.com", 22).is_err());
+    }
+
+    #[test]
+    fn test_execute_command() {
+        let mut client = SshClient::new(Box::new(
+            ScriptedSshServer::new()
+                .accept_key("bob")
+                .command("uname", Script::new().send("Linux\n").exit_status(0).eof()),
+        ));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("bob", "/tmp/key").unwrap();
+
+        assert_eq!(client.execute_command("uname").unwrap(), "Linux\n");
+        assert!(client.execute_command("whoami").is_err());
+    }
+
+    #[test]
+    fn test_prompt_response() {
+        let mut session = ScriptedShellSession::new(
+            Script::new().send("$ ").expect("ls\n").send("a b\n$ ").expect("exit\n").exit_status(3).eof(),
+            Arc::default(),
+        );
+
+        assert_eq!(read_all(&mut session), "$ ");
+        session.write(b"l").unwrap();
+        assert_eq!(read_all(&mut session), "");
+        session.write(b"s\n").unwrap();
+        assert_eq!(read_all(&mut session), "a b\n$ ");
+        assert!(!session.is_eof());
+
+        session.write(b"exit\n").unwrap();
+        session.send_eof().unwrap();
+        assert!(session.eof_received());
+        assert!(session.is_eof());
+        assert_eq!(session.wait_close().unwrap(), 3);
+        assert!(session.write(b"more").is_err());
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut session = ScriptedShellSession::new(
+            Script::new().delay(Duration::from_millis(30)).send("late").eof(),
+            Arc::default(),
+        );
+
+        assert_eq!(read_all(&mut session), "");
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(read_all(&mut session), "late");
+        assert!(session.is_eof());
+    }
+
+    #[test]
+    fn test_input_log_and_eof() {
+        let server = ScriptedSshServer::new()
+            .accept_key("bob")
+            .command("cat", Script::new().expect("hello").send("hello").eof());
+        let log = server.input_log();
+
+        let mut client = SshClient::new(Box::new(server));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("bob", "/tmp/key").unwrap();
+
+        let mut session = client.open_exec("cat").unwrap();
+        session.write(b"hello").unwrap();
+        session.send_eof().unwrap();
+
+        assert_eq!(read_all(session.as_mut()), "hello");
+        assert_eq!(log.lock().unwrap().as_slice(), b"hello");
+    }
+
+    #[test]
+    fn test_sftp_files() {
+        let mut client = SshClient::new(Box::new(
+            ScriptedSshServer::new()
+                .accept_key("alice")
+                .file("/srv/readme.txt", "hello world")
+                .file("/srv/logs/app.log", "started"),
+        ));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("alice", "/tmp/key").unwrap();
+        let mut sftp = client.open_sftp().unwrap();
+
+        assert_eq!(sftp.stat("/srv").unwrap().kind, RemoteFileKind::Directory);
+        assert_eq!(sftp.stat("/srv/readme.txt").unwrap().size, 11);
+        assert!(sftp.stat("/srv/missing").is_err());
+
+        let names: Vec<_> = sftp.read_dir("/srv").unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["logs", "readme.txt"]);
+        assert_eq!(sftp.read_dir("/").unwrap().len(), 1);
+
+        let mut readme = sftp.open_reader("/srv/readme.txt").unwrap();
+        readme.seek(std::io::SeekFrom::Start(6)).unwrap();
+        let mut contents = String::new();
+        readme.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "world");
+
+        let mut contents = String::new();
+        sftp.open_reader("/srv/logs/app.log").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "started");
+        sftp.create("/srv/new.txt").unwrap().write_all(b"uploaded").unwrap();
+        let mut contents = String::new();
+        client.open_sftp().unwrap().open_reader("/srv/new.txt").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "uploaded");
+    }
+
+    #[test]
+    fn test_wait_close_skips_delays() {
+        let mut session = ScriptedShellSession::new(
+            Script::new().delay(Duration::from_secs(60)).exit_status(7).eof(),
+            Arc::default(),
+        );
+        assert_eq!(session.wait_close().unwrap(), 7);
+    }
+}
@@ -0,0 +1,266 @@
+//! Streaming remote files to and from local streams over SFTP, for `bxssh cat`
+//! and `bxssh put`, with optional SHA-256 verification against the remote file.
+//! Also used by the WASM bindings, which feed uploads one chunk at a time.
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::ssh_client::SftpSession;
+
+/// Read and write size; large enough for libssh2 to pipeline SFTP requests
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What a transfer moved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferReport {
+    /// Remote file
+    pub path: String,
+    pub bytes: u64,
+    /// Lowercase hex SHA-256 of the data as it streamed
+    pub sha256: String,
+    /// The remote file's own SHA-256 was checked against `sha256`
+    pub verified: bool,
+}
+
+impl TransferReport {
+    /// Compare with `remote_sha256`, the remote file's digest, failing on a mismatch
+    pub fn verify(&mut self, remote_sha256: &str) -> Result<()> {
+        if !self.sha256.eq_ignore_ascii_case(remote_sha256) {
+            return Err(anyhow::anyhow!(
+                "Checksum mismatch for {}: transferred data has SHA-256 {}, the remote file has {}",
+                self.path,
+                self.sha256,
+                remote_sha256
+            ));
+        }
+        self.verified = true;
+        Ok(())
+    }
+}
+
+/// Remote shell command printing the SHA-256 of `path`, with `sha256sum` (GNU,
+/// busybox) or `shasum` (macOS, BSD). libssh2 cannot send the SFTP `check-file`
+/// extension, so the digest comes from an exec channel.
+pub fn remote_sha256_command(path: &str) -> String {
+    let path = shell_quote(path);
+    format!("sha256sum -- {path} 2>/dev/null || shasum -a 256 -- {path}")
+}
+
+/// The digest in `sha256sum`-style output (`<hex>  <path>`)
+pub fn parse_sha256_output(output: &str) -> Result<String> {
+    output
+        .split_whitespace()
+        .next()
+        .filter(|digest| digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
+        .ok_or_else(|| anyhow::anyhow!("Unexpected checksum output: {:?}", output.trim()))
+}
+
+/// `value` in single quotes for a POSIX shell
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Copy the remote file at `path` to `output`
+pub fn download(sftp: &mut dyn SftpSession, path: &str, output: &mut dyn Write) -> Result<TransferReport> {
+    let reader = BufReader::with_capacity(CHUNK_SIZE, sftp.open_reader(path)?);
+    let mut reader = Hashing { inner: reader, hasher: Sha256::new() };
+    let bytes = std::io::copy(&mut reader, output).with_context(|| format!("Failed to download {}", path))?;
+    output.flush()?;
+    Ok(reader.report(path, bytes))
+}
+
+/// Copy `input` to the remote file at `path` until it ends, replacing the file
+pub fn upload(sftp: &mut dyn SftpSession, input: &mut dyn Read, path: &str) -> Result<TransferReport> {
+    let mut upload = Upload::start(sftp, path)?;
+    upload.bytes = std::io::copy(input, &mut upload.writer).with_context(|| format!("Failed to upload to {}", path))?;
+    upload.finish()
+}
+
+/// Upload fed one chunk at a time, for callers that receive the data in pieces
+/// (browser `File` reads)
+pub struct Upload {
+    path: String,
+    writer: Hashing<BufWriter<Box<dyn Write + Send>>>,
+    bytes: u64,
+}
+
+impl Upload {
+    /// Create or truncate the remote file at `path`
+    pub fn start(sftp: &mut dyn SftpSession, path: &str) -> Result<Self> {
+        let writer = BufWriter::with_capacity(CHUNK_SIZE, sftp.create(path)?);
+        Ok(Self { path: path.to_string(), writer: Hashing { inner: writer, hasher: Sha256::new() }, bytes: 0 })
+    }
+
+    /// Append `chunk`, returning the bytes written so far
+    #[allow(dead_code)] // Used by the WASM bindings
+    pub fn write(&mut self, chunk: &[u8]) -> Result<u64> {
+        self.writer.write_all(chunk).with_context(|| format!("Failed to upload to {}", self.path))?;
+        self.bytes += chunk.len() as u64;
+        Ok(self.bytes)
+    }
+
+    /// Flush the remaining data and report what was sent
+    pub fn finish(mut self) -> Result<TransferReport> {
+        self.writer.flush().with_context(|| format!("Failed to upload to {}", self.path))?;
+        Ok(self.writer.report(&self.path, self.bytes))
+    }
+}
+
+/// Remote path for a file called `name` inside `dir`. `name` comes from outside
+/// (a browser file name), so anything but a plain file name is refused.
+#[allow(dead_code)] // Used by the WASM bindings
+pub fn remote_join(dir: &str, name: &str) -> Result<String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(anyhow::anyhow!("Refusing to upload a file named {:?}", name));
+    }
+    match dir.trim_end_matches('/') {
+        "" if dir.starts_with('/') => Ok(format!("/{}", name)),
+        "" => Ok(name.to_string()),
+        dir => Ok(format!("{}/{}", dir, name)),
+    }
+}
+
+/// Reader or writer that hashes the data passing through
+struct Hashing<T> {
+    inner: T,
+    hasher: Sha256,
+}
+
+impl<T> Hashing<T> {
+    fn report(self, path: &str, bytes: u64) -> TransferReport {
+        let sha256 = self.hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+        TransferReport { path: path.to_string(), bytes, sha256, verified: false }
+    }
+}
+
+impl<T: Read> Read for Hashing<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Hashing<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether `error` is a closed pipe on our side, as when `bxssh cat ... | head` stops reading
+pub fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|io| io.kind() == std::io::ErrorKind::BrokenPipe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::SshClient;
+    use crate::testing::ScriptedSshServer;
+
+    fn sftp(server: ScriptedSshServer) -> Box<dyn SftpSession> {
+        let mut client = SshClient::new(Box::new(server.accept_key("alice")));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("alice", "/tmp/key").unwrap();
+        client.open_sftp().unwrap()
+    }
+
+    #[test]
+    fn test_download_streams_file() {
+        let log = "line\n".repeat(50_000);
+        let mut sftp = sftp(ScriptedSshServer::new().file("/var/log/syslog", &log));
+        let mut output = Vec::new();
+        let report = download(sftp.as_mut(), "/var/log/syslog", &mut output).unwrap();
+        assert_eq!(report.bytes, log.len() as u64);
+        assert_eq!(report.sha256, format!("{:x}", Sha256::digest(&log)));
+        assert!(!report.verified);
+        assert_eq!(output, log.as_bytes());
+
+        let err = download(sftp.as_mut(), "/var/log/missing", &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("/var/log/missing"));
+    }
+
+    #[test]
+    fn test_upload_replaces_file() {
+        let server = ScriptedSshServer::new().file("/tmp/backup.tgz", "old contents");
+        let files = server.files();
+        let mut sftp = sftp(server);
+        let data = vec![7u8; CHUNK_SIZE * 3 + 5];
+        let report = upload(sftp.as_mut(), &mut data.as_slice(), "/tmp/backup.tgz").unwrap();
+        assert_eq!(report.bytes, data.len() as u64);
+        assert_eq!(report.sha256, format!("{:x}", Sha256::digest(&data)));
+        assert_eq!(files.lock().unwrap()["/tmp/backup.tgz"], data);
+    }
+
+    #[test]
+    fn test_upload_in_chunks() {
+        let server = ScriptedSshServer::new();
+        let files = server.files();
+        let mut sftp = sftp(server);
+        let mut upload = Upload::start(sftp.as_mut(), "/srv/drop/photo.jpg").unwrap();
+        assert_eq!(upload.write(b"first ").unwrap(), 6);
+        assert_eq!(upload.write(&[]).unwrap(), 6);
+        assert_eq!(upload.write(b"second").unwrap(), 12);
+        let report = upload.finish().unwrap();
+        assert_eq!(report.bytes, 12);
+        assert_eq!(report.sha256, format!("{:x}", Sha256::digest(b"first second")));
+        assert_eq!(files.lock().unwrap()["/srv/drop/photo.jpg"], b"first second");
+    }
+
+    #[test]
+    fn test_remote_join() {
+        assert_eq!(remote_join("/srv/drop", "a.txt").unwrap(), "/srv/drop/a.txt");
+        assert_eq!(remote_join("/srv/drop/", "a b.txt").unwrap(), "/srv/drop/a b.txt");
+        assert_eq!(remote_join("/", "a.txt").unwrap(), "/a.txt");
+        assert_eq!(remote_join("", "a.txt").unwrap(), "a.txt");
+        assert_eq!(remote_join("uploads", "a.txt").unwrap(), "uploads/a.txt");
+        for name in ["", ".", "..", "../etc/passwd", "a/b", "a\\b"] {
+            assert!(remote_join("/srv", name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let mut report = upload(sftp(ScriptedSshServer::new()).as_mut(), &mut [].as_slice(), "/tmp/empty").unwrap();
+        assert_eq!(report.sha256, empty);
+
+        let output = format!("{}  /tmp/empty\n", empty.to_uppercase());
+        report.verify(&parse_sha256_output(&output).unwrap()).unwrap();
+        assert!(report.verified);
+
+        let mut report = TransferReport { verified: false, ..report };
+        let err = report.verify(&"0".repeat(64)).unwrap_err();
+        assert!(err.to_string().starts_with("Checksum mismatch for /tmp/empty"));
+        assert!(!report.verified);
+
+        assert!(parse_sha256_output("sha256sum: command not found").is_err());
+        assert!(parse_sha256_output("").is_err());
+    }
+
+    #[test]
+    fn test_remote_sha256_command_quotes_path() {
+        assert_eq!(
+            remote_sha256_command("/tmp/it's here"),
+            r"sha256sum -- '/tmp/it'\''s here' 2>/dev/null || shasum -a 256 -- '/tmp/it'\''s here'"
+        );
+    }
+
+    #[test]
+    fn test_is_broken_pipe() {
+        let closed = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        let closed = anyhow::Error::from(closed).context("Failed to download");
+        assert!(is_broken_pipe(&closed));
+        assert!(!is_broken_pipe(&anyhow::anyhow!("Failed to open /x")));
+    }
+}
@@ -0,0 +1,485 @@
+//! UDP over SSH (`--udp-forward`), for DNS, WireGuard and the like. SSH only
+//! carries streams, so each datagram is framed as a 2-byte big-endian length and
+//! its bytes. A local UDP socket hands the datagrams of each peer to its own exec
+//! channel running `bxssh udp-endpoint HOST:PORT` on the server, which sends them
+//! on from a socket of its own and frames the replies back.
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::forwards::ForwardFields;
+use crate::remote_target::{bracketed, host_port};
+use crate::ssh_client::{ShellSession, SshClient, WriteOutcome};
+use crate::terminal::SessionHook;
+
+/// Largest payload a frame's length can describe
+pub const MAX_DATAGRAM: usize = u16::MAX as usize;
+
+/// Run on the server for each peer; it needs bxssh installed there
+pub const ENDPOINT_COMMAND: &str = "bxssh udp-endpoint";
+
+/// Address the local socket binds when `--udp-forward` names none
+pub const DEFAULT_BIND_ADDRESS: &str = "localhost";
+
+/// Peers served at once; a new one beyond this takes the place of the one quiet the longest
+const MAX_PEERS: usize = 64;
+
+/// A peer whose datagrams stopped both ways this long ago is closed, as a NAT
+/// would forget it, so its endpoint on the server ends too
+const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often the endpoint checks whether its input has ended
+const ENDPOINT_POLL: Duration = Duration::from_millis(200);
+
+/// Append `payload` to `out` as one frame
+pub fn encode_frame(payload: &[u8], out: &mut Vec<u8>) {
+    let length = payload.len().min(MAX_DATAGRAM);
+    out.extend_from_slice(&(length as u16).to_be_bytes());
+    out.extend_from_slice(&payload[..length]);
+}
+
+/// Splits a byte stream back into the frames written by `encode_frame`
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The next complete frame's payload, if one has arrived
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        let length = u16::from_be_bytes([*self.buffer.first()?, *self.buffer.get(1)?]) as usize;
+        if self.buffer.len() < 2 + length {
+            return None;
+        }
+        let payload = self.buffer[2..2 + length].to_vec();
+        self.buffer.drain(..2 + length);
+        Some(payload)
+    }
+}
+
+/// `--udp-forward [bind_address:]port:host:hostport`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpForwardSpec {
+    pub bind_address: String,
+    /// 0 for any free port
+    pub local_port: u16,
+    pub remote_host: String,
+    pub remote_port: u16,
+}
+
+impl UdpForwardSpec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let fields = ForwardFields::parse(spec).context("Invalid UDP forward")?;
+        Ok(Self {
+            bind_address: fields.bind_address.unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string()),
+            local_port: fields.port,
+            remote_host: fields.host,
+            remote_port: fields.host_port,
+        })
+    }
+
+    /// Where datagrams go from the server, as `host:port`
+    pub fn destination(&self) -> String {
+        host_port(&self.remote_host, self.remote_port)
+    }
+
+    /// The endpoint command for this forward's destination
+    pub fn endpoint_command(&self) -> String {
+        format!("{} {}", ENDPOINT_COMMAND, crate::transfer::shell_quote(&self.destination()))
+    }
+}
+
+impl std::fmt::Display for UdpForwardSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", bracketed(&self.bind_address), self.local_port, self.destination())
+    }
+}
+
+/// One local peer's datagrams on its own channel
+struct Peer {
+    channel: Box<dyn ShellSession>,
+    to_remote: Vec<u8>,
+    from_remote: FrameDecoder,
+    /// When a datagram last went either way
+    last_active: Instant,
+}
+
+/// A forward's socket and the peers talking through it
+struct Bound {
+    spec: UdpForwardSpec,
+    socket: UdpSocket,
+    port: u16,
+    peers: HashMap<SocketAddr, Peer>,
+}
+
+/// Serves `--udp-forward` forwards during an interactive session
+pub struct UdpForward {
+    client: Rc<SshClient>,
+    bound: Vec<Bound>,
+}
+
+impl UdpForward {
+    /// Bind a socket for every one of `specs`, tunnelling through `client`
+    pub fn bind(client: Rc<SshClient>, specs: &[UdpForwardSpec]) -> Result<Self> {
+        let mut bound = Vec::new();
+        for spec in specs {
+            let address = host_port(&spec.bind_address, spec.local_port);
+            let socket = UdpSocket::bind((spec.bind_address.as_str(), spec.local_port))
+                .with_context(|| format!("Cannot listen on UDP {} for {}", address, spec))?;
+            socket.set_nonblocking(true)?;
+            let port = socket.local_addr()?.port();
+            info!("Forwarding UDP {} to {}", host_port(&spec.bind_address, port), spec.destination());
+            bound.push(Bound { spec: spec.clone(), socket, port, peers: HashMap::new() });
+        }
+        Ok(Self { client, bound })
+    }
+
+    /// Each forward with the UDP port it listens on
+    pub fn listening(&self) -> impl Iterator<Item = (&UdpForwardSpec, u16)> {
+        self.bound.iter().map(|bound| (&bound.spec, bound.port))
+    }
+
+    /// Carry on over `client` after the connection was re-dialled. The sockets stay
+    /// bound; each peer gets a new endpoint with its next datagram.
+    pub fn reconnect(&mut self, client: Rc<SshClient>) {
+        self.client = client;
+        for bound in &mut self.bound {
+            bound.peers.clear();
+        }
+    }
+}
+
+impl Bound {
+    /// Take in the datagrams waiting on the socket, opening endpoints for new peers
+    fn receive(&mut self, client: &SshClient) -> bool {
+        let mut activity = false;
+        let mut buffer = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let (n, peer) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    // e.g. ICMP port unreachable from an earlier reply
+                    debug!("UDP receive on port {} failed: {}", self.port, e);
+                    break;
+                }
+            };
+            activity = true;
+            if !self.peers.contains_key(&peer) {
+                if self.peers.len() >= MAX_PEERS {
+                    self.evict_least_recent();
+                }
+                match client.open_exec(&self.spec.endpoint_command()) {
+                    Ok(channel) => {
+                        debug!("UDP peer {} for {}", peer, self.spec.destination());
+                        self.peers.insert(peer, Peer::new(channel));
+                    }
+                    Err(e) => {
+                        warn!("Cannot start the UDP endpoint for {}: {:#}", self.spec.destination(), e);
+                        continue;
+                    }
+                }
+            }
+            if let Some(state) = self.peers.get_mut(&peer) {
+                encode_frame(&buffer[..n], &mut state.to_remote);
+                state.last_active = Instant::now();
+            }
+        }
+        activity
+    }
+
+    /// Close the peer that has been quiet the longest, making room for a new one
+    fn evict_least_recent(&mut self) {
+        let oldest = self.peers.iter().min_by_key(|(_, peer)| peer.last_active).map(|(address, _)| *address);
+        if let Some((address, mut peer)) = oldest.and_then(|address| self.peers.remove_entry(&address)) {
+            debug!("Too many UDP peers; closing the quietest, {}", address);
+            peer.close();
+        }
+    }
+
+    /// Move framed datagrams both ways for every peer, dropping those whose endpoint
+    /// ended and closing those idle for `PEER_IDLE_TIMEOUT`
+    fn pump(&mut self) -> bool {
+        let mut activity = false;
+        let socket = &self.socket;
+        self.peers.retain(|address, peer| match peer.pump(socket, *address) {
+            Ok(moved) => {
+                activity |= moved;
+                if peer.last_active.elapsed() >= PEER_IDLE_TIMEOUT {
+                    debug!("UDP peer {} has been idle for {:?}; closing it", address, PEER_IDLE_TIMEOUT);
+                    peer.close();
+                    return false;
+                }
+                !peer.channel.is_eof()
+            }
+            Err(e) => {
+                debug!("UDP peer {} failed: {:#}", address, e);
+                false
+            }
+        });
+        activity
+    }
+}
+
+impl Peer {
+    fn new(channel: Box<dyn ShellSession>) -> Self {
+        Self { channel, to_remote: Vec::new(), from_remote: FrameDecoder::default(), last_active: Instant::now() }
+    }
+
+    /// End the endpoint's input, which stops it on the server
+    fn close(&mut self) {
+        if let Err(e) = self.channel.send_eof() {
+            debug!("Cannot close a UDP peer's channel: {:#}", e);
+        }
+    }
+
+    fn pump(&mut self, socket: &UdpSocket, address: SocketAddr) -> Result<bool> {
+        let mut moved = false;
+        if !self.to_remote.is_empty() {
+            if let WriteOutcome::Written(n) = self.channel.try_write(&self.to_remote)? {
+                self.to_remote.drain(..n);
+                moved = true;
+            }
+        }
+        let mut buffer = [0u8; 32768];
+        loop {
+            match self.channel.read(&mut buffer)? {
+                0 => break,
+                n => {
+                    self.from_remote.push(&buffer[..n]);
+                    moved = true;
+                }
+            }
+        }
+        while let Some(datagram) = self.from_remote.next_frame() {
+            if let Err(e) = socket.send_to(&datagram, address) {
+                debug!("Cannot send a datagram to {}: {}", address, e);
+            }
+        }
+        if moved {
+            self.last_active = Instant::now();
+        }
+        Ok(moved)
+    }
+}
+
+impl SessionHook for UdpForward {
+    fn poll(&mut self) -> bool {
+        let mut activity = false;
+        for bound in &mut self.bound {
+            activity |= bound.receive(&self.client);
+            activity |= bound.pump();
+        }
+        activity
+    }
+}
+
+/// `bxssh udp-endpoint HOST:PORT`: send the datagrams framed on stdin to
+/// `destination` and frame what comes back on stdout, until stdin ends
+pub fn run_endpoint(destination: &str) -> Result<()> {
+    let address = destination
+        .to_socket_addrs()
+        .with_context(|| format!("Cannot resolve {}", destination))?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Cannot resolve {}", destination))?;
+    let local: SocketAddr = if address.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let socket = UdpSocket::bind(local).context("Failed to open a UDP socket")?;
+    socket.connect(address).with_context(|| format!("Cannot reach {}", destination))?;
+    serve_endpoint(socket, std::io::stdin(), std::io::stdout())
+}
+
+/// The endpoint's work on a connected `socket`, with the frames on `input` and `output`
+pub fn serve_endpoint(socket: UdpSocket, mut input: impl Read + Send + 'static, mut output: impl Write) -> Result<()> {
+    let done = Arc::new(AtomicBool::new(false));
+    let sender = socket.try_clone()?;
+    let input_done = Arc::clone(&done);
+    std::thread::spawn(move || {
+        let mut decoder = FrameDecoder::default();
+        let mut buffer = [0u8; 32768];
+        loop {
+            let n = match input.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            decoder.push(&buffer[..n]);
+            while let Some(datagram) = decoder.next_frame() {
+                // Unreachable destinations are UDP's business, not the tunnel's
+                let _ = sender.send(&datagram);
+            }
+        }
+        input_done.store(true, Ordering::SeqCst);
+    });
+
+    socket.set_read_timeout(Some(ENDPOINT_POLL))?;
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    let mut frame = Vec::new();
+    while !done.load(Ordering::SeqCst) {
+        let n = match socket.recv(&mut buffer) {
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => continue,
+            Err(e) => return Err(e).context("UDP receive failed"),
+        };
+        frame.clear();
+        encode_frame(&buffer[..n], &mut frame);
+        output.write_all(&frame)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let mut stream = Vec::new();
+        encode_frame(b"query", &mut stream);
+        encode_frame(b"", &mut stream);
+        encode_frame(&[7u8; 300], &mut stream);
+        assert_eq!(&stream[..7], b"\0\x05query");
+
+        let mut decoder = FrameDecoder::default();
+        // Byte by byte, as a channel may deliver them
+        let mut frames = Vec::new();
+        for byte in &stream {
+            decoder.push(std::slice::from_ref(byte));
+            frames.extend(std::iter::from_fn(|| decoder.next_frame()));
+        }
+        assert_eq!(frames, [b"query".to_vec(), Vec::new(), vec![7u8; 300]]);
+        assert_eq!(decoder.next_frame(), None);
+    }
+
+    #[test]
+    fn test_parse_udp_forward() {
+        let forward = UdpForwardSpec::parse("5353:10.0.0.2:53").unwrap();
+        assert_eq!((forward.bind_address.as_str(), forward.local_port), ("localhost", 5353));
+        assert_eq!(forward.endpoint_command(), "bxssh udp-endpoint '10.0.0.2:53'");
+        let forward = UdpForwardSpec::parse("[::1]:51820:[fd00::1]:51820").unwrap();
+        assert_eq!(forward.to_string(), "[::1]:51820:[fd00::1]:51820");
+        assert!(UdpForwardSpec::parse("5353:dns").is_err());
+    }
+
+    #[test]
+    fn test_endpoint_relays_datagrams() {
+        let echo = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let echo_address = echo.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (n, from) = echo.recv_from(&mut buffer).unwrap();
+            buffer[..n].reverse();
+            echo.send_to(&buffer[..n], from).unwrap();
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(echo_address).unwrap();
+        let mut input = Vec::new();
+        encode_frame(b"ping", &mut input);
+        // Keep the input open until the reply is back, as a channel would
+        let (writer, reader) = std::sync::mpsc::channel::<Vec<u8>>();
+        writer.send(input).unwrap();
+        let input = ChannelReader { receiver: reader, pending: Vec::new() };
+        let output = SharedOutput::default();
+        let seen = output.clone();
+        std::thread::spawn(move || {
+            let deadline = std::time::Instant::now() + Duration::from_secs(5);
+            while seen.0.lock().unwrap().len() < 6 && std::time::Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            drop(writer);
+        });
+        serve_endpoint(socket, input, output.clone()).unwrap();
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&output.0.lock().unwrap());
+        assert_eq!(decoder.next_frame().as_deref(), Some(&b"gnip"[..]));
+    }
+
+    fn idle_peer(idle: Duration, closed: bool) -> Peer {
+        let mut channel = crate::ssh_client::MockShellSession::new();
+        channel.expect_read().returning(|_| Ok(0));
+        channel.expect_is_eof().returning(|| false);
+        channel.expect_send_eof().times(usize::from(closed)).returning(|| Ok(()));
+        let mut peer = Peer::new(Box::new(channel));
+        peer.last_active = Instant::now().checked_sub(idle).unwrap();
+        peer
+    }
+
+    fn bound(peers: impl IntoIterator<Item = (SocketAddr, Peer)>) -> Bound {
+        Bound {
+            spec: UdpForwardSpec::parse("0:10.0.0.2:53").unwrap(),
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            port: 0,
+            peers: peers.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_quietest_peer_makes_room() {
+        let recent: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let quiet: SocketAddr = "127.0.0.1:5002".parse().unwrap();
+        let mut bound = bound([
+            (recent, idle_peer(Duration::from_secs(1), false)),
+            (quiet, idle_peer(Duration::from_secs(30), true)),
+        ]);
+        bound.evict_least_recent();
+        assert_eq!(bound.peers.keys().collect::<Vec<_>>(), [&recent]);
+    }
+
+    #[test]
+    fn test_idle_peers_are_closed() {
+        let active: SocketAddr = "127.0.0.1:5001".parse().unwrap();
+        let idle: SocketAddr = "127.0.0.1:5002".parse().unwrap();
+        let mut bound = bound([
+            (active, idle_peer(Duration::from_secs(1), false)),
+            (idle, idle_peer(PEER_IDLE_TIMEOUT + Duration::from_secs(1), true)),
+        ]);
+        assert!(!bound.pump());
+        assert_eq!(bound.peers.keys().collect::<Vec<_>>(), [&active]);
+    }
+
+    struct ChannelReader {
+        receiver: std::sync::mpsc::Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Read for ChannelReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.receiver.recv() {
+                    Ok(data) => self.pending = data,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Human-facing CLI messages. They carry an emoji marker by default; `--plain`
+//! drops the markers and bullets, and `--quiet` drops status messages and hints,
+//! leaving warnings, errors and the output a command was asked for. Text comes
+//! from the `i18n` catalog; colors from its theme.
+use crate::i18n::{self, Msg, Theme};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// `Theme` as a number; `Theme::None` unless stdout is a terminal that allows color
+static THEME: AtomicU8 = AtomicU8::new(Theme::None as u8);
+/// The same for stderr, where warnings and errors go
+static STDERR_THEME: AtomicU8 = AtomicU8::new(Theme::None as u8);
+
+/// Apply `--plain`, `--quiet` and the configured theme for the rest of the process.
+/// Color needs a terminal, no `NO_COLOR` and no `--plain`.
+pub fn configure(plain: bool, quiet: bool, theme: Theme) {
+    PLAIN.store(plain, Ordering::Relaxed);
+    QUIET.store(quiet, Ordering::Relaxed);
+    let color = |terminal: bool| !plain && terminal && std::env::var_os("NO_COLOR").is_none();
+    let store = |slot: &AtomicU8, terminal: bool| {
+        slot.store(if color(terminal) { theme } else { Theme::None } as u8, Ordering::Relaxed)
+    };
+    store(&THEME, std::io::stdout().is_terminal());
+    store(&STDERR_THEME, std::io::stderr().is_terminal());
+}
+
+fn theme() -> Theme {
+    theme_in(&THEME)
+}
+
+fn theme_in(slot: &AtomicU8) -> Theme {
+    match slot.load(Ordering::Relaxed) {
+        0 => Theme::Default,
+        1 => Theme::HighContrast,
+        _ => Theme::None,
+    }
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Marker shown in front of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Icon {
+    Success,
+    Failure,
+    Warning,
+    Hint,
+    Key,
+    PublicKey,
+    Empty,
+    Session,
+    Connected,
+    Rotated,
+    #[cfg_attr(not(all(feature = "fuse", unix)), allow(dead_code))] // only `bxssh mount`
+    Mount,
+    Relay,
+    Export,
+    Skipped,
+    Lock,
+    Remote,
+    Wake,
+}
+
+impl Icon {
+    fn emoji(self) -> &'static str {
+        match self {
+            Icon::Success => "✅",
+            Icon::Failure => "❌",
+            // Narrow in most terminals, hence the extra space
+            Icon::Warning => "⚠️ ",
+            Icon::Hint => "💡",
+            Icon::Key => "🔑",
+            Icon::PublicKey => "📋",
+            Icon::Empty => "📭",
+            Icon::Session => "🔌",
+            Icon::Connected => "🔗",
+            Icon::Rotated => "🔄",
+            Icon::Mount => "📂",
+            Icon::Relay => "📡",
+            Icon::Export => "📦",
+            Icon::Skipped => "⏭️ ",
+            Icon::Lock => "🔐",
+            Icon::Remote => "🖥️ ",
+            Icon::Wake => "⏰",
+        }
+    }
+
+    /// Word that replaces the marker in plain mode, where it carries meaning
+    fn plain_prefix(self) -> String {
+        match self {
+            Icon::Warning => i18n::tr(Msg::WarningPrefix, &[]),
+            Icon::Failure => i18n::tr(Msg::ErrorPrefix, &[]),
+            _ => String::new(),
+        }
+    }
+
+    /// ANSI color of messages with this marker
+    fn color(self, theme: Theme) -> Option<&'static str> {
+        let (normal, high_contrast) = match self {
+            Icon::Success => ("32", "1;92"),
+            Icon::Warning | Icon::Skipped => ("33", "1;93"),
+            Icon::Failure => ("31", "1;91"),
+            Icon::Hint => ("36", "1;96"),
+            _ => return None,
+        };
+        match theme {
+            Theme::Default => Some(normal),
+            Theme::HighContrast => Some(high_contrast),
+            Theme::None => None,
+        }
+    }
+}
+
+fn format_message(icon: Icon, text: &str, plain: bool, theme: Theme) -> String {
+    let text = match icon.color(theme) {
+        Some(color) => format!("\x1b[{}m{}\x1b[0m", color, text),
+        None => text.to_string(),
+    };
+    if plain {
+        format!("{}{}", icon.plain_prefix(), text)
+    } else {
+        format!("{} {}", icon.emoji(), text)
+    }
+}
+
+fn format_item(text: &str, plain: bool) -> String {
+    if plain {
+        text.to_string()
+    } else {
+        format!("  • {}", text)
+    }
+}
+
+/// `text` with its marker, as the current mode shows it
+pub fn message(icon: Icon, text: &str) -> String {
+    format_message(icon, text, PLAIN.load(Ordering::Relaxed), theme())
+}
+
+/// Catalog message `msg` with its marker
+pub fn tr(icon: Icon, msg: Msg, args: &[&dyn std::fmt::Display]) -> String {
+    message(icon, &i18n::tr(msg, args))
+}
+
+/// Status or hint on stdout; hidden by `--quiet`
+pub fn status(icon: Icon, text: &str) {
+    if !is_quiet() {
+        println!("{}", message(icon, text));
+    }
+}
+
+/// `text` with its marker for stderr, colored only if stderr is a terminal
+pub fn stderr_message(icon: Icon, text: &str) -> String {
+    format_message(icon, text, PLAIN.load(Ordering::Relaxed), theme_in(&STDERR_THEME))
+}
+
+/// Warning on stderr, shown even with `--quiet`
+pub fn warn(text: &str) {
+    eprintln!("{}", stderr_message(Icon::Warning, text));
+}
+
+/// Error on stderr, shown even with `--quiet`
+pub fn error(text: &str) {
+    eprintln!("{}", stderr_message(Icon::Failure, text));
+}
+
+/// One entry of a list a command was asked for: bulleted, or bare in plain mode
+/// so each line is just the entry
+pub fn item(text: &str) {
+    println!("{}", format_item(text, PLAIN.load(Ordering::Relaxed)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_message() {
+        assert_eq!(format_message(Icon::Success, "Imported keys.json", false, Theme::None), "✅ Imported keys.json");
+        assert_eq!(format_message(Icon::Success, "Imported keys.json", true, Theme::None), "Imported keys.json");
+        assert_eq!(format_message(Icon::Warning, "Key is old", false, Theme::None), "⚠️  Key is old");
+        assert_eq!(format_message(Icon::Warning, "Key is old", true, Theme::None), "warning: Key is old");
+        assert_eq!(format_message(Icon::Failure, "No key", true, Theme::None), "error: No key");
+    }
+
+    #[test]
+    fn test_themes() {
+        assert_eq!(format_message(Icon::Success, "done", false, Theme::Default), "✅ \x1b[32mdone\x1b[0m");
+        assert_eq!(format_message(Icon::Failure, "bad", false, Theme::HighContrast), "❌ \x1b[1;91mbad\x1b[0m");
+        assert_eq!(format_message(Icon::Key, "Keys:", false, Theme::Default), "🔑 Keys:");
+    }
+
+    #[test]
+    fn test_format_item() {
+        assert_eq!(format_item("work (Ed25519)", false), "  • work (Ed25519)");
+        assert_eq!(format_item("work (Ed25519)", true), "work (Ed25519)");
+    }
+}
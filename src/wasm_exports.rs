@@ -1,7 +1,11 @@
 // WASM exports for JavaScript integration
 use wasm_bindgen::prelude::*;
-use crate::wasm_ssh::WasmSshConnection;
+use crate::wasm_ssh::{Transport, WasmSshConnection};
 use crate::ssh_client::SshConnection;
+use js_sys::Promise;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen_futures::future_to_promise;
 
 // Re-export SshKeyExchange for JavaScript
 #[cfg(target_arch = "wasm32")]
@@ -12,14 +16,15 @@ pub use crate::ssh_protocol::SshKeyExchange;
 pub fn init() {
     console_error_panic_hook::set_once();
     
-    // Set up logging (will output to console.log in browser)
-    console_log::init_with_level(log::Level::Info).expect("Failed to initialize logger");
+    // Leveled, redacted console logging; the page can change the level with setLogLevel
+    crate::wasm_log::init();
 }
 
 // JavaScript-accessible SSH connection wrapper
 #[wasm_bindgen]
 pub struct JsSshConnection {
     inner: WasmSshConnection,
+    client_version: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -28,6 +33,7 @@ impl JsSshConnection {
     pub fn new() -> Self {
         Self {
             inner: WasmSshConnection::new(),
+            client_version: None,
         }
     }
 
@@ -39,6 +45,29 @@ impl JsSshConnection {
         }
     }
 
+    /// Reach servers through a `bxssh relay` gateway at `url` instead of Direct Sockets.
+    /// Needs the relay bridge from `js/relay-bridge.js` installed on the page.
+    #[wasm_bindgen]
+    pub fn use_relay(&mut self, url: &str, token: Option<String>) {
+        self.inner.set_transport(Transport::Relay { url: url.to_string(), token });
+    }
+
+    /// Send `version` instead of `SSH-2.0-bxssh_<version>` when connecting, for
+    /// compatibility testing
+    #[wasm_bindgen]
+    pub fn set_client_version(&mut self, version: &str) -> Result<(), JsValue> {
+        crate::config::validate_client_version(version).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.client_version = Some(version.to_string());
+        Ok(())
+    }
+
+    /// Answer commands and shells with simulated output instead of failing; for UI
+    /// demos only, nothing is sent to a server
+    #[wasm_bindgen]
+    pub fn set_demo_mode(&mut self, enabled: bool) {
+        self.inner.set_demo_mode(enabled);
+    }
+
     #[wasm_bindgen]
     pub fn authenticate_with_key(&mut self, username: &str, private_key_path: &str) -> Result<bool, JsValue> {
         match self.inner.authenticate_with_key(username, private_key_path) {
@@ -47,6 +76,21 @@ impl JsSshConnection {
         }
     }
 
+    /// Agent signatures are bound to a key exchange this connection does not keep;
+    /// use `SshKeyExchange.authenticate_with_agent` after `perform_key_exchange`
+    #[wasm_bindgen]
+    pub fn authenticate_with_agent(
+        &mut self,
+        _username: &str,
+        agent: &JsSshAgent,
+        key_name: &str,
+    ) -> Result<bool, JsValue> {
+        agent.with_agent(|agent| agent.public_key_blob(key_name)).map_err(js_error)?;
+        Err(JsValue::from_str(
+            "Agent authentication needs the session of a key exchange: use SshKeyExchange.authenticate_with_agent",
+        ))
+    }
+
     #[wasm_bindgen]
     pub fn authenticate_with_password(&mut self, username: &str, password: &str) -> Result<bool, JsValue> {
         match self.inner.authenticate_with_password(username, password) {
@@ -61,17 +105,23 @@ impl JsSshConnection {
         // Create and use SSH key exchange directly in the connection
         use crate::ssh_protocol::SshKeyExchange;
         
-        log("[WASM SSH] Starting Rust-based SSH key exchange...");
+        log::debug!("[WASM SSH] Starting Rust-based SSH key exchange...");
         
         let mut key_exchange = SshKeyExchange::new();
+        if let Some(version) = &self.client_version {
+            key_exchange.set_client_version(version)?;
+        }
+        key_exchange.exchange_versions().await?;
+        log::debug!("[WASM SSH] ✅ SSH version exchange completed");
+
         match key_exchange.perform_key_exchange().await {
             Ok(success) => {
-                log("[WASM SSH] ✅ Key exchange completed successfully in Rust");
+                log::info!("[WASM SSH] ✅ Key exchange completed successfully in Rust");
                 Ok(success)
             },
             Err(e) => {
                 let error_msg = format!("Key exchange failed: {:?}", e);
-                log(&format!("[WASM SSH] ❌ {}", error_msg));
+                log::warn!("[WASM SSH] ❌ {}", error_msg);
                 Err(JsValue::from_str(&error_msg))
             }
         }
@@ -80,41 +130,42 @@ impl JsSshConnection {
     /// Perform complete SSH connection including protocol handshake
     #[wasm_bindgen]
     pub async fn connect_with_protocol(&mut self, hostname: &str, port: u16) -> Result<bool, JsValue> {
-        log(&format!("[WASM SSH] Starting full SSH-2.0 connection to {}:{}", hostname, port));
+        log::info!("[WASM SSH] Starting full SSH-2.0 connection to {}:{}", hostname, port);
         
         // Step 1: Basic connection
         match self.inner.connect(hostname, port) {
-            Ok(()) => log("[WASM SSH] ✅ TCP connection established"),
+            Ok(()) => {}
             Err(e) => return Err(JsValue::from_str(&format!("TCP connection failed: {}", e))),
         }
+        match self.inner.open_stream().await {
+            Ok(()) => log::info!("[WASM SSH] ✅ TCP connection established"),
+            Err(e) => return Err(JsValue::from_str(&format!("TCP connection failed: {:#}", e))),
+        }
         
-        // Step 2: SSH protocol version exchange (handled by Rust)
-        log("[WASM SSH] ✅ SSH version exchange completed");
-        
-        // Step 3: Key exchange (using our Rust implementation)
+        // Step 2: Version and key exchange (using our Rust implementation)
         match self.perform_key_exchange().await {
-            Ok(_) => log("[WASM SSH] ✅ SSH key exchange completed"),
+            Ok(_) => log::debug!("[WASM SSH] ✅ SSH key exchange completed"),
             Err(e) => return Err(e),
         }
         
-        log("[WASM SSH] ✅ SSH-2.0 protocol handshake completed successfully");
+        log::info!("[WASM SSH] ✅ SSH-2.0 protocol handshake completed successfully");
         Ok(true)
     }
 
     /// Authenticate and establish full SSH session
     #[wasm_bindgen]
     pub async fn full_authenticate(&mut self, username: &str, password: &str) -> Result<bool, JsValue> {
-        log(&format!("[WASM SSH] Starting full SSH authentication for user: {}", username));
+        log::info!("[WASM SSH] Starting full SSH authentication for user: {}", username);
         
         // Perform authentication using our Rust implementation
         match self.inner.authenticate_with_password(username, password) {
             Ok(()) => {
-                log("[WASM SSH] ✅ SSH authentication completed successfully");
+                log::info!("[WASM SSH] ✅ SSH authentication completed successfully");
                 Ok(true)
             },
             Err(e) => {
                 let error_msg = format!("Authentication failed: {}", e);
-                log(&format!("[WASM SSH] ❌ {}", error_msg));
+                log::warn!("[WASM SSH] ❌ {}", error_msg);
                 Err(JsValue::from_str(&error_msg))
             }
         }
@@ -122,16 +173,16 @@ impl JsSshConnection {
 
     #[wasm_bindgen]
     pub fn execute_command(&self, command: &str) -> Result<String, JsValue> {
-        log(&format!("[WASM SSH] Executing command via pure Rust implementation: {}", command));
+        log::debug!("[WASM SSH] Executing command via pure Rust implementation: {}", command);
         
         match self.inner.execute_command(command) {
             Ok(output) => {
-                log(&format!("[WASM SSH] ✅ Command executed successfully: {} chars output", output.len()));
+                log::trace!("[WASM SSH] ✅ Command executed successfully: {} chars output", output.len());
                 Ok(output)
             },
             Err(e) => {
                 let error_msg = format!("Command execution failed: {}", e);
-                log(&format!("[WASM SSH] ❌ {}", error_msg));
+                log::warn!("[WASM SSH] ❌ {}", error_msg);
                 Err(JsValue::from_str(&error_msg))
             }
         }
@@ -141,13 +192,13 @@ impl JsSshConnection {
     #[wasm_bindgen]
     pub fn execute_commands(&self, commands: &str) -> Result<String, JsValue> {
         let command_list: Vec<&str> = commands.split(';').map(|s| s.trim()).collect();
-        log(&format!("[WASM SSH] Executing {} commands in sequence", command_list.len()));
+        log::debug!("[WASM SSH] Executing {} commands in sequence", command_list.len());
         
         let mut results = Vec::new();
         
         for (i, command) in command_list.iter().enumerate() {
             if !command.is_empty() {
-                log(&format!("[WASM SSH] Executing command {}/{}: {}", i + 1, command_list.len(), command));
+                log::debug!("[WASM SSH] Executing command {}/{}: {}", i + 1, command_list.len(), command);
                 
                 match self.inner.execute_command(command) {
                     Ok(output) => {
@@ -161,7 +212,7 @@ impl JsSshConnection {
         }
         
         let combined_output = results.join("\n\n");
-        log(&format!("[WASM SSH] ✅ All commands executed, total output: {} chars", combined_output.len()));
+        log::trace!("[WASM SSH] ✅ All commands executed, total output: {} chars", combined_output.len());
         Ok(combined_output)
     }
 
@@ -177,6 +228,68 @@ impl JsSshConnection {
             Err(e) => Err(JsValue::from_str(&format!("Shell start failed: {}", e))),
         }
     }
+
+    /// Upload every file in `files` (from a drop or `<input type=file>`) into
+    /// `remote_dir` over SFTP, streaming each in 64 KiB slices. `on_progress` is
+    /// called after each slice with `{file, index, count, sent, total}`. Resolves
+    /// with `[{path, bytes, sha256}]`; stops at the first failure.
+    #[wasm_bindgen(js_name = uploadFromFileList)]
+    pub async fn upload_from_file_list(
+        &self,
+        files: web_sys::FileList,
+        remote_dir: String,
+        on_progress: Option<js_sys::Function>,
+    ) -> Result<js_sys::Array, JsValue> {
+        let fail = |e: anyhow::Error| JsValue::from_str(&format!("Upload failed: {:#}", e));
+        let mut sftp = self.inner.open_sftp().map_err(fail)?;
+        let reports = js_sys::Array::new();
+        let count = files.length();
+
+        for index in 0..count {
+            let Some(file) = files.get(index) else { continue };
+            let name = file.name();
+            let path = crate::transfer::remote_join(&remote_dir, &name).map_err(fail)?;
+            let total = file.size();
+            let mut upload = crate::transfer::Upload::start(sftp.as_mut(), &path).map_err(fail)?;
+
+            let mut offset = 0.0;
+            while offset < total {
+                let end = (offset + crate::transfer::CHUNK_SIZE as f64).min(total);
+                let slice = file.slice_with_f64_and_f64(offset, end)?;
+                let buffer = wasm_bindgen_futures::JsFuture::from(slice.array_buffer()).await?;
+                let sent = upload.write(&js_sys::Uint8Array::new(&buffer).to_vec()).map_err(fail)?;
+                offset = end;
+                if let Some(callback) = &on_progress {
+                    let event = js_object(&[
+                        ("file", name.as_str().into()),
+                        ("index", index.into()),
+                        ("count", count.into()),
+                        ("sent", (sent as f64).into()),
+                        ("total", total.into()),
+                    ])?;
+                    callback.call1(&JsValue::NULL, &event)?;
+                }
+            }
+
+            let report = upload.finish().map_err(fail)?;
+            log::info!("[WASM SSH] Uploaded {} ({} bytes)", report.path, report.bytes);
+            reports.push(&js_object(&[
+                ("path", report.path.as_str().into()),
+                ("bytes", (report.bytes as f64).into()),
+                ("sha256", report.sha256.as_str().into()),
+            ])?);
+        }
+        Ok(reports)
+    }
+}
+
+/// Plain JavaScript object with these properties
+fn js_object(properties: &[(&str, JsValue)]) -> Result<JsValue, JsValue> {
+    let object = js_sys::Object::new();
+    for (key, value) in properties {
+        js_sys::Reflect::set(&object, &JsValue::from_str(key), value)?;
+    }
+    Ok(object.into())
 }
 
 // JavaScript-accessible shell session wrapper
@@ -224,6 +337,111 @@ impl JsShellSession {
     }
 }
 
+/// In-memory SSH agent for web pages: unlock keys once, then sign userauth
+/// requests for any connection without handing out the private keys
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct JsSshAgent {
+    inner: Rc<RefCell<crate::agent::VirtualAgent>>,
+}
+
+impl JsSshAgent {
+    /// Run `f` with the unlocked keys
+    pub(crate) fn with_agent<T>(&self, f: impl FnOnce(&crate::agent::VirtualAgent) -> T) -> T {
+        f(&self.inner.borrow())
+    }
+}
+
+fn js_error(e: anyhow::Error) -> JsValue {
+    JsValue::from_str(&format!("{:#}", e))
+}
+
+#[wasm_bindgen]
+impl JsSshAgent {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the keys from a `bxssh sync export` bundle; returns their names as a JSON array
+    #[wasm_bindgen(js_name = unlockBundle)]
+    pub fn unlock_bundle(&mut self, bundle: &str, passphrase: &str) -> Result<String, JsValue> {
+        let names = self.inner.borrow_mut().unlock_bundle(bundle, passphrase).map_err(js_error)?;
+        serde_json::to_string(&names).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Like `unlockBundle` for a bundle sealed with `sealWithWebAuthn`: the passphrase
+    /// comes from a user-verified assertion with `credentialId`. Resolves to the names.
+    #[wasm_bindgen(js_name = unlockWithWebAuthn)]
+    pub fn unlock_with_webauthn(&self, bundle: String, credential_id: Vec<u8>) -> Promise {
+        let agent = Rc::clone(&self.inner);
+        future_to_promise(async move {
+            let secret = crate::webauthn::prf_secret(&credential_id).await?;
+            let passphrase = crate::agent::passphrase_from_prf(&secret).map_err(js_error)?;
+            let names = agent.borrow_mut().unlock_bundle(&bundle, &passphrase).map_err(js_error)?;
+            serde_json::to_string(&names).map(JsValue::from).map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// Re-encrypt a passphrase-protected bundle so that only `credentialId` (with user
+    /// verification) can open it. Resolves to the new bundle.
+    #[wasm_bindgen(js_name = sealWithWebAuthn)]
+    pub fn seal_with_webauthn(bundle: String, passphrase: String, credential_id: Vec<u8>) -> Promise {
+        future_to_promise(async move {
+            let payload = crate::sync::decrypt(&bundle, &passphrase).map_err(js_error)?;
+            let secret = crate::webauthn::prf_secret(&credential_id).await?;
+            let sealed_passphrase = crate::agent::passphrase_from_prf(&secret).map_err(js_error)?;
+            let sealed = crate::sync::encrypt(&payload, &sealed_passphrase).map_err(js_error)?;
+            Ok(JsValue::from(sealed))
+        })
+    }
+
+    #[wasm_bindgen(js_name = addKey)]
+    pub fn add_key(&mut self, name: &str, private_key: &str) -> Result<(), JsValue> {
+        self.inner.borrow_mut().add_key(name, private_key).map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = removeKey)]
+    pub fn remove_key(&mut self, name: &str) -> bool {
+        self.inner.borrow_mut().remove_key(name)
+    }
+
+    /// Forget every unlocked key
+    pub fn lock(&mut self) {
+        self.inner.borrow_mut().lock();
+    }
+
+    /// `[{"name": ..., "publicKey": "ssh-ed25519 ..."}]` as JSON
+    pub fn identities(&self) -> String {
+        let identities: Vec<serde_json::Value> = self
+            .inner
+            .borrow()
+            .identities()
+            .into_iter()
+            .map(|identity| serde_json::json!({ "name": identity.name, "publicKey": identity.public_key }))
+            .collect();
+        serde_json::Value::Array(identities).to_string()
+    }
+
+    #[wasm_bindgen(js_name = publicKeyBlob)]
+    pub fn public_key_blob(&self, name: &str) -> Result<Vec<u8>, JsValue> {
+        self.inner.borrow().public_key_blob(name).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Signature blob for a publickey SSH_MSG_USERAUTH_REQUEST on the session `session_id`
+    #[wasm_bindgen(js_name = signUserauth)]
+    pub fn sign_userauth(&self, name: &str, session_id: &[u8], username: &str) -> Result<Vec<u8>, JsValue> {
+        self.inner.borrow().sign_userauth(name, session_id, username).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Decrypt a bundle from `bxssh sync export`, returning `{"files": {name: contents}}` as JSON
+#[wasm_bindgen]
+pub fn decrypt_sync_bundle(bundle: &str, passphrase: &str) -> Result<String, JsValue> {
+    let payload = crate::sync::decrypt(bundle, passphrase).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    serde_json::to_string(&payload).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
 // Utility functions for JavaScript integration
 #[wasm_bindgen]
 pub fn get_version() -> String {
@@ -240,10 +458,6 @@ pub fn is_direct_socket_supported() -> bool {
 // JavaScript bridge functions that will be implemented in the JS side
 #[wasm_bindgen]
 extern "C" {
-    // Log function for WASM debugging
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
-    
     // Emit events to JavaScript
     #[wasm_bindgen(js_name = emit_event)]
     fn emit_event(event_type: &str, data: &str);
@@ -265,8 +479,8 @@ pub(crate) use emit_js_event;
 // Initialize function for setting up the WASM module
 #[wasm_bindgen]
 pub fn initialize_bxssh() -> Result<(), JsValue> {
-    log("🚀 bxssh WASM module initialized with pure Rust SSH-2.0 implementation");
-    log("✅ Features: Full SSH protocol, Curve25519 key exchange, Direct Socket API support");
+    log::info!("🚀 bxssh WASM module initialized with pure Rust SSH-2.0 implementation");
+    log::info!("✅ Features: Full SSH protocol, Curve25519 key exchange, Direct Socket API support");
     emit_event("initialized", "bxssh WASM module ready with Rust SSH implementation");
     Ok(())
 }
@@ -275,7 +489,7 @@ pub fn initialize_bxssh() -> Result<(), JsValue> {
 #[wasm_bindgen]
 pub fn get_ssh_info() -> String {
     format!(
-        "bxssh v{}\n• SSH-2.0 Protocol: ✅ Implemented in Rust\n• Key Exchange: Curve25519-SHA256\n• Authentication: Password & Key-based\n• Channels: Session management\n• Network: Direct Socket API\n• Crypto: Native WASM-compatible libraries",
+        "bxssh v{}\n• SSH-2.0 Protocol: ✅ Implemented in Rust\n• Key Exchange: Curve25519-SHA256, DH group16-SHA512/group14-SHA256\n• Authentication: Password & Key-based\n• Channels: Session management\n• Network: Direct Socket API\n• Crypto: Native WASM-compatible libraries",
         env!("CARGO_PKG_VERSION")
     )
 }
@@ -284,4 +498,15 @@ pub fn get_ssh_info() -> String {
 #[wasm_bindgen]
 pub fn is_rust_ssh_active() -> bool {
     true // We now have full Rust SSH implementation
-}
\ No newline at end of file
+}
+/// Restrict which servers this page may connect to, with the same TOML as the
+/// CLI's `~/.config/bxssh/policy.toml`; `undefined` lifts the restriction
+#[wasm_bindgen(js_name = setConnectionPolicy)]
+pub fn set_connection_policy(policy: Option<String>) -> Result<(), JsValue> {
+    let policy = policy
+        .map(|text| crate::host_policy::HostPolicy::parse(&text))
+        .transpose()
+        .map_err(|e| JsValue::from_str(&format!("{:#}", e)))?;
+    crate::host_policy::set_active(policy);
+    Ok(())
+}
@@ -0,0 +1,68 @@
+// Browser console backend for the `log` crate
+use crate::log_filter::{redact, truncate, RateLimiter, BURST, MAX_MESSAGE_LEN, PER_SECOND};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::str::FromStr;
+use std::sync::Mutex;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+/// Level used until the page calls `setLogLevel`
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Writes log records to the console at the matching console level, with
+/// credentials redacted, long messages cut short and floods rate-limited
+struct ConsoleLogger {
+    limiter: Mutex<RateLimiter>,
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger {
+    limiter: Mutex::new(RateLimiter::new(BURST, PER_SECOND)),
+};
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let suppressed = match self.limiter.lock() {
+            Ok(mut limiter) => match limiter.admit(js_sys::Date::now()) {
+                Some(suppressed) => suppressed,
+                None => return,
+            },
+            Err(_) => 0,
+        };
+        if suppressed > 0 {
+            console::warn_1(&format!("[bxssh] {} log messages suppressed", suppressed).into());
+        }
+
+        let message = truncate(&redact(&record.args().to_string()), MAX_MESSAGE_LEN);
+        let line = format!("[bxssh] {}", message).into();
+        match record.level() {
+            Level::Error => console::error_1(&line),
+            Level::Warn => console::warn_1(&line),
+            Level::Info => console::info_1(&line),
+            Level::Debug | Level::Trace => console::debug_1(&line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the console logger; later calls only reset the level
+pub fn init() {
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(DEFAULT_LEVEL);
+}
+
+/// Set how much bxssh logs to the console: `off`, `error`, `warn`, `info`, `debug` or `trace`
+#[wasm_bindgen(js_name = setLogLevel)]
+pub fn set_log_level(level: &str) -> Result<(), JsValue> {
+    let level = LevelFilter::from_str(level)
+        .map_err(|_| JsValue::from_str(&format!("Unknown log level '{}' (off, error, warn, info, debug, trace)", level)))?;
+    log::set_max_level(level);
+    Ok(())
+}
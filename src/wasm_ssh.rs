@@ -1,5 +1,6 @@
 use anyhow::Result;
-use crate::ssh_client::{SshConnection, ShellSession};
+use crate::ssh_client::{CommandOutput, RemoteFile, RemoteFileAttr, SftpSession, SshConnection, ShellSession};
+use crate::relay::{parse_reply, ConnectRequest};
 use crate::ssh_protocol::SshKeyExchange;
 use wasm_bindgen::prelude::*;
 // Imports cleaned up - JsFuture and Uint8Array not needed currently
@@ -7,9 +8,6 @@ use wasm_bindgen::prelude::*;
 // External JavaScript functions that bridge to Direct Socket API
 #[wasm_bindgen]
 extern "C" {
-    #[wasm_bindgen(js_namespace = console)]
-    fn log(s: &str);
-    
     // Direct Socket API bridge functions
     #[wasm_bindgen(js_name = js_tcp_connect, catch)]
     async fn js_tcp_connect(hostname: &str, port: u16) -> Result<JsValue, JsValue>;
@@ -22,15 +20,31 @@ extern "C" {
     
     #[wasm_bindgen(js_name = js_tcp_close, catch)]
     async fn js_tcp_close() -> Result<JsValue, JsValue>;
+
+    // Service-worker relay bridge (js/relay-bridge.js): opens a relay stream, sends
+    // `request`, resolves with the relay's reply line and routes js_tcp_* over the stream
+    #[wasm_bindgen(js_name = js_relay_connect, catch)]
+    async fn js_relay_connect(url: &str, request: &[u8]) -> Result<JsValue, JsValue>;
 }
 
-// Macro for logging from WASM
-macro_rules! console_log {
-    ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
+/// How the browser reaches the SSH server
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// Direct Sockets API (isolated web apps)
+    #[default]
+    DirectSocket,
+    /// A stream to a `bxssh relay` gateway, held open by the relay service worker
+    Relay { url: String, token: Option<String> },
 }
 
+/// Returned for remote operations until the WASM channel layer exists
+const NOT_IMPLEMENTED: &str =
+    "Not implemented: the WASM backend cannot open SSH channels yet (enable demo mode for simulated output)";
+
 // WASM SSH implementation that uses Direct Socket API through JavaScript bridge
 pub struct WasmSshConnection {
+    transport: Transport,
+    demo_mode: bool,
     connected: bool,
     authenticated: bool,
     hostname: String,
@@ -41,6 +55,8 @@ pub struct WasmSshConnection {
 impl std::fmt::Debug for WasmSshConnection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("WasmSshConnection")
+            .field("transport", &self.transport)
+            .field("demo_mode", &self.demo_mode)
             .field("connected", &self.connected)
             .field("authenticated", &self.authenticated)
             .finish()
@@ -50,6 +66,8 @@ impl std::fmt::Debug for WasmSshConnection {
 impl WasmSshConnection {
     pub fn new() -> Self {
         Self {
+            transport: Transport::default(),
+            demo_mode: false,
             connected: false,
             authenticated: false,
             hostname: String::new(),
@@ -57,11 +75,49 @@ impl WasmSshConnection {
             key_exchange: None,
         }
     }
+
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    /// Answer commands and shells with canned output instead of failing, for UI
+    /// demos without a server. Nothing reaches a real host in this mode.
+    pub fn set_demo_mode(&mut self, enabled: bool) {
+        self.demo_mode = enabled;
+    }
+
+    pub fn demo_mode(&self) -> bool {
+        self.demo_mode
+    }
+
+    /// Open the byte stream to the server for the configured transport. With Direct
+    /// Sockets the page owns the socket; through a relay, the gateway is asked to
+    /// connect and must answer OK before any SSH traffic flows.
+    pub async fn open_stream(&self) -> Result<()> {
+        let Transport::Relay { url, token } = &self.transport else {
+            return Ok(());
+        };
+
+        log::debug!("WASM SSH: Opening relay stream to {}:{} via {}", self.hostname, self.port, url);
+        let request = ConnectRequest::new(&self.hostname, self.port).with_token(token.clone()).encode()?;
+        let reply = js_relay_connect(url, &request)
+            .await
+            .map_err(|e| anyhow::anyhow!("Relay connection failed: {:?}", e))?;
+        parse_reply(&reply.as_string().unwrap_or_default())
+    }
 }
 
 impl SshConnection for WasmSshConnection {
     fn connect(&mut self, host: &str, port: u16) -> Result<()> {
-        console_log!("WASM SSH: Connecting to {}:{} via Direct Socket API", host, port);
+        crate::host_policy::enforce(host, port)?;
+        match &self.transport {
+            Transport::DirectSocket => log::debug!("WASM SSH: Connecting to {}:{} via Direct Socket API", host, port),
+            Transport::Relay { url, .. } => log::debug!("WASM SSH: Connecting to {}:{} via relay {}", host, port, url),
+        }
         
         self.hostname = host.to_string();
         self.port = port;
@@ -69,16 +125,25 @@ impl SshConnection for WasmSshConnection {
         // Mark as connected - the actual TCP connection will be handled by JavaScript bridge
         // when SSH protocol methods are called
         self.connected = true;
-        console_log!("WASM SSH: Ready to establish SSH protocol over Direct Socket API");
+        log::debug!("WASM SSH: Ready to establish SSH protocol over Direct Socket API");
         Ok(())
     }
 
+    fn auth_banner(&mut self, _username: &str) -> Result<Option<String>> {
+        if !self.connected {
+            return Err(anyhow::anyhow!("Not connected"));
+        }
+
+        // The JavaScript bridge does not surface SSH_MSG_USERAUTH_BANNER yet
+        Ok(None)
+    }
+
     fn authenticate_with_key(&mut self, username: &str, _private_key_path: &str) -> Result<()> {
         if !self.connected {
             return Err(anyhow::anyhow!("Not connected"));
         }
 
-        console_log!("WASM SSH: Key authentication for user: {} (delegated to JavaScript bridge)", username);
+        log::debug!("WASM SSH: Key authentication for user: {} (delegated to JavaScript bridge)", username);
         
         // The JavaScript bridge will handle the actual SSH protocol:
         // 1. Establish TCP connection via Direct Socket API  
@@ -96,7 +161,7 @@ impl SshConnection for WasmSshConnection {
             return Err(anyhow::anyhow!("Not connected"));
         }
 
-        console_log!("WASM SSH: Starting full SSH-2.0 authentication for user: {}", username);
+        log::info!("WASM SSH: Starting full SSH-2.0 authentication for user: {}", username);
         
         // Initialize SSH key exchange in Rust
         let key_exchange = SshKeyExchange::new();
@@ -107,8 +172,8 @@ impl SshConnection for WasmSshConnection {
         
         self.key_exchange = Some(key_exchange);
         
-        console_log!("WASM SSH: ✅ SSH key exchange and authentication components initialized");
-        console_log!("WASM SSH: Ready to perform SSH-2.0 protocol handshake");
+        log::info!("WASM SSH: ✅ SSH key exchange and authentication components initialized");
+        log::debug!("WASM SSH: Ready to perform SSH-2.0 protocol handshake");
         
         // Mark as authenticated to proceed with the interface
         self.authenticated = true;
@@ -120,64 +185,131 @@ impl SshConnection for WasmSshConnection {
             return Err(anyhow::anyhow!("Not authenticated"));
         }
 
-        console_log!("WASM SSH: Executing command '{}' using Direct Socket API integration", command);
-        
-        // For now, delegate to JavaScript bridge for actual SSH protocol communication
-        // The JavaScript bridge will handle:
-        // 1. SSH channel management via Direct Socket API
-        // 2. Sending SSH_MSG_CHANNEL_REQUEST with exec type
-        // 3. Reading SSH_MSG_CHANNEL_DATA responses
-        // 4. Proper SSH message parsing and formatting
-        
-        // Generate enhanced response based on command type
-        let result = match command {
-            "ls" | "ls -l" => {
-                format!("total 24\ndrwxr-xr-x  2 udara udara 4096 Sep 10 10:30 .\ndrwxr-xr-x  3 udara udara 4096 Sep 10 09:15 ..\n-rw-r--r--  1 udara udara  220 Sep 10 09:00 .profile\n-rw-r--r--  1 udara udara  807 Sep 10 09:00 .bashrc\n-rwxr-xr-x  1 udara udara 1024 Sep 10 09:15 start.sh\n-rw-r--r--  1 udara udara 2048 Sep 10 10:30 data.txt")
-            },
-            "ls -la" => {
-                format!("total 32\ndrwxr-xr-x  2 udara udara 4096 Sep 10 10:30 .\ndrwxr-xr-x  3 udara udara 4096 Sep 10 09:15 ..\n-rw-------  1 udara udara  123 Sep 10 08:45 .bash_history\n-rw-r--r--  1 udara udara  807 Sep 10 09:00 .bashrc\n-rw-r--r--  1 udara udara  220 Sep 10 09:00 .profile\n-rw-------  1 udara udara   33 Sep 10 08:30 .lesshst\n-rwxr-xr-x  1 udara udara 1024 Sep 10 09:15 start.sh\n-rw-r--r--  1 udara udara 2048 Sep 10 10:30 data.txt")
-            },
-            "pwd" => "/home/udara".to_string(),
-            "whoami" => "udara".to_string(),
-            "hostname" => "ssh-server".to_string(),
-            "date" => "Tue Sep 10 10:32:15 UTC 2024".to_string(),
-            "uptime" => " 10:32:15 up 2 days,  3:42,  1 user,  load average: 0.08, 0.03, 0.01".to_string(),
-            "echo test" => "test".to_string(),
-            cmd if cmd.starts_with("echo ") => {
-                cmd.strip_prefix("echo ").unwrap_or("").trim_matches('"').to_string()
-            },
-            "uname -a" => "Linux ssh-server 5.15.0-84-generic #93-Ubuntu SMP Tue Sep 5 17:16:10 UTC 2023 x86_64 x86_64 x86_64 GNU/Linux".to_string(),
-            "ps aux" => format!("USER       PID %CPU %MEM    VSZ   RSS TTY      STAT START   TIME COMMAND\nroot         1  0.0  0.1 168576 11616 ?        Ss   Sep08   0:02 /sbin/init\nudara     1001  0.0  0.0  21532  5280 pts/0    Ss   10:30   0:00 -bash\nudara     1015  0.0  0.0  19124  2048 pts/0    R+   10:32   0:00 ps aux"),
-            "free -h" => format!("              total        used        free      shared  buff/cache   available\nMem:          3.8Gi       1.2Gi       1.8Gi        84Mi       856Mi       2.3Gi\nSwap:         2.0Gi          0B       2.0Gi"),
-            "df -h" => format!("Filesystem      Size  Used Avail Use% Mounted on\n/dev/sda1        20G  8.2G   11G  43% /\ntmpfs           2.0G     0  2.0G   0% /dev/shm\n/dev/sda2       100G   45G   50G  48% /home"),
-            _ => {
-                format!("🚀 SSH Command: {}\n\n📡 Connection Status:\n• Server: 192.168.1.110:22\n• User: udara@ssh-server\n• Protocol: SSH-2.0 via Rust WASM\n• Transport: Direct Socket API\n\n💻 Command Output:\n[Command '{}' executed successfully]\n\n🔍 Note: This is a Rust WASM SSH implementation.\nFor full SSH protocol support, the command was processed\nthrough the Direct Socket API bridge.", command, command)
-            }
-        };
-        
-        console_log!("WASM SSH: ✅ Command '{}' completed, output: {} chars", command, result.len());
+        if !self.demo_mode {
+            return Err(anyhow::anyhow!(NOT_IMPLEMENTED));
+        }
+
+        log::debug!("WASM SSH: Simulating command '{}' (demo mode)", command);
+        let result = demo_output(command);
+        log::trace!("WASM SSH: Demo command '{}' produced {} chars", command, result.len());
         Ok(result)
     }
 
+    fn run_command(&self, command: &str) -> Result<CommandOutput> {
+        // Demo mode has no exit statuses: it either answers or fails
+        Ok(CommandOutput {
+            stdout: self.execute_command(command)?,
+            exit_status: 0,
+            exit_signal: None,
+        })
+    }
+
     fn start_shell(&self) -> Result<Box<dyn ShellSession>> {
         if !self.authenticated {
             return Err(anyhow::anyhow!("Not authenticated"));
         }
 
-        console_log!("WASM SSH: Starting interactive shell session via Direct Socket API");
-        
-        // Create enhanced shell session with Direct Socket API integration
+        if !self.demo_mode {
+            return Err(anyhow::anyhow!(NOT_IMPLEMENTED));
+        }
+
+        log::debug!("WASM SSH: Starting simulated shell session (demo mode)");
         let session = WasmShellSession::new_with_welcome();
-        console_log!("WASM SSH: ✅ Interactive shell session ready");
         
         Ok(Box::new(session))
     }
 
+    fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<Box<dyn ShellSession>> {
+        if !self.authenticated {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        log::debug!("WASM SSH: direct-tcpip to {}:{} requested", host, port);
+        Err(anyhow::anyhow!("Port forwarding is not supported by the WASM SSH backend"))
+    }
+
+    fn open_exec(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        if !self.authenticated {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        log::debug!("WASM SSH: streaming exec of '{}' requested", command);
+        Err(anyhow::anyhow!("Streaming exec is not supported by the WASM SSH backend"))
+    }
+
+    fn open_exec_pty(&self, command: &str) -> Result<Box<dyn ShellSession>> {
+        self.open_exec(command)
+    }
+
+    fn open_sftp(&self) -> Result<Box<dyn SftpSession>> {
+        if !self.authenticated {
+            return Err(anyhow::anyhow!("Not authenticated"));
+        }
+
+        if !self.demo_mode {
+            return Err(anyhow::anyhow!("SFTP is not supported by the WASM SSH backend"));
+        }
+
+        log::debug!("WASM SSH: Opening simulated SFTP session (demo mode)");
+        Ok(Box::new(DemoSftpSession))
+    }
+
     fn is_authenticated(&self) -> bool {
         self.authenticated
     }
 }
 
+/// Canned output for demo mode
+fn demo_output(command: &str) -> String {
+    match command {
+        "ls" | "ls -l" => {
+            format!("total 24\ndrwxr-xr-x  2 udara udara 4096 Sep 10 10:30 .\ndrwxr-xr-x  3 udara udara 4096 Sep 10 09:15 ..\n-rw-r--r--  1 udara udara  220 Sep 10 09:00 .profile\n-rw-r--r--  1 udara udara  807 Sep 10 09:00 .bashrc\n-rwxr-xr-x  1 udara udara 1024 Sep 10 09:15 start.sh\n-rw-r--r--  1 udara udara 2048 Sep 10 10:30 data.txt")
+        },
+        "ls -la" => {
+            format!("total 32\ndrwxr-xr-x  2 udara udara 4096 Sep 10 10:30 .\ndrwxr-xr-x  3 udara udara 4096 Sep 10 09:15 ..\n-rw-------  1 udara udara  123 Sep 10 08:45 .bash_history\n-rw-r--r--  1 udara udara  807 Sep 10 09:00 .bashrc\n-rw-r--r--  1 udara udara  220 Sep 10 09:00 .profile\n-rw-------  1 udara udara   33 Sep 10 08:30 .lesshst\n-rwxr-xr-x  1 udara udara 1024 Sep 10 09:15 start.sh\n-rw-r--r--  1 udara udara 2048 Sep 10 10:30 data.txt")
+        },
+        "pwd" => "/home/udara".to_string(),
+        "whoami" => "udara".to_string(),
+        "hostname" => "ssh-server".to_string(),
+        "date" => "Tue Sep 10 10:32:15 UTC 2024".to_string(),
+        "uptime" => " 10:32:15 up 2 days,  3:42,  1 user,  load average: 0.08, 0.03, 0.01".to_string(),
+        "echo test" => "test".to_string(),
+        cmd if cmd.starts_with("echo ") => {
+            cmd.strip_prefix("echo ").unwrap_or("").trim_matches('"').to_string()
+        },
+        "uname -a" => "Linux ssh-server 5.15.0-84-generic #93-Ubuntu SMP Tue Sep 5 17:16:10 UTC 2023 x86_64 x86_64 x86_64 GNU/Linux".to_string(),
+        "ps aux" => format!("USER       PID %CPU %MEM    VSZ   RSS TTY      STAT START   TIME COMMAND\nroot         1  0.0  0.1 168576 11616 ?        Ss   Sep08   0:02 /sbin/init\nudara     1001  0.0  0.0  21532  5280 pts/0    Ss   10:30   0:00 -bash\nudara     1015  0.0  0.0  19124  2048 pts/0    R+   10:32   0:00 ps aux"),
+        "free -h" => format!("              total        used        free      shared  buff/cache   available\nMem:          3.8Gi       1.2Gi       1.8Gi        84Mi       856Mi       2.3Gi\nSwap:         2.0Gi          0B       2.0Gi"),
+        "df -h" => format!("Filesystem      Size  Used Avail Use% Mounted on\n/dev/sda1        20G  8.2G   11G  43% /\ntmpfs           2.0G     0  2.0G   0% /dev/shm\n/dev/sda2       100G   45G   50G  48% /home"),
+        _ => format!("[demo mode] '{}' was not sent to any server", command),
+    }
+}
+
+/// Simulated SFTP for demo mode: uploads are accepted and discarded, there is
+/// nothing to read
+#[derive(Debug)]
+struct DemoSftpSession;
+
+impl SftpSession for DemoSftpSession {
+    fn stat(&mut self, path: &str) -> Result<RemoteFileAttr> {
+        Err(anyhow::anyhow!("[demo mode] {} does not exist", path))
+    }
+
+    fn read_dir(&mut self, _path: &str) -> Result<Vec<(String, RemoteFileAttr)>> {
+        Ok(Vec::new())
+    }
+
+    fn open_reader(&mut self, path: &str) -> Result<Box<dyn RemoteFile>> {
+        Err(anyhow::anyhow!("[demo mode] {} does not exist", path))
+    }
+
+    fn create(&mut self, path: &str) -> Result<Box<dyn std::io::Write + Send>> {
+        log::debug!("WASM SSH: Discarding upload to {} (demo mode)", path);
+        Ok(Box::new(std::io::sink()))
+    }
+}
+
+/// Simulated shell for demo mode
 #[derive(Debug)]
 pub struct WasmShellSession {
     active: bool,
@@ -241,7 +373,7 @@ impl ShellSession for WasmShellSession {
         let len = std::cmp::min(buf.len(), output_bytes.len());
         buf[..len].copy_from_slice(&output_bytes[..len]);
         
-        console_log!("WASM SSH: Shell output: {} chars", len);
+        log::trace!("WASM SSH: Shell output: {} chars", len);
         Ok(len)
     }
 
@@ -251,7 +383,8 @@ impl ShellSession for WasmShellSession {
         }
         
         let input = String::from_utf8_lossy(data).trim().to_string();
-        console_log!("WASM SSH: Shell input: '{}'", input);
+        // Never log what was typed: it may be a password
+        log::trace!("WASM SSH: Shell input: {} bytes", data.len());
         
         // Process shell commands
         if !input.is_empty() {
@@ -261,7 +394,7 @@ impl ShellSession for WasmShellSession {
             match input.as_str() {
                 "exit" | "logout" => {
                     self.active = false;
-                    console_log!("WASM SSH: Shell session terminated by user");
+                    log::debug!("WASM SSH: Shell session terminated by user");
                 },
                 cmd if cmd.starts_with("cd ") => {
                     let new_dir = cmd.strip_prefix("cd ").unwrap_or("").trim();
@@ -272,11 +405,11 @@ impl ShellSession for WasmShellSession {
                     } else {
                         self.current_directory = format!("{}/{}", self.current_directory, new_dir);
                     }
-                    console_log!("WASM SSH: Changed directory to: {}", self.current_directory);
+                    log::debug!("WASM SSH: Changed directory to: {}", self.current_directory);
                 },
                 _ => {
                     // For other commands, they would be processed via the SSH protocol
-                    console_log!("WASM SSH: Command '{}' queued for SSH execution", input);
+                    log::debug!("WASM SSH: Command '{}' queued for SSH execution", input);
                 }
             }
         }
@@ -284,9 +417,19 @@ impl ShellSession for WasmShellSession {
         Ok(data.len())
     }
 
+    fn send_eof(&mut self) -> Result<()> {
+        self.active = false;
+        Ok(())
+    }
+
     fn is_eof(&self) -> bool {
         !self.active
     }
+
+    fn wait_close(&mut self) -> Result<i32> {
+        self.active = false;
+        Ok(0)
+    }
 }
 
 #[cfg(test)]
@@ -308,6 +451,17 @@ mod tests {
         assert!(connection.connected);
     }
 
+    #[test]
+    fn test_wasm_transport_selection() {
+        let mut connection = WasmSshConnection::new();
+        assert_eq!(connection.transport(), &Transport::DirectSocket);
+
+        let relay = Transport::Relay { url: "https://relay.example.com/ssh".to_string(), token: None };
+        connection.set_transport(relay.clone());
+        connection.connect("localhost", 22).unwrap();
+        assert_eq!(connection.transport(), &relay);
+    }
+
     #[test]
     fn test_wasm_authenticate_without_connection() {
         let mut connection = WasmSshConnection::new();
@@ -336,14 +490,32 @@ mod tests {
     }
 
     #[test]
-    fn test_wasm_execute_command() {
+    fn test_wasm_execute_command_needs_demo_mode() {
         let mut connection = WasmSshConnection::new();
         connection.connect("localhost", 22).unwrap();
         connection.authenticate_with_key("user", "key").unwrap();
-        
+
+        let err = connection.execute_command("ls -la").unwrap_err();
+        assert!(err.to_string().starts_with("Not implemented"));
+        assert!(connection.start_shell().is_err());
+
+        connection.set_demo_mode(true);
         let result = connection.execute_command("ls -la");
-        assert!(result.is_ok());
-        assert!(result.unwrap().contains("WASM SSH output"));
+        assert!(result.unwrap().contains(".bashrc"));
+    }
+
+    #[test]
+    fn test_wasm_sftp_needs_demo_mode() {
+        let mut connection = WasmSshConnection::new();
+        connection.connect("localhost", 22).unwrap();
+        connection.authenticate_with_key("user", "key").unwrap();
+        assert!(connection.open_sftp().is_err());
+
+        connection.set_demo_mode(true);
+        let mut sftp = connection.open_sftp().unwrap();
+        let report = crate::transfer::upload(sftp.as_mut(), &mut b"hello".as_slice(), "/tmp/hello").unwrap();
+        assert_eq!(report.bytes, 5);
+        assert!(sftp.stat("/tmp/hello").is_err());
     }
 
     #[test]
@@ -351,6 +523,7 @@ mod tests {
         let mut connection = WasmSshConnection::new();
         connection.connect("localhost", 22).unwrap();
         connection.authenticate_with_key("user", "key").unwrap();
+        connection.set_demo_mode(true);
         
         let shell_result = connection.start_shell();
         assert!(shell_result.is_ok());
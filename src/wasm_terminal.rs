@@ -1,7 +1,5 @@
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
-#[cfg(target_arch = "wasm32")]
-use web_sys::console;
 
 use anyhow::Result;
 use std::collections::VecDeque;
@@ -65,13 +63,11 @@ impl TerminalIO for WasmTerminalIO {
                 let js_str = JsValue::from_str(&output_str);
                 
                 if let Err(e) = callback.call1(&JsValue::NULL, &js_str) {
-                    console::error_1(&format!("Output callback error: {:?}", e).into());
+                    log::error!("Output callback error: {:?}", e);
                 }
             } else {
-                // Fallback to console output
-                let output_str = String::from_utf8_lossy(data);
-                let js_str: JsValue = output_str.as_ref().into();
-                console::log_1(&js_str);
+                // Without a callback the output only reaches the console, through the logger
+                log::trace!("Session output: {}", String::from_utf8_lossy(data));
             }
         }
         
@@ -93,14 +89,14 @@ impl TerminalIO for WasmTerminalIO {
     
     fn initialize(&mut self) -> Result<()> {
         #[cfg(target_arch = "wasm32")]
-        console::log_1(&"🔗 WebAssembly SSH session started".into());
+        log::debug!("WebAssembly SSH session started");
         
         Ok(())
     }
     
     fn cleanup(&mut self) -> Result<()> {
         #[cfg(target_arch = "wasm32")]
-        console::log_1(&"🔌 WebAssembly SSH session ended".into());
+        log::debug!("WebAssembly SSH session ended");
         
         Ok(())
     }
@@ -154,7 +150,7 @@ impl WasmSSHSession {
     pub async fn start_session(&mut self, host: &str, username: &str, _password: &str) -> Result<(), JsValue> {
         // This would integrate with the WebSocket SSH implementation
         // For now, just log the attempt
-        console::log_1(&format!("Starting WASM SSH session to {}@{}", username, host).into());
+        log::debug!("Starting WASM SSH session to {}@{}", username, host);
         
         // TODO: Create actual SSH session using WASM SSH implementation
         // let ssh_session = create_wasm_ssh_session(host, username, password).await?;
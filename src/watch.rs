@@ -0,0 +1,217 @@
+//! `bxssh watch`: follow changes under a remote path over one exec channel. The
+//! remote side runs `inotifywait` when it is installed and a `find`-based poll
+//! otherwise, printing one line per change; changes arriving close together are
+//! handed to the callback as one batch.
+use anyhow::{Context, Result};
+use std::time::{Duration, Instant};
+
+use crate::ssh_client::ShellSession;
+use crate::transfer::shell_quote;
+
+/// Changes closer together than this are reported together
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How the remote side detects changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Inotify,
+    Poll,
+}
+
+/// One line of the remote watcher's output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The watcher started, using this method
+    Started(Method),
+    /// Something under the watched path changed: a file's contents, or an entry
+    /// created, deleted or moved
+    Changed(String),
+}
+
+impl Event {
+    /// Parse one output line; anything else is ignored
+    pub fn parse(line: &str) -> Option<Event> {
+        match line.split_once(' ') {
+            Some(("changed", path)) if !path.is_empty() => Some(Event::Changed(path.to_string())),
+            Some(("watching", "inotify")) => Some(Event::Started(Method::Inotify)),
+            Some(("watching", "poll")) => Some(Event::Started(Method::Poll)),
+            _ => None,
+        }
+    }
+}
+
+/// POSIX shell script watching `path`: `inotifywait` unless `force_poll`, else a
+/// scan every `interval` for files newer than the last scan and for a changed
+/// entry count (deletions)
+pub fn remote_command(path: &str, interval: Duration, force_poll: bool) -> String {
+    let path = shell_quote(path);
+    let interval = interval.as_secs().max(1);
+    let inotify = if force_poll {
+        String::new()
+    } else {
+        format!(
+            "if command -v inotifywait >/dev/null 2>&1; then \
+             echo 'watching inotify'; \
+             exec inotifywait -m -r -q -e close_write,create,delete,move --format 'changed %w%f' -- {path}; \
+             fi; "
+        )
+    };
+    format!(
+        "[ -e {path} ] || {{ echo 'No such file or directory: '{path} >&2; exit 1; }}; \
+         {inotify}\
+         echo 'watching poll'; \
+         stamp=$(mktemp) || exit 1; trap 'rm -f \"$stamp\" \"$stamp.next\"' EXIT; trap 'exit 0' HUP INT TERM; \
+         count=$(find {path} | wc -l); \
+         while sleep {interval}; do \
+         touch \"$stamp.next\"; \
+         find {path} -newer \"$stamp\" ! -type d | sed 's/^/changed /'; \
+         now=$(find {path} | wc -l); [ \"$now\" = \"$count\" ] || echo 'changed '{path}; count=$now; \
+         mv \"$stamp.next\" \"$stamp\"; \
+         done"
+    )
+}
+
+/// Read events from `session` (the remote command's output) until it ends, calling
+/// `on_start` once the watcher runs and `on_change` with each batch of changed
+/// paths, in order and without repeats. Fails when the remote watcher exits.
+pub fn run(
+    session: &mut dyn ShellSession,
+    debounce: Duration,
+    mut on_start: impl FnMut(Method),
+    mut on_change: impl FnMut(&[String]) -> Result<()>,
+) -> Result<()> {
+    let mut buffer = [0u8; 8192];
+    let mut line = Vec::new();
+    let mut pending: Vec<String> = Vec::new();
+    let mut last_change = Instant::now();
+
+    loop {
+        let n = session.read(&mut buffer)?;
+        for &byte in &buffer[..n] {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            match Event::parse(&String::from_utf8_lossy(&line)) {
+                Some(Event::Started(method)) => on_start(method),
+                Some(Event::Changed(path)) => {
+                    if !pending.contains(&path) {
+                        pending.push(path);
+                    }
+                    last_change = Instant::now();
+                }
+                None => log::debug!("Ignoring watcher output: {}", String::from_utf8_lossy(&line)),
+            }
+            line.clear();
+        }
+
+        if !pending.is_empty() && last_change.elapsed() >= debounce {
+            on_change(&pending)?;
+            pending.clear();
+        }
+
+        if n == 0 {
+            if session.is_eof() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    if !pending.is_empty() {
+        on_change(&pending)?;
+    }
+    let status = session.wait_close().context("Remote watcher failed")?;
+    Err(anyhow::anyhow!("Remote watcher exited with status {}", status))
+}
+
+/// Run `command` through the local shell with the changed paths in
+/// `BXSSH_CHANGED`, one per line. A failing command is reported, not fatal.
+pub fn run_local_command(command: &str, changed: &[String]) -> Result<()> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let status = std::process::Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .env("BXSSH_CHANGED", changed.join("\n"))
+        .status()
+        .with_context(|| format!("Failed to run '{}'", command))?;
+    if !status.success() {
+        log::warn!("'{}' exited with {}", command, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_client::SshClient;
+    use crate::testing::{Script, ScriptedSshServer};
+
+    #[test]
+    fn test_parse_events() {
+        assert_eq!(Event::parse("watching inotify"), Some(Event::Started(Method::Inotify)));
+        assert_eq!(Event::parse("watching poll"), Some(Event::Started(Method::Poll)));
+        assert_eq!(Event::parse("changed /srv/app/main.rs"), Some(Event::Changed("/srv/app/main.rs".into())));
+        assert_eq!(Event::parse("changed /srv/a file"), Some(Event::Changed("/srv/a file".into())));
+        assert_eq!(Event::parse("changed "), None);
+        assert_eq!(Event::parse("Setting up watches."), None);
+    }
+
+    #[test]
+    fn test_remote_command() {
+        let command = remote_command("/srv/it's", Duration::from_millis(10), false);
+        assert!(command.contains("exec inotifywait -m -r -q"));
+        assert!(command.contains("-- '/srv/it'\\''s'"));
+        assert!(command.contains("while sleep 1; do"));
+
+        let poll = remote_command("/srv", Duration::from_secs(5), true);
+        assert!(!poll.contains("inotifywait"));
+        assert!(poll.contains("while sleep 5; do"));
+    }
+
+    #[test]
+    fn test_run_batches_changes() {
+        let script = Script::new()
+            .send("watching inotify\n")
+            .send("changed /srv/a.rs\nchanged /srv/b.rs\nchanged /srv/a.rs\n")
+            .delay(Duration::from_millis(200))
+            .send("changed /srv/c")
+            .send(".rs\n")
+            .exit_status(1)
+            .eof();
+        let mut client = SshClient::new(Box::new(
+            ScriptedSshServer::new().accept_key("alice").command("watch", script),
+        ));
+        client.connect("example.com", 22).unwrap();
+        client.authenticate_with_key("alice", "/tmp/key").unwrap();
+        let mut session = client.open_exec("watch").unwrap();
+
+        let mut methods = Vec::new();
+        let mut batches = Vec::new();
+        let err = run(
+            session.as_mut(),
+            Duration::from_millis(50),
+            |method| methods.push(method),
+            |changed| {
+                batches.push(changed.to_vec());
+                Ok(())
+            },
+        )
+        .unwrap_err();
+
+        assert_eq!(methods, [Method::Inotify]);
+        assert_eq!(batches, [vec!["/srv/a.rs", "/srv/b.rs"], vec!["/srv/c.rs"]]);
+        assert_eq!(err.to_string(), "Remote watcher exited with status 1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_local_command_sees_changes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let command = format!("printf '%s' \"$BXSSH_CHANGED\" > '{}'", out.display());
+        run_local_command(&command, &["/srv/a".to_string(), "/srv/b".to_string()]).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "/srv/a\n/srv/b");
+        run_local_command("exit 3", &[]).unwrap();
+    }
+}
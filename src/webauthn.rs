@@ -0,0 +1,64 @@
+//! WebAuthn PRF assertions for unlocking the browser key store
+//!
+//! The credential must be a platform authenticator registered with the `prf`
+//! extension enabled (`extensions: { prf: {} }` in `navigator.credentials.create`).
+
+use js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+fn set(target: &Object, key: &str, value: &JsValue) -> Result<(), JsValue> {
+    Reflect::set(target, &JsValue::from_str(key), value).map(|_| ())
+}
+
+fn get(target: &JsValue, key: &str) -> Result<JsValue, JsValue> {
+    Reflect::get(target, &JsValue::from_str(key))
+}
+
+/// Run a user-verified assertion with `credential_id` and return its PRF output for
+/// the key store salt. Fails if WebAuthn or the PRF extension is unavailable.
+pub async fn prf_secret(credential_id: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let credentials = get(&get(&js_sys::global(), "navigator")?, "credentials")?;
+    if credentials.is_undefined() {
+        return Err(JsValue::from_str("WebAuthn is not available in this context"));
+    }
+
+    let mut challenge = [0u8; 32];
+    getrandom::getrandom(&mut challenge).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let allowed = Object::new();
+    set(&allowed, "type", &JsValue::from_str("public-key"))?;
+    set(&allowed, "id", &Uint8Array::from(credential_id).into())?;
+
+    let eval = Object::new();
+    set(&eval, "first", &Uint8Array::from(&crate::agent::webauthn_prf_salt()[..]).into())?;
+    let prf = Object::new();
+    set(&prf, "eval", &eval)?;
+    let extensions = Object::new();
+    set(&extensions, "prf", &prf)?;
+
+    let public_key = Object::new();
+    set(&public_key, "challenge", &Uint8Array::from(&challenge[..]).into())?;
+    set(&public_key, "allowCredentials", &Array::of1(&allowed))?;
+    set(&public_key, "userVerification", &JsValue::from_str("required"))?;
+    set(&public_key, "extensions", &extensions)?;
+    let options = Object::new();
+    set(&options, "publicKey", &public_key)?;
+
+    let get_assertion: Function = get(&credentials, "get")?.dyn_into()?;
+    let promise: Promise = get_assertion.call1(&credentials, &options)?.dyn_into()?;
+    let credential = JsFuture::from(promise).await?;
+    if credential.is_null() || credential.is_undefined() {
+        return Err(JsValue::from_str("No WebAuthn credential was returned"));
+    }
+
+    let extension_results: Function = get(&credential, "getClientExtensionResults")?.dyn_into()?;
+    let results = extension_results.call0(&credential)?;
+    let first = get(&results, "prf")
+        .and_then(|prf| if prf.is_undefined() { Ok(prf) } else { get(&prf, "results") })
+        .and_then(|results| if results.is_undefined() { Ok(results) } else { get(&results, "first") })?;
+    if first.is_undefined() {
+        return Err(JsValue::from_str("This authenticator does not support the WebAuthn PRF extension"));
+    }
+    Ok(Uint8Array::new(&first).to_vec())
+}
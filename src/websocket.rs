@@ -0,0 +1,388 @@
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use sha1::{Digest, Sha1};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Appended to the client's key to form Sec-WebSocket-Accept (RFC 6455 section 1.3)
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest HTTP request head read during the handshake
+const MAX_HEAD: usize = 8 * 1024;
+
+/// Largest message accepted from a client; browsers send one message per socket write
+pub const MAX_MESSAGE: usize = 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Server end of a WebSocket (RFC 6455) over a TCP stream. Clones share the
+/// connection, so one thread can receive while another sends.
+#[derive(Debug)]
+pub struct WebSocket {
+    reader: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+}
+
+impl WebSocket {
+    /// Complete the opening handshake. `request_line` is the `GET ...` line the caller
+    /// already read; the headers are read from `stream`. Browser pages may connect
+    /// from the `origins` given, or from the same host when there are none.
+    pub fn accept(mut stream: TcpStream, request_line: &str, origins: &[String]) -> Result<Self> {
+        let head = read_head(&mut stream)?;
+        match handshake_response(request_line, &head, origins) {
+            Ok(response) => {
+                stream.write_all(response.as_bytes())?;
+                let writer = Arc::new(Mutex::new(stream.try_clone()?));
+                Ok(Self { reader: stream, writer })
+            }
+            Err(e) => {
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n");
+                Err(e)
+            }
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            reader: self.reader.try_clone()?,
+            writer: Arc::clone(&self.writer),
+        })
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.reader.set_read_timeout(timeout)?)
+    }
+
+    /// Next text or binary message; `None` once the client closes the socket.
+    /// Pings are answered along the way.
+    pub fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut message = Vec::new();
+        let mut in_message = false;
+        loop {
+            let (fin, opcode, payload) = match self.read_frame()? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            match opcode {
+                OP_PING => self.send_frame(OP_PONG, &payload)?,
+                OP_PONG => {}
+                OP_CLOSE => {
+                    let _ = self.send_frame(OP_CLOSE, &payload);
+                    return Ok(None);
+                }
+                OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                    if (opcode == OP_CONTINUATION) != in_message {
+                        return Err(anyhow::anyhow!("Unexpected WebSocket continuation frame"));
+                    }
+                    if message.len() + payload.len() > MAX_MESSAGE {
+                        return Err(anyhow::anyhow!("WebSocket message larger than {} bytes", MAX_MESSAGE));
+                    }
+                    message.extend_from_slice(&payload);
+                    if fin {
+                        return Ok(Some(message));
+                    }
+                    in_message = true;
+                }
+                _ => return Err(anyhow::anyhow!("Unknown WebSocket opcode {:#x}", opcode)),
+            }
+        }
+    }
+
+    /// Send `data` as one binary message
+    pub fn send(&self, data: &[u8]) -> Result<()> {
+        self.send_frame(OP_BINARY, data)
+    }
+
+    /// Start the closing handshake and stop sending
+    pub fn close(&self) -> Result<()> {
+        self.send_frame(OP_CLOSE, &1000u16.to_be_bytes())?;
+        let writer = self.writer.lock().map_err(|_| anyhow::anyhow!("WebSocket lock poisoned"))?;
+        let _ = writer.shutdown(Shutdown::Write);
+        Ok(())
+    }
+
+    fn send_frame(&self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+
+        let mut writer = self.writer.lock().map_err(|_| anyhow::anyhow!("WebSocket lock poisoned"))?;
+        writer.write_all(&frame).context("Failed to send WebSocket frame")
+    }
+
+    /// One frame as (fin, opcode, unmasked payload); `None` on a clean EOF between frames
+    fn read_frame(&mut self) -> Result<Option<(bool, u8, Vec<u8>)>> {
+        let mut header = [0u8; 2];
+        match self.reader.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut header[1..])?,
+        }
+        let (fin, opcode) = (header[0] & 0x80 != 0, header[0] & 0x0F);
+        if header[0] & 0x70 != 0 {
+            return Err(anyhow::anyhow!("WebSocket extensions are not supported"));
+        }
+        if header[1] & 0x80 == 0 {
+            return Err(anyhow::anyhow!("Client WebSocket frames must be masked"));
+        }
+
+        let len = match header[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                self.reader.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.reader.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if len > MAX_MESSAGE as u64 {
+            return Err(anyhow::anyhow!("WebSocket frame larger than {} bytes", MAX_MESSAGE));
+        }
+
+        let mut mask = [0u8; 4];
+        self.reader.read_exact(&mut mask)?;
+        let mut payload = vec![0u8; len as usize];
+        self.reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok(Some((fin, opcode, payload)))
+    }
+}
+
+/// Read header lines up to the blank line that ends the request head
+fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") && !head.ends_with(b"\n\n") && head != b"\r\n" && head != b"\n" {
+        if head.len() >= MAX_HEAD {
+            return Err(anyhow::anyhow!("HTTP request head larger than {} bytes", MAX_HEAD));
+        }
+        if stream.read(&mut byte).context("Failed to read HTTP request")? == 0 {
+            return Err(anyhow::anyhow!("Client closed during the WebSocket handshake"));
+        }
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).context("HTTP request head is not UTF-8")
+}
+
+/// The 101 response for a valid upgrade request from an allowed origin
+fn handshake_response(request_line: &str, head: &str, origins: &[String]) -> Result<String> {
+    if !request_line.starts_with("GET ") || !request_line.trim_end().ends_with("HTTP/1.1") {
+        return Err(anyhow::anyhow!("Not an HTTP/1.1 GET request"));
+    }
+
+    let header = |name: &str| {
+        head.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    };
+    let has_token = |name: &str, token: &str| {
+        header(name).is_some_and(|value| value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token)))
+    };
+
+    if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+        return Err(anyhow::anyhow!("Request is not a WebSocket upgrade"));
+    }
+    if header("Sec-WebSocket-Version") != Some("13") {
+        return Err(anyhow::anyhow!("Unsupported WebSocket version"));
+    }
+    let key = header("Sec-WebSocket-Key").ok_or_else(|| anyhow::anyhow!("Missing Sec-WebSocket-Key"))?;
+    // Other pages in the browser could otherwise use the socket (cross-site WebSocket
+    // hijacking); clients that aren't browsers send no Origin
+    if let Some(origin) = header("Origin") {
+        if !origin_allowed(origin, header("Host"), origins) {
+            return Err(anyhow::anyhow!("Origin {} is not allowed", origin));
+        }
+    }
+
+    Ok(format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    ))
+}
+
+/// Whether a page from `origin` may connect: one of `allowed`, or without those,
+/// a page served by `host`, the host the request was sent to
+fn origin_allowed(origin: &str, host: Option<&str>, allowed: &[String]) -> bool {
+    let origin = origin.trim_end_matches('/');
+    if !allowed.is_empty() {
+        return allowed.iter().any(|entry| entry.trim_end_matches('/').eq_ignore_ascii_case(origin));
+    }
+    match (origin.split_once("://"), host) {
+        (Some((_, authority)), Some(host)) => authority.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    general_purpose::STANDARD.encode(Sha1::digest(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    /// A masked client frame
+    pub(crate) fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        frame
+    }
+
+    /// Read one unmasked server frame as (opcode, payload)
+    pub(crate) fn server_frame(stream: &mut impl Read) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        let len = match header[1] {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).unwrap();
+        (header[0] & 0x0F, payload)
+    }
+
+    /// Connect to `addr` and complete the client side of the handshake
+    pub(crate) fn client_handshake(addr: std::net::SocketAddr) -> BufReader<TcpStream> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /ssh HTTP/1.1\r\nHost: relay\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        assert_eq!(status, "HTTP/1.1 101 Switching Protocols\r\n");
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                return reader;
+            }
+        }
+    }
+
+    fn accept_one() -> (std::net::SocketAddr, std::thread::JoinHandle<Result<WebSocket>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = Vec::new();
+            let mut byte = [0u8; 1];
+            while byte[0] != b'\n' {
+                stream.read_exact(&mut byte).unwrap();
+                line.push(byte[0]);
+            }
+            WebSocket::accept(stream, std::str::from_utf8(&line).unwrap(), &[])
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_handshake_rejects_plain_http() {
+        let err = handshake_response("GET / HTTP/1.1\r\n", "Host: relay\r\n\r\n", &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Request is not a WebSocket upgrade");
+        let head = "Upgrade: websocket\r\nConnection: Upgrade\r\n";
+        assert!(handshake_response("POST / HTTP/1.1", head, &[]).is_err());
+    }
+
+    #[test]
+    fn test_handshake_checks_origin() {
+        let head = |origin: &str| {
+            format!(
+                "Host: relay.example.com:8022\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+                origin
+            )
+        };
+        let accept = |origin: &str, allowed: &[String]| handshake_response("GET / HTTP/1.1", &head(origin), allowed);
+
+        // Same host, or no Origin at all (not a browser)
+        assert!(accept("Origin: https://relay.example.com:8022\r\n", &[]).is_ok());
+        assert!(accept("", &[]).is_ok());
+        let err = accept("Origin: https://evil.example\r\n", &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Origin https://evil.example is not allowed");
+        assert!(accept("Origin: null\r\n", &[]).is_err());
+
+        // An explicit list replaces the same-host rule
+        let allowed = vec!["https://app.example.com/".to_string()];
+        assert!(accept("Origin: https://app.example.com\r\n", &allowed).is_ok());
+        assert!(accept("Origin: https://relay.example.com:8022\r\n", &allowed).is_err());
+    }
+
+    #[test]
+    fn test_messages_pings_and_close() {
+        let (addr, server) = accept_one();
+        let mut client = client_handshake(addr);
+        let mut ws = server.join().unwrap().unwrap();
+
+        client.get_mut().write_all(&client_frame(false, OP_BINARY, b"hello ")).unwrap();
+        client.get_mut().write_all(&client_frame(true, OP_PING, b"p")).unwrap();
+        client.get_mut().write_all(&client_frame(true, OP_CONTINUATION, &[b'x'; 200])).unwrap();
+        let message = ws.recv().unwrap().unwrap();
+        assert_eq!(&message[..6], b"hello ");
+        assert_eq!(message.len(), 206);
+        assert_eq!(server_frame(&mut client), (OP_PONG, b"p".to_vec()));
+
+        ws.send(b"world").unwrap();
+        assert_eq!(server_frame(&mut client), (OP_BINARY, b"world".to_vec()));
+
+        client.get_mut().write_all(&client_frame(true, OP_CLOSE, &1000u16.to_be_bytes())).unwrap();
+        assert!(ws.recv().unwrap().is_none());
+        assert_eq!(server_frame(&mut client).0, OP_CLOSE);
+    }
+
+    #[test]
+    fn test_unmasked_frames_are_rejected() {
+        let (addr, server) = accept_one();
+        let mut client = client_handshake(addr);
+        let mut ws = server.join().unwrap().unwrap();
+
+        client.get_mut().write_all(&[0x82, 0x01, b'x']).unwrap();
+        assert_eq!(ws.recv().unwrap_err().to_string(), "Client WebSocket frames must be masked");
+    }
+}
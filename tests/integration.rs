@@ -1,7 +1,19 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
+use std::path::Path;
 use std::process::Command;
 
+/// bxssh with `home` as its home directory, and none of the variables that would
+/// move its files elsewhere (so tests never touch the real ones) or log to stderr
+fn bxssh(home: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.env("HOME", home);
+    for name in ["BXSSH_HOME", "XDG_CONFIG_HOME", "XDG_DATA_HOME", "XDG_STATE_HOME", "RUST_LOG"] {
+        cmd.env_remove(name);
+    }
+    cmd
+}
+
 #[test]
 fn test_cli_help() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
@@ -37,7 +49,7 @@ fn test_cli_missing_required_args() {
 #[test]
 fn test_cli_invalid_port() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
-    cmd.args(&["--user", "testuser", "--port", "invalid", "localhost"]);
+    cmd.args(["--user", "testuser", "--port", "invalid", "localhost"]);
     
     cmd.assert()
         .failure()
@@ -47,7 +59,7 @@ fn test_cli_invalid_port() {
 #[test]
 fn test_cli_port_out_of_range() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
-    cmd.args(&["--user", "testuser", "--port", "70000", "localhost"]);
+    cmd.args(["--user", "testuser", "--port", "70000", "localhost"]);
     
     cmd.assert()
         .failure()
@@ -57,7 +69,7 @@ fn test_cli_port_out_of_range() {
 #[test]
 fn test_cli_with_valid_args_but_connection_fails() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
-    cmd.args(&[
+    cmd.args([
         "--user", "testuser", 
         "--identity", "/nonexistent/key",
         "nonexistent-host.local"
@@ -71,7 +83,7 @@ fn test_cli_with_valid_args_but_connection_fails() {
 #[test]
 fn test_cli_with_command_option() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
-    cmd.args(&[
+    cmd.args([
         "--user", "testuser",
         "--command", "echo hello",
         "--identity", "/nonexistent/key", 
@@ -86,7 +98,7 @@ fn test_cli_with_command_option() {
 #[test]
 fn test_cli_with_custom_port() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
-    cmd.args(&[
+    cmd.args([
         "--user", "testuser",
         "--port", "2222",
         "--identity", "/nonexistent/key",
@@ -101,7 +113,7 @@ fn test_cli_with_custom_port() {
 #[test]
 fn test_cli_empty_username() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
-    cmd.args(&["--user", "", "localhost"]);
+    cmd.args(["--user", "", "localhost"]);
     
     cmd.assert()
         .failure();
@@ -110,9 +122,700 @@ fn test_cli_empty_username() {
 #[test]
 fn test_cli_empty_host() {
     let mut cmd = Command::cargo_bin("bxssh").unwrap();
-    cmd.args(&["--user", "testuser", ""]);
+    cmd.args(["--user", "testuser", ""]);
     
     // This tests empty hostname handling
     cmd.assert()
         .failure();
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_cli_stdio_forward_invalid_target() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["-W", "no-port-given", "testuser@localhost"]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid forward target"));
+}
+
+#[test]
+fn test_cli_stdio_forward_conflicts_with_command() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["-W", "internal:22", "-c", "ls", "testuser@localhost"]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_cli_notify_needs_a_command() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["--notify", "testuser@localhost"]);
+    cmd.assert().failure().stderr(predicate::str::contains("required arguments were not provided"));
+}
+
+#[test]
+fn test_cli_tmux_session_name() {
+    let home = tempfile::TempDir::new().unwrap();
+    let mut cmd = bxssh(home.path());
+    cmd.args(["--tmux=a:b", "testuser@localhost"]);
+    cmd.assert().failure().stderr(predicate::str::contains("Invalid tmux session name 'a:b'"));
+
+    // The session name needs `=`, so a following target stays the target
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["--tmux", "testuser@localhost", "-c", "ls"]);
+    cmd.assert().failure().stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_cli_auto_forward_is_for_interactive_shells() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["--auto-forward", "-c", "ls", "testuser@localhost"]);
+    cmd.assert().failure().stderr(predicate::str::contains("cannot be used with"));
+
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["--scan-ports", "testuser@localhost"]);
+    cmd.assert().failure().stderr(predicate::str::contains("--auto-forward"));
+}
+
+#[test]
+fn test_cli_latency_is_for_interactive_shells() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["--latency", "-c", "ls", "testuser@localhost"]);
+    cmd.assert().failure().stderr(predicate::str::contains("cannot be used with"));
+
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["--latency-warn", "0", "testuser@localhost"]);
+    cmd.assert().failure().stderr(predicate::str::contains("--latency-warn"));
+}
+
+#[test]
+fn test_cli_print_config_with_openssh_options() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["-o", "User=git", "-o", "Port=2222", "-G", "example.com"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("user git"))
+        .stdout(predicate::str::contains("hostname example.com"))
+        .stdout(predicate::str::contains("port 2222"));
+}
+
+#[test]
+fn test_cli_port_flag_overrides_option() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["-p", "2200", "-o", "Port=2222", "-G", "git@example.com"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("port 2200"));
+}
+
+#[test]
+fn test_cli_print_config_with_several_identities() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["-i", "~/.ssh/work", "-i", "deploy", "-o", "IdentitiesOnly=yes", "-G", "git@example.com"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("identityfile ~/.ssh/work\nidentityfile deploy\nidentitiesonly yes\n"));
+}
+
+#[test]
+fn test_cli_print_config_with_profile() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(home.path().join(".bxssh")).unwrap();
+    std::fs::write(
+        home.path().join(".bxssh").join("profiles.toml"),
+        "[prod]\nHostName = \"prod.example.com\"\nUser = \"deploy\"\nPort = 2222\n\
+         KnockSequence = [\"tcp:7000\", \"udp:8000\"]\n",
+    )
+    .unwrap();
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-G", "prod"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("user deploy"))
+        .stdout(predicate::str::contains("hostname prod.example.com"))
+        .stdout(predicate::str::contains("port 2222"))
+        .stdout(predicate::str::contains("knocksequence tcp:7000,udp:8000"));
+
+    // Explicit flags still win over the profile
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-p", "22", "-G", "admin@prod"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("user admin"))
+        .stdout(predicate::str::contains("port 22\n"));
+}
+
+#[test]
+fn test_cli_recent_lists_connections_then_profiles() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(home.path().join(".bxssh")).unwrap();
+    std::fs::write(home.path().join(".bxssh").join("profiles.toml"), "[prod]\nUser = \"deploy\"\n\n[staging]\n").unwrap();
+    std::fs::write(
+        home.path().join(".bxssh").join("recent.json"),
+        r#"{"connections": [{"host": "prod", "user": "deploy", "port": 2222, "last_connected": "2026-05-01T09:30:00Z"}]}"#,
+    )
+    .unwrap();
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["--plain", "recent", "--list"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(" 1  deploy@prod:2222  2026-05-01 09:30 UTC"))
+        .stdout(predicate::str::contains(" 2  staging  (profile)"));
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["recent", "--clear"]);
+    cmd.assert().success();
+    let mut cmd = bxssh(home.path());
+    cmd.args(["--plain", "recent", "--list"]);
+    cmd.assert().success().stdout(predicate::str::contains("deploy@prod").not());
+}
+
+#[test]
+fn test_cli_print_config_with_host_patterns() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(home.path().join(".bxssh")).unwrap();
+    std::fs::write(
+        home.path().join(".bxssh").join("profiles.toml"),
+        "[\"*\"]\nCanonicalizeHostname = true\nCanonicalDomains = [\"invalid\"]\n\n\
+         [\"build*\"]\nUser = \"ci\"\nPort = 2200\n",
+    )
+    .unwrap();
+
+    // A trailing dot is already canonical; the stripped name still picks up its section
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-G", "build."]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("user ci"))
+        .stdout(predicate::str::contains("hostname build\n"))
+        .stdout(predicate::str::contains("port 2200"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_print_config_with_match_exec() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(home.path().join(".bxssh")).unwrap();
+    std::fs::write(
+        home.path().join(".bxssh").join("profiles.toml"),
+        "[never]\nMatch = { Exec = \"false\" }\nPort = 1111\n\n\
+         [deploys]\nMatch = { User = \"deploy\", Exec = \"test \\\"$BXSSH_HOST\\\" = web\" }\nPort = 2222\n",
+    )
+    .unwrap();
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-G", "deploy@web"]);
+    cmd.assert().success().stdout(predicate::str::contains("port 2222"));
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-G", "alice@web"]);
+    cmd.assert().success().stdout(predicate::str::contains("port 22\n"));
+}
+
+#[test]
+fn test_cli_config_dump_layers_profiles_and_ssh_config() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(home.path().join(".bxssh")).unwrap();
+    std::fs::create_dir(home.path().join(".ssh")).unwrap();
+    std::fs::write(
+        home.path().join(".bxssh").join("profiles.toml"),
+        "[\"*\"]\nPort = 2200\n\n[prod]\nUser = \"deploy\"\n",
+    )
+    .unwrap();
+    std::fs::write(home.path().join(".ssh").join("work"), "Host prod\n  HostName prod.example.com\n  User ignored\n").unwrap();
+    std::fs::write(home.path().join(".ssh").join("config"), "Include work\n").unwrap();
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["config", "dump", "--host", "prod"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("# [prod] "))
+        .stdout(predicate::str::contains("# [*] "))
+        .stdout(predicate::str::contains("user deploy\n"))
+        .stdout(predicate::str::contains("hostname prod.example.com\n"))
+        .stdout(predicate::str::contains("port 2200\n"));
+}
+
+#[test]
+fn test_cli_config_validate() {
+    let home = tempfile::TempDir::new().unwrap();
+    bxssh(home.path())
+        .args(["config", "validate"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("defaults apply"));
+
+    std::fs::create_dir_all(home.path().join(".config/bxssh")).unwrap();
+    let settings = home.path().join(".config/bxssh/config.toml");
+    std::fs::write(&settings, "[terminal]\ntheme = \"none\"\n\n[logging]\nlevel = \"warn\"\n").unwrap();
+    bxssh(home.path()).args(["config", "validate"]).assert().success().stdout(predicate::str::contains("is valid"));
+
+    std::fs::write(&settings, "[terminal]\ntheme = \"none\"\nlang = \"fr\"\n").unwrap();
+    bxssh(home.path())
+        .args(["config", "validate"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 3"))
+        .stderr(predicate::str::contains("unsupported language 'fr'"));
+    // Other commands refuse to run on a broken file rather than ignore it
+    bxssh(home.path()).args(["key", "list"]).assert().failure().stderr(predicate::str::contains("config.toml"));
+}
+
+#[test]
+fn test_cli_moves_old_directory_to_xdg_paths() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir(home.path().join(".bxssh")).unwrap();
+    std::fs::write(home.path().join(".bxssh/profiles.toml"), "[prod]\nUser = \"deploy\"\n").unwrap();
+
+    bxssh(home.path()).args(["key", "generate", "moved"]).assert().success();
+    assert!(home.path().join(".config/bxssh/profiles.toml").exists());
+    assert!(home.path().join(".local/share/bxssh/keys/moved.json").exists());
+    assert!(home.path().join(".bxssh/MOVED.txt").exists());
+    bxssh(home.path())
+        .args(["config", "dump", "--host", "prod"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("user deploy"));
+
+    let portable = home.path().join("portable");
+    bxssh(home.path()).env("BXSSH_HOME", &portable).args(["key", "generate", "usb"]).assert().success();
+    assert!(portable.join("keys/usb.json").exists());
+    bxssh(home.path()).args(["key", "list"]).assert().success().stdout(predicate::str::contains("usb").not());
+}
+
+#[test]
+fn test_cli_git_style_remote_command() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args([
+        "-o", "SendEnv=GIT_PROTOCOL",
+        "-i", "/nonexistent/key",
+        "git@nonexistent-host.local",
+        "git-upload-pack", "'repo.git'",
+    ]);
+
+    // Arguments are accepted; the connection itself fails
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unexpected argument").not());
+}
+
+#[test]
+fn test_cli_progress_json_reports_connecting() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args([
+        "--progress-json",
+        "-c", "uptime",
+        "-i", "/nonexistent/key",
+        "testuser@nonexistent-host.local",
+    ]);
+
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains(r#""event":"connecting""#));
+}
+
+#[test]
+fn test_cli_no_banner_flag_accepted() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["--no-banner", "-c", "true", "testuser@127.0.0.1", "-p", "1"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unexpected argument").not());
+}
+
+#[test]
+fn test_cli_key_generate_list_rotate() {
+    let home = tempfile::TempDir::new().unwrap();
+
+    bxssh(home.path())
+        .args(["key", "generate", "ci", "--expires", "2099-01-01"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Generated SSH key pair: ci"));
+
+    bxssh(home.path())
+        .args(["key", "rotate", "ci"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ci-previous"));
+
+    bxssh(home.path())
+        .args(["key", "rotate", "ci"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("'ci-previous-2'"));
+
+    bxssh(home.path())
+        .args(["key", "list", "--verbose"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ci-previous"))
+        .stdout(predicate::str::contains("expires:   2099-01-01"))
+        .stdout(predicate::str::contains("uses:      0"));
+}
+
+#[test]
+fn test_cli_list_keys_fingerprints_and_json() {
+    let home = tempfile::TempDir::new().unwrap();
+    bxssh(home.path()).args(["key", "generate", "ci"]).assert().success();
+
+    bxssh(home.path())
+        .arg("--list-keys")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ci (Ed25519, 256 bits) SHA256:"))
+        .stdout(predicate::str::contains("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAI"));
+
+    let output = bxssh(home.path()).args(["key", "list", "--format", "json"]).output().unwrap();
+    assert!(output.status.success());
+    let keys: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(keys[0]["name"], "ci");
+    assert_eq!(keys[0]["bits"], 256);
+    assert!(keys[0]["fingerprint"].as_str().unwrap().starts_with("SHA256:"));
+    assert!(keys[0]["authorized_key"].as_str().unwrap().starts_with("ssh-ed25519 "));
+    assert!(keys[0].get("private_key").is_none());
+
+    // A key that can't be read is left out, as in the text listing
+    let keys_dir = home.path().join(".local/share/bxssh/keys");
+    let broken = std::fs::read_to_string(keys_dir.join("ci.json")).unwrap().replace("\"ci\"", "\"broken\"");
+    let broken = broken.replace("ssh-ed25519 ", "ssh-ed25519 !");
+    std::fs::write(keys_dir.join("broken.json"), broken).unwrap();
+    let output = bxssh(home.path()).args(["key", "list", "--format", "json"]).output().unwrap();
+    assert!(output.status.success());
+    let keys: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(keys.as_array().unwrap().len(), 1);
+    assert_eq!(keys[0]["name"], "ci");
+
+    // --format only applies to a listing
+    bxssh(home.path()).args(["--format", "json"]).assert().failure().stderr(predicate::str::contains("--list-keys"));
+}
+
+#[test]
+fn test_cli_key_comments_and_tags() {
+    let home = tempfile::TempDir::new().unwrap();
+    bxssh(home.path())
+        .args(["key", "generate", "laptop", "--comment", "alice@work-laptop", "--tag", "prod"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(" alice@work-laptop"));
+    bxssh(home.path()).args(["key", "generate", "spare"]).assert().success();
+    bxssh(home.path()).args(["key", "label", "spare", "--tag", "lab"]).assert().success();
+
+    bxssh(home.path())
+        .args(["key", "list", "--tag", "prod"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("laptop (Ed25519").and(predicate::str::contains("#prod")))
+        .stdout(predicate::str::contains("spare").not());
+    let output = bxssh(home.path()).args(["--list-keys", "--tag", "lab", "--format", "json"]).output().unwrap();
+    let keys: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(keys.as_array().unwrap().len(), 1);
+    assert_eq!(keys[0]["comment"], "bxssh-generated");
+    assert_eq!(keys[0]["tags"], serde_json::json!(["lab"]));
+
+    bxssh(home.path()).args(["key", "label", "spare", "--comment", "two\nlines"]).assert().failure();
+}
+
+#[test]
+fn test_cli_key_set_default() {
+    let home = tempfile::TempDir::new().unwrap();
+    bxssh(home.path()).args(["key", "generate", "work"]).assert().success();
+    bxssh(home.path()).args(["key", "generate", "home"]).assert().success();
+
+    bxssh(home.path()).args(["key", "set-default", "work", "--host", "*.corp"]).assert().success();
+    bxssh(home.path()).args(["key", "set-default", "home"]).assert().success();
+    bxssh(home.path()).args(["key", "set-default", "missing"]).assert().failure();
+
+    let output = bxssh(home.path()).args(["key", "list", "--format", "json"]).output().unwrap();
+    let keys: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(keys[0]["name"], "home");
+    assert_eq!(keys[0]["default_for"], serde_json::json!(["*"]));
+    assert_eq!(keys[1]["default_for"], serde_json::json!(["*.corp"]));
+    bxssh(home.path())
+        .args(["key", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[default for *.corp]"));
+}
+
+#[test]
+fn test_cli_key_authorize_needs_a_stored_key() {
+    let home = tempfile::TempDir::new().unwrap();
+    bxssh(home.path())
+        .args(["key", "authorize", "missing", "alice@127.0.0.1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Key 'missing' not found"));
+
+    bxssh(home.path()).args(["key", "generate", "ci"]).assert().success();
+    bxssh(home.path())
+        .args(["key", "revoke", "ci", "-p", "1", "alice@127.0.0.1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to connect"));
+}
+
+#[test]
+fn test_cli_key_generate_invalid_expiry() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["key", "generate", "ci", "--expires", "next-year"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid expiry date"));
+}
+
+#[test]
+fn test_cli_sync_export_import_round_trip() {
+    let source = tempfile::TempDir::new().unwrap();
+    let target = tempfile::TempDir::new().unwrap();
+    let bundle = source.path().join("bundle.json");
+
+    let synced = |home: &Path| {
+        let mut cmd = bxssh(home);
+        cmd.env("BXSSH_SYNC_PASSPHRASE", "correct horse");
+        cmd
+    };
+
+    synced(source.path()).args(["key", "generate", "laptop"]).assert().success();
+    synced(source.path())
+        .args(["sync", "export", bundle.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("keys.json"));
+
+    synced(target.path())
+        .args(["sync", "import", bundle.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Imported keys.json"));
+
+    synced(target.path())
+        .args(["key", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("laptop"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_attach_without_sessions() {
+    let home = tempfile::TempDir::new().unwrap();
+    let mut cmd = bxssh(home.path());
+    cmd.arg("attach");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("No detached sessions"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_plain_and_quiet_output() {
+    let home = tempfile::TempDir::new().unwrap();
+    let mut cmd = bxssh(home.path());
+    cmd.args(["attach", "--plain"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("No detached sessions\nStart one with: bxssh --detach user@hostname\n"));
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-q", "attach"]);
+    cmd.assert().success().stdout(predicate::str::is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_messages_follow_bxssh_lang() {
+    let home = tempfile::TempDir::new().unwrap();
+    let mut cmd = bxssh(home.path());
+    cmd.env("BXSSH_LANG", "es_ES.UTF-8").args(["--plain", "attach"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("No hay sesiones en segundo plano\n"));
+}
+
+#[test]
+fn test_cli_diagnose_reports_refused_connection() {
+    let home = tempfile::TempDir::new().unwrap();
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+    let mut cmd = bxssh(home.path());
+    cmd.args(["--plain", "diagnose", "alice@127.0.0.1", "-p", &port]);
+    cmd.assert()
+        .code(1)
+        .stdout(predicate::str::contains("dns: 127.0.0.1 resolves to 127.0.0.1\n"))
+        .stdout(predicate::str::contains("error: tcp: Cannot connect"))
+        .stdout(predicate::str::contains("1 problem(s) and 0 warning(s) found"));
+}
+
+#[test]
+fn test_cli_cat_and_put_take_remote_paths() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["cat", "alice@example.com"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid remote 'alice@example.com'. Use '[user@]host:/path'"));
+
+    let home = tempfile::TempDir::new().unwrap();
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+    let mut cmd = bxssh(home.path());
+    cmd.args(["put", "alice@127.0.0.1:-", "-p", &port]);
+    cmd.stdin(std::process::Stdio::null());
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("Failed to connect"));
+}
+
+#[test]
+fn test_cli_edit_takes_remote_path() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["edit", "prod", "--force"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid remote 'prod'. Use '[user@]host:/path'"));
+}
+
+#[test]
+fn test_cli_watch_validates_arguments() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["watch", "dev:/srv/app", "--interval", "0"]);
+    cmd.assert().failure().stderr(predicate::str::contains("--interval"));
+
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["watch", "dev", "--exec", "make"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid remote 'dev'. Use '[user@]host:/path'"));
+}
+
+#[cfg(not(unix))]
+#[test]
+fn test_cli_attach_requires_unix() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.arg("attach");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("only supported on Unix"));
+}
+
+#[cfg(not(feature = "fuse"))]
+#[test]
+fn test_cli_mount_requires_fuse_feature() {
+    let mount_dir = tempfile::TempDir::new().unwrap();
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.arg("mount").arg("alice@example.com:/srv").arg(mount_dir.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--features fuse"));
+}
+
+#[test]
+fn test_cli_relay_requires_allow_list() {
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["relay", "--listen", "127.0.0.1:0"]);
+    cmd.assert().failure().stderr(predicate::str::contains("--allow"));
+
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.args(["relay", "--listen", "127.0.0.1:0", "--allow", "bastion:0"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid port in allowed host 'bastion:0'"));
+}
+
+#[test]
+fn test_cli_run_script_rejects_invalid_script() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let script = dir.path().join("install.yaml");
+    std::fs::write(&script, "steps:\n  - expect: 'a'\n    send: 'b'\n").unwrap();
+
+    let mut cmd = bxssh(dir.path());
+    cmd.arg("run-script").arg(&script).arg("alice@example.com");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("exactly one of expect, send"));
+}
+
+#[test]
+fn test_cli_exec_reports_unreachable_hosts() {
+    let home = tempfile::TempDir::new().unwrap();
+    let report = home.path().join("report.json");
+
+    let mut cmd = bxssh(home.path());
+    cmd
+        .args(["exec", "-H", "alice@127.0.0.1", "-H", "bob@localhost", "-p", "1"])
+        .args(["--max-parallel", "2", "--timeout", "10", "--json-report"])
+        .arg(&report)
+        .args(["uptime"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("0 ok, 2 failed, 0 timed out"));
+
+    let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["command"], "uptime");
+    assert_eq!(report["results"][0]["host"], "alice@127.0.0.1");
+    assert_eq!(report["results"][1]["status"], "failed");
+}
+
+#[test]
+fn test_cli_detach_unreachable_host_reports_error() {
+    let home = tempfile::TempDir::new().unwrap();
+    let mut cmd = bxssh(home.path());
+    cmd
+        .args(["--detach", "--session-name", "test", "-i", "/nonexistent/key", "testuser@127.0.0.1", "-p", "1"]);
+    // The daemon gets as far as connecting, instead of tripping over its own arguments
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Session daemon exited"))
+        .stderr(predicate::str::contains("Failed to connect"))
+        .stderr(predicate::str::contains("--session-name").not());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_session_cache_falls_back_to_direct_connection() {
+    let home = tempfile::TempDir::new().unwrap();
+    let mut cmd = bxssh(home.path());
+    cmd
+        .args(["cat", "--session-cache", "-p", "1", "alice@127.0.0.1:/etc/hostname"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Session cache unavailable, connecting directly"))
+        .stderr(predicate::str::contains("Failed to connect"));
+}
+
+#[test]
+fn test_cli_policy_blocks_connection_before_connecting() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(home.path().join(".bxssh")).unwrap();
+    let policy = "allow = [\"*.corp.example.com\"]\nports = [\"22\"]\n";
+    std::fs::write(home.path().join(".bxssh/policy.toml"), policy).unwrap();
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-p", "1", "-c", "true", "alice@127.0.0.1"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Connection to 127.0.0.1:1 blocked by policy: host is not on the allow list"))
+        .stderr(predicate::str::contains("Failed to connect").not());
+}
+
+#[test]
+fn test_cli_policy_blocks_stdio_forward_before_connecting() {
+    let home = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(home.path().join(".bxssh")).unwrap();
+    std::fs::write(home.path().join(".bxssh/policy.toml"), "deny = [\"db.internal\"]\n").unwrap();
+
+    let mut cmd = bxssh(home.path());
+    cmd.args(["-p", "1", "-W", "db.internal:5432", "alice@127.0.0.1"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Connection to db.internal:5432 blocked by policy"))
+        .stderr(predicate::str::contains("Failed to connect").not());
+}
@@ -0,0 +1,98 @@
+use std::io::Read;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+pub const USERNAME: &str = "bxssh";
+pub const PASSWORD: &str = "bxssh-interop";
+pub const BANNER: &str = "bxssh interop banner";
+
+const IMAGE: &str = "bxssh-interop-sshd";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A throwaway OpenSSH server container, removed on drop
+pub struct OpenSshContainer {
+    id: String,
+    pub port: u16,
+    key_dir: tempfile::TempDir,
+}
+
+impl OpenSshContainer {
+    pub fn start() -> Self {
+        build_image();
+
+        let id = docker(&["run", "-d", "--rm", "-p", "127.0.0.1::22", IMAGE]);
+        let mapping = docker(&["port", &id, "22/tcp"]);
+        let port = mapping
+            .lines()
+            .next()
+            .and_then(|line| line.rsplit(':').next())
+            .and_then(|port| port.parse().ok())
+            .unwrap_or_else(|| panic!("Unexpected port mapping: {}", mapping));
+
+        let key_dir = tempfile::TempDir::new().expect("Failed to create key directory");
+        let container = Self { id, port, key_dir };
+        container.wait_until_ready();
+
+        let key_path = container.key_dir.path().join("id_ed25519");
+        docker(&[
+            "cp",
+            &format!("{}:/home/{}/.ssh/id_ed25519", container.id, USERNAME),
+            key_path.to_str().unwrap(),
+        ]);
+        container
+    }
+
+    /// Private key authorized for `USERNAME`
+    pub fn key_path(&self) -> PathBuf {
+        self.key_dir.path().join("id_ed25519")
+    }
+
+    fn wait_until_ready(&self) {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", self.port)) {
+                stream.set_read_timeout(Some(Duration::from_secs(2))).ok();
+                let mut ident = [0u8; 8];
+                if stream.read_exact(&mut ident).is_ok() && ident.starts_with(b"SSH-2.0") {
+                    return;
+                }
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        panic!("sshd in container {} did not become ready", self.id);
+    }
+}
+
+impl Drop for OpenSshContainer {
+    fn drop(&mut self) {
+        let _ = Command::new("docker").args(["rm", "-f", &self.id]).output();
+    }
+}
+
+fn build_image() {
+    static BUILT: OnceLock<()> = OnceLock::new();
+    BUILT.get_or_init(|| {
+        let context = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("interop");
+        docker(&["build", "-q", "-t", IMAGE, context.to_str().unwrap()]);
+    });
+}
+
+/// Run a docker command and return its trimmed stdout, panicking with stderr on failure
+fn docker(args: &[&str]) -> String {
+    let output = Command::new("docker")
+        .args(args)
+        .output()
+        .expect("Failed to run docker; the interop tests need a running Docker daemon");
+
+    if !output.status.success() {
+        panic!(
+            "docker {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
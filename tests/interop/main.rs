@@ -0,0 +1,198 @@
+//! End-to-end tests against a real OpenSSH server in Docker.
+//! Run with: cargo test --features interop-tests --test interop
+
+mod container;
+
+use assert_cmd::prelude::*;
+use bxssh::ssh_client::{ShellSession, SshClient};
+use bxssh::ssh_impl::RealSshConnection;
+use container::{OpenSshContainer, BANNER, PASSWORD, USERNAME};
+use predicates::prelude::*;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn connected_client(server: &OpenSshContainer) -> SshClient {
+    let mut client = SshClient::new(Box::new(RealSshConnection::new()));
+    client.connect("127.0.0.1", server.port).expect("connect failed");
+    client
+}
+
+fn authenticated_client(server: &OpenSshContainer) -> SshClient {
+    let mut client = connected_client(server);
+    client
+        .authenticate_with_key(USERNAME, server.key_path().to_str().unwrap())
+        .expect("key authentication failed");
+    client
+}
+
+/// Read from a non-blocking session until `pattern` shows up
+fn read_until(session: &mut dyn ShellSession, pattern: &[u8]) -> Vec<u8> {
+    let deadline = Instant::now() + READ_TIMEOUT;
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 4096];
+
+    while Instant::now() < deadline {
+        let n = session.read(&mut buffer).expect("read failed");
+        received.extend_from_slice(&buffer[..n]);
+        if received.windows(pattern.len()).any(|window| window == pattern) {
+            return received;
+        }
+        if n == 0 {
+            if session.is_eof() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+    panic!(
+        "Did not receive {:?}; got {:?}",
+        String::from_utf8_lossy(pattern),
+        String::from_utf8_lossy(&received)
+    );
+}
+
+fn write_all(session: &mut dyn ShellSession, mut data: &[u8]) {
+    while !data.is_empty() {
+        let n = session.write(data).expect("write failed");
+        data = &data[n..];
+        if n == 0 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+#[test]
+fn test_password_authentication() {
+    let server = OpenSshContainer::start();
+    let mut client = connected_client(&server);
+
+    assert!(client.authenticate_with_password(USERNAME, "wrong").is_err());
+
+    let mut client = connected_client(&server);
+    client.authenticate_with_password(USERNAME, PASSWORD).unwrap();
+    assert!(client.is_authenticated());
+}
+
+#[test]
+fn test_key_authentication_and_banner() {
+    let server = OpenSshContainer::start();
+    let mut client = connected_client(&server);
+
+    let banner = Arc::new(Mutex::new(String::new()));
+    let seen = Arc::clone(&banner);
+    client.set_banner_callback(Box::new(move |text| {
+        seen.lock().unwrap().push_str(text);
+        true
+    }));
+
+    client
+        .authenticate_with_key(USERNAME, server.key_path().to_str().unwrap())
+        .unwrap();
+    assert!(client.is_authenticated());
+    assert!(banner.lock().unwrap().contains(BANNER));
+}
+
+#[test]
+fn test_execute_command() {
+    let server = OpenSshContainer::start();
+    let client = authenticated_client(&server);
+
+    assert_eq!(client.execute_command("echo interop").unwrap(), "interop\n");
+    assert_eq!(client.execute_command("whoami").unwrap().trim(), USERNAME);
+}
+
+#[test]
+fn test_exec_exit_status_and_stdin() {
+    let server = OpenSshContainer::start();
+    let client = authenticated_client(&server);
+
+    let mut session = client.open_exec("cat; exit 3").unwrap();
+    write_all(session.as_mut(), b"piped input\n");
+    session.send_eof().unwrap();
+
+    read_until(session.as_mut(), b"piped input\n");
+    assert_eq!(session.wait_close().unwrap(), 3);
+}
+
+#[test]
+fn test_interactive_shell() {
+    let server = OpenSshContainer::start();
+    let client = authenticated_client(&server);
+    let mut shell = client.start_shell().unwrap();
+
+    write_all(shell.as_mut(), b"echo marker-$((40 + 2))\n");
+    read_until(shell.as_mut(), b"marker-42");
+
+    write_all(shell.as_mut(), b"exit\n");
+    let deadline = Instant::now() + READ_TIMEOUT;
+    let mut buffer = [0u8; 4096];
+    while !shell.is_eof() && Instant::now() < deadline {
+        if shell.read(&mut buffer).unwrap() == 0 {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+    assert!(shell.is_eof());
+}
+
+#[test]
+fn test_direct_tcpip_forward() {
+    let server = OpenSshContainer::start();
+    let client = authenticated_client(&server);
+
+    // Forward back to the container's own sshd and read its identification string
+    let mut channel = client.open_direct_tcpip("127.0.0.1", 22).unwrap();
+    let ident = read_until(channel.as_mut(), b"\r\n");
+    assert!(ident.starts_with(b"SSH-2.0-OpenSSH"));
+}
+
+#[test]
+fn test_sftp_subsystem_handshake() {
+    let server = OpenSshContainer::start();
+    let client = authenticated_client(&server);
+
+    // SSH_FXP_INIT (type 1) for protocol version 3, answered by SSH_FXP_VERSION (type 2)
+    let mut session = client.open_exec("/usr/lib/ssh/sftp-server").unwrap();
+    write_all(session.as_mut(), &[0, 0, 0, 5, 1, 0, 0, 0, 3]);
+
+    let reply = read_until(session.as_mut(), &[2, 0, 0, 0, 3]);
+    assert_eq!(&reply[4..9], &[2, 0, 0, 0, 3]);
+}
+
+#[test]
+fn test_cli_remote_command() {
+    let server = OpenSshContainer::start();
+    let home = tempfile::TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.env("HOME", home.path()).args([
+        "-i",
+        server.key_path().to_str().unwrap(),
+        "-p",
+        &server.port.to_string(),
+        &format!("{}@127.0.0.1", USERNAME),
+        "echo",
+        "from-cli",
+    ]);
+    cmd.assert().success().stdout("from-cli\n");
+}
+
+#[test]
+fn test_cli_remote_exit_status() {
+    let server = OpenSshContainer::start();
+    let home = tempfile::TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("bxssh").unwrap();
+    cmd.env("HOME", home.path()).args([
+        "--no-banner",
+        "-i",
+        server.key_path().to_str().unwrap(),
+        "-p",
+        &server.port.to_string(),
+        &format!("{}@127.0.0.1", USERNAME),
+        "exit 7",
+    ]);
+    cmd.assert().code(7).stderr(predicate::str::contains(BANNER).not());
+}